@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::path::Path;
+
+use anyhow::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use winit::event::MouseButton;
+use winit::keyboard::KeyCode;
+
+use crate::input::{GamepadAxis, GamepadButton, GamepadEvent, Input, MidiEvent};
+
+/// How far a stick has to move before a binding counts it, so a pad
+/// that doesn't center perfectly doesn't drift
+const STICK_DEAD_ZONE: f32 = 0.15;
+
+/// Something on a keyboard, mouse, MIDI controller or gamepad that can
+/// trigger an action
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    MouseButton(MouseButton),
+    /// A knob or fader, which gives values between 0 and 1
+    MidiControl {
+        channel: u8,
+        controller: u8,
+    },
+    MidiNote {
+        channel: u8,
+        note: u8,
+    },
+    GamepadButton(GamepadButton),
+    /// One direction of a stick, which gives values between 0 and 1 as
+    /// it's pushed that way
+    GamepadAxis {
+        axis: GamepadAxis,
+        positive: bool,
+    },
+}
+
+impl Binding {
+    /// 1 while held, or how far a MIDI control, trigger or stick is
+    /// turned up
+    pub fn value(&self, input: &Input) -> f32 {
+        match *self {
+            Binding::Key(key) => input.is_key_held(key) as u8 as f32,
+            Binding::MouseButton(button) => input.is_button_held(button) as u8 as f32,
+            Binding::MidiControl {
+                channel,
+                controller,
+            } => input.midi_control(channel, controller).unwrap_or(0.0),
+            Binding::MidiNote { channel, note } => {
+                input.midi_note(channel, note).is_some() as u8 as f32
+            }
+            Binding::GamepadButton(button) => input.gamepad_button(button),
+            Binding::GamepadAxis { axis, positive } => {
+                let value = input.gamepad_axis(axis);
+                let value = if positive { value } else { -value };
+                ((value - STICK_DEAD_ZONE) / (1.0 - STICK_DEAD_ZONE)).max(0.0)
+            }
+        }
+    }
+
+    /// Whether it went down this frame. MIDI controls and sticks never do.
+    pub fn is_pressed(&self, input: &Input) -> bool {
+        match *self {
+            Binding::Key(key) => input.is_key_pressed(key),
+            Binding::MouseButton(button) => input.is_button_pressed(button),
+            Binding::MidiControl { .. } => false,
+            Binding::MidiNote { channel, note } => input.midi_events().iter().any(|e| {
+                matches!(*e, MidiEvent::NoteOn { channel: c, note: n, .. } if c == channel && n == note)
+            }),
+            Binding::GamepadButton(button) => input.is_gamepad_button_pressed(button),
+            Binding::GamepadAxis { .. } => false,
+        }
+    }
+
+    /// The first thing that was pressed, turned, played or pushed this
+    /// frame. Sticks have to be pushed over halfway.
+    pub fn first_pressed(input: &Input) -> Option<Self> {
+        let midi = input.midi_events().iter().find_map(|e| match *e {
+            MidiEvent::NoteOn { channel, note, .. } => Some(Binding::MidiNote { channel, note }),
+            MidiEvent::ControlChange {
+                channel,
+                controller,
+                ..
+            } => Some(Binding::MidiControl {
+                channel,
+                controller,
+            }),
+            _ => None,
+        });
+        let gamepad = input.gamepad_events().iter().find_map(|e| match *e {
+            GamepadEvent::ButtonPressed(button) => Some(Binding::GamepadButton(button)),
+            GamepadEvent::AxisChanged(axis, value) if value.abs() > 0.5 => {
+                Some(Binding::GamepadAxis {
+                    axis,
+                    positive: value > 0.0,
+                })
+            }
+            _ => None,
+        });
+        input
+            .keys_pressed()
+            .map(Binding::Key)
+            .chain(input.buttons_pressed().map(Binding::MouseButton))
+            .chain(midi)
+            .chain(gamepad)
+            .next()
+    }
+}
+
+/// What the framework's controllers respond to. Demos with actions of
+/// their own can use their own enum with [ActionMap].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+}
+
+impl Action {
+    pub const ALL: [Action; 6] = [
+        Action::MoveForward,
+        Action::MoveBackward,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+    ];
+}
+
+/// Maps actions to the bindings that trigger them, so controllers ask
+/// whether `MoveForward` is held instead of checking `KeyW` and `ArrowUp`.
+/// Bindings can be changed at runtime with [ActionMap::start_rebinding]
+/// and saved to JSON.
+///
+/// ```ignore
+/// let mut actions = ActionMap::camera();
+/// actions.bind(Action::MoveUp, Binding::Key(KeyCode::KeyE));
+///
+/// // In Demo::update
+/// let forward = actions.axis(input, Action::MoveBackward, Action::MoveForward);
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(serialize = "A: Serialize", deserialize = "A: DeserializeOwned"))]
+pub struct ActionMap<A: Eq + Hash = Action> {
+    bindings: HashMap<A, Vec<Binding>>,
+    #[serde(skip)]
+    rebinding: Option<A>,
+}
+
+impl<A: Eq + Hash> Default for ActionMap<A> {
+    fn default() -> Self {
+        Self {
+            bindings: HashMap::new(),
+            rebinding: None,
+        }
+    }
+}
+
+impl<A: Copy + Eq + Hash> ActionMap<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds another binding for `action`
+    pub fn bind(&mut self, action: A, binding: Binding) -> &mut Self {
+        let bindings = self.bindings.entry(action).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+        self
+    }
+
+    pub fn unbind(&mut self, action: A, binding: Binding) -> &mut Self {
+        if let Some(bindings) = self.bindings.get_mut(&action) {
+            bindings.retain(|b| *b != binding);
+        }
+        self
+    }
+
+    pub fn clear(&mut self, action: A) -> &mut Self {
+        self.bindings.remove(&action);
+        self
+    }
+
+    pub fn bindings(&self, action: A) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /// Every action bound to `binding`
+    pub fn actions_for(&self, binding: Binding) -> impl Iterator<Item = A> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, bindings)| bindings.contains(&binding))
+            .map(|(action, _)| *action)
+    }
+
+    /// The largest value of any of the action's bindings, from 0 to 1
+    pub fn value(&self, input: &Input, action: A) -> f32 {
+        self.bindings(action)
+            .iter()
+            .map(|b| b.value(input))
+            .fold(0.0, f32::max)
+    }
+
+    pub fn is_held(&self, input: &Input, action: A) -> bool {
+        self.value(input, action) > 0.0
+    }
+
+    pub fn is_pressed(&self, input: &Input, action: A) -> bool {
+        self.bindings(action).iter().any(|b| b.is_pressed(input))
+    }
+
+    /// `positive` less `negative`, from -1 to 1
+    pub fn axis(&self, input: &Input, negative: A, positive: A) -> f32 {
+        self.value(input, positive) - self.value(input, negative)
+    }
+
+    /// The next thing pressed replaces `action`'s bindings. See
+    /// [ActionMap::update_rebinding].
+    pub fn start_rebinding(&mut self, action: A) {
+        self.rebinding = Some(action);
+    }
+
+    pub fn cancel_rebinding(&mut self) {
+        self.rebinding = None;
+    }
+
+    /// The action waiting for a new binding
+    pub fn rebinding(&self) -> Option<A> {
+        self.rebinding
+    }
+
+    /// Call this every frame while rebinding. Returns the new binding once
+    /// something has been pressed.
+    pub fn update_rebinding(&mut self, input: &Input) -> Option<Binding> {
+        let action = self.rebinding?;
+        let binding = Binding::first_pressed(input)?;
+        self.rebinding = None;
+        self.bindings.insert(action, vec![binding]);
+        Some(binding)
+    }
+}
+
+impl<A: Copy + Eq + Hash + Serialize + DeserializeOwned> ActionMap<A> {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Unable to open {}", path.display()))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("{} isn't a valid action map", path.display()))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Unable to create {}", path.display()))?;
+        serde_json::to_writer_pretty(std::io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+impl ActionMap<Action> {
+    /// The keys [crate::CameraController] always used: WASD or the arrow
+    /// keys to move, space and left shift to go up and down. A gamepad's
+    /// left stick moves and its bumpers go up and down.
+    pub fn camera() -> Self {
+        let mut map = Self::new();
+        map.bind(Action::MoveForward, Binding::Key(KeyCode::KeyW))
+            .bind(Action::MoveForward, Binding::Key(KeyCode::ArrowUp))
+            .bind(Action::MoveBackward, Binding::Key(KeyCode::KeyS))
+            .bind(Action::MoveBackward, Binding::Key(KeyCode::ArrowDown))
+            .bind(Action::MoveLeft, Binding::Key(KeyCode::KeyA))
+            .bind(Action::MoveLeft, Binding::Key(KeyCode::ArrowLeft))
+            .bind(Action::MoveRight, Binding::Key(KeyCode::KeyD))
+            .bind(Action::MoveRight, Binding::Key(KeyCode::ArrowRight))
+            .bind(Action::MoveUp, Binding::Key(KeyCode::Space))
+            .bind(Action::MoveDown, Binding::Key(KeyCode::ShiftLeft));
+        let stick = |axis, positive| Binding::GamepadAxis { axis, positive };
+        map.bind(Action::MoveForward, stick(GamepadAxis::LeftStickY, true))
+            .bind(Action::MoveBackward, stick(GamepadAxis::LeftStickY, false))
+            .bind(Action::MoveLeft, stick(GamepadAxis::LeftStickX, false))
+            .bind(Action::MoveRight, stick(GamepadAxis::LeftStickX, true))
+            .bind(
+                Action::MoveUp,
+                Binding::GamepadButton(GamepadButton::RightBumper),
+            )
+            .bind(
+                Action::MoveDown,
+                Binding::GamepadButton(GamepadButton::LeftBumper),
+            );
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::ElementState;
+
+    #[test]
+    fn rebinding_replaces_bindings() {
+        let mut actions = ActionMap::camera();
+        let mut input = Input::new();
+        input.process_key(KeyCode::ArrowUp, ElementState::Pressed);
+        assert!(actions.is_pressed(&input, Action::MoveForward));
+        assert_eq!(
+            actions.axis(&input, Action::MoveBackward, Action::MoveForward),
+            1.0
+        );
+        input.end_frame();
+
+        actions.start_rebinding(Action::MoveForward);
+        assert_eq!(actions.update_rebinding(&input), None);
+        input.process_key(KeyCode::KeyI, ElementState::Pressed);
+        assert_eq!(
+            actions.update_rebinding(&input),
+            Some(Binding::Key(KeyCode::KeyI))
+        );
+        assert_eq!(
+            actions.bindings(Action::MoveForward),
+            &[Binding::Key(KeyCode::KeyI)]
+        );
+        assert_eq!(actions.rebinding(), None);
+        assert_eq!(actions.value(&input, Action::MoveForward), 1.0);
+        // ArrowUp is still held but isn't bound anymore
+        input.process_key(KeyCode::KeyI, ElementState::Released);
+        assert!(!actions.is_held(&input, Action::MoveForward));
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut actions = ActionMap::camera();
+        actions.bind(
+            Action::MoveUp,
+            Binding::MidiControl {
+                channel: 0,
+                controller: 7,
+            },
+        );
+        let json = serde_json::to_string(&actions).unwrap();
+        let loaded: ActionMap = serde_json::from_str(&json).unwrap();
+        for action in Action::ALL.iter().copied() {
+            assert_eq!(loaded.bindings(action), actions.bindings(action));
+        }
+    }
+
+    #[test]
+    fn sticks_drive_both_directions() {
+        let mut actions = ActionMap::camera();
+        let mut input = Input::new();
+        input.process_gamepad(GamepadEvent::AxisChanged(GamepadAxis::LeftStickY, -1.0));
+        assert_eq!(
+            actions.axis(&input, Action::MoveBackward, Action::MoveForward),
+            -1.0
+        );
+        // Inside the dead zone
+        input.process_gamepad(GamepadEvent::AxisChanged(GamepadAxis::LeftStickY, 0.1));
+        assert_eq!(actions.value(&input, Action::MoveForward), 0.0);
+        input.end_frame();
+
+        actions.start_rebinding(Action::MoveUp);
+        input.process_gamepad(GamepadEvent::AxisChanged(GamepadAxis::RightStickY, 0.8));
+        let stick = Binding::GamepadAxis {
+            axis: GamepadAxis::RightStickY,
+            positive: true,
+        };
+        assert_eq!(actions.update_rebinding(&input), Some(stick));
+        assert!(actions.is_held(&input, Action::MoveUp));
+
+        let json = serde_json::to_string(&actions).unwrap();
+        let loaded: ActionMap = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.bindings(Action::MoveUp), &[stick]);
+    }
+}
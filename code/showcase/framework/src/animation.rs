@@ -0,0 +1,546 @@
+//! Skeletal animation. A [Skeleton] is a joint hierarchy, an
+//! [AnimationClip] holds keyframed joint transforms, and an [Animator]
+//! plays clips on a skeleton and produces the joint matrices that skinned
+//! vertices are transformed by. [JointBuffer] gets those matrices to the
+//! GPU, or [JointTexture] where storage buffers aren't available.
+//!
+//! ```ignore
+//! let mut animator = Animator::new(skeleton);
+//! animator.add_clip(walk);
+//! animator.play("walk")?;
+//! let joints = JointBuffer::new(&display.device, animator.skeleton().len());
+//!
+//! // Each frame
+//! animator.update(&display.time);
+//! joints.write(&display.queue, animator.joint_matrices());
+//! pass.set_bind_group(2, &joints.bind_group, &[]);
+//! ```
+
+use anyhow::*;
+use cgmath::*;
+
+use crate::scene::Transform;
+use crate::time::Time;
+use crate::timeline::Track;
+
+#[derive(Debug, Clone)]
+pub struct Joint {
+    pub name: String,
+    /// Index of the parent joint in the [Skeleton]
+    pub parent: Option<usize>,
+    /// The joint's transform relative to its parent when no clip is
+    /// animating it
+    pub rest: Transform,
+    /// Takes vertices from model space into the joint's space at bind time
+    pub inverse_bind: Matrix4<f32>,
+}
+
+/// A joint hierarchy. Joints keep the order they were given in, as that's
+/// what skinned vertices index by.
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    joints: Vec<Joint>,
+    /// Joint indices with parents before children
+    order: Vec<usize>,
+    /// Applied above the root joints, for transforms on nodes that own the
+    /// skeleton but aren't joints themselves
+    pub root: Matrix4<f32>,
+}
+
+impl Skeleton {
+    pub fn new(joints: Vec<Joint>) -> Result<Self> {
+        let mut order = Vec::with_capacity(joints.len());
+        let mut visited = vec![false; joints.len()];
+        for (i, joint) in joints.iter().enumerate() {
+            if let Some(parent) = joint.parent {
+                ensure!(
+                    parent < joints.len(),
+                    "Joint {:?} has a parent that doesn't exist",
+                    joint.name
+                );
+            }
+            // Walk up to the first visited ancestor, then add the chain
+            // back down so parents always come first
+            let mut chain = Vec::new();
+            let mut current = Some(i);
+            while let Some(j) = current {
+                if visited[j] {
+                    break;
+                }
+                ensure!(
+                    !chain.contains(&j),
+                    "Joint {:?} is its own ancestor",
+                    joints[j].name
+                );
+                chain.push(j);
+                current = joints[j].parent;
+            }
+            for j in chain.into_iter().rev() {
+                visited[j] = true;
+                order.push(j);
+            }
+        }
+        Ok(Self {
+            joints,
+            order,
+            root: Matrix4::identity(),
+        })
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|j| j.name == name)
+    }
+
+    pub fn rest_pose(&self) -> Vec<Transform> {
+        self.joints.iter().map(|j| j.rest).collect()
+    }
+
+    /// Model space transforms of every joint for `pose`, which holds each
+    /// joint's transform relative to its parent
+    pub fn world_matrices(&self, pose: &[Transform]) -> Vec<Matrix4<f32>> {
+        let mut world = vec![Matrix4::identity(); self.joints.len()];
+        for &i in &self.order {
+            let parent = match self.joints[i].parent {
+                Some(parent) => world[parent],
+                None => self.root,
+            };
+            world[i] = parent * pose[i].matrix();
+        }
+        world
+    }
+
+    /// The matrices skinned vertices are multiplied by for `pose`. The
+    /// rest pose gives identity matrices when the inverse bind matrices
+    /// match it.
+    pub fn joint_matrices(&self, pose: &[Transform]) -> Vec<Matrix4<f32>> {
+        self.world_matrices(pose)
+            .into_iter()
+            .zip(&self.joints)
+            .map(|(world, joint)| world * joint.inverse_bind)
+            .collect()
+    }
+}
+
+/// Keyframes for one joint. Empty tracks leave that part of the joint's
+/// transform alone.
+#[derive(Debug, Clone, Default)]
+pub struct JointChannel {
+    pub translation: Track<Vector3<f32>>,
+    pub rotation: Track<Quaternion<f32>>,
+    pub scale: Track<Vector3<f32>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    channels: Vec<(usize, JointChannel)>,
+}
+
+impl AnimationClip {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            channels: Vec::new(),
+        }
+    }
+
+    /// The keyframes for `joint`, added if the clip doesn't animate it yet
+    pub fn channel(&mut self, joint: usize) -> &mut JointChannel {
+        let i = match self.channels.iter().position(|(j, _)| *j == joint) {
+            Some(i) => i,
+            None => {
+                self.channels.push((joint, JointChannel::default()));
+                self.channels.len() - 1
+            }
+        };
+        &mut self.channels[i].1
+    }
+
+    pub fn channels(&self) -> impl Iterator<Item = (usize, &JointChannel)> {
+        self.channels.iter().map(|(j, c)| (*j, c))
+    }
+
+    /// The time of the last keyframe in seconds
+    pub fn duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .flat_map(|(_, c)| [c.translation.end(), c.rotation.end(), c.scale.end()])
+            .fold(0.0, f32::max)
+    }
+
+    /// Writes the animated parts of each joint at `time` into `pose`.
+    /// Channels for joints past the end of `pose` are ignored.
+    pub fn sample(&self, time: f32, pose: &mut [Transform]) {
+        for (joint, channel) in &self.channels {
+            let transform = match pose.get_mut(*joint) {
+                Some(transform) => transform,
+                None => continue,
+            };
+            if let Some(translation) = channel.translation.sample(time) {
+                transform.translation = translation;
+            }
+            if let Some(rotation) = channel.rotation.sample(time) {
+                transform.rotation = rotation.normalize();
+            }
+            if let Some(scale) = channel.scale.sample(time) {
+                transform.scale = scale;
+            }
+        }
+    }
+}
+
+/// Plays [AnimationClip]s on a [Skeleton]
+pub struct Animator {
+    skeleton: Skeleton,
+    clips: Vec<AnimationClip>,
+    current: Option<usize>,
+    time: f32,
+    playing: bool,
+    pub looping: bool,
+    /// Playback rate, 1 is real time
+    pub speed: f32,
+    pose: Vec<Transform>,
+    joint_matrices: Vec<Matrix4<f32>>,
+}
+
+impl Animator {
+    pub fn new(skeleton: Skeleton) -> Self {
+        let pose = skeleton.rest_pose();
+        let joint_matrices = skeleton.joint_matrices(&pose);
+        Self {
+            skeleton,
+            clips: Vec::new(),
+            current: None,
+            time: 0.0,
+            playing: false,
+            looping: true,
+            speed: 1.0,
+            pose,
+            joint_matrices,
+        }
+    }
+
+    pub fn skeleton(&self) -> &Skeleton {
+        &self.skeleton
+    }
+
+    pub fn add_clip(&mut self, clip: AnimationClip) -> usize {
+        self.clips.push(clip);
+        self.clips.len() - 1
+    }
+
+    pub fn clips(&self) -> &[AnimationClip] {
+        &self.clips
+    }
+
+    /// The clip being played, if any
+    pub fn current(&self) -> Option<&AnimationClip> {
+        self.current.map(|i| &self.clips[i])
+    }
+
+    /// Starts the named clip from the beginning
+    pub fn play(&mut self, name: &str) -> Result<()> {
+        let index = self
+            .clips
+            .iter()
+            .position(|c| c.name == name)
+            .with_context(|| format!("No animation clip named {:?}", name))?;
+        self.current = Some(index);
+        self.time = 0.0;
+        self.playing = true;
+        self.apply();
+        Ok(())
+    }
+
+    /// Goes back to the rest pose
+    pub fn stop(&mut self) {
+        self.current = None;
+        self.playing = false;
+        self.time = 0.0;
+        self.apply();
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn resume(&mut self) {
+        self.playing = self.current.is_some();
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn seek(&mut self, time: f32) {
+        let duration = self.current().map_or(0.0, |c| c.duration());
+        self.time = time.clamp(0.0, duration);
+        self.apply();
+    }
+
+    /// Advances by the frame's delta time and updates the pose
+    pub fn update(&mut self, time: &Time) {
+        self.advance(time.delta_secs());
+    }
+
+    /// Like [Animator::update], but with an explicit delta in seconds
+    pub fn advance(&mut self, dt: f32) {
+        if !self.playing {
+            return;
+        }
+        let duration = self.current().map_or(0.0, |c| c.duration());
+        self.time += dt * self.speed;
+        if self.time >= duration {
+            if self.looping && duration > 0.0 {
+                self.time %= duration;
+            } else {
+                self.time = duration;
+                self.playing = false;
+            }
+        }
+        self.apply();
+    }
+
+    /// Each joint's transform relative to its parent
+    pub fn pose(&self) -> &[Transform] {
+        &self.pose
+    }
+
+    /// For the current pose, see [Skeleton::joint_matrices]
+    pub fn joint_matrices(&self) -> &[Matrix4<f32>] {
+        &self.joint_matrices
+    }
+
+    fn apply(&mut self) {
+        // Joints the clip doesn't touch stay at rest
+        for (pose, joint) in self.pose.iter_mut().zip(self.skeleton.joints()) {
+            *pose = joint.rest;
+        }
+        if let Some(clip) = self.current {
+            self.clips[clip].sample(self.time, &mut self.pose);
+        }
+        self.joint_matrices = self.skeleton.joint_matrices(&self.pose);
+    }
+}
+
+/// A storage buffer of joint matrices and a bind group for it, visible to
+/// vertex and compute shaders as `array<mat4x4<f32>>` at binding 0.
+pub struct JointBuffer {
+    pub layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    buffer: wgpu::Buffer,
+    capacity: usize,
+}
+
+impl JointBuffer {
+    pub fn new(device: &wgpu::Device, joint_count: usize) -> Self {
+        let capacity = joint_count.max(1);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("JointBuffer::buffer"),
+            size: (capacity * std::mem::size_of::<[[f32; 4]; 4]>()) as _,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("JointBuffer::layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("JointBuffer::bind_group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        Self {
+            layout,
+            bind_group,
+            buffer,
+            capacity,
+        }
+    }
+
+    pub(crate) fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Uploads `matrices`, usually [Animator::joint_matrices]. Any past
+    /// the joint count the buffer was made for are dropped.
+    pub fn write(&self, queue: &wgpu::Queue, matrices: &[Matrix4<f32>]) {
+        let raw = joint_texels(matrices, self.capacity);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&raw));
+    }
+}
+
+/// Joint matrices in a texture, for skinning in the vertex shader on
+/// WebGL2 where there are no storage buffers. Each joint is a row of 4
+/// texels, one per column. [SKINNING_TEXTURE_WGSL](crate::SKINNING_TEXTURE_WGSL)
+/// reads it back.
+pub struct JointTexture {
+    pub layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    texture: wgpu::Texture,
+    capacity: usize,
+}
+
+impl JointTexture {
+    pub fn new(device: &wgpu::Device, joint_count: usize) -> Self {
+        let capacity = joint_count.max(1);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("JointTexture::texture"),
+            size: wgpu::Extent3d {
+                width: 4,
+                height: capacity as u32,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("JointTexture::layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+        let view = texture.create_view(&Default::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("JointTexture::bind_group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            }],
+        });
+        Self {
+            layout,
+            bind_group,
+            texture,
+            capacity,
+        }
+    }
+
+    /// Same as [JointBuffer::write]
+    pub fn write(&self, queue: &wgpu::Queue, matrices: &[Matrix4<f32>]) {
+        let raw = joint_texels(matrices, self.capacity);
+        if raw.is_empty() {
+            return;
+        }
+        queue.write_texture(
+            self.texture.as_image_copy(),
+            bytemuck::cast_slice(&raw),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(std::mem::size_of::<[[f32; 4]; 4]>() as u32),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: 4,
+                height: raw.len() as u32,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+/// Rows of a [JointTexture], one per joint with a column of its matrix in
+/// each texel. Joints past `capacity` are dropped.
+pub(crate) fn joint_texels(matrices: &[Matrix4<f32>], capacity: usize) -> Vec<[[f32; 4]; 4]> {
+    matrices
+        .iter()
+        .take(capacity)
+        .map(|m| -> [[f32; 4]; 4] { (*m).into() })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeline::Easing;
+
+    fn joint(name: &str, parent: Option<usize>, y: f32) -> Joint {
+        let rest = Transform {
+            translation: Vector3::new(0.0, y, 0.0),
+            ..Default::default()
+        };
+        Joint {
+            name: name.to_string(),
+            parent,
+            rest,
+            inverse_bind: Matrix4::identity(),
+        }
+    }
+
+    #[test]
+    fn joints_follow_their_parents() {
+        // The child comes first, like glTF skins allow
+        let mut skeleton =
+            Skeleton::new(vec![joint("hand", Some(1), 1.0), joint("arm", None, 2.0)]).unwrap();
+        assert!(Skeleton::new(vec![joint("a", Some(1), 0.0), joint("b", Some(0), 0.0)]).is_err());
+        let world = skeleton.world_matrices(&skeleton.rest_pose());
+        assert_eq!(world[0].w.truncate(), Vector3::new(0.0, 3.0, 0.0));
+
+        // Bind at rest, so the rest pose doesn't move anything
+        for (i, m) in world.iter().enumerate() {
+            skeleton.joints[i].inverse_bind = m.invert().unwrap();
+        }
+        let mut animator = Animator::new(skeleton);
+        assert!(animator.joint_matrices()[0].abs_diff_eq(&Matrix4::identity(), 1e-5));
+
+        let mut clip = AnimationClip::new("wave");
+        clip.channel(1)
+            .rotation
+            .key(0.0, Quaternion::one(), Easing::Linear)
+            .key(2.0, Quaternion::from_angle_z(Deg(90.0)), Easing::Linear);
+        animator.add_clip(clip);
+        assert!(animator.play("run").is_err());
+        animator.play("wave").unwrap();
+        animator.advance(2.0);
+        // Looped back around to the start
+        assert_eq!(animator.time(), 0.0);
+        animator.looping = false;
+        animator.advance(3.0);
+        assert!(!animator.is_playing());
+
+        // The hand swung around the arm's joint, 1 unit along -x
+        let hand = animator.joint_matrices()[0] * Vector4::new(0.0, 3.0, 0.0, 1.0);
+        assert!(hand.abs_diff_eq(&Vector4::new(-1.0, 2.0, 0.0, 1.0), 1e-5));
+        assert_eq!(animator.pose()[0], animator.skeleton().joints()[0].rest);
+    }
+}
@@ -0,0 +1,408 @@
+use anyhow::*;
+use cgmath::*;
+
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::post::{fullscreen_pass, PostContext, PostEffect, PostProcessChain};
+use crate::texture::Texture;
+
+/// Which pass [AntiAliasing] runs
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum AntiAliasingMode {
+    /// Skipped in the chain
+    #[default]
+    None,
+    /// Fast approximate anti-aliasing: blurs along edges it finds in the
+    /// final image. Cheap and needs nothing from the scene, but softens
+    /// text and fine detail. Works best on tonemapped colors.
+    Fxaa,
+    /// Temporal anti-aliasing: moves the projection by under a pixel
+    /// each frame and blends the frames together. Smooths edges and
+    /// shimmering better than FXAA, but can ghost behind moving objects.
+    Taa,
+}
+
+/// Tuning for [AntiAliasing], changed with [AntiAliasing::set_settings]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AntiAliasingSettings {
+    /// How far in pixels FXAA blurs along an edge
+    pub fxaa_span: f32,
+    /// How much of each new frame TAA blends into its history. Lower is
+    /// smoother but slower to catch up with changes.
+    pub taa_blend: f32,
+}
+
+impl Default for AntiAliasingSettings {
+    fn default() -> Self {
+        Self {
+            fxaa_span: 8.0,
+            taa_blend: 0.1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AntiAliasingUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    prev_view_proj: [[f32; 4]; 4],
+    fxaa: [f32; 4],
+    blend: f32,
+    has_history: u32,
+    _padding: [u32; 2],
+}
+
+/// Anti-aliasing as a [PostEffect], picked with an [AntiAliasingMode].
+/// FXAA goes after [crate::Tonemap] in a [PostProcessChain]. TAA keeps the
+/// history and the projection's jitter, so the demo only has to draw with
+/// [AntiAliasing::jitter_projection], pass the unjittered matrix to
+/// [AntiAliasing::set_view_proj], and hand the depth buffer to
+/// [PostProcessChain::process]. It needs [crate::Demo::SAMPLE_COUNT] at 1.
+///
+/// ```ignore
+/// post.add(AntiAliasing::new(&display.device, &post, AntiAliasingMode::Taa)?);
+///
+/// // In Demo::render
+/// let aa = post.get_mut::<AntiAliasing>().unwrap();
+/// let proj = projection.calc_matrix();
+/// camera_uniform.view_proj = aa.jitter_projection(proj) * camera.calc_matrix();
+/// aa.set_view_proj(proj * camera.calc_matrix());
+/// // Draw the scene into hdr with depth
+/// post.process(display, &mut encoder, hdr.view(), Some(&depth.view), &frame_view);
+/// ```
+pub struct AntiAliasing {
+    mode: AntiAliasingMode,
+    settings: AntiAliasingSettings,
+    format: wgpu::TextureFormat,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    fxaa_pipeline: wgpu::RenderPipeline,
+    taa_pipeline: wgpu::RenderPipeline,
+    history: [Texture<'static>; 2],
+    /// Bound in place of the scene's depth when the chain has none, which
+    /// only TAA reads
+    no_depth: Texture<'static>,
+    /// Which history TAA writes next
+    current: usize,
+    frame: u32,
+    has_history: bool,
+    view_proj: Matrix4<f32>,
+    prev_view_proj: Matrix4<f32>,
+}
+
+impl AntiAliasing {
+    pub fn new(
+        device: &wgpu::Device,
+        chain: &PostProcessChain,
+        mode: AntiAliasingMode,
+    ) -> Result<Self> {
+        let format = chain.format();
+        let texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let filterable = wgpu::TextureSampleType::Float { filterable: true };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("AntiAliasing::layout"),
+            entries: &[
+                texture_entry(0, filterable),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(3, wgpu::TextureSampleType::Depth),
+                texture_entry(4, filterable),
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("AntiAliasing::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("AntiAliasing::uniform_buffer"),
+            size: std::mem::size_of::<AntiAliasingUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("AntiAliasing::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            let shader = framework_shader("antialiasing.wgsl", include_str!("antialiasing.wgsl"));
+            RenderPipelineBuilder::new()
+                .layout(&pipeline_layout)
+                .vertex_shader(shader.module())
+                .fragment_shader(shader.module())
+                .vertex_entry_point("vs_main")
+                .fragment_entry_point(entry_point)
+                .color_solid(format)
+                .build(device)
+        };
+        let fxaa_pipeline = pipeline("fs_fxaa")?;
+        let taa_pipeline = pipeline("fs_taa")?;
+
+        let no_depth = Texture::from_descriptor(
+            device,
+            wgpu::TextureDescriptor {
+                label: Some("AntiAliasing::no_depth"),
+                size: wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Texture::DEPTH_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        );
+        Ok(Self {
+            mode,
+            settings: AntiAliasingSettings::default(),
+            format,
+            layout,
+            sampler,
+            uniform_buffer,
+            fxaa_pipeline,
+            taa_pipeline,
+            history: create_history(device, format, chain.width(), chain.height()),
+            no_depth,
+            current: 0,
+            frame: 0,
+            has_history: false,
+            view_proj: Matrix4::identity(),
+            prev_view_proj: Matrix4::identity(),
+        })
+    }
+
+    pub fn mode(&self) -> AntiAliasingMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: AntiAliasingMode) {
+        if mode != self.mode {
+            self.mode = mode;
+            self.has_history = false;
+        }
+    }
+
+    pub fn settings(&self) -> AntiAliasingSettings {
+        self.settings
+    }
+
+    /// Takes effect on the next frame
+    pub fn set_settings(&mut self, settings: AntiAliasingSettings) {
+        self.settings = settings;
+    }
+
+    /// Drops the TAA history, eg. when the camera jumps somewhere new
+    pub fn reset_history(&mut self) {
+        self.has_history = false;
+    }
+
+    /// The offset in pixels TAA moves this frame's projection by, from -0.5
+    /// to 0.5. Zero in the other modes.
+    pub fn jitter(&self) -> Vector2<f32> {
+        if self.mode != AntiAliasingMode::Taa {
+            return Vector2::zero();
+        }
+        // A Halton sequence covers the pixel evenly in a few frames
+        let index = self.frame % 8 + 1;
+        Vector2::new(halton(index, 2) - 0.5, halton(index, 3) - 0.5)
+    }
+
+    /// `projection` moved by [AntiAliasing::jitter]. Draw the scene with
+    /// it, and pass the unmoved one to [AntiAliasing::set_view_proj].
+    pub fn jitter_projection(&self, projection: Matrix4<f32>) -> Matrix4<f32> {
+        self.jitter_matrix() * projection
+    }
+
+    /// The camera's projection and view without jitter, for TAA to
+    /// reproject with. Needs setting every frame before the chain runs.
+    pub fn set_view_proj(&mut self, view_proj: Matrix4<f32>) {
+        self.view_proj = view_proj;
+    }
+
+    fn jitter_matrix(&self) -> Matrix4<f32> {
+        let jitter = self.jitter();
+        let size = self.history[0].desc.size;
+        // Clip space spans 2 across the target, and y goes up
+        Matrix4::from_translation(Vector3::new(
+            jitter.x * 2.0 / size.width as f32,
+            -jitter.y * 2.0 / size.height as f32,
+            0.0,
+        ))
+    }
+}
+
+impl PostEffect for AntiAliasing {
+    fn enabled(&self) -> bool {
+        self.mode != AntiAliasingMode::None
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.history = create_history(device, self.format, width, height);
+        self.has_history = false;
+    }
+
+    fn process(&mut self, encoder: &mut wgpu::CommandEncoder, ctx: &PostContext) {
+        crate::cpu_scope!("AntiAliasing::process");
+        let jittered = self.jitter_matrix() * self.view_proj;
+        let uniform = AntiAliasingUniform {
+            inv_view_proj: jittered.invert().unwrap_or_else(Matrix4::identity).into(),
+            prev_view_proj: self.prev_view_proj.into(),
+            fxaa: [self.settings.fxaa_span, 1.0 / 8.0, 1.0 / 128.0, 0.0],
+            blend: self.settings.taa_blend.clamp(0.0, 1.0),
+            has_history: self.has_history as u32,
+            _padding: [0; 2],
+        };
+        ctx.queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let depth = ctx.depth.unwrap_or(&self.no_depth.view);
+        let bind_group = |history: &wgpu::TextureView| {
+            ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("AntiAliasing::bind_group"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(ctx.input),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(depth),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(history),
+                    },
+                ],
+            })
+        };
+        match self.mode {
+            AntiAliasingMode::None => ctx.copy(encoder),
+            AntiAliasingMode::Fxaa => fullscreen_pass(
+                encoder,
+                "AntiAliasing::fxaa",
+                ctx.output,
+                wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                &self.fxaa_pipeline,
+                &bind_group(&self.history[0].view),
+            ),
+            AntiAliasingMode::Taa => {
+                let current = self.current;
+                // Reads the other history, which has the last frame
+                fullscreen_pass(
+                    encoder,
+                    "AntiAliasing::taa",
+                    &self.history[current].view,
+                    wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    &self.taa_pipeline,
+                    &bind_group(&self.history[1 - current].view),
+                );
+                ctx.copy_to(encoder, &self.history[current].view, ctx.output);
+                self.current = 1 - current;
+                self.has_history = true;
+            }
+        }
+        self.prev_view_proj = self.view_proj;
+        self.frame = self.frame.wrapping_add(1);
+    }
+}
+
+fn create_history(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+) -> [Texture<'static>; 2] {
+    [0, 1].map(|_| {
+        Texture::from_descriptor(
+            device,
+            wgpu::TextureDescriptor {
+                label: Some("AntiAliasing::history"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        )
+    })
+}
+
+/// Element `index` of the Halton sequence in `base`, from 0 to 1
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halton_spreads_jitter() {
+        assert_eq!(halton(1, 2), 0.5);
+        assert_eq!(halton(2, 3), 2.0 / 3.0);
+        assert_eq!(halton(3, 2), 0.75);
+    }
+
+    #[test]
+    fn antialiasing_wgsl_validates() {
+        assert_eq!(std::mem::size_of::<AntiAliasingUniform>(), 160);
+        crate::shader::validate_wgsl(
+            &framework_shader("antialiasing.wgsl", include_str!("antialiasing.wgsl")).source,
+        )
+        .unwrap();
+    }
+}
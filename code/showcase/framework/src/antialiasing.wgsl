@@ -0,0 +1,107 @@
+// Post process anti-aliasing. fs_fxaa blurs along edges it finds from
+// luma, and fs_taa blends each frame into a reprojected history of
+// jittered frames.
+
+struct AntiAliasingUniform {
+    // Of this frame's jittered projection
+    inv_view_proj: mat4x4<f32>,
+    // Last frame's, without jitter
+    prev_view_proj: mat4x4<f32>,
+    // x is the furthest the edge search reaches in pixels, y and z scale
+    // and floor how much the edge direction is trusted
+    fxaa: vec4<f32>,
+    // How much of this frame goes into the history
+    blend: f32,
+    has_history: u32,
+    _padding: vec2<u32>,
+}
+
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var input_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> aa: AntiAliasingUniform;
+@group(0) @binding(3)
+var depth_texture: texture_depth_2d;
+@group(0) @binding(4)
+var history_texture: texture_2d<f32>;
+
+#include "framework/fullscreen.wgsl"
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+fn fxaa_sample(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(input_texture, input_sampler, uv, 0.0).rgb;
+}
+
+@fragment
+fn fs_fxaa(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(input_texture));
+    let uv = in.clip_position.xy * texel;
+    let center = textureSampleLevel(input_texture, input_sampler, uv, 0.0);
+    let luma_nw = luma(fxaa_sample(uv + vec2<f32>(-1.0, -1.0) * texel));
+    let luma_ne = luma(fxaa_sample(uv + vec2<f32>(1.0, -1.0) * texel));
+    let luma_sw = luma(fxaa_sample(uv + vec2<f32>(-1.0, 1.0) * texel));
+    let luma_se = luma(fxaa_sample(uv + vec2<f32>(1.0, 1.0) * texel));
+    let luma_m = luma(center.rgb);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // Points along the edge, across the gradient
+    var dir = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * aa.fxaa.y, aa.fxaa.z);
+    let scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + reduce);
+    dir = clamp(dir * scale, vec2<f32>(-aa.fxaa.x), vec2<f32>(aa.fxaa.x)) * texel;
+
+    let near = 0.5 * (fxaa_sample(uv + dir * (1.0 / 3.0 - 0.5)) + fxaa_sample(uv + dir * (2.0 / 3.0 - 0.5)));
+    let far = near * 0.5 + 0.25 * (fxaa_sample(uv - dir * 0.5) + fxaa_sample(uv + dir * 0.5));
+    // Reaching further crossed into something else, so stay close
+    let far_luma = luma(far);
+    if (far_luma < luma_min || far_luma > luma_max) {
+        return vec4<f32>(near, center.a);
+    }
+    return vec4<f32>(far, center.a);
+}
+
+@fragment
+fn fs_taa(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(in.clip_position.xy);
+    let size = vec2<i32>(textureDimensions(input_texture));
+    let current = textureLoad(input_texture, coord, 0);
+
+    // The history is kept to colors around this pixel, which stops things
+    // that moved or were uncovered from smearing
+    var low = current.rgb;
+    var high = current.rgb;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let c = clamp(coord + vec2<i32>(x, y), vec2<i32>(0), size - 1);
+            let neighbour = textureLoad(input_texture, c, 0).rgb;
+            low = min(low, neighbour);
+            high = max(high, neighbour);
+        }
+    }
+    if (aa.has_history == 0u) {
+        return current;
+    }
+
+    // Where this pixel was last frame
+    let uv = (vec2<f32>(coord) + 0.5) / vec2<f32>(size);
+    let depth = textureLoad(depth_texture, coord, 0);
+    let world = aa.inv_view_proj * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let prev_clip = aa.prev_view_proj * vec4<f32>(world.xyz / world.w, 1.0);
+    let prev_uv = prev_clip.xy / prev_clip.w * vec2<f32>(0.5, -0.5) + 0.5;
+    if (any(prev_uv < vec2<f32>(0.0)) || any(prev_uv > vec2<f32>(1.0))) {
+        return current;
+    }
+
+    let history = textureSampleLevel(history_texture, input_sampler, prev_uv, 0.0).rgb;
+    let color = mix(clamp(history, low, high), current.rgb, aa.blend);
+    return vec4<f32>(color, current.a);
+}
@@ -0,0 +1,430 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use crate::deletion::DeletionQueue;
+use crate::model::Model;
+use crate::texture::Texture;
+
+/// A shared reference to something in [Assets]. Cloning is cheap, and the
+/// asset is destroyed once the last handle is dropped and the GPU is done
+/// with it.
+pub struct Handle<T> {
+    index: usize,
+    refs: Arc<()>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    /// Unique among live assets of the same type
+    pub fn id(&self) -> usize {
+        self.index
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            index: self.index,
+            refs: self.refs.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle<{}>({})", std::any::type_name::<T>(), self.index)
+    }
+}
+
+struct Slot<T> {
+    value: T,
+    // The store keeps one reference, so a count of 1 means no handles
+    // are left
+    refs: Arc<()>,
+    path: Option<PathBuf>,
+}
+
+struct Store<T> {
+    slots: Vec<Option<Slot<T>>>,
+    free: Vec<usize>,
+    paths: HashMap<PathBuf, usize>,
+    /// Handles held by [Assets::keep]
+    kept: Vec<Handle<T>>,
+}
+
+impl<T> Default for Store<T> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            paths: HashMap::new(),
+            kept: Vec::new(),
+        }
+    }
+}
+
+impl<T> Store<T> {
+    fn insert(&mut self, value: T, path: Option<PathBuf>) -> Handle<T> {
+        let refs = Arc::new(());
+        let slot = Slot {
+            value,
+            refs: refs.clone(),
+            path: path.clone(),
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index] = Some(slot);
+                index
+            }
+            None => {
+                self.slots.push(Some(slot));
+                self.slots.len() - 1
+            }
+        };
+        if let Some(path) = path {
+            self.paths.insert(path, index);
+        }
+        Handle {
+            index,
+            refs,
+            _marker: PhantomData,
+        }
+    }
+
+    fn slot(&self, index: usize) -> &Slot<T> {
+        // Slots are only freed once every handle is gone
+        self.slots[index].as_ref().unwrap()
+    }
+
+    fn find(&self, path: &Path) -> Option<Handle<T>> {
+        let index = *self.paths.get(path)?;
+        Some(Handle {
+            index,
+            refs: self.slot(index).refs.clone(),
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// Lets [Assets] keep stores of different types in one map
+trait AnyStore {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn collect(&mut self, deletion_queue: &mut DeletionQueue);
+    fn release_kept(&mut self);
+}
+
+impl<T: 'static> AnyStore for Store<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn collect(&mut self, deletion_queue: &mut DeletionQueue) {
+        for index in 0..self.slots.len() {
+            let unused =
+                matches!(&self.slots[index], Some(slot) if Arc::strong_count(&slot.refs) == 1);
+            if unused {
+                let slot = self.slots[index].take().unwrap();
+                if let Some(path) = slot.path {
+                    self.paths.remove(&path);
+                }
+                self.free.push(index);
+                deletion_queue.defer(slot.value);
+            }
+        }
+    }
+
+    fn release_kept(&mut self) {
+        self.kept.clear();
+    }
+}
+
+/// Owns textures, models and anything else demos want to share by
+/// [Handle]. Call [Assets::maintain] once a frame so unused assets get
+/// cleaned up.
+///
+/// ```ignore
+/// let brick = assets.load_texture(&display.device, &display.queue, "brick.png", false);
+/// // Loading the same file again shares the texture
+/// let again = assets.load_texture(&display.device, &display.queue, "brick.png", false);
+/// assert_eq!(brick, again);
+/// let view = &assets.get(&brick).view;
+/// ```
+#[derive(Default)]
+pub struct Assets {
+    stores: HashMap<TypeId, Box<dyn AnyStore>>,
+}
+
+impl Assets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn store<T: 'static>(&self) -> Option<&Store<T>> {
+        self.stores
+            .get(&TypeId::of::<T>())
+            .and_then(|s| s.as_any().downcast_ref())
+    }
+
+    fn store_mut<T: 'static>(&mut self) -> &mut Store<T> {
+        self.stores
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::<Store<T>>::default())
+            .as_any_mut()
+            .downcast_mut()
+            .unwrap()
+    }
+
+    pub fn add<T: 'static>(&mut self, value: T) -> Handle<T> {
+        self.store_mut().insert(value, None)
+    }
+
+    /// Adds an asset that [Assets::find] can look up by `path`
+    pub fn add_with_path<T: 'static, P: AsRef<Path>>(&mut self, value: T, path: P) -> Handle<T> {
+        self.store_mut()
+            .insert(value, Some(path.as_ref().to_path_buf()))
+    }
+
+    pub fn get<T: 'static>(&self, handle: &Handle<T>) -> &T {
+        &self.store::<T>().unwrap().slot(handle.index).value
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, handle: &Handle<T>) -> &mut T {
+        &mut self.store_mut::<T>().slots[handle.index]
+            .as_mut()
+            .unwrap()
+            .value
+    }
+
+    /// A handle to an asset that's already been loaded from `path`
+    pub fn find<T: 'static, P: AsRef<Path>>(&self, path: P) -> Option<Handle<T>> {
+        self.store::<T>()?.find(path.as_ref())
+    }
+
+    /// How many assets of a type have handles
+    pub fn count<T: 'static>(&self) -> usize {
+        self.store::<T>()
+            .map_or(0, |s| s.slots.iter().filter(|s| s.is_some()).count())
+    }
+
+    /// Loads a texture, or shares it if it's already loaded. Files that
+    /// fail to load become [Texture::placeholder].
+    pub fn load_texture<P: AsRef<Path>>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: P,
+        is_normal_map: bool,
+    ) -> Handle<Texture<'static>> {
+        if let Some(handle) = self.find(path.as_ref()) {
+            return handle;
+        }
+        let texture = Texture::load_or_placeholder(device, queue, path.as_ref(), is_normal_map);
+        self.add_with_path(texture, path)
+    }
+
+    /// Like [Assets::load_texture], but files that fail to load are an
+    /// error instead of a placeholder
+    pub fn try_load_texture<P: AsRef<Path>>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: P,
+        is_normal_map: bool,
+    ) -> Result<Handle<Texture<'static>>> {
+        let path = path.as_ref();
+        if let Some(handle) = self.find(path) {
+            return Ok(handle);
+        }
+        let texture = Texture::load(device, queue, path, is_normal_map)
+            .with_context(|| format!("Unable to load {}", path.display()))?;
+        Ok(self.add_with_path(texture, path))
+    }
+
+    /// A 1x1 texture of `color`, shared by everything that asks for the
+    /// same one
+    pub fn solid_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        is_normal_map: bool,
+    ) -> Handle<Texture<'static>> {
+        let key = format!("<solid {:?} {}>", color, is_normal_map);
+        self.find_or_add(key, || Texture::solid(device, queue, color, is_normal_map))
+    }
+
+    /// A shared [Texture::placeholder]
+    pub fn placeholder_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        is_normal_map: bool,
+    ) -> Handle<Texture<'static>> {
+        let key = format!("<placeholder {}>", is_normal_map);
+        self.find_or_add(key, || Texture::placeholder(device, queue, is_normal_map))
+    }
+
+    /// Shares an asset that isn't loaded from a file, like a generated
+    /// texture, under a made up `key`
+    pub fn find_or_add<T: 'static, P: AsRef<Path>>(
+        &mut self,
+        key: P,
+        create: impl FnOnce() -> T,
+    ) -> Handle<T> {
+        match self.find(key.as_ref()) {
+            Some(handle) => handle,
+            None => self.add_with_path(create(), key),
+        }
+    }
+
+    /// [Assets::load_texture] for several files at once, decoding the
+    /// ones that aren't loaded yet in parallel
+    pub fn load_textures<P: AsRef<Path> + Sync>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        files: &[(P, bool)],
+    ) -> Vec<Handle<Texture<'static>>> {
+        let missing: Vec<_> = files
+            .iter()
+            .filter(|(path, _)| self.find::<Texture, _>(path).is_none())
+            .map(|(path, is_normal_map)| (path.as_ref(), *is_normal_map))
+            .collect();
+        let loaded = Texture::load_all(device, queue, &missing);
+        for ((path, is_normal_map), texture) in missing.iter().zip(loaded) {
+            let texture = texture.unwrap_or_else(|e| {
+                log::warn!("{:#}, using a placeholder", e);
+                Texture::placeholder(device, queue, *is_normal_map)
+            });
+            self.add_with_path(texture, path);
+        }
+        files
+            .iter()
+            .map(|(path, is_normal_map)| self.load_texture(device, queue, path, *is_normal_map))
+            .collect()
+    }
+
+    /// Loads an OBJ model, or shares it if it's already loaded. Files that
+    /// fail to load become [Model::placeholder]. Its textures go in here
+    /// too, shared with any other model or material that uses them.
+    pub fn load_model<P: AsRef<Path>>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        path: P,
+    ) -> Handle<Model> {
+        if let Some(handle) = self.find(path.as_ref()) {
+            return handle;
+        }
+        let model = Model::load_obj_or_placeholder(device, queue, self, layout, path.as_ref());
+        self.add_with_path(model, path)
+    }
+
+    /// Holds on to an asset even after every other handle is dropped, so
+    /// something big that several demos or scenes use, like Sponza or an
+    /// environment map, is loaded once up front and shared instead of
+    /// being loaded again each time it comes back
+    pub fn keep<T: 'static>(&mut self, handle: &Handle<T>) {
+        let store = self.store_mut::<T>();
+        if !store.kept.contains(handle) {
+            store.kept.push(handle.clone());
+        }
+    }
+
+    /// Lets everything passed to [Assets::keep] be cleaned up once its
+    /// other handles are gone
+    pub fn release_kept(&mut self) {
+        for store in self.stores.values_mut() {
+            store.release_kept();
+        }
+    }
+
+    /// Hands assets that no longer have handles to `deletion_queue`,
+    /// usually [crate::Display::deletion_queue], so they're destroyed once
+    /// in-flight frames are done with them
+    pub fn maintain(&mut self, deletion_queue: &mut DeletionQueue) {
+        for store in self.stores.values_mut() {
+            store.collect(deletion_queue);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn unused_assets_are_deferred() {
+        let mut assets = Assets::new();
+        let mut deletion_queue = DeletionQueue::new();
+        let tracker = Rc::new(());
+        let handle = assets.add_with_path(tracker.clone(), "tracker");
+        let shared = assets.find::<Rc<()>, _>("tracker").unwrap();
+        assert_eq!(handle, shared);
+        assert_eq!(assets.count::<Rc<()>>(), 1);
+        assert_eq!(assets.count::<u32>(), 0);
+
+        drop(handle);
+        assets.maintain(&mut deletion_queue);
+        assert!(deletion_queue.is_empty());
+
+        drop(shared);
+        assets.maintain(&mut deletion_queue);
+        assert_eq!(assets.count::<Rc<()>>(), 0);
+        assert!(assets.find::<Rc<()>, _>("tracker").is_none());
+        // Still alive until the GPU is done with it
+        assert_eq!(Rc::strong_count(&tracker), 2);
+        drop(deletion_queue);
+        assert_eq!(Rc::strong_count(&tracker), 1);
+
+        // Slots get reused
+        let handle = assets.add(Rc::new(()));
+        assert_eq!(handle.id(), 0);
+    }
+
+    #[test]
+    fn kept_assets_outlive_their_handles() {
+        let mut assets = Assets::new();
+        let mut deletion_queue = DeletionQueue::new();
+        let handle = assets.add_with_path(1u32, "preloaded");
+        assets.keep(&handle);
+        assets.keep(&handle);
+        drop(handle);
+        assets.maintain(&mut deletion_queue);
+        assert_eq!(assets.count::<u32>(), 1);
+        assert!(assets.find::<u32, _>("preloaded").is_some());
+
+        assets.release_kept();
+        assets.maintain(&mut deletion_queue);
+        assert_eq!(assets.count::<u32>(), 0);
+    }
+}
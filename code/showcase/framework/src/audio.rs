@@ -0,0 +1,529 @@
+//! Music reactive demos. Samples from a microphone or an audio file go
+//! into an [AudioAnalyzer], which turns them into frequency bands and
+//! beats that shaders can read from an [AudioUniform].
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::*;
+use rustfft::num_complex::Complex;
+
+/// Number of frequency bands in [AudioFeatures::bands]
+pub const AUDIO_BANDS: usize = 8;
+/// Number of samples analyzed each frame
+const FFT_SIZE: usize = 2048;
+const MIN_FREQUENCY: f32 = 40.0;
+const MAX_FREQUENCY: f32 = 16000.0;
+/// How much audio the beat detector compares against, in seconds
+const BEAT_HISTORY: f32 = 1.0;
+
+/// What the analyzer heard this frame. In WGSL this looks like
+///
+/// ```wgsl
+/// struct AudioFeatures {
+///     bands: array<vec4<f32>, 2>,
+///     volume: f32,
+///     beat: f32,
+///     time_since_beat: f32,
+/// }
+/// ```
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct AudioFeatures {
+    /// Amplitude of log spaced frequency bands from 40Hz to 16kHz
+    pub bands: [f32; AUDIO_BANDS],
+    /// RMS of the latest samples
+    pub volume: f32,
+    /// Jumps to 1 on a beat and fades back to 0
+    pub beat: f32,
+    /// Seconds since the last beat
+    pub time_since_beat: f32,
+    _padding: f32,
+}
+
+/// Collects samples from audio threads. Clone it into whatever produces
+/// the audio.
+#[derive(Debug, Clone, Default)]
+pub struct AudioSink {
+    samples: Arc<Mutex<VecDeque<f32>>>,
+}
+
+impl AudioSink {
+    /// Caps how many samples can pile up if nothing is analyzing them
+    const MAX_QUEUED: usize = FFT_SIZE * 64;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds mono samples
+    pub fn push(&self, samples: &[f32]) {
+        let mut queue = self.samples.lock().unwrap();
+        queue.extend(samples);
+        let excess = queue.len().saturating_sub(Self::MAX_QUEUED);
+        queue.drain(..excess);
+    }
+
+    /// Adds interleaved samples, mixing them down to mono
+    pub fn push_interleaved(&self, samples: &[f32], channels: usize) {
+        let channels = channels.max(1);
+        let mono = samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+            .collect::<Vec<_>>();
+        self.push(&mono);
+    }
+
+    fn drain(&self) -> Vec<f32> {
+        self.samples.lock().unwrap().drain(..).collect()
+    }
+}
+
+/// Runs an FFT over the most recent samples once per frame.
+pub struct AudioAnalyzer {
+    sink: AudioSink,
+    sample_rate: u32,
+    window: VecDeque<f32>,
+    fft: Arc<dyn rustfft::Fft<f32>>,
+    buffer: Vec<Complex<f32>>,
+    smoothing: f32,
+    sensitivity: f32,
+    energy_history: VecDeque<(f32, f32)>,
+    features: AudioFeatures,
+}
+
+impl AudioAnalyzer {
+    pub fn new(sample_rate: u32) -> Self {
+        let fft = rustfft::FftPlanner::new().plan_fft_forward(FFT_SIZE);
+        Self {
+            sink: AudioSink::new(),
+            sample_rate,
+            window: VecDeque::from(vec![0.0; FFT_SIZE]),
+            fft,
+            buffer: vec![Complex::default(); FFT_SIZE],
+            smoothing: 0.85,
+            sensitivity: 1.4,
+            energy_history: VecDeque::new(),
+            features: AudioFeatures {
+                time_since_beat: f32::MAX,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Where audio sources should send their samples
+    pub fn sink(&self) -> AudioSink {
+        self.sink.clone()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Has to match the rate of whatever is feeding [AudioAnalyzer::sink]
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// How much of the previous frame's bands is kept each 60th of a
+    /// second, between 0 and 1. Defaults to 0.85.
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.smoothing = smoothing.clamp(0.0, 1.0);
+    }
+
+    /// How far above the recent average the bass has to jump to count as
+    /// a beat. Defaults to 1.4.
+    pub fn set_sensitivity(&mut self, sensitivity: f32) {
+        self.sensitivity = sensitivity;
+    }
+
+    pub fn features(&self) -> &AudioFeatures {
+        &self.features
+    }
+
+    /// Analyzes everything that has arrived at the sink since the last
+    /// call. `dt` is the frame time in seconds.
+    pub fn update(&mut self, dt: f32) -> &AudioFeatures {
+        let samples = self.sink.drain();
+        self.window.extend(samples);
+        let excess = self.window.len().saturating_sub(FFT_SIZE);
+        self.window.drain(..excess);
+
+        // Hann window to reduce leakage between bins
+        for (i, (out, sample)) in self.buffer.iter_mut().zip(&self.window).enumerate() {
+            let hann = 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / FFT_SIZE as f32).cos();
+            *out = Complex::new(sample * hann, 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+
+        let decay = self.smoothing.powf(dt * 60.0);
+        let bands = band_amplitudes(&self.buffer, self.sample_rate);
+        for (band, new) in self.features.bands.iter_mut().zip(bands) {
+            *band = new.max(*band * decay);
+        }
+        self.features.volume =
+            (self.window.iter().map(|s| s * s).sum::<f32>() / FFT_SIZE as f32).sqrt();
+
+        self.detect_beat(bands[0] + bands[1], dt);
+        &self.features
+    }
+
+    fn detect_beat(&mut self, energy: f32, dt: f32) {
+        let total_time = self.energy_history.iter().map(|(_, dt)| dt).sum::<f32>();
+        let average = if self.energy_history.is_empty() {
+            0.0
+        } else {
+            self.energy_history.iter().map(|(e, _)| e).sum::<f32>()
+                / self.energy_history.len() as f32
+        };
+
+        self.features.time_since_beat += dt;
+        self.features.beat = (self.features.beat - dt * 4.0).max(0.0);
+        // Ignore silence, and don't trigger more than ~5 times a second
+        if energy > average * self.sensitivity
+            && energy > 0.01
+            && self.features.time_since_beat > 0.2
+            && total_time > BEAT_HISTORY * 0.5
+        {
+            self.features.beat = 1.0;
+            self.features.time_since_beat = 0.0;
+        }
+
+        self.energy_history.push_back((energy, dt));
+        let mut total_time = total_time + dt;
+        while total_time > BEAT_HISTORY && self.energy_history.len() > 1 {
+            let (_, old_dt) = self.energy_history.pop_front().unwrap();
+            total_time -= old_dt;
+        }
+    }
+}
+
+fn band_amplitudes(spectrum: &[Complex<f32>], sample_rate: u32) -> [f32; AUDIO_BANDS] {
+    let bin_width = sample_rate as f32 / FFT_SIZE as f32;
+    let max_frequency = MAX_FREQUENCY.min(sample_rate as f32 / 2.0);
+    let ratio = (max_frequency / MIN_FREQUENCY).powf(1.0 / AUDIO_BANDS as f32);
+    // The window halves the amplitude, and only half the spectrum is used
+    let scale = 4.0 / FFT_SIZE as f32;
+
+    let mut bands = [0.0; AUDIO_BANDS];
+    for (i, band) in bands.iter_mut().enumerate() {
+        let low = MIN_FREQUENCY * ratio.powi(i as i32);
+        let high = low * ratio;
+        let first = (low / bin_width) as usize;
+        let last = ((high / bin_width) as usize).clamp(first + 1, FFT_SIZE / 2);
+        *band = spectrum[first..last]
+            .iter()
+            .map(|c| c.norm() * scale)
+            .fold(0.0, f32::max);
+    }
+    bands
+}
+
+/// Decoded audio, mixed down to mono.
+#[derive(Debug, Clone)]
+pub struct AudioClip {
+    pub samples: Arc<[f32]>,
+    pub sample_rate: u32,
+}
+
+impl AudioClip {
+    pub fn load_wav<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let reader = hound::WavReader::open(path)
+            .with_context(|| format!("Unable to open {}", path.display()))?;
+        let spec = reader.spec();
+        let samples = match spec.sample_format {
+            hound::SampleFormat::Float => reader
+                .into_samples::<f32>()
+                .collect::<Result<Vec<_>, _>>()?,
+            hound::SampleFormat::Int => {
+                let max = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .into_samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 / max))
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        };
+        let channels = spec.channels.max(1) as usize;
+        let samples = samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+            .collect();
+        Ok(Self {
+            samples,
+            sample_rate: spec.sample_rate,
+        })
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.samples.len() as f32 / self.sample_rate as f32
+    }
+}
+
+/// Feeds an [AudioClip] to a sink in step with the frame time without
+/// playing it. Useful when the audio is played some other way, or when
+/// rendering offline.
+pub struct ClipCursor {
+    clip: AudioClip,
+    position: usize,
+    pub looping: bool,
+}
+
+impl ClipCursor {
+    pub fn new(clip: AudioClip) -> Self {
+        Self {
+            clip,
+            position: 0,
+            looping: true,
+        }
+    }
+
+    /// Seconds into the clip
+    pub fn time(&self) -> f32 {
+        self.position as f32 / self.clip.sample_rate as f32
+    }
+
+    pub fn advance(&mut self, dt: f32, sink: &AudioSink) {
+        let count = (dt * self.clip.sample_rate as f32).round() as usize;
+        let samples = &self.clip.samples;
+        let mut remaining = count;
+        while remaining > 0 && !samples.is_empty() {
+            let end = (self.position + remaining).min(samples.len());
+            sink.push(&samples[self.position..end]);
+            remaining -= end - self.position;
+            self.position = end;
+            if self.position == samples.len() {
+                if !self.looping {
+                    break;
+                }
+                self.position = 0;
+            }
+        }
+    }
+}
+
+/// A live audio stream. Audio stops when this is dropped.
+#[cfg(feature = "audio-device")]
+pub struct AudioStream {
+    _stream: cpal::Stream,
+    sample_rate: u32,
+}
+
+#[cfg(feature = "audio-device")]
+impl AudioStream {
+    /// Sends the default input device's samples to `sink`
+    pub fn microphone(sink: AudioSink) -> Result<Self> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+        let device = cpal::default_host()
+            .default_input_device()
+            .context("No microphone found")?;
+        let config = device.default_input_config()?;
+        let channels = config.channels() as usize;
+        let sample_rate = config.sample_rate().0;
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => {
+                build_input_stream::<f32>(&device, &config.into(), sink, channels)?
+            }
+            cpal::SampleFormat::I16 => {
+                build_input_stream::<i16>(&device, &config.into(), sink, channels)?
+            }
+            cpal::SampleFormat::U16 => {
+                build_input_stream::<u16>(&device, &config.into(), sink, channels)?
+            }
+            format => bail!("Unsupported sample format {:?}", format),
+        };
+        stream.play()?;
+
+        Ok(Self {
+            _stream: stream,
+            sample_rate,
+        })
+    }
+
+    /// Plays `clip` on the default output device on loop, sending what's
+    /// played to `sink`.
+    pub fn play(clip: AudioClip, sink: AudioSink) -> Result<Self> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+        let device = cpal::default_host()
+            .default_output_device()
+            .context("No audio output found")?;
+        let config = device.default_output_config()?;
+        if config.sample_format() != cpal::SampleFormat::F32 {
+            bail!("Unsupported sample format {:?}", config.sample_format());
+        }
+        let channels = config.channels() as usize;
+        let sample_rate = config.sample_rate().0;
+
+        // Nearest neighbour resampling is good enough for visualization
+        let step = clip.sample_rate as f64 / sample_rate as f64;
+        let mut position = 0.0f64;
+        let mut played = Vec::new();
+        let stream = device.build_output_stream(
+            &config.into(),
+            move |data: &mut [f32], _| {
+                played.clear();
+                for frame in data.chunks_mut(channels) {
+                    let sample = clip.samples.get(position as usize).copied().unwrap_or(0.0);
+                    frame.fill(sample);
+                    played.push(sample);
+                    position += step;
+                    if position as usize >= clip.samples.len() {
+                        position = 0.0;
+                    }
+                }
+                sink.push(&played);
+            },
+            |e| log::error!("Audio stream error: {}", e),
+            None,
+        )?;
+        stream.play()?;
+
+        Ok(Self {
+            _stream: stream,
+            sample_rate,
+        })
+    }
+
+    /// Pass this to [AudioAnalyzer::set_sample_rate]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+#[cfg(feature = "audio-device")]
+fn build_input_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sink: AudioSink,
+    channels: usize,
+) -> Result<cpal::Stream>
+where
+    T: cpal::SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    use cpal::traits::DeviceTrait;
+
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _| {
+            let samples = data
+                .iter()
+                .map(|s| s.to_sample::<f32>())
+                .collect::<Vec<_>>();
+            sink.push_interleaved(&samples, channels);
+        },
+        |e| log::error!("Audio stream error: {}", e),
+        None,
+    )?;
+    Ok(stream)
+}
+
+/// Puts [AudioFeatures] in a uniform buffer so shaders can react to the
+/// music. Bind it wherever the shader expects it.
+pub struct AudioUniform {
+    buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl AudioUniform {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("AudioUniform::buffer"),
+            size: std::mem::size_of::<AudioFeatures>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("AudioUniform::layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX
+                    | wgpu::ShaderStages::FRAGMENT
+                    | wgpu::ShaderStages::COMPUTE,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("AudioUniform::bind_group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        Self {
+            buffer,
+            layout,
+            bind_group,
+        }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, features: &AudioFeatures) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(features));
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn sine(frequency: f32, amplitude: f32, count: usize) -> Vec<f32> {
+        (0..count)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                amplitude * (std::f32::consts::TAU * frequency * t).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tone_lands_in_matching_band() {
+        let mut analyzer = AudioAnalyzer::new(SAMPLE_RATE);
+        analyzer.sink().push(&sine(1000.0, 0.5, FFT_SIZE));
+        let features = *analyzer.update(1.0 / 60.0);
+
+        let loudest = (0..AUDIO_BANDS)
+            .max_by(|a, b| features.bands[*a].total_cmp(&features.bands[*b]))
+            .unwrap();
+        // Bands are 40Hz * 2.11^i, so 1kHz is in the 5th one
+        assert_eq!(loudest, 4);
+        assert!((features.bands[4] - 0.5).abs() < 0.1);
+        assert!((features.volume - 0.5 / 2f32.sqrt()).abs() < 0.05);
+    }
+
+    #[test]
+    fn bass_hit_is_a_beat() {
+        let dt = 1.0 / 60.0;
+        let per_frame = SAMPLE_RATE as usize / 60;
+        let mut analyzer = AudioAnalyzer::new(SAMPLE_RATE);
+        let sink = analyzer.sink();
+        for _ in 0..60 {
+            sink.push(&sine(60.0, 0.05, per_frame));
+            assert_eq!(analyzer.update(dt).beat, 0.0);
+        }
+
+        sink.push(&sine(60.0, 0.8, FFT_SIZE));
+        let features = analyzer.update(dt);
+        assert_eq!(features.beat, 1.0);
+        assert_eq!(features.time_since_beat, 0.0);
+    }
+}
@@ -0,0 +1,595 @@
+//! Tries the framework's subsystems on every adapter on this machine and
+//! prints which ones work where. Handy for "works on Vulkan but not GL"
+//! reports.
+//!
+//! ```text
+//! cargo run -p framework --bin compat
+//! ```
+
+use anyhow::*;
+use cgmath::*;
+use framework::*;
+use wgpu::util::DeviceExt;
+
+const OUTPUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+enum Status {
+    Ok,
+    Unsupported,
+    Failed(String),
+}
+
+impl Status {
+    fn cell(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Unsupported => "n/a",
+            Status::Failed(_) => "FAIL",
+        }
+    }
+}
+
+struct Check {
+    name: &'static str,
+    /// Returns false when the device can't run it
+    run: fn(&wgpu::Device, &wgpu::Queue, &Capabilities) -> Result<bool>,
+}
+
+const CHECKS: &[Check] = &[
+    Check {
+        name: "shadow",
+        run: shadow,
+    },
+    Check {
+        name: "cascades",
+        run: cascades,
+    },
+    Check {
+        name: "point",
+        run: point_shadow,
+    },
+    Check {
+        name: "post",
+        run: post,
+    },
+    Check {
+        name: "material",
+        run: material,
+    },
+    Check {
+        name: "deferred",
+        run: deferred,
+    },
+    Check {
+        name: "ibl",
+        run: ibl,
+    },
+    Check {
+        name: "skybox",
+        run: skybox,
+    },
+    Check {
+        name: "compute",
+        run: compute,
+    },
+    Check {
+        name: "skinning",
+        run: skinning,
+    },
+];
+
+/// The tutorials' instance matrix
+const INSTANCE_LAYOUT: wgpu::VertexBufferLayout = wgpu::VertexBufferLayout {
+    array_stride: std::mem::size_of::<[[f32; 4]; 4]>() as _,
+    step_mode: wgpu::VertexStepMode::Instance,
+    attributes: &wgpu::vertex_attr_array![
+        5 => Float32x4,
+        6 => Float32x4,
+        7 => Float32x4,
+        8 => Float32x4,
+    ],
+};
+
+/// A normal and parallax mapped fragment shader for MODEL_VERTEX_WGSL
+const MATERIAL_FS_WGSL: &str = r#"
+@fragment
+fn fs_main(in: ModelVertexOutput) -> @location(0) vec4<f32> {
+    let v = normalize(camera.view_position.xyz - in.world_position);
+    let uv = parallax_uv(in.tex_coords, tangent_space(in, v));
+    let surface = sample_material(uv);
+    let n = world_normal(in, surface.normal);
+    let color = pbr_light(surface, n, v, normalize(vec3<f32>(1.0)), vec3<f32>(1.0));
+    return vec4<f32>(color, 1.0);
+}
+"#;
+
+/// The part of the tutorials' fragment shader that reads shadows
+const RECEIVER_WGSL: &str = r#"
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.5, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return vec4<f32>(shadow(vec3<f32>(position.x * 0.01, 0.0, position.y * 0.01)));
+}
+"#;
+
+fn output_texture(device: &wgpu::Device, size: u32) -> wgpu::TextureView {
+    let output = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("compat::output"),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: OUTPUT_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    output.create_view(&Default::default())
+}
+
+const FILTERS: [ShadowFilter; 3] = [
+    ShadowFilter::Hard,
+    ShadowFilter::Pcf { kernel: 3 },
+    ShadowFilter::Pcss { light_size: 8.0 },
+];
+
+/// Draws with a shader that samples a shadow map bound to group 3
+fn receive_shadows(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    snippet: &str,
+    layout: &wgpu::BindGroupLayout,
+    bind_group: &wgpu::BindGroup,
+) -> Result<()> {
+    let empty_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("compat::empty_layout"),
+        entries: &[],
+    });
+    let empty = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("compat::empty"),
+        layout: &empty_layout,
+        entries: &[],
+    });
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("compat::receiver_layout"),
+        bind_group_layouts: &[&empty_layout, &empty_layout, &empty_layout, layout],
+        push_constant_ranges: &[],
+    });
+    let source = format!("{}{}", snippet, RECEIVER_WGSL);
+    let shader = || wgpu::ShaderModuleDescriptor {
+        label: Some("compat::receiver"),
+        source: wgpu::ShaderSource::Wgsl(source.as_str().into()),
+    };
+    let pipeline = RenderPipelineBuilder::new()
+        .layout(&pipeline_layout)
+        .vertex_shader(shader())
+        .fragment_shader(shader())
+        .vertex_entry_point("vs_main")
+        .fragment_entry_point("fs_main")
+        .color_solid(OUTPUT_FORMAT)
+        .build(device)?;
+    let view = output_texture(device, 64);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("compat::receive_shadows"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: Default::default(),
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&pipeline);
+        for i in 0..3 {
+            pass.set_bind_group(i, &empty, &[]);
+        }
+        pass.set_bind_group(3, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+    queue.submit([encoder.finish()]);
+    Ok(())
+}
+
+fn shadow(device: &wgpu::Device, queue: &wgpu::Queue, _: &Capabilities) -> Result<bool> {
+    let mut shadow = ShadowMap::new(device, 256, 64)?;
+    shadow.update(queue);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    drop(shadow.begin_pass(&mut encoder));
+    queue.submit([encoder.finish()]);
+    for filter in FILTERS.iter().copied() {
+        shadow.filter = filter;
+        shadow.update(queue);
+        receive_shadows(
+            device,
+            queue,
+            SHADOW_WGSL,
+            &shadow.layout,
+            &shadow.bind_group,
+        )?;
+    }
+    Ok(true)
+}
+
+fn cascades(device: &wgpu::Device, queue: &wgpu::Queue, _: &Capabilities) -> Result<bool> {
+    let mut shadow = CascadedShadowMap::new(device, 256, 3, 64)?;
+    let camera = Camera::new((0.0, 5.0, 10.0), Deg(-90.0), Deg(-20.0));
+    let projection = Projection::new(256, 256, Deg(45.0), 0.1, 100.0);
+    shadow.update(queue, &camera, &projection);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    for cascade in 0..shadow.cascades() {
+        drop(shadow.begin_pass(&mut encoder, cascade));
+    }
+    queue.submit([encoder.finish()]);
+    for filter in FILTERS.iter().copied() {
+        shadow.filter = filter;
+        shadow.update(queue, &camera, &projection);
+        receive_shadows(
+            device,
+            queue,
+            SHADOW_CASCADES_WGSL,
+            &shadow.layout,
+            &shadow.bind_group,
+        )?;
+    }
+    Ok(true)
+}
+
+fn point_shadow(device: &wgpu::Device, queue: &wgpu::Queue, _: &Capabilities) -> Result<bool> {
+    let mut shadow = PointShadowMap::new(device, 128, 64)?;
+    shadow.update(queue);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    for face in 0..6 {
+        drop(shadow.begin_pass(&mut encoder, face));
+    }
+    queue.submit([encoder.finish()]);
+    for filter in FILTERS.iter().copied() {
+        shadow.filter = filter;
+        shadow.update(queue);
+        receive_shadows(
+            device,
+            queue,
+            SHADOW_POINT_WGSL,
+            &shadow.layout,
+            &shadow.bind_group,
+        )?;
+    }
+    Ok(true)
+}
+
+/// A full screen pass reading a depth texture, which is what most post
+/// effects boil down to
+fn post(device: &wgpu::Device, queue: &wgpu::Queue, _: &Capabilities) -> Result<bool> {
+    let shadow = ShadowMap::new(device, 64, 64)?;
+    let mut inset = DebugInset::new(device, OUTPUT_FORMAT)?;
+    inset.add_source(device, "shadow", shadow.texture(), DebugView::Depth);
+    inset.select(Some("shadow"));
+    let view = output_texture(device, 64);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    drop(shadow.begin_pass(&mut encoder));
+    inset.render(&mut encoder, &view, ViewportRect::full(64, 64));
+    queue.submit([encoder.finish()]);
+    Ok(true)
+}
+
+fn material(device: &wgpu::Device, queue: &wgpu::Queue, _: &Capabilities) -> Result<bool> {
+    let layout = Material::create_layout(device);
+    let mut assets = Assets::new();
+    let mut material = Material::placeholder(device, queue, &mut assets, &layout);
+    material.factors.roughness = 0.5;
+    material.update_factors(queue);
+    let albedo = assets.solid_texture(device, queue, [255; 4], false);
+    material.set_albedo_texture(device, &assets, albedo, &layout);
+    let height = assets.solid_texture(device, queue, [128; 4], true);
+    material.set_height_texture(device, &assets, height, &layout);
+    material.factors.height_scale = 0.05;
+    material.update_factors(queue);
+
+    // Draw a cube with parallax occlusion mapping on
+    let camera_uniform = CameraUniform::new(device);
+    let camera_binding = UniformBinding::new(device, &camera_uniform);
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("compat::material"),
+        bind_group_layouts: &[&layout, &camera_binding.layout],
+        push_constant_ranges: &[],
+    });
+    let source = format!("{}{}{}", PBR_WGSL, MODEL_VERTEX_WGSL, MATERIAL_FS_WGSL);
+    let shader = || wgpu::ShaderModuleDescriptor {
+        label: Some("compat::material"),
+        source: wgpu::ShaderSource::Wgsl(source.as_str().into()),
+    };
+    let pipeline = RenderPipelineBuilder::new()
+        .layout(&pipeline_layout)
+        .vertex_shader(shader())
+        .fragment_shader(shader())
+        .vertex_entry_point("vs_model")
+        .fragment_entry_point("fs_main")
+        .vertex_buffer::<ModelVertex>()
+        .vertex_buffer_desc(INSTANCE_LAYOUT.clone())
+        .color_solid(OUTPUT_FORMAT)
+        .build(device)?;
+    let cube = Mesh::unit_cube(device, 0);
+    let identity: [[f32; 4]; 4] = Matrix4::identity().into();
+    let instances = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("compat::instances"),
+        contents: bytemuck::cast_slice(&[identity]),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+
+    let view = output_texture(device, 64);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("compat::material"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &material.bind_group, &[]);
+        pass.set_bind_group(1, &camera_binding.bind_group, &[]);
+        pass.set_vertex_buffer(0, cube.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, instances.slice(..));
+        pass.set_index_buffer(cube.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        pass.draw_indexed(0..cube.num_elements, 0, 0..1);
+    }
+    queue.submit([encoder.finish()]);
+    Ok(true)
+}
+
+fn deferred(device: &wgpu::Device, queue: &wgpu::Queue, caps: &Capabilities) -> Result<bool> {
+    if !caps.fragment_storage_buffers {
+        return Ok(false);
+    }
+    let layout = Material::create_layout(device);
+    let mut assets = Assets::new();
+    let material = Material::placeholder(device, queue, &mut assets, &layout);
+    let camera_uniform = CameraUniform::new(device);
+    let camera_binding = UniformBinding::new(device, &camera_uniform);
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("compat::deferred"),
+        bind_group_layouts: &[&layout, &camera_binding.layout],
+        push_constant_ranges: &[],
+    });
+    let gbuffer = GBuffer::new(device, 64, 64);
+    let geometry =
+        GBuffer::create_geometry_pipeline(device, &pipeline_layout, INSTANCE_LAYOUT.clone())?;
+    let mut lighting = DeferredLighting::new(device, &gbuffer, OUTPUT_FORMAT)?;
+    lighting
+        .lights
+        .push(PointLight::new((2.0, 2.0, 2.0), [1.0; 3], 10.0));
+    lighting.spot_lights.push(SpotLight::new(
+        (0.0, 2.0, 2.0),
+        (0.0, -1.0, -1.0),
+        [1.0; 3],
+        10.0,
+    ));
+    let camera = Camera::new((0.0, 0.0, 3.0), Deg(-90.0), Deg(0.0));
+    let projection = Projection::new(64, 64, Deg(45.0), 0.1, 100.0);
+    lighting.update(device, queue, &camera, &projection);
+
+    let cube = Mesh::unit_cube(device, 0);
+    let identity: [[f32; 4]; 4] = Matrix4::identity().into();
+    let instances = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("compat::instances"),
+        contents: bytemuck::cast_slice(&[identity]),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+    let view = output_texture(device, 64);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut pass = gbuffer.begin_geometry_pass(&mut encoder);
+        pass.set_pipeline(&geometry);
+        pass.set_vertex_buffer(1, instances.slice(..));
+        pass.draw_mesh(
+            &cube,
+            &material,
+            &camera_binding.bind_group,
+            &camera_binding.bind_group,
+        );
+    }
+    lighting.render(&mut encoder, &gbuffer, &view);
+    queue.submit([encoder.finish()]);
+    Ok(true)
+}
+
+fn ibl(device: &wgpu::Device, queue: &wgpu::Queue, _: &Capabilities) -> Result<bool> {
+    let panorama =
+        image::Rgb32FImage::from_fn(64, 32, |x, y| image::Rgb([x as f32, y as f32, 1.0]));
+    let sky = Cubemap::from_equirectangular(device, queue, &panorama)?;
+    EnvironmentMap::new(device, queue, &sky.view, 16)?;
+    Ok(true)
+}
+
+fn skybox(device: &wgpu::Device, queue: &wgpu::Queue, _: &Capabilities) -> Result<bool> {
+    let panorama =
+        image::Rgb32FImage::from_fn(64, 32, |x, y| image::Rgb([x as f32, y as f32, 1.0]));
+    let sky = Cubemap::from_equirectangular(device, queue, &panorama)?;
+    let skybox = Skybox::new(device, &sky, OUTPUT_FORMAT, Some(Texture::DEPTH_FORMAT))?;
+    let camera = Camera::new((0.0, 0.0, 0.0), Deg(-90.0), Deg(10.0));
+    let projection = Projection::new(64, 64, Deg(45.0), 0.1, 100.0);
+    skybox.update(queue, &camera, &projection);
+
+    let view = output_texture(device, 64);
+    let depth = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("compat::depth"),
+        size: wgpu::Extent3d {
+            width: 64,
+            height: 64,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: Texture::DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    let depth_view = depth.create_view(&Default::default());
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("compat::skybox"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        skybox.draw(&mut pass);
+    }
+    queue.submit([encoder.finish()]);
+    Ok(true)
+}
+
+fn compute(device: &wgpu::Device, queue: &wgpu::Queue, caps: &Capabilities) -> Result<bool> {
+    if !caps.compute_shaders {
+        return Ok(false);
+    }
+    let mut culler = GpuCuller::new(device)?;
+    culler.compare = true;
+    let spheres = (0..100)
+        .map(|i| BoundingSphere::new(Point3::new(i as f32 - 50.0, 0.0, -10.0), 1.0))
+        .collect::<Vec<_>>();
+    culler.set_spheres(device, &spheres);
+    let proj = perspective(Deg(60.0), 1.0, 1.0, 100.0);
+    let view = Matrix4::look_to_rh(Point3::origin(), -Vector3::unit_z(), Vector3::unit_y());
+    let mut encoder = device.create_command_encoder(&Default::default());
+    culler.dispatch(queue, &mut encoder, &Frustum::from_matrix(proj * view));
+    queue.submit([encoder.finish()]);
+    culler.check(device, &mut FrameStats::default())?;
+    ensure!(
+        culler.mismatches().is_empty(),
+        "{} spheres culled differently on the GPU",
+        culler.mismatches().len()
+    );
+    Ok(true)
+}
+
+fn skinning(device: &wgpu::Device, queue: &wgpu::Queue, caps: &Capabilities) -> Result<bool> {
+    match SkinningMode::new(caps) {
+        SkinningMode::Compute => {
+            let skinning = Skinning::new(device)?;
+            let joints = JointBuffer::new(device, 2);
+            joints.write(queue, &[Matrix4::identity(); 2]);
+            let mut encoder = device.create_command_encoder(&Default::default());
+            skinning.skin(&mut encoder, &[]);
+            queue.submit([encoder.finish()]);
+        }
+        SkinningMode::VertexTexture => {
+            let joints = JointTexture::new(device, 2);
+            joints.write(queue, &[Matrix4::identity(); 2]);
+        }
+    }
+    Ok(true)
+}
+
+/// Runs `check` and turns validation errors and panics into failures
+fn run_check(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    caps: &Capabilities,
+    check: &Check,
+) -> Status {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        (check.run)(device, queue, caps)
+    }));
+    device.poll(wgpu::Maintain::Wait);
+    let error = pollster::block_on(device.pop_error_scope());
+    match (result, error) {
+        (_, Some(e)) => Status::Failed(e.to_string()),
+        (Err(_), None) => Status::Failed("panicked".to_string()),
+        (Result::Ok(Err(e)), None) => Status::Failed(format!("{:#}", e)),
+        (Result::Ok(Result::Ok(false)), None) => Status::Unsupported,
+        (Result::Ok(Result::Ok(true)), None) => Status::Ok,
+    }
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..Default::default()
+    });
+    let adapters = instance.enumerate_adapters(wgpu::Backends::all());
+    ensure!(!adapters.is_empty(), "No adapters found");
+
+    print!("{:<40} {:<8}", "adapter", "backend");
+    for check in CHECKS {
+        print!(" {:<9}", check.name);
+    }
+    println!();
+
+    let mut failures = Vec::new();
+    for adapter in adapters {
+        let info = adapter.get_info();
+        print!("{:<40} {:<8}", info.name, format!("{:?}", info.backend));
+        let device = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("compat"),
+                required_features: adapter.features() & Capabilities::WANTED_FEATURES,
+                required_limits: adapter.limits(),
+                memory_hints: Default::default(),
+            },
+            None,
+        ));
+        let (device, queue) = match device {
+            Result::Ok(device) => device,
+            Err(e) => {
+                println!(" no device: {}", e);
+                continue;
+            }
+        };
+        // Errors show up through the error scopes instead
+        device.on_uncaptured_error(Box::new(|e| log::error!("{}", e)));
+        let caps = Capabilities::new(&adapter, &device);
+        for check in CHECKS {
+            let status = run_check(&device, &queue, &caps, check);
+            print!(" {:<9}", status.cell());
+            if let Status::Failed(reason) = status {
+                failures.push(format!(
+                    "{} ({:?}) {}: {}",
+                    info.name, info.backend, check.name, reason
+                ));
+            }
+        }
+        println!();
+    }
+
+    for failure in &failures {
+        println!("\n{}", failure);
+    }
+    Ok(())
+}
@@ -0,0 +1,22 @@
+//! Bundles a folder into an [AssetPack](framework::AssetPack), so a demo
+//! can load everything from one file.
+//!
+//! ```text
+//! cargo run -p framework --bin pack -- <folder> <output.pack>
+//! ```
+
+use anyhow::*;
+use framework::PackBuilder;
+
+fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (dir, output) = match args.as_slice() {
+        [dir, output] => (dir, output),
+        _ => bail!("Usage: pack <folder> <output.pack>"),
+    };
+    let mut builder = PackBuilder::new();
+    builder.add_dir(dir)?;
+    builder.write(output)?;
+    println!("Packed {} files into {}", builder.len(), output);
+    Ok(())
+}
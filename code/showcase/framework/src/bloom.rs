@@ -0,0 +1,381 @@
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::post::{fullscreen_pass, PostContext, PostEffect, PostProcessChain};
+
+/// The most mips [Bloom] blurs through. Each one doubles the glow's reach.
+pub const MAX_BLOOM_MIPS: u32 = 6;
+
+/// Changed at runtime with [Bloom::set_settings]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BloomSettings {
+    /// Scene brightness that starts to glow. Above 1 keeps bloom to what
+    /// the HDR target lets go past white.
+    pub threshold: f32,
+    /// How far under `threshold` the glow fades in
+    pub knee: f32,
+    /// How much of the glow is added back onto the scene
+    pub intensity: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    _padding: f32,
+}
+
+impl From<BloomSettings> for BloomUniform {
+    fn from(settings: BloomSettings) -> Self {
+        Self {
+            threshold: settings.threshold,
+            knee: settings.knee,
+            intensity: settings.intensity,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// Makes the bright parts of the scene glow. It needs color past 1 to
+/// pick them out, so it goes before [crate::Tonemap] in a
+/// [PostProcessChain].
+pub struct Bloom {
+    settings: BloomSettings,
+    uniform_buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
+    mips: BloomMips,
+}
+
+/// The half resolution mips and the bind groups that read each of them
+struct BloomMips {
+    views: Vec<wgpu::TextureView>,
+    /// One for each mip
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl Bloom {
+    pub fn new(
+        device: &wgpu::Device,
+        chain: &PostProcessChain,
+        settings: BloomSettings,
+    ) -> Result<Self> {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom::uniform_buffer"),
+            contents: bytemuck::cast_slice(&[BloomUniform::from(settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bloom::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Bloom::sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let pipeline = |entry_point: &str, blend: Option<wgpu::BlendState>| {
+            let shader = framework_shader("bloom.wgsl", include_str!("bloom.wgsl"));
+            RenderPipelineBuilder::new()
+                .layout(&pipeline_layout)
+                .vertex_shader(shader.module())
+                .fragment_shader(shader.module())
+                .vertex_entry_point("vs_main")
+                .fragment_entry_point(entry_point)
+                .color_state(wgpu::ColorTargetState {
+                    format: chain.format(),
+                    blend,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })
+                .build(device)
+        };
+        let add = Some(wgpu::BlendState {
+            color: additive,
+            alpha: additive,
+        });
+        let prefilter_pipeline = pipeline("fs_prefilter", None)?;
+        let downsample_pipeline = pipeline("fs_downsample", None)?;
+        let upsample_pipeline = pipeline("fs_upsample", add)?;
+        let composite_pipeline = pipeline("fs_composite", add)?;
+
+        let mips = BloomMips::new(
+            device,
+            &layout,
+            &sampler,
+            &uniform_buffer,
+            chain.format(),
+            chain.width(),
+            chain.height(),
+        );
+
+        Ok(Self {
+            settings,
+            uniform_buffer,
+            layout,
+            sampler,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            composite_pipeline,
+            format: chain.format(),
+            mips,
+        })
+    }
+
+    pub fn settings(&self) -> BloomSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: BloomSettings) {
+        self.settings = settings;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[BloomUniform::from(settings)]),
+        );
+    }
+
+    fn bind_group(&self, device: &wgpu::Device, view: &wgpu::TextureView) -> wgpu::BindGroup {
+        create_bind_group(
+            device,
+            &self.layout,
+            &self.sampler,
+            &self.uniform_buffer,
+            view,
+        )
+    }
+}
+
+impl PostEffect for Bloom {
+    fn enabled(&self) -> bool {
+        self.settings.intensity > 0.0
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.mips = BloomMips::new(
+            device,
+            &self.layout,
+            &self.sampler,
+            &self.uniform_buffer,
+            self.format,
+            width,
+            height,
+        );
+    }
+
+    /// Copies the input over and adds the glow onto it
+    fn process(&mut self, encoder: &mut wgpu::CommandEncoder, ctx: &PostContext) {
+        crate::cpu_scope!("Bloom::process");
+        let mips = &self.mips;
+        let last = mips.views.len() - 1;
+        fullscreen_pass(
+            encoder,
+            "Bloom::prefilter",
+            &mips.views[0],
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            &self.prefilter_pipeline,
+            &self.bind_group(ctx.device, ctx.input),
+        );
+        for i in 1..=last {
+            fullscreen_pass(
+                encoder,
+                "Bloom::downsample",
+                &mips.views[i],
+                wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                &self.downsample_pipeline,
+                &mips.bind_groups[i - 1],
+            );
+        }
+        for i in (0..last).rev() {
+            fullscreen_pass(
+                encoder,
+                "Bloom::upsample",
+                &mips.views[i],
+                wgpu::LoadOp::Load,
+                &self.upsample_pipeline,
+                &mips.bind_groups[i + 1],
+            );
+        }
+        ctx.copy(encoder);
+        fullscreen_pass(
+            encoder,
+            "Bloom::composite",
+            ctx.output,
+            wgpu::LoadOp::Load,
+            &self.composite_pipeline,
+            &mips.bind_groups[0],
+        );
+    }
+}
+
+impl BloomMips {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let [width, height] = bloom_size(width, height);
+        let mip_level_count = bloom_mip_count(width, height);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Bloom::texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let views = (0..mip_level_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Bloom::mip"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let bind_groups = views
+            .iter()
+            .map(|view| create_bind_group(device, layout, sampler, uniform_buffer, view))
+            .collect();
+        Self { views, bind_groups }
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    uniform_buffer: &wgpu::Buffer,
+    view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Bloom::bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+/// The first mip is half the size of the scene
+fn bloom_size(width: u32, height: u32) -> [u32; 2] {
+    [(width / 2).max(1), (height / 2).max(1)]
+}
+
+/// Halves down to [MAX_BLOOM_MIPS] times, stopping before a side gets
+/// smaller than 4 texels
+fn bloom_mip_count(width: u32, height: u32) -> u32 {
+    let mut count = 1;
+    let mut size = width.min(height);
+    while count < MAX_BLOOM_MIPS && size >= 8 {
+        size /= 2;
+        count += 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_chain_stops_before_tiny_mips() {
+        assert_eq!(bloom_mip_count(960, 540), MAX_BLOOM_MIPS);
+        assert_eq!(bloom_mip_count(16, 400), 3);
+        assert_eq!(bloom_mip_count(1, 1), 1);
+    }
+
+    #[test]
+    fn chain_starts_at_half_size() {
+        assert_eq!(bloom_size(1920, 1080), [960, 540]);
+        assert_eq!(bloom_size(1921, 1081), [960, 540]);
+        assert_eq!(bloom_size(1, 1), [1, 1]);
+    }
+
+    #[test]
+    fn bloom_wgsl_validates() {
+        crate::shader::validate_wgsl(
+            &framework_shader("bloom.wgsl", include_str!("bloom.wgsl")).source,
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+// Bloom for a PostProcessChain. The bright parts of the scene are cut out
+// into the first mip of a half resolution chain, blurred down the chain,
+// added back up it, and the top is added onto the scene.
+
+struct BloomUniform {
+    threshold: f32,
+    // How far under the threshold bloom fades in, so it doesn't pop
+    knee: f32,
+    intensity: f32,
+    _padding: f32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> bloom: BloomUniform;
+
+#include "framework/fullscreen.wgsl"
+
+// Four bilinear taps, averaging the 4x4 texels around `uv`
+fn box_filter(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let o = texel * vec2<f32>(-1.0, 1.0);
+    var color = textureSample(source, source_sampler, uv + o.xx).rgb;
+    color += textureSample(source, source_sampler, uv + o.yx).rgb;
+    color += textureSample(source, source_sampler, uv + o.xy).rgb;
+    color += textureSample(source, source_sampler, uv + o.yy).rgb;
+    return color * 0.25;
+}
+
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = box_filter(in.uv);
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = max(bloom.knee, 0.0001);
+    var soft = clamp(brightness - bloom.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee);
+    let contribution = max(soft, brightness - bloom.threshold) / max(brightness, 0.0001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(box_filter(in.uv), 1.0);
+}
+
+// A 3x3 tent over the smaller mip, added onto the larger one
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    var color = vec3<f32>(0.0);
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let weight = f32((2 - abs(x)) * (2 - abs(y)));
+            color += textureSample(source, source_sampler, in.uv + vec2<f32>(f32(x), f32(y)) * texel).rgb * weight;
+        }
+    }
+    return vec4<f32>(color / 16.0, 1.0);
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(source, source_sampler, in.uv).rgb * bloom.intensity, 0.0);
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use winit::keyboard::KeyCode;
+
+use crate::camera::Camera;
+use crate::input::Input;
+use crate::settings::Settings;
+
+/// A saved camera viewpoint
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraBookmark {
+    pub position: [f32; 3],
+    /// A quaternion as `[x, y, z, w]`
+    pub orientation: [f32; 4],
+}
+
+impl CameraBookmark {
+    pub fn new(camera: &Camera) -> Self {
+        Self {
+            position: camera.position.into(),
+            orientation: camera.orientation().into(),
+        }
+    }
+
+    pub fn camera(&self) -> Camera {
+        Camera::from_orientation(self.position, self.orientation.into())
+    }
+}
+
+struct Transition {
+    from: Camera,
+    to: Camera,
+    elapsed: f32,
+}
+
+/// Ctrl+1 to Ctrl+9 save the camera's viewpoint and 1 to 9 glide back to
+/// it, for comparing the same angle before and after changing something.
+/// Bookmarks are kept in [Settings] for each demo. The framework runs
+/// this for demos that return their camera from
+/// [crate::Demo::camera_mut].
+pub struct CameraBookmarks {
+    pub enabled: bool,
+    /// Which demo the bookmarks belong to. Defaults to the executable's
+    /// name.
+    pub scene: String,
+    /// How long going to a bookmark takes in seconds. 0 jumps straight
+    /// there.
+    pub transition_time: f32,
+    transition: Option<Transition>,
+}
+
+impl Default for CameraBookmarks {
+    fn default() -> Self {
+        let scene = std::env::current_exe()
+            .ok()
+            .and_then(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "default".to_string());
+        Self {
+            enabled: true,
+            scene,
+            transition_time: 0.5,
+            transition: None,
+        }
+    }
+}
+
+impl CameraBookmarks {
+    pub const KEYS: [KeyCode; 9] = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, settings: &Settings, slot: u8) -> Option<CameraBookmark> {
+        settings
+            .camera_bookmarks
+            .get(&self.scene)
+            .and_then(|bookmarks| bookmarks.get(&slot))
+            .copied()
+    }
+
+    pub fn save(&self, settings: &mut Settings, slot: u8, camera: &Camera) {
+        settings
+            .camera_bookmarks
+            .entry(self.scene.clone())
+            .or_default()
+            .insert(slot, CameraBookmark::new(camera));
+    }
+
+    /// Starts moving `camera` towards `target`
+    pub fn go_to(&mut self, camera: &Camera, target: Camera) {
+        self.transition = Some(Transition {
+            from: *camera,
+            to: target,
+            elapsed: 0.0,
+        });
+    }
+
+    pub fn is_transitioning(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    /// Handles the bookmark keys and moves `camera` if it's going to a
+    /// bookmark
+    pub fn update(&mut self, input: &Input, settings: &mut Settings, camera: &mut Camera, dt: f32) {
+        if !self.enabled {
+            return;
+        }
+        let ctrl =
+            input.is_key_held(KeyCode::ControlLeft) || input.is_key_held(KeyCode::ControlRight);
+        for (i, key) in Self::KEYS.iter().enumerate() {
+            if !input.is_key_pressed(*key) {
+                continue;
+            }
+            let slot = i as u8 + 1;
+            if ctrl {
+                self.save(settings, slot, camera);
+                log::info!("Saved camera bookmark {}", slot);
+            } else if let Some(bookmark) = self.get(settings, slot) {
+                self.go_to(camera, bookmark.camera());
+            }
+        }
+
+        if let Some(transition) = &mut self.transition {
+            transition.elapsed += dt;
+            let t = (transition.elapsed / self.transition_time.max(f32::EPSILON)).min(1.0);
+            // Ease in and out so the camera doesn't lurch
+            let eased = t * t * (3.0 - 2.0 * t);
+            *camera = transition.from.lerp(&transition.to, eased);
+            if t >= 1.0 {
+                self.transition = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::*;
+    use winit::event::ElementState;
+
+    #[test]
+    fn saves_and_returns_to_bookmarks() {
+        let mut settings = Settings::default();
+        let mut bookmarks = CameraBookmarks::new();
+        let mut input = Input::new();
+        let saved = Camera::new((1.0, 2.0, 3.0), Deg(30.0), Deg(-10.0));
+        let mut camera = saved;
+
+        input.process_key(KeyCode::ControlLeft, ElementState::Pressed);
+        input.process_key(KeyCode::Digit3, ElementState::Pressed);
+        bookmarks.update(&input, &mut settings, &mut camera, 0.0);
+        input.end_frame();
+        input.process_key(KeyCode::ControlLeft, ElementState::Released);
+        input.process_key(KeyCode::Digit3, ElementState::Released);
+        assert!(bookmarks.get(&settings, 3).is_some());
+
+        camera = Camera::new((0.0, 0.0, 0.0), Deg(-90.0), Deg(0.0));
+        input.process_key(KeyCode::Digit3, ElementState::Pressed);
+        bookmarks.update(&input, &mut settings, &mut camera, 0.25);
+        assert!(bookmarks.is_transitioning());
+        input.end_frame();
+        bookmarks.update(&input, &mut settings, &mut camera, 0.25);
+        assert!(!bookmarks.is_transitioning());
+        assert!((camera.position - saved.position).magnitude() < 1e-5);
+        assert!(camera.forward().dot(saved.forward()) > 0.9999);
+
+        let json = serde_json::to_string(&settings).unwrap();
+        let loaded: Settings = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, settings);
+    }
+}
@@ -0,0 +1,55 @@
+// The metallic-roughness shading model glTF uses, without any bindings
+// so passes that don't read a Material can use it too
+
+struct Surface {
+    albedo: vec4<f32>,
+    // In tangent space, so multiply it by the TBN matrix
+    normal: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    occlusion: f32,
+    emissive: vec3<f32>,
+}
+
+const PBR_PI: f32 = 3.14159265;
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PBR_PI * d * d);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let gv = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let gl = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return gv * gl;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Light reflected towards the viewer by one light. `n`, `v` and `l` are
+// normalized and point away from the surface. `radiance` is the light's
+// color times its attenuation.
+fn pbr_light(surface: Surface, n: vec3<f32>, v: vec3<f32>, l: vec3<f32>, radiance: vec3<f32>) -> vec3<f32> {
+    let h = normalize(v + l);
+    let n_dot_l = max(dot(n, l), 0.0);
+    let n_dot_v = max(dot(n, v), 0.0001);
+    let f0 = mix(vec3<f32>(0.04), surface.albedo.rgb, surface.metallic);
+    let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    let d = distribution_ggx(max(dot(n, h), 0.0), surface.roughness);
+    let g = geometry_smith(n_dot_v, n_dot_l, surface.roughness);
+    let specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 0.0001);
+    // Metals don't have a diffuse part
+    let diffuse = (1.0 - f) * (1.0 - surface.metallic) * surface.albedo.rgb / PBR_PI;
+    return (diffuse + specular) * radiance * n_dot_l;
+}
+
+// Flat ambient light plus emission, which only need adding once
+fn pbr_ambient(surface: Surface, ambient: vec3<f32>) -> vec3<f32> {
+    return ambient * surface.albedo.rgb * surface.occlusion + surface.emissive;
+}
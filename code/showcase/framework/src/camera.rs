@@ -5,6 +5,10 @@ use winit::dpi::PhysicalPosition;
 use winit::event::*;
 use winit::keyboard::KeyCode;
 
+use crate::actions::{Action, ActionMap, Binding};
+use crate::input::Input;
+use crate::scene::Transform;
+
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -15,11 +19,14 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
-#[derive(Debug)]
+/// A camera's position and orientation. Orientation is a quaternion so
+/// the camera can roll and blend smoothly between viewpoints, but
+/// [Camera::yaw] and [Camera::pitch] are still there for mouse look. Yaw
+/// is measured from +X towards +Z, like it always was.
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Camera {
     pub position: Point3<f32>,
-    yaw: Rad<f32>,
-    pitch: Rad<f32>,
+    orientation: Quaternion<f32>,
 }
 
 impl Camera {
@@ -27,26 +34,123 @@ impl Camera {
         position: V,
         yaw: Y,
         pitch: P,
+    ) -> Self {
+        let mut camera = Self::from_orientation(position, Quaternion::one());
+        camera.set_yaw_pitch_roll(yaw.into(), pitch.into(), Rad(0.0));
+        camera
+    }
+
+    /// A camera looking down `orientation`'s -Z axis with +Y up, which is
+    /// how glTF cameras and [Transform] rotations work
+    pub fn from_orientation<V: Into<Point3<f32>>>(
+        position: V,
+        orientation: Quaternion<f32>,
     ) -> Self {
         Self {
             position: position.into(),
-            yaw: yaw.into(),
-            pitch: pitch.into(),
+            orientation: orientation.normalize(),
         }
     }
 
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
-        let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
-        let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+    /// For cameras driven by an [crate::Animator] or a physics body.
+    /// Scale is ignored.
+    pub fn from_transform(transform: &Transform) -> Self {
+        Self::from_orientation(Point3::from_vec(transform.translation), transform.rotation)
+    }
 
-        Matrix4::look_to_rh(
-            self.position,
-            Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
-            Vector3::unit_y(),
+    pub fn transform(&self) -> Transform {
+        Transform::new(
+            self.position.to_vec(),
+            self.orientation,
+            Vector3::new(1.0, 1.0, 1.0),
         )
     }
+
+    pub fn orientation(&self) -> Quaternion<f32> {
+        self.orientation
+    }
+
+    pub fn set_orientation(&mut self, orientation: Quaternion<f32>) {
+        // Bad input shouldn't leave the camera stuck on NaN
+        if orientation.magnitude2().is_normal() {
+            self.orientation = orientation.normalize();
+        }
+    }
+
+    pub fn yaw(&self) -> Rad<f32> {
+        let forward = self.forward();
+        Rad(forward.z.atan2(forward.x))
+    }
+
+    pub fn pitch(&self) -> Rad<f32> {
+        Rad(self.forward().y.clamp(-1.0, 1.0).asin())
+    }
+
+    /// How far the camera is tilted around the direction it's looking,
+    /// clockwise from the viewer's point of view
+    pub fn roll(&self) -> Rad<f32> {
+        let level = yaw_pitch_rotation(self.yaw(), self.pitch());
+        let roll = level.conjugate() * self.orientation;
+        Rad(-2.0 * roll.v.z.atan2(roll.s))
+    }
+
+    pub fn set_roll<R: Into<Rad<f32>>>(&mut self, roll: R) {
+        self.set_yaw_pitch_roll(self.yaw(), self.pitch(), roll.into());
+    }
+
+    /// Turns the camera, keeping it from looking straight up or down where
+    /// yaw stops making sense. Roll is kept.
+    pub fn rotate<Y: Into<Rad<f32>>, P: Into<Rad<f32>>>(&mut self, yaw: Y, pitch: P) {
+        let yaw = self.yaw() + yaw.into();
+        let pitch = self.pitch() + pitch.into();
+        self.set_yaw_pitch_roll(yaw, pitch, self.roll());
+    }
+
+    fn set_yaw_pitch_roll(&mut self, yaw: Rad<f32>, pitch: Rad<f32>, roll: Rad<f32>) {
+        if !(yaw.0.is_finite() && pitch.0.is_finite() && roll.0.is_finite()) {
+            return;
+        }
+        let pitch = Rad(pitch.0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+        self.orientation = yaw_pitch_rotation(yaw, pitch) * Quaternion::from_angle_z(-roll);
+    }
+
+    /// The direction the camera is looking
+    pub fn forward(&self) -> Vector3<f32> {
+        self.orientation.rotate_vector(-Vector3::unit_z())
+    }
+
+    pub fn right(&self) -> Vector3<f32> {
+        self.orientation.rotate_vector(Vector3::unit_x())
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        self.orientation.rotate_vector(Vector3::unit_y())
+    }
+
+    /// Blends position and orientation, taking the shortest way around.
+    /// For moving between saved viewpoints.
+    pub fn lerp(&self, other: &Camera, t: f32) -> Camera {
+        let mut target = other.orientation;
+        if self.orientation.dot(target) < 0.0 {
+            target = -target;
+        }
+        Camera {
+            position: self.position + (other.position - self.position) * t,
+            orientation: self.orientation.slerp(target, t).normalize(),
+        }
+    }
+
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_to_rh(self.position, self.forward(), self.up())
+    }
 }
 
+/// Rotates -Z to point along `yaw` and `pitch`
+fn yaw_pitch_rotation(yaw: Rad<f32>, pitch: Rad<f32>) -> Quaternion<f32> {
+    Quaternion::from_angle_y(-yaw - Rad(FRAC_PI_2)) * Quaternion::from_angle_x(pitch)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Projection {
     aspect: f32,
     fovy: Rad<f32>,
@@ -71,10 +175,28 @@ impl Projection {
     pub fn calc_matrix(&self) -> Matrix4<f32> {
         OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
     }
+
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    pub fn fovy(&self) -> Rad<f32> {
+        self.fovy
+    }
+
+    pub fn znear(&self) -> f32 {
+        self.znear
+    }
+
+    pub fn zfar(&self) -> f32 {
+        self.zfar
+    }
 }
 
 #[derive(Debug)]
 pub struct CameraController {
+    /// What moves the camera. Starts out as [ActionMap::camera].
+    pub actions: ActionMap,
     amount_left: f32,
     amount_right: f32,
     amount_forward: f32,
@@ -89,8 +211,16 @@ pub struct CameraController {
 }
 
 impl CameraController {
+    /// Radians turned per pixel of mouse motion, and distance moved per
+    /// pixel of scrolling, before `sensitivity` is applied
+    const LOOK_SCALE: f32 = 1.0 / 60.0;
+    const MAX_STEP: f32 = 1.0 / 60.0;
+    /// Keeps a huge dt from taking forever
+    const MAX_STEPS: f32 = 64.0;
+
     pub fn new(speed: f32, sensitivity: f32) -> Self {
         Self {
+            actions: ActionMap::camera(),
             amount_left: 0.0,
             amount_right: 0.0,
             amount_forward: 0.0,
@@ -111,38 +241,42 @@ impl CameraController {
         } else {
             0.0
         };
-        match key {
-            KeyCode::KeyW | KeyCode::ArrowUp => {
-                self.amount_forward = amount;
-                true
-            }
-            KeyCode::KeyS | KeyCode::ArrowDown => {
-                self.amount_backward = amount;
-                true
-            }
-            KeyCode::KeyA | KeyCode::ArrowLeft => {
-                self.amount_left = amount;
-                true
-            }
-            KeyCode::KeyD | KeyCode::ArrowRight => {
-                self.amount_right = amount;
-                true
-            }
-            KeyCode::Space => {
-                self.amount_up = amount;
-                true
-            }
-            KeyCode::ShiftLeft => {
-                self.amount_down = amount;
-                true
-            }
-            _ => false,
+        let actions = self
+            .actions
+            .actions_for(Binding::Key(key))
+            .collect::<Vec<_>>();
+        for action in &actions {
+            self.set_amount(*action, amount);
+        }
+        !actions.is_empty()
+    }
+
+    /// Reads every action from [Input] instead of waiting for key events,
+    /// which also picks up mouse buttons, MIDI controls and gamepads
+    pub fn process_input(&mut self, input: &Input) {
+        for action in Action::ALL.iter().copied() {
+            let amount = self.actions.value(input, action);
+            self.set_amount(action, amount);
         }
     }
 
+    fn set_amount(&mut self, action: Action, amount: f32) {
+        let field = match action {
+            Action::MoveForward => &mut self.amount_forward,
+            Action::MoveBackward => &mut self.amount_backward,
+            Action::MoveLeft => &mut self.amount_left,
+            Action::MoveRight => &mut self.amount_right,
+            Action::MoveUp => &mut self.amount_up,
+            Action::MoveDown => &mut self.amount_down,
+        };
+        *field = amount;
+    }
+
+    /// Accumulates motion, which [update_camera](Self::update_camera)
+    /// applies and then clears
     pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        self.rotate_horizontal = mouse_dx as f32;
-        self.rotate_vertical = mouse_dy as f32;
+        self.rotate_horizontal += mouse_dx as f32;
+        self.rotate_vertical += mouse_dy as f32;
     }
 
     pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
@@ -156,42 +290,207 @@ impl CameraController {
     pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
         let dt = dt.as_secs_f32();
 
-        // Move forward/backward and left/right
-        let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
-        let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
-        let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
-        camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
-        camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
+        // Mouse motion and scrolling are distances rather than speeds, so
+        // they aren't scaled by dt
+        let yaw = self.rotate_horizontal * self.sensitivity * Self::LOOK_SCALE;
+        let pitch = -self.rotate_vertical * self.sensitivity * Self::LOOK_SCALE;
+        let scroll = self.scroll * self.speed * self.sensitivity * Self::LOOK_SCALE;
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+        self.scroll = 0.0;
+
+        // Turning and moving are interleaved in steps of at most MAX_STEP,
+        // so the path curves the same way at any frame rate
+        let steps = (dt / Self::MAX_STEP).ceil().clamp(1.0, Self::MAX_STEPS);
+        let step_dt = dt / steps;
+        let half_yaw = Rad(yaw / steps * 0.5);
+        let half_pitch = Rad(pitch / steps * 0.5);
+        for _ in 0..steps as u32 {
+            // Moving in the middle of each step's turn is more accurate
+            // than before or after it
+            camera.rotate(half_yaw, half_pitch);
+            self.move_camera(camera, step_dt);
+            camera.rotate(half_yaw, half_pitch);
+        }
 
         // Move in/out (aka. "zoom")
         // Note: this isn't an actual zoom. The camera's position
         // changes when zooming. I've added this to make it easier
         // to get closer to an object you want to focus on.
-        let (pitch_sin, pitch_cos) = camera.pitch.0.sin_cos();
-        let scrollward =
-            Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
-        camera.position += scrollward * self.scroll * self.speed * self.sensitivity * dt;
-        self.scroll = 0.0;
+        camera.position += camera.forward() * scroll;
+    }
+
+    fn move_camera(&self, camera: &mut Camera, dt: f32) {
+        // Move forward/backward and left/right
+        let (yaw_sin, yaw_cos) = camera.yaw().0.sin_cos();
+        let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
+        let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
+        camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
+        camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
 
-        // Move up/down. Since we don't use roll, we can just
-        // modify the y coordinate directly.
+        // Move up/down. This stays along the world's up axis even when the
+        // camera is rolled, so the controls don't tilt with the view.
         camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
+    }
+}
+
+/// Spins the camera around `target` like a model on a turntable, for
+/// model viewers and rotating previews. It works alongside
+/// [CameraController], so the camera can still be moved while it spins.
+///
+/// ```ignore
+/// // A GIF that loops perfectly, unless the turntable is stopped
+/// let recorder = turntable
+///     .revolution_time()
+///     .map(|time| record_gif(time.as_secs_f32(), 30, "preview.gif"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Turntable {
+    pub enabled: bool,
+    /// What the camera circles around. It spins about the vertical axis
+    /// through this point.
+    pub target: Point3<f32>,
+    /// How fast it turns each second. Negative goes the other way.
+    pub speed: Rad<f32>,
+    /// Turns it on and off
+    pub key: KeyCode,
+}
 
-        // Rotate
-        camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
-        camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity * dt;
+impl Turntable {
+    pub fn new<T: Into<Point3<f32>>, S: Into<Rad<f32>>>(target: T, speed: S) -> Self {
+        Self {
+            enabled: false,
+            target: target.into(),
+            speed: speed.into(),
+            key: KeyCode::KeyT,
+        }
+    }
 
-        // If process_mouse isn't called every frame, these values
-        // will not get set to zero, and the camera will rotate
-        // when moving in a non cardinal direction.
-        self.rotate_horizontal = 0.0;
-        self.rotate_vertical = 0.0;
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    /// How long one full turn takes, or `None` if the speed is zero, too
+    /// small for a turn to ever finish, or not finite
+    pub fn revolution_time(&self) -> Option<Duration> {
+        let time = std::f32::consts::TAU / self.speed.0.abs();
+        Duration::try_from_secs_f32(time)
+            .ok()
+            .filter(|time| !time.is_zero())
+    }
+
+    /// Toggles the turntable when [Turntable::key] is pressed
+    pub fn process_input(&mut self, input: &Input) {
+        if input.is_key_pressed(self.key) {
+            self.toggle();
+        }
+    }
+
+    pub fn update_camera(&self, camera: &mut Camera, dt: Duration) {
+        if !self.enabled {
+            return;
+        }
+        // Turning the camera with its position keeps the target in the
+        // same place on screen
+        let rotation = Quaternion::from_angle_y(-self.speed * dt.as_secs_f32());
+        camera.position = self.target + rotation.rotate_vector(camera.position - self.target);
+        camera.set_orientation(rotation * camera.orientation());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    /// Holds forward and turns by `motion` over one second
+    fn simulate(fps: u32, motion: f64) -> Camera {
+        let mut camera = Camera::new((0.0, 0.0, 0.0), Rad(0.0), Rad(0.0));
+        let mut controller = CameraController::new(4.0, 0.4);
+        controller.process_keyboard(KeyCode::KeyW, ElementState::Pressed);
+        for _ in 0..fps {
+            controller.process_mouse(motion / fps as f64, 0.0);
+            controller.update_camera(&mut camera, Duration::from_secs_f64(1.0 / fps as f64));
+        }
+        camera
+    }
+
+    #[test]
+    fn frame_rate_independent() {
+        let slow = simulate(30, 300.0);
+        let fast = simulate(240, 300.0);
+        assert!((slow.yaw().0 - fast.yaw().0).abs() < 1e-4);
+        assert!((slow.position - fast.position).magnitude() < 0.01);
+    }
+
+    #[test]
+    fn pitch_is_clamped() {
+        let mut camera = Camera::new((0.0, 0.0, 0.0), Deg(0.0), Deg(120.0));
+        assert!(camera.pitch().0 < FRAC_PI_2);
+        camera.rotate(Rad(0.0), Rad(f32::NAN));
+        assert!(camera.pitch().0.is_finite());
+        camera.rotate(Rad(10.0 * PI), Rad(-10.0));
+        assert!(camera.pitch().0 > -FRAC_PI_2);
+        assert!(camera.yaw().0.abs() <= PI);
+    }
+
+    #[test]
+    fn orientation_matches_yaw_and_pitch() {
+        let mut camera = Camera::new((0.0, 0.0, 0.0), Deg(-90.0), Deg(-20.0));
+        let forward = camera.forward();
+        assert!((forward - Vector3::new(0.0, -0.342, -0.940)).magnitude() < 1e-3);
+        assert!((Deg::from(camera.yaw()).0 + 90.0).abs() < 1e-3);
+        assert!((Deg::from(camera.pitch()).0 + 20.0).abs() < 1e-3);
+
+        camera.set_roll(Deg(30.0));
+        assert!((Deg::from(camera.roll()).0 - 30.0).abs() < 1e-3);
+        // Rolling doesn't change where the camera looks
+        assert!((camera.forward() - forward).magnitude() < 1e-5);
+        assert!(camera.forward().dot(camera.up()).abs() < 1e-5);
+
+        camera.rotate(Deg(10.0), Deg(5.0));
+        assert!((Deg::from(camera.roll()).0 - 30.0).abs() < 1e-3);
+        assert!((Deg::from(camera.pitch()).0 + 15.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn lerp_takes_the_short_way() {
+        let a = Camera::new((0.0, 0.0, 0.0), Deg(170.0), Deg(0.0));
+        let b = Camera::new((2.0, 0.0, 0.0), Deg(-170.0), Deg(0.0));
+        let half = a.lerp(&b, 0.5);
+        assert!((Deg::from(half.yaw()).0.abs() - 180.0).abs() < 1e-2);
+        assert!((half.position.x - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn turntable_circles_the_target() {
+        let mut turntable = Turntable::new((1.0, 0.0, 0.0), Deg(90.0));
+        turntable.toggle();
+        let start = Camera::new((1.0, 1.0, 5.0), Deg(-90.0), Deg(-10.0));
+        let mut camera = start;
+        turntable.update_camera(&mut camera, Duration::from_secs(1));
+        // A quarter turn, still looking at the same point
+        assert!((camera.position - Point3::new(-4.0, 1.0, 0.0)).magnitude() < 1e-4);
+        assert!(Deg::from(camera.yaw()).0.abs() < 1e-3);
+        assert!((Deg::from(camera.pitch()).0 + 10.0).abs() < 1e-3);
+
+        let steps = 120;
+        let dt = turntable.revolution_time().unwrap() / steps;
+        let mut camera = start;
+        for _ in 0..steps {
+            turntable.update_camera(&mut camera, dt);
+        }
+        assert!((camera.position - start.position).magnitude() < 1e-3);
+        assert!(camera.forward().dot(start.forward()) > 0.9999);
+    }
 
-        // Keep the camera's angle from going too high/low.
-        if camera.pitch < -Rad(SAFE_FRAC_PI_2) {
-            camera.pitch = -Rad(SAFE_FRAC_PI_2);
-        } else if camera.pitch > Rad(SAFE_FRAC_PI_2) {
-            camera.pitch = Rad(SAFE_FRAC_PI_2);
+    #[test]
+    fn stopped_turntables_never_finish_a_turn() {
+        let mut turntable = Turntable::new((0.0, 0.0, 0.0), Deg(-90.0));
+        assert_eq!(turntable.revolution_time(), Some(Duration::from_secs(4)));
+        for speed in [0.0, f32::MIN_POSITIVE / 4.0, f32::INFINITY, f32::NAN] {
+            turntable.speed = Rad(speed);
+            assert_eq!(turntable.revolution_time(), None);
         }
     }
 }
@@ -0,0 +1,90 @@
+/// What the device can do, worked out once in [crate::Display::new].
+/// Subsystems with more than one code path pick between them with this
+/// rather than checking limits or the target themselves.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Capabilities {
+    /// Not on WebGL2
+    pub compute_shaders: bool,
+    /// Storage buffers readable from vertex shaders
+    pub vertex_storage_buffers: bool,
+    pub fragment_storage_buffers: bool,
+    pub bc_textures: bool,
+    /// Linear filtering of Rgba32Float and friends
+    pub float32_filterable: bool,
+    /// `draw_indirect` and `draw_indexed_indirect`
+    pub indirect_draws: bool,
+    pub multi_draw_indirect: bool,
+    pub timestamp_queries: bool,
+    /// Timestamps between passes rather than only at their edges, see
+    /// [crate::GpuProfiler]
+    pub timestamps_inside_encoders: bool,
+    pub max_texture_size: u32,
+}
+
+impl Capabilities {
+    /// Optional features [crate::Display] turns on when the adapter has
+    /// them
+    pub const WANTED_FEATURES: wgpu::Features = wgpu::Features::TEXTURE_COMPRESSION_BC
+        .union(wgpu::Features::FLOAT32_FILTERABLE)
+        .union(wgpu::Features::MULTI_DRAW_INDIRECT)
+        .union(wgpu::Features::TIMESTAMP_QUERY)
+        .union(wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS);
+
+    pub fn new(adapter: &wgpu::Adapter, device: &wgpu::Device) -> Self {
+        Self::from_parts(
+            device.features(),
+            &device.limits(),
+            adapter.get_downlevel_capabilities().flags,
+        )
+    }
+
+    fn from_parts(
+        features: wgpu::Features,
+        limits: &wgpu::Limits,
+        downlevel: wgpu::DownlevelFlags,
+    ) -> Self {
+        let storage = limits.max_storage_buffers_per_shader_stage > 0;
+        Self {
+            compute_shaders: downlevel.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+                && limits.max_compute_workgroups_per_dimension > 0,
+            vertex_storage_buffers: storage
+                && downlevel.contains(wgpu::DownlevelFlags::VERTEX_STORAGE),
+            fragment_storage_buffers: storage
+                && downlevel.contains(wgpu::DownlevelFlags::FRAGMENT_STORAGE),
+            bc_textures: features.contains(wgpu::Features::TEXTURE_COMPRESSION_BC),
+            float32_filterable: features.contains(wgpu::Features::FLOAT32_FILTERABLE),
+            indirect_draws: downlevel.contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION),
+            multi_draw_indirect: features.contains(wgpu::Features::MULTI_DRAW_INDIRECT),
+            timestamp_queries: features.contains(wgpu::Features::TIMESTAMP_QUERY),
+            timestamps_inside_encoders: features
+                .contains(wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS),
+            max_texture_size: limits.max_texture_dimension_2d,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webgl2_has_no_compute_or_storage() {
+        let caps = Capabilities::from_parts(
+            wgpu::Features::empty(),
+            &wgpu::Limits::downlevel_webgl2_defaults(),
+            wgpu::DownlevelFlags::empty(),
+        );
+        assert!(!caps.compute_shaders);
+        assert!(!caps.vertex_storage_buffers);
+        assert!(!caps.indirect_draws);
+        assert_eq!(caps.max_texture_size, 2048);
+
+        let caps = Capabilities::from_parts(
+            wgpu::Features::FLOAT32_FILTERABLE,
+            &wgpu::Limits::default(),
+            wgpu::DownlevelFlags::all(),
+        );
+        assert!(caps.compute_shaders && caps.vertex_storage_buffers && caps.indirect_draws);
+        assert!(caps.float32_filterable && !caps.bc_textures);
+    }
+}
@@ -0,0 +1,512 @@
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::*;
+
+use crate::color::{linear_to_srgb, srgb_to_linear};
+use crate::msaa::MsaaTarget;
+use crate::texture::Texture;
+use crate::Display;
+
+/// Copies a texture into a mappable buffer so it can be read on the CPU.
+/// Only 8 bit RGBA and BGRA formats are supported.
+pub struct TextureReadback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+    unpadded_bytes_per_row: u32,
+}
+
+impl TextureReadback {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        // wgpu requires texture -> buffer copies to be aligned using
+        // wgpu::COPY_BYTES_PER_ROW_ALIGNMENT, so we need to strip the
+        // padding when reading the data back
+        let unpadded_bytes_per_row = width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("TextureReadback::buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            width,
+            height,
+            padded_bytes_per_row,
+            unpadded_bytes_per_row,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Records a copy of `texture` into the readback buffer. The texture
+    /// needs `COPY_SRC` usage and the same size as this readback.
+    pub fn copy(&self, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Waits for the GPU and returns the pixels as tightly packed RGBA.
+    /// `format` is the format of the texture that was copied. This blocks,
+    /// so it doesn't work on the web.
+    pub fn read(&self, device: &wgpu::Device, format: wgpu::TextureFormat) -> Result<Vec<u8>> {
+        let swizzle = match format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            _ => bail!("Unable to read back {:?} textures", format),
+        };
+
+        let slice = self.buffer.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv()??;
+
+        let padded = slice.get_mapped_range();
+        let mut data = Vec::with_capacity((self.unpadded_bytes_per_row * self.height) as usize);
+        for row in padded.chunks(self.padded_bytes_per_row as usize) {
+            data.extend_from_slice(&row[..self.unpadded_bytes_per_row as usize]);
+        }
+        drop(padded);
+        self.buffer.unmap();
+
+        if swizzle {
+            for pixel in data.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// How a [Screenshot] is rendered and saved
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScreenshotOptions {
+    /// Renders this many times wider and taller and averages down to the
+    /// window's size, for smoother edges. 1 is off, 2 or 4 are usual.
+    pub supersample: u32,
+    /// Whether the demo should draw its gui, text and debug lines into
+    /// the screenshot, see [Screenshot::include_ui]
+    pub include_ui: bool,
+    /// Saves the alpha channel, for scenes cleared to transparent.
+    /// Otherwise the image is saved as opaque RGB.
+    pub alpha: bool,
+}
+
+impl Default for ScreenshotOptions {
+    fn default() -> Self {
+        Self {
+            supersample: 1,
+            include_ui: true,
+            alpha: false,
+        }
+    }
+}
+
+/// A frame rendered offscreen and saved as a PNG. It can be bigger than
+/// the window and leave out overlays, for clean images of a demo. The
+/// demo draws into it with the code it draws the window with, using
+/// [Screenshot::color_attachment] and [Screenshot::create_depth_texture]
+/// in place of the [Display] ones. Anything sized to the window, like an
+/// [crate::HdrPipeline], needs resizing to [Screenshot::width] and
+/// [Screenshot::height] for the frame.
+///
+/// ```ignore
+/// let shot = Screenshot::new(display, ScreenshotOptions { supersample: 4, include_ui: false, ..Default::default() });
+/// let depth = shot.create_depth_texture(&display.device);
+/// let mut encoder = display.device.create_command_encoder(&Default::default());
+/// {
+///     let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+///         color_attachments: &[Some(shot.color_attachment(wgpu::LoadOp::Clear(wgpu::Color::BLACK)))],
+///         depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+///             view: &depth.view,
+///             depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Clear(1.0), store: wgpu::StoreOp::Store }),
+///             stencil_ops: None,
+///         }),
+///         ..Default::default()
+///     });
+///     self.draw_scene(&mut pass);
+/// }
+/// if shot.include_ui() {
+///     self.text.draw(...);
+/// }
+/// display.queue.submit([encoder.finish()]);
+/// shot.save(display, "screenshot.png")?;
+/// ```
+pub struct Screenshot {
+    options: ScreenshotOptions,
+    /// The window's configuration at the screenshot's size
+    config: wgpu::SurfaceConfiguration,
+    texture: Texture<'static>,
+    msaa: MsaaTarget,
+}
+
+impl Screenshot {
+    /// Sized to the window times [ScreenshotOptions::supersample], in the
+    /// surface's format and with the display's sample count, so pipelines
+    /// that draw to the window can draw to this too
+    pub fn new(display: &Display, options: ScreenshotOptions) -> Self {
+        let max = display.device.limits().max_texture_dimension_2d;
+        let largest = display.config.width.max(display.config.height).max(1);
+        let supersample = options.supersample.clamp(1, (max / largest).max(1));
+        if supersample != options.supersample {
+            log::warn!(
+                "{}x supersampling is too big for this GPU, using {}x",
+                options.supersample,
+                supersample
+            );
+        }
+        let options = ScreenshotOptions {
+            supersample,
+            ..options
+        };
+        let mut config = display.config.clone();
+        config.width = display.config.width.max(1) * supersample;
+        config.height = display.config.height.max(1) * supersample;
+        let texture = Texture::from_descriptor(
+            &display.device,
+            wgpu::TextureDescriptor {
+                label: Some("Screenshot::texture"),
+                size: wgpu::Extent3d {
+                    width: config.width,
+                    height: config.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: config.format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            },
+        );
+        let msaa = MsaaTarget::new(
+            &display.device,
+            display.adapter(),
+            &config,
+            display.sample_count(),
+        );
+        Self {
+            options,
+            config,
+            texture,
+            msaa,
+        }
+    }
+
+    /// With the supersampling clamped to what the GPU can render
+    pub fn options(&self) -> ScreenshotOptions {
+        self.options
+    }
+
+    /// Whether the demo should draw its overlays into the screenshot
+    pub fn include_ui(&self) -> bool {
+        self.options.include_ui
+    }
+
+    /// The size it's rendered at, before averaging down
+    pub fn width(&self) -> u32 {
+        self.config.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.config.height
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    /// Like [Display::color_attachment] for the screenshot
+    pub fn color_attachment(
+        &self,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'_> {
+        self.msaa.color_attachment(&self.texture.view, load)
+    }
+
+    /// Like [Display::create_depth_texture] for the screenshot
+    pub fn create_depth_texture(&self, device: &wgpu::Device) -> Texture<'static> {
+        Texture::create_depth_texture_multisampled(device, &self.config, self.msaa.sample_count())
+    }
+
+    /// Reads the screenshot back and writes it to `path` as a PNG. Submit
+    /// the commands that draw it first. This blocks, so it doesn't work
+    /// on the web.
+    pub fn save<P: AsRef<Path>>(&self, display: &Display, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let readback = TextureReadback::new(&display.device, self.width(), self.height());
+        let mut encoder = display
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Screenshot::save"),
+            });
+        readback.copy(&mut encoder, &self.texture.texture);
+        display.queue.submit(std::iter::once(encoder.finish()));
+        let pixels = readback.read(&display.device, self.format())?;
+
+        let factor = self.options.supersample;
+        let pixels = downsample(
+            &pixels,
+            self.width(),
+            self.height(),
+            factor,
+            holds_srgb(self.format()),
+        );
+        let (width, height) = (self.width() / factor, self.height() / factor);
+        let image = image::RgbaImage::from_raw(width, height, pixels)
+            .context("Screenshot pixels don't match its size")?;
+        let result = if self.options.alpha {
+            image.save(path)
+        } else {
+            image::DynamicImage::ImageRgba8(image).to_rgb8().save(path)
+        };
+        result.with_context(|| format!("Unable to save {}", path.display()))?;
+        log::info!("Saved {}", path.display());
+        Ok(())
+    }
+}
+
+/// Whether pixels read from `format` are sRGB encoded. That includes
+/// formats like `Bgra8Unorm`, which [crate::PostProcessChain] encodes into
+/// by hand.
+fn holds_srgb(format: wgpu::TextureFormat) -> bool {
+    format.is_srgb() || crate::post::needs_encoding(format)
+}
+
+/// Averages each `factor` by `factor` block of RGBA pixels into one.
+/// sRGB colors are averaged as linear light, so edges don't darken.
+fn downsample(pixels: &[u8], width: u32, height: u32, factor: u32, srgb: bool) -> Vec<u8> {
+    if factor <= 1 {
+        return pixels.to_vec();
+    }
+    let to_linear = |v: u8| {
+        let v = v as f32 / 255.0;
+        if srgb {
+            srgb_to_linear(v)
+        } else {
+            v
+        }
+    };
+    let from_linear = |v: f32| {
+        let v = if srgb { linear_to_srgb(v) } else { v };
+        (v.clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+    let (out_width, out_height) = (width / factor, height / factor);
+    let count = (factor * factor) as f32;
+    let mut out = Vec::with_capacity((out_width * out_height * 4) as usize);
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let mut sum = [0.0f32; 4];
+            for sy in 0..factor {
+                for sx in 0..factor {
+                    let i = (((y * factor + sy) * width + x * factor + sx) * 4) as usize;
+                    for c in 0..3 {
+                        sum[c] += to_linear(pixels[i + c]);
+                    }
+                    sum[3] += pixels[i + 3] as f32 / 255.0;
+                }
+            }
+            out.extend_from_slice(&[
+                from_linear(sum[0] / count),
+                from_linear(sum[1] / count),
+                from_linear(sum[2] / count),
+                (sum[3] / count * 255.0).round() as u8,
+            ]);
+        }
+    }
+    out
+}
+
+/// Starts recording an animated GIF. Call [GifRecorder::capture] with the
+/// frame's texture every frame until [GifRecorder::is_finished] is true.
+///
+/// The surface needs to be readable for this to work, see
+/// [Display::enable_readback].
+pub fn record_gif<P: AsRef<Path>>(duration: f32, fps: u32, path: P) -> GifRecorder {
+    GifRecorder {
+        path: path.as_ref().to_path_buf(),
+        fps: fps.max(1),
+        frame_count: (duration * fps.max(1) as f32).ceil().max(1.0) as usize,
+        frames: Vec::new(),
+        readback: None,
+        since_capture: Duration::MAX,
+        finished: false,
+    }
+}
+
+pub struct GifRecorder {
+    path: PathBuf,
+    fps: u32,
+    frame_count: usize,
+    frames: Vec<Vec<u8>>,
+    readback: Option<TextureReadback>,
+    since_capture: Duration,
+    finished: bool,
+}
+
+impl GifRecorder {
+    /// The time between frames of the GIF. Advancing [crate::Time] by this
+    /// instead of the wall clock gives smooth output even if capturing is
+    /// slow.
+    pub fn frame_delta(&self) -> Duration {
+        Duration::from_secs_f32(1.0 / self.fps as f32)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// How far through the recording we are, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        self.frames.len() as f32 / self.frame_count as f32
+    }
+
+    /// Grabs `texture` if enough time has passed since the last frame.
+    /// Call this after submitting the frame's commands but before
+    /// presenting. Writes the file once all frames have been captured.
+    pub fn capture(&mut self, display: &Display, texture: &wgpu::Texture) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.since_capture = self.since_capture.saturating_add(display.time.delta());
+        if self.since_capture < self.frame_delta() {
+            return Ok(());
+        }
+        self.since_capture = Duration::ZERO;
+
+        let size = texture.size();
+        let readback = match &self.readback {
+            Some(readback) => readback,
+            None => self.readback.insert(TextureReadback::new(
+                &display.device,
+                size.width,
+                size.height,
+            )),
+        };
+        if readback.width() != size.width || readback.height() != size.height {
+            bail!("The window was resized while recording a GIF");
+        }
+
+        let mut encoder = display
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("GifRecorder::capture"),
+            });
+        readback.copy(&mut encoder, texture);
+        display.queue.submit(std::iter::once(encoder.finish()));
+        self.frames
+            .push(readback.read(&display.device, texture.format())?);
+
+        if self.frames.len() >= self.frame_count {
+            self.finished = true;
+            let frames = std::mem::take(&mut self.frames);
+            save_gif(&self.path, frames, size.width, size.height, self.fps)?;
+            log::info!("Saved {}", self.path.display());
+        }
+        Ok(())
+    }
+}
+
+/// Encodes RGBA frames as a looping GIF.
+pub fn save_gif<P: AsRef<Path>>(
+    path: P,
+    frames: Vec<Vec<u8>>,
+    width: u32,
+    height: u32,
+    fps: u32,
+) -> Result<()> {
+    use gif::{Encoder, Frame, Repeat};
+
+    let width = u16::try_from(width).context("GIFs can't be wider than 65535 pixels")?;
+    let height = u16::try_from(height).context("GIFs can't be taller than 65535 pixels")?;
+    let file = std::fs::File::create(path.as_ref())
+        .with_context(|| format!("Unable to create {}", path.as_ref().display()))?;
+    let mut encoder = Encoder::new(file, width, height, &[])?;
+    encoder.set_repeat(Repeat::Infinite)?;
+
+    // GIF delays are in hundredths of a second
+    let delay = (100.0 / fps.max(1) as f32).round() as u16;
+    for mut pixels in frames {
+        let mut frame = Frame::from_rgba_speed(width, height, &mut pixels, 10);
+        frame.delay = delay;
+        encoder.write_frame(&frame)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downsampling_averages_linear_light() {
+        // A 2x2 block of black and white, and a 2x2 block of grey
+        #[rustfmt::skip]
+        let pixels = [
+            0, 0, 0, 255,       255, 255, 255, 255, 128, 128, 128, 0, 128, 128, 128, 0,
+            255, 255, 255, 255, 0, 0, 0, 255,       128, 128, 128, 0, 128, 128, 128, 0,
+        ];
+        assert_eq!(
+            downsample(&pixels, 4, 2, 2, false),
+            [128, 128, 128, 255, 128, 128, 128, 0]
+        );
+        // Half white is brighter than 128 once it's encoded as sRGB
+        let srgb = downsample(&pixels, 4, 2, 2, true);
+        assert_eq!(&srgb[..4], [188, 188, 188, 255]);
+        assert_eq!(&srgb[4..], [128, 128, 128, 0]);
+        assert_eq!(downsample(&pixels, 4, 2, 1, true), pixels);
+    }
+
+    #[test]
+    fn unorm_surfaces_downsample_as_srgb() {
+        assert!(holds_srgb(wgpu::TextureFormat::Bgra8UnormSrgb));
+        assert!(holds_srgb(wgpu::TextureFormat::Bgra8Unorm));
+        assert!(holds_srgb(wgpu::TextureFormat::Rgba8Unorm));
+        assert!(!holds_srgb(wgpu::TextureFormat::Rgba16Float));
+
+        // Black and white stripes next to a flat 64
+        #[rustfmt::skip]
+        let pixels = [
+            0, 0, 0, 255, 255, 255, 255, 255, 64, 64, 64, 255, 64, 64, 64, 255,
+            0, 0, 0, 255, 255, 255, 255, 255, 64, 64, 64, 255, 64, 64, 64, 255,
+        ];
+        let format = wgpu::TextureFormat::Bgra8Unorm;
+        let out = downsample(&pixels, 4, 2, 2, holds_srgb(format));
+        assert_eq!(out.len(), 8);
+        assert_eq!(&out[..4], [188, 188, 188, 255]);
+        assert_eq!(&out[4..], [64, 64, 64, 255]);
+    }
+}
@@ -0,0 +1,527 @@
+use cgmath::*;
+use wgpu::util::DeviceExt;
+
+/// How many levels fit in the uniforms in clipmap.wgsl
+pub const MAX_CLIPMAP_LEVELS: u32 = 16;
+const HEIGHT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+/// WGSL with `vs_clipmap`, the vertex shader for [GeometryClipmap] with
+/// the clipmap at group 0 and the camera at group 1, and
+/// `clipmap_hidden`, which fragment shaders need to discard with where
+/// levels overlap.
+pub const CLIPMAP_WGSL: &str = include_str!("clipmap.wgsl");
+
+/// Heights over a rectangle of the ground, usually from a grayscale image
+#[derive(Debug, Clone)]
+pub struct Heightmap {
+    width: u32,
+    height: u32,
+    values: Vec<f32>,
+    min: Point2<f32>,
+    max: Point2<f32>,
+}
+
+impl Heightmap {
+    /// `values` go row by row, with the first row at `min.y`
+    pub fn new(
+        width: u32,
+        height: u32,
+        values: Vec<f32>,
+        min: Point2<f32>,
+        max: Point2<f32>,
+    ) -> Self {
+        assert_eq!(values.len(), (width * height) as usize);
+        assert!(width > 1 && height > 1);
+        Self {
+            width,
+            height,
+            values,
+            min,
+            max,
+        }
+    }
+
+    /// Black is 0 and white is `height_scale`, with the top row of `img`
+    /// at `min.y`. 16 bit images avoid terraces.
+    pub fn from_image(
+        img: &image::DynamicImage,
+        min: Point2<f32>,
+        max: Point2<f32>,
+        height_scale: f32,
+    ) -> Self {
+        let gray = img.to_luma32f();
+        let (width, height) = gray.dimensions();
+        let values = gray
+            .into_raw()
+            .into_iter()
+            .map(|v| v * height_scale)
+            .collect();
+        Self::new(width, height, values, min, max)
+    }
+
+    /// The height at `point`, blended between samples. The first and
+    /// last samples are on the edges of the rectangle, and outside it the
+    /// edge carries on.
+    pub fn sample(&self, point: Point2<f32>) -> f32 {
+        let size = self.max - self.min;
+        let x = ((point.x - self.min.x) / size.x).clamp(0.0, 1.0) * (self.width - 1) as f32;
+        let y = ((point.y - self.min.y) / size.y).clamp(0.0, 1.0) * (self.height - 1) as f32;
+        let (x0, y0) = (x as u32, y as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let value = |x: u32, y: u32| self.values[(y * self.width + x) as usize];
+        let (fx, fy) = (x.fract(), y.fract());
+        let top = value(x0, y0) * (1.0 - fx) + value(x1, y0) * fx;
+        let bottom = value(x0, y1) * (1.0 - fx) + value(x1, y1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipmapSettings {
+    /// Each level has twice the spacing of the one inside it, up to
+    /// [MAX_CLIPMAP_LEVELS]
+    pub levels: u32,
+    /// Quads along each side of a level. Has to be a multiple of 4.
+    pub resolution: u32,
+    /// The distance between vertices in the innermost level
+    pub spacing: f32,
+}
+
+impl Default for ClipmapSettings {
+    fn default() -> Self {
+        Self {
+            levels: 8,
+            resolution: 128,
+            spacing: 1.0,
+        }
+    }
+}
+
+impl ClipmapSettings {
+    fn level_spacing(&self, level: u32) -> f32 {
+        self.spacing * (1 << level) as f32
+    }
+
+    /// The grid point of a level's first vertex for a camera at `camera`.
+    /// It's always even, so the level's edges line up with the vertices
+    /// of the next one out.
+    fn level_origin(&self, level: u32, camera: Point3<f32>) -> [i32; 2] {
+        let spacing = self.level_spacing(level);
+        let half = (self.resolution / 2) as i32;
+        [camera.x, camera.z].map(|c| (c / (spacing * 2.0)).floor() as i32 * 2 - half)
+    }
+}
+
+/// A rectangle of grid points, `[x, y, width, height]`
+type GridRect = [i32; 4];
+
+/// The grid points in the window at `new` that weren't in the one at
+/// `old`, for windows `size` points across
+fn exposed_regions(old: Option<[i32; 2]>, new: [i32; 2], size: i32) -> Vec<GridRect> {
+    let old = match old {
+        Some(old) if (new[0] - old[0]).abs() < size && (new[1] - old[1]).abs() < size => old,
+        _ => return vec![[new[0], new[1], size, size]],
+    };
+    let mut regions = Vec::new();
+    let (dx, dy) = (new[0] - old[0], new[1] - old[1]);
+    if dx > 0 {
+        regions.push([old[0] + size, new[1], dx, size]);
+    } else if dx < 0 {
+        regions.push([new[0], new[1], -dx, size]);
+    }
+    // Just the columns both windows have, the rest are done above
+    let (x, width) = (new[0].max(old[0]), size - dx.abs());
+    if dy > 0 {
+        regions.push([x, old[1] + size, width, dy]);
+    } else if dy < 0 {
+        regions.push([x, new[1], width, -dy]);
+    }
+    regions
+}
+
+/// Splits a rectangle of grid points where it wraps around a texture
+/// `size` texels across. Gives the texel and grid point each piece
+/// starts at and its size.
+fn wrapped_rects(rect: GridRect, size: i32) -> Vec<([u32; 2], [i32; 2], [u32; 2])> {
+    let [x, y, width, height] = rect;
+    let split = |start: i32, length: i32| {
+        let texel = start.rem_euclid(size);
+        let first = length.min(size - texel);
+        let mut spans = vec![(texel, start, first)];
+        if first < length {
+            spans.push((0, start + first, length - first));
+        }
+        spans
+    };
+    let mut rects = Vec::new();
+    for (tx, gx, w) in split(x, width) {
+        for &(ty, gy, h) in &split(y, height) {
+            rects.push(([tx as u32, ty as u32], [gx, gy], [w as u32, h as u32]));
+        }
+    }
+    rects
+}
+
+/// The grid every level draws, and the same grid with a hole where the
+/// next level in goes. The hole is a quad short of the inner level on
+/// each side, as that can sit a quad off center.
+fn clipmap_grids(resolution: u32) -> (Vec<[u32; 2]>, Vec<u32>, Vec<u32>) {
+    let n = resolution;
+    let row = n + 1;
+    let vertices = (0..=n)
+        .flat_map(|j| (0..=n).map(move |i| [i, j]))
+        .collect::<Vec<_>>();
+    let hole = n / 4 + 1..n * 3 / 4 - 1;
+    let mut full = Vec::new();
+    let mut ring = Vec::new();
+    for j in 0..n {
+        for i in 0..n {
+            let a = j * row + i;
+            let quad = [a, a + row + 1, a + 1, a, a + row, a + row + 1];
+            full.extend_from_slice(&quad);
+            if !(hole.contains(&i) && hole.contains(&j)) {
+                ring.extend_from_slice(&quad);
+            }
+        }
+    }
+    (vertices, full, ring)
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClipmapLevelRaw {
+    origin: [i32; 4],
+    spacing: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClipmapRaw {
+    levels: [ClipmapLevelRaw; MAX_CLIPMAP_LEVELS as usize],
+    count: u32,
+    size: u32,
+    _padding: [u32; 2],
+}
+
+/// Terrain drawn as nested square rings around the camera, each twice
+/// the size and spacing of the one inside it, so detail falls off with
+/// distance while every level is the same grid. Each level keeps its
+/// heights in a layer of a texture that wraps around, so when the camera
+/// moves only the rows and columns that came into view are uploaded
+/// rather than the whole terrain.
+///
+/// ```ignore
+/// let heightmap = Heightmap::from_image(&image::open("res/height.png")?, min, max, 200.0);
+/// let mut clipmap = GeometryClipmap::new(&display.device, ClipmapSettings::default(), heightmap);
+/// let pipeline = RenderPipelineBuilder::new()
+///     .layout(&layout) // clipmap.layout() then the camera
+///     .vertex_shader(shader.module())
+///     .vertex_entry_point("vs_clipmap")
+///     .vertex_buffer_desc(GeometryClipmap::vertex_desc())
+///     // ...
+///     .build(&display.device)?;
+/// // Every frame
+/// clipmap.update(&display.queue, camera.position);
+/// pass.set_pipeline(&pipeline);
+/// pass.set_bind_group(1, &camera_bind_group, &[]);
+/// clipmap.draw(&mut pass);
+/// ```
+pub struct GeometryClipmap {
+    settings: ClipmapSettings,
+    heightmap: Heightmap,
+    /// Where each level's window was last filled from
+    loaded: Vec<Option<[i32; 2]>>,
+    heights: wgpu::Texture,
+    uniforms: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    vertices: wgpu::Buffer,
+    full: wgpu::Buffer,
+    num_full: u32,
+    ring: wgpu::Buffer,
+    num_ring: u32,
+}
+
+impl GeometryClipmap {
+    pub fn new(device: &wgpu::Device, settings: ClipmapSettings, heightmap: Heightmap) -> Self {
+        assert!(
+            (1..=MAX_CLIPMAP_LEVELS).contains(&settings.levels),
+            "Clipmaps have 1 to {} levels",
+            MAX_CLIPMAP_LEVELS
+        );
+        assert!(
+            settings.resolution >= 8 && settings.resolution.is_multiple_of(4),
+            "Clipmap resolution has to be a multiple of 4"
+        );
+        let size = settings.resolution + 1;
+        let heights = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("GeometryClipmap::heights"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: settings.levels,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HEIGHT_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = heights.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GeometryClipmap::uniforms"),
+            size: std::mem::size_of::<ClipmapRaw>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let stages = wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT;
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GeometryClipmap::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: stages,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: stages,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("GeometryClipmap::bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+            ],
+        });
+
+        let (vertices, full, ring) = clipmap_grids(settings.resolution);
+        let buffer = |label, contents: &[u8], usage| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage,
+            })
+        };
+        let vertex_buffer = buffer(
+            "GeometryClipmap::vertices",
+            bytemuck::cast_slice(&vertices),
+            wgpu::BufferUsages::VERTEX,
+        );
+        let full_buffer = buffer(
+            "GeometryClipmap::full",
+            bytemuck::cast_slice(&full),
+            wgpu::BufferUsages::INDEX,
+        );
+        let ring_buffer = buffer(
+            "GeometryClipmap::ring",
+            bytemuck::cast_slice(&ring),
+            wgpu::BufferUsages::INDEX,
+        );
+
+        Self {
+            loaded: vec![None; settings.levels as usize],
+            settings,
+            heightmap,
+            heights,
+            uniforms,
+            layout,
+            bind_group,
+            vertices: vertex_buffer,
+            full: full_buffer,
+            num_full: full.len() as u32,
+            ring: ring_buffer,
+            num_ring: ring.len() as u32,
+        }
+    }
+
+    pub fn settings(&self) -> &ClipmapSettings {
+        &self.settings
+    }
+
+    /// Group 0 of the pipeline that draws the clipmap
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    /// The grid's vertex buffer, at location 0
+    pub fn vertex_desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<[u32; 2]>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Uint32x2,
+            }],
+        }
+    }
+
+    /// Moves the levels to follow the camera, uploading the heights that
+    /// came into view. Returns how many texels were uploaded.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: Point3<f32>) -> usize {
+        crate::cpu_scope!("GeometryClipmap::update");
+        let size = (self.settings.resolution + 1) as i32;
+        let mut uploaded = 0;
+        let mut raw = ClipmapRaw {
+            levels: [ClipmapLevelRaw {
+                origin: [0; 4],
+                spacing: [0.0; 4],
+            }; MAX_CLIPMAP_LEVELS as usize],
+            count: self.settings.levels,
+            size: size as u32,
+            _padding: [0; 2],
+        };
+        for level in 0..self.settings.levels {
+            let origin = self.settings.level_origin(level, camera);
+            let spacing = self.settings.level_spacing(level);
+            raw.levels[level as usize] = ClipmapLevelRaw {
+                origin: [origin[0], origin[1], 0, 0],
+                spacing: [spacing, 0.0, 0.0, 0.0],
+            };
+            let regions = exposed_regions(self.loaded[level as usize], origin, size);
+            for region in regions {
+                for (texel, start, [width, height]) in wrapped_rects(region, size) {
+                    let mut data = Vec::with_capacity((width * height) as usize);
+                    for y in 0..height as i32 {
+                        for x in 0..width as i32 {
+                            let point = Point2::new(
+                                (start[0] + x) as f32 * spacing,
+                                (start[1] + y) as f32 * spacing,
+                            );
+                            data.push(self.heightmap.sample(point));
+                        }
+                    }
+                    queue.write_texture(
+                        wgpu::ImageCopyTexture {
+                            aspect: wgpu::TextureAspect::All,
+                            texture: &self.heights,
+                            mip_level: 0,
+                            origin: wgpu::Origin3d {
+                                x: texel[0],
+                                y: texel[1],
+                                z: level,
+                            },
+                        },
+                        bytemuck::cast_slice(&data),
+                        wgpu::ImageDataLayout {
+                            offset: 0,
+                            bytes_per_row: Some(width * 4),
+                            rows_per_image: None,
+                        },
+                        wgpu::Extent3d {
+                            width,
+                            height,
+                            depth_or_array_layers: 1,
+                        },
+                    );
+                    uploaded += data.len();
+                }
+            }
+            self.loaded[level as usize] = Some(origin);
+        }
+        queue.write_buffer(&self.uniforms, 0, bytemuck::cast_slice(&[raw]));
+        uploaded
+    }
+
+    /// Draws the innermost level whole and the rest as rings. The
+    /// pipeline and camera need to be set already.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertices.slice(..));
+        pass.set_index_buffer(self.full.slice(..), wgpu::IndexFormat::Uint32);
+        pass.draw_indexed(0..self.num_full, 0, 0..1);
+        if self.settings.levels > 1 {
+            pass.set_index_buffer(self.ring.slice(..), wgpu::IndexFormat::Uint32);
+            pass.draw_indexed(0..self.num_ring, 0, 1..self.settings.levels);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_only_uploads_the_new_edges() {
+        assert_eq!(exposed_regions(None, [4, 6], 9), vec![[4, 6, 9, 9]]);
+        assert!(exposed_regions(Some([4, 6]), [4, 6], 9).is_empty());
+        // Two columns to the right and two rows down
+        let regions = exposed_regions(Some([0, 0]), [2, -2], 9);
+        assert_eq!(regions, vec![[9, -2, 2, 9], [2, -2, 7, 2]]);
+        let texels: i32 = regions.iter().map(|r| r[2] * r[3]).sum();
+        assert_eq!(texels, 9 * 9 - 7 * 7);
+        // Too far to reuse anything
+        assert_eq!(
+            exposed_regions(Some([0, 0]), [20, 0], 9),
+            vec![[20, 0, 9, 9]]
+        );
+
+        // Columns 7 and 8 then 9 and 10, which wrap to 0 and 1
+        let rects = wrapped_rects([7, -2, 4, 2], 9);
+        assert_eq!(
+            rects,
+            vec![([7, 7], [7, -2], [2, 2]), ([0, 7], [9, -2], [2, 2])]
+        );
+    }
+
+    #[test]
+    fn levels_line_up() {
+        let settings = ClipmapSettings {
+            levels: 3,
+            resolution: 16,
+            spacing: 1.0,
+        };
+        let camera = Point3::new(13.7, 0.0, -5.2);
+        for level in 0..2 {
+            let inner = settings.level_origin(level, camera);
+            let outer = settings.level_origin(level + 1, camera);
+            for axis in 0..2 {
+                assert_eq!(inner[axis] % 2, 0);
+                // In the outer level's quads the inner one starts 4 or 5 in,
+                // which always covers the ring's hole from 5 to 11
+                let offset = inner[axis] / 2 - outer[axis];
+                assert!(offset == 4 || offset == 5, "{}", offset);
+            }
+        }
+        let (vertices, full, ring) = clipmap_grids(16);
+        assert_eq!(vertices.len(), 17 * 17);
+        assert_eq!(full.len(), 16 * 16 * 6);
+        assert_eq!(ring.len(), (16 * 16 - 6 * 6) * 6);
+
+        let heightmap = Heightmap::new(
+            2,
+            2,
+            vec![0.0, 1.0, 2.0, 3.0],
+            Point2::new(0.0, 0.0),
+            Point2::new(1.0, 1.0),
+        );
+        assert_eq!(heightmap.sample(Point2::new(0.5, 0.5)), 1.5);
+        assert_eq!(heightmap.sample(Point2::new(5.0, -5.0)), 1.0);
+        crate::shader::validate_wgsl(CLIPMAP_WGSL).unwrap();
+    }
+}
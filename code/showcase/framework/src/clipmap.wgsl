@@ -0,0 +1,101 @@
+// A vertex shader for GeometryClipmap, with the clipmap at group 0 and
+// the camera at group 1. Every level draws the same grid as an instance,
+// the instance index being the level.
+
+struct Camera {
+    view_position: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+struct ClipmapLevel {
+    // The grid point of the level's first vertex, in multiples of spacing
+    origin: vec4<i32>,
+    // x is the distance between vertices
+    spacing: vec4<f32>,
+}
+
+struct Clipmap {
+    levels: array<ClipmapLevel, 16>,
+    count: u32,
+    // Texels along each side of a level, one more than the quads
+    size: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> clipmap: Clipmap;
+// One layer per level, wrapped around so moving only rewrites the edges
+@group(0) @binding(1)
+var clipmap_heights: texture_2d_array<f32>;
+
+struct ClipmapVertexInput {
+    @location(0) grid: vec2<u32>,
+}
+
+struct ClipmapVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) @interpolate(flat) level: u32,
+}
+
+// The height at a grid point of a level, which has to be in its window
+fn clipmap_height(level: u32, point: vec2<i32>) -> f32 {
+    let size = i32(clipmap.size);
+    let texel = ((point % size) + size) % size;
+    return textureLoad(clipmap_heights, texel, level, 0).r;
+}
+
+@vertex
+fn vs_clipmap(in: ClipmapVertexInput, @builtin(instance_index) level: u32) -> ClipmapVertexOutput {
+    let info = clipmap.levels[level];
+    let spacing = info.spacing.x;
+    let point = vec2<i32>(in.grid) + info.origin.xy;
+    var height = clipmap_height(level, point);
+
+    // Near the outside, heights slide towards what the next level's
+    // bigger triangles have there so the two meet without cracks. Levels
+    // start on even grid points, so odd points are never on the edge and
+    // both neighbours are loaded.
+    let quads = f32(clipmap.size - 1u);
+    let p = vec2<f32>(in.grid);
+    let from_edge = min(min(p.x, p.y), min(quads - p.x, quads - p.y));
+    if (level + 1u < clipmap.count) {
+        let odd = point & vec2<i32>(1);
+        let coarse = 0.5 * (clipmap_height(level, point - odd) + clipmap_height(level, point + odd));
+        let blend = 1.0 - clamp(from_edge / (quads * 0.1), 0.0, 1.0);
+        height = mix(height, coarse, blend);
+    }
+
+    let first = info.origin.xy;
+    let last = first + vec2<i32>(i32(clipmap.size) - 1);
+    let left = clipmap_height(level, max(point - vec2<i32>(1, 0), first));
+    let right = clipmap_height(level, min(point + vec2<i32>(1, 0), last));
+    let down = clipmap_height(level, max(point - vec2<i32>(0, 1), first));
+    let up = clipmap_height(level, min(point + vec2<i32>(0, 1), last));
+
+    let position = vec3<f32>(f32(point.x) * spacing, height, f32(point.y) * spacing);
+    var out: ClipmapVertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.world_position = position;
+    out.normal = normalize(vec3<f32>(left - right, 2.0 * spacing, down - up));
+    out.level = level;
+    return out;
+}
+
+// Whether the next level in covers this fragment. Levels overlap a
+// little, so fragment shaders should discard these.
+fn clipmap_hidden(in: ClipmapVertexOutput) -> bool {
+    if (in.level == 0u) {
+        return false;
+    }
+    let finer = clipmap.levels[in.level - 1u];
+    let spacing = finer.spacing.x;
+    let low = vec2<f32>(finer.origin.xy) * spacing;
+    let high = low + f32(clipmap.size - 1u) * spacing;
+    let p = in.world_position.xz;
+    let margin = spacing * 0.01;
+    return all(p > low + margin) && all(p < high - margin);
+}
@@ -0,0 +1,424 @@
+use crate::texture;
+
+/// sRGB encoded to linear light, for one channel from 0 to 1
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Linear light to sRGB encoded, for one channel from 0 to 1
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// A color in linear light, which is what shaders and the rest of the
+/// framework expect. Colors picked from a color picker or written as hex
+/// are sRGB, so bring them in with [Color::from_srgb] or [Color::hex].
+/// Alpha is never encoded.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const TRANSPARENT: Self = Self::new(0.0, 0.0, 0.0, 0.0);
+    pub const BLACK: Self = Self::rgb(0.0, 0.0, 0.0);
+    pub const WHITE: Self = Self::rgb(1.0, 1.0, 1.0);
+    /// sRGB 50% grey
+    pub const GREY: Self = Self::rgb(0.2140411, 0.2140411, 0.2140411);
+    pub const RED: Self = Self::rgb(1.0, 0.0, 0.0);
+    pub const GREEN: Self = Self::rgb(0.0, 1.0, 0.0);
+    pub const BLUE: Self = Self::rgb(0.0, 0.0, 1.0);
+    pub const YELLOW: Self = Self::rgb(1.0, 1.0, 0.0);
+    pub const CYAN: Self = Self::rgb(0.0, 1.0, 1.0);
+    pub const MAGENTA: Self = Self::rgb(1.0, 0.0, 1.0);
+    /// sRGB `#ff8000`
+    pub const ORANGE: Self = Self::rgb(1.0, 0.2158605, 0.0);
+
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub const fn rgb(r: f32, g: f32, b: f32) -> Self {
+        Self::new(r, g, b, 1.0)
+    }
+
+    pub fn from_srgb(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self::new(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a)
+    }
+
+    pub fn from_srgb8(rgba: [u8; 4]) -> Self {
+        let [r, g, b, a] = rgba.map(|v| v as f32 / 255.0);
+        Self::from_srgb(r, g, b, a)
+    }
+
+    /// An opaque sRGB color written like CSS, so `0xff8000` is orange
+    pub fn hex(rgb: u32) -> Self {
+        let [_, r, g, b] = rgb.to_be_bytes();
+        Self::from_srgb8([r, g, b, 255])
+    }
+
+    pub fn to_srgb(self) -> [f32; 4] {
+        [
+            linear_to_srgb(self.r),
+            linear_to_srgb(self.g),
+            linear_to_srgb(self.b),
+            self.a,
+        ]
+    }
+
+    /// Clamped to 0 to 1 first, like writing to an `Rgba8UnormSrgb`
+    /// texture would
+    pub fn to_srgb8(self) -> [u8; 4] {
+        self.to_srgb()
+            .map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+    }
+
+    pub fn with_alpha(self, a: f32) -> Self {
+        Self { a, ..self }
+    }
+
+    /// Hue in degrees, saturation and value from 0 to 1. Like color
+    /// pickers, these describe the sRGB encoded color.
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32, a: f32) -> Self {
+        let h = hue.rem_euclid(360.0) / 60.0;
+        let c = value * saturation;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = value - c;
+        Self::from_srgb(r + m, g + m, b + m, a)
+    }
+
+    /// The reverse of [Color::from_hsv], as `[hue, saturation, value]`
+    pub fn to_hsv(self) -> [f32; 3] {
+        let [r, g, b, _] = self.to_srgb();
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let saturation = if max == 0.0 { 0.0 } else { delta / max };
+        [hue, saturation, max]
+    }
+
+    /// OKLab's `[lightness, a, b]`. Steps of the same size in OKLab look
+    /// about as different as each other, which makes it the space to
+    /// blend gradients in.
+    pub fn to_oklab(self) -> [f32; 3] {
+        let Self { r, g, b, .. } = self;
+        let l = (0.41222146 * r + 0.53633255 * g + 0.051445995 * b).cbrt();
+        let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+        let s = (0.08830246 * r + 0.28171885 * g + 0.6299787 * b).cbrt();
+        [
+            0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+            1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+            0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+        ]
+    }
+
+    pub fn from_oklab(lab: [f32; 3], a: f32) -> Self {
+        let [lightness, lab_a, lab_b] = lab;
+        let l = (lightness + 0.39633778 * lab_a + 0.21580376 * lab_b).powi(3);
+        let m = (lightness - 0.105561346 * lab_a - 0.06385417 * lab_b).powi(3);
+        let s = (lightness - 0.08948418 * lab_a - 1.2914855 * lab_b).powi(3);
+        Self::new(
+            4.0767417 * l - 3.3077116 * m + 0.23096994 * s,
+            -1.268438 * l + 2.6097574 * m - 0.34131938 * s,
+            -0.0041960864 * l - 0.7034186 * m + 1.7076147 * s,
+            a,
+        )
+    }
+
+    /// Mixes in linear light, like blending on the GPU does
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        Self::new(
+            mix(self.r, other.r),
+            mix(self.g, other.g),
+            mix(self.b, other.b),
+            mix(self.a, other.a),
+        )
+    }
+
+    /// Mixes in OKLab, which keeps the brightness even across the blend
+    /// instead of dipping through muddy colors
+    pub fn lerp_oklab(self, other: Self, t: f32) -> Self {
+        let (from, to) = (self.to_oklab(), other.to_oklab());
+        let lab = [0, 1, 2].map(|i| from[i] + (to[i] - from[i]) * t);
+        Self::from_oklab(lab, self.a + (other.a - self.a) * t)
+    }
+
+    /// Mixes in HSV, going around the hue circle the short way, so red to
+    /// blue passes through magenta rather than grey
+    pub fn lerp_hsv(self, other: Self, t: f32) -> Self {
+        let [h0, s0, v0] = self.to_hsv();
+        let [h1, s1, v1] = other.to_hsv();
+        let dh = (h1 - h0 + 180.0).rem_euclid(360.0) - 180.0;
+        Self::from_hsv(
+            h0 + dh * t,
+            s0 + (s1 - s0) * t,
+            v0 + (v1 - v0) * t,
+            self.a + (other.a - self.a) * t,
+        )
+    }
+}
+
+impl From<[f32; 4]> for Color {
+    fn from([r, g, b, a]: [f32; 4]) -> Self {
+        Self::new(r, g, b, a)
+    }
+}
+
+impl From<[f32; 3]> for Color {
+    fn from([r, g, b]: [f32; 3]) -> Self {
+        Self::rgb(r, g, b)
+    }
+}
+
+impl From<Color> for [f32; 4] {
+    fn from(c: Color) -> Self {
+        [c.r, c.g, c.b, c.a]
+    }
+}
+
+impl From<Color> for [f32; 3] {
+    fn from(c: Color) -> Self {
+        [c.r, c.g, c.b]
+    }
+}
+
+/// Tableau 10, for telling apart things that have no order, like objects
+/// or clusters
+const CATEGORICAL: [u32; 10] = [
+    0x4e79a7, 0xf28e2b, 0xe15759, 0x76b7b2, 0x59a14f, 0xedc948, 0xb07aa1, 0xff9da7, 0x9c755f,
+    0xbab0ac,
+];
+
+/// One of 10 colors that are easy to tell apart, repeating after that.
+/// Color debug views by an id with it.
+pub fn categorical(index: usize) -> Color {
+    Color::hex(CATEGORICAL[index % CATEGORICAL.len()])
+}
+
+/// The space a [Gradient] blends between its stops in
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ColorSpace {
+    Linear,
+    Hsv,
+    #[default]
+    Oklab,
+}
+
+/// Colors at points from 0 to 1, for mapping a value to a color, like
+/// heat in a heatmap or a particle's age. [Gradient::to_texture] bakes it
+/// into a ramp to sample in a shader.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    stops: Vec<(f32, Color)>,
+    space: ColorSpace,
+}
+
+impl Gradient {
+    /// `stops` are `(position, color)`, in any order. Blends in OKLab
+    /// unless changed with [Gradient::with_space].
+    pub fn new(stops: impl IntoIterator<Item = (f32, Color)>) -> Self {
+        let mut stops: Vec<_> = stops.into_iter().collect();
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self {
+            stops,
+            space: ColorSpace::default(),
+        }
+    }
+
+    /// `colors` spread out evenly from 0 to 1
+    pub fn even(colors: &[Color]) -> Self {
+        let last = colors.len().saturating_sub(1).max(1) as f32;
+        Self::new(
+            colors
+                .iter()
+                .enumerate()
+                .map(|(i, c)| (i as f32 / last, *c)),
+        )
+    }
+
+    pub fn with_space(self, space: ColorSpace) -> Self {
+        Self { space, ..self }
+    }
+
+    /// Perceptually even from dark blue to yellow, and still readable in
+    /// greyscale. The go to for heatmaps.
+    pub fn viridis() -> Self {
+        Self::even(&[0x440154, 0x3b528b, 0x21918c, 0x5ec962, 0xfde725].map(Color::hex))
+    }
+
+    /// Perceptually even from black through red to pale yellow
+    pub fn inferno() -> Self {
+        Self::even(&[0x000004, 0x57106e, 0xbc3754, 0xf98e09, 0xfcffa4].map(Color::hex))
+    }
+
+    /// Blue for cold through green and yellow to red for hot. Easy to
+    /// read at a glance, but the bands aren't even like [Gradient::viridis].
+    pub fn heat() -> Self {
+        Self::even(&[
+            Color::BLUE,
+            Color::CYAN,
+            Color::GREEN,
+            Color::YELLOW,
+            Color::RED,
+        ])
+        .with_space(ColorSpace::Hsv)
+    }
+
+    /// Once around the hue circle, ending back at red
+    pub fn rainbow() -> Self {
+        Self::even(&[0.0, 120.0, 240.0, 360.0].map(|h| Color::from_hsv(h, 1.0, 1.0, 1.0)))
+            .with_space(ColorSpace::Hsv)
+    }
+
+    pub fn greyscale() -> Self {
+        Self::even(&[Color::BLACK, Color::WHITE]).with_space(ColorSpace::Linear)
+    }
+
+    /// The color at `t`, clamped to the first and last stops
+    pub fn sample(&self, t: f32) -> Color {
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Color::TRANSPARENT,
+        };
+        if t <= first.0 {
+            return first.1;
+        }
+        if t >= last.0 {
+            return last.1;
+        }
+        let next = self.stops.iter().position(|s| s.0 > t).unwrap();
+        let ((p0, c0), (p1, c1)) = (self.stops[next - 1], self.stops[next]);
+        let t = (t - p0) / (p1 - p0);
+        match self.space {
+            ColorSpace::Linear => c0.lerp(c1, t),
+            ColorSpace::Hsv => c0.lerp_hsv(c1, t),
+            ColorSpace::Oklab => c0.lerp_oklab(c1, t),
+        }
+    }
+
+    /// `width` evenly spaced samples as sRGB bytes, the first at 0 and the
+    /// last at 1
+    pub fn to_srgb8(&self, width: u32) -> Vec<[u8; 4]> {
+        let last = width.saturating_sub(1).max(1) as f32;
+        (0..width)
+            .map(|x| self.sample(x as f32 / last).to_srgb8())
+            .collect()
+    }
+
+    /// Bakes the gradient into a `width` by 1 sRGB texture, which shaders
+    /// sample by `vec2(t, 0.5)` and get linear colors back from. 256 is
+    /// plenty for smooth gradients.
+    pub fn to_texture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: u32,
+    ) -> texture::Texture<'static> {
+        let texels = self.to_srgb8(width.max(1));
+        let img = image::RgbaImage::from_fn(texels.len() as u32, 1, |x, _| {
+            image::Rgba(texels[x as usize])
+        });
+        let mut texture = texture::Texture::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(img),
+            Some("Gradient"),
+            false,
+        )
+        .unwrap();
+        texture.sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Gradient"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        texture
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-3)
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        assert!(close(
+            &<[f32; 4]>::from(Color::hex(0xff8000)),
+            &<[f32; 4]>::from(Color::ORANGE)
+        ));
+        assert_eq!(Color::hex(0x808080).to_srgb8(), [128, 128, 128, 255]);
+        assert!(close(&[srgb_to_linear(0.5)], &[Color::GREY.r]));
+
+        let orange: [f32; 4] = Color::from_hsv(30.0, 1.0, 1.0, 1.0).into();
+        assert!(close(
+            &orange,
+            &<[f32; 4]>::from(Color::from_srgb(1.0, 0.5, 0.0, 1.0))
+        ));
+        assert!(close(
+            &Color::hex(0x3b528b).to_hsv(),
+            &[222.75, 0.576, 0.545]
+        ));
+
+        // Reference values from Björn Ottosson's post
+        assert!(close(&Color::WHITE.to_oklab(), &[1.0, 0.0, 0.0]));
+        assert!(close(&Color::RED.to_oklab(), &[0.628, 0.225, 0.126]));
+        let back: [f32; 4] = Color::from_oklab(Color::ORANGE.to_oklab(), 1.0).into();
+        assert!(close(&back, &<[f32; 4]>::from(Color::ORANGE)));
+    }
+
+    #[test]
+    fn gradients_blend_between_stops() {
+        let gradient = Gradient::new([(1.0, Color::WHITE), (0.0, Color::BLACK)]);
+        assert_eq!(gradient.sample(-1.0), Color::BLACK);
+        assert_eq!(gradient.sample(2.0), Color::WHITE);
+        // Halfway in OKLab is a perceptual mid grey, which is much less
+        // light than halfway in linear light
+        let mid = gradient.sample(0.5);
+        assert!(close(&[mid.to_oklab()[0], mid.r], &[0.5, 0.125]));
+        let linear = gradient.clone().with_space(ColorSpace::Linear);
+        assert!(close(
+            &<[f32; 4]>::from(linear.sample(0.5)),
+            &[0.5, 0.5, 0.5, 1.0]
+        ));
+
+        // The short way round from red to blue is through magenta
+        let hsv = Gradient::even(&[Color::RED, Color::BLUE]).with_space(ColorSpace::Hsv);
+        assert!(close(&hsv.sample(0.5).to_hsv(), &[300.0, 1.0, 1.0]));
+
+        let ramp = Gradient::viridis().to_srgb8(5);
+        assert_eq!(ramp[0], [0x44, 0x01, 0x54, 255]);
+        assert_eq!(ramp[4], [0xfd, 0xe7, 0x25, 255]);
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::post::{fullscreen_pass, PostContext, PostEffect, PostProcessChain};
+use crate::texture::ColorLut;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ColorGradingUniform {
+    strength: f32,
+    lut_size: f32,
+    _padding: [f32; 2],
+}
+
+/// Grades the image with a [ColorLut] as a [PostEffect]. LUTs expect
+/// colors from 0 to 1, so it goes after [crate::Tonemap] in the chain.
+///
+/// ```ignore
+/// let lut = ColorLut::load(&display.device, &display.queue, "res/teal_orange.cube")?;
+/// let grading = ColorGrading::new(&display.device, &post, lut)?;
+/// post.add(tonemap).add(grading);
+/// ```
+pub struct ColorGrading {
+    lut: ColorLut,
+    strength: f32,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ColorGrading {
+    pub fn new(device: &wgpu::Device, chain: &PostProcessChain, lut: ColorLut) -> Result<Self> {
+        let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ColorGrading::layout"),
+            entries: &[
+                texture_entry(0, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(3, wgpu::TextureViewDimension::D3),
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("ColorGrading::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let strength = 1.0;
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("ColorGrading::uniform_buffer"),
+            contents: bytemuck::bytes_of(&uniform(strength, lut.size)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ColorGrading::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = framework_shader("color_grading.wgsl", include_str!("color_grading.wgsl"));
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(shader.module())
+            .fragment_shader(shader.module())
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .color_solid(chain.format())
+            .build(device)?;
+
+        Ok(Self {
+            lut,
+            strength,
+            layout,
+            sampler,
+            uniform_buffer,
+            pipeline,
+        })
+    }
+
+    pub fn lut(&self) -> &ColorLut {
+        &self.lut
+    }
+
+    pub fn set_lut(&mut self, queue: &wgpu::Queue, lut: ColorLut) {
+        self.lut = lut;
+        self.write_uniform(queue);
+    }
+
+    pub fn strength(&self) -> f32 {
+        self.strength
+    }
+
+    /// Blends between the original colors at 0 and the LUT's at 1
+    pub fn set_strength(&mut self, queue: &wgpu::Queue, strength: f32) {
+        self.strength = strength;
+        self.write_uniform(queue);
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        let uniform = uniform(self.strength, self.lut.size);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+}
+
+impl PostEffect for ColorGrading {
+    fn enabled(&self) -> bool {
+        self.strength > 0.0
+    }
+
+    fn process(&mut self, encoder: &mut wgpu::CommandEncoder, ctx: &PostContext) {
+        crate::cpu_scope!("ColorGrading::process");
+        let bind_group = create_bind_group(
+            ctx.device,
+            &self.layout,
+            &self.sampler,
+            &self.uniform_buffer,
+            ctx.input,
+            &self.lut,
+        );
+        fullscreen_pass(
+            encoder,
+            "ColorGrading::process",
+            ctx.output,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            &self.pipeline,
+            &bind_group,
+        );
+    }
+}
+
+fn uniform(strength: f32, lut_size: u32) -> ColorGradingUniform {
+    ColorGradingUniform {
+        strength: strength.clamp(0.0, 1.0),
+        lut_size: lut_size as f32,
+        _padding: [0.0; 2],
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    uniform_buffer: &wgpu::Buffer,
+    source: &wgpu::TextureView,
+    lut: &ColorLut,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("ColorGrading::bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(source),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&lut.view),
+            },
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_grading_wgsl_validates() {
+        assert_eq!(std::mem::size_of::<ColorGradingUniform>(), 16);
+        crate::shader::validate_wgsl(
+            &framework_shader("color_grading.wgsl", include_str!("color_grading.wgsl")).source,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn strength_is_clamped() {
+        assert_eq!(uniform(0.5, 33).strength, 0.5);
+        assert_eq!(uniform(2.0, 33).strength, 1.0);
+        assert_eq!(uniform(-1.0, 33).strength, 0.0);
+        assert_eq!(uniform(1.0, 33).lut_size, 33.0);
+    }
+}
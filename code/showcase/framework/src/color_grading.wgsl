@@ -0,0 +1,44 @@
+// Color grading with a 3D lookup table. LUTs map sRGB encoded color to
+// sRGB encoded color, which is how they're authored, so the chain's linear
+// color is encoded for the lookup and decoded after.
+
+struct ColorGradingUniform {
+    strength: f32,
+    lut_size: f32,
+    _padding: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> grading: ColorGradingUniform;
+@group(0) @binding(3)
+var lut: texture_3d<f32>;
+
+#include "framework/fullscreen.wgsl"
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let source_color = textureSample(source, source_sampler, in.uv);
+    var color = linear_to_srgb(clamp(source_color.rgb, vec3<f32>(0.0), vec3<f32>(1.0)));
+    // The first and last texels' centers are 0 and 1
+    let scale = (grading.lut_size - 1.0) / grading.lut_size;
+    let offset = 0.5 / grading.lut_size;
+    let graded = textureSampleLevel(lut, source_sampler, color * scale + offset, 0.0).rgb;
+    color = mix(color, graded, grading.strength);
+    return vec4<f32>(srgb_to_linear(color), source_color.a);
+}
@@ -0,0 +1,60 @@
+// Conway's game of life. Hold the mouse over the canvas to add cells.
+
+struct SimulationData {
+    clear_color: vec4<f32>,
+    canvas_size: vec2<f32>,
+    mouse_pos: vec2<f32>,
+    time: f32,
+    delta_time: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> sim: SimulationData;
+@group(0) @binding(1)
+var previous: texture_2d<f32>;
+@group(0) @binding(2)
+var output: texture_storage_2d<rgba16float, write>;
+
+fn hash(p: vec2<u32>) -> u32 {
+    var h = p.x * 374761393u + p.y * 668265263u;
+    h = (h ^ (h >> 13u)) * 1274126177u;
+    return h ^ (h >> 16u);
+}
+
+fn alive(coord: vec2<i32>, size: vec2<i32>) -> u32 {
+    // The alpha channel holds the cell state. Wrap around the edges.
+    let wrapped = (coord + size) % size;
+    return u32(textureLoad(previous, wrapped, 0).a > 0.5);
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(previous));
+    let coord = vec2<i32>(id.xy);
+    if (any(coord >= size)) {
+        return;
+    }
+
+    var cell = alive(coord, size);
+    if (sim.time == 0.0) {
+        // Seed the board on the first frame
+        cell = u32(hash(id.xy) % 4u == 0u);
+    } else {
+        var neighbours = 0u;
+        for (var y = -1; y <= 1; y += 1) {
+            for (var x = -1; x <= 1; x += 1) {
+                if (x != 0 || y != 0) {
+                    neighbours += alive(coord + vec2<i32>(x, y), size);
+                }
+            }
+        }
+        cell = u32(neighbours == 3u || (cell == 1u && neighbours == 2u));
+    }
+
+    if (distance(vec2<f32>(coord), sim.mouse_pos) < 4.0) {
+        cell = 1u;
+    }
+
+    let color = mix(sim.clear_color, vec4<f32>(1.0), f32(cell));
+    textureStore(output, coord, vec4<f32>(color.rgb, f32(cell)));
+}
@@ -0,0 +1,470 @@
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::shader::{catch_validation_errors, validate_shader_module};
+use crate::shader_canvas::{ShaderBuildError, SimulationClock, SimulationData};
+use crate::texture::Texture;
+
+/// Like [crate::ShaderCanvas], but the user shader is a compute shader that
+/// writes the whole canvas each frame. The previous frame is available to
+/// read from, which makes cellular automata and other simulations easy.
+///
+/// The compute shader gets these bindings:
+///
+/// ```wgsl
+/// @group(0) @binding(0) var<uniform> sim: SimulationData;
+/// @group(0) @binding(1) var previous: texture_2d<f32>;
+/// @group(0) @binding(2) var output: texture_storage_2d<rgba16float, write>;
+/// ```
+///
+/// The result is stretched over the frame when rendering. See
+/// `compute_canvas.comp.wgsl` for an example.
+pub struct ComputeCanvas {
+    pipeline: wgpu::ComputePipeline,
+    blit_pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    blit_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    states: [Texture<'static>; 2],
+    bind_groups: [wgpu::BindGroup; 2],
+    blit_bind_groups: [wgpu::BindGroup; 2],
+    workgroup_size: [u32; 2],
+    clock: SimulationClock,
+    simulation_data: SimulationData,
+    simulation_data_buffer: wgpu::Buffer,
+    /// The size of the window the mouse positions are in
+    window_size: [f32; 2],
+    /// The index of the state written most recently
+    current: usize,
+}
+
+impl ComputeCanvas {
+    pub const STATE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// Takes the mouse position in window pixels. The shader gets it in
+    /// the canvas' texels.
+    pub fn input(&mut self, mouse_x: f32, mouse_y: f32) {
+        let scale = self.texels_per_pixel();
+        self.simulation_data.mouse_pos[0] = mouse_x * scale[0];
+        self.simulation_data.mouse_pos[1] = mouse_y * scale[1];
+    }
+
+    pub fn delta_input(&mut self, dx: f32, dy: f32) {
+        let scale = self.texels_per_pixel();
+        self.simulation_data.mouse_pos[0] += dx * scale[0];
+        self.simulation_data.mouse_pos[1] += dy * scale[1];
+    }
+
+    /// Call it when the window resizes so mouse positions keep lining up
+    /// with the canvas, which is stretched over the whole frame.
+    pub fn set_window_size(&mut self, width: u32, height: u32) {
+        self.window_size = [width as f32, height as f32];
+    }
+
+    fn texels_per_pixel(&self) -> [f32; 2] {
+        texels_per_pixel(self.simulation_data.canvas_size, self.window_size)
+    }
+
+    /// Recreates the state textures at the new size. This clears the
+    /// simulation.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.simulation_data.canvas_size = [width as f32, height as f32];
+        self.states = create_states(device, width, height);
+        self.bind_groups = create_bind_groups(
+            device,
+            &self.layout,
+            &self.simulation_data_buffer,
+            &self.states,
+        );
+        self.blit_bind_groups =
+            create_blit_bind_groups(device, &self.blit_layout, &self.sampler, &self.states);
+    }
+
+    /// Runs one step of the simulation, then draws it to `frame`.
+    pub fn render(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: &wgpu::TextureView,
+    ) {
+        crate::cpu_scope!("ComputeCanvas::render");
+        self.clock.tick(&mut self.simulation_data);
+        queue.write_buffer(
+            &self.simulation_data_buffer,
+            0,
+            bytemuck::cast_slice(&[self.simulation_data]),
+        );
+
+        // bind_groups[i] reads state i and writes the other one
+        let read = self.current;
+        self.current = 1 - self.current;
+
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("ComputeCanvas::simulate"),
+                timestamp_writes: None,
+            });
+            let size = self.states[0].desc.size;
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_groups[read], &[]);
+            pass.dispatch_workgroups(
+                size.width.div_ceil(self.workgroup_size[0]),
+                size.height.div_ceil(self.workgroup_size[1]),
+                1,
+            );
+        }
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("ComputeCanvas::blit"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: frame,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.blit_pipeline);
+        pass.set_bind_group(0, &self.blit_bind_groups[self.current], &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+pub struct ComputeCanvasBuilder<'a> {
+    canvas_size: [u32; 2],
+    clear_color: [f32; 4],
+    label: Option<&'a str>,
+    display_format: Option<wgpu::TextureFormat>,
+    compute_code: Option<wgpu::ShaderModuleDescriptor<'a>>,
+    entry_point: &'a str,
+    workgroup_size: [u32; 2],
+    window_size: Option<[u32; 2]>,
+}
+
+impl<'a> Default for ComputeCanvasBuilder<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> ComputeCanvasBuilder<'a> {
+    pub fn new() -> Self {
+        Self {
+            canvas_size: [256; 2],
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            label: None,
+            display_format: None,
+            compute_code: Some(wgpu::include_wgsl!("compute_canvas.comp.wgsl")),
+            entry_point: "main",
+            workgroup_size: [8, 8],
+            window_size: None,
+        }
+    }
+
+    /// The size of the simulation in texels. This doesn't need to match
+    /// the size of the frame.
+    pub fn canvas_size(&mut self, width: u32, height: u32) -> &mut Self {
+        self.canvas_size = [width, height];
+        self
+    }
+
+    pub fn clear_color(&mut self, color: [f32; 4]) -> &mut Self {
+        self.clear_color = color;
+        self
+    }
+
+    pub fn label(&mut self, label: &'a str) -> &mut Self {
+        self.label = Some(label);
+        self
+    }
+
+    pub fn display_format(&mut self, format: wgpu::TextureFormat) -> &mut Self {
+        self.display_format = Some(format);
+        self
+    }
+
+    /// The size of the window mouse positions are given in. Defaults to
+    /// the canvas size. See [ComputeCanvas::set_window_size].
+    pub fn window_size(&mut self, width: u32, height: u32) -> &mut Self {
+        self.window_size = Some([width, height]);
+        self
+    }
+
+    pub fn use_swap_chain_desc(&mut self, config: &wgpu::SurfaceConfiguration) -> &mut Self {
+        self.display_format(config.format);
+        self.window_size(config.width, config.height);
+        self.canvas_size(config.width, config.height)
+    }
+
+    pub fn compute_shader(&mut self, code: wgpu::ShaderModuleDescriptor<'a>) -> &mut Self {
+        self.compute_code = Some(code);
+        self
+    }
+
+    /// Defaults to `"main"`
+    pub fn entry_point(&mut self, name: &'a str) -> &mut Self {
+        self.entry_point = name;
+        self
+    }
+
+    /// Has to match the `@workgroup_size` in the shader. Defaults to 8x8.
+    pub fn workgroup_size(&mut self, x: u32, y: u32) -> &mut Self {
+        self.workgroup_size = [x, y];
+        self
+    }
+
+    pub fn build(&mut self, device: &wgpu::Device) -> Result<ComputeCanvas, ShaderBuildError> {
+        let display_format = self
+            .display_format
+            .ok_or(ShaderBuildError::InvalidDisplayFormat)?;
+        let compute_code = self
+            .compute_code
+            .take()
+            .ok_or(ShaderBuildError::InvalidComputeShader)?;
+        validate_shader_module(&compute_code)?;
+
+        let [width, height] = self.canvas_size;
+        let simulation_data = SimulationData::new([width as f32, height as f32], self.clear_color);
+        let simulation_data_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: self.label,
+            contents: bytemuck::cast_slice(&[simulation_data]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ComputeCanvas::layout"),
+            entries: &[
+                // SimulationData
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    count: None,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                },
+                // Previous state
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    count: None,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                },
+                // Next state
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    count: None,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: ComputeCanvas::STATE_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                },
+            ],
+        });
+        let blit_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ComputeCanvas::blit_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    count: None,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    count: None,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: self.label,
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let entry_point = self.entry_point;
+        let pipeline = catch_validation_errors(device, || {
+            let module = device.create_shader_module(compute_code);
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: self.label,
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point,
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        })?;
+
+        let blit_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ComputeCanvas::blit_pipeline_layout"),
+            bind_group_layouts: &[&blit_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = framework_shader("compute_canvas.wgsl", include_str!("compute_canvas.wgsl"));
+        let blit_pipeline = RenderPipelineBuilder::new()
+            .layout(&blit_pipeline_layout)
+            .vertex_shader(shader.module())
+            .fragment_shader(shader.module())
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .color_solid(display_format)
+            .build(device)?;
+
+        // Nearest keeps individual cells crisp when the canvas is scaled
+        // up, and keeps them from blurring together when it's scaled down
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("ComputeCanvas::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let states = create_states(device, width, height);
+        let bind_groups = create_bind_groups(device, &layout, &simulation_data_buffer, &states);
+        let blit_bind_groups = create_blit_bind_groups(device, &blit_layout, &sampler, &states);
+
+        Ok(ComputeCanvas {
+            pipeline,
+            blit_pipeline,
+            layout,
+            blit_layout,
+            sampler,
+            states,
+            bind_groups,
+            blit_bind_groups,
+            workgroup_size: self.workgroup_size,
+            clock: SimulationClock::default(),
+            simulation_data,
+            simulation_data_buffer,
+            window_size: self
+                .window_size
+                .unwrap_or(self.canvas_size)
+                .map(|x| x as f32),
+            current: 0,
+        })
+    }
+}
+
+fn create_states(device: &wgpu::Device, width: u32, height: u32) -> [Texture<'static>; 2] {
+    [0, 1].map(|_| {
+        Texture::from_descriptor(
+            device,
+            wgpu::TextureDescriptor {
+                label: Some("ComputeCanvas::state"),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: ComputeCanvas::STATE_FORMAT,
+                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        )
+    })
+}
+
+fn create_bind_groups(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    simulation_data_buffer: &wgpu::Buffer,
+    states: &[Texture; 2],
+) -> [wgpu::BindGroup; 2] {
+    [0, 1].map(|read| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ComputeCanvas::bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: simulation_data_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&states[read].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&states[1 - read].view),
+                },
+            ],
+        })
+    })
+}
+
+fn create_blit_bind_groups(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    states: &[Texture; 2],
+) -> [wgpu::BindGroup; 2] {
+    [0, 1].map(|i| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ComputeCanvas::blit_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&states[i].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    })
+}
+
+/// How many canvas texels one window pixel covers on each axis
+fn texels_per_pixel(canvas_size: [f32; 2], window_size: [f32; 2]) -> [f32; 2] {
+    let scale = |canvas: f32, window: f32| if window > 0.0 { canvas / window } else { 1.0 };
+    [
+        scale(canvas_size[0], window_size[0]),
+        scale(canvas_size[1], window_size[1]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulation_data_matches_wgsl() {
+        // WGSL rounds the struct up to the alignment of its vec4
+        assert_eq!(std::mem::size_of::<SimulationData>(), 48);
+    }
+
+    #[test]
+    fn mouse_scales_to_texels() {
+        assert_eq!(
+            texels_per_pixel([256.0, 128.0], [1024.0, 1024.0]),
+            [0.25, 0.125]
+        );
+        // A minimized window doesn't divide by 0
+        assert_eq!(texels_per_pixel([256.0, 256.0], [0.0, 0.0]), [1.0, 1.0]);
+    }
+}
@@ -0,0 +1,11 @@
+#include "framework/fullscreen.wgsl"
+
+@group(0) @binding(0)
+var state: texture_2d<f32>;
+@group(0) @binding(1)
+var state_sampler: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(state, state_sampler, in.uv).rgb, 1.0);
+}
@@ -0,0 +1,260 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Times the rest of the enclosing block and adds it to this frame's
+/// [cpu_frame_timings]. With the `puffin` feature the scope is also sent
+/// to puffin, once `puffin::set_scopes_on(true)` has been called.
+///
+/// ```ignore
+/// {
+///     framework::cpu_scope!("encode shadow pass");
+///     // ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! cpu_scope {
+    ($name:expr) => {
+        let _cpu_scope = $crate::CpuScope::new($name);
+    };
+}
+
+/// How long a scope took in total over a frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuScopeTiming {
+    pub name: &'static str,
+    /// How many scopes this one was nested in
+    pub depth: usize,
+    pub duration: Duration,
+    /// How many times the scope ran this frame
+    pub calls: u32,
+}
+
+/// A single run of a scope, for drawing flamegraphs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuSpan {
+    pub name: &'static str,
+    pub depth: usize,
+    /// Relative to the first scope of the frame
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+struct Record {
+    name: &'static str,
+    depth: usize,
+    start: Instant,
+    duration: Option<Duration>,
+}
+
+/// Collects scopes into frames. [cpu_scope!] and the free functions use
+/// the global one, which the framework ends a frame on after every
+/// [crate::Demo::render]. Separate instances are for timing something on
+/// its own, without other threads' scopes mixed in.
+pub struct CpuProfiler {
+    current_frame: Mutex<Vec<Record>>,
+    frame: AtomicU64,
+    last_frame: Mutex<Vec<CpuScopeTiming>>,
+    last_spans: Mutex<Vec<CpuSpan>>,
+}
+
+static GLOBAL: CpuProfiler = CpuProfiler::new();
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+impl Default for CpuProfiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CpuProfiler {
+    pub const fn new() -> Self {
+        Self {
+            current_frame: Mutex::new(Vec::new()),
+            frame: AtomicU64::new(0),
+            last_frame: Mutex::new(Vec::new()),
+            last_spans: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The profiler [cpu_scope!] records into
+    pub fn global() -> &'static CpuProfiler {
+        &GLOBAL
+    }
+
+    /// Like [cpu_scope!], recording into this profiler. Nesting depth is
+    /// counted per thread across every profiler.
+    pub fn scope(&self, name: &'static str) -> CpuScope<'_> {
+        let depth = DEPTH.with(|d| d.replace(d.get() + 1));
+        #[cfg(feature = "puffin")]
+        let _puffin =
+            puffin::are_scopes_on().then(|| puffin::ProfilerScope::new(puffin_scope_id(name), ""));
+        let mut records = self.current_frame.lock().unwrap();
+        records.push(Record {
+            name,
+            depth,
+            start: Instant::now(),
+            duration: None,
+        });
+        CpuScope {
+            profiler: self,
+            frame: self.frame.load(Ordering::Relaxed),
+            index: records.len() - 1,
+            #[cfg(feature = "puffin")]
+            _puffin,
+        }
+    }
+
+    /// Aggregates the scopes recorded since the last call
+    pub fn end_frame(&self) {
+        let records = {
+            let mut current = self.current_frame.lock().unwrap();
+            self.frame.fetch_add(1, Ordering::Relaxed);
+            std::mem::take(&mut *current)
+        };
+        let frame_start = records.first().map(|r| r.start);
+        let mut timings: Vec<CpuScopeTiming> = Vec::new();
+        let mut spans = Vec::new();
+        for record in records {
+            let duration = match record.duration {
+                Some(duration) => duration,
+                None => continue,
+            };
+            spans.push(CpuSpan {
+                name: record.name,
+                depth: record.depth,
+                start: frame_start.map_or(Duration::ZERO, |s| record.start - s),
+                duration,
+            });
+            match timings
+                .iter_mut()
+                .find(|t| t.name == record.name && t.depth == record.depth)
+            {
+                Some(timing) => {
+                    timing.duration += duration;
+                    timing.calls += 1;
+                }
+                None => timings.push(CpuScopeTiming {
+                    name: record.name,
+                    depth: record.depth,
+                    duration,
+                    calls: 1,
+                }),
+            }
+        }
+        *self.last_frame.lock().unwrap() = timings;
+        *self.last_spans.lock().unwrap() = spans;
+    }
+
+    /// The scopes from the last complete frame, in the order they started
+    pub fn frame_timings(&self) -> Vec<CpuScopeTiming> {
+        self.last_frame.lock().unwrap().clone()
+    }
+
+    /// Every scope from the last complete frame, in the order they started
+    pub fn frame_spans(&self) -> Vec<CpuSpan> {
+        self.last_spans.lock().unwrap().clone()
+    }
+}
+
+/// Guard created by [cpu_scope!]. The time is recorded when it's dropped.
+pub struct CpuScope<'a> {
+    profiler: &'a CpuProfiler,
+    frame: u64,
+    index: usize,
+    #[cfg(feature = "puffin")]
+    _puffin: Option<puffin::ProfilerScope>,
+}
+
+impl CpuScope<'static> {
+    /// Starts a scope on [CpuProfiler::global]
+    pub fn new(name: &'static str) -> Self {
+        GLOBAL.scope(name)
+    }
+}
+
+impl Drop for CpuScope<'_> {
+    fn drop(&mut self) {
+        DEPTH.with(|d| d.set(d.get().saturating_sub(1)));
+        let mut records = self.profiler.current_frame.lock().unwrap();
+        // The frame may have ended while this scope was open, in which case
+        // it's dropped rather than attributed to the wrong frame
+        if self.profiler.frame.load(Ordering::Relaxed) == self.frame {
+            if let Some(record) = records.get_mut(self.index) {
+                record.duration = Some(record.start.elapsed());
+            }
+        }
+    }
+}
+
+/// puffin identifies scopes by an id registered up front, so we keep one
+/// per name
+#[cfg(feature = "puffin")]
+fn puffin_scope_id(name: &'static str) -> puffin::ScopeId {
+    static IDS: Mutex<Vec<(&'static str, puffin::ScopeId)>> = Mutex::new(Vec::new());
+    let mut ids = IDS.lock().unwrap();
+    match ids.iter().find(|(n, _)| *n == name) {
+        Some((_, id)) => *id,
+        None => {
+            let id = puffin::ThreadProfiler::call(|tp| {
+                tp.register_named_scope(name, "", file!(), line!())
+            });
+            ids.push((name, id));
+            id
+        }
+    }
+}
+
+/// Aggregates the scopes recorded since the last call. The framework calls
+/// this after every frame.
+pub fn end_cpu_frame() {
+    GLOBAL.end_frame();
+
+    #[cfg(feature = "puffin")]
+    puffin::GlobalProfiler::lock().new_frame();
+}
+
+/// The scopes from the last complete frame, in the order they started
+pub fn cpu_frame_timings() -> Vec<CpuScopeTiming> {
+    GLOBAL.frame_timings()
+}
+
+/// Every scope from the last complete frame, in the order they started
+pub fn cpu_frame_spans() -> Vec<CpuSpan> {
+    GLOBAL.frame_spans()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_scopes_are_aggregated() {
+        // Other tests record into the global profiler at the same time
+        let profiler = CpuProfiler::new();
+        {
+            let _frame = profiler.scope("frame");
+            for _ in 0..3 {
+                let _pass = profiler.scope("pass");
+            }
+        }
+        profiler.end_frame();
+
+        let timings = profiler.frame_timings();
+        let summary = timings
+            .iter()
+            .map(|t| (t.name, t.depth, t.calls))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [("frame", 0, 1), ("pass", 1, 3)]);
+        assert!(timings[0].duration >= timings[1].duration);
+
+        let spans = profiler.frame_spans();
+        assert_eq!(spans.len(), 4);
+        assert_eq!(spans[0].start, Duration::ZERO);
+        assert!(spans[3].start >= spans[1].start + spans[1].duration);
+    }
+}
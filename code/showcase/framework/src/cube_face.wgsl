@@ -0,0 +1,32 @@
+// Draws one face of a cube map per pass with a full screen triangle.
+// Bind the face being drawn and use face_direction to find where each
+// fragment looks.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// The direction a texel of a cube map face looks along, with v going down
+// the face like wgpu expects
+fn face_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    let s = uv.x * 2.0 - 1.0;
+    let t = uv.y * 2.0 - 1.0;
+    switch face {
+        case 0u: { return normalize(vec3<f32>(1.0, -t, -s)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -t, s)); }
+        case 2u: { return normalize(vec3<f32>(s, 1.0, t)); }
+        case 3u: { return normalize(vec3<f32>(s, -1.0, -t)); }
+        case 4u: { return normalize(vec3<f32>(s, -t, 1.0)); }
+        default: { return normalize(vec3<f32>(-s, -t, -1.0)); }
+    }
+}
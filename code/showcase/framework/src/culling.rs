@@ -0,0 +1,569 @@
+use anyhow::*;
+use cgmath::*;
+use winit::keyboard::KeyCode;
+
+use crate::camera::{Camera, Projection};
+use crate::color::Color;
+use crate::debug_draw::DebugDraw;
+use crate::input::Input;
+use crate::jobs::JobPool;
+use crate::shader::catch_validation_errors;
+use crate::stats::FrameStats;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Spheres closer to a plane than this are on the fence, and the CPU and
+/// GPU are allowed to disagree about them
+const BOUNDARY_EPSILON: f32 = 1e-4;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BoundingSphere {
+    pub center: [f32; 3],
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn new(center: Point3<f32>, radius: f32) -> Self {
+        Self {
+            center: center.into(),
+            radius,
+        }
+    }
+
+    /// A sphere around all of `points`. It's not the smallest one, but
+    /// it's close enough for culling.
+    pub fn from_points(points: &[Point3<f32>]) -> Self {
+        if points.is_empty() {
+            return Self::new(Point3::origin(), 0.0);
+        }
+        let (min, max) = points.iter().fold((points[0], points[0]), |(min, max), p| {
+            (
+                Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+            )
+        });
+        let center = min.midpoint(max);
+        let radius = points
+            .iter()
+            .map(|p| p.distance(center))
+            .fold(0.0, f32::max);
+        Self::new(center, radius)
+    }
+
+    /// The sphere after `matrix`, grown to cover non-uniform scaling
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> Self {
+        let center = matrix * Point3::from(self.center).to_homogeneous();
+        let scale = [matrix.x, matrix.y, matrix.z]
+            .iter()
+            .map(|axis| axis.truncate().magnitude())
+            .fold(0.0, f32::max);
+        Self::new(Point3::from_homogeneous(center), self.radius * scale)
+    }
+}
+
+/// The six planes of a camera's view volume, pointing inwards
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    pub planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix with wgpu's 0 to
+    /// 1 depth range
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+        let m = view_proj.transpose();
+        let planes = [m.w + m.x, m.w - m.x, m.w + m.y, m.w - m.y, m.z, m.w - m.z]
+            .map(|p| p / p.truncate().magnitude());
+        Self { planes }
+    }
+
+    /// How far inside the frustum the sphere reaches past the closest
+    /// plane. Negative means it's completely outside.
+    pub fn margin(&self, sphere: &BoundingSphere) -> f32 {
+        let center = Vector3::from(sphere.center);
+        self.planes
+            .iter()
+            .map(|p| p.truncate().dot(center) + p.w + sphere.radius)
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    pub fn contains(&self, sphere: &BoundingSphere) -> bool {
+        self.margin(sphere) >= 0.0
+    }
+
+    /// Whether each sphere is visible. Big lists are split over
+    /// [JobPool::global].
+    pub fn cull(&self, spheres: &[BoundingSphere]) -> Vec<bool> {
+        JobPool::global().map(spheres, 4096, |s| self.contains(s))
+    }
+}
+
+/// The corners of the volume `view_proj` sees in world space, with wgpu's
+/// 0 to 1 depth range. The near plane's corners come first, going round
+/// from the bottom left, then the far plane's in the same order.
+pub fn frustum_corners(view_proj: Matrix4<f32>) -> [Point3<f32>; 8] {
+    let inverse = view_proj.invert().unwrap_or_else(Matrix4::identity);
+    let square = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+    let mut corners = [Point3::origin(); 8];
+    for (i, corner) in corners.iter_mut().enumerate() {
+        let (x, y) = square[i % 4];
+        let z = if i < 4 { 0.0 } else { 1.0 };
+        *corner = Point3::from_homogeneous(inverse * Vector4::new(x, y, z, 1.0));
+    }
+    corners
+}
+
+/// A debug toggle that keeps culling and shadow fitting on a saved camera
+/// while the real one flies around, to see from outside what they
+/// include. Things that should be on screen but got culled show up as
+/// missing inside the frozen frustum. F7 toggles it.
+///
+/// ```ignore
+/// // In Demo::update
+/// self.culling_camera.update(&display.input, &self.camera, &self.projection);
+/// let (camera, projection) = self.culling_camera.get(&self.camera, &self.projection);
+/// let frustum = Frustum::from_matrix(projection.calc_matrix() * camera.calc_matrix());
+/// shadow_map.fit(camera, projection);
+/// self.culling_camera.draw(&mut self.debug_draw);
+/// ```
+pub struct CullingCamera {
+    pub key: KeyCode,
+    frozen: Option<(Camera, Projection)>,
+}
+
+impl Default for CullingCamera {
+    fn default() -> Self {
+        Self {
+            key: KeyCode::F7,
+            frozen: None,
+        }
+    }
+}
+
+impl CullingCamera {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles the key binding, freezing on `camera` and `projection`
+    pub fn update(&mut self, input: &Input, camera: &Camera, projection: &Projection) {
+        if input.is_key_pressed(self.key) {
+            if self.is_frozen() {
+                self.unfreeze();
+            } else {
+                self.freeze(camera, projection);
+            }
+            log::info!(
+                "Culling camera {}",
+                if self.is_frozen() { "frozen" } else { "live" }
+            );
+        }
+    }
+
+    pub fn freeze(&mut self, camera: &Camera, projection: &Projection) {
+        self.frozen = Some((*camera, *projection));
+    }
+
+    pub fn unfreeze(&mut self) {
+        self.frozen = None;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.is_some()
+    }
+
+    /// The camera to cull and fit shadows with: the saved one while
+    /// frozen, otherwise the live `camera` and `projection`
+    pub fn get<'a>(
+        &'a self,
+        camera: &'a Camera,
+        projection: &'a Projection,
+    ) -> (&'a Camera, &'a Projection) {
+        match &self.frozen {
+            Some((camera, projection)) => (camera, projection),
+            None => (camera, projection),
+        }
+    }
+
+    pub fn view_proj(&self, camera: &Camera, projection: &Projection) -> Matrix4<f32> {
+        let (camera, projection) = self.get(camera, projection);
+        projection.calc_matrix() * camera.calc_matrix()
+    }
+
+    /// Outlines the frozen frustum, if there is one
+    pub fn draw(&self, debug: &mut DebugDraw) {
+        if let Some((camera, projection)) = &self.frozen {
+            debug.frustum(
+                projection.calc_matrix() * camera.calc_matrix(),
+                Color::rgb(1.0, 0.8, 0.0),
+            );
+        }
+    }
+}
+
+/// A sphere the CPU and GPU culled differently
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CullMismatch {
+    pub index: usize,
+    pub cpu_visible: bool,
+    pub gpu_visible: bool,
+}
+
+/// Checks `gpu_visible` against culling on the CPU. Spheres right on the
+/// edge of the frustum aren't reported, as rounding can go either way.
+pub fn compare_culling(
+    frustum: &Frustum,
+    spheres: &[BoundingSphere],
+    gpu_visible: &[bool],
+) -> Vec<CullMismatch> {
+    spheres
+        .iter()
+        .zip(gpu_visible)
+        .enumerate()
+        .filter_map(|(index, (sphere, &gpu_visible))| {
+            let margin = frustum.margin(sphere);
+            let cpu_visible = margin >= 0.0;
+            let on_the_fence = margin.abs() <= BOUNDARY_EPSILON * sphere.radius.max(1.0);
+            (cpu_visible != gpu_visible && !on_the_fence).then_some(CullMismatch {
+                index,
+                cpu_visible,
+                gpu_visible,
+            })
+        })
+        .collect()
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniforms {
+    planes: [[f32; 4]; 6],
+    count: u32,
+    _padding: [u32; 3],
+}
+
+/// Frustum culls bounding spheres in a compute shader, writing a `u32` per
+/// sphere to [GpuCuller::visibility_buffer] that's 1 if it's visible.
+/// Fall back to [Frustum::cull] without
+/// [Capabilities::compute_shaders](crate::Capabilities).
+///
+/// With [GpuCuller::compare] on, the results are read back and checked
+/// against [Frustum::cull], which is handy for testing changes to the
+/// shader and for showing how the two approaches line up. F6 toggles it.
+///
+/// ```ignore
+/// culler.set_spheres(&display.device, &bounds);
+/// culler.update(&display.input);
+/// culler.dispatch(&display.queue, &mut encoder, &Frustum::from_matrix(view_proj));
+/// display.queue.submit([encoder.finish()]);
+/// culler.check(&display.device, &mut display.frame_stats)?;
+/// ```
+pub struct GpuCuller {
+    pub compare: bool,
+    pub key: KeyCode,
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    uniforms: wgpu::Buffer,
+    spheres: Vec<BoundingSphere>,
+    sphere_buffer: wgpu::Buffer,
+    visibility: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// The frustum of the last dispatch, if it's waiting to be compared
+    pending: Option<Frustum>,
+    mismatches: Vec<CullMismatch>,
+}
+
+impl GpuCuller {
+    pub fn new(device: &wgpu::Device) -> Result<Self> {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GpuCuller::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GpuCuller::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = catch_validation_errors(device, || {
+            let module = device.create_shader_module(wgpu::include_wgsl!("culling.wgsl"));
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("GpuCuller::pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        })?;
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuCuller::uniforms"),
+            size: std::mem::size_of::<CullUniforms>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (sphere_buffer, visibility, readback, bind_group) =
+            create_buffers(device, &layout, &uniforms, &[]);
+
+        Ok(Self {
+            compare: false,
+            key: KeyCode::F6,
+            layout,
+            pipeline,
+            uniforms,
+            spheres: Vec::new(),
+            sphere_buffer,
+            visibility,
+            readback,
+            bind_group,
+            pending: None,
+            mismatches: Vec::new(),
+        })
+    }
+
+    /// Replaces the spheres to cull, usually once per frame after the
+    /// objects have moved
+    pub fn set_spheres(&mut self, device: &wgpu::Device, spheres: &[BoundingSphere]) {
+        let (sphere_buffer, visibility, readback, bind_group) =
+            create_buffers(device, &self.layout, &self.uniforms, spheres);
+        self.sphere_buffer = sphere_buffer;
+        self.visibility = visibility;
+        self.readback = readback;
+        self.bind_group = bind_group;
+        self.spheres = spheres.to_vec();
+        self.pending = None;
+    }
+
+    /// Storage buffer with a `u32` per sphere, 1 if it's visible
+    pub fn visibility_buffer(&self) -> &wgpu::Buffer {
+        &self.visibility
+    }
+
+    /// Handles the key binding
+    pub fn update(&mut self, input: &Input) {
+        if input.is_key_pressed(self.key) {
+            self.compare = !self.compare;
+            log::info!(
+                "CPU/GPU cull comparison {}",
+                if self.compare { "on" } else { "off" }
+            );
+        }
+    }
+
+    pub fn dispatch(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        frustum: &Frustum,
+    ) {
+        crate::cpu_scope!("GpuCuller::dispatch");
+        let uniforms = CullUniforms {
+            planes: frustum.planes.map(|p| p.into()),
+            count: self.spheres.len() as u32,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniforms, 0, bytemuck::bytes_of(&uniforms));
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("GpuCuller::dispatch"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.dispatch_workgroups((self.spheres.len() as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        self.pending = None;
+        if self.compare && !self.spheres.is_empty() {
+            encoder.copy_buffer_to_buffer(
+                &self.visibility,
+                0,
+                &self.readback,
+                0,
+                self.readback.size(),
+            );
+            self.pending = Some(*frustum);
+        }
+    }
+
+    /// When comparing, waits for the last dispatch to finish and checks
+    /// it against the CPU. Call it after submitting the dispatch. Both
+    /// culled counts go into `stats`, and mismatches are logged.
+    pub fn check(&mut self, device: &wgpu::Device, stats: &mut FrameStats) -> Result<()> {
+        let frustum = match self.pending.take() {
+            Some(frustum) => frustum,
+            None => return Ok(()),
+        };
+        crate::cpu_scope!("GpuCuller::check");
+        let slice = self.readback.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv()??;
+        let gpu_visible = bytemuck::cast_slice::<_, u32>(&slice.get_mapped_range())
+            .iter()
+            .map(|v| *v != 0)
+            .collect::<Vec<_>>();
+        self.readback.unmap();
+
+        stats.culled_gpu += gpu_visible.iter().filter(|v| !**v).count() as u32;
+        stats.culled_cpu += self.spheres.iter().filter(|s| !frustum.contains(s)).count() as u32;
+        self.mismatches = compare_culling(&frustum, &self.spheres, &gpu_visible);
+        for mismatch in &self.mismatches {
+            log::warn!(
+                "Sphere {} {:?} is {} on the CPU but {} on the GPU",
+                mismatch.index,
+                self.spheres[mismatch.index],
+                if mismatch.cpu_visible {
+                    "visible"
+                } else {
+                    "culled"
+                },
+                if mismatch.gpu_visible {
+                    "visible"
+                } else {
+                    "culled"
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Mismatches found by the last [GpuCuller::check]
+    pub fn mismatches(&self) -> &[CullMismatch] {
+        &self.mismatches
+    }
+}
+
+fn create_buffers(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniforms: &wgpu::Buffer,
+    spheres: &[BoundingSphere],
+) -> (wgpu::Buffer, wgpu::Buffer, wgpu::Buffer, wgpu::BindGroup) {
+    use wgpu::util::DeviceExt;
+
+    // Empty bindings aren't allowed, so keep room for one sphere
+    let placeholder = [BoundingSphere::new(Point3::origin(), 0.0)];
+    let contents = if spheres.is_empty() {
+        &placeholder[..]
+    } else {
+        spheres
+    };
+    let sphere_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("GpuCuller::spheres"),
+        contents: bytemuck::cast_slice(contents),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+    let size = (contents.len() * std::mem::size_of::<u32>()) as wgpu::BufferAddress;
+    let visibility = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("GpuCuller::visibility"),
+        size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("GpuCuller::readback"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("GpuCuller::bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniforms.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: sphere_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: visibility.as_entire_binding(),
+            },
+        ],
+    });
+    (sphere_buffer, visibility, readback, bind_group)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn frustum_culls_and_comparison_ignores_the_edge() {
+        // Remaps depth from -1 to 1 into 0 to 1
+        #[rustfmt::skip]
+        let to_wgpu = Matrix4::new(
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 0.5, 0.0,
+            0.0, 0.0, 0.5, 1.0,
+        );
+        let proj = to_wgpu * perspective(Deg(90.0), 1.0, 1.0, 100.0);
+        let view = Matrix4::look_to_rh(Point3::origin(), -Vector3::unit_z(), Vector3::unit_y());
+        let frustum = Frustum::from_matrix(proj * view);
+
+        let spheres = [
+            BoundingSphere::new(Point3::new(0.0, 0.0, -10.0), 1.0),
+            // Behind the camera
+            BoundingSphere::new(Point3::new(0.0, 0.0, 10.0), 1.0),
+            // Past the far plane
+            BoundingSphere::new(Point3::new(0.0, 0.0, -200.0), 1.0),
+            // Off to the side, but big enough to poke in
+            BoundingSphere::new(Point3::new(-20.0, 0.0, -10.0), 8.0),
+            // Just touching the near plane from behind
+            BoundingSphere::new(Point3::new(0.0, 0.0, 0.0), 1.0),
+        ];
+        assert_eq!(frustum.cull(&spheres[..4]), [true, false, false, true]);
+
+        let corners = frustum_corners(proj * view);
+        assert!((corners[0] - Point3::new(-1.0, -1.0, -1.0)).magnitude() < 1e-3);
+        assert!((corners[6] - Point3::new(100.0, 100.0, -100.0)).magnitude() < 1e-2);
+        for corner in corners {
+            assert!(frustum.margin(&BoundingSphere::new(corner, 0.0)).abs() < 1e-2);
+        }
+
+        let gpu = [true, true, false, true, false];
+        assert_eq!(
+            compare_culling(&frustum, &spheres, &gpu),
+            [CullMismatch {
+                index: 1,
+                cpu_visible: false,
+                gpu_visible: true,
+            }]
+        );
+
+        let sphere = BoundingSphere::new(Point3::new(1.0, 0.0, 0.0), 1.0).transformed(
+            &(Matrix4::from_translation(Vector3::unit_y())
+                * Matrix4::from_nonuniform_scale(1.0, 3.0, 1.0)),
+        );
+        assert_eq!(sphere, BoundingSphere::new(Point3::new(1.0, 1.0, 0.0), 3.0));
+    }
+}
@@ -0,0 +1,30 @@
+struct CullUniforms {
+    planes: array<vec4<f32>, 6>,
+    count: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> cull: CullUniforms;
+// xyz is the center, w the radius
+@group(0) @binding(1)
+var<storage, read> spheres: array<vec4<f32>>;
+// 1 if visible
+@group(0) @binding(2)
+var<storage, read_write> visibility: array<u32>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= cull.count) {
+        return;
+    }
+    let sphere = spheres[i];
+    var visible = 1u;
+    for (var p = 0u; p < 6u; p = p + 1u) {
+        let plane = cull.planes[p];
+        if (dot(plane.xyz, sphere.xyz) + plane.w < -sphere.w) {
+            visible = 0u;
+        }
+    }
+    visibility[i] = visible;
+}
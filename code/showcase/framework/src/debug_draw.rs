@@ -0,0 +1,282 @@
+use anyhow::*;
+use cgmath::*;
+
+use crate::camera::{Camera, Projection};
+use crate::color::Color;
+use crate::culling::frustum_corners;
+use crate::pipeline::RenderPipelineBuilder;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+const CIRCLE_SEGMENTS: u32 = 32;
+
+const VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+    wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+
+/// Lines added over a frame for seeing what the code thinks is going on,
+/// like where a camera is looking or what a bounding volume covers.
+/// Everything added is drawn once by the next [DebugDraw::update] and
+/// [DebugDraw::draw] and then forgotten, so add it again every frame it
+/// should stay. It all goes in one vertex buffer and one draw, unlit.
+///
+/// ```ignore
+/// let mut debug = DebugDraw::new(&display.device, display.config.format, Some(Texture::DEPTH_FORMAT), display.sample_count())?;
+///
+/// // In Demo::render
+/// debug.grid((0.0, 0.0, 0.0), 20, 1.0, Color::GREY);
+/// debug.axes(light_transform.matrix(), 0.5);
+/// debug.sphere(bounds.center, bounds.radius, Color::YELLOW);
+/// debug.update(&display.device, &display.queue, &camera, &projection);
+/// model_pass.draw_model(...);
+/// debug.draw(&mut model_pass);
+/// ```
+pub struct DebugDraw {
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    vertices: Vec<DebugVertex>,
+    vertex_buffer: wgpu::Buffer,
+    num_vertices: u32,
+}
+
+impl DebugDraw {
+    /// `depth_format` is the format of the pass's depth attachment, if it
+    /// has one. Lines are depth tested against the scene but don't write
+    /// depth themselves.
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
+    ) -> Result<Self> {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("DebugDraw::layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("DebugDraw::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let mut builder = RenderPipelineBuilder::new();
+        builder
+            .layout(&pipeline_layout)
+            .vertex_shader(wgpu::include_wgsl!("debug_draw.wgsl"))
+            .fragment_shader(wgpu::include_wgsl!("debug_draw.wgsl"))
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .primitive_topology(wgpu::PrimitiveTopology::LineList)
+            .vertex_buffer_desc(wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<DebugVertex>() as _,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &VERTEX_ATTRIBUTES,
+            })
+            .color_solid(color_format)
+            .sample_count(sample_count);
+        if let Some(format) = depth_format {
+            builder.depth_no_stencil(format, false, wgpu::CompareFunction::LessEqual);
+        }
+        let pipeline = builder.build(device)?;
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("DebugDraw::uniform_buffer"),
+            size: std::mem::size_of::<[[f32; 4]; 4]>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("DebugDraw::bind_group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        Ok(Self {
+            pipeline,
+            uniform_buffer,
+            bind_group,
+            vertices: Vec::new(),
+            vertex_buffer: create_vertex_buffer(device, 256),
+            num_vertices: 0,
+        })
+    }
+
+    pub fn line<P: Into<Point3<f32>>>(&mut self, from: P, to: P, color: Color) {
+        for p in [from.into(), to.into()] {
+            self.vertices.push(DebugVertex {
+                position: p.into(),
+                color: color.into(),
+            });
+        }
+    }
+
+    /// The outline of the volume `view_proj` sees, like a camera's from
+    /// [crate::CullingCamera::view_proj]
+    pub fn frustum(&mut self, view_proj: Matrix4<f32>, color: Color) {
+        self.box_edges(frustum_corners(view_proj), color);
+    }
+
+    /// An axis aligned box between two opposite corners
+    pub fn aabb<P: Into<Point3<f32>>>(&mut self, min: P, max: P, color: Color) {
+        let (min, max) = (min.into(), max.into());
+        let corners = [0, 1, 2, 3, 4, 5, 6, 7].map(|i: usize| {
+            // Same order as frustum_corners, with min z as the near side
+            let x = if i % 4 == 1 || i % 4 == 2 {
+                max.x
+            } else {
+                min.x
+            };
+            let y = if i % 4 >= 2 { max.y } else { min.y };
+            let z = if i >= 4 { max.z } else { min.z };
+            Point3::new(x, y, z)
+        });
+        self.box_edges(corners, color);
+    }
+
+    /// Circles around each axis, which reads as a sphere from any side.
+    /// Handy for [crate::BoundingSphere]s and light ranges.
+    pub fn sphere<P: Into<Point3<f32>>>(&mut self, center: P, radius: f32, color: Color) {
+        let center = center.into();
+        let axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+        for i in 0..3 {
+            let points = circle(center, axes[i] * radius, axes[(i + 1) % 3] * radius);
+            for pair in points.windows(2) {
+                self.line(pair[0], pair[1], color);
+            }
+        }
+    }
+
+    /// `matrix`'s X, Y and Z axes in red, green and blue, `size` long,
+    /// from its origin. Shows where a [crate::Transform] or a light
+    /// points.
+    pub fn axes(&mut self, matrix: Matrix4<f32>, size: f32) {
+        let origin = Point3::from_homogeneous(matrix.w);
+        let colors = [Color::RED, Color::GREEN, Color::BLUE];
+        for (axis, color) in [matrix.x, matrix.y, matrix.z].iter().zip(colors) {
+            let end = origin + axis.truncate().normalize_to(size);
+            self.line(origin, end, color);
+        }
+    }
+
+    /// A flat grid on the XZ plane around `center`, `cells` squares of
+    /// `spacing` across, for a sense of scale and where the ground is
+    pub fn grid<P: Into<Point3<f32>>>(
+        &mut self,
+        center: P,
+        cells: u32,
+        spacing: f32,
+        color: Color,
+    ) {
+        let center = center.into();
+        let half = cells as f32 * spacing / 2.0;
+        for i in 0..=cells {
+            let offset = i as f32 * spacing - half;
+            self.line(
+                center + Vector3::new(offset, 0.0, -half),
+                center + Vector3::new(offset, 0.0, half),
+                color,
+            );
+            self.line(
+                center + Vector3::new(-half, 0.0, offset),
+                center + Vector3::new(half, 0.0, offset),
+                color,
+            );
+        }
+    }
+
+    /// The 12 edges of a box with corners in [frustum_corners]'s order
+    fn box_edges(&mut self, c: [Point3<f32>; 8], color: Color) {
+        for i in 0..4 {
+            let next = (i + 1) % 4;
+            self.line(c[i], c[next], color);
+            self.line(c[i + 4], c[next + 4], color);
+            self.line(c[i], c[i + 4], color);
+        }
+    }
+
+    /// Uploads the camera and everything added since the last update,
+    /// then starts collecting the next frame's lines
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        projection: &Projection,
+    ) {
+        let view_proj: [[f32; 4]; 4] = (projection.calc_matrix() * camera.calc_matrix()).into();
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[view_proj]));
+        let needed = (self.vertices.len() * std::mem::size_of::<DebugVertex>()) as u64;
+        if needed > self.vertex_buffer.size() {
+            self.vertex_buffer =
+                create_vertex_buffer(device, self.vertices.len().next_power_of_two());
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+        self.num_vertices = self.vertices.len() as u32;
+        self.vertices.clear();
+    }
+
+    /// Draws the lines from the last update into a pass that's already
+    /// been started. It changes the pass's bind group 0.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        if self.num_vertices == 0 {
+            return;
+        }
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw(0..self.num_vertices, 0..1);
+    }
+}
+
+/// Points around a circle from `center + u` through `center + v`, with
+/// the first repeated at the end to close it
+fn circle(center: Point3<f32>, u: Vector3<f32>, v: Vector3<f32>) -> Vec<Point3<f32>> {
+    (0..=CIRCLE_SEGMENTS)
+        .map(|i| {
+            let angle = Rad::full_turn() * (i as f32 / CIRCLE_SEGMENTS as f32);
+            center + u * angle.cos() + v * angle.sin()
+        })
+        .collect()
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("DebugDraw::vertex_buffer"),
+        size: (capacity.max(1) * std::mem::size_of::<DebugVertex>()) as u64,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circles_close_at_the_radius() {
+        let center = Point3::new(1.0, 2.0, 3.0);
+        let points = circle(center, Vector3::unit_x() * 2.0, Vector3::unit_z() * 2.0);
+        assert_eq!(points.len(), CIRCLE_SEGMENTS as usize + 1);
+        assert!((points[0] - points[points.len() - 1]).magnitude() < 1e-4);
+        for p in &points {
+            assert!((p.distance(center) - 2.0).abs() < 1e-4);
+            assert!((p.y - center.y).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::texture::Texture;
+use crate::viewport::{Corner, RenderPassExt, ViewportRect};
+
+/// How a texture is shown in a [DebugInset]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DebugView {
+    /// RGB as is
+    Color,
+    /// The red channel in gray, for single channel targets like SSAO
+    Grayscale,
+    /// Depth from an orthographic projection, which is already linear,
+    /// like a directional light's shadow map
+    Depth,
+    /// Depth from a perspective projection, linearized so the whole range
+    /// is visible instead of everything past the near plane being white
+    PerspectiveDepth { near: f32, far: f32 },
+}
+
+impl DebugView {
+    fn uniforms(self) -> InsetUniforms {
+        let (mode, near, far) = match self {
+            DebugView::Color => (0, 0.0, 1.0),
+            DebugView::Grayscale => (1, 0.0, 1.0),
+            DebugView::Depth => (2, 0.0, 1.0),
+            DebugView::PerspectiveDepth { near, far } => (3, near, far),
+        };
+        InsetUniforms {
+            mode,
+            near,
+            far,
+            _padding: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct InsetUniforms {
+    mode: u32,
+    near: f32,
+    far: f32,
+    _padding: u32,
+}
+
+struct InsetSource {
+    name: String,
+    size: wgpu::Extent3d,
+    is_depth: bool,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Draws one of a set of registered textures in a corner of the screen,
+/// for checking what intermediate passes (shadow maps, SSAO, G-buffers)
+/// actually contain.
+///
+/// ```ignore
+/// inset.add_source(device, "shadow map", &shadow_map, DebugView::Depth);
+/// inset.add_source(device, "ssao", &ssao_target, DebugView::Grayscale);
+/// // On a key press
+/// inset.cycle();
+/// // After the frame is drawn
+/// inset.render(&mut encoder, &view, display.viewport());
+/// ```
+pub struct DebugInset {
+    pub corner: Corner,
+    /// Fraction of the viewport's height the inset takes up
+    pub size: f32,
+    color_layout: wgpu::BindGroupLayout,
+    depth_layout: wgpu::BindGroupLayout,
+    color_pipeline: wgpu::RenderPipeline,
+    depth_pipeline: wgpu::RenderPipeline,
+    sources: Vec<InsetSource>,
+    selected: Option<usize>,
+}
+
+impl DebugInset {
+    pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Result<Self> {
+        let color_layout = create_layout(
+            device,
+            "DebugInset::color_layout",
+            wgpu::TextureSampleType::Float { filterable: false },
+        );
+        let depth_layout = create_layout(
+            device,
+            "DebugInset::depth_layout",
+            wgpu::TextureSampleType::Depth,
+        );
+        let color_pipeline = create_pipeline(device, &color_layout, "fs_color", output_format)?;
+        let depth_pipeline = create_pipeline(device, &depth_layout, "fs_depth", output_format)?;
+
+        Ok(Self {
+            corner: Corner::BottomRight,
+            size: 0.3,
+            color_layout,
+            depth_layout,
+            color_pipeline,
+            depth_pipeline,
+            sources: Vec::new(),
+            selected: None,
+        })
+    }
+
+    /// Registers a texture that can be shown. It needs `TEXTURE_BINDING`
+    /// usage. Adding a source with the same name replaces it, which is how
+    /// to keep up with textures that get recreated on resize.
+    pub fn add_source(
+        &mut self,
+        device: &wgpu::Device,
+        name: &str,
+        texture: &Texture,
+        view: DebugView,
+    ) {
+        let is_depth = texture.desc.format.is_depth_stencil_format();
+        let uniforms = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("DebugInset::uniforms"),
+            contents: bytemuck::bytes_of(&view.uniforms()),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(name),
+            layout: if is_depth {
+                &self.depth_layout
+            } else {
+                &self.color_layout
+            },
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+            ],
+        });
+        let source = InsetSource {
+            name: name.to_string(),
+            size: texture.desc.size,
+            is_depth,
+            bind_group,
+        };
+        match self.sources.iter_mut().find(|s| s.name == name) {
+            Some(existing) => *existing = source,
+            None => self.sources.push(source),
+        }
+    }
+
+    pub fn remove_source(&mut self, name: &str) {
+        let selected = self.selected();
+        self.sources.retain(|s| s.name != name);
+        self.selected = selected.and_then(|s| self.sources.iter().position(|x| x.name == s));
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = &str> {
+        self.sources.iter().map(|s| s.name.as_str())
+    }
+
+    /// The name of the source being shown
+    pub fn selected(&self) -> Option<String> {
+        self.selected.map(|i| self.sources[i].name.clone())
+    }
+
+    /// Shows the named source, or hides the inset with `None`
+    pub fn select(&mut self, name: Option<&str>) {
+        self.selected = name.and_then(|n| self.sources.iter().position(|s| s.name == n));
+    }
+
+    /// Steps through each source and then back to hidden
+    pub fn cycle(&mut self) {
+        self.selected = match self.selected {
+            None if !self.sources.is_empty() => Some(0),
+            Some(i) if i + 1 < self.sources.len() => Some(i + 1),
+            _ => None,
+        };
+    }
+
+    /// Draws the selected source over `output`, inside `viewport`
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        viewport: ViewportRect,
+    ) {
+        let source = match self.selected {
+            Some(i) => &self.sources[i],
+            None => return,
+        };
+        crate::cpu_scope!("DebugInset::render");
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("DebugInset::render"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_viewport_rect(inset_rect(viewport, self.corner, self.size, source.size));
+        pass.set_pipeline(if source.is_depth {
+            &self.depth_pipeline
+        } else {
+            &self.color_pipeline
+        });
+        pass.set_bind_group(0, &source.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+/// Where a texture of `texture_size` is drawn, keeping its aspect ratio
+fn inset_rect(
+    viewport: ViewportRect,
+    corner: Corner,
+    size: f32,
+    texture_size: wgpu::Extent3d,
+) -> ViewportRect {
+    let aspect = texture_size.width as f32 / texture_size.height.max(1) as f32;
+    viewport.inset(corner, size, aspect, 10.0)
+}
+
+fn create_layout(
+    device: &wgpu::Device,
+    label: &str,
+    sample_type: wgpu::TextureSampleType,
+) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some(label),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    entry_point: &str,
+    output_format: wgpu::TextureFormat,
+) -> Result<wgpu::RenderPipeline> {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("DebugInset::pipeline_layout"),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    });
+    let shader = framework_shader("debug_inset.wgsl", include_str!("debug_inset.wgsl"));
+    RenderPipelineBuilder::new()
+        .layout(&pipeline_layout)
+        .vertex_shader(shader.module())
+        .fragment_shader(shader.module())
+        .vertex_entry_point("vs_main")
+        .fragment_entry_point(entry_point)
+        .color_solid(output_format)
+        .build(device)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(width: u32, height: u32) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        }
+    }
+
+    #[test]
+    fn inset_keeps_the_texture_shape_inside_the_viewport() {
+        let surface = ViewportRect::full(1920, 1080);
+        let shadow_map = inset_rect(surface, Corner::BottomRight, 0.3, size(2048, 2048));
+        assert_eq!(shadow_map, ViewportRect::new(1586.0, 746.0, 324.0, 324.0));
+
+        // The right half of a split screen
+        let half = ViewportRect::new(960.0, 0.0, 960.0, 1080.0);
+        let gbuffer = inset_rect(half, Corner::TopLeft, 0.3, size(1920, 1080));
+        assert_eq!(gbuffer, ViewportRect::new(970.0, 10.0, 576.0, 324.0));
+
+        // A texture that's still being created doesn't break the math
+        let empty = inset_rect(surface, Corner::TopRight, 0.3, size(0, 0));
+        assert_eq!(empty, ViewportRect::new(1910.0, 10.0, 0.0, 324.0));
+    }
+}
@@ -0,0 +1,58 @@
+// Modes, see DebugView
+const MODE_COLOR: u32 = 0u;
+const MODE_GRAYSCALE: u32 = 1u;
+const MODE_DEPTH: u32 = 2u;
+const MODE_PERSPECTIVE_DEPTH: u32 = 3u;
+
+struct InsetUniforms {
+    mode: u32,
+    near: f32,
+    far: f32,
+}
+
+#include "framework/fullscreen.wgsl"
+
+@group(0) @binding(0)
+var<uniform> inset: InsetUniforms;
+
+fn show_depth(depth: f32) -> vec4<f32> {
+    var value = depth;
+    if (inset.mode == MODE_PERSPECTIVE_DEPTH) {
+        // Undo the perspective divide so the falloff is even
+        let linear = inset.near * inset.far / (inset.far - depth * (inset.far - inset.near));
+        value = (linear - inset.near) / (inset.far - inset.near);
+    }
+    return vec4<f32>(vec3<f32>(value), 1.0);
+}
+
+@group(0) @binding(1)
+var color_texture: texture_2d<f32>;
+
+@fragment
+fn fs_color(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(color_texture));
+    let coord = vec2<i32>(min(in.uv * size, size - 1.0));
+    let color = textureLoad(color_texture, coord, 0);
+    switch inset.mode {
+        case MODE_GRAYSCALE: {
+            return vec4<f32>(vec3<f32>(color.r), 1.0);
+        }
+        case MODE_DEPTH, MODE_PERSPECTIVE_DEPTH: {
+            // Depth copied into a float texture
+            return show_depth(color.r);
+        }
+        default: {
+            return vec4<f32>(color.rgb, 1.0);
+        }
+    }
+}
+
+@group(0) @binding(1)
+var depth_texture: texture_depth_2d;
+
+@fragment
+fn fs_depth(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(depth_texture));
+    let coord = vec2<i32>(min(in.uv * size, size - 1.0));
+    return show_depth(textureLoad(depth_texture, coord, 0));
+}
@@ -0,0 +1,528 @@
+use anyhow::*;
+use cgmath::*;
+
+use crate::camera::{Camera, Projection};
+use crate::gbuffer_debug::GBufferTargets;
+use crate::light::{LightBuffer, PointLight, SpotLight, LIGHTS_WGSL};
+use crate::model::{ModelVertex, BRDF_WGSL, MODEL_VERTEX_WGSL, PBR_WGSL};
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::ssao::Ssao;
+use crate::texture::Texture;
+
+/// WGSL with `fs_gbuffer`, which writes a [crate::Material] into a
+/// [GBuffer]. Put [PBR_WGSL] and [MODEL_VERTEX_WGSL] in front. Custom
+/// geometry shaders can return `gbuffer_output(surface, n, distance)`
+/// with the world space normal and distance from the camera.
+pub const GBUFFER_WGSL: &str = include_str!("gbuffer.wgsl");
+
+/// The render targets of the deferred path. The geometry pass draws
+/// [crate::Model]s into these with [GBuffer::create_geometry_pipeline] and
+/// [DeferredLighting] turns them into the lit image. How far each pixel is
+/// from the camera is written into the alpha of `normal` and `emissive` as
+/// well as to `depth`, because GL can't read depth textures without a
+/// comparison.
+pub struct GBuffer {
+    /// Base color in rgb
+    pub albedo: Texture<'static>,
+    /// World space normals in rgb, and the distance from the camera
+    /// rounded to a half float in a, or 0 where nothing was drawn
+    pub normal: Texture<'static>,
+    /// Roughness in r, metallic in g and occlusion in b
+    pub material: Texture<'static>,
+    /// Emitted light in rgb, and what the distance in `normal` was
+    /// rounded by in a
+    pub emissive: Texture<'static>,
+    pub depth: Texture<'static>,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl GBuffer {
+    pub const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    pub const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    pub const MATERIAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+    pub const EMISSIVE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = Texture::DEPTH_FORMAT;
+
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GBuffer::layout"),
+            entries: &(0..4)
+                .map(|binding| wgpu::BindGroupLayoutEntry {
+                    binding,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                })
+                .collect::<Vec<_>>(),
+        });
+        let [albedo, normal, material, emissive, depth] = create_targets(device, width, height);
+        let bind_group =
+            create_bind_group(device, &layout, [&albedo, &normal, &material, &emissive]);
+        Self {
+            albedo,
+            normal,
+            material,
+            emissive,
+            depth,
+            layout,
+            bind_group,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let [albedo, normal, material, emissive, depth] = create_targets(device, width, height);
+        self.bind_group = create_bind_group(
+            device,
+            &self.layout,
+            [&albedo, &normal, &material, &emissive],
+        );
+        self.albedo = albedo;
+        self.normal = normal;
+        self.material = material;
+        self.emissive = emissive;
+        self.depth = depth;
+    }
+
+    pub fn width(&self) -> u32 {
+        self.albedo.desc.size.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.albedo.desc.size.height
+    }
+
+    /// The targets a geometry pass writes, in the order of `GBufferOutput`.
+    pub fn color_targets() -> [Option<wgpu::ColorTargetState>; 4] {
+        [
+            Self::NORMAL_FORMAT,
+            Self::EMISSIVE_FORMAT,
+            Self::ALBEDO_FORMAT,
+            Self::MATERIAL_FORMAT,
+        ]
+        .map(|format| {
+            Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+        })
+    }
+
+    /// A pipeline that draws [ModelVertex] meshes with [crate::Material]s
+    /// into the G-buffer. `layout` needs the material layout at group 0 and
+    /// the camera's at group 1, like the tutorials' forward pipelines. The
+    /// shader doesn't read group 2, but [crate::DrawModel] binds a light
+    /// there, so give it the forward light layout as well to draw with
+    /// that. `instance_layout` is the tutorials' instance matrix at
+    /// locations 5 to 8.
+    pub fn create_geometry_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        instance_layout: wgpu::VertexBufferLayout,
+    ) -> Result<wgpu::RenderPipeline> {
+        let source = format!("{}{}{}", PBR_WGSL, MODEL_VERTEX_WGSL, GBUFFER_WGSL);
+        let shader = || wgpu::ShaderModuleDescriptor {
+            label: Some("GBuffer::geometry"),
+            source: wgpu::ShaderSource::Wgsl(source.as_str().into()),
+        };
+        RenderPipelineBuilder::new()
+            .layout(layout)
+            .vertex_shader(shader())
+            .fragment_shader(shader())
+            .vertex_entry_point("vs_model")
+            .fragment_entry_point("fs_gbuffer")
+            .vertex_buffer::<ModelVertex>()
+            .vertex_buffer_desc(instance_layout)
+            .gbuffer()
+            .build(device)
+    }
+
+    /// Starts a geometry pass, clearing every target
+    pub fn begin_geometry_pass<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+    ) -> wgpu::RenderPass<'e> {
+        let clear = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })
+        };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("GBuffer::geometry"),
+            color_attachments: &[
+                clear(&self.normal.view),
+                clear(&self.emissive.view),
+                clear(&self.albedo.view),
+                clear(&self.material.view),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        })
+    }
+
+    /// For showing the targets with [crate::GBufferDebug]
+    pub fn debug_targets(&self, projection: &Projection) -> GBufferTargets<'_> {
+        GBufferTargets {
+            albedo: &self.albedo.view,
+            normal: &self.normal.view,
+            normals_packed: false,
+            material: &self.material.view,
+            depth: &self.depth.view,
+            velocity: None,
+            near: projection.znear(),
+            far: projection.zfar(),
+        }
+    }
+}
+
+fn create_targets(device: &wgpu::Device, width: u32, height: u32) -> [Texture<'static>; 5] {
+    [
+        ("GBuffer::albedo", GBuffer::ALBEDO_FORMAT),
+        ("GBuffer::normal", GBuffer::NORMAL_FORMAT),
+        ("GBuffer::material", GBuffer::MATERIAL_FORMAT),
+        ("GBuffer::emissive", GBuffer::EMISSIVE_FORMAT),
+        ("GBuffer::depth", GBuffer::DEPTH_FORMAT),
+    ]
+    .map(|(label, format)| {
+        Texture::from_descriptor(
+            device,
+            wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        )
+    })
+}
+
+/// Binds everything but depth for [DeferredLighting]
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    targets: [&Texture; 4],
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("GBuffer::bind_group"),
+        layout,
+        entries: &(0..)
+            .zip(targets.iter())
+            .map(|(binding, target)| wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(&target.view),
+            })
+            .collect::<Vec<_>>(),
+    })
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DeferredUniforms {
+    inv_view_proj: [[f32; 4]; 4],
+    camera_position: [f32; 4],
+    ambient: [f32; 4],
+}
+
+impl DeferredUniforms {
+    fn new(
+        camera: &Camera,
+        projection: &Projection,
+        ambient: [f32; 3],
+        has_occlusion: bool,
+    ) -> Self {
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
+        Self {
+            inv_view_proj: view_proj.invert().unwrap_or_else(Matrix4::identity).into(),
+            camera_position: camera.position.to_homogeneous().into(),
+            ambient: [
+                ambient[0],
+                ambient[1],
+                ambient[2],
+                has_occlusion as u32 as f32,
+            ],
+        }
+    }
+}
+
+/// The lighting half of the deferred path. It shades every pixel of a
+/// [GBuffer] once for each light with the same model as [PBR_WGSL], so
+/// demos can switch between forward and deferred and get the same image.
+/// The lights go through a [LightBuffer], so it needs
+/// [Capabilities::fragment_storage_buffers](crate::Capabilities).
+///
+/// ```ignore
+/// let gbuffer = GBuffer::new(&display.device, width, height);
+/// // DrawModel binds the material, camera and light at groups 0 to 2
+/// let layout = display.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+///     label: Some("geometry_layout"),
+///     bind_group_layouts: &[&material_layout, &camera_layout, &light_layout],
+///     push_constant_ranges: &[],
+/// });
+/// let geometry = GBuffer::create_geometry_pipeline(&display.device, &layout, InstanceRaw::desc())?;
+/// let mut lighting = DeferredLighting::new(&display.device, &gbuffer, display.config.format)?;
+/// lighting.lights.push(PointLight::new((2.0, 2.0, 2.0), [1.0; 3], 0.0));
+///
+/// // In Demo::render
+/// lighting.update(&display.device, &display.queue, &camera, &projection);
+/// {
+///     let mut pass = gbuffer.begin_geometry_pass(&mut encoder);
+///     pass.set_pipeline(&geometry);
+///     pass.draw_model_instanced(&model, 0..instances, &camera_bind_group, &light_bind_group);
+/// }
+/// lighting.render(&mut encoder, &gbuffer, &view);
+/// ```
+pub struct DeferredLighting {
+    pub lights: Vec<PointLight>,
+    pub spot_lights: Vec<SpotLight>,
+    pub ambient: [f32; 3],
+    /// What pixels with nothing drawn in them are cleared to. `None`
+    /// leaves whatever was in the output, eg. a skybox.
+    pub clear_color: Option<wgpu::Color>,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    /// Bound when there's no occlusion texture, and never read
+    no_occlusion: wgpu::TextureView,
+    has_occlusion: bool,
+    light_buffer: LightBuffer,
+}
+
+impl DeferredLighting {
+    pub fn new(
+        device: &wgpu::Device,
+        gbuffer: &GBuffer,
+        output_format: wgpu::TextureFormat,
+    ) -> Result<Self> {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("DeferredLighting::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                Ssao::layout_entry(1),
+            ],
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("DeferredLighting::uniform_buffer"),
+            size: std::mem::size_of::<DeferredUniforms>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let no_occlusion = device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("DeferredLighting::no_occlusion"),
+                size: wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Ssao::FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&Default::default());
+        let bind_group =
+            create_lighting_bind_group(device, &layout, &uniform_buffer, &no_occlusion);
+        let light_buffer = LightBuffer::new(device, 16);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("DeferredLighting::pipeline_layout"),
+            bind_group_layouts: &[&gbuffer.layout, &layout, &light_buffer.layout],
+            push_constant_ranges: &[],
+        });
+        let source = format!(
+            "{}{}{}",
+            BRDF_WGSL,
+            LIGHTS_WGSL,
+            include_str!("deferred.wgsl")
+        );
+        let shader = framework_shader("DeferredLighting", &source);
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(shader.module())
+            .fragment_shader(shader.module())
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .color_solid(output_format)
+            .build(device)?;
+
+        Ok(Self {
+            lights: Vec::new(),
+            spot_lights: Vec::new(),
+            ambient: [0.03; 3],
+            clear_color: Some(wgpu::Color::BLACK),
+            pipeline,
+            uniform_buffer,
+            layout,
+            bind_group,
+            no_occlusion,
+            has_occlusion: false,
+            light_buffer,
+        })
+    }
+
+    /// Multiplies ambient light by the r of `occlusion`, like
+    /// [Ssao::view], on top of the materials' own occlusion. It's
+    /// stretched over the G-buffer, so it can be a lower resolution.
+    /// Takes effect on the next [DeferredLighting::update], and needs
+    /// setting again when the texture is remade.
+    pub fn set_occlusion(&mut self, device: &wgpu::Device, occlusion: Option<&wgpu::TextureView>) {
+        self.has_occlusion = occlusion.is_some();
+        self.bind_group = create_lighting_bind_group(
+            device,
+            &self.layout,
+            &self.uniform_buffer,
+            occlusion.unwrap_or(&self.no_occlusion),
+        );
+    }
+
+    /// Uploads the camera and lights. Call this when any of them change.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        projection: &Projection,
+    ) {
+        let uniforms = DeferredUniforms::new(camera, projection, self.ambient, self.has_occlusion);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+        self.light_buffer
+            .update(device, queue, &self.lights, &self.spot_lights);
+    }
+
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        gbuffer: &GBuffer,
+        view: &wgpu::TextureView,
+    ) {
+        let load = match self.clear_color {
+            Some(color) => wgpu::LoadOp::Clear(color),
+            None => wgpu::LoadOp::Load,
+        };
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("DeferredLighting"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &gbuffer.bind_group, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.set_bind_group(2, &self.light_buffer.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_lighting_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    occlusion: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("DeferredLighting::bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(occlusion),
+            },
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniforms_undo_the_camera() {
+        let camera = Camera::new((1.0, 2.0, 3.0), Deg(-120.0), Deg(15.0));
+        let projection = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+        let uniforms = DeferredUniforms::new(&camera, &projection, [0.1; 3], true);
+        assert_eq!(uniforms.ambient, [0.1, 0.1, 0.1, 1.0]);
+        let camera_position = Point3::from_homogeneous(uniforms.camera_position.into());
+        assert_eq!(camera_position, camera.position);
+
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
+        let inv_view_proj = Matrix4::from(uniforms.inv_view_proj);
+        let unproject = |ndc: Vector2<f32>, depth| {
+            Point3::from_homogeneous(inv_view_proj * Vector4::new(ndc.x, ndc.y, depth, 1.0))
+        };
+        let world = camera.position + camera.forward() * 20.0 + camera.up() * 3.0;
+        let clip = view_proj * world.to_homogeneous();
+        let ndc = clip.truncate().truncate() / clip.w;
+        // deferred.wgsl finds the pixel's points on the line through these
+        let (a, b) = (unproject(ndc, 0.0), unproject(ndc, 0.5));
+        let along = (b - a).normalize();
+        let off_line = (world - a) - along * (world - a).dot(along);
+        assert!(off_line.magnitude() < 1e-3, "{:?}", off_line);
+    }
+
+    #[test]
+    fn deferred_wgsl_validates() {
+        crate::shader::validate_wgsl(&format!(
+            "{}{}{}",
+            PBR_WGSL, MODEL_VERTEX_WGSL, GBUFFER_WGSL
+        ))
+        .unwrap();
+        let source = format!(
+            "{}{}{}",
+            BRDF_WGSL,
+            LIGHTS_WGSL,
+            include_str!("deferred.wgsl")
+        );
+        crate::shader::validate_wgsl(&framework_shader("deferred.wgsl", &source).source).unwrap();
+        assert_eq!(std::mem::size_of::<DeferredUniforms>(), 96);
+    }
+}
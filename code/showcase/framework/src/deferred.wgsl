@@ -0,0 +1,86 @@
+// Lights a GBuffer with a full screen triangle. Needs BRDF_WGSL and
+// LIGHTS_WGSL in front.
+
+struct DeferredUniforms {
+    inv_view_proj: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    // w is 1 when occlusion_texture is set
+    ambient: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var albedo_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var normal_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var material_texture: texture_2d<f32>;
+@group(0) @binding(3)
+var emissive_texture: texture_2d<f32>;
+
+@group(1) @binding(0)
+var<uniform> deferred: DeferredUniforms;
+@group(1) @binding(1)
+var occlusion_texture: texture_2d<f32>;
+
+#include "framework/fullscreen.wgsl"
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let point = deferred.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return point.xyz / point.w;
+}
+
+// The point `distance` from the camera that was drawn at `ndc`. Every
+// point drawn there is on one line, but OPENGL_TO_WGPU_MATRIX adds depth
+// into w, so the line misses the camera away from the middle of the
+// screen and walking `distance` straight out from the camera would land
+// off it.
+fn world_position_at(ndc: vec2<f32>, distance: f32) -> vec3<f32> {
+    let camera_position = deferred.camera_position.xyz;
+    let a = unproject(ndc, 0.0);
+    var direction = normalize(unproject(ndc, 0.5) - a);
+    let offset = a - camera_position;
+    if (dot(direction, offset) < 0.0) {
+        direction = -direction;
+    }
+    // Solve |offset + t * direction| = distance for the far side
+    let b = dot(direction, offset);
+    let c = dot(offset, offset) - distance * distance;
+    let t = -b + sqrt(max(b * b - c, 0.0));
+    return a + direction * t;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(in.clip_position.xy);
+    let normal = textureLoad(normal_texture, coord, 0);
+    let emissive = textureLoad(emissive_texture, coord, 0);
+    let distance = normal.a + emissive.a;
+    // Nothing was drawn here
+    if (distance <= 0.0) {
+        discard;
+    }
+
+    let ndc = in.uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    let world_position = world_position_at(ndc, distance);
+    let ray = normalize(world_position - deferred.camera_position.xyz);
+
+    let material = textureLoad(material_texture, coord, 0);
+    var surface: Surface;
+    surface.albedo = textureLoad(albedo_texture, coord, 0);
+    surface.roughness = material.r;
+    surface.metallic = material.g;
+    surface.occlusion = material.b;
+    if (deferred.ambient.w > 0.0) {
+        // Stretched over the G-buffer, which can be a different size
+        let scale = vec2<f32>(textureDimensions(occlusion_texture)) / vec2<f32>(textureDimensions(albedo_texture));
+        let occlusion_coord = vec2<i32>(vec2<f32>(coord) * scale);
+        surface.occlusion *= textureLoad(occlusion_texture, occlusion_coord, 0).r;
+    }
+    surface.emissive = emissive.rgb;
+    let n = normalize(normal.xyz);
+    let v = -ray;
+
+    let color = pbr_ambient(surface, deferred.ambient.rgb)
+        + pbr_lights(surface, n, v, world_position);
+    return vec4<f32>(color, 1.0);
+}
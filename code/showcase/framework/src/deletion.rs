@@ -0,0 +1,108 @@
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+type Batch = Vec<Box<dyn Any>>;
+
+/// Holds on to GPU resources until the work submitted before they were
+/// dropped has finished. Replacing a buffer or texture halfway through a
+/// frame and dropping the old one straight away can trip validation on
+/// some backends, so hand the old one to [DeletionQueue::defer] instead.
+///
+/// The framework owns one in [crate::Display] and calls
+/// [DeletionQueue::end_frame] after every frame.
+#[derive(Default)]
+pub struct DeletionQueue {
+    current: Batch,
+    /// Batches waiting on the queue. The flag is set once everything that
+    /// was submitted before the batch was sealed has completed.
+    pending: Vec<(Arc<AtomicBool>, Batch)>,
+}
+
+impl DeletionQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops `resource` once the GPU is done with the current frame
+    pub fn defer<T: 'static>(&mut self, resource: T) {
+        self.current.push(Box::new(resource));
+    }
+
+    /// How many resources are waiting to be dropped
+    pub fn len(&self) -> usize {
+        self.current.len() + self.pending.iter().map(|(_, b)| b.len()).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Ties this frame's resources to the submissions made so far and
+    /// drops any earlier batches the GPU has finished with. Call this
+    /// after the frame's commands have been submitted.
+    pub fn end_frame(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if let Some(done) = self.seal() {
+            queue.on_submitted_work_done(move || done.store(true, Ordering::Release));
+        }
+        // Callbacks only run while the device is being polled
+        device.poll(wgpu::Maintain::Poll);
+        self.collect();
+    }
+
+    /// Waits for the GPU to go idle and drops everything. Useful before
+    /// tearing down or rebuilding everything at once.
+    pub fn flush(&mut self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Wait);
+        self.current.clear();
+        self.pending.clear();
+    }
+
+    fn seal(&mut self) -> Option<Arc<AtomicBool>> {
+        if self.current.is_empty() {
+            return None;
+        }
+        let done = Arc::new(AtomicBool::new(false));
+        self.pending
+            .push((done.clone(), std::mem::take(&mut self.current)));
+        Some(done)
+    }
+
+    fn collect(&mut self) {
+        self.pending
+            .retain(|(done, _)| !done.load(Ordering::Acquire));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn batches_drop_when_their_work_is_done() {
+        let mut queue = DeletionQueue::new();
+        let first = Rc::new(());
+        let second = Rc::new(());
+
+        queue.defer(first.clone());
+        let first_done = queue.seal().unwrap();
+        queue.defer(second.clone());
+        let second_done = queue.seal().unwrap();
+        assert!(queue.seal().is_none());
+        assert_eq!(queue.len(), 2);
+
+        queue.collect();
+        assert_eq!(Rc::strong_count(&first), 2);
+
+        second_done.store(true, Ordering::Release);
+        queue.collect();
+        assert_eq!(Rc::strong_count(&second), 1);
+        assert_eq!(Rc::strong_count(&first), 2);
+
+        first_done.store(true, Ordering::Release);
+        queue.collect();
+        assert!(queue.is_empty());
+        assert_eq!(Rc::strong_count(&first), 1);
+    }
+}
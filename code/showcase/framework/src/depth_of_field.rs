@@ -0,0 +1,346 @@
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::camera::Projection;
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::post::{fullscreen_pass, PostContext, PostEffect, PostProcessChain};
+use crate::texture::Texture;
+
+/// Changed at runtime with [DepthOfField::set_settings], which is cheap
+/// enough to call from [crate::Demo::update] for focus pulls
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DepthOfFieldSettings {
+    /// How far from the camera things are sharpest
+    pub focus_distance: f32,
+    /// How wide in pixels the blur gets on things infinitely far away.
+    /// Things closer than the focus blur more, a wider lens does the
+    /// same. 0 turns the effect off.
+    pub aperture: f32,
+    /// The widest the blur gets in pixels. Past about 16 the samples
+    /// start to show.
+    pub max_blur: f32,
+}
+
+impl Default for DepthOfFieldSettings {
+    fn default() -> Self {
+        Self {
+            focus_distance: 5.0,
+            aperture: 6.0,
+            max_blur: 12.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DepthOfFieldUniform {
+    focus_distance: f32,
+    aperture: f32,
+    max_blur: f32,
+    near: f32,
+    far: f32,
+    _padding: [f32; 3],
+}
+
+impl DepthOfFieldUniform {
+    fn new(settings: DepthOfFieldSettings, near: f32, far: f32) -> Self {
+        Self {
+            focus_distance: settings.focus_distance.max(0.0),
+            aperture: settings.aperture.max(0.0),
+            max_blur: settings.max_blur.max(0.0),
+            near,
+            far,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// Blurs the scene by how far each pixel is from the focus distance,
+/// like a camera lens. It goes before [crate::Tonemap] in a
+/// [PostProcessChain], and reads the depth buffer passed to
+/// [PostProcessChain::process], so the display needs a sample count of 1.
+/// Without a depth buffer it does nothing.
+///
+/// ```ignore
+/// let dof = DepthOfField::new(&display.device, &post, &projection, Default::default())?;
+/// post.add(dof);
+///
+/// // In Demo::update
+/// let dof = post.get_mut::<DepthOfField>().unwrap();
+/// let mut settings = dof.settings();
+/// settings.focus_distance = (target - camera.position).magnitude();
+/// dof.set_settings(&display.queue, settings);
+/// ```
+pub struct DepthOfField {
+    settings: DepthOfFieldSettings,
+    near: f32,
+    far: f32,
+    uniform_buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    prepare_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
+    targets: DepthOfFieldTargets,
+}
+
+/// The half resolution copy of the scene and its blur
+struct DepthOfFieldTargets {
+    half: Texture<'static>,
+    blurred: Texture<'static>,
+}
+
+impl DepthOfField {
+    /// `projection` is what the scene's depth buffer is drawn with
+    pub fn new(
+        device: &wgpu::Device,
+        chain: &PostProcessChain,
+        projection: &Projection,
+        settings: DepthOfFieldSettings,
+    ) -> Result<Self> {
+        let (near, far) = (projection.znear(), projection.zfar());
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("DepthOfField::uniform_buffer"),
+            contents: bytemuck::bytes_of(&DepthOfFieldUniform::new(settings, near, far)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("DepthOfField::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("DepthOfField::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("DepthOfField::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point: &str, blend: Option<wgpu::BlendState>| {
+            let shader =
+                framework_shader("depth_of_field.wgsl", include_str!("depth_of_field.wgsl"));
+            RenderPipelineBuilder::new()
+                .layout(&pipeline_layout)
+                .vertex_shader(shader.module())
+                .fragment_shader(shader.module())
+                .vertex_entry_point("vs_main")
+                .fragment_entry_point(entry_point)
+                .color_state(wgpu::ColorTargetState {
+                    format: chain.format(),
+                    blend,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })
+                .build(device)
+        };
+        let prepare_pipeline = pipeline("fs_prepare", None)?;
+        let blur_pipeline = pipeline("fs_blur", None)?;
+        // Keeps the scene's alpha
+        let over = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        };
+        let composite_pipeline = pipeline("fs_composite", Some(over))?;
+
+        let targets =
+            DepthOfFieldTargets::new(device, chain.format(), chain.width(), chain.height());
+        Ok(Self {
+            settings,
+            near,
+            far,
+            uniform_buffer,
+            layout,
+            sampler,
+            prepare_pipeline,
+            blur_pipeline,
+            composite_pipeline,
+            format: chain.format(),
+            targets,
+        })
+    }
+
+    pub fn settings(&self) -> DepthOfFieldSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: DepthOfFieldSettings) {
+        self.settings = settings;
+        self.write_uniform(queue);
+    }
+
+    /// Needs calling when the projection's near or far plane changes
+    pub fn set_projection(&mut self, queue: &wgpu::Queue, projection: &Projection) {
+        self.near = projection.znear();
+        self.far = projection.zfar();
+        self.write_uniform(queue);
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        let uniform = DepthOfFieldUniform::new(self.settings, self.near, self.far);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+}
+
+impl PostEffect for DepthOfField {
+    fn enabled(&self) -> bool {
+        self.settings.aperture > 0.0 && self.settings.max_blur > 0.0
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.targets = DepthOfFieldTargets::new(device, self.format, width, height);
+    }
+
+    /// Copies the input over and blends the blur onto the out of focus
+    /// parts
+    fn process(&mut self, encoder: &mut wgpu::CommandEncoder, ctx: &PostContext) {
+        crate::cpu_scope!("DepthOfField::process");
+        let depth = match ctx.depth {
+            Some(depth) => depth,
+            None => return ctx.copy(encoder),
+        };
+        let bind_group = |view: &wgpu::TextureView| {
+            ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("DepthOfField::bind_group"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(depth),
+                    },
+                ],
+            })
+        };
+        let targets = &self.targets;
+        fullscreen_pass(
+            encoder,
+            "DepthOfField::prepare",
+            &targets.half.view,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            &self.prepare_pipeline,
+            &bind_group(ctx.input),
+        );
+        fullscreen_pass(
+            encoder,
+            "DepthOfField::blur",
+            &targets.blurred.view,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            &self.blur_pipeline,
+            &bind_group(&targets.half.view),
+        );
+        ctx.copy(encoder);
+        fullscreen_pass(
+            encoder,
+            "DepthOfField::composite",
+            ctx.output,
+            wgpu::LoadOp::Load,
+            &self.composite_pipeline,
+            &bind_group(&targets.blurred.view),
+        );
+    }
+}
+
+impl DepthOfFieldTargets {
+    fn new(device: &wgpu::Device, format: wgpu::TextureFormat, width: u32, height: u32) -> Self {
+        let target = |label| {
+            Texture::from_descriptor(
+                device,
+                wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: (width / 2).max(1),
+                        height: (height / 2).max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                },
+            )
+        };
+        Self {
+            half: target("DepthOfField::half"),
+            blurred: target("DepthOfField::blurred"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_of_field_wgsl_validates() {
+        assert_eq!(std::mem::size_of::<DepthOfFieldUniform>(), 32);
+        crate::shader::validate_wgsl(
+            &framework_shader("depth_of_field.wgsl", include_str!("depth_of_field.wgsl")).source,
+        )
+        .unwrap();
+    }
+}
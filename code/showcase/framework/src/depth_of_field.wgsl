@@ -0,0 +1,76 @@
+// Depth of field for a PostProcessChain. The scene is copied to half
+// resolution with each pixel's circle of confusion in alpha, blurred with
+// a disc that size, and blended back over the scene where it's out of
+// focus.
+
+struct DepthOfFieldUniform {
+    focus_distance: f32,
+    // The blur in pixels of something infinitely far away
+    aperture: f32,
+    max_blur: f32,
+    near: f32,
+    far: f32,
+    _padding0: f32,
+    _padding1: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> dof: DepthOfFieldUniform;
+@group(0) @binding(3)
+var depth_texture: texture_depth_2d;
+
+const DOF_SAMPLES: u32 = 32u;
+const GOLDEN_ANGLE: f32 = 2.3999632;
+
+#include "framework/fullscreen.wgsl"
+
+// How wide the blur is in full resolution pixels where the depth buffer
+// is at `uv`
+fn circle_of_confusion(uv: vec2<f32>) -> f32 {
+    let size = vec2<f32>(textureDimensions(depth_texture));
+    let coord = clamp(vec2<i32>(uv * size), vec2<i32>(0), vec2<i32>(size) - 1);
+    let depth = textureLoad(depth_texture, coord, 0);
+    let distance = dof.near * dof.far / (dof.far - depth * (dof.far - dof.near));
+    return min(dof.aperture * abs(distance - dof.focus_distance) / distance, dof.max_blur);
+}
+
+@fragment
+fn fs_prepare(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv).rgb;
+    return vec4<f32>(color, circle_of_confusion(in.uv));
+}
+
+// Gathers a disc of samples as wide as this pixel's blur. Samples only
+// count if their own blur reaches this far, so sharp things don't smear
+// onto blurry ones.
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    let center = textureSample(source, source_sampler, in.uv);
+    let radius = center.a;
+    // The source is half resolution
+    let texel = 0.5 / vec2<f32>(textureDimensions(source));
+    var color = center.rgb;
+    var weights = 1.0;
+    for (var i = 0u; i < DOF_SAMPLES; i++) {
+        let r = sqrt((f32(i) + 0.5) / f32(DOF_SAMPLES)) * radius;
+        let theta = f32(i) * GOLDEN_ANGLE;
+        let offset = vec2<f32>(cos(theta), sin(theta)) * r * texel;
+        let s = textureSampleLevel(source, source_sampler, in.uv + offset, 0.0);
+        let weight = clamp(s.a - r + 1.0, 0.0, 1.0);
+        color += s.rgb * weight;
+        weights += weight;
+    }
+    return vec4<f32>(color / weights, radius);
+}
+
+// Blended over the scene by how out of focus each pixel is
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let blurred = textureSample(source, source_sampler, in.uv).rgb;
+    let amount = smoothstep(0.5, 2.0, circle_of_confusion(in.uv));
+    return vec4<f32>(blurred, amount);
+}
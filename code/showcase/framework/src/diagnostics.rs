@@ -0,0 +1,171 @@
+//! Bug report dumps. [dump] collects everything about the device that
+//! usually gets asked for first when something renders wrong on someone
+//! else's machine. Pressing [Display::diagnostics_key] saves it, along
+//! with the frame that was on screen.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use serde_json::json;
+
+use crate::capture::TextureReadback;
+use crate::Display;
+
+/// The adapter, its limits and features, what the framework made of them,
+/// and the surface setup, as JSON
+pub fn dump(display: &Display) -> serde_json::Value {
+    let adapter = display.adapter();
+    let surface_caps = display.surface().get_capabilities(adapter);
+    json!({
+        "framework": framework_info(),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "adapter": adapter.get_info(),
+        "downlevel": adapter.get_downlevel_capabilities(),
+        "adapter_features": adapter.features(),
+        "device_features": display.device.features(),
+        "limits": display.device.limits(),
+        "capabilities": display.capabilities,
+        "surface": {
+            "width": display.config.width,
+            "height": display.config.height,
+            "format": display.config.format,
+            "present_mode": display.config.present_mode,
+            "alpha_mode": display.config.alpha_mode,
+            "supported_formats": surface_caps.formats,
+            "supported_present_modes": surface_caps.present_modes,
+            "supported_alpha_modes": surface_caps.alpha_modes,
+        },
+    })
+}
+
+/// Writes [dump] to `bug-report-<unix time>.json` in the working
+/// directory. If there's a `frame` it's saved next to it as
+/// `bug-report-<unix time>.png`, which the JSON names under
+/// `"screenshot"`. The frame needs `COPY_SRC`, see
+/// [Display::enable_readback]. If it can't be read the report is saved
+/// without it.
+pub fn save(display: &Display, frame: Option<&wgpu::Texture>) -> Result<PathBuf> {
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let path = PathBuf::from(format!("bug-report-{}.json", time));
+    let screenshot = frame.and_then(|frame| {
+        let path = path.with_extension("png");
+        match save_frame(display, frame, &path) {
+            Result::Ok(()) => Some(path),
+            Err(e) => {
+                log::warn!("Bug report saved without a screenshot: {:?}", e);
+                None
+            }
+        }
+    });
+
+    let mut report = dump(display);
+    set_screenshot(&mut report, screenshot.as_deref());
+    let file = std::fs::File::create(&path)
+        .with_context(|| format!("Unable to create {}", path.display()))?;
+    serde_json::to_writer_pretty(std::io::BufWriter::new(file), &report)?;
+    Ok(path)
+}
+
+/// Names the screenshot relative to the report, since they're saved side
+/// by side. `null` if there isn't one.
+fn set_screenshot(report: &mut serde_json::Value, screenshot: Option<&Path>) {
+    report["screenshot"] = json!(screenshot
+        .and_then(|path| path.file_name())
+        .map(|name| name.to_string_lossy()));
+}
+
+/// Reads `frame` back and writes it to `path` as an opaque PNG
+fn save_frame(display: &Display, frame: &wgpu::Texture, path: &Path) -> Result<()> {
+    if !frame.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+        bail!("The frame can't be copied, see Display::enable_readback");
+    }
+    let (width, height) = (frame.width(), frame.height());
+    let readback = TextureReadback::new(&display.device, width, height);
+    let mut encoder = display
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("diagnostics::save_frame"),
+        });
+    readback.copy(&mut encoder, frame);
+    display.queue.submit(std::iter::once(encoder.finish()));
+    let pixels = readback.read(&display.device, frame.format())?;
+    let image = image::RgbaImage::from_raw(width, height, pixels)
+        .context("Frame pixels don't match its size")?;
+    image::DynamicImage::ImageRgba8(image)
+        .to_rgb8()
+        .save(path)
+        .with_context(|| format!("Unable to save {}", path.display()))
+}
+
+fn framework_info() -> serde_json::Value {
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "features": enabled_features(),
+    })
+}
+
+/// Every optional feature in Cargo.toml, and whether it's on
+const FEATURES: [(&str, bool); 12] = [
+    ("gui", cfg!(feature = "gui")),
+    ("audio", cfg!(feature = "audio")),
+    ("audio-device", cfg!(feature = "audio-device")),
+    ("midi", cfg!(feature = "midi")),
+    ("gamepad", cfg!(feature = "gamepad")),
+    ("renderdoc", cfg!(feature = "renderdoc")),
+    ("puffin", cfg!(feature = "puffin")),
+    ("gltf", cfg!(feature = "gltf")),
+    ("archives", cfg!(feature = "archives")),
+    ("glsl", cfg!(feature = "glsl")),
+    ("spirv", cfg!(feature = "spirv")),
+    ("wasm-threads", cfg!(feature = "wasm-threads")),
+];
+
+fn enabled_features() -> Vec<&'static str> {
+    FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_cargo_feature_is_reported() {
+        let manifest = include_str!("../Cargo.toml");
+        let section = manifest.split("[features]").nth(1).unwrap();
+        let section = section.split("\n[").next().unwrap();
+        let declared = section
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split(" = ").next())
+            .filter(|name| !name.trim().is_empty())
+            .collect::<Vec<_>>();
+        let listed = FEATURES.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        assert_eq!(declared, listed);
+
+        let info = framework_info();
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["features"], json!(enabled_features()));
+        let gltf = json!("gltf");
+        let features = info["features"].as_array().unwrap();
+        assert_eq!(features.contains(&gltf), cfg!(feature = "gltf"));
+    }
+
+    #[test]
+    fn screenshot_is_named_next_to_the_report() {
+        let mut report = json!({ "os": "linux" });
+        set_screenshot(&mut report, Some(Path::new("reports/bug-report-5.png")));
+        assert_eq!(report["screenshot"], "bug-report-5.png");
+        assert_eq!(report["os"], "linux");
+
+        set_screenshot(&mut report, None);
+        assert!(report["screenshot"].is_null());
+    }
+}
@@ -0,0 +1,399 @@
+use anyhow::*;
+use cgmath::*;
+use wgpu::util::DeviceExt;
+
+use crate::model::{build_vertices, LoadOptions, ModelVertex};
+use crate::pipeline::{dispatch, storage_buffer_entry, ComputePipelineBuilder};
+
+const DISPLACEMENT_WGSL: &str = include_str!("displacement.wgsl");
+/// The most pieces an edge is cut into. A patch at this level writes
+/// 3 * 64 * 64 vertices.
+pub const MAX_DISPLACEMENT_LEVEL: u32 = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplacementSettings {
+    /// How far a height of 1 moves the surface along its normal
+    pub scale: f32,
+    /// The height that leaves the surface where it is
+    pub bias: f32,
+    /// Pieces an edge 1 long is cut into when 1 away from the camera.
+    /// Edges twice as far away get half as many.
+    pub detail: f32,
+    /// Up to [MAX_DISPLACEMENT_LEVEL]
+    pub max_level: u32,
+    /// The most vertices written in a frame. Detail is lowered everywhere
+    /// to stay under it.
+    pub max_vertices: u32,
+}
+
+impl Default for DisplacementSettings {
+    fn default() -> Self {
+        Self {
+            scale: 0.1,
+            bias: 0.5,
+            detail: 8.0,
+            max_level: 32,
+            max_vertices: 1 << 20,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DisplacementUniforms {
+    scale: f32,
+    bias: f32,
+    num_patches: u32,
+    _padding: u32,
+}
+
+/// Where a patch's vertices go and how finely each of its edges is cut,
+/// in the order AB, AC then BC
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+struct Patch {
+    first_vertex: u32,
+    edges: [u32; 3],
+}
+
+impl Patch {
+    fn num_vertices(&self) -> u32 {
+        let level = self.edges.iter().copied().max().unwrap_or(1);
+        3 * level * level
+    }
+}
+
+/// How many pieces to cut an edge into. Only depends on the edge itself,
+/// so the triangles either side always agree.
+fn edge_level(a: Point3<f32>, b: Point3<f32>, camera: Point3<f32>, detail: f32, max: u32) -> u32 {
+    let distance = a.midpoint(b).distance(camera).max(1e-3);
+    let pieces = (a.distance(b) * detail / distance).ceil();
+    (pieces as u32).clamp(1, max)
+}
+
+/// Picks levels for every triangle, lowering the detail until they fit
+/// in `max_vertices`. Returns the patches and how many vertices they
+/// write.
+fn plan_patches(
+    positions: &[Point3<f32>],
+    indices: &[u32],
+    camera: Point3<f32>,
+    settings: &DisplacementSettings,
+) -> (Vec<Patch>, u32) {
+    let max_level = settings.max_level.clamp(1, MAX_DISPLACEMENT_LEVEL);
+    let mut detail = settings.detail;
+    loop {
+        let mut patches = Vec::with_capacity(indices.len() / 3);
+        let mut total = 0u32;
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| positions[triangle[i] as usize]);
+            let level = |p, q| edge_level(p, q, camera, detail, max_level);
+            let patch = Patch {
+                first_vertex: total,
+                edges: [level(a, b), level(a, c), level(b, c)],
+            };
+            total = total.saturating_add(patch.num_vertices());
+            patches.push(patch);
+        }
+        // Level 1 everywhere always fits, as the buffer is made big enough
+        if total <= settings.max_vertices || detail <= 0.0 {
+            return (patches, total);
+        }
+        let shrink = (settings.max_vertices as f32 / total as f32).sqrt();
+        detail = if shrink > 0.01 {
+            detail * shrink.min(0.9)
+        } else {
+            0.0
+        };
+    }
+}
+
+/// A mesh cut into smaller triangles near the camera and pushed out by a
+/// heightmap, as wgpu has no tessellation shaders. Each triangle of the
+/// base mesh is a patch. The CPU picks how finely to cut each edge from
+/// its distance to the camera, and a compute shader writes the result as
+/// plain [ModelVertex] triangles, so it draws with any pipeline meshes
+/// do.
+///
+/// Edges on the boundary between patches with different levels snap to
+/// the coarser steps, so there are no cracks as long as the base mesh
+/// shares its vertices.
+///
+/// ```ignore
+/// let height = Texture::load(&device, &queue, "res/rock_height.png", true)?;
+/// let mut displaced = DisplacedMesh::new(
+///     &device,
+///     (&mesh.positions, &mesh.texcoords, &mesh.normals),
+///     &mesh.indices,
+///     &height,
+///     DisplacementSettings::default(),
+/// )?;
+/// // Every frame
+/// displaced.update(&queue, &mut encoder, camera.position);
+/// // With the model pipeline and its material, camera and lights set
+/// displaced.draw(&mut pass);
+/// ```
+pub struct DisplacedMesh {
+    settings: DisplacementSettings,
+    positions: Vec<Point3<f32>>,
+    indices: Vec<u32>,
+    pipeline: wgpu::ComputePipeline,
+    uniforms: wgpu::Buffer,
+    patches: wgpu::Buffer,
+    vertices: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    num_vertices: u32,
+}
+
+impl DisplacedMesh {
+    /// The base mesh is given as flat positions, texture coordinates and
+    /// normals, like [crate::Model::load_obj] reads them, and has to be
+    /// free of the problems the loaders would repair. `heightmap` is read
+    /// from its red channel, and should be linear rather than sRGB.
+    pub fn new(
+        device: &wgpu::Device,
+        (positions, tex_coords, normals): (&[f32], &[f32], &[f32]),
+        indices: &[u32],
+        heightmap: &crate::texture::Texture,
+        settings: DisplacementSettings,
+    ) -> Result<Self> {
+        let (vertices, indices) = build_vertices(
+            positions,
+            tex_coords,
+            normals,
+            indices,
+            &LoadOptions { strict: true },
+        )
+        .context("Bad DisplacedMesh base mesh")?;
+        ensure!(!indices.is_empty(), "DisplacedMesh needs triangles");
+        let compute = wgpu::ShaderStages::COMPUTE;
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("DisplacedMesh::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: compute,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_buffer_entry(1, compute, true),
+                storage_buffer_entry(2, compute, true),
+                storage_buffer_entry(3, compute, true),
+                storage_buffer_entry(4, compute, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: compute,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: compute,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("DisplacedMesh::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = ComputePipelineBuilder::new()
+            .label("DisplacedMesh::displace")
+            .layout(&pipeline_layout)
+            .shader(wgpu::ShaderModuleDescriptor {
+                label: Some("DisplacedMesh::displace"),
+                source: wgpu::ShaderSource::Wgsl(DISPLACEMENT_WGSL.into()),
+            })
+            .entry_point("displace")
+            .build(device)?;
+
+        let num_patches = (indices.len() / 3) as u32;
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("DisplacedMesh::uniforms"),
+            size: std::mem::size_of::<DisplacementUniforms>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let base_vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("DisplacedMesh::base_vertices"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let base_indices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("DisplacedMesh::base_indices"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let patches = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("DisplacedMesh::patches"),
+            size: num_patches as u64 * std::mem::size_of::<Patch>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let capacity = settings.max_vertices.max(3 * num_patches);
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("DisplacedMesh::vertices"),
+            size: capacity as u64 * std::mem::size_of::<ModelVertex>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("DisplacedMesh::bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: base_vertices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: base_indices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: patches.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: output.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&heightmap.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&heightmap.sampler),
+                },
+            ],
+        });
+
+        Ok(Self {
+            settings: DisplacementSettings {
+                max_vertices: capacity,
+                ..settings
+            },
+            positions: positions
+                .chunks_exact(3)
+                .map(|p| Point3::new(p[0], p[1], p[2]))
+                .collect(),
+            indices,
+            pipeline,
+            uniforms,
+            patches,
+            vertices: output,
+            bind_group,
+            num_vertices: 0,
+        })
+    }
+
+    pub fn settings(&self) -> &DisplacementSettings {
+        &self.settings
+    }
+
+    /// Scale and bias apply on the next [DisplacedMesh::update]
+    pub fn set_height(&mut self, scale: f32, bias: f32) {
+        self.settings.scale = scale;
+        self.settings.bias = bias;
+    }
+
+    pub fn set_detail(&mut self, detail: f32) {
+        self.settings.detail = detail;
+    }
+
+    /// Cuts and displaces the mesh again for a camera at `camera`
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        camera: Point3<f32>,
+    ) {
+        crate::cpu_scope!("DisplacedMesh::update");
+        let (patches, num_vertices) =
+            plan_patches(&self.positions, &self.indices, camera, &self.settings);
+        self.num_vertices = num_vertices;
+        let uniforms = DisplacementUniforms {
+            scale: self.settings.scale,
+            bias: self.settings.bias,
+            num_patches: patches.len() as u32,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.uniforms, 0, bytemuck::cast_slice(&[uniforms]));
+        queue.write_buffer(&self.patches, 0, bytemuck::cast_slice(&patches));
+        dispatch(
+            encoder,
+            &self.pipeline,
+            &[&self.bind_group],
+            [patches.len() as u32, 1, 1],
+        );
+    }
+
+    /// The triangles written by the last update, as [ModelVertex]
+    pub fn vertex_buffer(&self) -> &wgpu::Buffer {
+        &self.vertices
+    }
+
+    pub fn num_vertices(&self) -> u32 {
+        self.num_vertices
+    }
+
+    /// Draws the triangles without an index buffer. Everything but the
+    /// vertex buffer needs to be set already.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_vertex_buffer(0, self.vertices.slice(..));
+        pass.draw(0..self.num_vertices, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patches_refine_near_the_camera_and_fit_the_budget() {
+        // The same triangle near the camera and far away
+        let positions = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, 1.0),
+            Point3::new(0.0, 0.0, 10.0),
+            Point3::new(1.0, 0.0, 10.0),
+            Point3::new(0.0, 0.0, 11.0),
+        ];
+        let indices = [0, 2, 1, 3, 5, 4];
+        let settings = DisplacementSettings::default();
+        let camera = Point3::new(0.5, 0.5, -0.5);
+        let (patches, total) = plan_patches(&positions, &indices, camera, &settings);
+        assert!(patches[0].num_vertices() > patches[1].num_vertices());
+        assert_eq!(patches[1].first_vertex, patches[0].num_vertices());
+        assert_eq!(total, patches[0].num_vertices() + patches[1].num_vertices());
+        // Edges are cut the same from either side
+        assert_eq!(
+            edge_level(positions[2], positions[1], camera, 8.0, 64),
+            edge_level(positions[1], positions[2], camera, 8.0, 64)
+        );
+
+        let tight = DisplacementSettings {
+            max_vertices: 100,
+            ..settings
+        };
+        let (_, total) = plan_patches(&positions, &indices, camera, &tight);
+        assert!(total <= 100, "{}", total);
+
+        crate::shader::validate_wgsl(DISPLACEMENT_WGSL).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+// Splits each triangle of a mesh into a grid of smaller ones and moves
+// them along the normal by a heightmap, standing in for tessellation
+// shaders. One workgroup does one patch.
+
+struct DisplacementUniforms {
+    scale: f32,
+    // The height that stays where the mesh was
+    bias: f32,
+    num_patches: u32,
+    _padding: u32,
+}
+
+// Where the patch's vertices start, and how many pieces its edges are cut
+// into. The interior uses the most of the three.
+struct Patch {
+    first_vertex: u32,
+    ab: u32,
+    ac: u32,
+    bc: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: DisplacementUniforms;
+// ModelVertex is 14 floats
+@group(0) @binding(1)
+var<storage, read> base_vertices: array<f32>;
+@group(0) @binding(2)
+var<storage, read> base_indices: array<u32>;
+@group(0) @binding(3)
+var<storage, read> patches: array<Patch>;
+@group(0) @binding(4)
+var<storage, read_write> vertices: array<f32>;
+@group(0) @binding(5)
+var heightmap: texture_2d<f32>;
+@group(0) @binding(6)
+var heightmap_sampler: sampler;
+
+struct Corner {
+    position: vec3<f32>,
+    uv: vec2<f32>,
+    normal: vec3<f32>,
+    tangent: vec3<f32>,
+    bitangent: vec3<f32>,
+}
+
+fn base_vertex(index: u32) -> Corner {
+    let i = index * 14u;
+    var c: Corner;
+    c.position = vec3<f32>(base_vertices[i], base_vertices[i + 1u], base_vertices[i + 2u]);
+    c.uv = vec2<f32>(base_vertices[i + 3u], base_vertices[i + 4u]);
+    c.normal = vec3<f32>(base_vertices[i + 5u], base_vertices[i + 6u], base_vertices[i + 7u]);
+    c.tangent = vec3<f32>(base_vertices[i + 8u], base_vertices[i + 9u], base_vertices[i + 10u]);
+    c.bitangent = vec3<f32>(base_vertices[i + 11u], base_vertices[i + 12u], base_vertices[i + 13u]);
+    return c;
+}
+
+var<private> a: Corner;
+var<private> b: Corner;
+var<private> c: Corner;
+
+// A point on the displaced surface, by weights for a, b and c
+fn surface(weights: vec3<f32>) -> vec3<f32> {
+    let position = a.position * weights.x + b.position * weights.y + c.position * weights.z;
+    let normal = normalize(a.normal * weights.x + b.normal * weights.y + c.normal * weights.z);
+    let uv = a.uv * weights.x + b.uv * weights.y + c.uv * weights.z;
+    let height = textureSampleLevel(heightmap, heightmap_sampler, uv, 0.0).r;
+    return position + normal * (height - uniforms.bias) * uniforms.scale;
+}
+
+// Points on an edge snap to that edge's own, coarser, steps. The patch on
+// the other side does the same, so the two meet without cracks.
+fn snap(t: f32, pieces: u32) -> f32 {
+    return round(t * f32(pieces)) / f32(pieces);
+}
+
+// The weights of grid point (i, j) of a patch cut `level` times a side
+fn grid_weights(i: u32, j: u32, level: u32, edges: Patch) -> vec3<f32> {
+    let n = f32(level);
+    var wb = f32(i) / n;
+    var wc = f32(j) / n;
+    if (j == 0u) {
+        wb = snap(wb, edges.ab);
+    } else if (i == 0u) {
+        wc = snap(wc, edges.ac);
+    } else if (i + j == level) {
+        wc = snap(wc, edges.bc);
+        wb = 1.0 - wc;
+    }
+    return vec3<f32>(1.0 - wb - wc, wb, wc);
+}
+
+fn write_vertex(index: u32, weights: vec3<f32>, step: f32) {
+    let position = surface(weights);
+    let interpolated = a.normal * weights.x + b.normal * weights.y + c.normal * weights.z;
+    // Half a step towards b and towards c gives the slope of the surface
+    let towards_b = surface(weights + vec3<f32>(-step, step, 0.0)) - position;
+    let towards_c = surface(weights + vec3<f32>(-step, 0.0, step)) - position;
+    var normal = normalize(cross(towards_b, towards_c));
+    if (dot(normal, interpolated) < 0.0) {
+        normal = -normal;
+    }
+    let t = a.tangent * weights.x + b.tangent * weights.y + c.tangent * weights.z;
+    let tangent = normalize(t - normal * dot(normal, t));
+    var bitangent = cross(normal, tangent);
+    let old_bitangent = a.bitangent * weights.x + b.bitangent * weights.y + c.bitangent * weights.z;
+    if (dot(bitangent, old_bitangent) < 0.0) {
+        bitangent = -bitangent;
+    }
+    let uv = a.uv * weights.x + b.uv * weights.y + c.uv * weights.z;
+    var data = array<f32, 14>(
+        position.x, position.y, position.z,
+        uv.x, uv.y,
+        normal.x, normal.y, normal.z,
+        tangent.x, tangent.y, tangent.z,
+        bitangent.x, bitangent.y, bitangent.z,
+    );
+    for (var k = 0u; k < 14u; k = k + 1u) {
+        vertices[index * 14u + k] = data[k];
+    }
+}
+
+@compute @workgroup_size(64)
+fn displace(
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+) {
+    let index = group.x;
+    if (index >= uniforms.num_patches) {
+        return;
+    }
+    let edges = patches[index];
+    a = base_vertex(base_indices[index * 3u]);
+    b = base_vertex(base_indices[index * 3u + 1u]);
+    c = base_vertex(base_indices[index * 3u + 2u]);
+    let level = max(edges.ab, max(edges.ac, edges.bc));
+    let step = 0.5 / f32(level);
+
+    // Row j has level - j triangles pointing up and one fewer pointing down
+    let count = 3u * level * level;
+    for (var v = local; v < count; v = v + 64u) {
+        var triangle = v / 3u;
+        var j = 0u;
+        while (triangle >= 2u * (level - j) - 1u) {
+            triangle = triangle - (2u * (level - j) - 1u);
+            j = j + 1u;
+        }
+        let up = level - j;
+        var points: array<vec2<u32>, 3>;
+        if (triangle < up) {
+            let i = triangle;
+            points = array<vec2<u32>, 3>(vec2<u32>(i, j), vec2<u32>(i + 1u, j), vec2<u32>(i, j + 1u));
+        } else {
+            let i = triangle - up;
+            points = array<vec2<u32>, 3>(
+                vec2<u32>(i + 1u, j),
+                vec2<u32>(i + 1u, j + 1u),
+                vec2<u32>(i, j + 1u),
+            );
+        }
+        let point = points[v % 3u];
+        write_vertex(edges.first_vertex + v, grid_weights(point.x, point.y, level, edges), step);
+    }
+}
@@ -0,0 +1,41 @@
+// Projects an equirectangular panorama onto a cube map. Needs
+// cube_face.wgsl in front.
+
+@group(0) @binding(0)
+var<uniform> face: u32;
+@group(0) @binding(1)
+var panorama: texture_2d<f32>;
+
+const PI: f32 = 3.14159265;
+
+// 32 bit float textures usually can't be filtered, so this blends the
+// nearest four texels itself. It wraps around horizontally.
+fn load_bilinear(uv: vec2<f32>) -> vec3<f32> {
+    let size = vec2<i32>(textureDimensions(panorama));
+    let p = uv * vec2<f32>(size) - 0.5;
+    let base = floor(p);
+    let f = p - base;
+    let x0 = (i32(base.x) % size.x + size.x) % size.x;
+    let x1 = (x0 + 1) % size.x;
+    let y0 = clamp(i32(base.y), 0, size.y - 1);
+    let y1 = clamp(i32(base.y) + 1, 0, size.y - 1);
+    let top = mix(
+        textureLoad(panorama, vec2<i32>(x0, y0), 0).rgb,
+        textureLoad(panorama, vec2<i32>(x1, y0), 0).rgb,
+        f.x,
+    );
+    let bottom = mix(
+        textureLoad(panorama, vec2<i32>(x0, y1), 0).rgb,
+        textureLoad(panorama, vec2<i32>(x1, y1), 0).rgb,
+        f.x,
+    );
+    return mix(top, bottom, f.y);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let d = face_direction(face, in.uv);
+    // The top row of the image is straight up
+    let uv = vec2<f32>(atan2(d.z, d.x) / (2.0 * PI) + 0.5, 0.5 - asin(clamp(d.y, -1.0, 1.0)) / PI);
+    return vec4<f32>(load_bilinear(uv), 1.0);
+}
@@ -0,0 +1,33 @@
+// Writes a Material into a GBuffer. Needs PBR_WGSL and MODEL_VERTEX_WGSL
+// in front.
+
+struct GBufferOutput {
+    @location(0) normal: vec4<f32>,
+    @location(1) emissive: vec4<f32>,
+    @location(2) albedo: vec4<f32>,
+    @location(3) material: vec4<f32>,
+}
+
+fn gbuffer_output(surface: Surface, n: vec3<f32>, distance: f32) -> GBufferOutput {
+    var out: GBufferOutput;
+    // Half floats alone are too coarse for positions, so what they round
+    // off from the distance goes in the emissive target
+    let rounded = unpack2x16float(pack2x16float(vec2<f32>(distance, 0.0))).x;
+    out.normal = vec4<f32>(n, rounded);
+    out.emissive = vec4<f32>(surface.emissive, distance - rounded);
+    out.albedo = vec4<f32>(surface.albedo.rgb, 1.0);
+    out.material = vec4<f32>(surface.roughness, surface.metallic, surface.occlusion, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_gbuffer(in: ModelVertexOutput) -> GBufferOutput {
+    let to_camera = camera.view_position.xyz - in.world_position;
+    let v = normalize(to_camera);
+    let uv = parallax_uv(in.tex_coords, tangent_space(in, v));
+    let surface = sample_material(uv);
+    if (material_clipped(surface)) {
+        discard;
+    }
+    return gbuffer_output(surface, world_normal(in, surface.normal), length(to_camera));
+}
@@ -0,0 +1,329 @@
+use anyhow::*;
+use winit::keyboard::KeyCode;
+
+use crate::input::Input;
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::viewport::{RenderPassExt, ViewportRect};
+
+/// What [GBufferDebug] shows in place of the lit image
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GBufferView {
+    /// Nothing, the lit image is left alone
+    Lit,
+    Albedo,
+    Normal,
+    Roughness,
+    Metallic,
+    Depth,
+    /// Screen space motion, with direction as hue and speed as brightness
+    Velocity,
+}
+
+impl GBufferView {
+    pub const ALL: [GBufferView; 7] = [
+        GBufferView::Lit,
+        GBufferView::Albedo,
+        GBufferView::Normal,
+        GBufferView::Roughness,
+        GBufferView::Metallic,
+        GBufferView::Depth,
+        GBufferView::Velocity,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GBufferView::Lit => "Lit",
+            GBufferView::Albedo => "Albedo",
+            GBufferView::Normal => "Normal",
+            GBufferView::Roughness => "Roughness",
+            GBufferView::Metallic => "Metallic",
+            GBufferView::Depth => "Depth",
+            GBufferView::Velocity => "Velocity",
+        }
+    }
+
+    fn step(self, backwards: bool, has_velocity: bool) -> Self {
+        let views = Self::ALL
+            .iter()
+            .copied()
+            .filter(|v| *v != GBufferView::Velocity || has_velocity)
+            .collect::<Vec<_>>();
+        let current = views.iter().position(|v| *v == self).unwrap_or(0);
+        let next = if backwards {
+            current + views.len() - 1
+        } else {
+            current + 1
+        };
+        views[next % views.len()]
+    }
+}
+
+/// The G-buffer textures of a deferred renderer, as views so any targets
+/// can be used. Color targets need a float format and `TEXTURE_BINDING`
+/// usage.
+pub struct GBufferTargets<'t> {
+    /// Base color in rgb
+    pub albedo: &'t wgpu::TextureView,
+    /// Normals in rgb
+    pub normal: &'t wgpu::TextureView,
+    /// Whether normals were packed into 0-1 to fit a unorm format
+    pub normals_packed: bool,
+    /// Roughness in r and metallic in g
+    pub material: &'t wgpu::TextureView,
+    pub depth: &'t wgpu::TextureView,
+    /// Motion since the last frame in uv units, in rg. Velocity is skipped
+    /// when cycling if there isn't one.
+    pub velocity: Option<&'t wgpu::TextureView>,
+    /// The camera's clip planes, for linearizing depth
+    pub near: f32,
+    pub far: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GBufferUniforms {
+    mode: u32,
+    normals_packed: u32,
+    near: f32,
+    far: f32,
+    velocity_scale: f32,
+    _padding: [u32; 3],
+}
+
+/// Full screen views of each G-buffer attribute, like the material debug
+/// modes most engines have. Bound to F4 by default.
+///
+/// ```ignore
+/// // After creating or resizing the G-buffer
+/// gbuffer_debug.set_targets(device, &GBufferTargets { ... });
+/// // In update
+/// gbuffer_debug.update(&display.input);
+/// // After the lighting pass
+/// gbuffer_debug.render(&mut encoder, &display.queue, &view, display.viewport());
+/// ```
+pub struct GBufferDebug {
+    pub view: GBufferView,
+    /// Cycles through the views, or backwards with shift held
+    pub key: KeyCode,
+    /// Multiplies velocity before it's shown, as motion is usually tiny
+    pub velocity_scale: f32,
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    uniforms: wgpu::Buffer,
+    bind_group: Option<wgpu::BindGroup>,
+    has_velocity: bool,
+    normals_packed: bool,
+    near: f32,
+    far: f32,
+    // Bound in place of a missing velocity target
+    no_velocity: wgpu::TextureView,
+}
+
+impl GBufferDebug {
+    pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Result<Self> {
+        let float_texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GBufferDebug::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                float_texture(1),
+                float_texture(2),
+                float_texture(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                float_texture(5),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GBufferDebug::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = framework_shader("gbuffer_debug.wgsl", include_str!("gbuffer_debug.wgsl"));
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(shader.module())
+            .fragment_shader(shader.module())
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .color_solid(output_format)
+            .build(device)?;
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GBufferDebug::uniforms"),
+            size: std::mem::size_of::<GBufferUniforms>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let no_velocity = device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("GBufferDebug::no_velocity"),
+                size: wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rg16Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&Default::default());
+
+        Ok(Self {
+            view: GBufferView::Lit,
+            key: KeyCode::F4,
+            velocity_scale: 50.0,
+            layout,
+            pipeline,
+            uniforms,
+            bind_group: None,
+            has_velocity: false,
+            normals_packed: false,
+            near: 0.1,
+            far: 100.0,
+            no_velocity,
+        })
+    }
+
+    /// Points the views at the G-buffer. Call it again whenever the
+    /// targets are recreated, such as on resize.
+    pub fn set_targets(&mut self, device: &wgpu::Device, targets: &GBufferTargets) {
+        let view = |binding, view| wgpu::BindGroupEntry {
+            binding,
+            resource: wgpu::BindingResource::TextureView(view),
+        };
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("GBufferDebug::bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniforms.as_entire_binding(),
+                },
+                view(1, targets.albedo),
+                view(2, targets.normal),
+                view(3, targets.material),
+                view(4, targets.depth),
+                view(5, targets.velocity.unwrap_or(&self.no_velocity)),
+            ],
+        }));
+        self.has_velocity = targets.velocity.is_some();
+        self.normals_packed = targets.normals_packed;
+        self.near = targets.near;
+        self.far = targets.far;
+        if self.view == GBufferView::Velocity && !self.has_velocity {
+            self.view = GBufferView::Lit;
+        }
+    }
+
+    /// The views that can be shown with the current targets
+    pub fn views(&self) -> impl Iterator<Item = GBufferView> {
+        let has_velocity = self.has_velocity;
+        GBufferView::ALL
+            .iter()
+            .copied()
+            .filter(move |v| *v != GBufferView::Velocity || has_velocity)
+    }
+
+    /// Steps to the next view, wrapping back around to [GBufferView::Lit]
+    pub fn cycle(&mut self, backwards: bool) {
+        self.view = self.view.step(backwards, self.has_velocity);
+    }
+
+    /// Handles the key binding
+    pub fn update(&mut self, input: &Input) {
+        if input.is_key_pressed(self.key) {
+            let shift =
+                input.is_key_held(KeyCode::ShiftLeft) || input.is_key_held(KeyCode::ShiftRight);
+            self.cycle(shift);
+            log::info!("G-buffer view: {}", self.view.name());
+        }
+    }
+
+    /// Draws the current view over `output`, covering `viewport`. Does
+    /// nothing for [GBufferView::Lit] or before [GBufferDebug::set_targets].
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        output: &wgpu::TextureView,
+        viewport: ViewportRect,
+    ) {
+        let bind_group = match &self.bind_group {
+            Some(bind_group) if self.view != GBufferView::Lit => bind_group,
+            _ => return,
+        };
+        crate::cpu_scope!("GBufferDebug::render");
+        let uniforms = GBufferUniforms {
+            mode: self.view as u32,
+            normals_packed: self.normals_packed as u32,
+            near: self.near,
+            far: self.far,
+            velocity_scale: self.velocity_scale,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniforms, 0, bytemuck::bytes_of(&uniforms));
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("GBufferDebug::render"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_viewport_rect(viewport);
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycling_wraps_and_skips_missing_velocity() {
+        assert_eq!(GBufferView::Lit.step(false, false), GBufferView::Albedo);
+        assert_eq!(GBufferView::Depth.step(false, false), GBufferView::Lit);
+        assert_eq!(GBufferView::Depth.step(false, true), GBufferView::Velocity);
+        assert_eq!(GBufferView::Velocity.step(false, true), GBufferView::Lit);
+        assert_eq!(GBufferView::Lit.step(true, false), GBufferView::Depth);
+        assert_eq!(GBufferView::Lit.step(true, true), GBufferView::Velocity);
+    }
+}
@@ -0,0 +1,83 @@
+// Modes, see GBufferView
+const MODE_ALBEDO: u32 = 1u;
+const MODE_NORMAL: u32 = 2u;
+const MODE_ROUGHNESS: u32 = 3u;
+const MODE_METALLIC: u32 = 4u;
+const MODE_DEPTH: u32 = 5u;
+const MODE_VELOCITY: u32 = 6u;
+
+struct GBufferUniforms {
+    mode: u32,
+    normals_packed: u32,
+    near: f32,
+    far: f32,
+    velocity_scale: f32,
+}
+
+#include "framework/fullscreen.wgsl"
+
+@group(0) @binding(0)
+var<uniform> gbuffer: GBufferUniforms;
+@group(0) @binding(1)
+var albedo_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var normal_texture: texture_2d<f32>;
+@group(0) @binding(3)
+var material_texture: texture_2d<f32>;
+@group(0) @binding(4)
+var depth_texture: texture_depth_2d;
+@group(0) @binding(5)
+var velocity_texture: texture_2d<f32>;
+
+// The targets can be a different size than the viewport
+fn texel(uv: vec2<f32>, size: vec2<u32>) -> vec2<i32> {
+    let fsize = vec2<f32>(size);
+    return vec2<i32>(min(uv * fsize, fsize - 1.0));
+}
+
+fn hue(angle: f32) -> vec3<f32> {
+    let h = angle / 6.2831853 + 0.5;
+    return clamp(abs(fract(h + vec3<f32>(0.0, 2.0 / 3.0, 1.0 / 3.0)) * 6.0 - 3.0) - 1.0, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    switch gbuffer.mode {
+        case MODE_ALBEDO: {
+            let coord = texel(in.uv, textureDimensions(albedo_texture));
+            return vec4<f32>(textureLoad(albedo_texture, coord, 0).rgb, 1.0);
+        }
+        case MODE_NORMAL: {
+            let coord = texel(in.uv, textureDimensions(normal_texture));
+            var normal = textureLoad(normal_texture, coord, 0).xyz;
+            if (gbuffer.normals_packed == 0u) {
+                normal = normal * 0.5 + 0.5;
+            }
+            return vec4<f32>(normal, 1.0);
+        }
+        case MODE_ROUGHNESS: {
+            let coord = texel(in.uv, textureDimensions(material_texture));
+            return vec4<f32>(vec3<f32>(textureLoad(material_texture, coord, 0).r), 1.0);
+        }
+        case MODE_METALLIC: {
+            let coord = texel(in.uv, textureDimensions(material_texture));
+            return vec4<f32>(vec3<f32>(textureLoad(material_texture, coord, 0).g), 1.0);
+        }
+        case MODE_DEPTH: {
+            let coord = texel(in.uv, textureDimensions(depth_texture));
+            let depth = textureLoad(depth_texture, coord, 0);
+            // Undo the perspective divide so the falloff is even
+            let linear = gbuffer.near * gbuffer.far / (gbuffer.far - depth * (gbuffer.far - gbuffer.near));
+            return vec4<f32>(vec3<f32>((linear - gbuffer.near) / (gbuffer.far - gbuffer.near)), 1.0);
+        }
+        case MODE_VELOCITY: {
+            let coord = texel(in.uv, textureDimensions(velocity_texture));
+            let velocity = textureLoad(velocity_texture, coord, 0).xy * gbuffer.velocity_scale;
+            let speed = min(length(velocity), 1.0);
+            return vec4<f32>(hue(atan2(velocity.y, velocity.x)) * speed, 1.0);
+        }
+        default: {
+            return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        }
+    }
+}
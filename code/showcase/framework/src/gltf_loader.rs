@@ -0,0 +1,739 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::*;
+use cgmath::*;
+// Shadowed by scene::Transform, but needed for transform_point
+use cgmath::Transform as _;
+
+use crate::animation::{AnimationClip, Animator, Joint, Skeleton};
+use crate::assets::{Assets, Handle};
+use crate::model::{
+    build_vertices, set_tangents, LoadOptions, Material, MaterialFactors, MaterialTextures, Mesh,
+    Model,
+};
+use crate::morph::{MorphDelta, MorphTarget};
+use crate::scene::Transform;
+use crate::skinning::{MeshSkin, SkinVertex};
+use crate::texture::Texture;
+use crate::timeline::Easing;
+
+impl Model {
+    /// Loads a .gltf or .glb file. Every mesh in the default scene is
+    /// imported with its node's transform baked in, so the model can be
+    /// drawn like one loaded with [Model::load_obj]. Skinned meshes are
+    /// left in their bind pose instead, with their joints and weights in
+    /// [Mesh::skin]. Textures go in `assets`.
+    pub fn load_gltf<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+        path: P,
+    ) -> Result<Self> {
+        Self::load_gltf_with_options(device, queue, assets, layout, path, &LoadOptions::default())
+    }
+
+    pub fn load_gltf_with_options<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+        path: P,
+        options: &LoadOptions,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let gltf::Gltf { document, blob } = gltf::Gltf::open(path)
+            .with_context(|| format!("Unable to parse {}", path.display()))?;
+        let base = path.parent().context("Directory has no parent")?;
+        let buffers = gltf::import_buffers(&document, Some(base), blob)
+            .with_context(|| format!("Unable to load the buffers of {}", path.display()))?;
+
+        let mut loader = GltfLoader {
+            device,
+            queue,
+            options,
+            document: &document,
+            buffers: &buffers,
+            path,
+            base,
+            images: HashMap::new(),
+        };
+
+        let mut materials = Vec::new();
+        for material in document.materials() {
+            let material = loader
+                .material(assets, layout, &material)
+                .with_context(|| format!("Loading {}", path.display()))?;
+            materials.push(material);
+        }
+        // Primitives without a material use the glTF default, which is
+        // plain white
+        let default_material = materials.len();
+        let albedo = assets.solid_texture(device, queue, [255; 4], false);
+        let normal = assets.solid_texture(device, queue, [128, 128, 255, 255], true);
+        let textures = MaterialTextures::new(device, queue, assets, albedo, normal);
+        materials.push(Material::new(
+            device,
+            assets,
+            "default",
+            textures,
+            MaterialFactors::default(),
+            layout,
+        ));
+
+        let mut meshes = Vec::new();
+        let roots = match document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+        {
+            Some(scene) => scene.nodes().collect::<Vec<_>>(),
+            // No scenes, so treat every node that isn't a child as a root
+            None => {
+                let children = document
+                    .nodes()
+                    .flat_map(|n| n.children().map(|c| c.index()).collect::<Vec<_>>())
+                    .collect::<Vec<_>>();
+                document
+                    .nodes()
+                    .filter(|n| !children.contains(&n.index()))
+                    .collect()
+            }
+        };
+        let mut stack = roots
+            .into_iter()
+            .map(|node| (node, Matrix4::identity()))
+            .collect::<Vec<_>>();
+        while let Some((node, parent)) = stack.pop() {
+            let world = parent * Matrix4::from(node.transform().matrix());
+            if let Some(mesh) = node.mesh() {
+                // The joints place skinned meshes, so glTF ignores the
+                // transform of the node they're on
+                let skin = node.skin().map(|skin| skin.index());
+                let transform = match skin {
+                    Some(_) => Matrix4::identity(),
+                    None => world,
+                };
+                loader
+                    .mesh(&mesh, transform, skin, default_material, &mut meshes)
+                    .with_context(|| format!("Loading {}", path.display()))?;
+            }
+            stack.extend(node.children().map(|child| (child, world)));
+        }
+
+        Ok(Self { meshes, materials })
+    }
+}
+
+impl Animator {
+    /// Loads the skins in a .gltf or .glb file, with one animator per skin.
+    /// Each animator gets a clip for every animation that moves its joints.
+    pub fn load_gltf<P: AsRef<Path>>(path: P) -> Result<Vec<Self>> {
+        let path = path.as_ref();
+        let gltf::Gltf { document, blob } = gltf::Gltf::open(path)
+            .with_context(|| format!("Unable to parse {}", path.display()))?;
+        let base = path.parent().context("Directory has no parent")?;
+        let buffers = gltf::import_buffers(&document, Some(base), blob)
+            .with_context(|| format!("Unable to load the buffers of {}", path.display()))?;
+
+        let mut parents = HashMap::new();
+        for node in document.nodes() {
+            for child in node.children() {
+                parents.insert(child.index(), node.index());
+            }
+        }
+        let nodes = document.nodes().collect::<Vec<_>>();
+        let world = |mut index: usize| {
+            let mut matrix = Matrix4::from(nodes[index].transform().matrix());
+            while let Some(&parent) = parents.get(&index) {
+                matrix = Matrix4::from(nodes[parent].transform().matrix()) * matrix;
+                index = parent;
+            }
+            matrix
+        };
+
+        let mut animators = Vec::new();
+        for skin in document.skins() {
+            let name = skin.name().unwrap_or("skin").to_string();
+            let joint_nodes = skin.joints().map(|j| j.index()).collect::<Vec<_>>();
+            let reader = skin.reader(|buffer| buffers.get(buffer.index()).map(|b| &b[..]));
+            let inverse_binds = reader
+                .read_inverse_bind_matrices()
+                .map(|m| m.map(Matrix4::from).collect::<Vec<_>>())
+                .unwrap_or_default();
+
+            let mut root = Matrix4::identity();
+            let mut joints = Vec::new();
+            for (i, &index) in joint_nodes.iter().enumerate() {
+                let node = &nodes[index];
+                let parent = parents.get(&index).copied();
+                let parent_joint = parent.and_then(|p| joint_nodes.iter().position(|j| *j == p));
+                if let (Some(parent), None) = (parent, parent_joint) {
+                    // Everything above the root joints is static
+                    root = world(parent);
+                }
+                let (translation, rotation, scale) = node.transform().decomposed();
+                joints.push(Joint {
+                    name: node
+                        .name()
+                        .map(str::to_string)
+                        .unwrap_or_else(|| format!("joint{}", i)),
+                    parent: parent_joint,
+                    rest: Transform::new(translation.into(), quaternion(rotation), scale.into()),
+                    inverse_bind: inverse_binds
+                        .get(i)
+                        .copied()
+                        .unwrap_or_else(Matrix4::identity),
+                });
+            }
+            let mut skeleton = Skeleton::new(joints).with_context(|| format!("Skin {:?}", name))?;
+            skeleton.root = root;
+
+            let mut animator = Animator::new(skeleton);
+            for animation in document.animations() {
+                let clip = load_clip(&animation, &joint_nodes, &buffers);
+                if clip.channels().next().is_some() {
+                    animator.add_clip(clip);
+                }
+            }
+            animators.push(animator);
+        }
+        Ok(animators)
+    }
+}
+
+fn load_clip(
+    animation: &gltf::Animation,
+    joint_nodes: &[usize],
+    buffers: &[gltf::buffer::Data],
+) -> AnimationClip {
+    use gltf::animation::util::ReadOutputs;
+    use gltf::animation::{Interpolation, Property};
+
+    let mut clip = AnimationClip::new(
+        &animation
+            .name()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("animation{}", animation.index())),
+    );
+    for channel in animation.channels() {
+        // These target meshes rather than joints, and there's nowhere to
+        // play them yet
+        if channel.target().property() == Property::MorphTargetWeights {
+            let node = channel.target().node();
+            log::warn!(
+                "Skipping the morph target weights channel for node {:?} in animation {:?}",
+                node.name().unwrap_or(&format!("node{}", node.index())),
+                clip.name
+            );
+            continue;
+        }
+        let joint = match joint_nodes
+            .iter()
+            .position(|j| *j == channel.target().node().index())
+        {
+            Some(joint) => joint,
+            None => continue,
+        };
+        let reader = channel.reader(|buffer| buffers.get(buffer.index()).map(|b| &b[..]));
+        let (times, outputs) = match (reader.read_inputs(), reader.read_outputs()) {
+            (Some(times), Some(outputs)) => (times.collect::<Vec<_>>(), outputs),
+            _ => continue,
+        };
+        let interpolation = channel.sampler().interpolation();
+        let easing = match interpolation {
+            Interpolation::Step => Easing::Step,
+            // Cubic splines are sampled linearly between their keys
+            Interpolation::Linear | Interpolation::CubicSpline => Easing::Linear,
+        };
+        // Cubic splines store an in tangent, the value and an out tangent
+        // for each key
+        let (skip, step) = match interpolation {
+            Interpolation::CubicSpline => (1, 3),
+            _ => (0, 1),
+        };
+        let target = clip.channel(joint);
+        match outputs {
+            ReadOutputs::Translations(values) => {
+                for (time, value) in times.iter().zip(values.skip(skip).step_by(step)) {
+                    target.translation.key(*time, value.into(), easing);
+                }
+            }
+            ReadOutputs::Rotations(values) => {
+                for (time, value) in times.iter().zip(values.into_f32().skip(skip).step_by(step)) {
+                    target.rotation.key(*time, quaternion(value), easing);
+                }
+            }
+            ReadOutputs::Scales(values) => {
+                for (time, value) in times.iter().zip(values.skip(skip).step_by(step)) {
+                    target.scale.key(*time, value.into(), easing);
+                }
+            }
+            // Skipped above
+            ReadOutputs::MorphTargetWeights(_) => {}
+        }
+    }
+    clip
+}
+
+/// glTF stores quaternions as xyzw
+fn quaternion([x, y, z, w]: [f32; 4]) -> Quaternion<f32> {
+    Quaternion::new(w, x, y, z)
+}
+
+struct GltfLoader<'l> {
+    device: &'l wgpu::Device,
+    queue: &'l wgpu::Queue,
+    options: &'l LoadOptions,
+    document: &'l gltf::Document,
+    buffers: &'l [gltf::buffer::Data],
+    path: &'l Path,
+    base: &'l Path,
+    /// Decoded images, converted to RGBA
+    images: HashMap<usize, image::RgbaImage>,
+}
+
+impl<'l> GltfLoader<'l> {
+    fn material(
+        &mut self,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+        material: &gltf::Material,
+    ) -> Result<Material> {
+        let name = material
+            .name()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("material{}", material.index().unwrap_or(0)));
+        let pbr = material.pbr_metallic_roughness();
+        let albedo = match pbr.base_color_texture() {
+            Some(info) => self.texture(assets, &info.texture(), false)?,
+            None => assets.solid_texture(self.device, self.queue, [255; 4], false),
+        };
+        let normal = match material.normal_texture() {
+            Some(normal) => self.texture(assets, &normal.texture(), true)?,
+            None => assets.solid_texture(self.device, self.queue, [128, 128, 255, 255], true),
+        };
+        let mut textures = MaterialTextures::new(self.device, self.queue, assets, albedo, normal);
+        if let Some(info) = pbr.metallic_roughness_texture() {
+            textures.metallic_roughness = self.texture(assets, &info.texture(), true)?;
+        }
+        if let Some(occlusion) = material.occlusion_texture() {
+            textures.occlusion = self.texture(assets, &occlusion.texture(), true)?;
+        }
+        if let Some(info) = material.emissive_texture() {
+            textures.emissive = self.texture(assets, &info.texture(), false)?;
+        }
+        let factors = MaterialFactors {
+            albedo: pbr.base_color_factor(),
+            emissive: material.emissive_factor(),
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            occlusion_strength: material.occlusion_texture().map_or(1.0, |o| o.strength()),
+            normal_scale: material.normal_texture().map_or(1.0, |n| n.scale()),
+            alpha_cutoff: match material.alpha_mode() {
+                gltf::material::AlphaMode::Mask => material.alpha_cutoff().unwrap_or(0.5),
+                _ => 0.0,
+            },
+            ..Default::default()
+        };
+        Ok(Material::new(
+            self.device,
+            assets,
+            &name,
+            textures,
+            factors,
+            layout,
+        ))
+    }
+
+    /// Each texture is only created once per file, even if several
+    /// materials use it
+    fn texture(
+        &mut self,
+        assets: &mut Assets,
+        texture: &gltf::Texture,
+        is_normal_map: bool,
+    ) -> Result<Handle<Texture<'static>>> {
+        // Normal maps aren't sRGB, so they're a different texture
+        let key = format!(
+            "{}#texture{}{}",
+            self.path.display(),
+            texture.index(),
+            if is_normal_map { "-linear" } else { "" }
+        );
+        if let Some(handle) = assets.find(&key) {
+            return Ok(handle);
+        }
+        let index = texture.source().index();
+        if !self.images.contains_key(&index) {
+            let image = self
+                .document
+                .images()
+                .nth(index)
+                .context("Texture references a missing image")?;
+            match gltf::image::Data::from_source(image.source(), Some(self.base), self.buffers) {
+                Result::Ok(data) => {
+                    self.images.insert(index, to_rgba(data));
+                }
+                Err(e) => {
+                    self.options
+                        .problem(format!("Unable to load image {}: {}", index, e))?;
+                    return Ok(assets.placeholder_texture(self.device, self.queue, is_normal_map));
+                }
+            }
+        }
+
+        let img = image::DynamicImage::ImageRgba8(self.images[&index].clone());
+        let mut result =
+            Texture::from_image(self.device, self.queue, &img, texture.name(), is_normal_map)?;
+        // glTF defaults to repeating textures, unlike Texture::from_image
+        let sampler = texture.sampler();
+        let filter = match sampler.mag_filter() {
+            Some(gltf::texture::MagFilter::Nearest) => wgpu::FilterMode::Nearest,
+            _ => wgpu::FilterMode::Linear,
+        };
+        result.sampler = self.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: texture.name(),
+            address_mode_u: address_mode(sampler.wrap_s()),
+            address_mode_v: address_mode(sampler.wrap_t()),
+            mag_filter: filter,
+            min_filter: filter,
+            ..Default::default()
+        });
+        Ok(assets.add_with_path(result, key))
+    }
+
+    fn mesh(
+        &mut self,
+        mesh: &gltf::Mesh,
+        world: Matrix4<f32>,
+        skin: Option<usize>,
+        default_material: usize,
+        meshes: &mut Vec<Mesh>,
+    ) -> Result<()> {
+        let name = mesh
+            .name()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("mesh{}", mesh.index()));
+        let linear = Matrix3::new(
+            world.x.x, world.x.y, world.x.z, world.y.x, world.y.y, world.y.z, world.z.x, world.z.y,
+            world.z.z,
+        );
+        let normal_matrix = linear
+            .invert()
+            .map(|m| m.transpose())
+            .unwrap_or_else(Matrix3::identity);
+        // Mirroring transforms turn triangles inside out
+        let flip_winding = world.determinant() < 0.0;
+
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                self.options.problem(format!(
+                    "Mesh {:?} uses {:?}, only triangles are supported",
+                    name,
+                    primitive.mode()
+                ))?;
+                continue;
+            }
+            let reader =
+                primitive.reader(|buffer| self.buffers.get(buffer.index()).map(|b| &b[..]));
+            let positions = reader
+                .read_positions()
+                .with_context(|| format!("Mesh {:?} has no positions", name))?
+                .flat_map(|p| {
+                    let p = world.transform_point(Point3::from(p));
+                    [p.x, p.y, p.z]
+                })
+                .collect::<Vec<_>>();
+            let normals = reader
+                .read_normals()
+                .map(|normals| {
+                    normals
+                        .flat_map(|n| {
+                            let n = (normal_matrix * Vector3::from(n)).normalize();
+                            [n.x, n.y, n.z]
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            let tex_coords = reader
+                .read_tex_coords(0)
+                .map(|tc| tc.into_f32().flatten().collect::<Vec<_>>())
+                .unwrap_or_default();
+            let mut indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                None => (0..positions.len() as u32 / 3).collect(),
+            };
+            if flip_winding {
+                for triangle in indices.chunks_exact_mut(3) {
+                    triangle.swap(1, 2);
+                }
+            }
+
+            let (mut vertices, indices) =
+                build_vertices(&positions, &tex_coords, &normals, &indices, self.options)
+                    .with_context(|| format!("Mesh {:?}", name))?;
+            // Tangents saved by the exporter match the normal map it was
+            // baked with, so they beat the ones we worked out. A mirroring
+            // transform flips which way the bitangent points.
+            if let Some(tangents) = reader.read_tangents() {
+                let tangents = tangents
+                    .map(|t| {
+                        let v = linear * Vector3::new(t[0], t[1], t[2]);
+                        let w = if flip_winding { -t[3] } else { t[3] };
+                        [v.x, v.y, v.z, w]
+                    })
+                    .collect::<Vec<_>>();
+                if tangents.len() == vertices.len() {
+                    set_tangents(&mut vertices, &tangents);
+                }
+            }
+            let material = primitive.material().index().unwrap_or(default_material);
+            let skin = match (skin, read_skin(&reader)) {
+                (Some(skin), Some(weights)) if weights.len() == vertices.len() => Some(MeshSkin {
+                    skin,
+                    vertices: vertices.clone(),
+                    weights,
+                    indices: indices.clone(),
+                }),
+                (Some(_), _) => {
+                    self.options.problem(format!(
+                        "Mesh {:?} is skinned but doesn't have joints and weights for every vertex",
+                        name
+                    ))?;
+                    None
+                }
+                _ => None,
+            };
+
+            // Deltas are directions, so they only get the node's rotation
+            // and scale
+            let vertex_count = vertices.len();
+            let transform_all = |values: Option<Vec<[f32; 3]>>, matrix: Matrix3<f32>| {
+                values
+                    .filter(|v| v.len() == vertex_count)
+                    .map(|v| {
+                        v.into_iter()
+                            .map(|d| (matrix * Vector3::from(d)).into())
+                            .collect::<Vec<[f32; 3]>>()
+                    })
+                    .unwrap_or_else(|| vec![[0.0; 3]; vertex_count])
+            };
+            let mut targets = Vec::new();
+            for (i, (positions, normals, tangents)) in reader.read_morph_targets().enumerate() {
+                let positions = transform_all(positions.map(|p| p.collect()), linear);
+                let normals = transform_all(normals.map(|n| n.collect()), normal_matrix);
+                let tangents = transform_all(tangents.map(|t| t.collect()), linear);
+                let deltas = (0..vertex_count)
+                    .map(|v| MorphDelta {
+                        position: positions[v],
+                        normal: normals[v],
+                        tangent: tangents[v],
+                    })
+                    .collect();
+                targets.push(MorphTarget {
+                    name: format!("target{}", i),
+                    deltas,
+                });
+            }
+
+            if targets.is_empty() {
+                let mut plain =
+                    Mesh::from_vertices(self.device, &name, &vertices, &indices, material);
+                plain.skin = skin;
+                meshes.push(plain);
+            } else {
+                let mut morphed = Mesh::with_morph_targets(
+                    self.device,
+                    &name,
+                    &vertices,
+                    &indices,
+                    material,
+                    &targets,
+                )?;
+                if let (Some(morph), Some(weights)) = (&mut morphed.morph_targets, mesh.weights()) {
+                    morph.set_weights(weights);
+                }
+                morphed.skin = skin;
+                meshes.push(morphed);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The first set of joints and weights of a primitive, if it has them
+fn read_skin<'a, 's, F>(reader: &gltf::mesh::Reader<'a, 's, F>) -> Option<Vec<SkinVertex>>
+where
+    F: Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+{
+    let joints = reader.read_joints(0)?.into_u16();
+    let weights = reader.read_weights(0)?.into_f32();
+    Some(
+        joints
+            .zip(weights)
+            .map(|(joints, weights)| SkinVertex {
+                joints: joints.map(u32::from),
+                weights,
+            })
+            .collect(),
+    )
+}
+
+fn address_mode(mode: gltf::texture::WrappingMode) -> wgpu::AddressMode {
+    match mode {
+        gltf::texture::WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        gltf::texture::WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        gltf::texture::WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    }
+}
+
+/// glTF images can be in a handful of formats, but our textures are all
+/// 8 bit RGBA
+fn to_rgba(data: gltf::image::Data) -> image::RgbaImage {
+    use gltf::image::Format;
+
+    let channels = match data.format {
+        Format::R8 | Format::R16 => 1,
+        Format::R8G8 | Format::R16G16 => 2,
+        Format::R8G8B8 | Format::R16G16B16 | Format::R32G32B32FLOAT => 3,
+        Format::R8G8B8A8 | Format::R16G16B16A16 | Format::R32G32B32A32FLOAT => 4,
+    };
+    let values: Vec<u8> = match data.format {
+        Format::R8 | Format::R8G8 | Format::R8G8B8 | Format::R8G8B8A8 => data.pixels,
+        Format::R16 | Format::R16G16 | Format::R16G16B16 | Format::R16G16B16A16 => data
+            .pixels
+            .chunks_exact(2)
+            .map(|c| (u16::from_ne_bytes([c[0], c[1]]) >> 8) as u8)
+            .collect(),
+        Format::R32G32B32FLOAT | Format::R32G32B32A32FLOAT => data
+            .pixels
+            .chunks_exact(4)
+            .map(|c| (f32::from_ne_bytes([c[0], c[1], c[2], c[3]]).clamp(0.0, 1.0) * 255.0) as u8)
+            .collect(),
+    };
+    let mut rgba = Vec::with_capacity((data.width * data.height * 4) as usize);
+    for pixel in values.chunks_exact(channels) {
+        rgba.extend_from_slice(&match *pixel {
+            [r] => [r, r, r, 255],
+            [r, g] => [r, g, 0, 255],
+            [r, g, b] => [r, g, b, 255],
+            [r, g, b, a] => [r, g, b, a],
+            _ => unreachable!(),
+        });
+    }
+    image::RgbaImage::from_raw(data.width, data.height, rgba).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gltf::image::{Data, Format};
+
+    #[test]
+    fn images_convert_to_rgba() {
+        let gray = Data {
+            pixels: vec![10, 200],
+            format: Format::R8,
+            width: 2,
+            height: 1,
+        };
+        assert_eq!(
+            to_rgba(gray).into_raw(),
+            [10, 10, 10, 255, 200, 200, 200, 255]
+        );
+
+        let wide = Data {
+            pixels: [0xffffu16, 0x8000, 0, 0x1234]
+                .iter()
+                .flat_map(|v| v.to_ne_bytes())
+                .collect(),
+            format: Format::R16G16B16A16,
+            width: 1,
+            height: 1,
+        };
+        assert_eq!(to_rgba(wide).into_raw(), [255, 128, 0, 0x12]);
+
+        let hdr = Data {
+            pixels: [2.0f32, 0.5, -1.0]
+                .iter()
+                .flat_map(|v| v.to_ne_bytes())
+                .collect(),
+            format: Format::R32G32B32FLOAT,
+            width: 1,
+            height: 1,
+        };
+        assert_eq!(to_rgba(hdr).into_raw(), [255, 127, 0, 255]);
+    }
+
+    #[test]
+    fn skinned_primitives_keep_joints_and_weights() {
+        // A .glb with one triangle skinned to two joints
+        let json = r#"{
+            "asset": {"version": "2.0"},
+            "buffers": [{"byteLength": 96}],
+            "bufferViews": [
+                {"buffer": 0, "byteOffset": 0, "byteLength": 36},
+                {"buffer": 0, "byteOffset": 36, "byteLength": 12},
+                {"buffer": 0, "byteOffset": 48, "byteLength": 48}
+            ],
+            "accessors": [
+                {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                    "min": [0, 0, 0], "max": [1, 1, 0]},
+                {"bufferView": 1, "componentType": 5121, "count": 3, "type": "VEC4"},
+                {"bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC4"}
+            ],
+            "meshes": [{"primitives": [{"attributes": {
+                "POSITION": 0, "JOINTS_0": 1, "WEIGHTS_0": 2
+            }}]}],
+            "nodes": [{"mesh": 0, "skin": 0}, {"children": [2]}, {}],
+            "skins": [{"joints": [1, 2]}]
+        }"#;
+        let mut bin = Vec::new();
+        for v in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            bin.extend_from_slice(&v.to_le_bytes());
+        }
+        bin.extend_from_slice(&[0, 1, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0]);
+        for v in [
+            1.0f32, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.25, 0.75, 0.0, 0.0,
+        ] {
+            bin.extend_from_slice(&v.to_le_bytes());
+        }
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut glb = Vec::new();
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&bin);
+
+        let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(&glb).unwrap();
+        let buffers = gltf::import_buffers(&document, None, blob).unwrap();
+        let node = document.nodes().next().unwrap();
+        assert_eq!(node.skin().map(|skin| skin.index()), Some(0));
+        let primitive = node.mesh().unwrap().primitives().next().unwrap();
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|b| &b[..]));
+        assert_eq!(
+            read_skin(&reader).unwrap(),
+            [
+                SkinVertex {
+                    joints: [0, 1, 0, 0],
+                    weights: [1.0, 0.0, 0.0, 0.0],
+                },
+                SkinVertex {
+                    joints: [1, 0, 0, 0],
+                    weights: [1.0, 0.0, 0.0, 0.0],
+                },
+                SkinVertex {
+                    joints: [0, 1, 0, 0],
+                    weights: [0.25, 0.75, 0.0, 0.0],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Duration;
+
+use crate::capabilities::Capabilities;
+
+/// Frames that can wait on the GPU at once. Frames past that aren't
+/// timed, so a stalled device doesn't pile up readbacks.
+const MAX_PENDING: usize = 4;
+
+/// How long a [GpuProfiler::scope] took on the GPU
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuScopeTiming {
+    pub name: &'static str,
+    /// How many scopes this one was nested in
+    pub depth: usize,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ScopeRecord {
+    name: &'static str,
+    depth: usize,
+    /// The start timestamp's index, with the end's right after it
+    query: u32,
+    /// Whether anything wrote the timestamps. Without
+    /// [Capabilities::timestamps_inside_encoders], scopes without passes
+    /// don't.
+    timed: bool,
+}
+
+struct PendingFrame {
+    buffer: wgpu::Buffer,
+    records: Vec<ScopeRecord>,
+    queries: u32,
+    mapped: Option<Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+/// Times spans of GPU work with timestamp queries, the way
+/// [crate::cpu_scope!] times the CPU. Results are read back a frame or
+/// two later without waiting on the GPU, and [GpuProfiler::last_frame]
+/// has the newest.
+///
+/// ```ignore
+/// let mut profiler = GpuProfiler::new(&display.device, &display.queue, &display.capabilities, 32);
+///
+/// // In Demo::render
+/// {
+///     let mut scope = profiler.scope("shadow pass", &mut encoder);
+///     let mut pass = scope.begin_render_pass(&shadow_pass_desc);
+///     // ...
+/// }
+/// profiler.resolve(&mut encoder);
+/// display.queue.submit([encoder.finish()]);
+/// profiler.end_frame(&display.device);
+/// display.stats_overlay.set_gpu_timings(profiler.last_frame());
+/// ```
+///
+/// With [Capabilities::timestamps_inside_encoders], a scope times
+/// everything recorded through it. Otherwise timestamps can only be taken
+/// at the edges of passes, so only passes begun with
+/// [GpuScope::begin_render_pass] or [GpuScope::begin_compute_pass] count.
+/// Without [Capabilities::timestamp_queries] scopes do nothing and there
+/// are no timings.
+pub struct GpuProfiler {
+    query_set: Option<wgpu::QuerySet>,
+    inside_encoders: bool,
+    resolve_buffer: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    max_queries: u32,
+    next_query: u32,
+    records: Vec<ScopeRecord>,
+    pending: VecDeque<PendingFrame>,
+    free_buffers: Vec<wgpu::Buffer>,
+    last_frame: Vec<GpuScopeTiming>,
+}
+
+impl GpuProfiler {
+    /// Times up to `max_scopes` scopes a frame. Any past that are left
+    /// out.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        capabilities: &Capabilities,
+        max_scopes: u32,
+    ) -> Self {
+        let max_queries = (max_scopes * 2).clamp(2, wgpu::QUERY_SET_MAX_QUERIES);
+        let size = max_queries as u64 * wgpu::QUERY_SIZE as u64;
+        let query_set = capabilities.timestamp_queries.then(|| {
+            device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("GpuProfiler::query_set"),
+                ty: wgpu::QueryType::Timestamp,
+                count: max_queries,
+            })
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuProfiler::resolve_buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let free_buffers = (0..MAX_PENDING)
+            .map(|_| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("GpuProfiler::readback"),
+                    size,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+        Self {
+            query_set,
+            inside_encoders: capabilities.timestamps_inside_encoders,
+            resolve_buffer,
+            period: queue.get_timestamp_period(),
+            max_queries,
+            next_query: 0,
+            records: Vec::new(),
+            pending: VecDeque::new(),
+            free_buffers,
+            last_frame: Vec::new(),
+        }
+    }
+
+    /// Whether scopes are timed on this device
+    pub fn is_supported(&self) -> bool {
+        self.query_set.is_some()
+    }
+
+    /// Starts timing work recorded into `encoder`, until the scope is
+    /// dropped. The scope stands in for the encoder in the meantime.
+    pub fn scope<'a>(
+        &'a mut self,
+        name: &'static str,
+        encoder: &'a mut wgpu::CommandEncoder,
+    ) -> GpuScope<'a> {
+        GpuScope::open(self, encoder, name, 0)
+    }
+
+    /// Copies this frame's timestamps out of the query set. Call it once
+    /// every scope is dropped, before finishing `encoder`.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let records = std::mem::take(&mut self.records);
+        let queries = std::mem::take(&mut self.next_query);
+        let query_set = match &self.query_set {
+            Some(query_set) if queries > 0 => query_set,
+            _ => return,
+        };
+        let buffer = match self.free_buffers.pop() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        let size = queries as u64 * wgpu::QUERY_SIZE as u64;
+        encoder.resolve_query_set(query_set, 0..queries, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &buffer, 0, size);
+        self.pending.push_back(PendingFrame {
+            buffer,
+            records,
+            queries,
+            mapped: None,
+        });
+    }
+
+    /// Starts reading back what [GpuProfiler::resolve] copied and picks
+    /// up any earlier frames the GPU has finished. Call it after
+    /// submitting the encoder passed to resolve.
+    pub fn end_frame(&mut self, device: &wgpu::Device) {
+        for frame in self.pending.iter_mut().filter(|f| f.mapped.is_none()) {
+            let (tx, rx) = std::sync::mpsc::channel();
+            let size = frame.queries as u64 * wgpu::QUERY_SIZE as u64;
+            frame
+                .buffer
+                .slice(..size)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    let _ = tx.send(result);
+                });
+            frame.mapped = Some(rx);
+        }
+        device.poll(wgpu::Maintain::Poll);
+
+        // Frames finish in order, so stop at the first that hasn't
+        while let Some(frame) = self.pending.front() {
+            let result = match frame.mapped.as_ref().map(|rx| rx.try_recv()) {
+                Some(Result::Ok(result)) => result,
+                Some(Err(TryRecvError::Empty)) | None => break,
+                Some(Err(TryRecvError::Disconnected)) => Err(wgpu::BufferAsyncError),
+            };
+            let frame = self.pending.pop_front().unwrap();
+            match result {
+                Result::Ok(()) => {
+                    let size = frame.queries as u64 * wgpu::QUERY_SIZE as u64;
+                    let slice = frame.buffer.slice(..size);
+                    let timestamps =
+                        bytemuck::cast_slice::<_, u64>(&slice.get_mapped_range()).to_vec();
+                    frame.buffer.unmap();
+                    self.last_frame = scope_timings(&frame.records, &timestamps, self.period);
+                }
+                Err(e) => log::warn!("Unable to read GPU timestamps: {}", e),
+            }
+            self.free_buffers.push(frame.buffer);
+        }
+    }
+
+    /// The scopes from the newest frame that's been read back, in the
+    /// order they started
+    pub fn last_frame(&self) -> &[GpuScopeTiming] {
+        &self.last_frame
+    }
+
+    /// The two timestamps for a new scope, if there's room
+    fn allocate(&mut self, name: &'static str, depth: usize) -> Option<usize> {
+        self.query_set.as_ref()?;
+        if self.next_query + 2 > self.max_queries {
+            return None;
+        }
+        self.records.push(ScopeRecord {
+            name,
+            depth,
+            query: self.next_query,
+            timed: false,
+        });
+        self.next_query += 2;
+        Some(self.records.len() - 1)
+    }
+}
+
+/// Turns resolved timestamps into durations. Scopes that weren't timed
+/// are left out.
+fn scope_timings(records: &[ScopeRecord], timestamps: &[u64], period: f32) -> Vec<GpuScopeTiming> {
+    records
+        .iter()
+        .filter(|r| r.timed)
+        .filter_map(|r| {
+            let start = *timestamps.get(r.query as usize)?;
+            let end = *timestamps.get(r.query as usize + 1)?;
+            let nanos = end.saturating_sub(start) as f64 * period as f64;
+            Some(GpuScopeTiming {
+                name: r.name,
+                depth: r.depth,
+                duration: Duration::from_nanos(nanos as u64),
+            })
+        })
+        .collect()
+}
+
+/// A span being timed, from [GpuProfiler::scope]. It derefs to the
+/// encoder, so record the scope's work through it. The end timestamp is
+/// taken when it's dropped.
+pub struct GpuScope<'a> {
+    profiler: &'a mut GpuProfiler,
+    encoder: &'a mut wgpu::CommandEncoder,
+    /// Index into the profiler's records, when the scope is timed
+    record: Option<usize>,
+    depth: usize,
+}
+
+impl<'a> GpuScope<'a> {
+    fn open(
+        profiler: &'a mut GpuProfiler,
+        encoder: &'a mut wgpu::CommandEncoder,
+        name: &'static str,
+        depth: usize,
+    ) -> Self {
+        let record = profiler.allocate(name, depth);
+        if let (Some(index), Some(query_set)) = (record, &profiler.query_set) {
+            if profiler.inside_encoders {
+                encoder.write_timestamp(query_set, profiler.records[index].query);
+                profiler.records[index].timed = true;
+            }
+        }
+        Self {
+            profiler,
+            encoder,
+            record,
+            depth,
+        }
+    }
+
+    /// Times a span inside this one
+    pub fn scope(&mut self, name: &'static str) -> GpuScope<'_> {
+        GpuScope::open(
+            &mut *self.profiler,
+            &mut *self.encoder,
+            name,
+            self.depth + 1,
+        )
+    }
+
+    /// Like [wgpu::CommandEncoder::begin_render_pass], but times the pass
+    /// when timestamps can't be written between passes. That replaces
+    /// any `timestamp_writes` in `desc`.
+    pub fn begin_render_pass(
+        &mut self,
+        desc: &wgpu::RenderPassDescriptor<'_>,
+    ) -> wgpu::RenderPass<'_> {
+        let writes = self.pass_writes();
+        let timestamp_writes = match (writes, &self.profiler.query_set) {
+            (Some((beginning, end)), Some(query_set)) => Some(wgpu::RenderPassTimestampWrites {
+                query_set,
+                beginning_of_pass_write_index: beginning,
+                end_of_pass_write_index: Some(end),
+            }),
+            _ => desc.timestamp_writes.clone(),
+        };
+        self.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            timestamp_writes,
+            ..desc.clone()
+        })
+    }
+
+    /// Like [GpuScope::begin_render_pass], for compute passes
+    pub fn begin_compute_pass(
+        &mut self,
+        desc: &wgpu::ComputePassDescriptor<'_>,
+    ) -> wgpu::ComputePass<'_> {
+        let writes = self.pass_writes();
+        let timestamp_writes = match (writes, &self.profiler.query_set) {
+            (Some((beginning, end)), Some(query_set)) => Some(wgpu::ComputePassTimestampWrites {
+                query_set,
+                beginning_of_pass_write_index: beginning,
+                end_of_pass_write_index: Some(end),
+            }),
+            _ => desc.timestamp_writes.clone(),
+        };
+        self.encoder
+            .begin_compute_pass(&wgpu::ComputePassDescriptor {
+                timestamp_writes,
+                ..desc.clone()
+            })
+    }
+
+    /// The queries a pass should write, when timing by passes. The first
+    /// pass starts the scope and each one moves its end later.
+    fn pass_writes(&mut self) -> Option<(Option<u32>, u32)> {
+        if self.profiler.inside_encoders {
+            return None;
+        }
+        let record = &mut self.profiler.records[self.record?];
+        let beginning = (!record.timed).then_some(record.query);
+        record.timed = true;
+        Some((beginning, record.query + 1))
+    }
+}
+
+impl Deref for GpuScope<'_> {
+    type Target = wgpu::CommandEncoder;
+
+    fn deref(&self) -> &Self::Target {
+        self.encoder
+    }
+}
+
+impl DerefMut for GpuScope<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.encoder
+    }
+}
+
+impl Drop for GpuScope<'_> {
+    fn drop(&mut self) {
+        if let (Some(index), Some(query_set)) = (self.record, &self.profiler.query_set) {
+            if self.profiler.inside_encoders {
+                let query = self.profiler.records[index].query + 1;
+                self.encoder.write_timestamp(query_set, query);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_become_durations() {
+        let record = |name, depth, query, timed| ScopeRecord {
+            name,
+            depth,
+            query,
+            timed,
+        };
+        let records = [
+            record("frame", 0, 0, true),
+            record("shadow pass", 1, 2, true),
+            record("no passes", 1, 4, false),
+        ];
+        let timestamps = [1000, 9000, 2000, 3000, 0, 0];
+        let timings = scope_timings(&records, &timestamps, 2.0);
+        assert_eq!(
+            timings,
+            vec![
+                GpuScopeTiming {
+                    name: "frame",
+                    depth: 0,
+                    duration: Duration::from_nanos(16_000),
+                },
+                GpuScopeTiming {
+                    name: "shadow pass",
+                    depth: 1,
+                    duration: Duration::from_nanos(2_000),
+                },
+            ]
+        );
+        // Some drivers wrap or reorder, which shouldn't go negative
+        let timings = scope_timings(&records[..1], &[5, 4], 1.0);
+        assert_eq!(timings[0].duration, Duration::ZERO);
+    }
+}
@@ -0,0 +1,148 @@
+//! egui on top of demos. [crate::run] forwards window events to a [Gui]
+//! on the [crate::Display] and builds the frame's ui from [crate::Demo::ui]
+//! before rendering. The demo draws it with [crate::Display::render_gui]
+//! as its last pass over the surface, after any tonemapping.
+//!
+//! ```ignore
+//! fn ui(&mut self, ctx: &egui::Context) {
+//!     egui::Window::new("Bloom").show(ctx, |ui| {
+//!         ui.add(egui::Slider::new(&mut self.bloom.intensity, 0.0..=2.0));
+//!     });
+//! }
+//!
+//! fn render(&mut self, display: &mut Display) {
+//!     // ... draw the scene to frame_view
+//!     display.render_gui(&mut encoder, &frame_view);
+//!     display.queue.submit([encoder.finish()]);
+//! }
+//! ```
+
+pub use egui;
+
+use winit::event::{ElementState, WindowEvent};
+use winit::window::Window;
+
+pub struct Gui {
+    ctx: egui::Context,
+    state: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    primitives: Vec<egui::ClippedPrimitive>,
+    /// Kept until the next [Gui::render], so textures aren't lost when a
+    /// frame isn't drawn
+    textures: egui::TexturesDelta,
+    pixels_per_point: f32,
+}
+
+impl Gui {
+    pub fn new(device: &wgpu::Device, window: &Window, format: wgpu::TextureFormat) -> Self {
+        let ctx = egui::Context::default();
+        let state = egui_winit::State::new(
+            ctx.clone(),
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            Some(device.limits().max_texture_dimension_2d as usize),
+        );
+        // Drawn straight to the surface, after MSAA has resolved
+        let renderer = egui_wgpu::Renderer::new(device, format, None, 1, false);
+        Self {
+            ctx,
+            state,
+            renderer,
+            primitives: Vec::new(),
+            textures: Default::default(),
+            pixels_per_point: window.scale_factor() as f32,
+        }
+    }
+
+    pub fn context(&self) -> &egui::Context {
+        &self.ctx
+    }
+
+    /// Whether egui is dragging or otherwise holding on to the mouse, so
+    /// the demo shouldn't turn the camera with it
+    pub fn wants_pointer(&self) -> bool {
+        self.ctx.is_using_pointer()
+    }
+
+    pub fn wants_keyboard(&self) -> bool {
+        self.ctx.wants_keyboard_input()
+    }
+
+    /// Passes `event` to egui. True when egui used it and the demo
+    /// shouldn't see it. Releases always get through, so a key pressed
+    /// before a text box took focus doesn't stay held.
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        let response = self.state.on_window_event(window, event);
+        response.consumed
+            && match event {
+                WindowEvent::KeyboardInput { event, .. } => event.state == ElementState::Pressed,
+                WindowEvent::MouseInput { state, .. } => *state == ElementState::Pressed,
+                WindowEvent::MouseWheel { .. } => true,
+                _ => false,
+            }
+    }
+
+    /// Lays out this frame's ui, with `build` adding the windows and
+    /// panels
+    pub fn run(&mut self, window: &Window, build: impl FnMut(&egui::Context)) {
+        crate::cpu_scope!("Gui::run");
+        let input = self.state.take_egui_input(window);
+        let output = self.ctx.run(input, build);
+        self.state
+            .handle_platform_output(window, output.platform_output);
+        self.primitives = self.ctx.tessellate(output.shapes, output.pixels_per_point);
+        self.textures.append(output.textures_delta);
+        self.pixels_per_point = output.pixels_per_point;
+    }
+
+    /// Draws the last [Gui::run] over `view`, which should be `width` by
+    /// `height` and in the format the gui was created with
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        crate::cpu_scope!("Gui::render");
+        for (id, delta) in &self.textures.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        let screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [width, height],
+            pixels_per_point: self.pixels_per_point,
+        };
+        // Only egui_wgpu::Callback paint callbacks make command buffers
+        let callbacks =
+            self.renderer
+                .update_buffers(device, queue, encoder, &self.primitives, &screen);
+        if !callbacks.is_empty() {
+            queue.submit(callbacks);
+        }
+        {
+            let pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Gui::render"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            self.renderer
+                .render(&mut pass.forget_lifetime(), &self.primitives, &screen);
+        }
+        for id in std::mem::take(&mut self.textures).free {
+            self.renderer.free_texture(&id);
+        }
+    }
+}
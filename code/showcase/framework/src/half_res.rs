@@ -0,0 +1,294 @@
+use anyhow::*;
+
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::texture::Texture;
+
+/// A color target at half the resolution of the surface along with a
+/// downsampled copy of the scene depth. Expensive screen space effects
+/// (SSAO, SSR, volumetrics) render into `color` and then get composited
+/// back onto the full resolution image with [BilateralUpsample].
+pub struct HalfResTarget {
+    pub color: Texture<'static>,
+    pub depth: Texture<'static>,
+}
+
+impl HalfResTarget {
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+    /// `width` and `height` are the full resolution dimensions
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let size = half_size(width, height);
+        let color = Texture::from_descriptor(
+            device,
+            wgpu::TextureDescriptor {
+                label: Some("HalfResTarget::color"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        );
+        let depth = Texture::from_descriptor(
+            device,
+            wgpu::TextureDescriptor {
+                label: Some("HalfResTarget::depth"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::DEPTH_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        );
+
+        Self { color, depth }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        *self = Self::new(device, width, height, self.color.desc.format);
+    }
+
+    pub fn width(&self) -> u32 {
+        self.color.desc.size.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.color.desc.size.height
+    }
+}
+
+/// Odd sizes round down, so the last full resolution row or column shares
+/// a texel with the one before it
+fn half_size(width: u32, height: u32) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: (width / 2).max(1),
+        height: (height / 2).max(1),
+        depth_or_array_layers: 1,
+    }
+}
+
+/// Downsamples the scene depth into a [HalfResTarget] and upsamples the
+/// half resolution color back to full resolution, using depth to avoid
+/// blurring across geometry edges.
+pub struct BilateralUpsample {
+    downsample_layout: wgpu::BindGroupLayout,
+    upsample_layout: wgpu::BindGroupLayout,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    downsample_bind_group: wgpu::BindGroup,
+    upsample_bind_group: wgpu::BindGroup,
+}
+
+impl BilateralUpsample {
+    /// `blend` controls how the upsampled result is composited onto the
+    /// output, eg. multiply for SSAO or additive for volumetric light.
+    pub fn new(
+        device: &wgpu::Device,
+        target: &HalfResTarget,
+        full_depth: &Texture,
+        output_format: wgpu::TextureFormat,
+        blend: Option<wgpu::BlendState>,
+    ) -> Result<Self> {
+        let downsample_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("BilateralUpsample::downsample_layout"),
+            entries: &[texture_entry(0, wgpu::TextureSampleType::Depth)],
+        });
+        let upsample_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("BilateralUpsample::upsample_layout"),
+            entries: &[
+                texture_entry(0, wgpu::TextureSampleType::Float { filterable: false }),
+                texture_entry(1, wgpu::TextureSampleType::Float { filterable: false }),
+                texture_entry(2, wgpu::TextureSampleType::Depth),
+            ],
+        });
+
+        let downsample_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("BilateralUpsample::downsample_pipeline_layout"),
+                bind_group_layouts: &[&downsample_layout],
+                push_constant_ranges: &[],
+            });
+        let shader = framework_shader("half_res.wgsl", include_str!("half_res.wgsl"));
+        let downsample_pipeline = RenderPipelineBuilder::new()
+            .layout(&downsample_pipeline_layout)
+            .vertex_shader(shader.module())
+            .fragment_shader(shader.module())
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_downsample")
+            .color_solid(HalfResTarget::DEPTH_FORMAT)
+            .build(device)?;
+
+        let upsample_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("BilateralUpsample::upsample_pipeline_layout"),
+                bind_group_layouts: &[&upsample_layout],
+                push_constant_ranges: &[],
+            });
+        let upsample_pipeline = RenderPipelineBuilder::new()
+            .layout(&upsample_pipeline_layout)
+            .vertex_shader(shader.module())
+            .fragment_shader(shader.module())
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_upsample")
+            .color_state(wgpu::ColorTargetState {
+                format: output_format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+            .build(device)?;
+
+        let (downsample_bind_group, upsample_bind_group) = create_bind_groups(
+            device,
+            &downsample_layout,
+            &upsample_layout,
+            target,
+            full_depth,
+        );
+
+        Ok(Self {
+            downsample_layout,
+            upsample_layout,
+            downsample_pipeline,
+            upsample_pipeline,
+            downsample_bind_group,
+            upsample_bind_group,
+        })
+    }
+
+    /// Call this after resizing the [HalfResTarget] or the depth texture.
+    pub fn rebind(&mut self, device: &wgpu::Device, target: &HalfResTarget, full_depth: &Texture) {
+        let (downsample_bind_group, upsample_bind_group) = create_bind_groups(
+            device,
+            &self.downsample_layout,
+            &self.upsample_layout,
+            target,
+            full_depth,
+        );
+        self.downsample_bind_group = downsample_bind_group;
+        self.upsample_bind_group = upsample_bind_group;
+    }
+
+    /// Fills `target.depth`. This needs to happen after the depth buffer
+    /// is written, but before any half resolution passes run.
+    pub fn downsample_depth(&self, encoder: &mut wgpu::CommandEncoder, target: &HalfResTarget) {
+        crate::cpu_scope!("BilateralUpsample::downsample_depth");
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("BilateralUpsample::downsample_depth"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target.depth.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.downsample_pipeline);
+        pass.set_bind_group(0, &self.downsample_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    /// Composites `target.color` onto `output` using the blend state
+    /// supplied in [BilateralUpsample::new].
+    pub fn upsample(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        crate::cpu_scope!("BilateralUpsample::upsample");
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("BilateralUpsample::upsample"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.upsample_pipeline);
+        pass.set_bind_group(0, &self.upsample_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn texture_entry(binding: u32, sample_type: wgpu::TextureSampleType) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+fn create_bind_groups(
+    device: &wgpu::Device,
+    downsample_layout: &wgpu::BindGroupLayout,
+    upsample_layout: &wgpu::BindGroupLayout,
+    target: &HalfResTarget,
+    full_depth: &Texture,
+) -> (wgpu::BindGroup, wgpu::BindGroup) {
+    let downsample = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("BilateralUpsample::downsample_bind_group"),
+        layout: downsample_layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&full_depth.view),
+        }],
+    });
+    let upsample = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("BilateralUpsample::upsample_bind_group"),
+        layout: upsample_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&target.color.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&target.depth.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&full_depth.view),
+            },
+        ],
+    });
+    (downsample, upsample)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_size_rounds_down() {
+        let size = |w, h| {
+            let size = half_size(w, h);
+            (size.width, size.height)
+        };
+        assert_eq!(size(1920, 1080), (960, 540));
+        assert_eq!(size(1921, 1081), (960, 540));
+        // Never empty, even for a minimized window
+        assert_eq!(size(1, 1), (1, 1));
+        assert_eq!(size(0, 0), (1, 1));
+    }
+}
@@ -0,0 +1,56 @@
+// How strongly depth differences reject low resolution samples. Higher
+// values keep edges crisper but can leave holes on thin geometry.
+const DEPTH_SHARPNESS: f32 = 1000.0;
+
+#include "framework/fullscreen.wgsl"
+
+@group(0) @binding(0)
+var full_depth: texture_depth_2d;
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) f32 {
+    let max_coord = vec2<i32>(textureDimensions(full_depth)) - 1;
+    let base = vec2<i32>(in.clip_position.xy) * 2;
+
+    // Keep the farthest depth so foreground objects don't bleed
+    // into the background when we upsample.
+    var depth = 0.0;
+    for (var i = 0; i < 4; i += 1) {
+        let coord = min(base + vec2<i32>(i % 2, i / 2), max_coord);
+        depth = max(depth, textureLoad(full_depth, coord, 0));
+    }
+    return depth;
+}
+
+@group(0) @binding(0)
+var low_color: texture_2d<f32>;
+@group(0) @binding(1)
+var low_depth: texture_2d<f32>;
+@group(0) @binding(2)
+var high_depth: texture_depth_2d;
+
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let depth = textureLoad(high_depth, vec2<i32>(in.clip_position.xy), 0);
+
+    let max_coord = vec2<i32>(textureDimensions(low_color)) - 1;
+    let low_pos = in.clip_position.xy * 0.5 - vec2<f32>(0.5);
+    let base = vec2<i32>(floor(low_pos));
+    let f = fract(low_pos);
+
+    // Bilinear weights scaled by how close each low resolution
+    // sample's depth is to the full resolution depth.
+    var total = vec4<f32>(0.0);
+    var weight_sum = 0.0;
+    for (var i = 0; i < 4; i += 1) {
+        let offset = vec2<i32>(i % 2, i / 2);
+        let coord = clamp(base + offset, vec2<i32>(0), max_coord);
+        let bilinear = select(1.0 - f.x, f.x, offset.x == 1) * select(1.0 - f.y, f.y, offset.y == 1);
+        let sample_depth = textureLoad(low_depth, coord, 0).r;
+        let weight = bilinear / (1.0 + abs(depth - sample_depth) * DEPTH_SHARPNESS);
+        total += textureLoad(low_color, coord, 0) * weight;
+        weight_sum += weight;
+    }
+
+    return total / max(weight_sum, 0.0001);
+}
@@ -0,0 +1,442 @@
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::msaa::MsaaTarget;
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::post::{PostContext, PostEffect, PostProcessChain};
+use crate::texture::Texture;
+use crate::Display;
+
+/// The curve [HdrPipeline] squeezes unbounded scene color into the
+/// surface's 0 to 1 with
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Tonemapper {
+    /// A filmic curve that keeps contrast in the mids and rolls highlights
+    /// off to white
+    #[default]
+    Aces,
+    /// `c / (1 + c)`, which never quite reaches white and looks flatter
+    Reinhard,
+}
+
+impl Tonemapper {
+    /// Matches the constants in hdr.wgsl
+    fn id(self) -> u32 {
+        match self {
+            Tonemapper::Aces => 0,
+            Tonemapper::Reinhard => 1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemappingUniform {
+    exposure: f32,
+    tonemapper: u32,
+    encode_srgb: u32,
+    _padding: u32,
+}
+
+/// An `Rgba16Float` target for the scene and the pass that tonemaps it
+/// onto the surface. Lighting can go past 1 without clipping, and the
+/// result comes out right on sRGB and linear surfaces alike.
+///
+/// ```ignore
+/// let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+///     color_attachments: &[Some(hdr.color_attachment(wgpu::LoadOp::Clear(wgpu::Color::BLACK)))],
+///     ..
+/// });
+/// // Draw the scene with pipelines built with `for_hdr(&hdr)`
+/// drop(pass);
+/// hdr.process(&mut encoder, &frame_view);
+/// ```
+pub struct HdrPipeline {
+    texture: Texture<'static>,
+    msaa: MsaaTarget,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    exposure: f32,
+    tonemapper: Tonemapper,
+    encode_srgb: bool,
+}
+
+impl HdrPipeline {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// Sized to the surface, with the display's sample count
+    pub fn new(display: &Display) -> Result<Self> {
+        let device = &display.device;
+        let texture = create_texture(device, &display.config);
+        let msaa = create_msaa(display);
+        let encode_srgb = !display.config.format.is_srgb();
+        let exposure = 1.0;
+        let tonemapper = Tonemapper::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("HdrPipeline::uniform_buffer"),
+            contents: bytemuck::cast_slice(&[TonemappingUniform {
+                exposure,
+                tonemapper: tonemapper.id(),
+                encode_srgb: encode_srgb as u32,
+                _padding: 0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let layout = create_layout(device);
+        let bind_group = create_bind_group(device, &layout, &texture, &uniform_buffer);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("HdrPipeline::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = framework_shader("hdr.wgsl", include_str!("hdr.wgsl"));
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(shader.module())
+            .fragment_shader(shader.module())
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .color_solid(display.config.format)
+            .build(device)?;
+
+        Ok(Self {
+            texture,
+            msaa,
+            layout,
+            bind_group,
+            pipeline,
+            uniform_buffer,
+            exposure,
+            tonemapper,
+            encode_srgb,
+        })
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        Self::FORMAT
+    }
+
+    /// Pipelines drawing into the target need this many samples, see
+    /// [RenderPipelineBuilder::for_hdr]
+    pub fn sample_count(&self) -> u32 {
+        self.msaa.sample_count()
+    }
+
+    /// The resolved scene color, for effects that read it before
+    /// [HdrPipeline::process]
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.texture.view
+    }
+
+    pub fn width(&self) -> u32 {
+        self.texture.desc.size.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.texture.desc.size.height
+    }
+
+    /// The color attachment for drawing the scene, through an MSAA target
+    /// when the display has one
+    pub fn color_attachment(
+        &self,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'_> {
+        self.msaa.color_attachment(&self.texture.view, load)
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Scales scene color before tonemapping, 1 leaves it as is. Keep it
+    /// at 1 for lights in physical units, which [crate::Exposure] has
+    /// already scaled.
+    pub fn set_exposure(&mut self, queue: &wgpu::Queue, exposure: f32) {
+        self.exposure = exposure;
+        self.write_uniform(queue);
+    }
+
+    pub fn tonemapper(&self) -> Tonemapper {
+        self.tonemapper
+    }
+
+    pub fn set_tonemapper(&mut self, queue: &wgpu::Queue, tonemapper: Tonemapper) {
+        self.tonemapper = tonemapper;
+        self.write_uniform(queue);
+    }
+
+    /// Remakes the target to match the surface. Needs calling from
+    /// [crate::Demo::resize].
+    pub fn resize(&mut self, display: &Display) {
+        self.texture = create_texture(&display.device, &display.config);
+        self.msaa = create_msaa(display);
+        self.bind_group = create_bind_group(
+            &display.device,
+            &self.layout,
+            &self.texture,
+            &self.uniform_buffer,
+        );
+    }
+
+    /// Tonemaps the scene onto `output`, normally the surface texture
+    pub fn process(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        crate::cpu_scope!("HdrPipeline::process");
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("HdrPipeline::process"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[TonemappingUniform {
+                exposure: self.exposure,
+                tonemapper: self.tonemapper.id(),
+                encode_srgb: self.encode_srgb as u32,
+                _padding: 0,
+            }]),
+        );
+    }
+}
+
+/// [HdrPipeline]'s tonemapping as a [PostEffect], so effects that want
+/// unbounded color can go before it in a [PostProcessChain] and ones that
+/// want 0 to 1 color after it
+pub struct Tonemap {
+    exposure: f32,
+    tonemapper: Tonemapper,
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+}
+
+impl Tonemap {
+    pub fn new(device: &wgpu::Device, chain: &PostProcessChain) -> Result<Self> {
+        let exposure = 1.0;
+        let tonemapper = Tonemapper::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tonemap::uniform_buffer"),
+            contents: bytemuck::cast_slice(&[TonemappingUniform {
+                exposure,
+                tonemapper: tonemapper.id(),
+                // The chain encodes on the way out if it has to
+                encode_srgb: 0,
+                _padding: 0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = create_layout(device);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = framework_shader("hdr.wgsl", include_str!("hdr.wgsl"));
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(shader.module())
+            .fragment_shader(shader.module())
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .color_solid(chain.format())
+            .build(device)?;
+        Ok(Self {
+            exposure,
+            tonemapper,
+            layout,
+            pipeline,
+            uniform_buffer,
+        })
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Scales scene color before tonemapping, 1 leaves it as is. Keep it
+    /// at 1 for lights in physical units, which [crate::Exposure] has
+    /// already scaled.
+    pub fn set_exposure(&mut self, queue: &wgpu::Queue, exposure: f32) {
+        self.exposure = exposure;
+        self.write_uniform(queue);
+    }
+
+    pub fn tonemapper(&self) -> Tonemapper {
+        self.tonemapper
+    }
+
+    pub fn set_tonemapper(&mut self, queue: &wgpu::Queue, tonemapper: Tonemapper) {
+        self.tonemapper = tonemapper;
+        self.write_uniform(queue);
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[TonemappingUniform {
+                exposure: self.exposure,
+                tonemapper: self.tonemapper.id(),
+                encode_srgb: 0,
+                _padding: 0,
+            }]),
+        );
+    }
+}
+
+impl PostEffect for Tonemap {
+    fn process(&mut self, encoder: &mut wgpu::CommandEncoder, ctx: &PostContext) {
+        crate::cpu_scope!("Tonemap::process");
+        let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tonemap::bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(ctx.input),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap::process"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: ctx.output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("HdrPipeline::layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+fn create_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Texture<'static> {
+    Texture::from_descriptor(
+        device,
+        wgpu::TextureDescriptor {
+            label: Some("HdrPipeline::texture"),
+            size: wgpu::Extent3d {
+                width: config.width.max(1),
+                height: config.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HdrPipeline::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+    )
+}
+
+/// The display's MSAA, but in the HDR format
+fn create_msaa(display: &Display) -> MsaaTarget {
+    let config = wgpu::SurfaceConfiguration {
+        format: HdrPipeline::FORMAT,
+        ..display.config.clone()
+    };
+    MsaaTarget::new(
+        &display.device,
+        display.adapter(),
+        &config,
+        display.sample_count(),
+    )
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    texture: &Texture,
+    uniform_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("HdrPipeline::bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tonemapping_shader_matches_uniform() {
+        assert_eq!(std::mem::size_of::<TonemappingUniform>(), 16);
+        assert_eq!(Tonemapper::Aces.id(), 0);
+        assert_eq!(Tonemapper::Reinhard.id(), 1);
+        crate::shader::validate_wgsl(
+            &framework_shader("hdr.wgsl", include_str!("hdr.wgsl")).source,
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+// Maps the HdrPipeline's Rgba16Float target into the 0 to 1 range of the
+// surface. Writes sRGB encoded color itself when the surface isn't an
+// sRGB format, so demos look the same whatever format they get.
+
+const TONEMAP_ACES: u32 = 0u;
+const TONEMAP_REINHARD: u32 = 1u;
+
+struct Tonemapping {
+    exposure: f32,
+    tonemapper: u32,
+    // 1 when the output needs sRGB encoding done here
+    encode_srgb: u32,
+    _padding: u32,
+}
+
+@group(0) @binding(0)
+var hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> tonemapping: Tonemapping;
+
+#include "framework/fullscreen.wgsl"
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (vec3<f32>(1.0) + color);
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr_color = textureLoad(hdr, vec2<i32>(in.clip_position.xy), 0);
+    let exposed = max(hdr_color.rgb * tonemapping.exposure, vec3<f32>(0.0));
+    var color: vec3<f32>;
+    if (tonemapping.tonemapper == TONEMAP_REINHARD) {
+        color = reinhard(exposed);
+    } else {
+        color = aces(exposed);
+    }
+    if (tonemapping.encode_srgb == 1u) {
+        color = linear_to_srgb(color);
+    }
+    return vec4<f32>(color, hdr_color.a);
+}
@@ -0,0 +1,228 @@
+use anyhow::*;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::shader::{FallbackPipeline, ShaderError};
+
+/// Watches files by checking when they were last modified. Polling a
+/// handful of shaders a few times a second is cheap, and works the same
+/// everywhere.
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    /// How long [FileWatcher::poll] waits between looking at the files
+    pub interval: Duration,
+    last_poll: Option<Instant>,
+}
+
+impl Default for FileWatcher {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            interval: Duration::from_millis(250),
+            last_poll: None,
+        }
+    }
+}
+
+impl FileWatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn watch<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        let path = path.as_ref();
+        if !self.files.iter().any(|(p, _)| p == path) {
+            self.files.push((path.to_path_buf(), modified(path)));
+        }
+        self
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|(p, _)| p.as_path())
+    }
+
+    /// Whether any file changed since the last poll. Editors often save
+    /// by deleting and rewriting, so a file that's briefly missing isn't
+    /// counted until it's back.
+    pub fn poll(&mut self) -> bool {
+        let now = Instant::now();
+        if self
+            .last_poll
+            .is_some_and(|last| now.duration_since(last) < self.interval)
+        {
+            return false;
+        }
+        self.last_poll = Some(now);
+        let mut changed = false;
+        for (path, last) in &mut self.files {
+            let current = modified(path);
+            if current.is_some() && current != *last {
+                *last = current;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The current contents of the shader files a [HotPipeline] watches
+pub struct ShaderFiles {
+    files: Vec<(PathBuf, String)>,
+}
+
+impl ShaderFiles {
+    fn read<'p>(paths: impl Iterator<Item = &'p Path>) -> Result<Self, ShaderError> {
+        paths
+            .map(|path| {
+                std::fs::read_to_string(path)
+                    .map(|source| (path.to_path_buf(), source))
+                    .map_err(|e| {
+                        let message = format!("Unable to read {}: {}", path.display(), e);
+                        ShaderError::new(message, None, None)
+                            .with_label(Some(&path.display().to_string()))
+                    })
+            })
+            .collect::<Result<_, _>>()
+            .map(|files| Self { files })
+    }
+
+    pub fn source<P: AsRef<Path>>(&self, path: P) -> Option<&str> {
+        let path = path.as_ref();
+        self.files
+            .iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, s)| s.as_str())
+    }
+
+    /// A WGSL module for [crate::RenderPipelineBuilder], labelled with
+    /// its path so errors say which file they're in. Panics if `path`
+    /// isn't one of the watched files.
+    pub fn module<'a>(&'a self, path: &'a str) -> wgpu::ShaderModuleDescriptor<'a> {
+        let source = self
+            .source(path)
+            .unwrap_or_else(|| panic!("{} isn't a watched shader", path));
+        wgpu::ShaderModuleDescriptor {
+            label: Some(path),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        }
+    }
+}
+
+type BuildPipeline<'a> =
+    Box<dyn FnMut(&wgpu::Device, &ShaderFiles) -> Result<wgpu::RenderPipeline> + 'a>;
+
+/// A render pipeline that's rebuilt when its WGSL files change, so shaders
+/// can be worked on without restarting the demo. If a change doesn't
+/// compile, the last pipeline that did stays in use and the error is
+/// logged and kept in [HotPipeline::error].
+///
+/// ```ignore
+/// let mut pipeline = HotPipeline::new(&display.device, ["src/shader.wgsl"], move |device, files| {
+///     RenderPipelineBuilder::new()
+///         .layout(&layout)
+///         .vertex_shader(files.module("src/shader.wgsl"))
+///         .fragment_shader(files.module("src/shader.wgsl"))
+///         .vertex_entry_point("vs_main")
+///         .fragment_entry_point("fs_main")
+///         .color_solid(format)
+///         .build(device)
+/// })?;
+/// // Every frame
+/// pipeline.maintain(&display.device);
+/// pass.set_pipeline(pipeline.pipeline());
+/// ```
+pub struct HotPipeline<'a> {
+    watcher: FileWatcher,
+    build: BuildPipeline<'a>,
+    pipeline: FallbackPipeline,
+}
+
+impl<'a> HotPipeline<'a> {
+    /// Fails if the pipeline doesn't build the first time, as there's
+    /// nothing to fall back to
+    pub fn new<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        paths: impl IntoIterator<Item = P>,
+        mut build: impl FnMut(&wgpu::Device, &ShaderFiles) -> Result<wgpu::RenderPipeline> + 'a,
+    ) -> Result<Self> {
+        let mut watcher = FileWatcher::new();
+        for path in paths {
+            watcher.watch(path);
+        }
+        let files = ShaderFiles::read(watcher.paths())?;
+        let pipeline = build(device, &files)?;
+        Ok(Self {
+            watcher,
+            build: Box::new(build),
+            pipeline: FallbackPipeline::new(pipeline),
+        })
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        self.pipeline.pipeline()
+    }
+
+    /// Why the last rebuild failed, if it did
+    pub fn error(&self) -> Option<&ShaderError> {
+        self.pipeline.error()
+    }
+
+    pub fn watcher_mut(&mut self) -> &mut FileWatcher {
+        &mut self.watcher
+    }
+
+    /// Rebuilds the pipeline straight away
+    pub fn reload(&mut self, device: &wgpu::Device) -> bool {
+        let result = ShaderFiles::read(self.watcher.paths())
+            .map_err(Error::from)
+            .and_then(|files| (self.build)(device, &files));
+        self.pipeline.update(result)
+    }
+
+    /// Rebuilds the pipeline if any of its files changed. Returns true if
+    /// it was replaced.
+    pub fn maintain(&mut self, device: &wgpu::Device) -> bool {
+        if !self.watcher.poll() {
+            return false;
+        }
+        let reloaded = self.reload(device);
+        if reloaded {
+            log::info!("Reloaded shaders");
+        }
+        reloaded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watcher_sees_changes() {
+        let dir = std::env::temp_dir().join(format!("framework-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shader.wgsl");
+        std::fs::write(&path, "fn a() {}").unwrap();
+
+        let mut watcher = FileWatcher::new();
+        watcher.interval = Duration::ZERO;
+        watcher.watch(&path).watch(&path);
+        assert_eq!(watcher.paths().count(), 1);
+        assert!(!watcher.poll());
+
+        // Some filesystems only keep whole seconds
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!watcher.poll());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
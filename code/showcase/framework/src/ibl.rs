@@ -0,0 +1,506 @@
+use anyhow::*;
+
+use crate::pipeline::RenderPipelineBuilder;
+
+/// WGSL for reading an [EnvironmentMap] bound to group 2. Put it after
+/// [crate::PBR_WGSL] to get `pbr_environment(surface, n, v)`.
+pub const IBL_WGSL: &str = include_str!("ibl_sample.wgsl");
+
+const BAKE_WGSL: &str = concat!(include_str!("cube_face.wgsl"), include_str!("ibl.wgsl"));
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const BRDF_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BakeParams {
+    face: u32,
+    roughness: f32,
+}
+
+/// Lighting from a cube map of the surroundings for
+/// [PBR_WGSL](crate::PBR_WGSL) materials. It has the diffuse light
+/// arriving from every direction, the environment blurred for each
+/// roughness, and a lookup table for the rest of the specular term. All
+/// three are baked once in [EnvironmentMap::new].
+///
+/// There are already four bind groups in the usual setup, so the maps go
+/// in the light's bind group after its uniform:
+///
+/// ```ignore
+/// let sky = Cubemap::load_hdr(&display.device, &display.queue, "res/sky.hdr")?;
+/// let environment = EnvironmentMap::new(&display.device, &display.queue, &sky.view, 128)?;
+///
+/// let mut entries = vec![light_uniform_entry];
+/// entries.extend_from_slice(&EnvironmentMap::layout_entries());
+/// let light_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+///     label: Some("light_layout"),
+///     entries: &entries,
+/// });
+/// let mut entries = vec![wgpu::BindGroupEntry { binding: 0, resource: light_buffer.as_entire_binding() }];
+/// entries.extend_from_slice(&environment.bind_group_entries());
+/// ```
+pub struct EnvironmentMap {
+    irradiance: wgpu::Texture,
+    irradiance_view: wgpu::TextureView,
+    prefiltered: wgpu::Texture,
+    prefiltered_view: wgpu::TextureView,
+    brdf_lut: wgpu::Texture,
+    brdf_lut_view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+}
+
+impl EnvironmentMap {
+    pub const IRRADIANCE_SIZE: u32 = 32;
+    /// Mip levels of the prefiltered map, from smooth to fully rough. The
+    /// WGSL depends on this.
+    pub const SPECULAR_MIPS: u32 = 5;
+    pub const BRDF_LUT_SIZE: u32 = 256;
+
+    /// Bakes the maps from `source`, a cube view of a filterable texture.
+    /// `specular_size` is the size of the sharpest prefiltered mip, and is
+    /// raised to fit [EnvironmentMap::SPECULAR_MIPS].
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        source: &wgpu::TextureView,
+        specular_size: u32,
+    ) -> Result<Self> {
+        let specular_size = specular_size.max(1 << (Self::SPECULAR_MIPS - 1));
+        let irradiance = cube_texture(
+            device,
+            "EnvironmentMap::irradiance",
+            Self::IRRADIANCE_SIZE,
+            1,
+        );
+        let prefiltered = cube_texture(
+            device,
+            "EnvironmentMap::prefiltered",
+            specular_size,
+            Self::SPECULAR_MIPS,
+        );
+        let brdf_lut = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("EnvironmentMap::brdf_lut"),
+            size: wgpu::Extent3d {
+                width: Self::BRDF_LUT_SIZE,
+                height: Self::BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: BRDF_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        Baker::new(device, source)?.bake(device, queue, &irradiance, &prefiltered, &brdf_lut);
+
+        let cube_view = |texture: &wgpu::Texture| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                ..Default::default()
+            })
+        };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("EnvironmentMap::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Ok(Self {
+            irradiance_view: cube_view(&irradiance),
+            irradiance,
+            prefiltered_view: cube_view(&prefiltered),
+            prefiltered,
+            brdf_lut_view: brdf_lut.create_view(&Default::default()),
+            brdf_lut,
+            sampler,
+        })
+    }
+
+    pub fn irradiance(&self) -> &wgpu::Texture {
+        &self.irradiance
+    }
+
+    pub fn prefiltered(&self) -> &wgpu::Texture {
+        &self.prefiltered
+    }
+
+    pub fn brdf_lut(&self) -> &wgpu::Texture {
+        &self.brdf_lut
+    }
+
+    /// Bindings 1 to 4 of the light's bind group layout, as [IBL_WGSL]
+    /// expects them
+    pub fn layout_entries() -> [wgpu::BindGroupLayoutEntry; 4] {
+        let texture = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        [
+            texture(1, wgpu::TextureViewDimension::Cube),
+            texture(2, wgpu::TextureViewDimension::Cube),
+            texture(3, wgpu::TextureViewDimension::D2),
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+    }
+
+    /// The resources for [EnvironmentMap::layout_entries]
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 4] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&self.irradiance_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&self.prefiltered_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&self.brdf_lut_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+        ]
+    }
+}
+
+fn cube_texture(device: &wgpu::Device, label: &str, size: u32, mips: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        },
+        mip_level_count: mips,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+/// The pipelines for [EnvironmentMap::new], which are only needed once
+struct Baker {
+    irradiance: wgpu::RenderPipeline,
+    prefilter: wgpu::RenderPipeline,
+    brdf: wgpu::RenderPipeline,
+    params: wgpu::Buffer,
+    params_stride: u32,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Baker {
+    fn new(device: &wgpu::Device, source: &wgpu::TextureView) -> Result<Self> {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("EnvironmentMap::bake_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<BakeParams>() as _
+                        ),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("EnvironmentMap::bake_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = || wgpu::ShaderModuleDescriptor {
+            label: Some("ibl.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(BAKE_WGSL.into()),
+        };
+        let pipeline = |entry_point, format| {
+            RenderPipelineBuilder::new()
+                .layout(&pipeline_layout)
+                .vertex_shader(shader())
+                .fragment_shader(shader())
+                .vertex_entry_point("vs_main")
+                .fragment_entry_point(entry_point)
+                .color_solid(format)
+                .build(device)
+        };
+        let irradiance = pipeline("fs_irradiance", FORMAT)?;
+        let prefilter = pipeline("fs_prefilter", FORMAT)?;
+        let brdf = pipeline("fs_brdf", BRDF_FORMAT)?;
+
+        // One set of params for each face baked, see bake_faces
+        let params_stride = device.limits().min_uniform_buffer_offset_alignment;
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("EnvironmentMap::bake_params"),
+            size: (params_stride * BAKE_SLOTS) as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("EnvironmentMap::bake_sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("EnvironmentMap::bake_bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &params,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<BakeParams>() as _),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        Ok(Self {
+            irradiance,
+            prefilter,
+            brdf,
+            params,
+            params_stride,
+            bind_group,
+        })
+    }
+
+    fn bake(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        irradiance: &wgpu::Texture,
+        prefiltered: &wgpu::Texture,
+        brdf_lut: &wgpu::Texture,
+    ) {
+        let mut params = vec![0u8; self.params.size() as usize];
+        let mut write = |slot: u32, face: u32, roughness: f32| {
+            let offset = (slot * self.params_stride) as usize;
+            let value = BakeParams { face, roughness };
+            let bytes = bytemuck::bytes_of(&value);
+            params[offset..offset + bytes.len()].copy_from_slice(bytes);
+            slot * self.params_stride
+        };
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("EnvironmentMap::bake"),
+        });
+        for bake in bake_faces() {
+            let offset = write(bake.slot, bake.face, bake.roughness);
+            let (texture, pipeline) = match bake.target {
+                BakeTarget::Prefiltered => (prefiltered, &self.prefilter),
+                BakeTarget::Irradiance => (irradiance, &self.irradiance),
+            };
+            let view = face_view(texture, bake.face, bake.mip);
+            self.draw(&mut encoder, pipeline, &view, offset);
+        }
+        let view = brdf_lut.create_view(&Default::default());
+        self.draw(&mut encoder, &self.brdf, &view, 0);
+
+        queue.write_buffer(&self.params, 0, &params);
+        queue.submit([encoder.finish()]);
+    }
+
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        view: &wgpu::TextureView,
+        offset: u32,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("EnvironmentMap::bake"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[offset]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+/// Params slots [bake_faces] uses
+const BAKE_SLOTS: u32 = 6 * (EnvironmentMap::SPECULAR_MIPS + 1);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum BakeTarget {
+    Prefiltered,
+    Irradiance,
+}
+
+/// One cube face drawn by [Baker::bake]
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct BakeFace {
+    target: BakeTarget,
+    face: u32,
+    mip: u32,
+    roughness: f32,
+    /// Where its [BakeParams] go in the params buffer
+    slot: u32,
+}
+
+/// Every face of each prefiltered mip, rougher with each mip, then the
+/// irradiance map's faces
+fn bake_faces() -> Vec<BakeFace> {
+    let mips = EnvironmentMap::SPECULAR_MIPS;
+    let mut faces = Vec::new();
+    for mip in 0..mips {
+        for face in 0..6 {
+            faces.push(BakeFace {
+                target: BakeTarget::Prefiltered,
+                face,
+                mip,
+                roughness: mip as f32 / (mips - 1) as f32,
+                slot: mip * 6 + face,
+            });
+        }
+    }
+    for face in 0..6 {
+        faces.push(BakeFace {
+            target: BakeTarget::Irradiance,
+            face,
+            mip: 0,
+            roughness: 0.0,
+            slot: mips * 6 + face,
+        });
+    }
+    faces
+}
+
+fn face_view(texture: &wgpu::Texture, face: u32, mip: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("EnvironmentMap::face_view"),
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        base_array_layer: face,
+        array_layer_count: Some(1),
+        base_mip_level: mip,
+        mip_level_count: Some(1),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_face_is_baked_once() {
+        let faces = bake_faces();
+        let mips = EnvironmentMap::SPECULAR_MIPS;
+        assert_eq!(faces.len() as u32, BAKE_SLOTS);
+        for (i, bake) in faces.iter().enumerate() {
+            assert_eq!(bake.slot, i as u32);
+        }
+
+        let prefiltered = faces
+            .iter()
+            .filter(|f| f.target == BakeTarget::Prefiltered)
+            .collect::<Vec<_>>();
+        for mip in 0..mips {
+            let mut sides = prefiltered
+                .iter()
+                .filter(|f| f.mip == mip)
+                .map(|f| f.face)
+                .collect::<Vec<_>>();
+            sides.sort();
+            assert_eq!(sides, [0, 1, 2, 3, 4, 5]);
+        }
+        // The shader picks the mip by roughness, from smooth to fully rough
+        assert_eq!(prefiltered.first().unwrap().roughness, 0.0);
+        assert_eq!(prefiltered.last().unwrap().roughness, 1.0);
+        assert!(prefiltered
+            .windows(2)
+            .all(|w| w[0].roughness <= w[1].roughness));
+
+        let irradiance = faces
+            .iter()
+            .filter(|f| f.target == BakeTarget::Irradiance)
+            .collect::<Vec<_>>();
+        assert_eq!(irradiance.len(), 6);
+        assert!(irradiance.iter().all(|f| f.mip == 0));
+    }
+
+    #[test]
+    fn shader_agrees_on_mip_count() {
+        let expected = format!(
+            "const ENV_SPECULAR_MIPS: f32 = {:.1};",
+            EnvironmentMap::SPECULAR_MIPS as f32
+        );
+        assert!(IBL_WGSL.contains(&expected));
+    }
+
+    #[test]
+    fn ibl_wgsl_validates() {
+        crate::shader::validate_wgsl(BAKE_WGSL).unwrap();
+        let shader = r#"
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>, @location(1) n: vec3<f32>) -> @location(0) vec4<f32> {
+    let surface = sample_material(uv);
+    let color = pbr_environment(surface, normalize(n), vec3<f32>(0.0, 0.0, 1.0)) + surface.emissive;
+    return vec4<f32>(color, 1.0);
+}
+"#;
+        let source = format!("{}{}{}", crate::PBR_WGSL, IBL_WGSL, shader);
+        crate::shader::validate_wgsl(&source).unwrap();
+    }
+}
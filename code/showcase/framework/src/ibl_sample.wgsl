@@ -0,0 +1,39 @@
+// Reads an EnvironmentMap bound to group 2, after the light at binding 0.
+// Needs the Surface and fresnel from PBR_WGSL.
+
+@group(2) @binding(1)
+var env_irradiance: texture_cube<f32>;
+@group(2) @binding(2)
+var env_prefiltered: texture_cube<f32>;
+@group(2) @binding(3)
+var env_brdf_lut: texture_2d<f32>;
+@group(2) @binding(4)
+var env_sampler: sampler;
+
+// EnvironmentMap::SPECULAR_MIPS
+const ENV_SPECULAR_MIPS: f32 = 5.0;
+
+// Like fresnel_schlick, but rough surfaces reflect less at grazing angles
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let f90 = max(vec3<f32>(1.0 - roughness), f0);
+    return f0 + (f90 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Light from the environment reflected towards the viewer. Use it in place
+// of pbr_ambient's flat ambient light, then add the surface's emission.
+fn pbr_environment(surface: Surface, n: vec3<f32>, v: vec3<f32>) -> vec3<f32> {
+    let n_dot_v = max(dot(n, v), 0.0);
+    let f0 = mix(vec3<f32>(0.04), surface.albedo.rgb, surface.metallic);
+    let f = fresnel_schlick_roughness(n_dot_v, f0, surface.roughness);
+    let kd = (1.0 - f) * (1.0 - surface.metallic);
+
+    let irradiance = textureSampleLevel(env_irradiance, env_sampler, n, 0.0).rgb;
+    let diffuse = kd * irradiance * surface.albedo.rgb;
+
+    let r = reflect(-v, n);
+    let level = surface.roughness * (ENV_SPECULAR_MIPS - 1.0);
+    let prefiltered = textureSampleLevel(env_prefiltered, env_sampler, r, level).rgb;
+    let brdf = textureSampleLevel(env_brdf_lut, env_sampler, vec2<f32>(n_dot_v, surface.roughness), 0.0).rg;
+    let specular = prefiltered * (f * brdf.x + brdf.y);
+    return (diffuse + specular) * surface.occlusion;
+}
@@ -0,0 +1,639 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, MouseButton, MouseScrollDelta};
+use winit::keyboard::KeyCode;
+
+/// A message from a MIDI controller. Channels are 0-15, everything else
+/// is 0-127 like on the wire.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MidiEvent {
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        note: u8,
+    },
+    /// Knobs, faders and most pads that aren't notes
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    /// 14 bit value centered on 8192
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+}
+
+impl MidiEvent {
+    /// Decodes a raw MIDI message. Messages we don't care about (clock,
+    /// sysex, aftertouch etc.) return `None`.
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        let (&status, data) = message.split_first()?;
+        let channel = status & 0x0f;
+        let data0 = *data.first()? & 0x7f;
+        let data1 = data.get(1).map(|d| d & 0x7f);
+        match status & 0xf0 {
+            // A note on with 0 velocity is how a lot of devices send note off
+            0x90 if data1? > 0 => Some(MidiEvent::NoteOn {
+                channel,
+                note: data0,
+                velocity: data1?,
+            }),
+            0x80 | 0x90 => Some(MidiEvent::NoteOff {
+                channel,
+                note: data0,
+            }),
+            0xb0 => Some(MidiEvent::ControlChange {
+                channel,
+                controller: data0,
+                value: data1?,
+            }),
+            0xe0 => Some(MidiEvent::PitchBend {
+                channel,
+                value: data0 as u16 | (data1? as u16) << 7,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A button on a gamepad, named by where it is on the pad rather than
+/// its label, so `South` is A on an Xbox pad and Cross on a PlayStation
+/// one
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    /// Triggers also report how far they're pulled, see
+    /// [Input::gamepad_button]
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    /// Pressing the stick in
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// A stick on a gamepad. Values go from -1 to 1, with up and right
+/// positive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+}
+
+/// Something that happened on a gamepad. Events from every connected pad
+/// are treated as coming from one.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GamepadEvent {
+    ButtonPressed(GamepadButton),
+    ButtonReleased(GamepadButton),
+    /// How far an analog button, like a trigger, is pressed from 0 to 1
+    ButtonChanged(GamepadButton, f32),
+    AxisChanged(GamepadAxis, f32),
+}
+
+#[cfg(feature = "gamepad")]
+impl GamepadEvent {
+    /// Converts an event from gilrs. Connections and buttons we don't
+    /// have a name for return `None`.
+    fn from_gilrs(event: gilrs::EventType) -> Option<Self> {
+        use gilrs::EventType;
+
+        match event {
+            EventType::ButtonPressed(button, _) => {
+                Some(GamepadEvent::ButtonPressed(gilrs_button(button)?))
+            }
+            EventType::ButtonReleased(button, _) => {
+                Some(GamepadEvent::ButtonReleased(gilrs_button(button)?))
+            }
+            EventType::ButtonChanged(button, value, _) => {
+                Some(GamepadEvent::ButtonChanged(gilrs_button(button)?, value))
+            }
+            EventType::AxisChanged(axis, value, _) => {
+                let axis = match axis {
+                    gilrs::Axis::LeftStickX => GamepadAxis::LeftStickX,
+                    gilrs::Axis::LeftStickY => GamepadAxis::LeftStickY,
+                    gilrs::Axis::RightStickX => GamepadAxis::RightStickX,
+                    gilrs::Axis::RightStickY => GamepadAxis::RightStickY,
+                    _ => return None,
+                };
+                Some(GamepadEvent::AxisChanged(axis, value))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn gilrs_button(button: gilrs::Button) -> Option<GamepadButton> {
+    use gilrs::Button;
+
+    Some(match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::North => GamepadButton::North,
+        Button::West => GamepadButton::West,
+        // gilrs calls the bumpers triggers and the triggers triggers 2
+        Button::LeftTrigger => GamepadButton::LeftBumper,
+        Button::RightTrigger => GamepadButton::RightBumper,
+        Button::LeftTrigger2 => GamepadButton::LeftTrigger,
+        Button::RightTrigger2 => GamepadButton::RightTrigger,
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftStick,
+        Button::RightThumb => GamepadButton::RightStick,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+        _ => return None,
+    })
+}
+
+/// Collects keyboard, mouse, MIDI and gamepad input so demos can query it from
+/// anywhere instead of tracking it in `process_keyboard`.
+///
+/// "Pressed" and "released" only last for the frame the event happened
+/// on, "held" lasts until the key is let go.
+#[derive(Default)]
+pub struct Input {
+    keys_held: HashSet<KeyCode>,
+    keys_pressed: HashSet<KeyCode>,
+    keys_released: HashSet<KeyCode>,
+    buttons_held: HashSet<MouseButton>,
+    buttons_pressed: HashSet<MouseButton>,
+    cursor_position: Option<(f64, f64)>,
+    mouse_delta: (f64, f64),
+    /// Multiplies [Input::mouse_delta]. The framework keeps it in sync
+    /// with [crate::Settings::mouse_sensitivity].
+    pub mouse_sensitivity: f64,
+    scale_factor: f64,
+    scroll_delta: f32,
+    midi_events: Vec<MidiEvent>,
+    midi_controls: HashMap<(u8, u8), u8>,
+    midi_notes: HashMap<(u8, u8), u8>,
+    #[cfg(feature = "midi")]
+    midi: Option<MidiConnection>,
+    gamepad_events: Vec<GamepadEvent>,
+    gamepad_buttons: HashMap<GamepadButton, f32>,
+    gamepad_buttons_pressed: HashSet<GamepadButton>,
+    gamepad_axes: HashMap<GamepadAxis, f32>,
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<gilrs::Gilrs>,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Self {
+            mouse_sensitivity: 1.0,
+            scale_factor: 1.0,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_key_held(&self, key: KeyCode) -> bool {
+        self.keys_held.contains(&key)
+    }
+
+    pub fn is_key_pressed(&self, key: KeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn is_key_released(&self, key: KeyCode) -> bool {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn is_button_held(&self, button: MouseButton) -> bool {
+        self.buttons_held.contains(&button)
+    }
+
+    pub fn is_button_pressed(&self, button: MouseButton) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    /// Keys that went down this frame
+    pub fn keys_pressed(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        self.keys_pressed.iter().copied()
+    }
+
+    pub fn buttons_pressed(&self) -> impl Iterator<Item = MouseButton> + '_ {
+        self.buttons_pressed.iter().copied()
+    }
+
+    pub fn keys_held(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        self.keys_held.iter().copied()
+    }
+
+    pub fn buttons_held(&self) -> impl Iterator<Item = MouseButton> + '_ {
+        self.buttons_held.iter().copied()
+    }
+
+    /// In physical pixels. `None` if the cursor isn't over the window.
+    pub fn cursor_position(&self) -> Option<(f64, f64)> {
+        self.cursor_position
+    }
+
+    /// Mouse motion since the last frame, scaled by the sensitivity and
+    /// evened out across display scales. Use this for mouse look.
+    pub fn mouse_delta(&self) -> (f64, f64) {
+        self.normalize_mouse_motion(self.mouse_delta.0, self.mouse_delta.1)
+    }
+
+    /// Mouse motion since the last frame, as the OS reported it
+    pub fn raw_mouse_delta(&self) -> (f64, f64) {
+        self.mouse_delta
+    }
+
+    /// Scales raw mouse motion the way [Input::mouse_delta] does. Some
+    /// platforms report motion in physical pixels, so dividing by the
+    /// window's scale factor keeps look speed about the same on high DPI
+    /// displays.
+    pub fn normalize_mouse_motion(&self, dx: f64, dy: f64) -> (f64, f64) {
+        let scale = self.mouse_sensitivity / self.scale_factor.max(f64::EPSILON);
+        (dx * scale, dy * scale)
+    }
+
+    /// Called by the framework when the window's scale factor changes
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.scale_factor = scale_factor;
+    }
+
+    /// Lines scrolled since the last frame
+    pub fn scroll_delta(&self) -> f32 {
+        self.scroll_delta
+    }
+
+    /// MIDI messages that arrived since the last frame, in order
+    pub fn midi_events(&self) -> &[MidiEvent] {
+        &self.midi_events
+    }
+
+    /// The last value of a knob or fader, mapped to 0-1. Controls that
+    /// haven't been touched yet return `None`.
+    pub fn midi_control(&self, channel: u8, controller: u8) -> Option<f32> {
+        self.midi_controls
+            .get(&(channel, controller))
+            .map(|v| *v as f32 / 127.0)
+    }
+
+    /// The velocity of a note that's currently held, mapped to 0-1
+    pub fn midi_note(&self, channel: u8, note: u8) -> Option<f32> {
+        self.midi_notes
+            .get(&(channel, note))
+            .map(|v| *v as f32 / 127.0)
+    }
+
+    /// Gamepad events that arrived since the last frame, in order
+    pub fn gamepad_events(&self) -> &[GamepadEvent] {
+        &self.gamepad_events
+    }
+
+    /// How far a button is pressed from 0 to 1. Digital buttons are
+    /// either.
+    pub fn gamepad_button(&self, button: GamepadButton) -> f32 {
+        self.gamepad_buttons.get(&button).copied().unwrap_or(0.0)
+    }
+
+    pub fn is_gamepad_button_held(&self, button: GamepadButton) -> bool {
+        self.gamepad_button(button) > 0.0
+    }
+
+    pub fn is_gamepad_button_pressed(&self, button: GamepadButton) -> bool {
+        self.gamepad_buttons_pressed.contains(&button)
+    }
+
+    pub fn gamepad_buttons_pressed(&self) -> impl Iterator<Item = GamepadButton> + '_ {
+        self.gamepad_buttons_pressed.iter().copied()
+    }
+
+    /// Where a stick is from -1 to 1, 0 if it hasn't moved yet
+    pub fn gamepad_axis(&self, axis: GamepadAxis) -> f32 {
+        self.gamepad_axes.get(&axis).copied().unwrap_or(0.0)
+    }
+
+    pub fn process_key(&mut self, key: KeyCode, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                // Ignore key repeats
+                if self.keys_held.insert(key) {
+                    self.keys_pressed.insert(key);
+                }
+            }
+            ElementState::Released => {
+                self.keys_held.remove(&key);
+                self.keys_released.insert(key);
+            }
+        }
+    }
+
+    pub fn process_mouse_button(&mut self, button: MouseButton, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                self.buttons_held.insert(button);
+                self.buttons_pressed.insert(button);
+            }
+            ElementState::Released => {
+                self.buttons_held.remove(&button);
+            }
+        }
+    }
+
+    pub fn process_cursor(&mut self, position: Option<(f64, f64)>) {
+        self.cursor_position = position;
+    }
+
+    pub fn process_mouse_motion(&mut self, dx: f64, dy: f64) {
+        self.mouse_delta.0 += dx;
+        self.mouse_delta.1 += dy;
+    }
+
+    pub fn process_scroll(&mut self, delta: MouseScrollDelta) {
+        self.scroll_delta += scroll_lines(delta);
+    }
+
+    pub fn process_midi(&mut self, event: MidiEvent) {
+        match event {
+            MidiEvent::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => {
+                self.midi_notes.insert((channel, note), velocity);
+            }
+            MidiEvent::NoteOff { channel, note } => {
+                self.midi_notes.remove(&(channel, note));
+            }
+            MidiEvent::ControlChange {
+                channel,
+                controller,
+                value,
+            } => {
+                self.midi_controls.insert((channel, controller), value);
+            }
+            MidiEvent::PitchBend { .. } => {}
+        }
+        self.midi_events.push(event);
+    }
+
+    pub fn process_gamepad(&mut self, event: GamepadEvent) {
+        match event {
+            GamepadEvent::ButtonPressed(button) => {
+                self.gamepad_buttons.insert(button, 1.0);
+                self.gamepad_buttons_pressed.insert(button);
+            }
+            GamepadEvent::ButtonReleased(button) => {
+                self.gamepad_buttons.remove(&button);
+            }
+            GamepadEvent::ButtonChanged(button, value) => {
+                if value > 0.0 {
+                    self.gamepad_buttons.insert(button, value.min(1.0));
+                } else {
+                    self.gamepad_buttons.remove(&button);
+                }
+            }
+            GamepadEvent::AxisChanged(axis, value) => {
+                self.gamepad_axes.insert(axis, value.clamp(-1.0, 1.0));
+            }
+        }
+        self.gamepad_events.push(event);
+    }
+
+    /// Starts listening to gamepads, which are then read in
+    /// [Input::begin_frame]. Pads plugged in later are picked up too.
+    #[cfg(feature = "gamepad")]
+    pub fn connect_gamepads(&mut self) -> anyhow::Result<()> {
+        let gilrs =
+            gilrs::Gilrs::new().map_err(|e| anyhow::anyhow!("Unable to open gamepads: {}", e))?;
+        for (_, gamepad) in gilrs.gamepads() {
+            log::info!("Found gamepad {}", gamepad.name());
+        }
+        self.gilrs = Some(gilrs);
+        Ok(())
+    }
+
+    /// Connects to the first MIDI input whose name contains `name`, or the
+    /// first one available if `name` is `None`. Returns the name of the
+    /// port that was opened.
+    #[cfg(feature = "midi")]
+    pub fn connect_midi(&mut self, name: Option<&str>) -> anyhow::Result<String> {
+        let connection = MidiConnection::open(name)?;
+        let port_name = connection.port_name.clone();
+        self.midi = Some(connection);
+        Ok(port_name)
+    }
+
+    /// Called by the framework before the demo renders
+    pub fn begin_frame(&mut self) {
+        #[cfg(feature = "midi")]
+        {
+            let events = match &self.midi {
+                Some(midi) => midi.receiver.try_iter().collect::<Vec<_>>(),
+                None => Vec::new(),
+            };
+            for event in events {
+                self.process_midi(event);
+            }
+        }
+        #[cfg(feature = "gamepad")]
+        {
+            let mut events = Vec::new();
+            if let Some(gilrs) = &mut self.gilrs {
+                while let Some(gilrs::Event { event, .. }) = gilrs.next_event() {
+                    events.extend(GamepadEvent::from_gilrs(event));
+                }
+            }
+            for event in events {
+                self.process_gamepad(event);
+            }
+        }
+    }
+
+    /// Called by the framework after the demo renders
+    pub fn end_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.buttons_pressed.clear();
+        self.mouse_delta = (0.0, 0.0);
+        self.scroll_delta = 0.0;
+        self.midi_events.clear();
+        self.gamepad_events.clear();
+        self.gamepad_buttons_pressed.clear();
+    }
+}
+
+pub(crate) fn scroll_lines(delta: MouseScrollDelta) -> f32 {
+    match delta {
+        MouseScrollDelta::LineDelta(_, y) => y,
+        // Roughly how many pixels a line is
+        MouseScrollDelta::PixelDelta(p) => p.y as f32 / 20.0,
+    }
+}
+
+#[cfg(feature = "midi")]
+struct MidiConnection {
+    // Messages stop arriving when this is dropped
+    _connection: midir::MidiInputConnection<()>,
+    receiver: std::sync::mpsc::Receiver<MidiEvent>,
+    port_name: String,
+}
+
+#[cfg(feature = "midi")]
+impl MidiConnection {
+    fn open(name: Option<&str>) -> anyhow::Result<Self> {
+        use anyhow::Context;
+
+        let midi_in = midir::MidiInput::new(env!("CARGO_PKG_NAME"))?;
+        let ports = midi_in.ports();
+        let port = ports
+            .iter()
+            .find(|port| match (name, midi_in.port_name(port)) {
+                (Some(name), Ok(port_name)) => port_name.contains(name),
+                (None, Ok(_)) => true,
+                _ => false,
+            })
+            .context("No matching MIDI input found")?;
+        let port_name = midi_in.port_name(port)?;
+        log::info!("Connecting to MIDI input {}", port_name);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let connection = midi_in
+            .connect(
+                port,
+                "framework-input",
+                move |_, message, _| {
+                    if let Some(event) = MidiEvent::parse(message) {
+                        let _ = sender.send(event);
+                    }
+                },
+                (),
+            )
+            .map_err(|e| anyhow::anyhow!("Unable to connect to {}: {}", port_name, e))?;
+
+        Ok(Self {
+            _connection: connection,
+            receiver,
+            port_name,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mouse_motion_is_normalized() {
+        let mut input = Input::new();
+        input.process_mouse_motion(3.0, -1.0);
+        input.process_mouse_motion(1.0, -1.0);
+        assert_eq!(input.mouse_delta(), (4.0, -2.0));
+
+        input.set_scale_factor(2.0);
+        input.mouse_sensitivity = 0.5;
+        assert_eq!(input.mouse_delta(), (1.0, -0.5));
+        assert_eq!(input.raw_mouse_delta(), (4.0, -2.0));
+    }
+
+    #[test]
+    fn parses_midi_messages() {
+        assert_eq!(
+            MidiEvent::parse(&[0x92, 60, 100]),
+            Some(MidiEvent::NoteOn {
+                channel: 2,
+                note: 60,
+                velocity: 100
+            })
+        );
+        assert_eq!(
+            MidiEvent::parse(&[0x90, 60, 0]),
+            Some(MidiEvent::NoteOff {
+                channel: 0,
+                note: 60
+            })
+        );
+        assert_eq!(
+            MidiEvent::parse(&[0xb1, 7, 127]),
+            Some(MidiEvent::ControlChange {
+                channel: 1,
+                controller: 7,
+                value: 127
+            })
+        );
+        assert_eq!(
+            MidiEvent::parse(&[0xe0, 0, 64]),
+            Some(MidiEvent::PitchBend {
+                channel: 0,
+                value: 8192
+            })
+        );
+        // Timing clock
+        assert_eq!(MidiEvent::parse(&[0xf8]), None);
+    }
+
+    #[test]
+    fn pressed_only_lasts_a_frame() {
+        let mut input = Input::new();
+        input.process_key(KeyCode::KeyW, ElementState::Pressed);
+        assert!(input.is_key_pressed(KeyCode::KeyW));
+        input.end_frame();
+
+        // Key repeat
+        input.process_key(KeyCode::KeyW, ElementState::Pressed);
+        assert!(!input.is_key_pressed(KeyCode::KeyW));
+        assert!(input.is_key_held(KeyCode::KeyW));
+
+        input.process_midi(MidiEvent::ControlChange {
+            channel: 0,
+            controller: 1,
+            value: 127,
+        });
+        input.end_frame();
+        assert!(input.midi_events().is_empty());
+        assert_eq!(input.midi_control(0, 1), Some(1.0));
+    }
+
+    #[test]
+    fn gamepad_state_follows_events() {
+        let mut input = Input::new();
+        input.process_gamepad(GamepadEvent::ButtonPressed(GamepadButton::South));
+        input.process_gamepad(GamepadEvent::ButtonChanged(
+            GamepadButton::RightTrigger,
+            0.25,
+        ));
+        input.process_gamepad(GamepadEvent::AxisChanged(GamepadAxis::LeftStickY, -2.0));
+        assert!(input.is_gamepad_button_pressed(GamepadButton::South));
+        assert_eq!(input.gamepad_button(GamepadButton::RightTrigger), 0.25);
+        assert_eq!(input.gamepad_axis(GamepadAxis::LeftStickY), -1.0);
+        assert_eq!(input.gamepad_axis(GamepadAxis::RightStickX), 0.0);
+        input.end_frame();
+
+        assert!(input.gamepad_events().is_empty());
+        assert!(!input.is_gamepad_button_pressed(GamepadButton::South));
+        assert!(input.is_gamepad_button_held(GamepadButton::South));
+        input.process_gamepad(GamepadEvent::ButtonReleased(GamepadButton::South));
+        input.process_gamepad(GamepadEvent::ButtonChanged(
+            GamepadButton::RightTrigger,
+            0.0,
+        ));
+        assert!(!input.is_gamepad_button_held(GamepadButton::South));
+        assert!(!input.is_gamepad_button_held(GamepadButton::RightTrigger));
+    }
+}
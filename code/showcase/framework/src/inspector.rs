@@ -0,0 +1,670 @@
+//! Debug panels for tweaking framework resources at runtime. These are
+//! plain egui functions, so they can be shown from any egui context.
+
+use cgmath::*;
+
+use crate::actions::{ActionMap, Binding};
+use crate::assets::Assets;
+use crate::cpu_profiler::{CpuScopeTiming, CpuSpan};
+use crate::debug_inset::DebugInset;
+use crate::gbuffer_debug::GBufferDebug;
+use crate::gpu_profiler::GpuScopeTiming;
+use crate::input::Input;
+use crate::model::Model;
+use crate::reflection::UniformScalar;
+use crate::scene::{NodeId, Scene};
+use crate::shader::ShaderError;
+use crate::shader_canvas::ShaderCanvas;
+use crate::stats::FrameStats;
+use crate::texture::Texture;
+
+/// Lists a model's materials and lets you swap their textures live.
+#[derive(Default)]
+pub struct MaterialInspector {
+    selected: usize,
+    texture_path: String,
+    error: Option<String>,
+}
+
+impl MaterialInspector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// `layout` has to be the layout the model's materials were created
+    /// with, and `assets` the cache their textures are in.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+        model: &mut Model,
+    ) {
+        egui::Window::new("Materials").show(ctx, |ui| {
+            if model.materials.is_empty() {
+                ui.label("Model has no materials");
+                return;
+            }
+            self.selected = self.selected.min(model.materials.len() - 1);
+
+            egui::ComboBox::from_label("Material")
+                .selected_text(&model.materials[self.selected].name)
+                .show_ui(ui, |ui| {
+                    for (i, material) in model.materials.iter().enumerate() {
+                        ui.selectable_value(&mut self.selected, i, &material.name);
+                    }
+                });
+
+            let material = &mut model.materials[self.selected];
+            let meshes = model
+                .meshes
+                .iter()
+                .filter(|m| m.material == self.selected)
+                .count();
+            ui.label(format!("Used by {} mesh(es)", meshes));
+            texture_label(ui, "Albedo", assets.get(&material.textures.albedo));
+            texture_label(ui, "Normal", assets.get(&material.textures.normal));
+            texture_label(
+                ui,
+                "Metallic/roughness",
+                assets.get(&material.textures.metallic_roughness),
+            );
+            texture_label(ui, "Occlusion", assets.get(&material.textures.occlusion));
+            texture_label(ui, "Emissive", assets.get(&material.textures.emissive));
+
+            let factors = &mut material.factors;
+            let mut changed = false;
+            ui.horizontal(|ui| {
+                ui.label("Albedo");
+                changed |= ui
+                    .color_edit_button_rgba_unmultiplied(&mut factors.albedo)
+                    .changed();
+                ui.label("Emissive");
+                changed |= ui.color_edit_button_rgb(&mut factors.emissive).changed();
+            });
+            for (value, name) in [
+                (&mut factors.metallic, "Metallic"),
+                (&mut factors.roughness, "Roughness"),
+                (&mut factors.occlusion_strength, "Occlusion"),
+            ] {
+                changed |= ui
+                    .add(egui::Slider::new(value, 0.0..=1.0).text(name))
+                    .changed();
+            }
+            if changed {
+                material.update_factors(queue);
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Texture path");
+                ui.text_edit_singleline(&mut self.texture_path);
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Load albedo").clicked() {
+                    self.error =
+                        match assets.try_load_texture(device, queue, &self.texture_path, false) {
+                            Ok(texture) => {
+                                material.set_albedo_texture(device, assets, texture, layout);
+                                None
+                            }
+                            Err(e) => Some(e.to_string()),
+                        };
+                }
+                if ui.button("Load normal").clicked() {
+                    self.error =
+                        match assets.try_load_texture(device, queue, &self.texture_path, true) {
+                            Ok(texture) => {
+                                material.set_normal_texture(device, assets, texture, layout);
+                                None
+                            }
+                            Err(e) => Some(e.to_string()),
+                        };
+                }
+            });
+            if let Some(error) = &self.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+        });
+    }
+}
+
+/// Shows a [Scene] as a tree. Clicking a node selects it and exposes its
+/// name, parent and transform for editing. Demos that support mouse
+/// picking can call [SceneInspector::select] to keep the two in sync.
+#[derive(Default)]
+pub struct SceneInspector {
+    selected: Option<NodeId>,
+    error: Option<String>,
+}
+
+impl SceneInspector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn selected(&self) -> Option<NodeId> {
+        self.selected
+    }
+
+    pub fn select(&mut self, id: Option<NodeId>) {
+        self.selected = id;
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, scene: &mut Scene) {
+        egui::Window::new("Scene").show(ctx, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(300.0)
+                .show(ui, |ui| {
+                    for &root in scene.roots() {
+                        self.node_tree(ui, scene, root);
+                    }
+                });
+
+            if let Some(id) = self.selected {
+                ui.separator();
+                self.node_editor(ui, scene, id);
+            }
+        });
+    }
+
+    fn node_tree(&mut self, ui: &mut egui::Ui, scene: &Scene, id: NodeId) {
+        let node = scene.node(id);
+        if ui
+            .selectable_label(self.selected == Some(id), &node.name)
+            .clicked()
+        {
+            self.selected = Some(id);
+            self.error = None;
+        }
+        if !node.children().is_empty() {
+            ui.indent(id, |ui| {
+                for &child in node.children() {
+                    self.node_tree(ui, scene, child);
+                }
+            });
+        }
+    }
+
+    fn node_editor(&mut self, ui: &mut egui::Ui, scene: &mut Scene, id: NodeId) {
+        // Nodes that aren't in this node's subtree are valid parents
+        let candidates = scene
+            .ids()
+            .filter(|other| !scene.is_ancestor(id, *other))
+            .map(|other| (other, scene.node(other).name.clone()))
+            .collect::<Vec<_>>();
+        let parent = scene.node(id).parent();
+        let mut new_parent = parent;
+        let parent_name = |p: Option<NodeId>| match p {
+            Some(p) => scene.node(p).name.clone(),
+            None => String::from("(root)"),
+        };
+        egui::ComboBox::from_label("Parent")
+            .selected_text(parent_name(parent))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut new_parent, None, "(root)");
+                for (other, name) in &candidates {
+                    ui.selectable_value(&mut new_parent, Some(*other), name);
+                }
+            });
+        if new_parent != parent {
+            self.error = scene
+                .set_parent(id, new_parent)
+                .err()
+                .map(|e| e.to_string());
+        }
+
+        let node = scene.node_mut(id);
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut node.name);
+        });
+
+        let transform = &mut node.transform;
+        ui.horizontal(|ui| {
+            ui.label("Translation");
+            ui.add(egui::DragValue::new(&mut transform.translation.x).speed(0.1));
+            ui.add(egui::DragValue::new(&mut transform.translation.y).speed(0.1));
+            ui.add(egui::DragValue::new(&mut transform.translation.z).speed(0.1));
+        });
+
+        // Quaternions aren't fun to edit by hand, so we use euler angles
+        let euler = Euler::from(transform.rotation);
+        let mut degrees = [
+            Deg::from(euler.x).0,
+            Deg::from(euler.y).0,
+            Deg::from(euler.z).0,
+        ];
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Rotation");
+            for d in degrees.iter_mut() {
+                changed |= ui
+                    .add(egui::DragValue::new(d).speed(1.0).suffix("°"))
+                    .changed();
+            }
+        });
+        if changed {
+            transform.rotation = Quaternion::from(Euler::new(
+                Deg(degrees[0]),
+                Deg(degrees[1]),
+                Deg(degrees[2]),
+            ));
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Scale");
+            ui.add(egui::DragValue::new(&mut transform.scale.x).speed(0.01));
+            ui.add(egui::DragValue::new(&mut transform.scale.y).speed(0.01));
+            ui.add(egui::DragValue::new(&mut transform.scale.z).speed(0.01));
+        });
+
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    }
+}
+
+/// A text editor bound to a [ShaderCanvas]'s fragment shader. The shader
+/// is recompiled on every change, and if it fails the canvas keeps
+/// drawing with the last version that worked.
+pub struct ShaderEditor {
+    source: String,
+    original: String,
+    error: Option<ShaderError>,
+}
+
+impl ShaderEditor {
+    /// The canvas needs to have been built from WGSL source
+    pub fn new(canvas: &ShaderCanvas) -> Self {
+        let source = canvas.fragment_source().unwrap_or_default().to_string();
+        Self {
+            original: source.clone(),
+            source,
+            error: None,
+        }
+    }
+
+    pub fn error(&self) -> Option<&ShaderError> {
+        self.error.as_ref()
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, device: &wgpu::Device, canvas: &mut ShaderCanvas) {
+        egui::Window::new("Shader").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Revert").clicked() {
+                    self.source = self.original.clone();
+                    self.compile(device, canvas);
+                }
+                match &self.error {
+                    Some(e) => match e.line {
+                        Some(line) => {
+                            ui.colored_label(egui::Color32::RED, format!("Error on line {}", line))
+                        }
+                        None => ui.colored_label(egui::Color32::RED, "Error"),
+                    },
+                    None => ui.label("Ok"),
+                };
+            });
+
+            let error_line = self.error.as_ref().and_then(|e| e.line);
+            let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+                let mut job = egui::text::LayoutJob::default();
+                for (i, line) in text.split_inclusive('\n').enumerate() {
+                    let mut format = egui::TextFormat {
+                        font_id: egui::FontId::monospace(12.0),
+                        color: ui.visuals().text_color(),
+                        ..Default::default()
+                    };
+                    if error_line == Some(i as u32 + 1) {
+                        format.background = egui::Color32::from_rgb(96, 24, 24);
+                    }
+                    job.append(line, 0.0, format);
+                }
+                job.wrap.max_width = wrap_width;
+                ui.fonts(|f| f.layout_job(job))
+            };
+
+            let response = egui::ScrollArea::vertical()
+                .max_height(400.0)
+                .show(ui, |ui| {
+                    ui.add(
+                        egui::TextEdit::multiline(&mut self.source)
+                            .code_editor()
+                            .desired_rows(20)
+                            .desired_width(f32::INFINITY)
+                            .layouter(&mut layouter),
+                    )
+                })
+                .inner;
+            if response.changed() {
+                self.compile(device, canvas);
+            }
+
+            if let Some(e) = &self.error {
+                ui.separator();
+                ui.label(
+                    egui::RichText::new(&e.message)
+                        .monospace()
+                        .color(egui::Color32::RED),
+                );
+            }
+        });
+    }
+
+    fn compile(&mut self, device: &wgpu::Device, canvas: &mut ShaderCanvas) {
+        self.error = canvas.set_fragment_source(device, &self.source).err();
+    }
+}
+
+/// Exposes the custom uniforms of a [ShaderCanvas]'s fragment shader.
+/// Float vectors with "color" in their name get a color picker, everything
+/// else gets a drag value per component.
+pub fn canvas_uniforms(ctx: &egui::Context, canvas: &mut ShaderCanvas) {
+    egui::Window::new("Uniforms").show(ctx, |ui| {
+        let uniforms = match canvas.uniforms_mut() {
+            Some(uniforms) => uniforms,
+            None => {
+                ui.label("Shader has no uniforms at @group(1) @binding(0)");
+                return;
+            }
+        };
+        let fields = uniforms.fields().to_vec();
+        egui::Grid::new("canvas_uniforms").show(ui, |ui| {
+            for (i, field) in fields.iter().enumerate() {
+                ui.label(&field.name);
+                ui.horizontal(|ui| match field.scalar {
+                    UniformScalar::Float => {
+                        let values = uniforms.floats_mut(i).unwrap();
+                        let is_color = field.name.to_lowercase().contains("color");
+                        match values {
+                            [r, g, b] if is_color => {
+                                let mut rgb = [*r, *g, *b];
+                                ui.color_edit_button_rgb(&mut rgb);
+                                [*r, *g, *b] = rgb;
+                            }
+                            [r, g, b, a] if is_color => {
+                                let mut rgba = [*r, *g, *b, *a];
+                                ui.color_edit_button_rgba_unmultiplied(&mut rgba);
+                                [*r, *g, *b, *a] = rgba;
+                            }
+                            values => {
+                                for v in values {
+                                    ui.add(egui::DragValue::new(v).speed(0.01));
+                                }
+                            }
+                        }
+                    }
+                    UniformScalar::Sint => {
+                        for v in uniforms.sints_mut(i).unwrap() {
+                            ui.add(egui::DragValue::new(v));
+                        }
+                    }
+                    UniformScalar::Uint => {
+                        for v in uniforms.uints_mut(i).unwrap() {
+                            ui.add(egui::DragValue::new(v));
+                        }
+                    }
+                });
+                ui.end_row();
+            }
+        });
+    });
+}
+
+/// Draws a shader compile error along the bottom of the screen, with the
+/// offending source lines underneath the message.
+pub fn shader_error_overlay(ctx: &egui::Context, error: &ShaderError) {
+    egui::TopBottomPanel::bottom("shader_error_overlay")
+        .frame(
+            egui::Frame::default()
+                .fill(egui::Color32::from_black_alpha(220))
+                .inner_margin(8.0),
+        )
+        .show(ctx, |ui| {
+            ui.label(
+                egui::RichText::new(format!("Shader error in {}", error.summary()))
+                    .strong()
+                    .color(egui::Color32::RED),
+            );
+            let context = error.source_context();
+            let text = if context.is_empty() {
+                &error.message
+            } else {
+                &context
+            };
+            ui.label(
+                egui::RichText::new(text)
+                    .monospace()
+                    .color(egui::Color32::LIGHT_GRAY),
+            );
+            ui.label("The last working shader will be used until this is fixed");
+        });
+}
+
+/// Lists the [crate::cpu_scope!] timings from the last frame, indented
+/// by nesting depth. Pass [crate::cpu_frame_timings].
+pub fn cpu_timings(ctx: &egui::Context, timings: &[CpuScopeTiming]) {
+    egui::Window::new("CPU").show(ctx, |ui| {
+        if timings.is_empty() {
+            ui.label("No scopes recorded");
+            return;
+        }
+        egui::Grid::new("cpu_timings").striped(true).show(ui, |ui| {
+            for timing in timings {
+                ui.label(format!("{}{}", "  ".repeat(timing.depth), timing.name));
+                ui.label(format!("{:.2} ms", timing.duration.as_secs_f64() * 1000.0));
+                if timing.calls > 1 {
+                    ui.label(format!("x{}", timing.calls));
+                }
+                ui.end_row();
+            }
+        });
+    });
+}
+
+/// Lists a [crate::GpuProfiler]'s scopes, indented by nesting depth.
+/// Pass [crate::GpuProfiler::last_frame].
+pub fn gpu_timings(ctx: &egui::Context, timings: &[GpuScopeTiming]) {
+    egui::Window::new("GPU").show(ctx, |ui| {
+        if timings.is_empty() {
+            ui.label("No scopes timed");
+            return;
+        }
+        egui::Grid::new("gpu_timings").striped(true).show(ui, |ui| {
+            for timing in timings {
+                ui.label(format!("{}{}", "  ".repeat(timing.depth), timing.name));
+                ui.label(format!("{:.2} ms", timing.duration.as_secs_f64() * 1000.0));
+                ui.end_row();
+            }
+        });
+    });
+}
+
+/// Draws the last frame's [crate::cpu_scope!]s as a flamegraph. Pass
+/// [crate::cpu_frame_spans]. Hover a bar to see its exact time.
+pub fn flamegraph(ctx: &egui::Context, spans: &[CpuSpan]) {
+    const ROW_HEIGHT: f32 = 18.0;
+
+    egui::Window::new("Flamegraph")
+        .default_width(500.0)
+        .show(ctx, |ui| {
+            let end = spans
+                .iter()
+                .map(|s| s.start + s.duration)
+                .max()
+                .unwrap_or_default();
+            let total_ms = end.as_secs_f32() * 1000.0;
+            ui.label(format!("{:.2} ms", total_ms));
+            if spans.is_empty() {
+                return;
+            }
+
+            let depth = spans.iter().map(|s| s.depth).max().unwrap_or(0) + 1;
+            let (rect, response) = ui.allocate_exact_size(
+                egui::vec2(ui.available_width(), depth as f32 * ROW_HEIGHT),
+                egui::Sense::hover(),
+            );
+            let painter = ui.painter_at(rect);
+            let scale = rect.width() / total_ms.max(f32::EPSILON);
+            for (i, span) in spans.iter().enumerate() {
+                let x = rect.left() + span.start.as_secs_f32() * 1000.0 * scale;
+                let width = (span.duration.as_secs_f32() * 1000.0 * scale).max(1.0);
+                let bar = egui::Rect::from_min_size(
+                    egui::pos2(x, rect.top() + span.depth as f32 * ROW_HEIGHT),
+                    egui::vec2(width, ROW_HEIGHT - 2.0),
+                );
+                let hue = (i as f32 * 0.618).fract();
+                painter.rect_filled(bar, 2.0, egui::ecolor::Hsva::new(hue, 0.5, 0.7, 1.0));
+                if width > 30.0 {
+                    painter.with_clip_rect(bar).text(
+                        bar.left_center() + egui::vec2(3.0, 0.0),
+                        egui::Align2::LEFT_CENTER,
+                        span.name,
+                        egui::FontId::proportional(12.0),
+                        egui::Color32::BLACK,
+                    );
+                }
+                if response.hover_pos().is_some_and(|pos| bar.contains(pos)) {
+                    response.clone().on_hover_text(format!(
+                        "{}: {:.3} ms",
+                        span.name,
+                        span.duration.as_secs_f64() * 1000.0
+                    ));
+                }
+            }
+        });
+}
+
+/// Shows the draw call, culling and state change counts from the last
+/// frame's [FrameStats].
+pub fn frame_stats(ctx: &egui::Context, stats: &FrameStats) {
+    egui::Window::new("Frame stats").show(ctx, |ui| {
+        egui::Grid::new("frame_stats").show(ui, |ui| {
+            for (name, value) in [
+                ("Draw calls", stats.draw_calls as u64),
+                ("Instances", stats.instances as u64),
+                ("Triangles", stats.triangles),
+                ("Culled (CPU)", stats.culled_cpu as u64),
+                ("Culled (GPU)", stats.culled_gpu as u64),
+                ("Pipeline changes", stats.pipeline_changes as u64),
+                ("Bind group changes", stats.bind_group_changes as u64),
+                ("Buffer changes", stats.buffer_changes as u64),
+            ] {
+                ui.label(name);
+                ui.label(value.to_string());
+                ui.end_row();
+            }
+        });
+    });
+}
+
+/// Picks which texture a [DebugInset] shows, and where.
+pub fn debug_inset(ctx: &egui::Context, inset: &mut DebugInset) {
+    egui::Window::new("Debug view").show(ctx, |ui| {
+        let mut selected = inset.selected();
+        let sources = inset.sources().map(str::to_string).collect::<Vec<_>>();
+        egui::ComboBox::from_label("Texture")
+            .selected_text(selected.as_deref().unwrap_or("None"))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut selected, None, "None");
+                for name in sources {
+                    ui.selectable_value(&mut selected, Some(name.clone()), name);
+                }
+            });
+        if selected != inset.selected() {
+            inset.select(selected.as_deref());
+        }
+
+        ui.horizontal(|ui| {
+            use crate::viewport::Corner;
+            for (corner, label) in [
+                (Corner::TopLeft, "Top left"),
+                (Corner::TopRight, "Top right"),
+                (Corner::BottomLeft, "Bottom left"),
+                (Corner::BottomRight, "Bottom right"),
+            ] {
+                ui.radio_value(&mut inset.corner, corner, label);
+            }
+        });
+        ui.add(egui::Slider::new(&mut inset.size, 0.1..=1.0).text("Size"));
+    });
+}
+
+pub fn gbuffer_debug(ctx: &egui::Context, debug: &mut GBufferDebug) {
+    egui::Window::new("G-buffer").show(ctx, |ui| {
+        let views = debug.views().collect::<Vec<_>>();
+        for view in views {
+            ui.radio_value(&mut debug.view, view, view.name());
+        }
+        ui.add(egui::Slider::new(&mut debug.velocity_scale, 1.0..=500.0).text("Velocity scale"));
+    });
+}
+
+/// Lists each action's bindings with a button to rebind it. `actions` has
+/// to be the full list of actions to show, like [crate::Action::ALL].
+pub fn action_map<A>(ctx: &egui::Context, map: &mut ActionMap<A>, actions: &[A], input: &Input)
+where
+    A: Copy + Eq + std::hash::Hash + std::fmt::Debug,
+{
+    // Clicks on the window itself aren't new bindings
+    let clicked_ui = ctx.is_pointer_over_area() && input.buttons_pressed().next().is_some();
+    if !clicked_ui {
+        map.update_rebinding(input);
+    }
+    egui::Window::new("Controls").show(ctx, |ui| {
+        egui::Grid::new("action_map").show(ui, |ui| {
+            for action in actions.iter().copied() {
+                ui.label(format!("{:?}", action));
+                let bindings = map
+                    .bindings(action)
+                    .iter()
+                    .map(binding_label)
+                    .collect::<Vec<_>>();
+                ui.label(if bindings.is_empty() {
+                    "Unbound".to_string()
+                } else {
+                    bindings.join(", ")
+                });
+                if map.rebinding() == Some(action) {
+                    if ui.button("Press something...").clicked() {
+                        map.cancel_rebinding();
+                    }
+                } else if ui.button("Rebind").clicked() {
+                    map.start_rebinding(action);
+                }
+                ui.end_row();
+            }
+        });
+    });
+}
+
+fn binding_label(binding: &Binding) -> String {
+    match *binding {
+        Binding::Key(key) => format!("{:?}", key),
+        Binding::MouseButton(button) => format!("Mouse {:?}", button),
+        Binding::MidiControl {
+            channel,
+            controller,
+        } => format!("MIDI CC {} ch {}", controller, channel),
+        Binding::MidiNote { channel, note } => format!("MIDI note {} ch {}", note, channel),
+        Binding::GamepadButton(button) => format!("Pad {:?}", button),
+        Binding::GamepadAxis { axis, positive } => {
+            format!("Pad {:?} {}", axis, if positive { "+" } else { "-" })
+        }
+    }
+}
+
+fn texture_label(ui: &mut egui::Ui, name: &str, texture: &Texture) {
+    let size = texture.desc.size;
+    ui.label(format!(
+        "{}: {}x{} {:?}",
+        name, size.width, size.height, texture.desc.format
+    ));
+}
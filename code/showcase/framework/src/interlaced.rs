@@ -0,0 +1,263 @@
+use anyhow::*;
+use cgmath::*;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::texture::Texture;
+
+/// Experimental performance mode that only renders every other column
+/// of pixels each frame. The scene is drawn into a half width target with
+/// a jittered projection, then [InterlacedRenderer::resolve] reconstructs
+/// the full image using the previous frame for the missing columns.
+///
+/// This roughly halves the fragment cost of heavy scenes, which is mostly
+/// useful on WebGL2 targets.
+pub struct InterlacedRenderer {
+    frames: [Texture<'static>; 2],
+    pub depth_texture: Texture<'static>,
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    parity_buffers: [wgpu::Buffer; 2],
+    bind_groups: [wgpu::BindGroup; 2],
+    full_width: u32,
+    frame_index: usize,
+}
+
+impl InterlacedRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Result<Self> {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("InterlacedRenderer::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("InterlacedRenderer::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = framework_shader("interlaced.wgsl", include_str!("interlaced.wgsl"));
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(shader.module())
+            .fragment_shader(shader.module())
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .color_solid(format)
+            .build(device)?;
+
+        // WebGL needs uniforms to be 16 byte aligned
+        let parity_buffers = [0u32, 1].map(|parity| {
+            device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("InterlacedRenderer::parity_buffer"),
+                contents: bytemuck::cast_slice(&[parity, 0, 0, 0]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            })
+        });
+
+        let frames = create_frames(device, width, height, format);
+        let depth_texture = create_depth_texture(device, width, height);
+        let bind_groups = create_bind_groups(device, &layout, &frames, &parity_buffers);
+
+        Ok(Self {
+            frames,
+            depth_texture,
+            layout,
+            pipeline,
+            parity_buffers,
+            bind_groups,
+            full_width: width,
+            frame_index: 0,
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let format = self.frames[0].desc.format;
+        self.frames = create_frames(device, width, height, format);
+        self.depth_texture = create_depth_texture(device, width, height);
+        self.bind_groups =
+            create_bind_groups(device, &self.layout, &self.frames, &self.parity_buffers);
+        self.full_width = width;
+    }
+
+    /// The half width texture the scene should be drawn into this frame.
+    pub fn target(&self) -> &wgpu::TextureView {
+        &self.frames[self.frame_index].view
+    }
+
+    /// Offsets the projection so this frame's pixels land on the right
+    /// columns. Multiply this on the left of the projection matrix.
+    pub fn jitter(&self) -> Matrix4<f32> {
+        field_jitter(self.frame_index, self.full_width)
+    }
+
+    /// Reconstructs the full resolution image into `output` and flips to
+    /// the other set of columns for the next frame.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        crate::cpu_scope!("InterlacedRenderer::resolve");
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("InterlacedRenderer::resolve"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_groups[self.frame_index], &[]);
+            pass.draw(0..3, 0..1);
+        }
+
+        self.frame_index = 1 - self.frame_index;
+    }
+}
+
+/// Shifts the scene half a half width pixel, so frame 0 draws the even
+/// full resolution columns and frame 1 the odd ones, matching the parity
+/// the resolve shader gets for each
+fn field_jitter(frame_index: usize, full_width: u32) -> Matrix4<f32> {
+    let offset = if frame_index == 0 { 1.0 } else { -1.0 };
+    Matrix4::from_translation(Vector3::new(offset / full_width as f32, 0.0, 0.0))
+}
+
+fn half_size(width: u32, height: u32) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: width.div_ceil(2).max(1),
+        height: height.max(1),
+        depth_or_array_layers: 1,
+    }
+}
+
+fn create_frames(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+) -> [Texture<'static>; 2] {
+    [0, 1].map(|_| {
+        Texture::from_descriptor(
+            device,
+            wgpu::TextureDescriptor {
+                label: Some("InterlacedRenderer::frame"),
+                size: half_size(width, height),
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        )
+    })
+}
+
+fn create_depth_texture(device: &wgpu::Device, width: u32, height: u32) -> Texture<'static> {
+    Texture::from_descriptor(
+        device,
+        wgpu::TextureDescriptor {
+            label: Some("InterlacedRenderer::depth_texture"),
+            size: half_size(width, height),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+    )
+}
+
+fn create_bind_groups(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    frames: &[Texture; 2],
+    parity_buffers: &[wgpu::Buffer; 2],
+) -> [wgpu::BindGroup; 2] {
+    [0, 1].map(|current| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("InterlacedRenderer::bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&frames[current].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&frames[1 - current].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: parity_buffers[current].as_entire_binding(),
+                },
+            ],
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_alternate_columns() {
+        let width = 8;
+        for parity in 0..2 {
+            let unjitter = field_jitter(parity, width).invert().unwrap();
+            for i in 0..width / 2 {
+                // The center of half width pixel i, in clip space
+                let half = -1.0 + (2 * i + 1) as f32 / (width / 2) as f32;
+                let x = (unjitter * Vector4::new(half, 0.0, 0.0, 1.0)).x;
+                // Lands on the center of a full resolution column
+                let column = (x + 1.0) / 2.0 * width as f32 - 0.5;
+                assert!((column - (2 * i + parity as u32) as f32).abs() < 1e-4);
+            }
+        }
+        // Odd widths keep the last column
+        assert_eq!(half_size(1921, 1081).width, 961);
+        assert_eq!(half_size(1921, 1081).height, 1081);
+    }
+}
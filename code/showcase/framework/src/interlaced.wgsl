@@ -0,0 +1,42 @@
+#include "framework/fullscreen.wgsl"
+
+struct Params {
+    // Which full resolution columns the current frame covers (0 or 1)
+    parity: u32,
+}
+
+@group(0) @binding(0)
+var current_frame: texture_2d<f32>;
+@group(0) @binding(1)
+var previous_frame: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let max_coord = vec2<i32>(textureDimensions(current_frame)) - 1;
+    let coord = min(vec2<i32>(pixel.x / 2, pixel.y), max_coord);
+
+    if (u32(pixel.x % 2) == params.parity) {
+        return textureLoad(current_frame, coord, 0);
+    }
+
+    // The missing column comes from the previous frame. To keep moving
+    // objects from combing, clamp it to the colors of the neighbouring
+    // columns that were rendered this frame.
+    let left = select(coord.x - 1, coord.x, params.parity == 0u);
+    let right = min(left + 1, max_coord.x);
+    var lo = vec4<f32>(1e6);
+    var hi = vec4<f32>(-1e6);
+    for (var y = -1; y <= 1; y += 1) {
+        let row = clamp(coord.y + y, 0, max_coord.y);
+        let a = textureLoad(current_frame, vec2<i32>(max(left, 0), row), 0);
+        let b = textureLoad(current_frame, vec2<i32>(right, row), 0);
+        lo = min(lo, min(a, b));
+        hi = max(hi, max(a, b));
+    }
+
+    let history = textureLoad(previous_frame, coord, 0);
+    return clamp(history, lo, hi);
+}
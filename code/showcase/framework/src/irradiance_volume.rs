@@ -0,0 +1,459 @@
+use anyhow::*;
+use cgmath::*;
+
+use crate::camera::OPENGL_TO_WGPU_MATRIX;
+use crate::sh::{self, ShProbe, SH_COEFFICIENTS};
+use crate::texture::Texture;
+
+/// WGSL for reading an [IrradianceVolume] bound to group 2, see
+/// [IrradianceVolume::layout_entries]. Put it after [crate::PBR_WGSL] and
+/// [crate::SH_WGSL] to get `volume_irradiance(position, n)` and
+/// `pbr_volume_ambient(surface, n, position)`.
+pub const IRRADIANCE_VOLUME_WGSL: &str = include_str!("irradiance_volume.wgsl");
+
+/// Texels each probe's coefficients are packed into
+const SLOTS: u32 = 7;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct IrradianceVolumeUniform {
+    min: [f32; 4],
+    max: [f32; 4],
+}
+
+/// What [IrradianceVolume::bake] hands the scene for each side of each
+/// probe. Draw into `color` and `depth`, clearing both, from `view_proj`.
+pub struct VolumeBakeFace<'a> {
+    /// `Rgba16Float`, [VolumeBakeFace::size] texels a side
+    pub color: &'a wgpu::TextureView,
+    /// [Texture::DEPTH_FORMAT]
+    pub depth: &'a wgpu::TextureView,
+    /// A 90 degree camera at the probe
+    pub view_proj: Matrix4<f32>,
+    pub position: Point3<f32>,
+    pub size: u32,
+}
+
+/// A grid of [ShProbe]s filling a box, so moving objects pick up the
+/// light bouncing around the scene where they are. The probes are baked
+/// by drawing the scene around each one, and sampled with
+/// [IrradianceVolume::sample] on the CPU or from a 3D texture in the
+/// shader.
+///
+/// ```ignore
+/// let mut volume = IrradianceVolume::new(&display.device, (-10.0, 0.0, -10.0).into(), (10.0, 5.0, 10.0).into(), [8, 3, 8]);
+/// volume.bake(&display.device, &display.queue, 32, 50.0, |encoder, face| {
+///     // Begin a pass on face.color and face.depth and draw the scene
+///     // with face.view_proj
+/// })?;
+/// ```
+pub struct IrradianceVolume {
+    /// The first and last probes' positions
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+    /// The probes, with x changing fastest and then y. Call
+    /// [IrradianceVolume::upload] after changing them.
+    pub probes: Vec<ShProbe>,
+    resolution: [u32; 3],
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+}
+
+impl IrradianceVolume {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// An unlit volume with `resolution` probes along each axis, spread
+    /// from `min` to `max`
+    pub fn new(
+        device: &wgpu::Device,
+        min: Point3<f32>,
+        max: Point3<f32>,
+        resolution: [u32; 3],
+    ) -> Self {
+        let resolution = resolution.map(|r| r.max(1));
+        let [nx, ny, nz] = resolution;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("IrradianceVolume::texture"),
+            size: wgpu::Extent3d {
+                width: nx,
+                height: ny,
+                depth_or_array_layers: nz * SLOTS,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&Default::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("IrradianceVolume::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("IrradianceVolume::uniform_buffer"),
+            size: std::mem::size_of::<IrradianceVolumeUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            min,
+            max,
+            probes: vec![ShProbe::default(); (nx * ny * nz) as usize],
+            resolution,
+            texture,
+            view,
+            sampler,
+            uniform_buffer,
+        }
+    }
+
+    pub fn resolution(&self) -> [u32; 3] {
+        self.resolution
+    }
+
+    /// Where probe (`x`, `y`, `z`) sits
+    pub fn probe_position(&self, x: u32, y: u32, z: u32) -> Point3<f32> {
+        probe_position(self.min, self.max, self.resolution, [x, y, z])
+    }
+
+    /// The probes around `position` blended together, the same as the
+    /// shader. Handy for giving one object an [crate::ShBuffer] probe.
+    pub fn sample(&self, position: Point3<f32>) -> ShProbe {
+        sample(self.min, self.max, self.resolution, &self.probes, position)
+    }
+
+    /// Draws the scene around every probe with `draw` and projects it.
+    /// Each side is `face_size` texels and sees as far as `far`. Lighting
+    /// the scene with the volume and baking again adds another bounce.
+    /// It waits for the GPU after each probe, so it's for loading screens
+    /// and doesn't work on the web.
+    pub fn bake(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        face_size: u32,
+        far: f32,
+        mut draw: impl FnMut(&mut wgpu::CommandEncoder, VolumeBakeFace),
+    ) -> Result<()> {
+        let size = face_size.max(1);
+        let faces = Texture::from_descriptor(
+            device,
+            wgpu::TextureDescriptor {
+                label: Some("IrradianceVolume::faces"),
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 6,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            },
+        );
+        let face_views = (0..6)
+            .map(|face| {
+                faces.texture.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: face,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let depth = Texture::from_descriptor(
+            device,
+            wgpu::TextureDescriptor {
+                label: Some("IrradianceVolume::depth"),
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Texture::DEPTH_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            },
+        );
+        let projection = OPENGL_TO_WGPU_MATRIX * perspective(Deg(90.0), 1.0, 0.05, far);
+
+        let [nx, ny, nz] = self.resolution;
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let position = self.probe_position(x, y, z);
+                    let mut encoder =
+                        device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                            label: Some("IrradianceVolume::bake"),
+                        });
+                    for (face, (forward, up)) in FACES.iter().enumerate() {
+                        let view = Matrix4::look_to_rh(position, *forward, *up);
+                        draw(
+                            &mut encoder,
+                            VolumeBakeFace {
+                                color: &face_views[face],
+                                depth: &depth.view,
+                                view_proj: projection * view,
+                                position,
+                                size,
+                            },
+                        );
+                    }
+                    queue.submit([encoder.finish()]);
+
+                    let texels = sh::read_layers(device, queue, &faces.texture, size, 6)?;
+                    let mut probe = ShProbe::default();
+                    for (face, (forward, up)) in FACES.iter().enumerate() {
+                        let right = forward.cross(*up);
+                        for ty in 0..size {
+                            for tx in 0..size {
+                                // Where the texel is on the face, from -1 to 1
+                                let s = (tx as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                                let t = (ty as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                                let direction = (forward + right * s - up * t).normalize();
+                                let color =
+                                    texels[((face as u32 * size + ty) * size + tx) as usize];
+                                let weight = sh::texel_solid_angle(tx, ty, size);
+                                probe.add_sample(direction, color, weight);
+                            }
+                        }
+                    }
+                    let index = self.index(x, y, z);
+                    self.probes[index] = probe;
+                }
+            }
+        }
+        self.upload(queue);
+        Ok(())
+    }
+
+    /// Copies the probes and bounds to the GPU
+    pub fn upload(&self, queue: &wgpu::Queue) {
+        let [nx, ny, nz] = self.resolution;
+        let mut texels = vec![[0u16; 4]; (nx * ny * nz * SLOTS) as usize];
+        for (i, probe) in self.probes.iter().enumerate() {
+            let i = i as u32;
+            let (x, y, z) = (i % nx, i / nx % ny, i / (nx * ny));
+            let mut floats = [0.0; SLOTS as usize * 4];
+            floats[..SH_COEFFICIENTS * 3].copy_from_slice(probe.convolved().as_flattened());
+            for slot in 0..SLOTS {
+                let layer = slot * nz + z;
+                let texel = &mut texels[((layer * ny + y) * nx + x) as usize];
+                for (c, value) in texel.iter_mut().enumerate() {
+                    *value = sh::f32_to_f16(floats[slot as usize * 4 + c]);
+                }
+            }
+        }
+        queue.write_texture(
+            self.texture.as_image_copy(),
+            bytemuck::cast_slice(&texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(nx * std::mem::size_of::<[u16; 4]>() as u32),
+                rows_per_image: Some(ny),
+            },
+            self.texture.size(),
+        );
+        let uniform = IrradianceVolumeUniform {
+            min: self.min.to_homogeneous().into(),
+            max: self.max.to_homogeneous().into(),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// The texture, sampler and bounds at bindings 7 to 9, where
+    /// [IRRADIANCE_VOLUME_WGSL] expects them in the light's bind group
+    pub fn layout_entries() -> [wgpu::BindGroupLayoutEntry; 3] {
+        [
+            wgpu::BindGroupLayoutEntry {
+                binding: 7,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D3,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 8,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 9,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ]
+    }
+
+    /// The resources for [IrradianceVolume::layout_entries]
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 3] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::TextureView(&self.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+        ]
+    }
+
+    fn index(&self, x: u32, y: u32, z: u32) -> usize {
+        probe_index(self.resolution, x, y, z)
+    }
+}
+
+fn probe_index([nx, ny, _]: [u32; 3], x: u32, y: u32, z: u32) -> usize {
+    (x + nx * (y + ny * z)) as usize
+}
+
+/// Probes are spread evenly from `min` to `max`, or in the middle along
+/// axes with only one
+fn probe_position(
+    min: Point3<f32>,
+    max: Point3<f32>,
+    resolution: [u32; 3],
+    [x, y, z]: [u32; 3],
+) -> Point3<f32> {
+    let t = |i: u32, n: u32| {
+        if n > 1 {
+            i as f32 / (n - 1) as f32
+        } else {
+            0.5
+        }
+    };
+    let [nx, ny, nz] = resolution;
+    let offset = max - min;
+    min + Vector3::new(
+        offset.x * t(x, nx),
+        offset.y * t(y, ny),
+        offset.z * t(z, nz),
+    )
+}
+
+/// Trilinear blend of the 8 probes around `position`, clamped to the box
+fn sample(
+    min: Point3<f32>,
+    max: Point3<f32>,
+    resolution: [u32; 3],
+    probes: &[ShProbe],
+    position: Point3<f32>,
+) -> ShProbe {
+    let size = max - min;
+    let [nx, ny, nz] = resolution;
+    let axis = |p: f32, min: f32, size: f32, n: u32| {
+        let t = if size.abs() > 1e-4 {
+            ((p - min) / size).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let f = t * (n - 1) as f32;
+        let i = (f.floor() as u32).min(n.saturating_sub(2));
+        (i, (i + 1).min(n - 1), f - i as f32)
+    };
+    let (x0, x1, tx) = axis(position.x, min.x, size.x, nx);
+    let (y0, y1, ty) = axis(position.y, min.y, size.y, ny);
+    let (z0, z1, tz) = axis(position.z, min.z, size.z, nz);
+    let probe = |x, y, z| &probes[probe_index(resolution, x, y, z)];
+    let along_x = |y, z| probe(x0, y, z).lerp(probe(x1, y, z), tx);
+    let along_y = |z| along_x(y0, z).lerp(&along_x(y1, z), ty);
+    along_y(z0).lerp(&along_y(z1), tz)
+}
+
+/// Forward and up for each side of a probe
+const FACES: [(Vector3<f32>, Vector3<f32>); 6] = [
+    (Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)),
+    (Vector3::new(-1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)),
+    (Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, -1.0)),
+    (Vector3::new(0.0, -1.0, 0.0), Vector3::new(0.0, 0.0, 1.0)),
+    (Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 1.0, 0.0)),
+    (Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.0, 1.0, 0.0)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: Point3<f32> = Point3::new(0.0, 0.0, 0.0);
+    const MAX: Point3<f32> = Point3::new(4.0, 2.0, 6.0);
+    /// Only one probe deep, in the middle of the box
+    const RESOLUTION: [u32; 3] = [3, 2, 1];
+
+    /// Each probe is a different shade of red, so blends can be told apart
+    fn probes() -> Vec<ShProbe> {
+        (0..6)
+            .map(|i| ShProbe::uniform([i as f32, 0.0, 0.0]))
+            .collect()
+    }
+
+    fn red(probe: &ShProbe) -> f32 {
+        probe.irradiance(Vector3::unit_y())[0]
+    }
+
+    #[test]
+    fn probes_fill_the_box() {
+        let position = |x, y, z| probe_position(MIN, MAX, RESOLUTION, [x, y, z]);
+        assert_eq!(position(0, 0, 0), Point3::new(0.0, 0.0, 3.0));
+        assert_eq!(position(1, 0, 0), Point3::new(2.0, 0.0, 3.0));
+        assert_eq!(position(2, 1, 0), Point3::new(4.0, 2.0, 3.0));
+        assert_eq!(probe_index(RESOLUTION, 2, 1, 0), 5);
+    }
+
+    #[test]
+    fn sampling_blends_the_nearest_probes() {
+        let probes = probes();
+        let at = |p: Point3<f32>| red(&sample(MIN, MAX, RESOLUTION, &probes, p));
+        let unit = red(&probes[1]);
+
+        // On a probe it's that probe, whatever the depth
+        for z in [0.0, 3.0, 6.0] {
+            assert!((at(Point3::new(2.0, 2.0, z)) - red(&probes[4])).abs() < 1e-4);
+        }
+        // Midway between two is their average
+        assert!((at(Point3::new(1.0, 0.0, 3.0)) - 0.5 * unit).abs() < 1e-4);
+        // and in the middle of four, all of theirs
+        assert!((at(Point3::new(3.0, 1.0, 3.0)) - 3.0 * unit).abs() < 1e-4);
+        // Outside the box clamps to the edge
+        assert!((at(Point3::new(-10.0, 20.0, 3.0)) - red(&probes[3])).abs() < 1e-4);
+        assert!((at(Point3::new(10.0, -20.0, 3.0)) - red(&probes[2])).abs() < 1e-4);
+    }
+
+    #[test]
+    fn irradiance_volume_wgsl_validates() {
+        crate::shader::validate_wgsl(&format!(
+            "{}{}{}",
+            crate::model::PBR_WGSL,
+            crate::sh::SH_WGSL,
+            IRRADIANCE_VOLUME_WGSL
+        ))
+        .unwrap();
+    }
+}
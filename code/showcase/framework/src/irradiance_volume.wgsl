@@ -0,0 +1,52 @@
+// Reads an IrradianceVolume bound to group 2 at bindings 7 to 9, after an
+// ShBuffer. Needs SH_WGSL in front for sh_evaluate.
+
+struct IrradianceVolumeUniform {
+    // The first and last probes' positions
+    min: vec4<f32>,
+    max: vec4<f32>,
+}
+
+// The 27 floats of each probe's coefficients are packed into 7 texels, one
+// block of layers after another along z
+@group(2) @binding(7)
+var volume_texture: texture_3d<f32>;
+@group(2) @binding(8)
+var volume_sampler: sampler;
+@group(2) @binding(9)
+var<uniform> volume: IrradianceVolumeUniform;
+
+const VOLUME_SLOTS: u32 = 7u;
+
+// The probes around `position` blended together and evaluated for a
+// surface facing `n`. Outside the volume it uses the probes on its edge.
+fn volume_irradiance(position: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    let dims = vec3<f32>(textureDimensions(volume_texture));
+    let count = vec3<f32>(dims.xy, dims.z / f32(VOLUME_SLOTS));
+    let t = clamp((position - volume.min.xyz) / max(volume.max.xyz - volume.min.xyz, vec3<f32>(0.0001)), vec3<f32>(0.0), vec3<f32>(1.0));
+    // Probes sit on texel centers, so blending never reaches into the next
+    // block of layers
+    let texel = t * (count - 1.0) + 0.5;
+
+    var packed: array<vec4<f32>, 7>;
+    for (var slot = 0u; slot < VOLUME_SLOTS; slot++) {
+        let uvw = vec3<f32>(texel.xy / dims.xy, (f32(slot) * count.z + texel.z) / dims.z);
+        packed[slot] = textureSampleLevel(volume_texture, volume_sampler, uvw, 0.0);
+    }
+    var c: array<vec4<f32>, 9>;
+    for (var i = 0u; i < 9u; i++) {
+        var rgb: vec3<f32>;
+        for (var channel = 0u; channel < 3u; channel++) {
+            let j = i * 3u + channel;
+            rgb[channel] = packed[j / 4u][j % 4u];
+        }
+        c[i] = vec4<f32>(rgb, 0.0);
+    }
+    return sh_evaluate(c, n);
+}
+
+// pbr_ambient lit by the volume. Needs the Surface from PBR_WGSL or
+// BRDF_WGSL.
+fn pbr_volume_ambient(surface: Surface, n: vec3<f32>, position: vec3<f32>) -> vec3<f32> {
+    return pbr_ambient(surface, volume_irradiance(position, n));
+}
@@ -0,0 +1,384 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+
+#[cfg(not(all(target_arch = "wasm32", feature = "wasm-threads")))]
+use std::thread as worker_thread;
+#[cfg(all(target_arch = "wasm32", feature = "wasm-threads"))]
+use wasm_thread as worker_thread;
+
+type Task = Box<dyn FnOnce() + Send + 'static>;
+type Panic = Box<dyn Any + Send + 'static>;
+
+/// Whether the pool can start threads at all. The web only can with the
+/// `wasm-threads` feature and a build with atomics.
+const THREADS: bool = !cfg!(target_arch = "wasm32")
+    || cfg!(all(feature = "wasm-threads", target_feature = "atomics"));
+
+/// Browsers don't let the main thread block, so waiting for the pool
+/// there spins instead
+const SPIN: bool = cfg!(target_arch = "wasm32");
+
+struct Shared {
+    /// One per worker. Workers take from the back of their own and steal
+    /// from the front of the others'.
+    queues: Vec<Mutex<VecDeque<Task>>>,
+    /// Tasks queued and not taken yet, for workers to sleep on
+    pending: Mutex<usize>,
+    wake: Condvar,
+    shutdown: AtomicBool,
+    next: AtomicUsize,
+}
+
+impl Shared {
+    fn push(&self, task: Task) {
+        // Counted first so it never drops below zero when taken right away
+        *self.pending.lock().unwrap() += 1;
+        let queue = self.next.fetch_add(1, Ordering::Relaxed) % self.queues.len();
+        self.queues[queue].lock().unwrap().push_back(task);
+        self.wake.notify_one();
+    }
+
+    fn take(&self, home: usize) -> Option<Task> {
+        let count = self.queues.len();
+        let task = self.queues[home].lock().unwrap().pop_back().or_else(|| {
+            (1..count).find_map(|i| self.queues[(home + i) % count].lock().unwrap().pop_front())
+        })?;
+        *self.pending.lock().unwrap() -= 1;
+        Some(task)
+    }
+
+    fn work(&self, home: usize) {
+        loop {
+            if let Some(task) = self.take(home) {
+                task();
+                continue;
+            }
+            let mut pending = self.pending.lock().unwrap();
+            while *pending == 0 {
+                if self.shutdown.load(Ordering::Acquire) {
+                    return;
+                }
+                pending = self.wake.wait(pending).unwrap();
+            }
+        }
+    }
+}
+
+/// Counts down the chunks of a [JobPool::parallel_for]
+struct Latch {
+    remaining: Mutex<usize>,
+    done: Condvar,
+    panic: Mutex<Option<Panic>>,
+}
+
+impl Latch {
+    fn finish(&self, result: thread::Result<()>) {
+        if let Err(payload) = result {
+            self.panic.lock().unwrap().get_or_insert(payload);
+        }
+        let mut remaining = self.remaining.lock().unwrap();
+        *remaining -= 1;
+        if *remaining == 0 {
+            self.done.notify_all();
+        }
+    }
+}
+
+/// A pool of worker threads for spreading CPU work over every core.
+/// Each worker has its own queue and steals from the others when it runs
+/// out, and threads waiting on the pool help with its work instead of
+/// sitting idle. [JobPool::global] is shared by the framework's own
+/// loaders and culling.
+///
+/// On the web everything runs on the calling thread as it's submitted,
+/// unless the `wasm-threads` feature is on and the build has atomics. Then
+/// the workers are web workers, which needs the page served with
+/// `Cross-Origin-Opener-Policy: same-origin` and
+/// `Cross-Origin-Embedder-Policy: require-corp` for `SharedArrayBuffer`.
+/// Waiting on the pool from the page's own thread spins, so it's better to
+/// poll a [Job] with [Job::try_wait] from [crate::Demo::update] there.
+///
+/// ```ignore
+/// let heights = JobPool::global().map(&points, 256, |p| terrain.height(*p));
+/// let decoded = JobPool::global().spawn(move || image::open(path));
+/// // Later
+/// let image = decoded.wait()?;
+/// ```
+pub struct JobPool {
+    shared: Option<Arc<Shared>>,
+    threads: Vec<worker_thread::JoinHandle<()>>,
+}
+
+impl JobPool {
+    /// A pool with `workers` threads. 0 runs everything on the calling
+    /// thread.
+    pub fn new(workers: usize) -> Self {
+        if workers == 0 || !THREADS {
+            return Self {
+                shared: None,
+                threads: Vec::new(),
+            };
+        }
+        let shared = Arc::new(Shared {
+            queues: (0..workers).map(|_| Mutex::default()).collect(),
+            pending: Mutex::new(0),
+            wake: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+            next: AtomicUsize::new(0),
+        });
+        let threads = (0..workers)
+            .map(|home| {
+                let shared = shared.clone();
+                worker_thread::Builder::new()
+                    .name(format!("framework-job-{}", home))
+                    .spawn(move || shared.work(home))
+                    .expect("Unable to start a job thread")
+            })
+            .collect();
+        Self {
+            shared: Some(shared),
+            threads,
+        }
+    }
+
+    /// One worker for each core besides the calling thread's, created the
+    /// first time it's used
+    pub fn global() -> &'static JobPool {
+        static GLOBAL: OnceLock<JobPool> = OnceLock::new();
+        GLOBAL.get_or_init(|| {
+            let cores = worker_thread::available_parallelism().map_or(1, |n| n.get());
+            Self::new(cores - 1)
+        })
+    }
+
+    pub fn workers(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Runs `f` on a worker. [Job::wait] gets the result, and carries on
+    /// any panic.
+    pub fn spawn<T, F>(&self, f: F) -> Job<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let slot = Arc::new(JobSlot {
+            result: Mutex::new(None),
+            done: Condvar::new(),
+        });
+        match &self.shared {
+            Some(shared) => {
+                let job_slot = slot.clone();
+                shared.push(Box::new(move || {
+                    let result = panic::catch_unwind(AssertUnwindSafe(f));
+                    *job_slot.result.lock().unwrap() = Some(result);
+                    job_slot.done.notify_all();
+                }));
+            }
+            None => *slot.result.lock().unwrap() = Some(panic::catch_unwind(AssertUnwindSafe(f))),
+        }
+        Job {
+            slot,
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Calls `f` with chunks of `0..len` at most `chunk` long, spread over
+    /// the pool, and returns once they're all done. The calling thread
+    /// works on chunks too. Work that fits in one chunk runs right here
+    /// without touching the pool, so pick `chunk` big enough to be worth
+    /// handing to another thread.
+    pub fn parallel_for<F>(&self, len: usize, chunk: usize, f: F)
+    where
+        F: Fn(Range<usize>) + Sync,
+    {
+        let chunk = chunk.max(1);
+        let chunks = len.div_ceil(chunk);
+        let range = move |i: usize| i * chunk..((i + 1) * chunk).min(len);
+        let shared = match &self.shared {
+            Some(shared) if chunks > 1 => shared,
+            _ => {
+                (0..chunks).for_each(|i| f(range(i)));
+                return;
+            }
+        };
+
+        let latch = Arc::new(Latch {
+            remaining: Mutex::new(chunks),
+            done: Condvar::new(),
+            panic: Mutex::new(None),
+        });
+        let f: &(dyn Fn(Range<usize>) + Sync) = &f;
+        // Safety: nothing returns from here until every chunk has finished
+        // with `f`, even if one panics, so it outlives the tasks using it
+        let f: &'static (dyn Fn(Range<usize>) + Sync) = unsafe { std::mem::transmute(f) };
+        for i in 1..chunks {
+            let latch = latch.clone();
+            shared.push(Box::new(move || {
+                latch.finish(panic::catch_unwind(AssertUnwindSafe(|| f(range(i)))));
+            }));
+        }
+        latch.finish(panic::catch_unwind(AssertUnwindSafe(|| f(range(0)))));
+
+        // Helps with whatever's queued, then sleeps on the stragglers
+        while *latch.remaining.lock().unwrap() > 0 {
+            match shared.take(0) {
+                Some(task) => task(),
+                None if SPIN => std::hint::spin_loop(),
+                None => {
+                    let mut remaining = latch.remaining.lock().unwrap();
+                    while *remaining > 0 {
+                        remaining = latch.done.wait(remaining).unwrap();
+                    }
+                }
+            }
+        }
+        let panicked = latch.panic.lock().unwrap().take();
+        if let Some(payload) = panicked {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    /// `f` applied to every item in order, `chunk` items to a task
+    pub fn map<T, U, F>(&self, items: &[T], chunk: usize, f: F) -> Vec<U>
+    where
+        T: Sync,
+        U: Send,
+        F: Fn(&T) -> U + Sync,
+    {
+        let chunk = chunk.max(1);
+        let results: Vec<Mutex<Vec<U>>> = (0..items.len().div_ceil(chunk))
+            .map(|_| Mutex::default())
+            .collect();
+        self.parallel_for(items.len(), chunk, |range| {
+            let mapped = items[range.clone()].iter().map(&f).collect();
+            *results[range.start / chunk].lock().unwrap() = mapped;
+        });
+        results
+            .into_iter()
+            .flat_map(|r| r.into_inner().unwrap())
+            .collect()
+    }
+}
+
+impl Drop for JobPool {
+    fn drop(&mut self) {
+        if let Some(shared) = &self.shared {
+            shared.shutdown.store(true, Ordering::Release);
+            // Takes the lock so no worker misses the wakeup between
+            // checking shutdown and sleeping
+            drop(shared.pending.lock().unwrap());
+            shared.wake.notify_all();
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct JobSlot<T> {
+    result: Mutex<Option<thread::Result<T>>>,
+    done: Condvar,
+}
+
+/// Work running on a [JobPool], from [JobPool::spawn]
+pub struct Job<T> {
+    slot: Arc<JobSlot<T>>,
+    shared: Option<Arc<Shared>>,
+}
+
+impl<T> Job<T> {
+    pub fn is_done(&self) -> bool {
+        self.slot.result.lock().unwrap().is_some()
+    }
+
+    /// The result if the job is done, or the job back if it isn't, for
+    /// checking on it each frame without waiting
+    pub fn try_wait(self) -> Result<T, Self> {
+        if self.is_done() {
+            Ok(self.wait())
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Blocks until the job is done, helping with the pool's other work
+    /// in the meantime
+    pub fn wait(self) -> T {
+        loop {
+            if self.is_done() {
+                break;
+            }
+            match self.shared.as_ref().and_then(|s| s.take(0)) {
+                Some(task) => task(),
+                None if SPIN => std::hint::spin_loop(),
+                None => {
+                    let mut result = self.slot.result.lock().unwrap();
+                    while result.is_none() {
+                        result = self.slot.done.wait(result).unwrap();
+                    }
+                }
+            }
+        }
+        match self.slot.result.lock().unwrap().take().unwrap() {
+            Ok(value) => value,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn work_is_spread_and_waited_for() {
+        for pool in [JobPool::new(0), JobPool::new(3)] {
+            let items: Vec<u64> = (0..10_000).collect();
+            let squares = pool.map(&items, 64, |i| i * i);
+            assert_eq!(squares.len(), items.len());
+            assert!(squares
+                .iter()
+                .enumerate()
+                .all(|(i, &s)| s == (i * i) as u64));
+
+            // Nested loops from inside the pool don't deadlock
+            let total = AtomicUsize::new(0);
+            pool.parallel_for(8, 1, |_| {
+                pool.parallel_for(100, 10, |range| {
+                    total.fetch_add(range.len(), Ordering::Relaxed);
+                });
+            });
+            assert_eq!(total.load(Ordering::Relaxed), 800);
+
+            let job = pool.spawn(|| 6 * 7);
+            assert_eq!(job.wait(), 42);
+
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                pool.parallel_for(4, 1, |range| assert!(range.start != 2, "chunk failed"));
+            }));
+            assert!(result.is_err());
+            // Still works afterwards
+            assert_eq!(pool.map(&[1, 2, 3], 1, |i| i + 1), [2, 3, 4]);
+        }
+    }
+
+    #[test]
+    fn try_wait_hands_back_pending_jobs() {
+        for pool in [JobPool::new(0), JobPool::new(3)] {
+            let mut job = pool.spawn(|| 6 * 7);
+            let answer = loop {
+                match job.try_wait() {
+                    Ok(answer) => break answer,
+                    Err(pending) => job = pending,
+                }
+            };
+            assert_eq!(answer, 42);
+        }
+    }
+}
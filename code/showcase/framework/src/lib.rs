@@ -1,19 +1,168 @@
+mod actions;
+mod animation;
+mod antialiasing;
+mod assets;
+#[cfg(feature = "audio")]
+mod audio;
+mod bloom;
+mod bookmarks;
 mod buffer;
 mod camera;
+mod capabilities;
+mod capture;
+mod clipmap;
+mod color;
+mod color_grading;
+mod compute_canvas;
+mod cpu_profiler;
+mod culling;
+mod debug_draw;
+mod debug_inset;
+mod deferred;
+mod deletion;
+mod depth_of_field;
+pub mod diagnostics;
+mod displacement;
+mod gbuffer_debug;
+#[cfg(feature = "gltf")]
+mod gltf_loader;
+mod gpu_profiler;
+#[cfg(feature = "gui")]
+pub mod gui;
+mod half_res;
+mod hdr;
+mod hot_reload;
+mod ibl;
+mod input;
+#[cfg(feature = "gui")]
+pub mod inspector;
+mod interlaced;
+mod irradiance_volume;
+mod jobs;
 mod light;
+mod lsystem;
+mod marching_cubes;
 mod model;
+mod morph;
+mod motion_blur;
+mod msaa;
+mod pack;
+mod particles;
+mod pause;
 mod pipeline;
+mod planet;
+mod portals;
+mod post;
 pub mod prelude;
+mod reflection;
+mod render_graph;
+#[cfg(feature = "renderdoc")]
+mod renderdoc;
+mod replay;
+mod rng;
+mod scatter;
+mod scene;
+mod settings;
+mod sh;
+mod shader;
 mod shader_canvas;
+mod shadow;
+mod skinning;
+mod sky;
+mod skybox;
+mod sources;
+mod ssao;
+mod stats;
+mod stats_overlay;
+mod stylized;
+mod text;
 mod texture;
-
+mod time;
+mod timeline;
+mod units;
+mod vertex_animation;
+mod viewport;
+mod voxel;
+
+#[cfg(feature = "renderdoc")]
+pub use crate::renderdoc::*;
+pub use actions::*;
+pub use animation::*;
+pub use antialiasing::*;
+pub use assets::*;
+#[cfg(feature = "audio")]
+pub use audio::*;
+pub use bloom::*;
+pub use bookmarks::*;
 pub use buffer::*;
 pub use camera::*;
+pub use capabilities::*;
+pub use capture::*;
+pub use clipmap::*;
+pub use color::*;
+pub use color_grading::*;
+pub use compute_canvas::*;
+pub use cpu_profiler::*;
+pub use culling::*;
+pub use debug_draw::*;
+pub use debug_inset::*;
+pub use deferred::*;
+pub use deletion::*;
+pub use depth_of_field::*;
+pub use displacement::*;
+pub use gbuffer_debug::*;
+pub use gpu_profiler::*;
+pub use half_res::*;
+pub use hdr::*;
+pub use hot_reload::*;
+pub use ibl::*;
+pub use input::*;
+pub use interlaced::*;
+pub use irradiance_volume::*;
+pub use jobs::*;
 pub use light::*;
+pub use lsystem::*;
+pub use marching_cubes::*;
 pub use model::*;
+pub use morph::*;
+pub use motion_blur::*;
+pub use msaa::*;
+pub use pack::*;
+pub use particles::*;
+pub use pause::*;
 pub use pipeline::*;
+pub use planet::*;
+pub use portals::*;
+pub use post::*;
+#[cfg(feature = "puffin")]
+pub use puffin;
+pub use reflection::*;
+pub use render_graph::*;
+pub use replay::*;
+pub use rng::*;
+pub use scatter::*;
+pub use scene::*;
+pub use settings::*;
+pub use sh::*;
+pub use shader::*;
 pub use shader_canvas::*;
+pub use shadow::*;
+pub use skinning::*;
+pub use sky::*;
+pub use skybox::*;
+pub use sources::*;
+pub use ssao::*;
+pub use stats::*;
+pub use stats_overlay::*;
+pub use stylized::*;
+pub use text::*;
 pub use texture::*;
+pub use time::*;
+pub use timeline::*;
+pub use units::*;
+pub use vertex_animation::*;
+pub use viewport::*;
+pub use voxel::*;
 
 use anyhow::*;
 use cgmath::*;
@@ -24,20 +173,57 @@ use winit::application::ApplicationHandler;
 use winit::event::*;
 use winit::event_loop::EventLoop;
 use winit::keyboard::{KeyCode, PhysicalKey};
-use winit::window::{Window, WindowAttributes};
+use winit::window::{CursorGrabMode, Window, WindowAttributes};
 
 pub struct Display {
+    instance: wgpu::Instance,
     surface: wgpu::Surface<'static>,
     pub window: Arc<Window>,
     pub config: wgpu::SurfaceConfiguration,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
+    pub capabilities: Capabilities,
+    adapter: wgpu::Adapter,
+    msaa: MsaaTarget,
+    /// Saves a [diagnostics::dump] and the next frame when pressed.
+    /// Defaults to F9.
+    pub diagnostics_key: KeyCode,
+    /// Set by [Display::diagnostics_key] and saved by the next
+    /// [Display::present], or after [Demo::render] if it didn't call it
+    bug_report_requested: bool,
+    pub input: Input,
+    /// Saved on exit when changed
+    pub settings: Settings,
+    saved_settings: Settings,
+    pub bookmarks: CameraBookmarks,
+    pub time: Time,
+    pub replay: InputReplay,
+    pub deletion_queue: DeletionQueue,
+    /// Tallies for the frame being drawn, see [CountingPass]
+    pub frame_stats: FrameStats,
+    last_frame_stats: FrameStats,
+    /// Drawn over the frame by [Display::present] while shown
+    pub stats_overlay: StatsOverlay,
+    aspect_lock: Option<f32>,
+    /// Pauses [Display::time] and shows a [PauseOverlay] while the window
+    /// is unfocused. Defaults to true.
+    pub pause_on_focus_loss: bool,
+    pause_overlay: PauseOverlay,
+    focused: bool,
+    focus_pause: pause::FocusPause,
+    cursor_grabbed: bool,
+    #[cfg(feature = "renderdoc")]
+    pub renderdoc: RenderDocCapture,
+    #[cfg(feature = "gui")]
+    pub gui: gui::Gui,
 }
 
 impl Display {
     pub async fn new(window: Window) -> Result<Display, Error> {
         let window = Arc::new(window);
         let size = window.inner_size();
+        #[cfg(feature = "renderdoc")]
+        let renderdoc = RenderDocCapture::new();
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             #[cfg(not(target_arch = "wasm32"))]
             backends: wgpu::Backends::PRIMARY,
@@ -58,7 +244,7 @@ impl Display {
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    required_features: wgpu::Features::empty(),
+                    required_features: adapter.features() & Capabilities::WANTED_FEATURES,
                     // WebGL doesn't support all of wgpu's features, so if
                     // we're building for the web we'll have to disable some.
                     required_limits: if cfg!(target_arch = "wasm32") {
@@ -72,10 +258,15 @@ impl Display {
             )
             .await
             .unwrap();
+        let capabilities = Capabilities::new(&adapter, &device);
+        let settings = Settings::load_or_default(Settings::PATH);
+        let mut input = Input::new();
+        input.mouse_sensitivity = settings.mouse_sensitivity;
+        input.set_scale_factor(window.scale_factor());
         let surface_caps = surface.get_capabilities(&adapter);
         // Shader code in this tutorial assumes an Srgb surface texture. Using a different
-        // one will result all the colors comming out darker. If you want to support non
-        // Srgb surfaces, you'll need to account for that when drawing to the frame.
+        // one will result all the colors comming out darker. Demos drawing through an
+        // HdrPipeline don't need to care, as its tonemapping pass encodes for either.
         let surface_format = surface_caps
             .formats
             .iter()
@@ -92,13 +283,44 @@ impl Display {
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
         };
+        let pause_overlay = PauseOverlay::new(&device, config.format)?;
+        let stats_overlay = StatsOverlay::new(&device, config.format)?;
+        let msaa = MsaaTarget::new(&device, &adapter, &config, 1);
+        #[cfg(feature = "gui")]
+        let gui = gui::Gui::new(&device, &window, config.format);
 
         Ok(Self {
+            instance,
             surface,
             window,
             config,
             device,
             queue,
+            capabilities,
+            adapter,
+            msaa,
+            diagnostics_key: KeyCode::F9,
+            bug_report_requested: false,
+            input,
+            settings: settings.clone(),
+            saved_settings: settings,
+            bookmarks: CameraBookmarks::new(),
+            time: Time::new(),
+            replay: InputReplay::from_env()?,
+            deletion_queue: DeletionQueue::new(),
+            frame_stats: FrameStats::default(),
+            last_frame_stats: FrameStats::default(),
+            stats_overlay,
+            aspect_lock: None,
+            pause_on_focus_loss: true,
+            pause_overlay,
+            focused: true,
+            focus_pause: Default::default(),
+            cursor_grabbed: false,
+            #[cfg(feature = "renderdoc")]
+            renderdoc,
+            #[cfg(feature = "gui")]
+            gui,
         })
     }
 
@@ -110,11 +332,234 @@ impl Display {
         self.config.width = width;
         self.config.height = height;
         self.surface.configure(&self.device, &self.config);
+        self.msaa.resize(&self.device, &self.config);
+    }
+
+    /// Turns on MSAA with up to `count` samples, or off with 1. Pipelines
+    /// drawing to the surface need to match, see
+    /// [RenderPipelineBuilder::for_display], so demos normally set
+    /// [Demo::SAMPLE_COUNT] rather than calling this.
+    pub fn set_sample_count(&mut self, count: u32) {
+        self.msaa = MsaaTarget::new(&self.device, &self.adapter, &self.config, count);
+    }
+
+    /// What [Display::set_sample_count] settled on
+    pub fn sample_count(&self) -> u32 {
+        self.msaa.sample_count()
+    }
+
+    /// The color attachment for drawing to `frame`, a view of the surface
+    /// texture, through the MSAA target when there is one
+    pub fn color_attachment<'a>(
+        &'a self,
+        frame: &'a wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        self.msaa.color_attachment(frame, load)
+    }
+
+    /// A depth texture the size of the surface with the same sample count
+    pub fn create_depth_texture(&self) -> texture::Texture<'static> {
+        texture::Texture::create_depth_texture_multisampled(
+            &self.device,
+            &self.config,
+            self.sample_count(),
+        )
+    }
+
+    /// Lets frames be copied out of the surface, which [GifRecorder]
+    /// needs. This can be slower on some platforms, so it's off by default.
+    pub fn enable_readback(&mut self) {
+        self.config.usage |= wgpu::TextureUsages::COPY_SRC;
+        self.surface.configure(&self.device, &self.config);
+    }
+
+    /// Keeps [Display::viewport] at a fixed aspect ratio, with bars on
+    /// the sides or top and bottom to fill the rest of the window. `None`
+    /// uses the whole window.
+    pub fn lock_aspect_ratio(&mut self, aspect: Option<f32>) {
+        self.aspect_lock = aspect;
+    }
+
+    /// Where the demo should draw, see [RenderPassExt::set_viewport_rect].
+    /// The bars around it are whatever the render pass clears to.
+    pub fn viewport(&self) -> ViewportRect {
+        let full = ViewportRect::full(self.config.width, self.config.height);
+        match self.aspect_lock {
+            Some(aspect) => full.letterbox(aspect),
+            None => full,
+        }
+    }
+
+    /// [Display::frame_stats] from the last finished frame
+    pub fn last_frame_stats(&self) -> FrameStats {
+        self.last_frame_stats
+    }
+
+    /// What's alive on the device right now, or `None` when running on
+    /// the browser's WebGPU, which doesn't say
+    pub fn resource_counts(&self) -> Option<ResourceCounts> {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self
+            .instance
+            .generate_report()
+            .map(|report| report.hub_report(self.adapter.get_info().backend).into());
+        #[cfg(target_arch = "wasm32")]
+        None
+    }
+
+    /// Draws the framework's overlays, like the [StatsOverlay], over
+    /// `frame` and presents it. Demos call this in place of
+    /// [wgpu::SurfaceTexture::present] once the frame is drawn.
+    pub fn present(&mut self, frame: wgpu::SurfaceTexture) {
+        if self.stats_overlay.is_visible() {
+            let view = frame.texture.create_view(&Default::default());
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Display::present"),
+                });
+            let resources = self.resource_counts();
+            self.stats_overlay.render(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                &view,
+                self.config.width,
+                self.config.height,
+                &self.frame_stats,
+                resources.as_ref(),
+            );
+            self.queue.submit([encoder.finish()]);
+        }
+        if self.bug_report_requested {
+            self.save_bug_report(Some(&frame.texture));
+        }
+        frame.present();
+    }
+
+    fn save_bug_report(&mut self, frame: Option<&wgpu::Texture>) {
+        self.bug_report_requested = false;
+        match diagnostics::save(self, frame) {
+            Result::Ok(path) => log::info!("Saved {}", path.display()),
+            Err(e) => log::error!("Unable to save diagnostics: {:?}", e),
+        }
     }
 
     pub fn surface(&self) -> &wgpu::Surface {
         &self.surface
     }
+
+    pub fn adapter(&self) -> &wgpu::Adapter {
+        &self.adapter
+    }
+
+    /// Writes [Display::settings] to [Settings::PATH] if they've changed
+    /// since they were loaded or last saved
+    pub fn save_settings(&mut self) -> Result<()> {
+        if self.settings != self.saved_settings {
+            self.settings.save(Settings::PATH)?;
+            self.saved_settings = self.settings.clone();
+        }
+        Ok(())
+    }
+
+    /// Hides the cursor and keeps it in the window, for mouse look. The
+    /// grab is released while the window is unfocused and taken back when
+    /// it returns.
+    pub fn set_cursor_grab(&mut self, grab: bool) {
+        self.cursor_grabbed = grab;
+        self.apply_cursor_grab(grab);
+    }
+
+    pub fn is_cursor_grabbed(&self) -> bool {
+        self.cursor_grabbed
+    }
+
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    /// Whether the demo is paused because the window lost focus. It isn't
+    /// updated or rendered until focus comes back.
+    pub fn is_focus_paused(&self) -> bool {
+        self.focus_pause.is_paused()
+    }
+
+    /// The random stream called `name` off [InputReplay::seed], so it
+    /// repeats when a recording is played back or `FRAMEWORK_SEED` is set.
+    /// Each system should take its own, see [Rng::stream].
+    pub fn rng(&self, name: &str) -> Rng {
+        Rng::stream(self.replay.seed(), name)
+    }
+
+    /// Draws the ui [Demo::ui] built this frame over `frame`, a view of
+    /// the surface texture. Call it last, so the ui ends up on top.
+    #[cfg(feature = "gui")]
+    pub fn render_gui(&mut self, encoder: &mut wgpu::CommandEncoder, frame: &wgpu::TextureView) {
+        self.gui.render(
+            &self.device,
+            &self.queue,
+            encoder,
+            frame,
+            self.config.width,
+            self.config.height,
+        );
+    }
+
+    fn apply_cursor_grab(&self, grab: bool) {
+        let result = if grab {
+            // Not every platform supports both modes
+            self.window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            self.window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(e) = result {
+            log::warn!("Unable to grab the cursor: {}", e);
+        }
+        self.window.set_cursor_visible(!grab);
+    }
+
+    fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        if self.cursor_grabbed {
+            self.apply_cursor_grab(focused);
+        }
+        if self
+            .focus_pause
+            .set_focused(focused, self.pause_on_focus_loss, &mut self.time)
+        {
+            self.window.request_redraw();
+        }
+    }
+
+    fn render_pause_overlay(&mut self) {
+        let frame = match self.surface.get_current_texture() {
+            Result::Ok(frame) => frame,
+            Err(wgpu::SurfaceError::Outdated) => return,
+            Err(e) => {
+                log::error!("Unable to draw the pause overlay: {}", e);
+                return;
+            }
+        };
+        let view = frame.texture.create_view(&Default::default());
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("PauseOverlay"),
+            });
+        self.pause_overlay.render(
+            &self.queue,
+            &mut encoder,
+            &view,
+            self.config.width,
+            self.config.height,
+        );
+        self.queue.submit([encoder.finish()]);
+        frame.present();
+    }
 }
 
 /**
@@ -125,6 +570,8 @@ impl Display {
 pub struct UniformData {
     view_position: cgmath::Vector4<f32>,
     view_proj: cgmath::Matrix4<f32>,
+    /// Last frame's view_proj, for [MotionBlur]'s velocity
+    prev_view_proj: cgmath::Matrix4<f32>,
 }
 
 unsafe impl bytemuck::Zeroable for UniformData {}
@@ -133,6 +580,8 @@ unsafe impl bytemuck::Pod for UniformData {}
 pub struct CameraUniform {
     data: UniformData,
     buffer: wgpu::Buffer,
+    /// Whether view_proj holds a real frame yet
+    history: bool,
 }
 
 impl CameraUniform {
@@ -140,6 +589,7 @@ impl CameraUniform {
         let data = UniformData {
             view_position: Zero::zero(),
             view_proj: cgmath::Matrix4::identity(),
+            prev_view_proj: cgmath::Matrix4::identity(),
         };
         let buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Camera Buffer"),
@@ -147,12 +597,30 @@ impl CameraUniform {
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
         });
 
-        Self { data, buffer }
+        Self {
+            data,
+            buffer,
+            history: false,
+        }
     }
 
+    /// Call once a frame, the previous matrix is kept for velocity
     pub fn update_view_proj(&mut self, camera: &camera::Camera, projection: &camera::Projection) {
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
         self.data.view_position = camera.position.to_homogeneous();
-        self.data.view_proj = projection.calc_matrix() * camera.calc_matrix()
+        self.data.prev_view_proj = if self.history {
+            self.data.view_proj
+        } else {
+            view_proj
+        };
+        self.data.view_proj = view_proj;
+        self.history = true;
+    }
+
+    /// Forgets the previous frame's matrix, so a camera cut doesn't
+    /// smear the next frame
+    pub fn reset_history(&mut self) {
+        self.history = false;
     }
 
     pub fn update_buffer(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
@@ -225,7 +693,24 @@ pub trait Demo: 'static + Sized {
     fn process_keyboard(&mut self, key: KeyCode, pressed: bool);
     fn resize(&mut self, display: &Display);
     fn update(&mut self, display: &Display, dt: Duration);
+    /// Draws a frame of the surface. Finish with [Display::present] so
+    /// the framework's overlays end up on top.
     fn render(&mut self, display: &mut Display);
+
+    /// Samples per pixel for [Display::color_attachment]. 4 turns on 4x
+    /// MSAA, falling back to fewer samples where that isn't supported.
+    const SAMPLE_COUNT: u32 = 1;
+
+    /// Lets the framework move the camera, for [CameraBookmarks]
+    fn camera_mut(&mut self) -> Option<&mut Camera> {
+        None
+    }
+
+    /// Adds egui windows and panels for this frame. Called after
+    /// [Demo::update], and drawn when [Demo::render] calls
+    /// [Display::render_gui].
+    #[cfg(feature = "gui")]
+    fn ui(&mut self, _ctx: &egui::Context) {}
 }
 
 enum App<D: Demo> {
@@ -263,7 +748,10 @@ impl<D: Demo> ApplicationHandler for App<D> {
                 .expect("Couldn't append canvas to document body.");
         }
 
-        let display = pollster::block_on(Display::new(window)).unwrap();
+        let mut display = pollster::block_on(Display::new(window)).unwrap();
+        if D::SAMPLE_COUNT != 1 {
+            display.set_sample_count(D::SAMPLE_COUNT);
+        }
         let demo = D::init(&display).unwrap();
         *self = App::Initialized { display, demo };
     }
@@ -276,6 +764,10 @@ impl<D: Demo> ApplicationHandler for App<D> {
     ) {
         if let App::Initialized { display, demo } = self {
             if window_id == display.window().id() {
+                #[cfg(feature = "gui")]
+                if display.gui.on_window_event(&display.window, &event) {
+                    return;
+                }
                 match event {
                     WindowEvent::CloseRequested
                     | WindowEvent::KeyboardInput {
@@ -286,7 +778,15 @@ impl<D: Demo> ApplicationHandler for App<D> {
                                 ..
                             },
                         ..
-                    } => event_loop.exit(),
+                    } => {
+                        if let Err(e) = display.replay.finish() {
+                            log::error!("Unable to save input recording: {:?}", e);
+                        }
+                        if let Err(e) = display.save_settings() {
+                            log::error!("Unable to save settings: {:?}", e);
+                        }
+                        event_loop.exit();
+                    }
                     WindowEvent::KeyboardInput {
                         event:
                             KeyEvent {
@@ -296,16 +796,113 @@ impl<D: Demo> ApplicationHandler for App<D> {
                             },
                         ..
                     } => {
-                        demo.process_keyboard(key_code, state.is_pressed());
+                        #[cfg(feature = "renderdoc")]
+                        display.renderdoc.process_key(key_code, state.is_pressed());
+                        if state.is_pressed() && key_code == display.diagnostics_key {
+                            // The screenshot is copied out of the surface
+                            if !display.config.usage.contains(wgpu::TextureUsages::COPY_SRC) {
+                                display.enable_readback();
+                            }
+                            display.bug_report_requested = true;
+                        }
+                        let event = InputEvent::Key {
+                            key: key_code,
+                            pressed: state.is_pressed(),
+                        };
+                        dispatch_live(display, demo, event);
+                    }
+                    WindowEvent::MouseInput { button, state, .. } => {
+                        let event = InputEvent::MouseButton {
+                            button,
+                            pressed: state.is_pressed(),
+                        };
+                        dispatch_live(display, demo, event);
+                    }
+                    WindowEvent::CursorMoved { position, .. } => {
+                        let event = InputEvent::Cursor(Some((position.x, position.y)));
+                        dispatch_live(display, demo, event);
+                    }
+                    WindowEvent::CursorLeft { .. } => {
+                        dispatch_live(display, demo, InputEvent::Cursor(None));
+                    }
+                    WindowEvent::MouseWheel { delta, .. } => {
+                        let event = InputEvent::Scroll(input::scroll_lines(delta));
+                        dispatch_live(display, demo, event);
                     }
                     WindowEvent::Resized(physical_size) => {
                         log::info!("physical_size: {physical_size:?}");
                         display.resize(physical_size.width, physical_size.height);
                     }
+                    WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                        display.input.set_scale_factor(scale_factor);
+                    }
+                    WindowEvent::Focused(focused) => {
+                        if !focused {
+                            // The releases go to whichever window has focus
+                            // now, so they'd stay held forever
+                            let keys = display.input.keys_held().collect::<Vec<_>>();
+                            for key in keys {
+                                let event = InputEvent::Key {
+                                    key,
+                                    pressed: false,
+                                };
+                                dispatch_live(display, demo, event);
+                            }
+                            let buttons = display.input.buttons_held().collect::<Vec<_>>();
+                            for button in buttons {
+                                let event = InputEvent::MouseButton {
+                                    button,
+                                    pressed: false,
+                                };
+                                dispatch_live(display, demo, event);
+                            }
+                        }
+                        display.set_focused(focused);
+                    }
+                    WindowEvent::RedrawRequested if display.is_focus_paused() => {
+                        // Redrawn when the window is exposed or resized, but
+                        // otherwise idle until focus comes back
+                        display.render_pause_overlay();
+                    }
                     WindowEvent::RedrawRequested => {
                         // This tells winit that we want another frame after this one
                         display.window().request_redraw();
-                        demo.render(display);
+                        display.stats_overlay.begin_frame();
+                        display.input.mouse_sensitivity = display.settings.mouse_sensitivity;
+                        let (dt, replayed) = display
+                            .replay
+                            .begin_frame(&mut display.time, &mut display.input);
+                        for event in replayed {
+                            notify_demo(demo, &display.input, &event);
+                        }
+                        if let Some(camera) = demo.camera_mut() {
+                            display.bookmarks.update(
+                                &display.input,
+                                &mut display.settings,
+                                camera,
+                                display.time.delta_secs(),
+                            );
+                        }
+                        display.stats_overlay.update(&display.input);
+                        {
+                            cpu_scope!("update");
+                            demo.update(display, dt);
+                        }
+                        #[cfg(feature = "gui")]
+                        display.gui.run(&display.window, |ctx| demo.ui(ctx));
+                        {
+                            cpu_scope!("render");
+                            demo.render(display);
+                        }
+                        if display.bug_report_requested {
+                            display.save_bug_report(None);
+                        }
+                        display
+                            .deletion_queue
+                            .end_frame(&display.device, &display.queue);
+                        display.last_frame_stats = std::mem::take(&mut display.frame_stats);
+                        display.input.end_frame();
+                        end_cpu_frame();
                     }
                     _ => {}
                 }
@@ -319,10 +916,13 @@ impl<D: Demo> ApplicationHandler for App<D> {
         _device_id: DeviceId,
         event: DeviceEvent,
     ) {
-        if let App::Initialized { demo, .. } = self {
+        if let App::Initialized { display, demo } = self {
             match event {
-                DeviceEvent::MouseMotion { delta } => {
-                    demo.process_mouse(delta.0, delta.1);
+                // Some platforms send raw motion to unfocused windows too
+                DeviceEvent::MouseMotion { delta }
+                    if display.is_focused() && !gui_wants_pointer(display) =>
+                {
+                    dispatch_live(display, demo, InputEvent::MouseMotion(delta.0, delta.1));
                 }
                 _ => {}
             }
@@ -330,6 +930,36 @@ impl<D: Demo> ApplicationHandler for App<D> {
     }
 }
 
+/// Whether the mouse is dragging a slider or similar, and shouldn't also
+/// turn the camera
+fn gui_wants_pointer(_display: &Display) -> bool {
+    #[cfg(feature = "gui")]
+    return _display.gui.wants_pointer();
+    #[cfg(not(feature = "gui"))]
+    false
+}
+
+/// Sends a live event to the input and demo, unless a replay is playing
+fn dispatch_live<D: Demo>(display: &mut Display, demo: &mut D, event: InputEvent) {
+    if display.replay.capture(&event) {
+        event.apply(&mut display.input);
+        notify_demo(demo, &display.input, &event);
+    }
+}
+
+/// Mouse motion is normalized like [Input::mouse_delta], so demos that
+/// use [Demo::process_mouse] get the same sensitivity
+fn notify_demo<D: Demo>(demo: &mut D, input: &Input, event: &InputEvent) {
+    match *event {
+        InputEvent::Key { key, pressed } => demo.process_keyboard(key, pressed),
+        InputEvent::MouseMotion(dx, dy) => {
+            let (dx, dy) = input.normalize_mouse_motion(dx, dy);
+            demo.process_mouse(dx, dy)
+        }
+        _ => {}
+    }
+}
+
 pub fn run<D: Demo>() -> Result<()> {
     env_logger::init();
 
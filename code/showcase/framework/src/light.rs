@@ -1,6 +1,8 @@
 use cgmath::*;
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
+use crate::units::{self, Exposure};
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct LightData {
@@ -38,3 +40,340 @@ pub struct LightBinding {
     pub layout: wgpu::BindGroupLayout,
     pub bind_group: wgpu::BindGroup,
 }
+
+/// WGSL for reading a [LightBuffer] bound to group 2. It has
+/// `pbr_lights(surface, n, v, world_position)`, which adds up `pbr_light`
+/// for every light, so it needs [crate::PBR_WGSL] or [crate::BRDF_WGSL]
+/// in front.
+pub const LIGHTS_WGSL: &str = include_str!("lights.wgsl");
+
+/// A light for [LightBuffer], laid out to match the WGSL
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLight {
+    pub position: [f32; 3],
+    /// How far the light reaches. 0 means it never fades, like the
+    /// tutorials' light.
+    pub range: f32,
+    pub color: [f32; 3],
+    /// Scales `color`. Set from physical units with
+    /// [PointLight::with_lumens] or [PointLight::with_candela].
+    pub intensity: f32,
+}
+
+impl PointLight {
+    pub fn new<P: Into<Point3<f32>>>(position: P, color: [f32; 3], range: f32) -> Self {
+        Self {
+            position: position.into().into(),
+            range,
+            color,
+            intensity: 1.0,
+        }
+    }
+
+    /// As bright as a bulb giving off `lumens`, seen through `exposure`.
+    /// A 60 W equivalent bulb is about 800.
+    pub fn with_lumens(self, lumens: f32, exposure: &Exposure) -> Self {
+        self.with_candela(units::lumens_to_candela(lumens), exposure)
+    }
+
+    pub fn with_candela(mut self, candela: f32, exposure: &Exposure) -> Self {
+        self.intensity = exposure.expose(candela);
+        self
+    }
+}
+
+/// A light shining in a cone, like a flashlight or stage light. Laid out
+/// to match the WGSL for [LightBuffer].
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SpotLight {
+    pub position: [f32; 3],
+    /// Like [PointLight::range]
+    pub range: f32,
+    pub color: [f32; 3],
+    /// Scales `color`. Set from physical units with
+    /// [SpotLight::with_lumens] or [SpotLight::with_candela].
+    pub intensity: f32,
+    /// The way the light points. It doesn't have to be normalized.
+    pub direction: [f32; 3],
+    /// The cosine of the angle from `direction` where the light starts
+    /// fading out. Set both cones with [SpotLight::with_cone].
+    pub inner_cos: f32,
+    /// The cosine of the angle where the light has faded out completely
+    pub outer_cos: f32,
+    _padding: [f32; 3],
+}
+
+impl SpotLight {
+    /// A light with a cone 40 degrees across that fades out over its last
+    /// 10 degrees
+    pub fn new<P: Into<Point3<f32>>, D: Into<Vector3<f32>>>(
+        position: P,
+        direction: D,
+        color: [f32; 3],
+        range: f32,
+    ) -> Self {
+        Self {
+            position: position.into().into(),
+            range,
+            color,
+            intensity: 1.0,
+            direction: direction.into().into(),
+            inner_cos: 0.0,
+            outer_cos: 0.0,
+            _padding: [0.0; 3],
+        }
+        .with_cone(Deg(10.0), Deg(20.0))
+    }
+
+    /// Sets the cone from angles between `direction` and its edge. The
+    /// light is full strength inside `inner` and fades out by `outer`.
+    pub fn with_cone<A: Into<Rad<f32>>>(mut self, inner: A, outer: A) -> Self {
+        let (inner, outer) = (inner.into(), outer.into());
+        self.outer_cos = outer.cos();
+        // An inner cone bigger than the outer one would flip the fade
+        self.inner_cos = if inner > outer { outer } else { inner }.cos();
+        self
+    }
+
+    /// As bright as a lamp giving off `lumens` into its cone, seen through
+    /// `exposure`. Comes after [SpotLight::with_cone], since a narrower
+    /// cone makes the same lumens brighter.
+    pub fn with_lumens(self, lumens: f32, exposure: &Exposure) -> Self {
+        let candela = units::spot_lumens_to_candela(lumens, self.outer_cos);
+        self.with_candela(candela, exposure)
+    }
+
+    pub fn with_candela(mut self, candela: f32, exposure: &Exposure) -> Self {
+        self.intensity = exposure.expose(candela);
+        self
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightCount {
+    point_lights: u32,
+    spot_lights: u32,
+    _padding: [u32; 2],
+}
+
+/// Any number of [PointLight]s and [SpotLight]s in storage buffers, for
+/// shaders that start with [LIGHTS_WGSL]. The buffers grow to fit, so get
+/// [LightBuffer::bind_group] after [LightBuffer::update] rather than
+/// holding on to it. Storage buffers aren't on WebGL2, see
+/// [Capabilities::fragment_storage_buffers](crate::Capabilities).
+///
+/// ```ignore
+/// let mut lights = LightBuffer::new(&display.device, 8);
+/// let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+///     bind_group_layouts: &[&material_layout, &camera_layout, &lights.layout],
+///     ..
+/// });
+///
+/// // In Demo::render
+/// lights.update(&display.device, &display.queue, &self.point_lights, &self.spot_lights);
+/// pass.set_bind_group(2, &lights.bind_group, &[]);
+/// ```
+pub struct LightBuffer {
+    pub layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    count: wgpu::Buffer,
+    point_lights: LightStorage<PointLight>,
+    spot_lights: LightStorage<SpotLight>,
+}
+
+impl LightBuffer {
+    /// Has room for `capacity` lights of each type before it has to grow
+    pub fn new(device: &wgpu::Device, capacity: usize) -> Self {
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("LightBuffer::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1),
+                storage_entry(2),
+            ],
+        });
+        let count = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("LightBuffer::count"),
+            contents: bytemuck::bytes_of(&LightCount {
+                point_lights: 0,
+                spot_lights: 0,
+                _padding: [0; 2],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let point_lights = LightStorage::new(device, "LightBuffer::point_lights", capacity);
+        let spot_lights = LightStorage::new(device, "LightBuffer::spot_lights", capacity);
+        let bind_group = bind_group(device, &layout, &count, &point_lights, &spot_lights);
+        Self {
+            layout,
+            bind_group,
+            count,
+            point_lights,
+            spot_lights,
+        }
+    }
+
+    /// How many point lights fit before the buffer has to grow
+    pub fn capacity(&self) -> usize {
+        self.point_lights.capacity
+    }
+
+    /// How many spot lights fit before the buffer has to grow
+    pub fn spot_capacity(&self) -> usize {
+        self.spot_lights.capacity
+    }
+
+    /// Uploads the lights. If there are more than fit, the buffer is
+    /// replaced with one sized to the next power of two and
+    /// [LightBuffer::bind_group] is remade. Returns whether that happened.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        point_lights: &[PointLight],
+        spot_lights: &[SpotLight],
+    ) -> bool {
+        let grew = self.point_lights.write(device, queue, point_lights)
+            | self.spot_lights.write(device, queue, spot_lights);
+        if grew {
+            self.bind_group = bind_group(
+                device,
+                &self.layout,
+                &self.count,
+                &self.point_lights,
+                &self.spot_lights,
+            );
+        }
+        let count = LightCount {
+            point_lights: point_lights.len() as u32,
+            spot_lights: spot_lights.len() as u32,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.count, 0, bytemuck::bytes_of(&count));
+        grew
+    }
+}
+
+struct LightStorage<T> {
+    label: &'static str,
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    _marker: std::marker::PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> LightStorage<T> {
+    fn new(device: &wgpu::Device, label: &'static str, capacity: usize) -> Self {
+        // Empty storage buffers can't be bound
+        let capacity = capacity.max(1);
+        Self {
+            label,
+            buffer: Self::create_buffer(device, label, capacity),
+            capacity,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, label: &str, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (std::mem::size_of::<T>() * capacity) as _,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Returns whether the buffer had to be replaced to fit `lights`
+    fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lights: &[T]) -> bool {
+        let grew = lights.len() > self.capacity;
+        if grew {
+            self.capacity = lights.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.label, self.capacity);
+        }
+        if !lights.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(lights));
+        }
+        grew
+    }
+}
+
+fn bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    count: &wgpu::Buffer,
+    point_lights: &LightStorage<PointLight>,
+    spot_lights: &LightStorage<SpotLight>,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("LightBuffer::bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: count.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: point_lights.buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: spot_lights.buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lights_wgsl_validates() {
+        let shader = r#"
+@fragment
+fn fs_main(@location(0) world_position: vec3<f32>, @location(1) n: vec3<f32>) -> @location(0) vec4<f32> {
+    var surface: Surface;
+    surface.albedo = vec4<f32>(1.0);
+    surface.roughness = 0.5;
+    let color = pbr_lights(surface, normalize(n), vec3<f32>(0.0, 0.0, 1.0), world_position);
+    return vec4<f32>(color, 1.0);
+}
+"#;
+        crate::shader::validate_wgsl(&format!("{}{}{}", crate::BRDF_WGSL, LIGHTS_WGSL, shader))
+            .unwrap();
+        // Have to match the WGSL structs' strides
+        assert_eq!(std::mem::size_of::<PointLight>(), 32);
+        assert_eq!(std::mem::size_of::<SpotLight>(), 64);
+    }
+
+    #[test]
+    fn spot_cones_stay_ordered() {
+        let light = SpotLight::new((0.0, 0.0, 0.0), (0.0, -1.0, 0.0), [1.0; 3], 10.0);
+        assert!(light.inner_cos > light.outer_cos);
+        assert!((light.outer_cos - Deg(20.0f32).cos()).abs() < 1e-6);
+        let light = light.with_cone(Deg(45.0), Deg(30.0));
+        assert_eq!(light.inner_cos, light.outer_cos);
+    }
+}
@@ -0,0 +1,74 @@
+// Reads a LightBuffer bound to group 2. Needs brdf.wgsl in front, which
+// PBR_WGSL and BRDF_WGSL both are.
+
+struct PointLight {
+    position: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+}
+
+struct SpotLight {
+    position: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    direction: vec3<f32>,
+    // Cosines of the angles where the cone starts and finishes fading
+    inner_cos: f32,
+    outer_cos: f32,
+}
+
+struct LightCount {
+    point_lights: u32,
+    spot_lights: u32,
+}
+
+@group(2) @binding(0)
+var<uniform> light_count: LightCount;
+@group(2) @binding(1)
+var<storage, read> point_lights: array<PointLight>;
+@group(2) @binding(2)
+var<storage, read> spot_lights: array<SpotLight>;
+
+// How much of a light reaches `d` away. Lights without a range never fade,
+// like the tutorials' light.
+fn light_attenuation(range: f32, d: f32) -> f32 {
+    if (range <= 0.0) {
+        return 1.0;
+    }
+    // Inverse square, faded out to reach 0 at the range
+    let fade = clamp(1.0 - pow(d / range, 4.0), 0.0, 1.0);
+    return fade * fade / max(d * d, 0.0001);
+}
+
+// How much of a spot light's cone reaches along `l`, which points from the
+// surface to the light
+fn spot_attenuation(light: SpotLight, l: vec3<f32>) -> f32 {
+    let cos_angle = dot(normalize(light.direction), -l);
+    let t = clamp((cos_angle - light.outer_cos) / max(light.inner_cos - light.outer_cos, 0.0001), 0.0, 1.0);
+    return t * t;
+}
+
+// pbr_light for every light in the buffer, added up
+fn pbr_lights(surface: Surface, n: vec3<f32>, v: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    var color = vec3<f32>(0.0);
+    let points = min(light_count.point_lights, arrayLength(&point_lights));
+    for (var i = 0u; i < points; i++) {
+        let light = point_lights[i];
+        let to_light = light.position - world_position;
+        let d = length(to_light);
+        let radiance = light.color * light.intensity * light_attenuation(light.range, d);
+        color += pbr_light(surface, n, v, to_light / max(d, 0.0001), radiance);
+    }
+    let spots = min(light_count.spot_lights, arrayLength(&spot_lights));
+    for (var i = 0u; i < spots; i++) {
+        let light = spot_lights[i];
+        let to_light = light.position - world_position;
+        let d = length(to_light);
+        let l = to_light / max(d, 0.0001);
+        let attenuation = light_attenuation(light.range, d) * spot_attenuation(light, l);
+        color += pbr_light(surface, n, v, l, light.color * light.intensity * attenuation);
+    }
+    return color;
+}
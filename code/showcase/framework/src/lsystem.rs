@@ -0,0 +1,314 @@
+use cgmath::*;
+use std::collections::HashMap;
+
+use crate::jobs::JobPool;
+use crate::model::{compute_tangents, Mesh, ModelVertex};
+use crate::rng::Rng;
+use crate::scene::Transform;
+
+/// Rewrites a string of symbols over and over, each time replacing every
+/// symbol that has a rule with that rule's string. With the turtle symbols
+/// [LSystem::interpret] understands this grows plants out of a few short
+/// rules.
+///
+/// ```ignore
+/// let tree = LSystem::tree().interpret(5, &TreeShape::default());
+/// let branches = tree.branch_mesh(&display.device, 0);
+/// let leaves = Mesh::leaf_quad(&display.device, 1);
+/// let leaf_instances = tree.leaf_instance_data();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct LSystem {
+    pub axiom: String,
+    rules: HashMap<char, String>,
+}
+
+impl LSystem {
+    pub fn new(axiom: &str) -> Self {
+        Self {
+            axiom: axiom.to_string(),
+            rules: HashMap::new(),
+        }
+    }
+
+    /// Replaces `symbol` with `replacement` on every iteration
+    pub fn rule(&mut self, symbol: char, replacement: &str) -> &mut Self {
+        self.rules.insert(symbol, replacement.to_string());
+        self
+    }
+
+    /// A bushy tree that splits in three, with leaves on every twig. 4 to 6
+    /// iterations look good.
+    pub fn tree() -> Self {
+        let mut system = Self::new("FFA");
+        system.rule('A', "[&FL!A]/////[&FL!A]///////[&FL!A]");
+        system
+    }
+
+    pub fn expand(&self, iterations: u32) -> String {
+        let mut current = self.axiom.clone();
+        for _ in 0..iterations {
+            let mut next = String::with_capacity(current.len() * 2);
+            for symbol in current.chars() {
+                match self.rules.get(&symbol) {
+                    Some(replacement) => next.push_str(replacement),
+                    None => next.push(symbol),
+                }
+            }
+            current = next;
+        }
+        current
+    }
+
+    /// Expands the system and walks a turtle over the result to build the
+    /// tree
+    pub fn interpret(&self, iterations: u32, shape: &TreeShape) -> Tree {
+        Tree::from_symbols(&self.expand(iterations), shape)
+    }
+}
+
+/// What the turtle symbols do. The turtle starts at the origin heading up
+/// +y, and understands:
+///
+/// | Symbol | Meaning |
+/// |--------|---------|
+/// | `F` | Grow a branch segment forward |
+/// | `f` | Move forward without growing |
+/// | `+` `-` | Turn left and right |
+/// | `&` `^` | Pitch down and up |
+/// | `\` `/` | Roll left and right |
+/// | `\|` | Turn around |
+/// | `!` | Make the following branches thinner and shorter |
+/// | `[` `]` | Start and end a side branch |
+/// | `L` | Put a leaf here |
+///
+/// Anything else is ignored, so it can be used for rules.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeShape {
+    pub angle: Deg<f32>,
+    /// How far each turn can be randomly off by
+    pub angle_jitter: Deg<f32>,
+    pub length: f32,
+    pub radius: f32,
+    /// What `!` multiplies the length by
+    pub length_scale: f32,
+    /// What `!` multiplies the radius by
+    pub radius_scale: f32,
+    /// Vertices around each branch
+    pub sides: u32,
+    pub leaf_size: f32,
+    pub seed: u64,
+}
+
+impl Default for TreeShape {
+    fn default() -> Self {
+        Self {
+            angle: Deg(22.5),
+            angle_jitter: Deg(5.0),
+            length: 1.0,
+            radius: 0.1,
+            length_scale: 0.8,
+            radius_scale: 0.7,
+            sides: 6,
+            leaf_size: 0.3,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Turtle {
+    position: Point3<f32>,
+    rotation: Quaternion<f32>,
+    length: f32,
+    radius: f32,
+    /// How far along the branch texture v has got
+    v: f32,
+}
+
+/// Branches and leaf placements from [LSystem::interpret]. Branches are a
+/// single mesh, and leaves are transforms to draw one leaf mesh instanced.
+/// Each leaf's transform keeps it where it grew, so a vertex shader can
+/// sway leaves by their instance position for wind.
+pub struct Tree {
+    pub branch_vertices: Vec<ModelVertex>,
+    pub branch_indices: Vec<u32>,
+    pub leaves: Vec<Transform>,
+}
+
+impl Tree {
+    pub fn from_symbols(symbols: &str, shape: &TreeShape) -> Self {
+        let mut rng = Rng::new(shape.seed);
+        let mut jittered =
+            |angle: Deg<f32>| angle + shape.angle_jitter * (rng.next_f32() * 2.0 - 1.0);
+        let mut tree = Self {
+            branch_vertices: Vec::new(),
+            branch_indices: Vec::new(),
+            leaves: Vec::new(),
+        };
+        let mut turtle = Turtle {
+            position: Point3::origin(),
+            rotation: Quaternion::one(),
+            length: shape.length,
+            radius: shape.radius,
+            v: 0.0,
+        };
+        let mut stack = Vec::new();
+        for symbol in symbols.chars() {
+            let turn = match symbol {
+                '+' => Quaternion::from_angle_z(jittered(shape.angle)),
+                '-' => Quaternion::from_angle_z(-jittered(shape.angle)),
+                '&' => Quaternion::from_angle_x(jittered(shape.angle)),
+                '^' => Quaternion::from_angle_x(-jittered(shape.angle)),
+                '\\' => Quaternion::from_angle_y(jittered(shape.angle)),
+                '/' => Quaternion::from_angle_y(-jittered(shape.angle)),
+                '|' => Quaternion::from_angle_z(Deg(180.0)),
+                _ => Quaternion::one(),
+            };
+            turtle.rotation = (turtle.rotation * turn).normalize();
+            match symbol {
+                'F' => {
+                    let end = turtle.position + turtle.rotation * Vector3::unit_y() * turtle.length;
+                    tree.add_segment(&turtle, end, shape);
+                    turtle.v += turtle.length / (std::f32::consts::TAU * turtle.radius);
+                    turtle.position = end;
+                }
+                'f' => {
+                    turtle.position += turtle.rotation * Vector3::unit_y() * turtle.length;
+                }
+                '!' => {
+                    turtle.length *= shape.length_scale;
+                    turtle.radius *= shape.radius_scale;
+                }
+                '[' => stack.push(turtle),
+                ']' => {
+                    if let Some(saved) = stack.pop() {
+                        turtle = saved;
+                    }
+                }
+                'L' => tree.leaves.push(Transform::new(
+                    turtle.position.to_vec(),
+                    turtle.rotation,
+                    Vector3::new(shape.leaf_size, shape.leaf_size, shape.leaf_size),
+                )),
+                _ => {}
+            }
+        }
+        compute_tangents(&mut tree.branch_vertices, &tree.branch_indices);
+        tree
+    }
+
+    /// A ring of vertices at each end of the segment, with the far ring
+    /// a little thinner so branches taper
+    fn add_segment(&mut self, turtle: &Turtle, end: Point3<f32>, shape: &TreeShape) {
+        let sides = shape.sides.max(3);
+        let base = self.branch_vertices.len() as u32;
+        let length = end.distance(turtle.position);
+        let end_radius = turtle.radius * (1.0 - (1.0 - shape.radius_scale) * 0.5);
+        let v_end = turtle.v + length / (std::f32::consts::TAU * turtle.radius);
+        for (center, radius, v) in [
+            (turtle.position, turtle.radius, turtle.v),
+            (end, end_radius, v_end),
+        ] {
+            for side in 0..=sides {
+                let u = side as f32 / sides as f32;
+                let (sin, cos) = Rad(u * std::f32::consts::TAU).sin_cos();
+                let normal = turtle.rotation * Vector3::new(cos, 0.0, -sin);
+                self.branch_vertices.push(ModelVertex::new(
+                    (center + normal * radius).into(),
+                    [u, v],
+                    normal.into(),
+                ));
+            }
+        }
+        let ring = sides + 1;
+        for side in 0..sides {
+            let (a, b) = (base + side, base + side + 1);
+            let (c, d) = (a + ring, b + ring);
+            self.branch_indices.extend_from_slice(&[a, b, d, a, d, c]);
+        }
+    }
+
+    pub fn branch_mesh(&self, device: &wgpu::Device, material: usize) -> Mesh {
+        Mesh::from_vertices(
+            device,
+            "tree_branches",
+            &self.branch_vertices,
+            &self.branch_indices,
+            material,
+        )
+    }
+
+    /// Model matrices for the leaves in the tutorials' InstanceRaw layout
+    pub fn leaf_instance_data(&self) -> Vec<[[f32; 4]; 4]> {
+        JobPool::global().map(&self.leaves, 1024, |t| t.matrix().into())
+    }
+}
+
+impl Mesh {
+    /// A 1x1 quad standing up from the origin along +y, facing +z, with
+    /// the back facing -z so it's visible from both sides without turning
+    /// off culling. [Tree] leaves are placed for this.
+    pub fn leaf_quad(device: &wgpu::Device, material: usize) -> Self {
+        let mut vertices = Vec::with_capacity(8);
+        let mut indices = Vec::with_capacity(12);
+        for normal in [Vector3::unit_z(), -Vector3::unit_z()] {
+            let base = vertices.len() as u32;
+            for (u, v) in [(0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)] {
+                let position = [u - 0.5, 1.0 - v, 0.0];
+                vertices.push(ModelVertex::new(position, [u, v], normal.into()));
+            }
+            if normal.z > 0.0 {
+                indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+            } else {
+                indices.extend_from_slice(&[base, base + 2, base + 1, base, base + 3, base + 2]);
+            }
+        }
+        compute_tangents(&mut vertices, &indices);
+        Self::from_vertices(device, "leaf_quad", &vertices, &indices, material)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_rewrite_every_symbol() {
+        let mut system = LSystem::new("A");
+        system.rule('A', "AB").rule('B', "A");
+        assert_eq!(system.expand(0), "A");
+        assert_eq!(system.expand(4), "ABAABABA");
+    }
+
+    #[test]
+    fn turtle_grows_branches_and_leaves() {
+        let shape = TreeShape {
+            angle: Deg(90.0),
+            angle_jitter: Deg(0.0),
+            ..Default::default()
+        };
+        let tree = Tree::from_symbols("F[+FL]fL", &shape);
+        let ring = (shape.sides + 1) as usize;
+        assert_eq!(tree.branch_vertices.len(), ring * 4);
+        assert_eq!(tree.branch_indices.len(), shape.sides as usize * 6 * 2);
+        assert_eq!(tree.leaves.len(), 2);
+        // Turned left off the trunk, then back to the trunk and up
+        let side = tree.leaves[0].translation;
+        assert!(
+            side.distance(Vector3::new(-1.0, 1.0, 0.0)) < 1e-5,
+            "{:?}",
+            side
+        );
+        let top = tree.leaves[1].translation;
+        assert!(
+            top.distance(Vector3::new(0.0, 2.0, 0.0)) < 1e-5,
+            "{:?}",
+            top
+        );
+        // Seeded, so the same every time
+        let a = LSystem::tree().interpret(3, &TreeShape::default());
+        let b = LSystem::tree().interpret(3, &TreeShape::default());
+        assert_eq!(a.leaves, b.leaves);
+    }
+}
@@ -0,0 +1,551 @@
+use anyhow::*;
+use cgmath::*;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::model::{compute_tangents, Mesh, ModelVertex};
+use crate::shader::catch_validation_errors;
+
+const WORKGROUP_SIZE: u32 = 4;
+
+/// Corner `i` of a cell is at `(i & 1, (i >> 1) & 1, (i >> 2) & 1)`
+const CORNERS: [[u32; 3]; 8] = [
+    [0, 0, 0],
+    [1, 0, 0],
+    [0, 1, 0],
+    [1, 1, 0],
+    [0, 0, 1],
+    [1, 0, 1],
+    [0, 1, 1],
+    [1, 1, 1],
+];
+
+/// The corners at each end of the 12 edges of a cell. marching_cubes.wgsl
+/// has a copy.
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// Values sampled on a grid, like a signed distance field or noise.
+/// Anywhere below the iso level counts as inside.
+#[derive(Debug, Clone)]
+pub struct ScalarField {
+    size: [u32; 3],
+    values: Vec<f32>,
+    /// Where the sample at `[0, 0, 0]` is
+    pub origin: Point3<f32>,
+    /// The distance between samples
+    pub spacing: f32,
+}
+
+impl ScalarField {
+    /// A field of zeros, `size` samples along each axis
+    pub fn new(size: [u32; 3], origin: Point3<f32>, spacing: f32) -> Self {
+        assert!(
+            size.iter().all(|&s| s >= 2),
+            "Fields need 2 samples per axis"
+        );
+        Self {
+            size,
+            values: vec![0.0; (size[0] * size[1] * size[2]) as usize],
+            origin,
+            spacing,
+        }
+    }
+
+    /// Samples `f` at every point of the grid
+    ///
+    /// ```ignore
+    /// // Two metaballs
+    /// let field = ScalarField::from_fn([64; 3], Point3::new(-2.0, -2.0, -2.0), 4.0 / 63.0, |p| {
+    ///     let a = 0.5 / p.distance2(Point3::new(-0.5, 0.0, 0.0));
+    ///     let b = 0.5 / p.distance2(Point3::new(0.6, 0.2, 0.0));
+    ///     1.0 - (a + b)
+    /// });
+    /// let mesh = marching_cubes(&field, 0.0).mesh(&display.device, 0);
+    /// ```
+    pub fn from_fn(
+        size: [u32; 3],
+        origin: Point3<f32>,
+        spacing: f32,
+        f: impl Fn(Point3<f32>) -> f32,
+    ) -> Self {
+        let mut field = Self::new(size, origin, spacing);
+        for z in 0..size[2] {
+            for y in 0..size[1] {
+                for x in 0..size[0] {
+                    let value = f(field.position([x, y, z]));
+                    field.set([x, y, z], value);
+                }
+            }
+        }
+        field
+    }
+
+    pub fn size(&self) -> [u32; 3] {
+        self.size
+    }
+
+    /// Every sample, x changing fastest then y then z
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    fn index(&self, [x, y, z]: [u32; 3]) -> usize {
+        ((z * self.size[1] + y) * self.size[0] + x) as usize
+    }
+
+    pub fn get(&self, at: [u32; 3]) -> f32 {
+        self.values[self.index(at)]
+    }
+
+    pub fn set(&mut self, at: [u32; 3], value: f32) {
+        let index = self.index(at);
+        self.values[index] = value;
+    }
+
+    pub fn position(&self, [x, y, z]: [u32; 3]) -> Point3<f32> {
+        self.origin + Vector3::new(x as f32, y as f32, z as f32) * self.spacing
+    }
+
+    /// Which way the values increase, from the samples either side
+    fn gradient(&self, [x, y, z]: [u32; 3]) -> Vector3<f32> {
+        let axis = |i: usize| {
+            let mut lo = [x, y, z];
+            let mut hi = [x, y, z];
+            lo[i] = lo[i].saturating_sub(1);
+            hi[i] = (hi[i] + 1).min(self.size[i] - 1);
+            (self.get(hi) - self.get(lo)) / ((hi[i] - lo[i]) as f32 * self.spacing)
+        };
+        Vector3::new(axis(0), axis(1), axis(2))
+    }
+}
+
+/// The surface from [marching_cubes]. Normals point out of the inside.
+#[derive(Debug, Clone, Default)]
+pub struct IsoMesh {
+    pub positions: Vec<Point3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    pub indices: Vec<u32>,
+}
+
+impl IsoMesh {
+    /// Texture coordinates are the world x and z, which suits terrain
+    pub fn mesh(&self, device: &wgpu::Device, material: usize) -> Mesh {
+        let mut vertices = self
+            .positions
+            .iter()
+            .zip(&self.normals)
+            .map(|(p, n)| ModelVertex::new((*p).into(), [p.x, p.z], (*n).into()))
+            .collect::<Vec<_>>();
+        compute_tangents(&mut vertices, &self.indices);
+        Mesh::from_vertices(device, "marching_cubes", &vertices, &self.indices, material)
+    }
+}
+
+/// Turns the surface where `field` crosses `iso` into triangles. Vertices
+/// are shared between neighbouring cells, so the mesh is watertight and
+/// normals come from the field itself rather than the triangles.
+pub fn marching_cubes(field: &ScalarField, iso: f32) -> IsoMesh {
+    let table = triangle_table();
+    let mut mesh = IsoMesh::default();
+    // Keyed by the lower corner of the edge and its axis
+    let mut edge_vertices: HashMap<([u32; 3], usize), u32> = HashMap::new();
+    let [sx, sy, sz] = field.size;
+    for z in 0..sz - 1 {
+        for y in 0..sy - 1 {
+            for x in 0..sx - 1 {
+                let corner = |i: usize| {
+                    let [cx, cy, cz] = CORNERS[i];
+                    [x + cx, y + cy, z + cz]
+                };
+                let case = (0..8).fold(0, |case, i| {
+                    case | (((field.get(corner(i)) < iso) as usize) << i)
+                });
+                for &edge in table[case].iter() {
+                    let (a, b) = EDGES[edge];
+                    let (a, b) = (corner(a), corner(b));
+                    let axis = (0..3).find(|&i| a[i] != b[i]).unwrap();
+                    let index = *edge_vertices.entry((a, axis)).or_insert_with(|| {
+                        let (va, vb) = (field.get(a), field.get(b));
+                        let t = if va == vb {
+                            0.5
+                        } else {
+                            ((iso - va) / (vb - va)).clamp(0.0, 1.0)
+                        };
+                        let position =
+                            field.position(a) + (field.position(b) - field.position(a)) * t;
+                        let gradient = field.gradient(a).lerp(field.gradient(b), t);
+                        let normal = if gradient.magnitude2() > 0.0 {
+                            gradient.normalize()
+                        } else {
+                            Vector3::unit_y()
+                        };
+                        mesh.positions.push(position);
+                        mesh.normals.push(normal);
+                        mesh.positions.len() as u32 - 1
+                    });
+                    mesh.indices.push(index);
+                }
+            }
+        }
+    }
+    mesh
+}
+
+/// The edges to put triangle corners on for each of the 256 ways a cell's
+/// corners can be inside or out, three per triangle.
+///
+/// Rather than the usual hand written table, this works each case out by
+/// walking the faces of the cell. On each face the surface cuts off every
+/// run of inside corners with a line, keeping diagonal inside corners
+/// apart. Neighbouring cells make the same choice for the face they
+/// share, so there are no cracks. The lines join up into loops around the
+/// cell, which are split into triangle fans.
+fn triangle_table() -> &'static [Vec<usize>; 256] {
+    static TABLE: OnceLock<[Vec<usize>; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let edge_between = |a: usize, b: usize| {
+            EDGES
+                .iter()
+                .position(|&(x, y)| (x, y) == (a, b) || (x, y) == (b, a))
+                .unwrap()
+        };
+        // The corners of each face in order around its outward normal
+        let faces = [
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+        ];
+        let mut table: [Vec<usize>; 256] = std::array::from_fn(|_| Vec::new());
+        for (case, triangles) in table.iter_mut().enumerate() {
+            let inside = |corner: usize| case & (1 << corner) != 0;
+            // Each line goes from the edge where a run of inside corners
+            // starts to the one where it ends, so following them from one
+            // face to the next always goes the same way around
+            let mut next = [usize::MAX; 12];
+            for face in &faces {
+                for i in 0..4 {
+                    let (prev, corner) = (face[(i + 3) % 4], face[i]);
+                    if inside(corner) && !inside(prev) {
+                        let mut end = i;
+                        while inside(face[(end + 1) % 4]) {
+                            end = (end + 1) % 4;
+                        }
+                        let start = edge_between(prev, corner);
+                        next[start] = edge_between(face[end], face[(end + 1) % 4]);
+                    }
+                }
+            }
+            let mut visited = [false; 12];
+            for first in 0..12 {
+                if next[first] == usize::MAX || visited[first] {
+                    continue;
+                }
+                let mut ring = Vec::new();
+                let mut edge = first;
+                while !visited[edge] {
+                    visited[edge] = true;
+                    ring.push(edge);
+                    edge = next[edge];
+                }
+                for i in 1..ring.len() - 1 {
+                    triangles.extend_from_slice(&[ring[0], ring[i], ring[i + 1]]);
+                }
+            }
+        }
+        table
+    })
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MarchingCubesUniforms {
+    size: [u32; 3],
+    iso: f32,
+    origin: [f32; 3],
+    spacing: f32,
+    max_vertices: u32,
+    _padding: [u32; 3],
+}
+
+/// [marching_cubes] in a compute shader, for fields that change every
+/// frame. Triangles go straight into [GpuMarchingCubes::vertex_buffer] as
+/// unindexed [ModelVertex]es, with the count in
+/// [GpuMarchingCubes::indirect_buffer] so it never has to come back to
+/// the CPU. Needs [Capabilities::compute_shaders](crate::Capabilities).
+///
+/// ```ignore
+/// let mut cubes = GpuMarchingCubes::new(&display.device, field.size(), 200_000)?;
+/// // Every frame
+/// cubes.extract(&display.queue, &mut encoder, &field, 0.0);
+/// pass.set_vertex_buffer(0, cubes.vertex_buffer().slice(..));
+/// pass.set_vertex_buffer(1, instance_buffer.slice(..));
+/// pass.draw_indirect(cubes.indirect_buffer(), 0);
+/// ```
+pub struct GpuMarchingCubes {
+    size: [u32; 3],
+    max_triangles: u32,
+    march: wgpu::ComputePipeline,
+    finish: wgpu::ComputePipeline,
+    uniforms: wgpu::Buffer,
+    values: wgpu::Buffer,
+    vertices: wgpu::Buffer,
+    draw: wgpu::Buffer,
+    counter: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl GpuMarchingCubes {
+    /// For fields of `size` samples. Triangles past `max_triangles` are
+    /// dropped.
+    pub fn new(device: &wgpu::Device, size: [u32; 3], max_triangles: u32) -> Result<Self> {
+        use wgpu::util::DeviceExt;
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let read = wgpu::BufferBindingType::Storage { read_only: true };
+        let write = wgpu::BufferBindingType::Storage { read_only: false };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GpuMarchingCubes::layout"),
+            entries: &[
+                entry(0, wgpu::BufferBindingType::Uniform),
+                entry(1, read),
+                entry(2, read),
+                entry(3, write),
+                entry(4, write),
+                entry(5, write),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GpuMarchingCubes::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let (march, finish) = catch_validation_errors(device, || {
+            let module = device.create_shader_module(wgpu::include_wgsl!("marching_cubes.wgsl"));
+            let pipeline = |entry_point| {
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("GpuMarchingCubes::pipeline"),
+                    layout: Some(&pipeline_layout),
+                    module: &module,
+                    entry_point,
+                    compilation_options: Default::default(),
+                    cache: None,
+                })
+            };
+            (pipeline("march"), pipeline("finish"))
+        })?;
+
+        // 16 slots a case, with -1 after the last edge
+        let mut table = vec![-1i32; 256 * 16];
+        for (case, edges) in triangle_table().iter().enumerate() {
+            for (slot, &edge) in edges.iter().enumerate() {
+                table[case * 16 + slot] = edge as i32;
+            }
+        }
+        let table = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("GpuMarchingCubes::table"),
+            contents: bytemuck::cast_slice(&table),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuMarchingCubes::uniforms"),
+            size: std::mem::size_of::<MarchingCubesUniforms>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let values = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuMarchingCubes::values"),
+            size: (size[0] * size[1] * size[2]) as u64 * 4,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let vertices = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuMarchingCubes::vertices"),
+            size: max_triangles.max(1) as u64 * 3 * std::mem::size_of::<ModelVertex>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let draw = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("GpuMarchingCubes::draw"),
+            contents: bytemuck::cast_slice(&[0u32, 1, 0, 0]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+        });
+        let counter = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuMarchingCubes::counter"),
+            size: 4,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("GpuMarchingCubes::bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: values.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: table.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: vertices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: draw.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: counter.as_entire_binding(),
+                },
+            ],
+        });
+
+        Ok(Self {
+            size,
+            max_triangles,
+            march,
+            finish,
+            uniforms,
+            values,
+            vertices,
+            draw,
+            counter,
+            bind_group,
+        })
+    }
+
+    /// Uploads `field`, which has to be the size this was made for, and
+    /// records the passes that extract its surface
+    pub fn extract(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        field: &ScalarField,
+        iso: f32,
+    ) {
+        crate::cpu_scope!("GpuMarchingCubes::extract");
+        assert_eq!(field.size(), self.size, "Field is the wrong size");
+        queue.write_buffer(&self.values, 0, bytemuck::cast_slice(field.values()));
+        queue.write_buffer(
+            &self.uniforms,
+            0,
+            bytemuck::cast_slice(&[MarchingCubesUniforms {
+                size: self.size,
+                iso,
+                origin: field.origin.into(),
+                spacing: field.spacing,
+                max_vertices: self.max_triangles * 3,
+                _padding: [0; 3],
+            }]),
+        );
+        encoder.clear_buffer(&self.counter, 0, None);
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("GpuMarchingCubes"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_pipeline(&self.march);
+        let cells = self.size.map(|s| (s - 1).div_ceil(WORKGROUP_SIZE));
+        pass.dispatch_workgroups(cells[0], cells[1], cells[2]);
+        pass.set_pipeline(&self.finish);
+        pass.dispatch_workgroups(1, 1, 1);
+    }
+
+    /// [ModelVertex]es, three to a triangle
+    pub fn vertex_buffer(&self) -> &wgpu::Buffer {
+        &self.vertices
+    }
+
+    /// Arguments for `draw_indirect` with the vertex count of the last
+    /// extraction
+    pub fn indirect_buffer(&self) -> &wgpu::Buffer {
+        &self.draw
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_closes_every_case() {
+        let table = triangle_table();
+        assert!(table[0].is_empty() && table[255].is_empty());
+        assert_eq!(table[1].len(), 3);
+        for edges in table.iter() {
+            assert_eq!(edges.len() % 3, 0);
+            // The WGSL table has room for 5 triangles
+            assert!(edges.len() <= 15);
+        }
+        // Flipping every corner gives the same surface
+        for case in 0..256 {
+            let mut a = table[case].clone();
+            let mut b = table[255 - case].clone();
+            a.sort();
+            b.sort();
+            a.dedup();
+            b.dedup();
+            assert_eq!(a, b, "case {}", case);
+        }
+    }
+
+    #[test]
+    fn sphere_is_closed_and_faces_out() {
+        let field = ScalarField::from_fn([20; 3], Point3::new(-1.0, -1.0, -1.0), 2.0 / 19.0, |p| {
+            p.to_vec().magnitude() - 0.7
+        });
+        let mesh = marching_cubes(&field, 0.0);
+        assert!(mesh.indices.len() > 300);
+        for p in &mesh.positions {
+            assert!((p.to_vec().magnitude() - 0.7).abs() < 0.05);
+        }
+        let mut edges = HashMap::new();
+        for t in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.positions[t[i] as usize]);
+            let normal = (b - a).cross(c - a);
+            let center = (a.to_vec() + b.to_vec() + c.to_vec()) / 3.0;
+            assert!(normal.dot(center) > 0.0, "triangle faces in");
+            for i in 0..3 {
+                *edges.entry((t[i], t[(i + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        // Closed and consistently wound, so each edge is walked once each
+        // way
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+        crate::shader::validate_wgsl(include_str!("marching_cubes.wgsl")).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+struct MarchingCubesUniforms {
+    size: vec3<u32>,
+    iso: f32,
+    origin: vec3<f32>,
+    spacing: f32,
+    max_vertices: u32,
+}
+
+struct DrawIndirectArgs {
+    vertex_count: u32,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> field: MarchingCubesUniforms;
+@group(0) @binding(1)
+var<storage, read> values: array<f32>;
+// 16 edges a case, -1 after the last one
+@group(0) @binding(2)
+var<storage, read> table: array<i32>;
+// ModelVertex is 14 floats, and vec3s in a struct would be padded
+@group(0) @binding(3)
+var<storage, read_write> vertices: array<f32>;
+@group(0) @binding(4)
+var<storage, read_write> draw: DrawIndirectArgs;
+@group(0) @binding(5)
+var<storage, read_write> counter: atomic<u32>;
+
+// Same order as EDGES in marching_cubes.rs. Private rather than const, as
+// const arrays can't be indexed by a variable.
+var<private> edges: array<vec2<u32>, 12> = array<vec2<u32>, 12>(
+    vec2<u32>(0u, 1u), vec2<u32>(2u, 3u), vec2<u32>(4u, 5u), vec2<u32>(6u, 7u),
+    vec2<u32>(0u, 2u), vec2<u32>(1u, 3u), vec2<u32>(4u, 6u), vec2<u32>(5u, 7u),
+    vec2<u32>(0u, 4u), vec2<u32>(1u, 5u), vec2<u32>(2u, 6u), vec2<u32>(3u, 7u),
+);
+
+fn corner(cell: vec3<u32>, i: u32) -> vec3<u32> {
+    return cell + vec3<u32>(i & 1u, (i >> 1u) & 1u, (i >> 2u) & 1u);
+}
+
+fn value(p: vec3<u32>) -> f32 {
+    return values[(p.z * field.size.y + p.y) * field.size.x + p.x];
+}
+
+fn gradient(p: vec3<u32>) -> vec3<f32> {
+    let lo = max(p, vec3<u32>(1u)) - vec3<u32>(1u);
+    let hi = min(p + vec3<u32>(1u), field.size - vec3<u32>(1u));
+    let d = vec3<f32>(hi - lo) * field.spacing;
+    return vec3<f32>(
+        (value(vec3<u32>(hi.x, p.y, p.z)) - value(vec3<u32>(lo.x, p.y, p.z))) / d.x,
+        (value(vec3<u32>(p.x, hi.y, p.z)) - value(vec3<u32>(p.x, lo.y, p.z))) / d.y,
+        (value(vec3<u32>(p.x, p.y, hi.z)) - value(vec3<u32>(p.x, p.y, lo.z))) / d.z,
+    );
+}
+
+fn write_vertex(index: u32, position: vec3<f32>, normal: vec3<f32>) {
+    // Any tangent will do, there's no texture layout to follow
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(normal.y) > 0.99) {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    // A var, as only arrays in memory can be indexed by a variable
+    var data = array<f32, 14>(
+        position.x, position.y, position.z,
+        position.x, position.z,
+        normal.x, normal.y, normal.z,
+        tangent.x, tangent.y, tangent.z,
+        bitangent.x, bitangent.y, bitangent.z,
+    );
+    for (var i = 0u; i < 14u; i = i + 1u) {
+        vertices[index * 14u + i] = data[i];
+    }
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn march(@builtin(global_invocation_id) cell: vec3<u32>) {
+    if (any(cell + vec3<u32>(1u) >= field.size)) {
+        return;
+    }
+    var case_index = 0u;
+    for (var i = 0u; i < 8u; i = i + 1u) {
+        if (value(corner(cell, i)) < field.iso) {
+            case_index = case_index | (1u << i);
+        }
+    }
+    var count = 0u;
+    while (count < 15u && table[case_index * 16u + count] >= 0) {
+        count = count + 1u;
+    }
+    if (count == 0u) {
+        return;
+    }
+    let first = atomicAdd(&counter, count);
+    // Both are multiples of 3, so this only ever drops whole triangles
+    let end = min(first + count, field.max_vertices);
+    for (var i = 0u; first + i < end; i = i + 1u) {
+        let edge = edges[table[case_index * 16u + i]];
+        let a = corner(cell, edge.x);
+        let b = corner(cell, edge.y);
+        let va = value(a);
+        let vb = value(b);
+        var t = 0.5;
+        if (va != vb) {
+            t = clamp((field.iso - va) / (vb - va), 0.0, 1.0);
+        }
+        let position = field.origin + mix(vec3<f32>(a), vec3<f32>(b), t) * field.spacing;
+        var normal = mix(gradient(a), gradient(b), t);
+        if (dot(normal, normal) > 0.0) {
+            normal = normalize(normal);
+        } else {
+            normal = vec3<f32>(0.0, 1.0, 0.0);
+        }
+        write_vertex(first + i, position, normal);
+    }
+}
+
+// Runs once after march, when the counter holds every vertex asked for
+@compute @workgroup_size(1)
+fn finish() {
+    draw.vertex_count = min(atomicLoad(&counter), field.max_vertices);
+    draw.instance_count = 1u;
+    draw.first_vertex = 0u;
+    draw.first_instance = 0u;
+}
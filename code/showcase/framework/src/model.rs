@@ -1,9 +1,13 @@
 use anyhow::*;
+use cgmath::{InnerSpace, Vector2, Vector3, Zero};
 use std::ops::Range;
 use std::path::Path;
 use wgpu::util::DeviceExt;
 
-use crate::texture;
+use crate::assets::{Assets, Handle};
+use crate::stylized::{self, Shading};
+use crate::texture::Texture;
+use crate::vertex_animation::VertexAnimation;
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
@@ -19,6 +23,20 @@ pub struct ModelVertex {
     bitangent: [f32; 3],
 }
 
+impl ModelVertex {
+    /// Tangents are left at zero for [Mesh::from_vertices] callers to
+    /// fill in with `compute_tangents`
+    pub(crate) fn new(position: [f32; 3], tex_coords: [f32; 2], normal: [f32; 3]) -> Self {
+        Self {
+            position,
+            tex_coords,
+            normal,
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        }
+    }
+}
+
 impl Vertex for ModelVertex {
     fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
@@ -57,51 +75,472 @@ impl Vertex for ModelVertex {
     }
 }
 
-pub struct Material<'a> {
+/// WGSL for a [Material] bound to group 0. It has `sample_material(uv)`
+/// to read the textures and factors, `parallax_uv` for parallax occlusion
+/// mapping, and `pbr_light` and `pbr_ambient` for metallic-roughness
+/// shading.
+pub const PBR_WGSL: &str = concat!(include_str!("pbr.wgsl"), include_str!("brdf.wgsl"));
+
+/// Just the shading part of [PBR_WGSL], for passes that get their
+/// `Surface` from somewhere other than a [Material]
+pub const BRDF_WGSL: &str = include_str!("brdf.wgsl");
+
+/// WGSL with `vs_model`, a vertex shader for [ModelVertex] and the
+/// tutorials' instance matrix at locations 5 to 8, with the camera at
+/// group 1. Fragment shaders take its `ModelVertexOutput` and pass the
+/// normal from [PBR_WGSL]'s `sample_material` to `world_normal`. For
+/// parallax occlusion mapping, pass `parallax_uv` the texture coordinates
+/// and `tangent_space` of the direction to the camera first.
+pub const MODEL_VERTEX_WGSL: &str = include_str!("model_vertex.wgsl");
+
+/// Multiplies a [Material]'s textures, like glTF's factors. Set them and
+/// call [Material::update_factors].
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialFactors {
+    pub albedo: [f32; 4],
+    pub emissive: [f32; 3],
+    pub metallic: f32,
+    pub roughness: f32,
+    /// How much of the AO texture applies, from 0 to 1
+    pub occlusion_strength: f32,
+    pub normal_scale: f32,
+    /// Fragments with less alpha than this are discarded. 0 draws
+    /// everything.
+    pub alpha_cutoff: f32,
+    /// How deep the height texture's black is, in texture coordinates.
+    /// Anything above 0 turns on parallax occlusion mapping.
+    pub height_scale: f32,
+    /// Where the real surface sits in the height texture, from 0 for
+    /// white to 1 for black. Above 0, bumps stick out of the surface
+    /// instead of everything sinking into it.
+    pub height_offset: f32,
+    /// How many steps parallax occlusion mapping takes looking straight at
+    /// the surface. More are taken at grazing angles.
+    pub parallax_min_layers: f32,
+    pub parallax_max_layers: f32,
+}
+
+impl Default for MaterialFactors {
+    fn default() -> Self {
+        Self {
+            albedo: [1.0; 4],
+            emissive: [0.0; 3],
+            metallic: 1.0,
+            roughness: 1.0,
+            occlusion_strength: 1.0,
+            normal_scale: 1.0,
+            alpha_cutoff: 0.0,
+            height_scale: 0.0,
+            height_offset: 0.0,
+            parallax_min_layers: 8.0,
+            parallax_max_layers: 32.0,
+        }
+    }
+}
+
+/// The textures of a [Material], kept in [Assets] so materials can share
+/// them. Metallic is read from blue and roughness from green, like glTF,
+/// and ambient occlusion and height from red.
+#[derive(Debug, Clone)]
+pub struct MaterialTextures {
+    pub albedo: Handle<Texture<'static>>,
+    pub normal: Handle<Texture<'static>>,
+    pub metallic_roughness: Handle<Texture<'static>>,
+    pub occlusion: Handle<Texture<'static>>,
+    pub emissive: Handle<Texture<'static>>,
+    /// Only read when [MaterialFactors::height_scale] is above 0. White is
+    /// high.
+    pub height: Handle<Texture<'static>>,
+    /// Only read with [crate::ShadingModel::Matcap]
+    pub matcap: Handle<Texture<'static>>,
+    /// Only read with [crate::ShadingModel::Toon]. Defaults to three bands.
+    pub ramp: Handle<Texture<'static>>,
+}
+
+impl MaterialTextures {
+    /// Fills the slots that aren't given with textures that leave the
+    /// factors as they are. Those are shared by every material.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &mut Assets,
+        albedo: Handle<Texture<'static>>,
+        normal: Handle<Texture<'static>>,
+    ) -> Self {
+        let white = assets.solid_texture(device, queue, [255; 4], true);
+        Self {
+            albedo,
+            normal,
+            metallic_roughness: white.clone(),
+            occlusion: white.clone(),
+            emissive: assets.solid_texture(device, queue, [255; 4], false),
+            height: white,
+            matcap: assets.solid_texture(device, queue, [255; 4], false),
+            ramp: assets.find_or_add("<toon ramp 3>", || stylized::toon_ramp(device, queue, 3)),
+        }
+    }
+}
+
+/// A metallic-roughness material, read in shaders with [PBR_WGSL]
+pub struct Material {
     pub name: String,
-    pub diffuse_texture: texture::Texture<'a>,
-    pub normal_texture: texture::Texture<'a>,
+    /// Change them through the setters, or call [Material::rebind] after
+    pub textures: MaterialTextures,
+    pub factors: MaterialFactors,
+    factors_buffer: wgpu::Buffer,
+    /// Set it and call [Material::update_animation]
+    pub animation: VertexAnimation,
+    animation_buffer: wgpu::Buffer,
+    /// Set it and call [Material::update_shading]
+    pub shading: Shading,
+    shading_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
-impl<'a> Material<'a> {
+impl Material {
+    /// The layout [Material::new] expects: each texture and its sampler in
+    /// the order of [MaterialTextures], then the factors, then the height
+    /// texture and its sampler, then the [VertexAnimation], then the
+    /// matcap and ramp textures and their samplers, then the [Shading]
+    pub fn create_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let mut entries = Vec::with_capacity(19);
+        for i in 0..5 {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: i * 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            });
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: i * 2 + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 10,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 11,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        });
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 12,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        });
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 13,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+        for binding in [14, 16] {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            });
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: binding + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+        // The outline's width is read in the vertex shader
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 18,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Material::layout"),
+            entries: &entries,
+        })
+    }
+
     pub fn new(
         device: &wgpu::Device,
+        assets: &Assets,
         name: &str,
-        diffuse_texture: texture::Texture<'a>,
-        normal_texture: texture::Texture<'a>,
+        textures: MaterialTextures,
+        factors: MaterialFactors,
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
-                },
-            ],
-            label: Some(name),
+        let factors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} factors", name)),
+            contents: bytemuck::cast_slice(&[factors]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let animation = VertexAnimation::default();
+        let animation_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} animation", name)),
+            contents: bytemuck::cast_slice(&[animation.to_raw(0.0)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let shading = Shading::default();
+        let shading_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} shading", name)),
+            contents: bytemuck::cast_slice(&[shading.to_raw()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
+        let bind_group = create_material_bind_group(
+            device,
+            assets,
+            name,
+            &textures,
+            [&factors_buffer, &animation_buffer, &shading_buffer],
+            layout,
+        );
 
         Self {
             name: String::from(name),
-            diffuse_texture,
-            normal_texture,
+            textures,
+            factors,
+            factors_buffer,
+            animation,
+            animation_buffer,
+            shading,
+            shading_buffer,
             bind_group,
         }
     }
+
+    /// A material using [Texture::placeholder] for its albedo and normal
+    /// textures
+    pub fn placeholder(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let albedo = assets.placeholder_texture(device, queue, false);
+        let normal = assets.placeholder_texture(device, queue, true);
+        let textures = MaterialTextures::new(device, queue, assets, albedo, normal);
+        Self::new(
+            device,
+            assets,
+            "placeholder",
+            textures,
+            MaterialFactors::default(),
+            layout,
+        )
+    }
+
+    /// Swaps the albedo texture and rebuilds the bind group. `layout`
+    /// needs to be the same layout the material was created with.
+    pub fn set_albedo_texture(
+        &mut self,
+        device: &wgpu::Device,
+        assets: &Assets,
+        albedo_texture: Handle<Texture<'static>>,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        self.textures.albedo = albedo_texture;
+        self.rebind(device, assets, layout);
+    }
+
+    /// Swaps the normal texture and rebuilds the bind group. `layout`
+    /// needs to be the same layout the material was created with.
+    pub fn set_normal_texture(
+        &mut self,
+        device: &wgpu::Device,
+        assets: &Assets,
+        normal_texture: Handle<Texture<'static>>,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        self.textures.normal = normal_texture;
+        self.rebind(device, assets, layout);
+    }
+
+    /// Swaps the height texture and rebuilds the bind group. Set
+    /// [MaterialFactors::height_scale] too for it to have an effect.
+    pub fn set_height_texture(
+        &mut self,
+        device: &wgpu::Device,
+        assets: &Assets,
+        height_texture: Handle<Texture<'static>>,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        self.textures.height = height_texture;
+        self.rebind(device, assets, layout);
+    }
+
+    /// Swaps the matcap texture and rebuilds the bind group. Matcaps are
+    /// read by [crate::ShadingModel::Matcap].
+    pub fn set_matcap_texture(
+        &mut self,
+        device: &wgpu::Device,
+        assets: &Assets,
+        matcap_texture: Handle<Texture<'static>>,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        self.textures.matcap = matcap_texture;
+        self.rebind(device, assets, layout);
+    }
+
+    /// Swaps the toon ramp and rebuilds the bind group, see
+    /// [crate::toon_ramp]
+    pub fn set_ramp_texture(
+        &mut self,
+        device: &wgpu::Device,
+        assets: &Assets,
+        ramp_texture: Handle<Texture<'static>>,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        self.textures.ramp = ramp_texture;
+        self.rebind(device, assets, layout);
+    }
+
+    /// Uploads [Material::factors] after they've been changed
+    pub fn update_factors(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.factors_buffer,
+            0,
+            bytemuck::cast_slice(&[self.factors]),
+        );
+    }
+
+    /// Uploads [Material::animation] as it is `time` seconds in. Needs
+    /// calling every frame while the material moves.
+    pub fn update_animation(&self, queue: &wgpu::Queue, time: f32) {
+        queue.write_buffer(
+            &self.animation_buffer,
+            0,
+            bytemuck::cast_slice(&[self.animation.to_raw(time)]),
+        );
+    }
+
+    /// Uploads [Material::shading] after it's been changed
+    pub fn update_shading(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.shading_buffer,
+            0,
+            bytemuck::cast_slice(&[self.shading.to_raw()]),
+        );
+    }
+
+    /// Rebuilds the bind group after [Material::textures] have been
+    /// changed. `assets` has to be the one the textures are in.
+    pub fn rebind(
+        &mut self,
+        device: &wgpu::Device,
+        assets: &Assets,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        self.bind_group = create_material_bind_group(
+            device,
+            assets,
+            &self.name,
+            &self.textures,
+            [
+                &self.factors_buffer,
+                &self.animation_buffer,
+                &self.shading_buffer,
+            ],
+            layout,
+        );
+    }
+}
+
+fn create_material_bind_group(
+    device: &wgpu::Device,
+    assets: &Assets,
+    name: &str,
+    textures: &MaterialTextures,
+    [factors_buffer, animation_buffer, shading_buffer]: [&wgpu::Buffer; 3],
+    layout: &wgpu::BindGroupLayout,
+) -> wgpu::BindGroup {
+    let height = assets.get(&textures.height);
+    let stylized = [assets.get(&textures.matcap), assets.get(&textures.ramp)];
+    let textures = [
+        assets.get(&textures.albedo),
+        assets.get(&textures.normal),
+        assets.get(&textures.metallic_roughness),
+        assets.get(&textures.occlusion),
+        assets.get(&textures.emissive),
+    ];
+    let mut entries = Vec::with_capacity(19);
+    for (i, texture) in (0..).zip(textures.iter()) {
+        entries.push(wgpu::BindGroupEntry {
+            binding: i * 2,
+            resource: wgpu::BindingResource::TextureView(&texture.view),
+        });
+        entries.push(wgpu::BindGroupEntry {
+            binding: i * 2 + 1,
+            resource: wgpu::BindingResource::Sampler(&texture.sampler),
+        });
+    }
+    entries.push(wgpu::BindGroupEntry {
+        binding: 10,
+        resource: factors_buffer.as_entire_binding(),
+    });
+    entries.push(wgpu::BindGroupEntry {
+        binding: 11,
+        resource: wgpu::BindingResource::TextureView(&height.view),
+    });
+    entries.push(wgpu::BindGroupEntry {
+        binding: 12,
+        resource: wgpu::BindingResource::Sampler(&height.sampler),
+    });
+    entries.push(wgpu::BindGroupEntry {
+        binding: 13,
+        resource: animation_buffer.as_entire_binding(),
+    });
+    for (i, texture) in (0..).zip(stylized.iter()) {
+        entries.push(wgpu::BindGroupEntry {
+            binding: 14 + i * 2,
+            resource: wgpu::BindingResource::TextureView(&texture.view),
+        });
+        entries.push(wgpu::BindGroupEntry {
+            binding: 15 + i * 2,
+            resource: wgpu::BindingResource::Sampler(&texture.sampler),
+        });
+    }
+    entries.push(wgpu::BindGroupEntry {
+        binding: 18,
+        resource: shading_buffer.as_entire_binding(),
+    });
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &entries,
+        label: Some(name),
+    })
 }
 
 pub struct Mesh {
@@ -110,139 +549,589 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    /// Set for meshes made with [Mesh::with_morph_targets]
+    pub morph_targets: Option<crate::morph::MorphTargets>,
+    /// Set for skinned meshes loaded from a file, see [crate::MeshSkin]
+    pub skin: Option<crate::skinning::MeshSkin>,
+}
+
+impl Mesh {
+    /// A 1x1x1 cube centered on the origin, with each face mapped to the
+    /// whole texture
+    pub fn unit_cube(device: &wgpu::Device, material: usize) -> Self {
+        let (vertices, indices) = unit_cube_vertices();
+        Self::from_vertices(device, "unit_cube", &vertices, &indices, material)
+    }
+
+    pub(crate) fn from_vertices(
+        device: &wgpu::Device,
+        name: &str,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+        material: usize,
+    ) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", name)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        Self {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            material,
+            morph_targets: None,
+            skin: None,
+        }
+    }
+
+    /// The arguments for drawing the whole mesh with
+    /// [DrawModel::draw_mesh_indirect]. A compute shader can change
+    /// `instance_count` afterwards, to cull instances on the GPU.
+    ///
+    /// `first_instance` is always 0, since anything else needs
+    /// `Features::INDIRECT_FIRST_INSTANCE`. To start further into the
+    /// instances, bind the instance buffer from that instance's offset.
+    pub fn indirect_args(&self, instance_count: u32) -> wgpu::util::DrawIndexedIndirectArgs {
+        wgpu::util::DrawIndexedIndirectArgs {
+            index_count: self.num_elements,
+            instance_count,
+            first_index: 0,
+            base_vertex: 0,
+            first_instance: 0,
+        }
+    }
+}
+
+/// Bytes between the draws in an indirect buffer
+pub const DRAW_INDEXED_INDIRECT_SIZE: wgpu::BufferAddress =
+    std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as _;
+
+/// Packs draws the way `multi_draw_indexed_indirect` reads them
+pub fn indirect_bytes(draws: &[wgpu::util::DrawIndexedIndirectArgs]) -> Vec<u8> {
+    draws
+        .iter()
+        .flat_map(|draw| draw.as_bytes().to_vec())
+        .collect()
+}
+
+/// A buffer of indirect draws that compute shaders can also write to
+pub fn create_indirect_buffer(
+    device: &wgpu::Device,
+    label: &str,
+    draws: &[wgpu::util::DrawIndexedIndirectArgs],
+) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: &indirect_bytes(draws),
+        usage: wgpu::BufferUsages::INDIRECT
+            | wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_DST,
+    })
 }
 
-pub struct Model<'a> {
+fn unit_cube_vertices() -> (Vec<ModelVertex>, Vec<u32>) {
+    // (normal, tangent) for each face. The tangent points along +u
+    let faces = [
+        (Vector3::unit_x(), -Vector3::unit_z()),
+        (-Vector3::unit_x(), Vector3::unit_z()),
+        (Vector3::unit_y(), Vector3::unit_x()),
+        (-Vector3::unit_y(), Vector3::unit_x()),
+        (Vector3::unit_z(), Vector3::unit_x()),
+        (-Vector3::unit_z(), -Vector3::unit_x()),
+    ];
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (normal, tangent) in faces {
+        // Picking up this way makes the corners counter clockwise when
+        // seen from outside the cube
+        let up = normal.cross(tangent);
+        let base = vertices.len() as u32;
+        for (u, v) in [(0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)] {
+            let position = normal * 0.5 + tangent * (u - 0.5) + up * (0.5 - v);
+            vertices.push(ModelVertex {
+                position: position.into(),
+                tex_coords: [u, v],
+                normal: normal.into(),
+                tangent: tangent.into(),
+                // Texture v goes down the face
+                bitangent: (-up).into(),
+            });
+        }
+        indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+    (vertices, indices)
+}
+
+/// Its materials' textures are in the [Assets] it was loaded with
+pub struct Model {
     pub meshes: Vec<Mesh>,
-    pub materials: Vec<Material<'a>>,
+    pub materials: Vec<Material>,
+}
+
+/// How the model loaders treat malformed files.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LoadOptions {
+    /// Fail on the first problem instead of repairing it and logging a
+    /// warning. Files that can't be repaired, like ones that don't parse,
+    /// always fail.
+    pub strict: bool,
+}
+
+impl LoadOptions {
+    pub(crate) fn problem(&self, message: String) -> Result<()> {
+        if self.strict {
+            bail!(message);
+        }
+        log::warn!("{}, repairing", message);
+        Ok(())
+    }
 }
 
-impl<'a> Model<'a> {
+impl Model {
+    /// [Mesh::indirect_args] for each mesh, in order
+    pub fn indirect_args(&self, instance_count: u32) -> Vec<wgpu::util::DrawIndexedIndirectArgs> {
+        self.meshes
+            .iter()
+            .map(|mesh| mesh.indirect_args(instance_count))
+            .collect()
+    }
+
+    /// The buffer [DrawModel::draw_model_indirect] reads, with one draw
+    /// per mesh
+    pub fn create_indirect_buffer(
+        &self,
+        device: &wgpu::Device,
+        instance_count: u32,
+    ) -> wgpu::Buffer {
+        create_indirect_buffer(
+            device,
+            "Model Indirect Buffer",
+            &self.indirect_args(instance_count),
+        )
+    }
+
+    /// A [Mesh::unit_cube] with [Material::placeholder]
+    pub fn placeholder(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        Self {
+            meshes: vec![Mesh::unit_cube(device, 0)],
+            materials: vec![Material::placeholder(device, queue, assets, layout)],
+        }
+    }
+
+    /// Like [Model::load_obj], but logs a warning and returns
+    /// [Model::placeholder] if the file can't be loaded, so one bad path
+    /// doesn't stop a whole scene from loading.
+    pub fn load_obj_or_placeholder<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+        path: P,
+    ) -> Self {
+        match Self::load_obj(device, queue, assets, layout, path.as_ref()) {
+            Result::Ok(model) => model,
+            Err(e) => {
+                log::warn!("{:#}, using a placeholder", e);
+                Self::placeholder(device, queue, assets, layout)
+            }
+        }
+    }
+
+    /// Loads an OBJ file. Its textures go in `assets`, shared with
+    /// anything else that loaded the same files.
     pub fn load_obj<P: AsRef<Path>>(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+        path: P,
+    ) -> Result<Self> {
+        Self::load_obj_with_options(device, queue, assets, layout, path, &LoadOptions::default())
+    }
+
+    pub fn load_obj_with_options<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &mut Assets,
         layout: &wgpu::BindGroupLayout,
         path: P,
+        options: &LoadOptions,
     ) -> Result<Self> {
-        let (obj_models, obj_materials) = tobj::load_obj(path.as_ref(), true)?;
+        let path = path.as_ref();
+        let (obj_models, obj_materials) = tobj::load_obj(path, true)
+            .with_context(|| format!("Unable to parse {}", path.display()))?;
 
         // We're assuming that the texture files are stored with the obj file
-        let containing_folder = path.as_ref().parent().context("Directory has no parent")?;
+        let containing_folder = path.parent().context("Directory has no parent")?;
 
+        Self::from_obj(
+            device,
+            queue,
+            assets,
+            layout,
+            &path.display().to_string(),
+            obj_models,
+            obj_materials,
+            options,
+            |assets, file, is_normal_map| {
+                assets.try_load_texture(device, queue, containing_folder.join(file), is_normal_map)
+            },
+        )
+    }
+
+    /// Builds a model out of a parsed OBJ file. `load_texture` is given
+    /// each texture file named by the materials, and should share ones
+    /// that are already in `assets`. `name` is only used in errors.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn from_obj(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+        name: &str,
+        obj_models: Vec<tobj::Model>,
+        obj_materials: Vec<tobj::Material>,
+        options: &LoadOptions,
+        load_texture: impl Fn(&mut Assets, &str, bool) -> Result<Handle<Texture<'static>>>,
+    ) -> Result<Self> {
         let mut materials = Vec::new();
         for mat in obj_materials {
-            let diffuse_path = mat.diffuse_texture;
-            let diffuse_texture =
-                texture::Texture::load(device, queue, containing_folder.join(diffuse_path), false)?;
-
-            let normal_path = mat.normal_texture;
-            let normal_texture =
-                texture::Texture::load(device, queue, containing_folder.join(normal_path), true)?;
+            let diffuse_texture = load_material_texture(
+                device,
+                queue,
+                assets,
+                &mat.diffuse_texture,
+                false,
+                options,
+                &load_texture,
+            )
+            .with_context(|| format!("Material {:?} in {}", mat.name, name))?;
+            let normal_texture = load_material_texture(
+                device,
+                queue,
+                assets,
+                &mat.normal_texture,
+                true,
+                options,
+                &load_texture,
+            )
+            .with_context(|| format!("Material {:?} in {}", mat.name, name))?;
 
+            // OBJ only has the older diffuse/specular model, so these are
+            // plain dielectrics
+            let factors = MaterialFactors {
+                metallic: 0.0,
+                roughness: 0.5,
+                ..Default::default()
+            };
+            let textures =
+                MaterialTextures::new(device, queue, assets, diffuse_texture, normal_texture);
             materials.push(Material::new(
-                device,
-                &mat.name,
-                diffuse_texture,
-                normal_texture,
-                layout,
+                device, assets, &mat.name, textures, factors, layout,
             ));
         }
 
         let mut meshes = Vec::new();
         for m in obj_models {
-            let mut vertices = Vec::new();
-            for i in 0..m.mesh.positions.len() / 3 {
-                vertices.push(ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ]
-                    .into(),
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]].into(),
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ]
-                    .into(),
-                    // We'll calculate these later
-                    tangent: [0.0; 3].into(),
-                    bitangent: [0.0; 3].into(),
-                });
-            }
+            let (vertices, indices) = build_vertices(
+                &m.mesh.positions,
+                &m.mesh.texcoords,
+                &m.mesh.normals,
+                &m.mesh.indices,
+                options,
+            )
+            .with_context(|| format!("Mesh {:?} in {}", m.name, name))?;
 
-            let indices = &m.mesh.indices;
-
-            // Calculate tangents and bitangets. We're going to
-            // use the triangles, so we need to loop through the
-            // indices in chunks of 3
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-
-                let pos0: cgmath::Vector3<_> = v0.position.into();
-                let pos1: cgmath::Vector3<_> = v1.position.into();
-                let pos2: cgmath::Vector3<_> = v2.position.into();
-
-                let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
-                let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
-                let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
-
-                // Calculate the edges of the triangle
-                let delta_pos1 = pos1 - pos0;
-                let delta_pos2 = pos2 - pos0;
-
-                // This will give us a direction to calculate the
-                // tangent and bitangent
-                let delta_uv1 = uv1 - uv0;
-                let delta_uv2 = uv2 - uv0;
-
-                // Solving the following system of equations will
-                // give us the tangent and bitangent.
-                //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
-                //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
-                // Luckily, the place I found this equation provided
-                // the solution!
-                let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-                let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-                let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * r;
-
-                // We'll use the same tangent/bitangent for each vertex in the triangle
-                vertices[c[0] as usize].tangent = tangent.into();
-                vertices[c[1] as usize].tangent = tangent.into();
-                vertices[c[2] as usize].tangent = tangent.into();
-
-                vertices[c[0] as usize].bitangent = bitangent.into();
-                vertices[c[1] as usize].bitangent = bitangent.into();
-                vertices[c[2] as usize].bitangent = bitangent.into();
-            }
+            let material = match m.mesh.material_id {
+                Some(id) if id >= materials.len() => {
+                    options
+                        .problem(format!(
+                            "Mesh {:?} uses material {} but only {} exist",
+                            m.name,
+                            id,
+                            materials.len()
+                        ))
+                        .with_context(|| format!("Loading {}", name))?;
+                    0
+                }
+                id => id.unwrap_or(0),
+            };
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", path.as_ref())),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", path.as_ref())),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
+            meshes.push(Mesh::from_vertices(
+                device, &m.name, &vertices, &indices, material,
+            ));
+        }
 
-            meshes.push(Mesh {
-                name: m.name,
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
-            });
+        // Files without a .mtl still need something to draw with
+        if materials.is_empty() && !meshes.is_empty() {
+            materials.push(Material::placeholder(device, queue, assets, layout));
         }
 
         Ok(Self { meshes, materials })
     }
 }
 
+/// Loads a texture referenced by a material. Missing textures are replaced
+/// by [Texture::placeholder] unless loading is strict.
+fn load_material_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    assets: &mut Assets,
+    file: &str,
+    is_normal_map: bool,
+    options: &LoadOptions,
+    load_texture: &impl Fn(&mut Assets, &str, bool) -> Result<Handle<Texture<'static>>>,
+) -> Result<Handle<Texture<'static>>> {
+    let result = if file.is_empty() {
+        Err(anyhow!("No texture specified"))
+    } else {
+        load_texture(assets, file, is_normal_map)
+    };
+    match result {
+        Result::Ok(texture) => Ok(texture),
+        Err(e) => {
+            options.problem(format!("{:#}", e))?;
+            Ok(assets.placeholder_texture(device, queue, is_normal_map))
+        }
+    }
+}
+
+fn finite_or_zero<const N: usize>(values: [f32; N]) -> ([f32; N], bool) {
+    if values.iter().all(|v| v.is_finite()) {
+        (values, true)
+    } else {
+        ([0.0; N], false)
+    }
+}
+
+/// Builds vertices from flat attribute arrays, checking everything that
+/// could make us panic or render garbage. Texture coordinates and normals
+/// can be empty.
+pub(crate) fn build_vertices(
+    positions: &[f32],
+    tex_coords: &[f32],
+    normals: &[f32],
+    indices: &[u32],
+    options: &LoadOptions,
+) -> Result<(Vec<ModelVertex>, Vec<u32>)> {
+    if !positions.len().is_multiple_of(3) {
+        bail!(
+            "Position data has {} floats, which isn't a multiple of 3",
+            positions.len()
+        );
+    }
+    let vertex_count = positions.len() / 3;
+    let has_tex_coords = !tex_coords.is_empty();
+    let has_normals = !normals.is_empty();
+    if has_tex_coords && tex_coords.len() != vertex_count * 2 {
+        bail!(
+            "Mesh has {} vertices but {} texture coordinates",
+            vertex_count,
+            tex_coords.len() / 2
+        );
+    }
+    if has_normals && normals.len() != vertex_count * 3 {
+        bail!(
+            "Mesh has {} vertices but {} normals",
+            vertex_count,
+            normals.len() / 3
+        );
+    }
+
+    let mut non_finite = 0;
+    let mut vertices = Vec::with_capacity(vertex_count);
+    for i in 0..vertex_count {
+        let (position, ok_position) =
+            finite_or_zero([positions[i * 3], positions[i * 3 + 1], positions[i * 3 + 2]]);
+        let (tex_coords, ok_tex_coords) = if has_tex_coords {
+            finite_or_zero([tex_coords[i * 2], tex_coords[i * 2 + 1]])
+        } else {
+            ([0.0; 2], true)
+        };
+        let (normal, ok_normal) = if has_normals {
+            finite_or_zero([normals[i * 3], normals[i * 3 + 1], normals[i * 3 + 2]])
+        } else {
+            ([0.0; 3], true)
+        };
+        if !(ok_position && ok_tex_coords && ok_normal) {
+            non_finite += 1;
+        }
+        vertices.push(ModelVertex {
+            position,
+            tex_coords,
+            normal,
+            // We'll calculate these later
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        });
+    }
+    if non_finite > 0 {
+        options.problem(format!(
+            "{} vertices have NaN or infinite values",
+            non_finite
+        ))?;
+    }
+
+    let mut indices = indices.to_vec();
+    if !indices.len().is_multiple_of(3) {
+        options.problem(format!(
+            "Index count {} isn't a multiple of 3",
+            indices.len()
+        ))?;
+        indices.truncate(indices.len() / 3 * 3);
+    }
+    let triangle_count = indices.len() / 3;
+    let mut valid = Vec::with_capacity(indices.len());
+    for c in indices.chunks(3) {
+        if c.iter().all(|i| (*i as usize) < vertex_count) {
+            valid.extend_from_slice(c);
+        }
+    }
+    if valid.len() != indices.len() {
+        options.problem(format!(
+            "{} of {} triangles index past the {} vertices",
+            triangle_count - valid.len() / 3,
+            triangle_count,
+            vertex_count
+        ))?;
+    }
+    let indices = valid;
+
+    compute_tangents(&mut vertices, &indices);
+
+    Ok((vertices, indices))
+}
+
+/// Fills in tangents and bitangents for normal mapping from the texture
+/// coordinates, like MikkTSpace does. Each vertex averages the triangles
+/// around it, weighted by their size, and the result is made perpendicular
+/// to the normal so normal maps don't come out skewed.
+pub(crate) fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut tangents = vec![Vector3::zero(); vertices.len()];
+    let mut bitangents = vec![Vector3::zero(); vertices.len()];
+    // Calculate tangents and bitangets. We're going to
+    // use the triangles, so we need to loop through the
+    // indices in chunks of 3
+    for c in indices.chunks_exact(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+
+        let pos0: Vector3<_> = v0.position.into();
+        let pos1: Vector3<_> = v1.position.into();
+        let pos2: Vector3<_> = v2.position.into();
+
+        let uv0: Vector2<_> = v0.tex_coords.into();
+        let uv1: Vector2<_> = v1.tex_coords.into();
+        let uv2: Vector2<_> = v2.tex_coords.into();
+
+        // Calculate the edges of the triangle
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+
+        // This will give us a direction to calculate the
+        // tangent and bitangent
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        // Solving the following system of equations will
+        // give us the tangent and bitangent.
+        //     delta_pos1 = delta_uv1.x * T + delta_uv1.y * B
+        //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
+        // Luckily, the place I found this equation provided
+        // the solution! Leaving out the division by the
+        // determinant weights bigger triangles more, and only
+        // its sign matters for mirrored UVs.
+        let det = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        // Triangles with no UV area (or no UVs at all) don't have a
+        // meaningful tangent
+        if det.abs() < f32::EPSILON {
+            continue;
+        }
+        let sign = det.signum();
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * sign;
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * sign;
+        for &i in c {
+            tangents[i as usize] += tangent;
+            bitangents[i as usize] += bitangent;
+        }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let normal = Vector3::from(vertex.normal);
+        let (tangent, bitangent) = orthonormalize(normal, tangents[i], bitangents[i]);
+        vertex.tangent = tangent.into();
+        vertex.bitangent = bitangent.into();
+    }
+}
+
+/// Makes `tangent` perpendicular to `normal`, and the bitangent
+/// perpendicular to both while keeping which way it pointed, since UVs
+/// can be mirrored. Vertices without a usable tangent get any tangent
+/// perpendicular to the normal, which is fine for flat normal maps.
+fn orthonormalize(
+    normal: Vector3<f32>,
+    tangent: Vector3<f32>,
+    bitangent: Vector3<f32>,
+) -> (Vector3<f32>, Vector3<f32>) {
+    if normal.magnitude2() < f32::EPSILON {
+        // Without a normal there's nothing to be perpendicular to
+        let normalize = |v: Vector3<f32>| {
+            if v.magnitude2() > f32::EPSILON {
+                v.normalize()
+            } else {
+                v
+            }
+        };
+        return (normalize(tangent), normalize(bitangent));
+    }
+    let normal = normal.normalize();
+    let mut tangent = tangent - normal * normal.dot(tangent);
+    if tangent.magnitude2() < f32::EPSILON {
+        let axis = if normal.x.abs() < 0.9 {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_y()
+        };
+        tangent = axis - normal * normal.dot(axis);
+    }
+    let tangent = tangent.normalize();
+    let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    (tangent, normal.cross(tangent) * handedness)
+}
+
+/// Replaces the computed tangents with ones from the file, given as
+/// `[x, y, z, w]` where `w` says which way the bitangent points
+#[cfg(feature = "gltf")]
+pub(crate) fn set_tangents(vertices: &mut [ModelVertex], tangents: &[[f32; 4]]) {
+    for (vertex, t) in vertices.iter_mut().zip(tangents) {
+        let normal = Vector3::from(vertex.normal);
+        let tangent = Vector3::new(t[0], t[1], t[2]);
+        if !(tangent.magnitude2().is_normal() && normal.magnitude2().is_normal()) {
+            continue;
+        }
+        let (tangent, bitangent) = orthonormalize(normal, tangent, normal.cross(tangent) * t[3]);
+        vertex.tangent = tangent.into();
+        vertex.bitangent = bitangent.into();
+    }
+}
+
 pub trait DrawModel<'a> {
     fn draw_mesh(
         &mut self,
@@ -281,6 +1170,42 @@ pub trait DrawModel<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
+
+    /// Draws with the arguments at `offset` in `indirect`. Needs
+    /// [crate::Capabilities::indirect_draws].
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        indirect: &'a wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    /// Draws `count` packed draws of the same mesh starting at `offset`,
+    /// in one call where [crate::Capabilities::multi_draw_indirect]
+    /// allows it and one call each otherwise.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_mesh_multi_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        indirect: &'a wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        count: u32,
+        capabilities: &crate::Capabilities,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    /// Draws each mesh with its entry in a buffer from
+    /// [Model::create_indirect_buffer]
+    fn draw_model_indirect(
+        &mut self,
+        model: &'a Model,
+        indirect: &'a wgpu::Buffer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
 }
 
 impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
@@ -359,6 +1284,76 @@ where
             );
         }
     }
+
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        indirect: &'b wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed_indirect(indirect, offset);
+    }
+
+    fn draw_mesh_multi_indirect(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        indirect: &'b wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        count: u32,
+        capabilities: &crate::Capabilities,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        if !capabilities.multi_draw_indirect {
+            for i in 0..count as wgpu::BufferAddress {
+                let offset = offset + i * DRAW_INDEXED_INDIRECT_SIZE;
+                self.draw_mesh_indirect(
+                    mesh,
+                    material,
+                    indirect,
+                    offset,
+                    camera_bind_group,
+                    light_bind_group,
+                );
+            }
+            return;
+        }
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.multi_draw_indexed_indirect(indirect, offset, count);
+    }
+
+    fn draw_model_indirect(
+        &mut self,
+        model: &'b Model,
+        indirect: &'b wgpu::Buffer,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        // Meshes have their own vertex buffers, so each needs its own call
+        for (i, mesh) in model.meshes.iter().enumerate() {
+            self.draw_mesh_indirect(
+                mesh,
+                &model.materials[mesh.material],
+                indirect,
+                i as wgpu::BufferAddress * DRAW_INDEXED_INDIRECT_SIZE,
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
 }
 
 pub trait DrawLight<'a> {
@@ -443,3 +1438,162 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(mesh: &tobj::Mesh, options: &LoadOptions) -> Result<(Vec<ModelVertex>, Vec<u32>)> {
+        build_vertices(
+            &mesh.positions,
+            &mesh.texcoords,
+            &mesh.normals,
+            &mesh.indices,
+            options,
+        )
+    }
+
+    fn triangle() -> tobj::Mesh {
+        let mut mesh = tobj::Mesh::empty();
+        mesh.positions = vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        mesh.texcoords = vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0];
+        mesh.indices = vec![0, 1, 2];
+        mesh
+    }
+
+    #[test]
+    fn repairs_or_rejects_bad_meshes() {
+        let strict = LoadOptions { strict: true };
+        let lenient = LoadOptions::default();
+
+        let (vertices, _) = build(&triangle(), &strict).unwrap();
+        assert_eq!(vertices[0].tangent, [1.0, 0.0, 0.0]);
+
+        let mut nan = triangle();
+        nan.positions[4] = f32::NAN;
+        assert!(build(&nan, &strict).is_err());
+        let (vertices, _) = build(&nan, &lenient).unwrap();
+        assert_eq!(vertices[1].position, [0.0; 3]);
+
+        let mut out_of_range = triangle();
+        out_of_range.indices.extend_from_slice(&[0, 1, 7, 2]);
+        assert!(build(&out_of_range, &strict).is_err());
+        let (_, indices) = build(&out_of_range, &lenient).unwrap();
+        assert_eq!(indices, [0, 1, 2]);
+
+        let mut truncated = triangle();
+        truncated.texcoords.pop();
+        assert!(build(&truncated, &lenient).is_err());
+    }
+
+    #[test]
+    fn unit_cube_faces_point_outwards() {
+        use cgmath::{InnerSpace, Vector3};
+
+        let (vertices, indices) = unit_cube_vertices();
+        assert_eq!(indices.len(), 36);
+        for c in indices.chunks(3) {
+            let p = |i: u32| Vector3::from(vertices[i as usize].position);
+            let winding = (p(c[1]) - p(c[0])).cross(p(c[2]) - p(c[0]));
+            let normal = Vector3::from(vertices[c[0] as usize].normal);
+            assert!(winding.normalize().dot(normal) > 0.99);
+            assert!(p(c[0]).dot(normal) > 0.0);
+        }
+    }
+
+    #[test]
+    fn pbr_wgsl_validates() {
+        let shader = r#"
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>, @location(1) n: vec3<f32>) -> @location(0) vec4<f32> {
+    let surface = sample_material(uv);
+    if (material_clipped(surface)) {
+        discard;
+    }
+    let v = vec3<f32>(0.0, 0.0, 1.0);
+    let color = pbr_light(surface, normalize(n), v, normalize(vec3<f32>(1.0)), vec3<f32>(1.0))
+        + pbr_ambient(surface, vec3<f32>(0.03));
+    return vec4<f32>(color, surface.albedo.a);
+}
+"#;
+        crate::shader::validate_wgsl(&format!("{}{}", PBR_WGSL, shader)).unwrap();
+        // Has to match the WGSL struct's layout
+        assert_eq!(std::mem::size_of::<MaterialFactors>(), 64);
+    }
+
+    #[test]
+    fn model_vertex_wgsl_validates() {
+        let shader = r#"
+@fragment
+fn fs_main(in: ModelVertexOutput) -> @location(0) vec4<f32> {
+    let v = normalize(camera.view_position.xyz - in.world_position);
+    let uv = parallax_uv(in.tex_coords, tangent_space(in, v));
+    let surface = sample_material(uv);
+    let n = world_normal(in, surface.normal);
+    let color = pbr_light(surface, n, v, normalize(vec3<f32>(1.0)), vec3<f32>(1.0));
+    return vec4<f32>(color, surface.albedo.a);
+}
+"#;
+        crate::shader::validate_wgsl(&format!("{}{}{}", PBR_WGSL, MODEL_VERTEX_WGSL, shader))
+            .unwrap();
+    }
+
+    #[test]
+    fn tangents_are_smoothed_and_orthogonal() {
+        // Two triangles folded along x, sharing a smooth normal on the
+        // fold, with the right half's UVs mirrored
+        let positions = [0.0, 0.0, 0.0, 0.0, 1.0, 0.0, -1.0, 0.0, 0.5, 1.0, 0.0, 0.5];
+        let tex_coords = [0.5, 0.0, 0.5, 1.0, 0.0, 0.0, 0.0, 0.0];
+        let normals = [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, -0.5, 0.0, 1.0, 0.5, 0.0, 1.0];
+        let indices = [0, 1, 2, 0, 3, 1];
+        let (vertices, _) = build_vertices(
+            &positions,
+            &tex_coords,
+            &normals,
+            &indices,
+            &LoadOptions::default(),
+        )
+        .unwrap();
+        for v in &vertices {
+            let n = Vector3::from(v.normal).normalize();
+            let t = Vector3::from(v.tangent);
+            let b = Vector3::from(v.bitangent);
+            assert!((t.magnitude() - 1.0).abs() < 1e-5);
+            assert!((b.magnitude() - 1.0).abs() < 1e-5);
+            assert!(t.dot(n).abs() < 1e-5);
+            assert!(b.dot(n).abs() < 1e-5);
+        }
+        // The mirrored side's bitangent flips
+        let handedness = |v: &ModelVertex| {
+            Vector3::from(v.normal)
+                .cross(v.tangent.into())
+                .dot(v.bitangent.into())
+        };
+        assert!(handedness(&vertices[2]) * handedness(&vertices[3]) < 0.0);
+    }
+
+    #[test]
+    fn indirect_draws_are_packed() {
+        let draws = [
+            wgpu::util::DrawIndexedIndirectArgs {
+                index_count: 36,
+                instance_count: 10,
+                ..Default::default()
+            },
+            wgpu::util::DrawIndexedIndirectArgs {
+                index_count: 6,
+                instance_count: 1,
+                first_index: 30,
+                base_vertex: -4,
+                first_instance: 2,
+            },
+        ];
+        let bytes = indirect_bytes(&draws);
+        assert_eq!(
+            bytes.len() as wgpu::BufferAddress,
+            2 * DRAW_INDEXED_INDIRECT_SIZE
+        );
+        let words: &[u32] = bytemuck::cast_slice(&bytes);
+        assert_eq!(words, &[36, 10, 0, 0, 0, 6, 1, 30, -4i32 as u32, 2]);
+    }
+}
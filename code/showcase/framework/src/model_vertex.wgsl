@@ -0,0 +1,96 @@
+// A vertex shader for ModelVertex with the tutorials' instance matrix,
+// and the tangent space needed for normal maps. Put PBR_WGSL in front to
+// read a Material.
+
+struct Camera {
+    view_position: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+struct ModelVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+struct ModelVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+}
+
+@vertex
+fn vs_model(model: ModelVertexInput, instance: InstanceInput) -> ModelVertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    // Directions only get the rotation and scale. This assumes the scale
+    // is the same on every axis.
+    let linear = mat3x3<f32>(
+        instance.model_matrix_0.xyz,
+        instance.model_matrix_1.xyz,
+        instance.model_matrix_2.xyz,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: ModelVertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    out.tex_coords = model.tex_coords;
+    out.normal = linear * model.normal;
+    out.tangent = linear * model.tangent;
+    out.bitangent = linear * model.bitangent;
+    return out;
+}
+
+// The tangent space basis. Interpolation skews it, so it gets
+// straightened out again here.
+fn tangent_frame(in: ModelVertexOutput) -> mat3x3<f32> {
+    let n = normalize(in.normal);
+    let t = normalize(in.tangent - n * dot(n, in.tangent));
+    // Mirrored UVs flip the bitangent
+    var b = cross(n, t);
+    if (dot(b, in.bitangent) < 0.0) {
+        b = -b;
+    }
+    return mat3x3<f32>(t, b, n);
+}
+
+// Turns a normal from a normal map into world space
+fn world_normal(in: ModelVertexOutput, tangent_normal: vec3<f32>) -> vec3<f32> {
+    return normalize(tangent_frame(in) * tangent_normal);
+}
+
+// Turns a world space direction into tangent space, like parallax_uv
+// wants for the direction to the camera
+fn tangent_space(in: ModelVertexOutput, direction: vec3<f32>) -> vec3<f32> {
+    return direction * tangent_frame(in);
+}
+
+// How far a point moved across the screen since last frame, in UV units,
+// for a fragment shader to write to a MotionBlur::VELOCITY_FORMAT target.
+// This only covers the camera moving, not the model.
+fn screen_velocity(world_position: vec3<f32>) -> vec2<f32> {
+    let clip = camera.view_proj * vec4<f32>(world_position, 1.0);
+    let prev_clip = camera.prev_view_proj * vec4<f32>(world_position, 1.0);
+    return (clip.xy / clip.w - prev_clip.xy / prev_clip.w) * vec2<f32>(0.5, -0.5);
+}
@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::model::{Mesh, ModelVertex};
+use crate::shader::catch_validation_errors;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// How far one vertex moves in a [MorphTarget] at full weight
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MorphDelta {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 3],
+}
+
+/// A blend shape, such as a smile or a blink, with a delta for every
+/// vertex of the mesh
+#[derive(Debug, Clone)]
+pub struct MorphTarget {
+    pub name: String,
+    pub deltas: Vec<MorphDelta>,
+}
+
+/// The GPU side of a mesh's morph targets, see [Mesh::with_morph_targets]
+pub struct MorphTargets {
+    weights: TargetWeights,
+    vertex_count: u32,
+    base: wgpu::Buffer,
+    deltas: wgpu::Buffer,
+    params: wgpu::Buffer,
+    weights_buffer: wgpu::Buffer,
+}
+
+impl MorphTargets {
+    fn new(
+        device: &wgpu::Device,
+        name: &str,
+        vertices: &[ModelVertex],
+        targets: &[MorphTarget],
+    ) -> Result<Self> {
+        check_targets(name, vertices.len(), targets)?;
+
+        let base = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Morph Base Buffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        // Storage bindings can't be empty
+        let mut deltas = targets
+            .iter()
+            .flat_map(|t| t.deltas.iter().copied())
+            .collect::<Vec<_>>();
+        if deltas.is_empty() {
+            deltas.push(MorphDelta::default());
+        }
+        let deltas = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Morph Delta Buffer", name)),
+            contents: bytemuck::cast_slice(&deltas),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let vertex_count = vertices.len() as u32;
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Morph Params", name)),
+            contents: bytemuck::cast_slice(&[vertex_count, targets.len() as u32, 0, 0]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let weights_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{} Morph Weights", name)),
+            size: (targets.len().max(1) * std::mem::size_of::<f32>()) as _,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Ok(Self {
+            weights: TargetWeights::new(targets),
+            vertex_count,
+            base,
+            deltas,
+            params,
+            weights_buffer,
+        })
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.weights.names
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.weights.find(name)
+    }
+
+    pub fn weights(&self) -> &[f32] {
+        &self.weights.weights
+    }
+
+    /// Weights usually go from 0 to 1, but anything works
+    pub fn set_weight(&mut self, target: usize, weight: f32) {
+        self.weights.set(target, weight);
+    }
+
+    /// Sets the weights in target order. Extra weights are ignored.
+    pub fn set_weights(&mut self, weights: &[f32]) {
+        self.weights.set_all(weights);
+    }
+}
+
+/// Every target needs a delta for each vertex
+fn check_targets(name: &str, vertex_count: usize, targets: &[MorphTarget]) -> Result<()> {
+    ensure!(vertex_count > 0, "{} has no vertices", name);
+    for target in targets {
+        ensure!(
+            target.deltas.len() == vertex_count,
+            "Morph target {:?} of {} has {} deltas but the mesh has {} vertices",
+            target.name,
+            name,
+            target.deltas.len(),
+            vertex_count
+        );
+    }
+    Ok(())
+}
+
+/// The CPU copy of the weights, uploaded by [Morphing::blend]
+#[derive(Debug, Clone, PartialEq)]
+struct TargetWeights {
+    names: Vec<String>,
+    weights: Vec<f32>,
+}
+
+impl TargetWeights {
+    fn new(targets: &[MorphTarget]) -> Self {
+        Self {
+            names: targets.iter().map(|t| t.name.clone()).collect(),
+            weights: vec![0.0; targets.len()],
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    fn set(&mut self, target: usize, weight: f32) {
+        if let Some(w) = self.weights.get_mut(target) {
+            *w = weight;
+        }
+    }
+
+    fn set_all(&mut self, weights: &[f32]) {
+        for (w, new) in self.weights.iter_mut().zip(weights) {
+            *w = *new;
+        }
+    }
+}
+
+impl Mesh {
+    /// A mesh that [Morphing::blend] can deform. Each target needs a delta
+    /// for every vertex. The weights start at 0, so it draws as `vertices`
+    /// until they're changed.
+    pub fn with_morph_targets(
+        device: &wgpu::Device,
+        name: &str,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+        material: usize,
+        targets: &[MorphTarget],
+    ) -> Result<Self> {
+        let morph_targets = MorphTargets::new(device, name, vertices, targets)?;
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", name)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        Ok(Self {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            material,
+            morph_targets: Some(morph_targets),
+            skin: None,
+        })
+    }
+}
+
+/// The compute pass that applies morph target weights. Meshes are blended
+/// into their own vertex buffer, so they draw like any other mesh
+/// afterwards. Needs [Capabilities::compute_shaders](crate::Capabilities).
+///
+/// ```ignore
+/// let morphing = Morphing::new(&display.device)?;
+/// let face = &mut model.meshes[0];
+/// let morph = face.morph_targets.as_mut().unwrap();
+/// morph.set_weight(morph.find("smile").unwrap(), 0.8);
+///
+/// // Each frame, before drawing
+/// morphing.blend(&display.device, &display.queue, &mut encoder, &model.meshes);
+/// ```
+pub struct Morphing {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    /// Keyed by the vertex buffer the bind group writes to
+    bind_groups: HashMap<wgpu::Id<wgpu::Buffer>, wgpu::BindGroup>,
+}
+
+impl Morphing {
+    pub fn new(device: &wgpu::Device) -> Result<Self> {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Morphing::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, true),
+                storage(3, true),
+                storage(4, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Morphing::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = catch_validation_errors(device, || {
+            let module = device.create_shader_module(wgpu::include_wgsl!("morph.wgsl"));
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Morphing::pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        })?;
+        Ok(Self {
+            layout,
+            pipeline,
+            bind_groups: HashMap::new(),
+        })
+    }
+
+    /// Uploads the weights of every mesh in `meshes` that has morph targets
+    /// and blends them. Meshes without targets are skipped.
+    pub fn blend(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        meshes: &[Mesh],
+    ) {
+        crate::cpu_scope!("Morphing::blend");
+        let morphed = meshes
+            .iter()
+            .filter_map(|m| Some((m, m.morph_targets.as_ref()?)))
+            .collect::<Vec<_>>();
+        // Forget meshes that weren't passed in, they've probably been dropped
+        self.bind_groups.retain(|id, _| {
+            morphed
+                .iter()
+                .any(|(mesh, _)| mesh.vertex_buffer.global_id() == *id)
+        });
+        for (mesh, morph) in &morphed {
+            if !morph.weights().is_empty() {
+                queue.write_buffer(
+                    &morph.weights_buffer,
+                    0,
+                    bytemuck::cast_slice(morph.weights()),
+                );
+            }
+            let layout = &self.layout;
+            self.bind_groups
+                .entry(mesh.vertex_buffer.global_id())
+                .or_insert_with(|| create_bind_group(device, layout, mesh, morph));
+        }
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Morphing::blend"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        for (mesh, morph) in &morphed {
+            pass.set_bind_group(0, &self.bind_groups[&mesh.vertex_buffer.global_id()], &[]);
+            pass.dispatch_workgroups(morph.vertex_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    mesh: &Mesh,
+    morph: &MorphTargets,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(&mesh.name),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: morph.params.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: morph.base.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: morph.deltas.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: morph.weights_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: mesh.vertex_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(name: &str, vertices: usize) -> MorphTarget {
+        MorphTarget {
+            name: name.to_string(),
+            deltas: vec![MorphDelta::default(); vertices],
+        }
+    }
+
+    #[test]
+    fn targets_need_a_delta_per_vertex() {
+        check_targets("face", 3, &[target("smile", 3), target("blink", 3)]).unwrap();
+        check_targets("face", 3, &[]).unwrap();
+        let error = check_targets("face", 3, &[target("smile", 3), target("blink", 2)])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("\"blink\""), "{}", error);
+        assert!(check_targets("face", 0, &[]).is_err());
+    }
+
+    #[test]
+    fn weights_are_set_by_index_or_name() {
+        let mut weights = TargetWeights::new(&[target("smile", 1), target("blink", 1)]);
+        assert_eq!(weights.weights, [0.0, 0.0]);
+        assert_eq!(weights.find("blink"), Some(1));
+        assert_eq!(weights.find("frown"), None);
+
+        weights.set(1, 0.5);
+        // Out of range targets are ignored
+        weights.set(2, 1.0);
+        assert_eq!(weights.weights, [0.0, 0.5]);
+
+        weights.set_all(&[0.25]);
+        assert_eq!(weights.weights, [0.25, 0.5]);
+        weights.set_all(&[1.0, -1.0, 3.0]);
+        assert_eq!(weights.weights, [1.0, -1.0]);
+    }
+
+    #[test]
+    fn morph_wgsl_validates() {
+        // The shader reads both as packed floats
+        assert_eq!(std::mem::size_of::<ModelVertex>(), 14 * 4);
+        assert_eq!(std::mem::size_of::<MorphDelta>(), 9 * 4);
+        crate::shader::validate_wgsl(include_str!("morph.wgsl")).unwrap();
+    }
+}
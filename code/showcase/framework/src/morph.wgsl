@@ -0,0 +1,83 @@
+struct MorphParams {
+    vertex_count: u32,
+    target_count: u32,
+}
+
+// ModelVertex is 14 tightly packed floats and MorphDelta is 9
+const STRIDE: u32 = 14u;
+const POSITION: u32 = 0u;
+const TEX_COORDS: u32 = 3u;
+const NORMAL: u32 = 5u;
+const TANGENT: u32 = 8u;
+const BITANGENT: u32 = 11u;
+const DELTA_STRIDE: u32 = 9u;
+
+@group(0) @binding(0)
+var<uniform> params: MorphParams;
+@group(0) @binding(1)
+var<storage, read> base: array<f32>;
+// Every vertex of the first target, then every vertex of the next
+@group(0) @binding(2)
+var<storage, read> deltas: array<f32>;
+@group(0) @binding(3)
+var<storage, read> weights: array<f32>;
+@group(0) @binding(4)
+var<storage, read_write> morphed: array<f32>;
+
+fn base3(offset: u32) -> vec3<f32> {
+    return vec3<f32>(base[offset], base[offset + 1u], base[offset + 2u]);
+}
+
+fn delta3(offset: u32) -> vec3<f32> {
+    return vec3<f32>(deltas[offset], deltas[offset + 1u], deltas[offset + 2u]);
+}
+
+fn write3(offset: u32, value: vec3<f32>) {
+    morphed[offset] = value.x;
+    morphed[offset + 1u] = value.y;
+    morphed[offset + 2u] = value.z;
+}
+
+fn safe_normalize(v: vec3<f32>) -> vec3<f32> {
+    let len = length(v);
+    if (len > 0.0) {
+        return v / len;
+    }
+    return v;
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= params.vertex_count) {
+        return;
+    }
+
+    let offset = i * STRIDE;
+    var position = base3(offset + POSITION);
+    let base_normal = base3(offset + NORMAL);
+    let base_tangent = base3(offset + TANGENT);
+    var normal = base_normal;
+    var tangent = base_tangent;
+    for (var t = 0u; t < params.target_count; t = t + 1u) {
+        let weight = weights[t];
+        if (weight == 0.0) {
+            continue;
+        }
+        let delta = (t * params.vertex_count + i) * DELTA_STRIDE;
+        position = position + delta3(delta) * weight;
+        normal = normal + delta3(delta + 3u) * weight;
+        tangent = tangent + delta3(delta + 6u) * weight;
+    }
+    normal = safe_normalize(normal);
+    tangent = safe_normalize(tangent);
+    // Keep the bitangent on the same side it started on
+    let handedness = select(1.0, -1.0, dot(cross(base_normal, base_tangent), base3(offset + BITANGENT)) < 0.0);
+
+    write3(offset + POSITION, position);
+    morphed[offset + TEX_COORDS] = base[offset + TEX_COORDS];
+    morphed[offset + TEX_COORDS + 1u] = base[offset + TEX_COORDS + 1u];
+    write3(offset + NORMAL, normal);
+    write3(offset + TANGENT, tangent);
+    write3(offset + BITANGENT, safe_normalize(cross(normal, tangent)) * handedness);
+}
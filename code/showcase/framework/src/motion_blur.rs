@@ -0,0 +1,286 @@
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::post::{fullscreen_pass, PostContext, PostEffect, PostProcessChain};
+use crate::texture::Texture;
+
+/// Changed at runtime with [MotionBlur::set_settings]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MotionBlurSettings {
+    /// How much of the frame the shutter is open for. 1 blurs over the
+    /// whole distance moved since last frame, 0 turns the effect off.
+    pub strength: f32,
+    /// The longest the blur gets in pixels
+    pub max_blur: f32,
+}
+
+impl Default for MotionBlurSettings {
+    fn default() -> Self {
+        Self {
+            strength: 0.5,
+            max_blur: 32.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MotionBlurUniform {
+    strength: f32,
+    max_blur: f32,
+    _padding: [f32; 2],
+}
+
+impl MotionBlurUniform {
+    fn new(settings: MotionBlurSettings) -> Self {
+        Self {
+            strength: settings.strength.max(0.0),
+            max_blur: settings.max_blur.max(0.0),
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Smears the scene along how far each pixel moved since last frame. It
+/// goes before [crate::Tonemap] in a [PostProcessChain]. The scene writes that to [MotionBlur]'s velocity buffer as
+/// a second color target: build its pipeline with
+/// [RenderPipelineBuilder::velocity], draw with
+/// [MotionBlur::velocity_attachment] after the scene's own attachment, and
+/// have the fragment shader return `screen_velocity` from
+/// [crate::MODEL_VERTEX_WGSL] at location 1. The previous frame's matrix
+/// comes from [crate::CameraUniform]. The velocity buffer isn't
+/// multisampled, so the display needs a sample count of 1.
+///
+/// ```ignore
+/// post.add(MotionBlur::new(&display.device, &post, Default::default())?);
+///
+/// // In Demo::render
+/// let motion_blur = post.get::<MotionBlur>().unwrap();
+/// let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+///     color_attachments: &[
+///         Some(hdr.color_attachment(wgpu::LoadOp::Clear(wgpu::Color::BLACK))),
+///         Some(motion_blur.velocity_attachment()),
+///     ],
+///     ..
+/// });
+/// // ...
+/// post.process(display, &mut encoder, hdr.view(), None, &frame_view);
+/// ```
+pub struct MotionBlur {
+    settings: MotionBlurSettings,
+    uniform_buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    pipeline: wgpu::RenderPipeline,
+    velocity: Texture<'static>,
+}
+
+impl MotionBlur {
+    /// Screen space motion in UV units, see `screen_velocity`
+    pub const VELOCITY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+    pub fn new(
+        device: &wgpu::Device,
+        chain: &PostProcessChain,
+        settings: MotionBlurSettings,
+    ) -> Result<Self> {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("MotionBlur::uniform_buffer"),
+            contents: bytemuck::bytes_of(&MotionBlurUniform::new(settings)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("MotionBlur::layout"),
+            entries: &[
+                texture_entry(0),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(3),
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("MotionBlur::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("MotionBlur::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = framework_shader("motion_blur.wgsl", include_str!("motion_blur.wgsl"));
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(shader.module())
+            .fragment_shader(shader.module())
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_blur")
+            .color_solid(chain.format())
+            .build(device)?;
+
+        Ok(Self {
+            settings,
+            uniform_buffer,
+            layout,
+            sampler,
+            pipeline,
+            velocity: create_velocity(device, chain.width(), chain.height()),
+        })
+    }
+
+    pub fn settings(&self) -> MotionBlurSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: MotionBlurSettings) {
+        self.settings = settings;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&MotionBlurUniform::new(settings)),
+        );
+    }
+
+    pub fn velocity_view(&self) -> &wgpu::TextureView {
+        &self.velocity.view
+    }
+
+    /// The second color attachment for the scene's pass. Clears to no
+    /// motion, so anything not drawn with velocity stays sharp.
+    pub fn velocity_attachment(&self) -> wgpu::RenderPassColorAttachment<'_> {
+        wgpu::RenderPassColorAttachment {
+            view: &self.velocity.view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                store: wgpu::StoreOp::Store,
+            },
+        }
+    }
+}
+
+impl PostEffect for MotionBlur {
+    fn enabled(&self) -> bool {
+        self.settings.strength > 0.0 && self.settings.max_blur > 0.0
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.velocity = create_velocity(device, width, height);
+    }
+
+    /// Blurs the input along the velocity buffer
+    fn process(&mut self, encoder: &mut wgpu::CommandEncoder, ctx: &PostContext) {
+        crate::cpu_scope!("MotionBlur::process");
+        let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("MotionBlur::bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(ctx.input),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.velocity.view),
+                },
+            ],
+        });
+        fullscreen_pass(
+            encoder,
+            "MotionBlur::blur",
+            ctx.output,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            &self.pipeline,
+            &bind_group,
+        );
+    }
+}
+
+fn create_velocity(device: &wgpu::Device, width: u32, height: u32) -> Texture<'static> {
+    Texture::from_descriptor(
+        device,
+        wgpu::TextureDescriptor {
+            label: Some("MotionBlur::velocity"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: MotionBlur::VELOCITY_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{MODEL_VERTEX_WGSL, PBR_WGSL};
+
+    #[test]
+    fn motion_blur_wgsl_validates() {
+        assert_eq!(std::mem::size_of::<MotionBlurUniform>(), 16);
+        // Has to match the Camera struct in MODEL_VERTEX_WGSL
+        assert_eq!(std::mem::size_of::<crate::UniformData>(), 144);
+        crate::shader::validate_wgsl(
+            &framework_shader("motion_blur.wgsl", include_str!("motion_blur.wgsl")).source,
+        )
+        .unwrap();
+
+        let shader = r#"
+struct SceneOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main(in: ModelVertexOutput) -> SceneOutput {
+    let surface = sample_material(in.tex_coords);
+    return SceneOutput(surface.albedo, screen_velocity(in.world_position));
+}
+"#;
+        crate::shader::validate_wgsl(&format!("{}{}{}", PBR_WGSL, MODEL_VERTEX_WGSL, shader))
+            .unwrap();
+    }
+}
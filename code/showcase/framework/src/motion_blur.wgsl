@@ -0,0 +1,45 @@
+// Motion blur for a PostProcessChain. Each pixel averages the scene along the
+// path its velocity says it took during the frame.
+
+struct MotionBlurUniform {
+    strength: f32,
+    max_blur: f32,
+    _padding: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> motion_blur: MotionBlurUniform;
+@group(0) @binding(3)
+var velocity_texture: texture_2d<f32>;
+
+const MOTION_BLUR_SAMPLES: u32 = 12u;
+
+#include "framework/fullscreen.wgsl"
+
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(source));
+    let center = textureSample(source, source_sampler, in.uv);
+    let coord = clamp(vec2<i32>(in.uv * size), vec2<i32>(0), vec2<i32>(size) - 1);
+    // In pixels, capped so fast things don't tear apart
+    var pixels = textureLoad(velocity_texture, coord, 0).xy * size * motion_blur.strength;
+    let length_pixels = length(pixels);
+    if (length_pixels < 0.5) {
+        return center;
+    }
+    pixels *= min(length_pixels, motion_blur.max_blur) / length_pixels;
+
+    // Centered on the pixel, so the blur trails both ways like a shutter
+    // open either side of the frame
+    let step = pixels / size;
+    var color = vec3<f32>(0.0);
+    for (var i = 0u; i < MOTION_BLUR_SAMPLES; i++) {
+        let t = f32(i) / f32(MOTION_BLUR_SAMPLES - 1u) - 0.5;
+        color += textureSampleLevel(source, source_sampler, in.uv + step * t, 0.0).rgb;
+    }
+    return vec4<f32>(color / f32(MOTION_BLUR_SAMPLES), center.a);
+}
@@ -0,0 +1,117 @@
+use crate::texture;
+
+/// The multisampled color target [crate::Display] draws into before
+/// resolving to the surface. With a sample count of 1 there's no target
+/// and passes draw to the surface directly.
+pub struct MsaaTarget {
+    sample_count: u32,
+    view: Option<wgpu::TextureView>,
+}
+
+impl MsaaTarget {
+    /// Uses the most samples up to `requested` that the surface and
+    /// depth formats both support
+    pub fn new(
+        device: &wgpu::Device,
+        adapter: &wgpu::Adapter,
+        config: &wgpu::SurfaceConfiguration,
+        requested: u32,
+    ) -> Self {
+        let color = adapter.get_texture_format_features(config.format).flags;
+        let depth = adapter
+            .get_texture_format_features(texture::Texture::DEPTH_FORMAT)
+            .flags;
+        let sample_count = supported_sample_count(requested, |count| {
+            color.sample_count_supported(count) && depth.sample_count_supported(count)
+        });
+        if sample_count != requested {
+            log::warn!(
+                "{}x MSAA isn't supported, using {}x",
+                requested,
+                sample_count
+            );
+        }
+        let mut target = Self {
+            sample_count,
+            view: None,
+        };
+        target.resize(device, config);
+        target
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Remakes the target to match the surface
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        if self.sample_count == 1 {
+            self.view = None;
+            return;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("MsaaTarget"),
+            size: wgpu::Extent3d {
+                width: config.width.max(1),
+                height: config.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: self.sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        self.view = Some(texture.create_view(&Default::default()));
+    }
+
+    /// Draws to the multisampled target and resolves into `frame`, or
+    /// draws to `frame` itself without MSAA
+    pub fn color_attachment<'a>(
+        &'a self,
+        frame: &'a wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        let (view, resolve_target) = match &self.view {
+            Some(view) => (view, Some(frame)),
+            None => (frame, None),
+        };
+        wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target,
+            ops: wgpu::Operations {
+                load,
+                // Kept so later passes can load what earlier ones drew
+                store: wgpu::StoreOp::Store,
+            },
+        }
+    }
+}
+
+/// The highest power of two up to `requested` that's `supported`
+fn supported_sample_count(requested: u32, supported: impl Fn(u32) -> bool) -> u32 {
+    let mut count = requested.max(1).next_power_of_two();
+    if count > requested.max(1) {
+        count /= 2;
+    }
+    while count > 1 && !supported(count) {
+        count /= 2;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_supported_counts() {
+        let up_to_4 = |count| count <= 4;
+        assert_eq!(supported_sample_count(4, up_to_4), 4);
+        assert_eq!(supported_sample_count(8, up_to_4), 4);
+        assert_eq!(supported_sample_count(6, up_to_4), 4);
+        assert_eq!(supported_sample_count(0, up_to_4), 1);
+        assert_eq!(supported_sample_count(4, |count| count == 1), 1);
+    }
+}
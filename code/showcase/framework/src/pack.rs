@@ -0,0 +1,346 @@
+use anyhow::*;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::assets::Assets;
+use crate::model::{LoadOptions, Model};
+use crate::texture::Texture;
+
+const MAGIC: &[u8; 4] = b"LWPK";
+const VERSION: u32 = 1;
+
+/// Files bundled into one `.pack` by [PackBuilder], so a demo ships a
+/// single data file and the web build fetches it once instead of once per
+/// asset. Files are looked up by the relative path they were packed
+/// under, always with `/` between folders.
+///
+/// ```ignore
+/// let pack = AssetPack::open("res/demo.pack")?;
+/// let shader = pack.load_shader("shader.wgsl")?;
+/// let model = pack.load_obj(&display.device, &display.queue, &mut assets, &layout, "cube.obj")?;
+/// ```
+pub struct AssetPack {
+    data: Vec<u8>,
+    index: BTreeMap<String, (usize, usize)>,
+}
+
+impl AssetPack {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data =
+            std::fs::read(path).with_context(|| format!("Unable to read {}", path.display()))?;
+        Self::from_bytes(data).with_context(|| format!("Unable to open {}", path.display()))
+    }
+
+    /// For a pack that's already in memory, like one fetched on the web
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        let mut reader = Reader { data: &data, at: 0 };
+        if reader.take(4)? != MAGIC {
+            bail!("Not an asset pack");
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            bail!("Asset pack version {} isn't supported", version);
+        }
+        let count = reader.u32()?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let len = reader.u32()? as usize;
+            let path = std::str::from_utf8(reader.take(len)?)
+                .context("Asset path isn't UTF-8")?
+                .to_string();
+            let offset = reader.u64()? as usize;
+            let len = reader.u64()? as usize;
+            entries.push((path, offset, len));
+        }
+        let start = reader.at;
+        let mut index = BTreeMap::new();
+        for (path, offset, len) in entries {
+            let begin = start
+                .checked_add(offset)
+                .filter(|&b| b <= data.len() && len <= data.len() - b)
+                .with_context(|| format!("{} runs past the end of the pack", path))?;
+            index.insert(path, (begin, len));
+        }
+        Ok(Self { data, index })
+    }
+
+    /// Every packed path, in order
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.index.keys().map(|p| p.as_str())
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.index.contains_key(path)
+    }
+
+    pub fn get(&self, path: &str) -> Option<&[u8]> {
+        let &(begin, len) = self.index.get(path)?;
+        Some(&self.data[begin..begin + len])
+    }
+
+    fn require(&self, path: &str) -> Result<&[u8]> {
+        self.get(path)
+            .with_context(|| format!("{} isn't in the pack", path))
+    }
+
+    pub fn load_texture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &str,
+        is_normal_map: bool,
+    ) -> Result<Texture<'static>> {
+        Texture::from_bytes(
+            device,
+            queue,
+            Some(path),
+            is_normal_map,
+            self.require(path)?,
+        )
+        .with_context(|| format!("Unable to load {}", path))
+    }
+
+    /// A packed WGSL file, ready for `create_shader_module`
+    pub fn load_shader<'a>(&'a self, path: &'a str) -> Result<wgpu::ShaderModuleDescriptor<'a>> {
+        let source = std::str::from_utf8(self.require(path)?)
+            .with_context(|| format!("{} isn't UTF-8", path))?;
+        Ok(wgpu::ShaderModuleDescriptor {
+            label: Some(path),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+        })
+    }
+
+    pub fn load_obj(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+        path: &str,
+    ) -> Result<Model> {
+        self.load_obj_with_options(device, queue, assets, layout, path, &LoadOptions::default())
+    }
+
+    /// Like [Model::load_obj_with_options]. The .mtl and textures are
+    /// looked up next to the .obj in the pack.
+    pub fn load_obj_with_options(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+        path: &str,
+        options: &LoadOptions,
+    ) -> Result<Model> {
+        load_obj_with(device, queue, assets, layout, path, options, |path| {
+            self.require(path).map(Cow::Borrowed)
+        })
+    }
+}
+
+/// Loads an OBJ file, its .mtl and its textures through `read`, which is
+/// given paths with `/` between folders. Textures already in `assets`
+/// under the same path are shared rather than read again.
+pub(crate) fn load_obj_with<'a>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    assets: &mut Assets,
+    layout: &wgpu::BindGroupLayout,
+    path: &str,
+    options: &LoadOptions,
+    read: impl Fn(&str) -> Result<Cow<'a, [u8]>>,
+) -> Result<Model> {
+    let folder = match path.rfind('/') {
+        Some(i) => &path[..=i],
+        None => "",
+    };
+    let obj = read(path)?;
+    let (obj_models, obj_materials) =
+        tobj::load_obj_buf(&mut BufReader::new(&obj[..]), true, |mtl| {
+            let mtl = format!("{}{}", folder, mtl.to_string_lossy());
+            let data = read(&mtl).map_err(|_| tobj::LoadError::OpenFileFailed)?;
+            tobj::load_mtl_buf(&mut BufReader::new(&data[..]))
+        })
+        .with_context(|| format!("Unable to parse {}", path))?;
+    Model::from_obj(
+        device,
+        queue,
+        assets,
+        layout,
+        path,
+        obj_models,
+        obj_materials,
+        options,
+        |assets, file, is_normal_map| {
+            let file = format!("{}{}", folder, file);
+            if let Some(texture) = assets.find(&file) {
+                return Ok(texture);
+            }
+            let texture =
+                Texture::from_bytes(device, queue, Some(&file), is_normal_map, &read(&file)?)
+                    .with_context(|| format!("Unable to load {}", file))?;
+            Ok(assets.add_with_path(texture, &file))
+        },
+    )
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.at..self.at.saturating_add(len))
+            .context("Asset pack index is cut short")?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// Collects files into an [AssetPack]. The `pack` binary runs this over a
+/// folder:
+///
+/// ```text
+/// cargo run -p framework --bin pack -- res res/demo.pack
+/// ```
+#[derive(Default)]
+pub struct PackBuilder {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl PackBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces the file at `path`
+    pub fn add(&mut self, path: &str, data: Vec<u8>) -> &mut Self {
+        self.files.insert(path.replace('\\', "/"), data);
+        self
+    }
+
+    /// Adds every file under `dir`, keyed by its path relative to `dir`.
+    /// Other `.pack` files are skipped, so writing the pack into the folder
+    /// it was made from doesn't pack the last one into the next.
+    pub fn add_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<&mut Self> {
+        let dir = dir.as_ref();
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(folder) = pending.pop() {
+            let entries = std::fs::read_dir(&folder)
+                .with_context(|| format!("Unable to read {}", folder.display()))?;
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                if path.extension().is_some_and(|ext| ext == "pack") {
+                    continue;
+                }
+                let relative = path.strip_prefix(dir)?;
+                let key = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let data = std::fs::read(&path)
+                    .with_context(|| format!("Unable to read {}", path.display()))?;
+                self.files.insert(key, data);
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.files.len() as u32).to_le_bytes());
+        let mut offset = 0u64;
+        for (path, data) in &self.files {
+            out.extend_from_slice(&(path.len() as u32).to_le_bytes());
+            out.extend_from_slice(path.as_bytes());
+            out.extend_from_slice(&offset.to_le_bytes());
+            out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            offset += data.len() as u64;
+        }
+        for data in self.files.values() {
+            out.extend_from_slice(data);
+        }
+        out
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_bytes())
+            .with_context(|| format!("Unable to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_round_trip() {
+        let mut builder = PackBuilder::new();
+        builder
+            .add("shader.wgsl", b"@vertex fn vs_main() {}".to_vec())
+            .add("models\\cube.obj", b"v 0 0 0".to_vec())
+            .add("empty", Vec::new());
+        let bytes = builder.to_bytes();
+        let pack = AssetPack::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(
+            pack.paths().collect::<Vec<_>>(),
+            ["empty", "models/cube.obj", "shader.wgsl"]
+        );
+        assert_eq!(pack.get("models/cube.obj"), Some(&b"v 0 0 0"[..]));
+        assert_eq!(pack.get("empty"), Some(&[][..]));
+        assert!(pack.get("missing").is_none());
+        assert!(pack.load_shader("shader.wgsl").is_ok());
+
+        assert!(AssetPack::from_bytes(b"nope".to_vec()).is_err());
+        // Cut off in the middle of the data
+        assert!(AssetPack::from_bytes(bytes[..bytes.len() - 3].to_vec()).is_err());
+    }
+
+    #[test]
+    fn add_dir_skips_packs() {
+        let dir = std::env::temp_dir().join(format!("framework-pack-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("models")).unwrap();
+        std::fs::write(dir.join("shader.wgsl"), "fn a() {}").unwrap();
+        std::fs::write(dir.join("models").join("cube.obj"), "v 0 0 0").unwrap();
+        std::fs::write(dir.join("demo.pack"), "an older pack").unwrap();
+
+        let mut builder = PackBuilder::new();
+        builder.add_dir(&dir).unwrap();
+        assert_eq!(
+            builder.files.keys().collect::<Vec<_>>(),
+            ["models/cube.obj", "shader.wgsl"]
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,341 @@
+use anyhow::*;
+use cgmath::*;
+
+use crate::color::Color;
+use crate::pipeline::{dispatch, storage_buffer_entry, workgroup_count, ComputePipelineBuilder};
+use crate::rng::Rng;
+
+/// WGSL with `vs_particle` and `fs_particle`, which draw a
+/// [ParticleSystem] with its [ParticleSystem::layout] at group 0 and the
+/// camera at group 1
+pub const PARTICLES_WGSL: &str = include_str!("particles.wgsl");
+const SIMULATE_WGSL: &str = include_str!("particles_simulate.wgsl");
+const WORKGROUP_SIZE: u32 = 64;
+/// position and age, then velocity and lifetime
+const PARTICLE_SIZE: wgpu::BufferAddress = 32;
+
+/// Where particles come from and how they behave. Values given as
+/// `[low, high]` are picked at random for each particle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmitterDescriptor {
+    /// Particles alive at once, which is also how many are drawn
+    pub max_particles: u32,
+    /// Particles a second
+    pub rate: f32,
+    pub position: Point3<f32>,
+    pub direction: Vector3<f32>,
+    /// Half the angle of the cone particles leave in, in radians
+    pub spread: f32,
+    pub speed: [f32; 2],
+    /// In seconds
+    pub lifetime: [f32; 2],
+    pub gravity: Vector3<f32>,
+    /// The fraction of velocity lost each second
+    pub drag: f32,
+    /// Width of a particle at birth and at death
+    pub size: [f32; 2],
+    /// Blended in linear light over each particle's life. For more than
+    /// two colors, pick these from a [crate::Gradient] per emitter.
+    pub start_color: Color,
+    pub end_color: Color,
+    /// Picks the random numbers. The same seed emits the same particles
+    /// every run, so take one from [crate::Display::rng] to follow the
+    /// demo's seed.
+    pub seed: u32,
+}
+
+impl Default for EmitterDescriptor {
+    fn default() -> Self {
+        Self {
+            max_particles: 10_000,
+            rate: 1000.0,
+            position: Point3::origin(),
+            direction: Vector3::unit_y(),
+            spread: 0.3,
+            speed: [2.0, 4.0],
+            lifetime: [1.5, 3.0],
+            gravity: Vector3::new(0.0, -9.8, 0.0),
+            drag: 0.1,
+            size: [0.1, 0.02],
+            start_color: Color::new(1.0, 0.8, 0.3, 1.0),
+            end_color: Color::new(0.8, 0.1, 0.0, 0.0),
+            seed: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticleUniforms {
+    origin: [f32; 4],
+    direction: [f32; 4],
+    gravity: [f32; 4],
+    speed_lifetime: [f32; 4],
+    size: [f32; 4],
+    start_color: [f32; 4],
+    end_color: [f32; 4],
+    dt: f32,
+    emit: u32,
+    seed: u32,
+    count: u32,
+}
+
+impl ParticleUniforms {
+    fn new(emitter: &EmitterDescriptor, count: u32, dt: f32, emit: u32, seed: u32) -> Self {
+        let p = emitter.position;
+        let d = emitter.direction;
+        let g = emitter.gravity;
+        Self {
+            origin: [p.x, p.y, p.z, emitter.spread],
+            direction: [d.x, d.y, d.z, 0.0],
+            gravity: [g.x, g.y, g.z, emitter.drag],
+            speed_lifetime: [
+                emitter.speed[0],
+                emitter.speed[1],
+                emitter.lifetime[0],
+                emitter.lifetime[1],
+            ],
+            size: [emitter.size[0], emitter.size[1], 0.0, 0.0],
+            start_color: emitter.start_color.into(),
+            end_color: emitter.end_color.into(),
+            dt,
+            emit,
+            seed,
+            count,
+        }
+    }
+}
+
+/// Turns a rate into whole particles for each frame, carrying the
+/// fractions over so low rates still emit
+#[derive(Debug, Default)]
+struct EmissionClock {
+    carry: f32,
+}
+
+impl EmissionClock {
+    fn tick(&mut self, rate: f32, dt: f32) -> u32 {
+        let due = self.carry + rate.max(0.0) * dt;
+        let whole = due.floor();
+        self.carry = due - whole;
+        whole as u32
+    }
+}
+
+/// Particles that live entirely on the GPU. A compute shader brings dead
+/// particles back at the emitter and moves the live ones, and
+/// [PARTICLES_WGSL] draws each as a quad facing the camera straight from
+/// the same storage buffer, so nothing goes back and forth with the CPU
+/// but the emitter.
+///
+/// Drawing reads storage buffers in the vertex shader, which needs
+/// [crate::Capabilities::vertex_storage_buffers]. Particles aren't
+/// sorted, so pipelines should blend additively or not write depth.
+///
+/// ```ignore
+/// let mut particles = ParticleSystem::new(&display.device, EmitterDescriptor::default())?;
+/// let pipeline = RenderPipelineBuilder::new()
+///     .layout(&layout) // particles.layout() then the camera
+///     .vertex_shader(shader.module())
+///     .vertex_entry_point("vs_particle")
+///     .fragment_entry_point("fs_particle")
+///     // ...
+///     .build(&display.device)?;
+/// // Every frame
+/// particles.update(&display.queue, &mut encoder, dt);
+/// pass.set_pipeline(&pipeline);
+/// pass.set_bind_group(1, &camera_bind_group, &[]);
+/// particles.draw(&mut pass);
+/// ```
+pub struct ParticleSystem {
+    emitter: EmitterDescriptor,
+    /// `max_particles` when the buffers were made
+    capacity: u32,
+    clock: EmissionClock,
+    /// Particles asked for with [ParticleSystem::burst]
+    pending: u32,
+    frame: u32,
+    simulate: wgpu::ComputePipeline,
+    uniforms: wgpu::Buffer,
+    emitted: wgpu::Buffer,
+    simulate_bind_group: wgpu::BindGroup,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ParticleSystem {
+    pub fn new(device: &wgpu::Device, emitter: EmitterDescriptor) -> Result<Self> {
+        let count = emitter.max_particles.max(1);
+        let uniform_entry = |visibility| wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let compute = wgpu::ShaderStages::COMPUTE;
+        let simulate_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ParticleSystem::simulate_layout"),
+            entries: &[
+                uniform_entry(compute),
+                storage_buffer_entry(1, compute, false),
+                storage_buffer_entry(2, compute, false),
+            ],
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ParticleSystem::layout"),
+            entries: &[
+                uniform_entry(wgpu::ShaderStages::VERTEX),
+                storage_buffer_entry(1, wgpu::ShaderStages::VERTEX, true),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ParticleSystem::simulate_pipeline_layout"),
+            bind_group_layouts: &[&simulate_layout],
+            push_constant_ranges: &[],
+        });
+        let simulate = ComputePipelineBuilder::new()
+            .label("ParticleSystem::simulate")
+            .layout(&pipeline_layout)
+            .shader(wgpu::ShaderModuleDescriptor {
+                label: Some("ParticleSystem::simulate"),
+                source: wgpu::ShaderSource::Wgsl(SIMULATE_WGSL.into()),
+            })
+            .entry_point("simulate")
+            .build(device)?;
+
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ParticleSystem::uniforms"),
+            size: std::mem::size_of::<ParticleUniforms>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // Zeroed particles have outlived their lifetime of 0, so they
+        // start out dead
+        let particles = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ParticleSystem::particles"),
+            size: count as wgpu::BufferAddress * PARTICLE_SIZE,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let emitted = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ParticleSystem::emitted"),
+            size: 4,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let simulate_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ParticleSystem::simulate_bind_group"),
+            layout: &simulate_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: particles.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: emitted.as_entire_binding(),
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ParticleSystem::bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: particles.as_entire_binding(),
+                },
+            ],
+        });
+
+        Ok(Self {
+            emitter,
+            capacity: count,
+            clock: EmissionClock::default(),
+            pending: 0,
+            frame: 0,
+            simulate,
+            uniforms,
+            emitted,
+            simulate_bind_group,
+            layout,
+            bind_group,
+        })
+    }
+
+    /// Group 0 of the pipeline that draws the particles
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn emitter(&self) -> &EmitterDescriptor {
+        &self.emitter
+    }
+
+    /// Changes take effect on the next [ParticleSystem::update], apart
+    /// from `max_particles`, which is fixed when the system is made
+    pub fn emitter_mut(&mut self) -> &mut EmitterDescriptor {
+        &mut self.emitter
+    }
+
+    /// Emits `count` particles on top of the rate next update, as long as
+    /// enough are dead
+    pub fn burst(&mut self, count: u32) {
+        self.pending = self.pending.saturating_add(count);
+    }
+
+    /// Emits and moves particles `dt` seconds forward
+    pub fn update(&mut self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, dt: f32) {
+        let emit = self.clock.tick(self.emitter.rate, dt) + std::mem::take(&mut self.pending);
+        self.frame = self.frame.wrapping_add(1);
+        // Hashed so neighbouring seeds don't emit the same particles a
+        // frame apart
+        let seed = Rng::new((self.emitter.seed as u64) << 32 | self.frame as u64).next_u32();
+        let uniforms = ParticleUniforms::new(&self.emitter, self.capacity, dt, emit, seed);
+        queue.write_buffer(&self.uniforms, 0, bytemuck::cast_slice(&[uniforms]));
+        queue.write_buffer(&self.emitted, 0, bytemuck::cast_slice(&[0u32]));
+        dispatch(
+            encoder,
+            &self.simulate,
+            &[&self.simulate_bind_group],
+            workgroup_count([self.capacity, 1, 1], [WORKGROUP_SIZE, 1, 1]),
+        );
+    }
+
+    /// Draws every particle, dead or alive. The pipeline and camera need
+    /// to be set already.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..6, 0..self.capacity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emission_carries_fractions() {
+        let mut clock = EmissionClock::default();
+        // 10 a second at 60fps is one every six frames
+        let emitted: u32 = (0..60).map(|_| clock.tick(10.0, 1.0 / 60.0)).sum();
+        assert!((9..=10).contains(&emitted), "{}", emitted);
+        assert_eq!(clock.tick(-5.0, 1.0), 0);
+
+        assert_eq!(std::mem::size_of::<ParticleUniforms>(), 128);
+        crate::shader::validate_wgsl(SIMULATE_WGSL).unwrap();
+        crate::shader::validate_wgsl(PARTICLES_WGSL).unwrap();
+    }
+}
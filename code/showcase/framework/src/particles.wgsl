@@ -0,0 +1,87 @@
+// Draws a ParticleSystem as camera facing quads, six vertices an instance
+// and an instance a particle, with the particles at group 0 and the
+// camera at group 1. Dead particles collapse to nothing.
+
+struct Camera {
+    view_position: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+struct Particle {
+    position: vec4<f32>,
+    velocity: vec4<f32>,
+}
+
+struct ParticleUniforms {
+    origin: vec4<f32>,
+    direction: vec4<f32>,
+    gravity: vec4<f32>,
+    speed_lifetime: vec4<f32>,
+    size: vec4<f32>,
+    start_color: vec4<f32>,
+    end_color: vec4<f32>,
+    dt: f32,
+    emit: u32,
+    seed: u32,
+    count: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> particle_uniforms: ParticleUniforms;
+@group(0) @binding(1)
+var<storage, read> particles: array<Particle>;
+
+struct ParticleVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // From -1 to 1 across the quad
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+var<private> corners: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0),
+);
+
+@vertex
+fn vs_particle(
+    @builtin(vertex_index) vertex: u32,
+    @builtin(instance_index) instance: u32,
+) -> ParticleVertexOutput {
+    var out: ParticleVertexOutput;
+    let p = particles[instance];
+    let age = p.position.w;
+    let lifetime = p.velocity.w;
+    if (age >= lifetime) {
+        // Every corner in the same place, so nothing is rasterized
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+    let t = age / lifetime;
+    let size = mix(particle_uniforms.size.x, particle_uniforms.size.y, t);
+
+    let to_camera = normalize(camera.view_position.xyz - p.position.xyz);
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(to_camera.y) > 0.99) {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let right = normalize(cross(up, to_camera));
+    up = cross(to_camera, right);
+
+    let corner = corners[vertex % 6u];
+    let position = p.position.xyz + (right * corner.x + up * corner.y) * size * 0.5;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.uv = corner;
+    out.color = mix(particle_uniforms.start_color, particle_uniforms.end_color, t);
+    return out;
+}
+
+// A soft round dot, for alpha or additive blending
+@fragment
+fn fs_particle(in: ParticleVertexOutput) -> @location(0) vec4<f32> {
+    let falloff = 1.0 - smoothstep(0.5, 1.0, length(in.uv));
+    return vec4<f32>(in.color.rgb, in.color.a * falloff);
+}
@@ -0,0 +1,91 @@
+struct Particle {
+    // w is the age in seconds
+    position: vec4<f32>,
+    // w is how long the particle lives. It's dead once age passes it.
+    velocity: vec4<f32>,
+}
+
+struct ParticleUniforms {
+    // w is the spread, the half angle of the cone particles leave in
+    origin: vec4<f32>,
+    direction: vec4<f32>,
+    // w is the drag
+    gravity: vec4<f32>,
+    // Lowest and highest speed, then lowest and highest lifetime
+    speed_lifetime: vec4<f32>,
+    // Size at birth and at death
+    size: vec4<f32>,
+    start_color: vec4<f32>,
+    end_color: vec4<f32>,
+    dt: f32,
+    // How many dead particles to bring back this frame
+    emit: u32,
+    seed: u32,
+    count: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: ParticleUniforms;
+@group(0) @binding(1)
+var<storage, read_write> particles: array<Particle>;
+@group(0) @binding(2)
+var<storage, read_write> emitted: atomic<u32>;
+
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+var<private> rng_state: u32;
+
+fn random() -> f32 {
+    rng_state = pcg(rng_state);
+    return f32(rng_state) / 4294967295.0;
+}
+
+fn spawn(index: u32) -> Particle {
+    rng_state = pcg(index ^ pcg(uniforms.seed));
+
+    // Uniform over the cap of the cone around the direction
+    let cos_theta = mix(cos(uniforms.origin.w), 1.0, random());
+    let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    let phi = random() * 6.2831853;
+    let n = normalize(uniforms.direction.xyz);
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(n.y) > 0.99) {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let t = normalize(cross(up, n));
+    let b = cross(n, t);
+    let direction = n * cos_theta + (t * cos(phi) + b * sin(phi)) * sin_theta;
+
+    let speed = mix(uniforms.speed_lifetime.x, uniforms.speed_lifetime.y, random());
+    let lifetime = mix(uniforms.speed_lifetime.z, uniforms.speed_lifetime.w, random());
+    var p: Particle;
+    p.position = vec4<f32>(uniforms.origin.xyz, 0.0);
+    p.velocity = vec4<f32>(direction * speed, lifetime);
+    return p;
+}
+
+@compute @workgroup_size(64)
+fn simulate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= uniforms.count) {
+        return;
+    }
+    var p = particles[index];
+    if (p.position.w >= p.velocity.w) {
+        // Dead, so it's free to be emitted again
+        if (atomicAdd(&emitted, 1u) < uniforms.emit) {
+            particles[index] = spawn(index);
+        }
+        return;
+    }
+    let dt = uniforms.dt;
+    var velocity = p.velocity.xyz + uniforms.gravity.xyz * dt;
+    velocity = velocity * max(1.0 - uniforms.gravity.w * dt, 0.0);
+    p.position = vec4<f32>(p.position.xyz + velocity * dt, p.position.w + dt);
+    p.velocity = vec4<f32>(velocity, p.velocity.w);
+    particles[index] = p;
+}
@@ -0,0 +1,165 @@
+use anyhow::*;
+
+use crate::pipeline::RenderPipelineBuilder;
+use crate::time::Time;
+
+/// Pauses [Time] while the window is out of focus, and puts it back how
+/// it was when focus returns, so a demo paused by the user stays paused
+#[derive(Debug, Default)]
+pub(crate) struct FocusPause {
+    /// Whether time was paused before focus was lost, while paused for it
+    was_paused: Option<bool>,
+}
+
+impl FocusPause {
+    pub(crate) fn is_paused(&self) -> bool {
+        self.was_paused.is_some()
+    }
+
+    /// Returns true when focus coming back resumes the demo, so it needs
+    /// redrawing
+    pub(crate) fn set_focused(
+        &mut self,
+        focused: bool,
+        pause_on_focus_loss: bool,
+        time: &mut Time,
+    ) -> bool {
+        if !focused && pause_on_focus_loss && self.was_paused.is_none() {
+            self.was_paused = Some(time.is_paused());
+            time.set_paused(true);
+        } else if focused {
+            if let Some(was_paused) = self.was_paused.take() {
+                time.set_paused(was_paused);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// What [crate::run] draws instead of the demo while it's paused because
+/// the window lost focus. The demo owns its frames, so the overlay covers
+/// the whole screen rather than drawing on top of the last one.
+pub struct PauseOverlay {
+    pipeline: wgpu::RenderPipeline,
+    screen_size: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl PauseOverlay {
+    pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Result<Self> {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("PauseOverlay::layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let screen_size = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("PauseOverlay::screen_size"),
+            size: std::mem::size_of::<[f32; 2]>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("PauseOverlay::bind_group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: screen_size.as_entire_binding(),
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PauseOverlay::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(wgpu::include_wgsl!("pause.wgsl"))
+            .fragment_shader(wgpu::include_wgsl!("pause.wgsl"))
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .color_solid(output_format)
+            .build(device)?;
+
+        Ok(Self {
+            pipeline,
+            screen_size,
+            bind_group,
+        })
+    }
+
+    pub fn render(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        let size = [width as f32, height as f32];
+        queue.write_buffer(&self.screen_size, 0, bytemuck::cast_slice(&size));
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("PauseOverlay"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn focus_loss_pauses_and_restores_time() {
+        let mut time = Time::new();
+        let mut pause = FocusPause::default();
+        assert!(!pause.set_focused(false, true, &mut time));
+        assert!(pause.is_paused() && time.is_paused());
+        // Losing focus twice doesn't forget how it was
+        pause.set_focused(false, true, &mut time);
+        assert!(pause.set_focused(true, true, &mut time));
+        assert!(!pause.is_paused() && !time.is_paused());
+        // Regaining focus again does nothing
+        assert!(!pause.set_focused(true, true, &mut time));
+    }
+
+    #[test]
+    fn user_pause_survives_focus_loss() {
+        let mut time = Time::new();
+        time.set_paused(true);
+        let mut pause = FocusPause::default();
+        pause.set_focused(false, true, &mut time);
+        assert!(pause.set_focused(true, true, &mut time));
+        assert!(time.is_paused());
+
+        let mut time = Time::new();
+        pause.set_focused(false, false, &mut time);
+        assert!(!pause.is_paused() && !time.is_paused());
+    }
+
+    #[test]
+    fn pause_wgsl_validates() {
+        crate::shader::validate_wgsl(include_str!("pause.wgsl")).unwrap();
+    }
+}
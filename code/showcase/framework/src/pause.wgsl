@@ -0,0 +1,21 @@
+// A dark screen with a pause symbol in the middle
+
+@group(0) @binding(0)
+var<uniform> screen_size: vec2<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    // In units of the smaller side, centered on the screen
+    let p = (position.xy - screen_size * 0.5) / min(screen_size.x, screen_size.y);
+    let bar = abs(abs(p.x) - 0.05) < 0.025 && abs(p.y) < 0.1;
+    if (bar) {
+        return vec4<f32>(0.8, 0.8, 0.8, 1.0);
+    }
+    return vec4<f32>(0.02, 0.02, 0.02, 1.0);
+}
@@ -0,0 +1,107 @@
+// Reads a Material bound to group 0. Needs brdf.wgsl, which PBR_WGSL
+// already includes.
+
+struct MaterialFactors {
+    albedo: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    alpha_cutoff: f32,
+    height_scale: f32,
+    height_offset: f32,
+    parallax_min_layers: f32,
+    parallax_max_layers: f32,
+}
+
+@group(0) @binding(0)
+var albedo_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var albedo_sampler: sampler;
+@group(0) @binding(2)
+var normal_texture: texture_2d<f32>;
+@group(0) @binding(3)
+var normal_sampler: sampler;
+@group(0) @binding(4)
+var metallic_roughness_texture: texture_2d<f32>;
+@group(0) @binding(5)
+var metallic_roughness_sampler: sampler;
+@group(0) @binding(6)
+var occlusion_texture: texture_2d<f32>;
+@group(0) @binding(7)
+var occlusion_sampler: sampler;
+@group(0) @binding(8)
+var emissive_texture: texture_2d<f32>;
+@group(0) @binding(9)
+var emissive_sampler: sampler;
+@group(0) @binding(10)
+var<uniform> material: MaterialFactors;
+@group(0) @binding(11)
+var height_texture: texture_2d<f32>;
+@group(0) @binding(12)
+var height_sampler: sampler;
+
+fn sample_material(uv: vec2<f32>) -> Surface {
+    var surface: Surface;
+    surface.albedo = textureSample(albedo_texture, albedo_sampler, uv) * material.albedo;
+    let normal = textureSample(normal_texture, normal_sampler, uv).xyz * 2.0 - 1.0;
+    surface.normal = normalize(normal * vec3<f32>(material.normal_scale, material.normal_scale, 1.0));
+    let mr = textureSample(metallic_roughness_texture, metallic_roughness_sampler, uv);
+    surface.metallic = mr.b * material.metallic;
+    // Fully smooth surfaces make the highlight vanish
+    surface.roughness = clamp(mr.g * material.roughness, 0.04, 1.0);
+    let occlusion = textureSample(occlusion_texture, occlusion_sampler, uv).r;
+    surface.occlusion = mix(1.0, occlusion, material.occlusion_strength);
+    surface.emissive = textureSample(emissive_texture, emissive_sampler, uv).rgb * material.emissive;
+    return surface;
+}
+
+// Parallax occlusion mapping: walks the view ray down into the height
+// texture and returns the texture coordinates where it hits. `view` points
+// from the surface to the camera in tangent space. Does nothing unless the
+// material has a height_scale.
+fn parallax_uv(uv: vec2<f32>, view: vec3<f32>) -> vec2<f32> {
+    if (material.height_scale <= 0.0) {
+        return uv;
+    }
+    let v = normalize(view);
+    // Grazing angles cover more of the texture, so they need more steps
+    let layers = mix(material.parallax_max_layers, material.parallax_min_layers, abs(v.z));
+    let layer_depth = 1.0 / max(layers, 1.0);
+    // How far across the texture the ray moves going all the way down
+    let shift = v.xy / max(v.z, 0.05) * material.height_scale;
+    let step = shift * layer_depth;
+    // Gradients have to come from outside the loop
+    let ddx = dpdx(uv);
+    let ddy = dpdy(uv);
+
+    var current_uv = uv + shift * material.height_offset;
+    var ray_depth = 0.0;
+    var surface_depth = 1.0 - textureSampleGrad(height_texture, height_sampler, current_uv, ddx, ddy).r;
+    for (var i = 0; i < 256; i++) {
+        if (ray_depth >= surface_depth || ray_depth >= 1.0) {
+            break;
+        }
+        current_uv -= step;
+        ray_depth += layer_depth;
+        surface_depth = 1.0 - textureSampleGrad(height_texture, height_sampler, current_uv, ddx, ddy).r;
+    }
+
+    // Blend between the last two steps to hide the layers
+    let previous_uv = current_uv + step;
+    let after = surface_depth - ray_depth;
+    let before = 1.0 - textureSampleGrad(height_texture, height_sampler, previous_uv, ddx, ddy).r
+        - (ray_depth - layer_depth);
+    let denominator = after - before;
+    var weight = 0.0;
+    if (abs(denominator) > 0.0001) {
+        weight = after / denominator;
+    }
+    return mix(current_uv, previous_uv, clamp(weight, 0.0, 1.0));
+}
+
+// Whether a fragment should be discarded for being under the alpha cutoff
+fn material_clipped(surface: Surface) -> bool {
+    return surface.albedo.a < material.alpha_cutoff;
+}
@@ -1,12 +1,20 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
 
+use crate::deferred::GBuffer;
 use crate::model::Vertex;
+use crate::reflection::ReflectedLayout;
+use crate::shader::{catch_validation_errors, validate_shader_module, ShaderError};
 use anyhow::*;
 
 pub struct RenderPipelineBuilder<'a> {
     layout: Option<&'a wgpu::PipelineLayout>,
     vertex_shader: Option<wgpu::ShaderModuleDescriptor<'a>>,
     fragment_shader: Option<wgpu::ShaderModuleDescriptor<'a>>,
+    vertex_entry_point: &'a str,
+    fragment_entry_point: &'a str,
     front_face: wgpu::FrontFace,
     cull_mode: Option<wgpu::Face>,
     depth_bias: i32,
@@ -21,6 +29,9 @@ pub struct RenderPipelineBuilder<'a> {
     sample_mask: u64,
     alpha_to_coverage_enabled: bool,
     multiview: Option<NonZeroU32>,
+    reflection: Option<&'a ReflectedLayout>,
+    /// From a GLSL or SPIR-V shader that didn't compile, for build to return
+    shader_error: Option<ShaderError>,
 }
 
 impl<'a> RenderPipelineBuilder<'a> {
@@ -29,6 +40,8 @@ impl<'a> RenderPipelineBuilder<'a> {
             layout: None,
             vertex_shader: None,
             fragment_shader: None,
+            vertex_entry_point: "main",
+            fragment_entry_point: "main",
             front_face: wgpu::FrontFace::Ccw,
             cull_mode: None,
             depth_bias: 0,
@@ -43,9 +56,20 @@ impl<'a> RenderPipelineBuilder<'a> {
             sample_mask: !0,
             alpha_to_coverage_enabled: false,
             multiview: None,
+            reflection: None,
+            shader_error: None,
         }
     }
 
+    /// Uses a layout made from the shaders, and checks the vertex buffers
+    /// supply what the vertex shader expects when building
+    pub fn from_reflection(reflection: &'a ReflectedLayout) -> Self {
+        let mut builder = Self::new();
+        builder.layout = Some(reflection.pipeline_layout());
+        builder.reflection = Some(reflection);
+        builder
+    }
+
     pub fn layout(&mut self, layout: &'a wgpu::PipelineLayout) -> &mut Self {
         self.layout = Some(layout);
         self
@@ -61,6 +85,60 @@ impl<'a> RenderPipelineBuilder<'a> {
         self
     }
 
+    /// Compiles a GLSL vertex shader. If it doesn't compile the error comes
+    /// back from [RenderPipelineBuilder::build].
+    #[cfg(feature = "glsl")]
+    pub fn vertex_shader_glsl(&mut self, label: &'a str, source: &str) -> &mut Self {
+        let module = crate::shader::glsl_module(label, source, wgpu::ShaderStages::VERTEX);
+        self.compiled_shader(module, true)
+    }
+
+    #[cfg(feature = "glsl")]
+    pub fn fragment_shader_glsl(&mut self, label: &'a str, source: &str) -> &mut Self {
+        let module = crate::shader::glsl_module(label, source, wgpu::ShaderStages::FRAGMENT);
+        self.compiled_shader(module, false)
+    }
+
+    /// Uses a compiled SPIR-V vertex shader. The entry point keeps whatever
+    /// name it was compiled with.
+    #[cfg(feature = "spirv")]
+    pub fn vertex_shader_spirv(&mut self, label: &'a str, data: &[u8]) -> &mut Self {
+        let module = crate::shader::spirv_module(label, data);
+        self.compiled_shader(module, true)
+    }
+
+    #[cfg(feature = "spirv")]
+    pub fn fragment_shader_spirv(&mut self, label: &'a str, data: &[u8]) -> &mut Self {
+        let module = crate::shader::spirv_module(label, data);
+        self.compiled_shader(module, false)
+    }
+
+    #[cfg(any(feature = "glsl", feature = "spirv"))]
+    fn compiled_shader(
+        &mut self,
+        module: Result<wgpu::ShaderModuleDescriptor<'a>, ShaderError>,
+        vertex: bool,
+    ) -> &mut Self {
+        match module {
+            Result::Ok(module) if vertex => self.vertex_shader = Some(module),
+            Result::Ok(module) => self.fragment_shader = Some(module),
+            Err(e) => self.shader_error = Some(e),
+        }
+        self
+    }
+
+    /// Defaults to `"main"` which is what the glsl shaders use
+    pub fn vertex_entry_point(&mut self, name: &'a str) -> &mut Self {
+        self.vertex_entry_point = name;
+        self
+    }
+
+    /// Defaults to `"main"` which is what the glsl shaders use
+    pub fn fragment_entry_point(&mut self, name: &'a str) -> &mut Self {
+        self.fragment_entry_point = name;
+        self
+    }
+
     #[allow(dead_code)]
     pub fn front_face(&mut self, ff: wgpu::FrontFace) -> &mut Self {
         self.front_face = ff;
@@ -137,6 +215,21 @@ impl<'a> RenderPipelineBuilder<'a> {
         self.depth_no_stencil(format, true, wgpu::CompareFunction::Less)
     }
 
+    /// Helper method that writes to every target of a [GBuffer] and
+    /// depth tests against its depth
+    pub fn gbuffer(&mut self) -> &mut Self {
+        for target in GBuffer::color_targets().iter().flatten() {
+            self.color_state(target.clone());
+        }
+        self.depth_format(GBuffer::DEPTH_FORMAT)
+    }
+
+    /// Helper method that adds a [crate::MotionBlur] velocity target after
+    /// the color targets added so far
+    pub fn velocity(&mut self) -> &mut Self {
+        self.color_solid(crate::MotionBlur::VELOCITY_FORMAT)
+    }
+
     #[allow(dead_code)]
     pub fn index_format(&mut self, ifmt: wgpu::IndexFormat) -> &mut Self {
         self.index_format = ifmt;
@@ -159,6 +252,29 @@ impl<'a> RenderPipelineBuilder<'a> {
         self
     }
 
+    /// Matches the sample count of [crate::Display::color_attachment], for
+    /// pipelines that draw to the surface
+    pub fn for_display(&mut self, display: &crate::Display) -> &mut Self {
+        self.sample_count(display.sample_count())
+    }
+
+    /// Draws into [crate::HdrPipeline::color_attachment], matching its
+    /// format and sample count
+    pub fn for_hdr(&mut self, hdr: &crate::HdrPipeline) -> &mut Self {
+        self.color_solid(hdr.format())
+            .sample_count(hdr.sample_count())
+    }
+
+    /// An inverted hull outline pass with [crate::STYLIZED_WGSL]'s
+    /// `vs_outline` and `fs_outline`. Front faces are culled so only the
+    /// pushed out shell behind the model shows. Draw it with the same
+    /// layout, vertex buffers and targets as the model itself.
+    pub fn outline(&mut self) -> &mut Self {
+        self.vertex_entry_point("vs_outline")
+            .fragment_entry_point("fs_outline")
+            .cull_mode(Some(wgpu::Face::Front))
+    }
+
     #[allow(dead_code)]
     pub fn sample_mask(&mut self, sm: u64) -> &mut Self {
         self.sample_mask = sm;
@@ -177,6 +293,10 @@ impl<'a> RenderPipelineBuilder<'a> {
     }
 
     pub fn build(&mut self, device: &wgpu::Device) -> Result<wgpu::RenderPipeline> {
+        if let Some(error) = self.shader_error.take() {
+            return Err(error.into());
+        }
+
         // We need a layout
         if self.layout.is_none() {
             bail!("No pipeline layout supplied!");
@@ -194,62 +314,612 @@ impl<'a> RenderPipelineBuilder<'a> {
         if self.vertex_shader.is_none() {
             bail!("No vertex shader supplied!")
         }
-        let vs = create_shader_module(
-            device,
-            self.vertex_shader
-                .take()
-                .context("Please include a vertex shader")?,
-        );
+        let vs_desc = self
+            .vertex_shader
+            .take()
+            .context("Please include a vertex shader")?;
 
         // The fragment shader is optional (IDK why, but it is).
         // Having the shader be optional is giving me issues with
         // the borrow checker so I'm going to use a default shader
         // if the user doesn't supply one.
-        let fs_spv = self
+        let fs_desc = self
             .fragment_shader
             .take()
             .context("Please include a fragment shader")?;
-        let fs = create_shader_module(device, fs_spv);
-
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(layout),
-            vertex: wgpu::VertexState {
-                module: &vs,
-                entry_point: "main",
-                buffers: &self.vertex_buffers,
-                compilation_options: Default::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &fs,
-                entry_point: "main",
-                targets: &self.color_states,
+
+        // wgpu panics on invalid shaders, so we check them with naga
+        // first to get an error we can show to the user.
+        validate_shader_module(&vs_desc)?;
+        validate_shader_module(&fs_desc)?;
+        if let Some(reflection) = self.reflection {
+            reflection.check_vertex_buffers(self.vertex_entry_point, &self.vertex_buffers)?;
+        }
+
+        let pipeline = catch_validation_errors(device, || {
+            let vs = create_shader_module(device, vs_desc);
+            let fs = create_shader_module(device, fs_desc);
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Render Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &vs,
+                    entry_point: self.vertex_entry_point,
+                    buffers: &self.vertex_buffers,
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &fs,
+                    entry_point: self.fragment_entry_point,
+                    targets: &self.color_states,
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: self.primitive_topology,
+                    front_face: self.front_face,
+                    cull_mode: self.cull_mode,
+                    strip_index_format: None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    ..Default::default()
+                },
+                depth_stencil: self.depth_stencil.clone(),
+                multisample: wgpu::MultisampleState {
+                    count: self.sample_count,
+                    mask: self.sample_mask,
+                    alpha_to_coverage_enabled: self.alpha_to_coverage_enabled,
+                },
+                multiview: self.multiview,
+                cache: None,
+            })
+        })?;
+        Ok(pipeline)
+    }
+}
+
+/// Builds a compute pipeline, checking the shader with naga first like
+/// [RenderPipelineBuilder] does
+///
+/// ```ignore
+/// let layout = display.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+///     label: Some("particles"),
+///     entries: &[
+///         storage_buffer_entry(0, wgpu::ShaderStages::COMPUTE, true),
+///         storage_buffer_entry(1, wgpu::ShaderStages::COMPUTE, false),
+///     ],
+/// });
+/// let pipeline_layout = display.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+///     label: Some("particles"),
+///     bind_group_layouts: &[&layout],
+///     push_constant_ranges: &[],
+/// });
+/// let pipeline = ComputePipelineBuilder::new()
+///     .layout(&pipeline_layout)
+///     .shader(wgpu::include_wgsl!("particles.wgsl"))
+///     .entry_point("simulate")
+///     .build(&display.device)?;
+/// // Every frame
+/// dispatch(&mut encoder, &pipeline, &[&bind_group], workgroup_count([count, 1, 1], [64, 1, 1]));
+/// ```
+pub struct ComputePipelineBuilder<'a> {
+    label: Option<&'a str>,
+    layout: Option<&'a wgpu::PipelineLayout>,
+    shader: Option<wgpu::ShaderModuleDescriptor<'a>>,
+    entry_point: &'a str,
+}
+
+impl<'a> Default for ComputePipelineBuilder<'a> {
+    fn default() -> Self {
+        Self {
+            label: None,
+            layout: None,
+            shader: None,
+            entry_point: "main",
+        }
+    }
+}
+
+impl<'a> ComputePipelineBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses a layout made from the shader, see [ReflectedLayout]
+    pub fn from_reflection(reflection: &'a ReflectedLayout) -> Self {
+        let mut builder = Self::new();
+        builder.layout = Some(reflection.pipeline_layout());
+        builder
+    }
+
+    pub fn label(&mut self, label: &'a str) -> &mut Self {
+        self.label = Some(label);
+        self
+    }
+
+    /// Without one wgpu works the layout out from the shader, and the bind
+    /// group layouts can be had from `get_bind_group_layout`
+    pub fn layout(&mut self, layout: &'a wgpu::PipelineLayout) -> &mut Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    pub fn shader(&mut self, src: wgpu::ShaderModuleDescriptor<'a>) -> &mut Self {
+        self.shader = Some(src);
+        self
+    }
+
+    /// Defaults to `"main"`
+    pub fn entry_point(&mut self, name: &'a str) -> &mut Self {
+        self.entry_point = name;
+        self
+    }
+
+    pub fn build(&mut self, device: &wgpu::Device) -> Result<wgpu::ComputePipeline> {
+        let desc = self.shader.take().context("No compute shader supplied!")?;
+        validate_shader_module(&desc)?;
+        let pipeline = catch_validation_errors(device, || {
+            let module = create_shader_module(device, desc);
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(self.label.unwrap_or("Compute Pipeline")),
+                layout: self.layout,
+                module: &module,
+                entry_point: self.entry_point,
                 compilation_options: Default::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: self.primitive_topology,
-                front_face: self.front_face,
-                cull_mode: self.cull_mode,
-                strip_index_format: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                ..Default::default()
-            },
-            depth_stencil: self.depth_stencil.clone(),
-            multisample: wgpu::MultisampleState {
-                count: self.sample_count,
-                mask: self.sample_mask,
-                alpha_to_coverage_enabled: self.alpha_to_coverage_enabled,
-            },
-            multiview: self.multiview,
-            cache: None,
-        });
+                cache: None,
+            })
+        })?;
         Ok(pipeline)
     }
 }
 
+/// A storage buffer in a bind group layout. Buffers a vertex shader reads
+/// need `read_only`, as vertex shaders can't write to storage.
+pub fn storage_buffer_entry(
+    binding: u32,
+    visibility: wgpu::ShaderStages,
+    read_only: bool,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// How many workgroups of `workgroup_size` it takes to cover `size`
+/// invocations. Shaders need to skip the ones past the end.
+pub fn workgroup_count(size: [u32; 3], workgroup_size: [u32; 3]) -> [u32; 3] {
+    [0, 1, 2].map(|i| size[i].div_ceil(workgroup_size[i].max(1)))
+}
+
+/// Records a compute pass that runs `pipeline` once with `bind_groups` at
+/// groups 0, 1 and so on
+pub fn dispatch(
+    encoder: &mut wgpu::CommandEncoder,
+    pipeline: &wgpu::ComputePipeline,
+    bind_groups: &[&wgpu::BindGroup],
+    [x, y, z]: [u32; 3],
+) {
+    let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+        label: Some("dispatch"),
+        timestamp_writes: None,
+    });
+    pass.set_pipeline(pipeline);
+    for (index, bind_group) in bind_groups.iter().enumerate() {
+        pass.set_bind_group(index as u32, bind_group, &[]);
+    }
+    pass.dispatch_workgroups(x, y, z);
+}
+
 fn create_shader_module(
     device: &wgpu::Device,
     spirv: wgpu::ShaderModuleDescriptor,
 ) -> wgpu::ShaderModule {
     device.create_shader_module(spirv)
 }
+
+/// How deep `#include`s can nest before we assume something's wrong
+const MAX_INCLUDE_DEPTH: u32 = 32;
+
+/// Runs `#include`, `#define` and `#ifdef` over WGSL before it's handed to
+/// [RenderPipelineBuilder], so shared lighting and shadow code can live in
+/// one file. The framework's own WGSL can be included by name, eg.
+/// `#include "framework/lights.wgsl"` for [crate::LIGHTS_WGSL].
+///
+/// | Directive | Meaning |
+/// |-----------|---------|
+/// | `#include "file"` | Pastes in a registered include, or a file next to the current one or in an include folder. Each is only pasted once. |
+/// | `#define NAME value` | Replaces `NAME` with `value` in the lines that follow. The value is optional. |
+/// | `#undef NAME` | |
+/// | `#ifdef NAME` `#ifndef NAME` | Keeps the lines up to `#else` or `#endif` if `NAME` is (or isn't) defined |
+/// | `#if NAME` | Like `#ifdef`, but `0` and `false` count as off |
+///
+/// ```ignore
+/// let mut preprocessor = ShaderPreprocessor::new();
+/// preprocessor.include_dir("res/shaders").define("SHADOW_CASCADES", "4");
+/// let shader = preprocessor.load("res/shaders/forward.wgsl")?;
+/// let pipeline = RenderPipelineBuilder::new()
+///     .vertex_shader(shader.module())
+///     .fragment_shader(shader.module())
+///     // ...
+///     .build(&display.device)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct ShaderPreprocessor {
+    includes: HashMap<String, Cow<'static, str>>,
+    include_dirs: Vec<PathBuf>,
+    defines: HashMap<String, String>,
+}
+
+impl Default for ShaderPreprocessor {
+    fn default() -> Self {
+        let builtins: [(&str, &'static str); 17] = [
+            ("framework/brdf.wgsl", crate::model::BRDF_WGSL),
+            ("framework/pbr.wgsl", crate::model::PBR_WGSL),
+            (
+                "framework/model_vertex.wgsl",
+                crate::model::MODEL_VERTEX_WGSL,
+            ),
+            ("framework/clipmap.wgsl", crate::clipmap::CLIPMAP_WGSL),
+            ("framework/fullscreen.wgsl", crate::post::FULLSCREEN_WGSL),
+            ("framework/lights.wgsl", crate::light::LIGHTS_WGSL),
+            ("framework/gbuffer.wgsl", crate::deferred::GBUFFER_WGSL),
+            ("framework/ibl.wgsl", crate::ibl::IBL_WGSL),
+            (
+                "framework/irradiance_volume.wgsl",
+                crate::irradiance_volume::IRRADIANCE_VOLUME_WGSL,
+            ),
+            ("framework/sh.wgsl", crate::sh::SH_WGSL),
+            ("framework/shadow.wgsl", crate::shadow::SHADOW_WGSL),
+            (
+                "framework/shadow_cascades.wgsl",
+                crate::shadow::SHADOW_CASCADES_WGSL,
+            ),
+            (
+                "framework/shadow_point.wgsl",
+                crate::shadow::SHADOW_POINT_WGSL,
+            ),
+            (
+                "framework/skinning_texture.wgsl",
+                crate::skinning::SKINNING_TEXTURE_WGSL,
+            ),
+            ("framework/stylized.wgsl", crate::stylized::STYLIZED_WGSL),
+            (
+                "framework/vertex_animation.wgsl",
+                crate::vertex_animation::VERTEX_ANIMATION_WGSL,
+            ),
+            ("framework/voxel.wgsl", crate::voxel::VOXEL_WGSL),
+        ];
+        Self {
+            includes: builtins
+                .iter()
+                .map(|(name, source)| (name.to_string(), Cow::Borrowed(*source)))
+                .collect(),
+            include_dirs: Vec::new(),
+            defines: HashMap::new(),
+        }
+    }
+}
+
+impl ShaderPreprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `source` available to `#include "name"`
+    pub fn add_include(&mut self, name: &str, source: impl Into<Cow<'static, str>>) -> &mut Self {
+        self.includes.insert(name.to_string(), source.into());
+        self
+    }
+
+    /// A folder to look for included files in
+    pub fn include_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.include_dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Defines `name` for every shader this processes, as if each started
+    /// with `#define name value`
+    pub fn define(&mut self, name: &str, value: &str) -> &mut Self {
+        self.defines.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn undefine(&mut self, name: &str) -> &mut Self {
+        self.defines.remove(name);
+        self
+    }
+
+    /// Processes `source`. `label` names it in errors.
+    pub fn process(&self, source: &str, label: &str) -> Result<String, ShaderError> {
+        let mut state = Expansion {
+            defines: self.defines.clone(),
+            included: HashSet::new(),
+            out: String::with_capacity(source.len()),
+        };
+        self.expand(source, label, None, 0, &mut state)?;
+        Result::Ok(state.out)
+    }
+
+    /// Reads and processes a WGSL file. Includes are looked for next to
+    /// it first.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<PreprocessedShader, ShaderError> {
+        let path = path.as_ref();
+        let label = path.display().to_string();
+        let source = std::fs::read_to_string(path).map_err(|e| {
+            ShaderError::new(format!("Unable to read {}: {}", label, e), None, None)
+                .with_label(Some(&label))
+        })?;
+        let mut state = Expansion {
+            defines: self.defines.clone(),
+            included: HashSet::new(),
+            out: String::with_capacity(source.len()),
+        };
+        state.included.insert(label.clone());
+        self.expand(&source, &label, path.parent(), 0, &mut state)?;
+        Result::Ok(PreprocessedShader {
+            label,
+            source: state.out,
+        })
+    }
+
+    fn expand(
+        &self,
+        source: &str,
+        label: &str,
+        folder: Option<&Path>,
+        depth: u32,
+        state: &mut Expansion,
+    ) -> Result<(), ShaderError> {
+        // Each #if pushes whether its lines are kept, and whether #else
+        // has been seen
+        let mut conditions: Vec<(bool, bool)> = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: String| {
+                let location = naga::SourceLocation {
+                    line_number: index as u32 + 1,
+                    line_position: 1,
+                    offset: 0,
+                    length: 0,
+                };
+                ShaderError::new(message, Some(source), Some(location)).with_label(Some(label))
+            };
+            let active = conditions.iter().all(|c| c.0);
+            let trimmed = line.trim();
+            let directive = match trimmed.strip_prefix('#') {
+                Some(directive) => directive,
+                None => {
+                    if active {
+                        substitute(line, &state.defines, &mut state.out);
+                        state.out.push('\n');
+                    }
+                    continue;
+                }
+            };
+            let (keyword, rest) = directive
+                .split_once(char::is_whitespace)
+                .unwrap_or((directive, ""));
+            let rest = rest.trim();
+            let name = || {
+                rest.split_whitespace()
+                    .next()
+                    .ok_or_else(|| error(format!("#{} needs a name", keyword)))
+            };
+            match keyword {
+                "ifdef" | "ifndef" | "if" => {
+                    let name = name()?;
+                    let value = state.defines.get(name);
+                    let keep = match keyword {
+                        "ifdef" => value.is_some(),
+                        "ifndef" => value.is_none(),
+                        _ => value.is_some_and(|v| v != "0" && v != "false"),
+                    };
+                    conditions.push((keep, false));
+                }
+                "else" => match conditions.last_mut() {
+                    Some((keep, seen_else)) if !*seen_else => {
+                        *keep = !*keep;
+                        *seen_else = true;
+                    }
+                    Some(_) => return Err(error("#else after #else".to_string())),
+                    None => return Err(error("#else without #if".to_string())),
+                },
+                "endif" => {
+                    if conditions.pop().is_none() {
+                        return Err(error("#endif without #if".to_string()));
+                    }
+                }
+                _ if !active => {}
+                "define" => {
+                    let name = name()?;
+                    let value = rest[name.len()..].trim();
+                    state.defines.insert(name.to_string(), value.to_string());
+                }
+                "undef" => {
+                    state.defines.remove(name()?);
+                }
+                "include" => {
+                    let name = rest
+                        .strip_prefix('"')
+                        .and_then(|r| r.strip_suffix('"'))
+                        .ok_or_else(|| error("#include needs a \"quoted\" name".to_string()))?;
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(error(format!("#includes nest too deep at {}", name)));
+                    }
+                    self.include(name, folder, depth, state)
+                        .map_err(|e| match e {
+                            IncludeError::NotFound => {
+                                error(format!("Can't find {} to include", name))
+                            }
+                            IncludeError::Shader(e) => e,
+                        })?;
+                }
+                _ => return Err(error(format!("Unknown directive #{}", keyword))),
+            }
+        }
+        if !conditions.is_empty() {
+            let location = naga::SourceLocation {
+                line_number: source.lines().count() as u32,
+                line_position: 1,
+                offset: 0,
+                length: 0,
+            };
+            return Err(ShaderError::new(
+                "#if without #endif".to_string(),
+                Some(source),
+                Some(location),
+            )
+            .with_label(Some(label)));
+        }
+        Result::Ok(())
+    }
+
+    fn include(
+        &self,
+        name: &str,
+        folder: Option<&Path>,
+        depth: u32,
+        state: &mut Expansion,
+    ) -> Result<(), IncludeError> {
+        if let Some(source) = self.includes.get(name) {
+            if state.included.insert(name.to_string()) {
+                self.expand(source, name, None, depth + 1, state)?;
+            }
+            return Result::Ok(());
+        }
+        let path = folder
+            .into_iter()
+            .chain(self.include_dirs.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .ok_or(IncludeError::NotFound)?;
+        let label = path.display().to_string();
+        if !state.included.insert(label.clone()) {
+            return Result::Ok(());
+        }
+        let source = std::fs::read_to_string(&path).map_err(|e| {
+            ShaderError::new(format!("Unable to read {}: {}", label, e), None, None)
+                .with_label(Some(&label))
+        })?;
+        self.expand(&source, &label, path.parent(), depth + 1, state)?;
+        Result::Ok(())
+    }
+}
+
+/// Expands one of the framework's own shaders, which only include
+/// other framework shaders
+pub(crate) fn framework_shader(label: &str, source: &str) -> PreprocessedShader {
+    let source = ShaderPreprocessor::new()
+        .process(source, label)
+        .unwrap_or_else(|e| panic!("{}: {}", e.summary(), e));
+    PreprocessedShader {
+        label: label.to_string(),
+        source,
+    }
+}
+
+/// WGSL from [ShaderPreprocessor::load]
+#[derive(Debug, Clone)]
+pub struct PreprocessedShader {
+    /// The path it was loaded from
+    pub label: String,
+    pub source: String,
+}
+
+impl PreprocessedShader {
+    /// A module for [RenderPipelineBuilder]
+    pub fn module(&self) -> wgpu::ShaderModuleDescriptor<'_> {
+        wgpu::ShaderModuleDescriptor {
+            label: Some(&self.label),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(&self.source)),
+        }
+    }
+}
+
+struct Expansion {
+    defines: HashMap<String, String>,
+    /// Includes are only pasted once, so shared files can include what
+    /// they need without clashing
+    included: HashSet<String>,
+    out: String,
+}
+
+enum IncludeError {
+    NotFound,
+    Shader(ShaderError),
+}
+
+impl From<ShaderError> for IncludeError {
+    fn from(e: ShaderError) -> Self {
+        Self::Shader(e)
+    }
+}
+
+/// Copies `line` to `out`, swapping whole identifiers that are defined
+/// for their values
+fn substitute(line: &str, defines: &HashMap<String, String>, out: &mut String) {
+    if defines.is_empty() {
+        out.push_str(line);
+        return;
+    }
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let mut rest = line;
+    while let Some(start) = rest.find(is_ident) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c: char| !is_ident(c)).unwrap_or(rest.len());
+        let word = &rest[..end];
+        match defines.get(word) {
+            Some(value) => out.push_str(value),
+            None => out.push_str(word),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workgroups_cover_every_invocation() {
+        assert_eq!(workgroup_count([100, 1, 1], [64, 1, 1]), [2, 1, 1]);
+        assert_eq!(workgroup_count([64, 17, 0], [64, 8, 1]), [1, 3, 0]);
+    }
+
+    #[test]
+    fn preprocessor_includes_and_switches() {
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor
+            .add_include("common.wgsl", "const SCALE: f32 = FACTOR;\n")
+            .add_include("both.wgsl", "#include \"common.wgsl\"\n")
+            .define("FACTOR", "2.0");
+        let source = "#include \"common.wgsl\"\n#include \"both.wgsl\"\n#ifdef SOFT\nfn soft() {}\n#else\nfn hard() {}\n#endif\n#define SOFT 0\n#if SOFT\nfn unreachable() {}\n#endif\n#ifdef SOFT\nfn soft_later() {}\n#endif\n";
+        let out = preprocessor.process(source, "test").unwrap();
+        assert_eq!(
+            out,
+            "const SCALE: f32 = 2.0;\nfn hard() {}\nfn soft_later() {}\n"
+        );
+        crate::shader::validate_wgsl(&out).unwrap();
+
+        let error = preprocessor
+            .process("fn a() {}\n#include \"missing.wgsl\"\n", "test")
+            .unwrap_err();
+        assert_eq!(error.line, Some(2));
+        assert!(preprocessor.process("#ifdef A\n", "test").is_err());
+        assert!(preprocessor.process("#endif\n", "test").is_err());
+    }
+
+    #[test]
+    fn framework_shaders_can_be_included() {
+        let out = ShaderPreprocessor::new()
+            .process("#include \"framework/brdf.wgsl\"\n", "test")
+            .unwrap();
+        assert_eq!(out.trim_end(), crate::model::BRDF_WGSL.trim_end());
+    }
+}
@@ -0,0 +1,628 @@
+use anyhow::*;
+use cgmath::*;
+use wgpu::util::DeviceExt;
+
+use crate::culling::{BoundingSphere, Frustum};
+use crate::shader::catch_validation_errors;
+
+const BAKE_WGSL: &str = concat!(
+    include_str!("cube_face.wgsl"),
+    include_str!("planet_bake.wgsl")
+);
+const HEIGHT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// WGSL with `vs_planet`, the vertex shader for [Planet] patches with the
+/// planet at group 0 and the camera at group 1. Its `PlanetVertexOutput`
+/// has the displaced normal and the height from -1 to 1 for fragment
+/// shaders to colour by.
+pub const PLANET_WGSL: &str = include_str!("planet.wgsl");
+
+/// The faces of the cube a [Planet] is made from, in cube map order.
+/// Each face has its own quadtree of patches.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CubeFace {
+    PosX,
+    NegX,
+    PosY,
+    NegY,
+    PosZ,
+    NegZ,
+}
+
+impl CubeFace {
+    pub const ALL: [Self; 6] = [
+        Self::PosX,
+        Self::NegX,
+        Self::PosY,
+        Self::NegY,
+        Self::PosZ,
+        Self::NegZ,
+    ];
+
+    /// Where `st`, from -1 to 1 across the face, is on the cube. s cross t
+    /// points out of the cube, so triangles wound anticlockwise in st face
+    /// outwards.
+    pub fn cube_point(self, [s, t]: [f32; 2]) -> Vector3<f32> {
+        match self {
+            Self::PosX => Vector3::new(1.0, t, -s),
+            Self::NegX => Vector3::new(-1.0, t, s),
+            Self::PosY => Vector3::new(s, 1.0, -t),
+            Self::NegY => Vector3::new(s, -1.0, t),
+            Self::PosZ => Vector3::new(s, t, 1.0),
+            Self::NegZ => Vector3::new(-s, t, -1.0),
+        }
+    }
+}
+
+/// Puffs a point on the -1 to 1 cube out onto the unit sphere. This
+/// bunches points up near the corners less than normalizing does.
+pub fn cube_to_sphere(p: Vector3<f32>) -> Vector3<f32> {
+    let (x2, y2, z2) = (p.x * p.x, p.y * p.y, p.z * p.z);
+    Vector3::new(
+        p.x * (1.0 - y2 / 2.0 - z2 / 2.0 + y2 * z2 / 3.0).sqrt(),
+        p.y * (1.0 - z2 / 2.0 - x2 / 2.0 + z2 * x2 / 3.0).sqrt(),
+        p.z * (1.0 - x2 / 2.0 - y2 / 2.0 + x2 * y2 / 3.0).sqrt(),
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanetSettings {
+    pub radius: f32,
+    /// How far the heights go above and below the radius
+    pub height_scale: f32,
+    /// Noise features across the planet, roughly
+    pub frequency: f32,
+    pub octaves: u32,
+    /// Picks the noise, eg. from [crate::Rng::next_u32]
+    pub seed: u32,
+    /// Texels along each side of the baked height cube map
+    pub height_resolution: u32,
+    /// Quads along each side of a patch
+    pub patch_resolution: u32,
+    /// How many times a face can be split
+    pub max_level: u32,
+    /// Patches split when the camera's closer than this many times their
+    /// size
+    pub split_distance: f32,
+}
+
+impl Default for PlanetSettings {
+    fn default() -> Self {
+        Self {
+            radius: 100.0,
+            height_scale: 4.0,
+            frequency: 4.0,
+            octaves: 8,
+            seed: 0,
+            height_resolution: 1024,
+            patch_resolution: 32,
+            max_level: 10,
+            split_distance: 2.0,
+        }
+    }
+}
+
+/// A square of a cube face drawn with the shared patch grid. This is the
+/// instance data for `vs_planet`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PlanetPatch {
+    /// The corner with the lowest s and t, from -1 to 1
+    pub origin: [f32; 2],
+    pub size: f32,
+    pub face: u32,
+}
+
+impl PlanetPatch {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<PlanetPatch>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+            ],
+        }
+    }
+}
+
+/// Walks each face's quadtree, splitting patches the camera is close to
+/// and dropping the ones outside `frustum`. Heights aren't known on the
+/// CPU, so patches are bounded as if they went the full `height_scale`
+/// up and down.
+pub fn select_patches(
+    settings: &PlanetSettings,
+    center: Point3<f32>,
+    camera: Point3<f32>,
+    frustum: Option<&Frustum>,
+) -> Vec<PlanetPatch> {
+    let mut patches = Vec::new();
+    let mut stack = CubeFace::ALL
+        .iter()
+        .map(|&face| (face, [-1.0, -1.0], 2.0, 0))
+        .collect::<Vec<_>>();
+    while let Some((face, [s, t], size, level)) = stack.pop() {
+        let surface = |st: [f32; 2]| center + cube_to_sphere(face.cube_point(st)) * settings.radius;
+        let middle = surface([s + size / 2.0, t + size / 2.0]);
+        let extent = [[s, t], [s + size, t], [s, t + size], [s + size, t + size]]
+            .iter()
+            .map(|&corner| surface(corner).distance(middle))
+            .fold(0.0, f32::max);
+        if let Some(frustum) = frustum {
+            let bounds = BoundingSphere::new(middle, extent + settings.height_scale);
+            if !frustum.contains(&bounds) {
+                continue;
+            }
+        }
+        if level < settings.max_level && camera.distance(middle) < extent * settings.split_distance
+        {
+            let half = size / 2.0;
+            for [ds, dt] in [[0.0, 0.0], [half, 0.0], [0.0, half], [half, half]] {
+                stack.push((face, [s + ds, t + dt], half, level + 1));
+            }
+        } else {
+            patches.push(PlanetPatch {
+                origin: [s, t],
+                size,
+                face: face as u32,
+            });
+        }
+    }
+    patches
+}
+
+/// The grid every patch is drawn with, with a skirt around the edge.
+/// Vertices are `[s, t, skirt]` with s and t from 0 to 1.
+fn patch_grid(resolution: u32) -> (Vec<[f32; 3]>, Vec<u32>) {
+    let n = resolution.max(1);
+    let row = n + 1;
+    let mut vertices = Vec::new();
+    for j in 0..=n {
+        for i in 0..=n {
+            vertices.push([i as f32 / n as f32, j as f32 / n as f32, 0.0]);
+        }
+    }
+    let mut indices = Vec::new();
+    for j in 0..n {
+        for i in 0..n {
+            let a = j * row + i;
+            let (b, c, d) = (a + 1, a + row + 1, a + row);
+            indices.extend_from_slice(&[a, b, c, a, c, d]);
+        }
+    }
+    // Around the edge anticlockwise, so the inside is on the left
+    let mut border = Vec::new();
+    border.extend(0..n);
+    border.extend((0..n).map(|j| j * row + n));
+    border.extend((0..n).map(|i| n * row + n - i));
+    border.extend((0..n).map(|j| (n - j) * row));
+    let skirt = vertices.len() as u32;
+    for &index in &border {
+        let [s, t, _] = vertices[index as usize];
+        vertices.push([s, t, 1.0]);
+    }
+    let count = border.len() as u32;
+    for k in 0..count {
+        let (a, b) = (border[k as usize], border[((k + 1) % count) as usize]);
+        let (a_low, b_low) = (skirt + k, skirt + (k + 1) % count);
+        // Facing out of the patch
+        indices.extend_from_slice(&[a, b_low, b, a, a_low, b_low]);
+    }
+    (vertices, indices)
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PlanetUniforms {
+    center: [f32; 3],
+    radius: f32,
+    height_scale: f32,
+    _padding: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PlanetNoise {
+    frequency: f32,
+    octaves: u32,
+    seed: u32,
+    size: u32,
+}
+
+/// A planet made from a cube whose faces are split into patches by
+/// distance, puffed out into a sphere. The heights are fractal noise
+/// baked into a cube map by a compute shader when it's made, and the
+/// vertex shader in [PLANET_WGSL] displaces the patches by them, so the
+/// CPU only ever decides which patches to draw. Needs
+/// [Capabilities::compute_shaders](crate::Capabilities).
+///
+/// ```ignore
+/// let mut planet = Planet::new(&display.device, &display.queue, PlanetSettings::default())?;
+/// let layout = display.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+///     bind_group_layouts: &[planet.layout(), &camera_layout],
+///     // ...
+/// });
+/// let pipeline = RenderPipelineBuilder::new()
+///     .layout(&layout)
+///     .vertex_shader(wgpu::ShaderModuleDescriptor { label: Some("planet"), source: wgpu::ShaderSource::Wgsl(shader.into()) })
+///     .vertex_entry_point("vs_planet")
+///     .vertex_buffer_desc(Planet::vertex_desc())
+///     .vertex_buffer_desc(PlanetPatch::desc())
+///     .cull_mode(Some(wgpu::Face::Back))
+///     // ...
+///     .build(&display.device)?;
+/// // Every frame
+/// planet.update(&display.device, &display.queue, camera.position, Some(&frustum));
+/// pass.set_pipeline(&pipeline);
+/// pass.set_bind_group(1, &camera_bind_group, &[]);
+/// planet.draw(&mut pass);
+/// ```
+pub struct Planet {
+    pub settings: PlanetSettings,
+    pub center: Point3<f32>,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    uniforms: wgpu::Buffer,
+    grid_vertices: wgpu::Buffer,
+    grid_indices: wgpu::Buffer,
+    num_grid_indices: u32,
+    patches: wgpu::Buffer,
+    num_patches: u32,
+}
+
+impl Planet {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: PlanetSettings,
+    ) -> Result<Self> {
+        let size = settings.height_resolution.max(1);
+        let heights = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Planet::heights"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HEIGHT_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        bake_heights(device, queue, &settings, &heights)?;
+
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Planet::uniforms"),
+            size: std::mem::size_of::<PlanetUniforms>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let stages = wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT;
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Planet::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: stages,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: stages,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: stages,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let view = heights.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Planet::sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Planet::bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let (vertices, indices) = patch_grid(settings.patch_resolution);
+        let grid_vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Planet::grid_vertices"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let grid_indices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Planet::grid_indices"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let patches = create_patch_buffer(device, 64);
+
+        Ok(Self {
+            settings,
+            center: Point3::origin(),
+            layout,
+            bind_group,
+            uniforms,
+            grid_vertices,
+            grid_indices,
+            num_grid_indices: indices.len() as u32,
+            patches,
+            num_patches: 0,
+        })
+    }
+
+    /// Group 0 of the pipeline that draws the planet
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    /// The patch grid's vertex buffer, at location 0. [PlanetPatch::desc]
+    /// goes after it.
+    pub fn vertex_desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x3,
+            }],
+        }
+    }
+
+    /// Picks the patches to draw from where the camera is. Changes to
+    /// [Planet::center] and the radius and height scale in
+    /// [Planet::settings] are picked up here too.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: Point3<f32>,
+        frustum: Option<&Frustum>,
+    ) {
+        crate::cpu_scope!("Planet::update");
+        let patches = select_patches(&self.settings, self.center, camera, frustum);
+        let needed = (patches.len() * std::mem::size_of::<PlanetPatch>()) as u64;
+        if needed > self.patches.size() {
+            self.patches = create_patch_buffer(device, patches.len().next_power_of_two());
+        }
+        queue.write_buffer(&self.patches, 0, bytemuck::cast_slice(&patches));
+        self.num_patches = patches.len() as u32;
+        queue.write_buffer(
+            &self.uniforms,
+            0,
+            bytemuck::cast_slice(&[PlanetUniforms {
+                center: self.center.into(),
+                radius: self.settings.radius,
+                height_scale: self.settings.height_scale,
+                _padding: [0.0; 3],
+            }]),
+        );
+    }
+
+    /// How many patches the last update picked
+    pub fn num_patches(&self) -> u32 {
+        self.num_patches
+    }
+
+    /// Draws the patches from the last update. The pipeline and camera
+    /// need to be set already.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.grid_vertices.slice(..));
+        pass.set_vertex_buffer(1, self.patches.slice(..));
+        pass.set_index_buffer(self.grid_indices.slice(..), wgpu::IndexFormat::Uint32);
+        pass.draw_indexed(0..self.num_grid_indices, 0, 0..self.num_patches);
+    }
+}
+
+fn create_patch_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Planet::patches"),
+        size: (capacity.max(1) * std::mem::size_of::<PlanetPatch>()) as u64,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn bake_heights(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    settings: &PlanetSettings,
+    heights: &wgpu::Texture,
+) -> Result<()> {
+    let size = heights.width();
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Planet::bake_layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: HEIGHT_FORMAT,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                },
+                count: None,
+            },
+        ],
+    });
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Planet::bake_pipeline_layout"),
+        bind_group_layouts: &[&layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = catch_validation_errors(device, || {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Planet::bake"),
+            source: wgpu::ShaderSource::Wgsl(BAKE_WGSL.into()),
+        });
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Planet::bake"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "bake",
+            compilation_options: Default::default(),
+            cache: None,
+        })
+    })?;
+    let noise = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Planet::noise"),
+        contents: bytemuck::cast_slice(&[PlanetNoise {
+            frequency: settings.frequency,
+            octaves: settings.octaves,
+            seed: settings.seed,
+            size,
+        }]),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    let view = heights.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Planet::bake_bind_group"),
+        layout: &layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: noise.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+        ],
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Planet::bake"),
+    });
+    {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Planet::bake"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        let groups = size.div_ceil(8);
+        pass.dispatch_workgroups(groups, groups, 6);
+    }
+    queue.submit([encoder.finish()]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patches_split_near_the_camera() {
+        let settings = PlanetSettings {
+            max_level: 6,
+            ..Default::default()
+        };
+        let far = select_patches(
+            &settings,
+            Point3::origin(),
+            Point3::new(0.0, 0.0, 1e5),
+            None,
+        );
+        assert_eq!(far.len(), 6);
+
+        let camera = Point3::new(0.0, settings.radius + 1.0, 0.0);
+        let near = select_patches(&settings, Point3::origin(), camera, None);
+        // The faces are still covered exactly once
+        for face in CubeFace::ALL {
+            let area: f32 = near
+                .iter()
+                .filter(|p| p.face == face as u32)
+                .map(|p| p.size * p.size)
+                .sum();
+            assert!((area - 4.0).abs() < 1e-4, "{:?} {}", face, area);
+        }
+        // The smallest patches are on the face under the camera
+        let smallest = near
+            .iter()
+            .min_by(|a, b| a.size.total_cmp(&b.size))
+            .unwrap();
+        assert_eq!(smallest.face, CubeFace::PosY as u32);
+        assert_eq!(smallest.size, 2.0 / 64.0);
+
+        // Every face's normal is s cross t
+        for face in CubeFace::ALL {
+            let s = face.cube_point([1.0, 0.0]) - face.cube_point([0.0, 0.0]);
+            let t = face.cube_point([0.0, 1.0]) - face.cube_point([0.0, 0.0]);
+            assert_eq!(s.cross(t), face.cube_point([0.0, 0.0]));
+        }
+        assert!((cube_to_sphere(Vector3::new(1.0, 1.0, -1.0)).magnitude() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn shaders_are_valid() {
+        crate::shader::validate_wgsl(BAKE_WGSL).unwrap();
+        crate::shader::validate_wgsl(PLANET_WGSL).unwrap();
+        let (vertices, indices) = patch_grid(4);
+        assert_eq!(vertices.len(), 25 + 16);
+        assert_eq!(indices.len(), 16 * 6 + 16 * 6);
+    }
+}
@@ -0,0 +1,104 @@
+// A vertex shader for Planet patches, with the planet at group 0 and the
+// camera at group 1. Each patch is an instance of the same grid, placed
+// on a face of a cube that's puffed out into a sphere and displaced by
+// the baked heights.
+
+struct Camera {
+    view_position: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+struct PlanetUniforms {
+    center: vec3<f32>,
+    radius: f32,
+    height_scale: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> planet: PlanetUniforms;
+@group(0) @binding(1)
+var planet_heights: texture_cube<f32>;
+@group(0) @binding(2)
+var planet_sampler: sampler;
+
+struct PatchVertexInput {
+    // xy goes 0 to 1 across the patch, z is 1 on the skirt
+    @location(0) grid: vec3<f32>,
+}
+
+struct PatchInstanceInput {
+    @location(1) origin: vec2<f32>,
+    @location(2) size: f32,
+    @location(3) face: u32,
+}
+
+struct PlanetVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    // Straight up from the center, for anything that depends on latitude
+    @location(2) up: vec3<f32>,
+    // The baked height, -1 to 1
+    @location(3) height: f32,
+}
+
+// Where a point on a face of the -1 to 1 cube is. Same faces as
+// CubeFace in planet.rs.
+fn cube_point(face: u32, st: vec2<f32>) -> vec3<f32> {
+    switch face {
+        case 0u: { return vec3<f32>(1.0, st.y, -st.x); }
+        case 1u: { return vec3<f32>(-1.0, st.y, st.x); }
+        case 2u: { return vec3<f32>(st.x, 1.0, -st.y); }
+        case 3u: { return vec3<f32>(st.x, -1.0, st.y); }
+        case 4u: { return vec3<f32>(st.x, st.y, 1.0); }
+        default: { return vec3<f32>(-st.x, st.y, -1.0); }
+    }
+}
+
+// Spreads points out more evenly than normalizing
+fn cube_to_sphere(p: vec3<f32>) -> vec3<f32> {
+    let p2 = p * p;
+    return p * sqrt(vec3<f32>(
+        1.0 - p2.y / 2.0 - p2.z / 2.0 + p2.y * p2.z / 3.0,
+        1.0 - p2.z / 2.0 - p2.x / 2.0 + p2.z * p2.x / 3.0,
+        1.0 - p2.x / 2.0 - p2.y / 2.0 + p2.x * p2.y / 3.0,
+    ));
+}
+
+fn planet_height(direction: vec3<f32>) -> f32 {
+    return textureSampleLevel(planet_heights, planet_sampler, direction, 0.0).r;
+}
+
+fn planet_surface(face: u32, st: vec2<f32>) -> vec3<f32> {
+    let direction = cube_to_sphere(cube_point(face, st));
+    return planet.center + direction * (planet.radius + planet_height(direction) * planet.height_scale);
+}
+
+@vertex
+fn vs_planet(vertex: PatchVertexInput, instance: PatchInstanceInput) -> PlanetVertexOutput {
+    let st = instance.origin + vertex.grid.xy * instance.size;
+    let up = cube_to_sphere(cube_point(instance.face, st));
+    let height = planet_height(up);
+    var position = planet.center + up * (planet.radius + height * planet.height_scale);
+
+    // The slope from the surface a texel either side
+    let delta = 2.0 / f32(textureDimensions(planet_heights).x);
+    let ds = planet_surface(instance.face, st + vec2<f32>(delta, 0.0))
+        - planet_surface(instance.face, st - vec2<f32>(delta, 0.0));
+    let dt = planet_surface(instance.face, st + vec2<f32>(0.0, delta))
+        - planet_surface(instance.face, st - vec2<f32>(0.0, delta));
+
+    // Skirts hang down to hide cracks against coarser patches
+    position = position - up * vertex.grid.z * instance.size * planet.radius * 0.05;
+
+    var out: PlanetVertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.world_position = position;
+    out.normal = normalize(cross(ds, dt));
+    out.up = up;
+    out.height = height;
+    return out;
+}
@@ -0,0 +1,62 @@
+// Bakes fractal noise heights into a cube map for Planet, one face per
+// layer. Goes after cube_face.wgsl for face_direction.
+
+struct PlanetNoise {
+    frequency: f32,
+    octaves: u32,
+    seed: u32,
+    size: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> noise: PlanetNoise;
+@group(0) @binding(1)
+var heights: texture_storage_2d_array<rgba16float, write>;
+
+// 0 to 1 for each lattice point
+fn lattice(cell: vec3<i32>) -> f32 {
+    let c = bitcast<vec3<u32>>(cell);
+    var h = (c.x * 73856093u) ^ (c.y * 19349663u) ^ (c.z * 83492791u) ^ noise.seed;
+    h = h * 747796405u + 2891336453u;
+    h = ((h >> ((h >> 28u) + 4u)) ^ h) * 277803737u;
+    h = (h >> 22u) ^ h;
+    return f32(h) / 4294967295.0;
+}
+
+// Smoothly interpolated lattice values, -1 to 1
+fn value_noise(p: vec3<f32>) -> f32 {
+    let i = vec3<i32>(floor(p));
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    let x00 = mix(lattice(i), lattice(i + vec3<i32>(1, 0, 0)), u.x);
+    let x10 = mix(lattice(i + vec3<i32>(0, 1, 0)), lattice(i + vec3<i32>(1, 1, 0)), u.x);
+    let x01 = mix(lattice(i + vec3<i32>(0, 0, 1)), lattice(i + vec3<i32>(1, 0, 1)), u.x);
+    let x11 = mix(lattice(i + vec3<i32>(0, 1, 1)), lattice(i + vec3<i32>(1, 1, 1)), u.x);
+    return mix(mix(x00, x10, u.y), mix(x01, x11, u.y), u.z) * 2.0 - 1.0;
+}
+
+// Octaves of noise, each twice the frequency and half the strength of the
+// last, scaled back to -1 to 1
+fn fbm(p: vec3<f32>) -> f32 {
+    var sum = 0.0;
+    var amplitude = 1.0;
+    var total = 0.0;
+    var q = p;
+    for (var i = 0u; i < noise.octaves; i = i + 1u) {
+        sum = sum + value_noise(q) * amplitude;
+        total = total + amplitude;
+        amplitude = amplitude * 0.5;
+        q = q * 2.0 + vec3<f32>(17.0, 31.0, 47.0);
+    }
+    return sum / max(total, 1e-6);
+}
+
+@compute @workgroup_size(8, 8)
+fn bake(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= noise.size || id.y >= noise.size) {
+        return;
+    }
+    let uv = (vec2<f32>(id.xy) + 0.5) / f32(noise.size);
+    let height = fbm(face_direction(id.z, uv) * noise.frequency);
+    textureStore(heights, id.xy, id.z, vec4<f32>(height, 0.0, 0.0, 1.0));
+}
@@ -0,0 +1,356 @@
+use std::path::Path;
+
+use anyhow::*;
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point3, Vector3, Vector4};
+use serde::{Deserialize, Serialize};
+
+use crate::culling::{BoundingSphere, Frustum};
+
+/// A box of space, usually one room of a building
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Room {
+    pub name: String,
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Room {
+    pub fn contains(&self, point: Point3<f32>) -> bool {
+        (0..3).all(|i| point[i] >= self.min[i] && point[i] <= self.max[i])
+    }
+
+    /// Whether any part of the sphere is inside the room
+    pub fn overlaps(&self, sphere: &BoundingSphere) -> bool {
+        let distance2: f32 = (0..3)
+            .map(|i| {
+                let c = sphere.center[i];
+                let d = (self.min[i] - c).max(c - self.max[i]).max(0.0);
+                d * d
+            })
+            .sum();
+        distance2 <= sphere.radius * sphere.radius
+    }
+}
+
+/// An opening between two rooms, like a doorway or a window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Portal {
+    /// The names of the rooms on either side
+    pub rooms: [String; 2],
+    /// The opening's outline in order around its edge. It should be
+    /// convex and flat.
+    pub corners: Vec<[f32; 3]>,
+}
+
+#[derive(Deserialize)]
+struct RoomsFile {
+    rooms: Vec<Room>,
+    #[serde(default)]
+    portals: Vec<Portal>,
+}
+
+/// Culls whole rooms of an indoor scene that can't be seen through the
+/// portals leading to them. Starting from the room with the camera in it,
+/// each portal still in view narrows the frustum to its outline, and
+/// whatever rooms are reached that way are visible.
+///
+/// Rooms and portals are placed by hand in a JSON file alongside the
+/// scene:
+///
+/// ```json
+/// {
+///     "rooms": [
+///         { "name": "hall", "min": [-5, 0, -5], "max": [5, 3, 5] },
+///         { "name": "kitchen", "min": [5, 0, -5], "max": [12, 3, 5] }
+///     ],
+///     "portals": [
+///         {
+///             "rooms": ["hall", "kitchen"],
+///             "corners": [[5, 0, -1], [5, 0, 1], [5, 2, 1], [5, 2, -1]]
+///         }
+///     ]
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Rooms {
+    rooms: Vec<Room>,
+    portals: Vec<Portal>,
+    /// Each room's portals and the room on their far side
+    links: Vec<Vec<(usize, usize)>>,
+}
+
+impl Rooms {
+    /// Fails if a portal names a room that doesn't exist
+    pub fn new(rooms: Vec<Room>, portals: Vec<Portal>) -> Result<Self> {
+        let index = |name: &str| {
+            rooms
+                .iter()
+                .position(|r| r.name == name)
+                .with_context(|| format!("There's no room called {:?}", name))
+        };
+        let mut links = vec![Vec::new(); rooms.len()];
+        for (i, portal) in portals.iter().enumerate() {
+            if portal.corners.len() < 3 {
+                bail!("Portal {} needs at least 3 corners", i);
+            }
+            let a = index(&portal.rooms[0])?;
+            let b = index(&portal.rooms[1])?;
+            links[a].push((i, b));
+            links[b].push((i, a));
+        }
+        Ok(Self {
+            rooms,
+            portals,
+            links,
+        })
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        let file: RoomsFile = serde_json::from_str(json)?;
+        Self::new(file.rooms, file.portals)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to open {}", path.display()))?;
+        Self::from_json(&json)
+            .with_context(|| format!("{} isn't a valid rooms file", path.display()))
+    }
+
+    pub fn rooms(&self) -> &[Room] {
+        &self.rooms
+    }
+
+    pub fn portals(&self) -> &[Portal] {
+        &self.portals
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.rooms.iter().position(|r| r.name == name)
+    }
+
+    /// The first room with `point` inside it
+    pub fn room_at(&self, point: Point3<f32>) -> Option<usize> {
+        self.rooms.iter().position(|r| r.contains(point))
+    }
+
+    /// Whether each room can be seen from `eye`. If the camera isn't in
+    /// any room every room counts as visible, since there's nothing to
+    /// say what it can see.
+    pub fn visible_rooms(&self, eye: Point3<f32>, view_proj: Matrix4<f32>) -> Vec<bool> {
+        let start = match self.room_at(eye) {
+            Some(room) => room,
+            None => return vec![true; self.rooms.len()],
+        };
+        let mut visible = vec![false; self.rooms.len()];
+        visible[start] = true;
+        let planes = Frustum::from_matrix(view_proj).planes.to_vec();
+        let mut path = Vec::new();
+        self.flood(start, eye, &planes, &mut path, &mut visible);
+        visible
+    }
+
+    fn flood(
+        &self,
+        room: usize,
+        eye: Point3<f32>,
+        planes: &[Vector4<f32>],
+        path: &mut Vec<usize>,
+        visible: &mut [bool],
+    ) {
+        for &(portal, next) in &self.links[room] {
+            // A portal can't be looked through twice down the same path,
+            // which stops rooms seeing themselves through a loop forever
+            if path.contains(&portal) {
+                continue;
+            }
+            let corners: Vec<_> = self.portals[portal]
+                .corners
+                .iter()
+                .map(|&c| Vector3::from(c))
+                .collect();
+
+            // Standing in the doorway the portal is too close to narrow
+            // the view by, so the next room gets the same frustum
+            let narrowed = if standing_in(eye, &corners) {
+                planes.to_vec()
+            } else {
+                let clipped = planes
+                    .iter()
+                    .fold(corners, |polygon, plane| clip(&polygon, *plane));
+                if clipped.len() < 3 {
+                    continue;
+                }
+                narrow(eye, &clipped, planes)
+            };
+
+            visible[next] = true;
+            path.push(portal);
+            self.flood(next, eye, &narrowed, path, visible);
+            path.pop();
+        }
+    }
+
+    /// Whether each sphere is in a visible room. Spheres outside every
+    /// room are always visible. This only looks at rooms, so it's meant to
+    /// go along with frustum culling.
+    pub fn cull(&self, visible_rooms: &[bool], spheres: &[BoundingSphere]) -> Vec<bool> {
+        spheres
+            .iter()
+            .map(|sphere| {
+                let mut outside = true;
+                for (room, &visible) in self.rooms.iter().zip(visible_rooms) {
+                    if room.overlaps(sphere) {
+                        if visible {
+                            return true;
+                        }
+                        outside = false;
+                    }
+                }
+                outside
+            })
+            .collect()
+    }
+}
+
+/// Whether `eye` is right up against the portal's plane inside its outline
+fn standing_in(eye: Point3<f32>, corners: &[Vector3<f32>]) -> bool {
+    const DOORWAY: f32 = 0.1;
+    let eye = eye.to_vec();
+    let normal = (corners[1] - corners[0])
+        .cross(corners[2] - corners[0])
+        .normalize();
+    if normal.dot(eye - corners[0]).abs() > DOORWAY {
+        return false;
+    }
+    let n = corners.len();
+    let sides: Vec<f32> = (0..n)
+        .map(|i| {
+            let edge = corners[(i + 1) % n] - corners[i];
+            edge.cross(eye - corners[i]).dot(normal)
+        })
+        .collect();
+    sides.iter().all(|&s| s >= 0.0) || sides.iter().all(|&s| s <= 0.0)
+}
+
+/// Cuts away the part of a convex polygon behind `plane`
+fn clip(polygon: &[Vector3<f32>], plane: Vector4<f32>) -> Vec<Vector3<f32>> {
+    let distance = |p: Vector3<f32>| plane.truncate().dot(p) + plane.w;
+    let mut out = Vec::with_capacity(polygon.len() + 1);
+    for (i, &a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        let (da, db) = (distance(a), distance(b));
+        if da >= 0.0 {
+            out.push(a);
+        }
+        if (da >= 0.0) != (db >= 0.0) {
+            out.push(a + (b - a) * (da / (da - db)));
+        }
+    }
+    out
+}
+
+/// The frustum through a portal: a plane from the eye through each edge
+/// of its outline, along with the old planes for the near and far limits
+fn narrow(
+    eye: Point3<f32>,
+    polygon: &[Vector3<f32>],
+    planes: &[Vector4<f32>],
+) -> Vec<Vector4<f32>> {
+    let eye = eye.to_vec();
+    let center = polygon.iter().fold(Vector3::zero(), |a, &b| a + b) / polygon.len() as f32;
+    let mut narrowed = planes.to_vec();
+    for (i, &a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        let normal = (a - eye).cross(b - eye);
+        let length = normal.magnitude();
+        if length < 1e-6 {
+            continue;
+        }
+        let mut normal = normal / length;
+        if normal.dot(center - eye) < 0.0 {
+            normal = -normal;
+        }
+        narrowed.push(normal.extend(-normal.dot(eye)));
+    }
+    narrowed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{perspective, Deg};
+
+    #[test]
+    fn rooms_are_only_visible_through_portals() {
+        // Three rooms in a row along x, with a doorway into the second and
+        // a doorway off to the side into the third
+        let rooms = Rooms::from_json(
+            r#"{
+                "rooms": [
+                    { "name": "a", "min": [0, 0, -5], "max": [10, 3, 5] },
+                    { "name": "b", "min": [10, 0, -5], "max": [20, 3, 5] },
+                    { "name": "c", "min": [20, 0, -5], "max": [30, 3, 5] }
+                ],
+                "portals": [
+                    { "rooms": ["a", "b"], "corners": [[10, 0, -1], [10, 0, 1], [10, 2, 1], [10, 2, -1]] },
+                    { "rooms": ["b", "c"], "corners": [[20, 0, 3], [20, 0, 4], [20, 2, 4], [20, 2, 3]] }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        // Remaps depth from -1 to 1 into 0 to 1
+        #[rustfmt::skip]
+        let to_wgpu = Matrix4::new(
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 0.5, 0.0,
+            0.0, 0.0, 0.5, 1.0,
+        );
+        let proj = to_wgpu * perspective(Deg(60.0), 1.0, 0.1, 100.0);
+        let eye = Point3::new(2.0, 1.0, 0.0);
+        let looking =
+            |direction: Vector3<f32>| proj * Matrix4::look_to_rh(eye, direction, Vector3::unit_y());
+
+        // Looking through the first doorway, the second one is too far to
+        // the side to see
+        let view_proj = looking(Vector3::unit_x());
+        assert_eq!(rooms.visible_rooms(eye, view_proj), [true, true, false]);
+        // Looking away from it
+        assert_eq!(
+            rooms.visible_rooms(eye, looking(-Vector3::unit_x())),
+            [true, false, false]
+        );
+        // Outside every room
+        assert_eq!(
+            rooms.visible_rooms(Point3::new(-5.0, 1.0, 0.0), view_proj),
+            [true, true, true]
+        );
+
+        // Standing in the first doorway looking at the second
+        let doorway = Point3::new(10.0, 1.0, 0.0);
+        let view_proj =
+            proj * Matrix4::look_at_rh(doorway, Point3::new(20.0, 1.0, 3.5), Vector3::unit_y());
+        assert_eq!(rooms.visible_rooms(doorway, view_proj), [true, true, true]);
+
+        let spheres = [
+            BoundingSphere::new(Point3::new(5.0, 1.0, 0.0), 1.0),
+            BoundingSphere::new(Point3::new(25.0, 1.0, 0.0), 1.0),
+            // Poking through the wall into a visible room
+            BoundingSphere::new(Point3::new(21.0, 1.0, 0.0), 2.0),
+            BoundingSphere::new(Point3::new(50.0, 1.0, 0.0), 1.0),
+        ];
+        assert_eq!(
+            rooms.cull(&[true, true, false], &spheres),
+            [true, false, true, true]
+        );
+
+        assert!(Rooms::from_json(
+            r#"{ "rooms": [], "portals": [{ "rooms": ["a", "b"], "corners": [[0, 0, 0], [1, 0, 0], [1, 1, 0]] }] }"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,424 @@
+use std::any::Any;
+
+use anyhow::*;
+
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::texture::Texture;
+use crate::Display;
+
+/// The vertex shader full screen passes share, as
+/// `#include "framework/fullscreen.wgsl"`. Its `vs_main` draws one
+/// triangle over the whole target when drawn with 3 vertices.
+pub const FULLSCREEN_WGSL: &str = include_str!("fullscreen.wgsl");
+
+/// What a [PostEffect] reads and writes during
+/// [PostProcessChain::process]
+pub struct PostContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    /// The previous effect's result, or the scene for the first effect
+    pub input: &'a wgpu::TextureView,
+    /// Where the effect writes. Effects have to cover every pixel.
+    pub output: &'a wgpu::TextureView,
+    /// The scene's depth buffer, if the demo passed one in
+    pub depth: Option<&'a wgpu::TextureView>,
+    /// Of `output`, and usually `input`. The scene could be a different
+    /// format.
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    blit: &'a Blit,
+}
+
+impl PostContext<'_> {
+    /// Copies `input` to `output` as is, for effects that draw over the
+    /// scene or have nothing to do this frame
+    pub fn copy(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.copy_to(encoder, self.input, self.output);
+    }
+
+    /// Copies between two views of the same size in the chain's format
+    pub fn copy_to(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        from: &wgpu::TextureView,
+        to: &wgpu::TextureView,
+    ) {
+        self.blit
+            .pass(self.device, encoder, &self.blit.copy_pipeline, from, to);
+    }
+}
+
+/// One step of a [PostProcessChain]. Effects keep their own pipelines
+/// and any buffers they need between frames, like a history, but not the
+/// targets they read and write.
+pub trait PostEffect: Any {
+    /// Disabled effects are skipped without costing a pass
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    /// Called when the chain changes size, for effects with screen sized
+    /// buffers of their own
+    fn resize(&mut self, _device: &wgpu::Device, _width: u32, _height: u32) {}
+
+    /// Reads `ctx.input` and writes all of `ctx.output`
+    fn process(&mut self, encoder: &mut wgpu::CommandEncoder, ctx: &PostContext);
+}
+
+/// Full screen copies, into the chain's format and into the output's
+struct Blit {
+    layout: wgpu::BindGroupLayout,
+    copy_pipeline: wgpu::RenderPipeline,
+    output_pipeline: wgpu::RenderPipeline,
+}
+
+impl Blit {
+    fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        output_format: wgpu::TextureFormat,
+    ) -> Result<Self> {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("PostProcessChain::layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PostProcessChain::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = framework_shader("post.wgsl", include_str!("post.wgsl"));
+        let pipeline = |entry_point, format| {
+            RenderPipelineBuilder::new()
+                .layout(&pipeline_layout)
+                .vertex_shader(shader.module())
+                .fragment_shader(shader.module())
+                .vertex_entry_point("vs_main")
+                .fragment_entry_point(entry_point)
+                .color_solid(format)
+                .build(device)
+        };
+        let copy_pipeline = pipeline("fs_copy", format)?;
+        let output_entry_point = if needs_encoding(output_format) {
+            "fs_encode"
+        } else {
+            "fs_copy"
+        };
+        let output_pipeline = pipeline(output_entry_point, output_format)?;
+        Ok(Self {
+            layout,
+            copy_pipeline,
+            output_pipeline,
+        })
+    }
+
+    fn pass(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        from: &wgpu::TextureView,
+        to: &wgpu::TextureView,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("PostProcessChain::bind_group"),
+            layout: &self.layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(from),
+            }],
+        });
+        fullscreen_pass(
+            encoder,
+            "PostProcessChain::copy",
+            to,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            pipeline,
+            &bind_group,
+        );
+    }
+}
+
+/// Draws [FULLSCREEN_WGSL]'s triangle over all of `view` with `pipeline`
+pub(crate) fn fullscreen_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    view: &wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+) {
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.draw(0..3, 0..1);
+}
+
+/// Formats like `Bgra8Unorm` that have an sRGB version but aren't it,
+/// which linear color has to be encoded for by hand
+pub(crate) fn needs_encoding(format: wgpu::TextureFormat) -> bool {
+    format.add_srgb_suffix() != format
+}
+
+/// Runs a list of [PostEffect]s over the scene in order, passing each
+/// one's result to the next through a pair of targets the chain owns, and
+/// copies the last result to the output. Resizing the display resizes
+/// the targets and every effect along with them.
+///
+/// ```ignore
+/// let mut post = PostProcessChain::new(display, HdrPipeline::FORMAT, display.config.format)?;
+/// let bloom = Bloom::new(&display.device, &post, Default::default())?;
+/// let tonemap = Tonemap::new(&display.device, &post)?;
+/// let fxaa = AntiAliasing::new(&display.device, &post, AntiAliasingMode::Fxaa)?;
+/// post.add(bloom).add(tonemap).add(fxaa);
+///
+/// // In Demo::render, after drawing the scene into hdr
+/// post.process(display, &mut encoder, hdr.view(), Some(&depth.view), &frame_view);
+///
+/// // Effects can be changed after they're added
+/// post.get_mut::<Bloom>().unwrap().set_settings(&display.queue, settings);
+/// ```
+pub struct PostProcessChain {
+    format: wgpu::TextureFormat,
+    output_format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    targets: [Texture<'static>; 2],
+    effects: Vec<Box<dyn PostEffect>>,
+    blit: Blit,
+}
+
+impl PostProcessChain {
+    /// Sized to the surface. Effects work in `format`, and the result
+    /// goes to an `output_format` view, usually the surface. The chain
+    /// holds linear color, which is encoded on the way out if the output
+    /// format doesn't do that itself.
+    pub fn new(
+        display: &Display,
+        format: wgpu::TextureFormat,
+        output_format: wgpu::TextureFormat,
+    ) -> Result<Self> {
+        let device = &display.device;
+        let width = display.config.width.max(1);
+        let height = display.config.height.max(1);
+        Ok(Self {
+            format,
+            output_format,
+            width,
+            height,
+            targets: create_targets(device, format, width, height),
+            effects: Vec::new(),
+            blit: Blit::new(device, format, output_format)?,
+        })
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    pub fn output_format(&self) -> wgpu::TextureFormat {
+        self.output_format
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Adds an effect to the end of the chain
+    pub fn add<E: PostEffect>(&mut self, effect: E) -> &mut Self {
+        self.effects.push(Box::new(effect));
+        self
+    }
+
+    /// Adds an effect at `index`, moving the ones after it along
+    pub fn insert<E: PostEffect>(&mut self, index: usize, effect: E) -> &mut Self {
+        self.effects.insert(index, Box::new(effect));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    /// The first effect of type `E`
+    pub fn get<E: PostEffect>(&self) -> Option<&E> {
+        self.effects
+            .iter()
+            .find_map(|e| (e.as_ref() as &dyn Any).downcast_ref())
+    }
+
+    pub fn get_mut<E: PostEffect>(&mut self) -> Option<&mut E> {
+        self.effects
+            .iter_mut()
+            .find_map(|e| (e.as_mut() as &mut dyn Any).downcast_mut())
+    }
+
+    /// Takes the first effect of type `E` out of the chain
+    pub fn remove<E: PostEffect>(&mut self) -> Option<E> {
+        let index = self
+            .effects
+            .iter()
+            .position(|e| (e.as_ref() as &dyn Any).is::<E>())?;
+        let effect: Box<dyn Any> = self.effects.remove(index);
+        effect.downcast().ok().map(|e| *e)
+    }
+
+    /// Remakes the targets and resizes every effect.
+    /// [PostProcessChain::process] calls this itself when the display
+    /// changes size.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let (width, height) = (width.max(1), height.max(1));
+        if (width, height) == (self.width, self.height) {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.targets = create_targets(device, self.format, width, height);
+        for effect in &mut self.effects {
+            effect.resize(device, width, height);
+        }
+    }
+
+    /// Runs the enabled effects over `scene` and writes the result to
+    /// `output`. `depth` is the scene's depth buffer, which effects like
+    /// [crate::DepthOfField] need.
+    pub fn process(
+        &mut self,
+        display: &Display,
+        encoder: &mut wgpu::CommandEncoder,
+        scene: &wgpu::TextureView,
+        depth: Option<&wgpu::TextureView>,
+        output: &wgpu::TextureView,
+    ) {
+        crate::cpu_scope!("PostProcessChain::process");
+        let device = &display.device;
+        self.resize(device, display.config.width, display.config.height);
+
+        // The last effect can skip the copy if it could write the output
+        // itself
+        let direct = self.output_format == self.format && !needs_encoding(self.format);
+        let enabled: Vec<_> = (0..self.effects.len())
+            .filter(|&i| self.effects[i].enabled())
+            .collect();
+        let mut input = scene;
+        for (n, &i) in enabled.iter().enumerate() {
+            let last = n + 1 == enabled.len();
+            let target = if last && direct {
+                output
+            } else {
+                &self.targets[n % 2].view
+            };
+            let ctx = PostContext {
+                device,
+                queue: &display.queue,
+                input,
+                output: target,
+                depth,
+                format: self.format,
+                width: self.width,
+                height: self.height,
+                blit: &self.blit,
+            };
+            self.effects[i].process(encoder, &ctx);
+            if last && direct {
+                return;
+            }
+            input = target;
+        }
+        self.blit
+            .pass(device, encoder, &self.blit.output_pipeline, input, output);
+    }
+}
+
+fn create_targets(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+) -> [Texture<'static>; 2] {
+    [0, 1].map(|_| {
+        Texture::from_descriptor(
+            device,
+            wgpu::TextureDescriptor {
+                label: Some("PostProcessChain::target"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_linear_unorm_outputs_need_encoding() {
+        assert!(needs_encoding(wgpu::TextureFormat::Bgra8Unorm));
+        assert!(!needs_encoding(wgpu::TextureFormat::Bgra8UnormSrgb));
+        assert!(!needs_encoding(wgpu::TextureFormat::Rgba16Float));
+    }
+
+    #[test]
+    fn post_wgsl_validates() {
+        crate::shader::validate_wgsl(
+            &framework_shader("post.wgsl", include_str!("post.wgsl")).source,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn fullscreen_shaders_validate() {
+        let shaders = [
+            ("compute_canvas.wgsl", include_str!("compute_canvas.wgsl")),
+            ("debug_inset.wgsl", include_str!("debug_inset.wgsl")),
+            ("gbuffer_debug.wgsl", include_str!("gbuffer_debug.wgsl")),
+            ("half_res.wgsl", include_str!("half_res.wgsl")),
+            ("interlaced.wgsl", include_str!("interlaced.wgsl")),
+        ];
+        for (label, source) in shaders {
+            crate::shader::validate_wgsl(&framework_shader(label, source).source).unwrap();
+        }
+    }
+}
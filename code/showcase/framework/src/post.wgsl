@@ -0,0 +1,24 @@
+// Copies between a PostProcessChain's targets. fs_encode also sRGB encodes
+// for outputs that don't do it themselves.
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+#include "framework/fullscreen.wgsl"
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_copy(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureLoad(source, vec2<i32>(in.clip_position.xy), 0);
+}
+
+@fragment
+fn fs_encode(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureLoad(source, vec2<i32>(in.clip_position.xy), 0);
+    return vec4<f32>(linear_to_srgb(clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0))), color.a);
+}
@@ -1 +1,3 @@
 pub use crate::model::{DrawLight, DrawModel};
+pub use crate::shadow::DrawShadow;
+pub use crate::viewport::RenderPassExt;
@@ -0,0 +1,561 @@
+//! Pulls information out of WGSL shaders using naga so we don't have to
+//! keep it in sync by hand on the Rust side.
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UniformScalar {
+    Float,
+    Sint,
+    Uint,
+}
+
+/// A scalar or vector member of a uniform struct.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UniformField {
+    pub name: String,
+    pub scalar: UniformScalar,
+    /// 1 for scalars, 2-4 for vectors
+    pub components: u32,
+    /// Byte offset into the struct
+    pub offset: u32,
+}
+
+/// The layout of a struct bound as `var<uniform>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UniformLayout {
+    /// Name of the global variable
+    pub name: String,
+    /// Size of the struct in bytes including padding
+    pub size: u32,
+    /// Members we know how to edit. Matrices, arrays and nested structs
+    /// are skipped, but still count towards `size`.
+    pub fields: Vec<UniformField>,
+}
+
+/// Finds the uniform bound at `@group(group) @binding(binding)`. Returns
+/// `None` if there isn't one, or it isn't a struct.
+pub fn reflect_uniform(module: &naga::Module, group: u32, binding: u32) -> Option<UniformLayout> {
+    let (_, var) = module.global_variables.iter().find(|(_, var)| {
+        var.space == naga::AddressSpace::Uniform
+            && var.binding == Some(naga::ResourceBinding { group, binding })
+    })?;
+
+    let (members, size) = match &module.types[var.ty].inner {
+        naga::TypeInner::Struct { members, span } => (members, *span),
+        _ => return None,
+    };
+
+    let fields = members
+        .iter()
+        .filter_map(|member| {
+            let (scalar, components) = match module.types[member.ty].inner {
+                naga::TypeInner::Scalar(scalar) => (scalar, 1),
+                naga::TypeInner::Vector { size, scalar } => (scalar, size as u32),
+                _ => return None,
+            };
+            let scalar = match (scalar.kind, scalar.width) {
+                (naga::ScalarKind::Float, 4) => UniformScalar::Float,
+                (naga::ScalarKind::Sint, 4) => UniformScalar::Sint,
+                (naga::ScalarKind::Uint, 4) => UniformScalar::Uint,
+                _ => return None,
+            };
+            Some(UniformField {
+                name: member.name.clone().unwrap_or_default(),
+                scalar,
+                components,
+                offset: member.offset,
+            })
+        })
+        .collect();
+
+    Some(UniformLayout {
+        name: var.name.clone().unwrap_or_default(),
+        size,
+        fields,
+    })
+}
+
+/// An input of a vertex shader that a vertex buffer has to supply
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexInput {
+    /// The argument or struct member name
+    pub name: String,
+    pub location: u32,
+    pub scalar: UniformScalar,
+    /// 1 for scalars, 2-4 for vectors
+    pub components: u32,
+}
+
+impl VertexInput {
+    /// The format that matches the shader's type exactly
+    pub fn format(&self) -> wgpu::VertexFormat {
+        use wgpu::VertexFormat::*;
+        let formats = match self.scalar {
+            UniformScalar::Float => [Float32, Float32x2, Float32x3, Float32x4],
+            UniformScalar::Sint => [Sint32, Sint32x2, Sint32x3, Sint32x4],
+            UniformScalar::Uint => [Uint32, Uint32x2, Uint32x3, Uint32x4],
+        };
+        formats[self.components as usize - 1]
+    }
+
+    /// Whether `format` can feed this input. Normalized formats read as
+    /// floats, and the component count doesn't have to match.
+    pub fn accepts(&self, format: wgpu::VertexFormat) -> bool {
+        use wgpu::VertexFormat::*;
+        let scalar =
+            match format {
+                Uint8x2 | Uint8x4 | Uint16x2 | Uint16x4 | Uint32 | Uint32x2 | Uint32x3
+                | Uint32x4 => UniformScalar::Uint,
+                Sint8x2 | Sint8x4 | Sint16x2 | Sint16x4 | Sint32 | Sint32x2 | Sint32x3
+                | Sint32x4 => UniformScalar::Sint,
+                _ => UniformScalar::Float,
+            };
+        scalar == self.scalar
+    }
+}
+
+/// The inputs of the vertex entry point `entry_point`, in location order.
+/// `None` if there isn't a vertex entry point with that name.
+pub fn reflect_vertex_inputs(module: &naga::Module, entry_point: &str) -> Option<Vec<VertexInput>> {
+    let entry = module
+        .entry_points
+        .iter()
+        .find(|e| e.stage == naga::ShaderStage::Vertex && e.name == entry_point)?;
+    let mut inputs = Vec::new();
+    let mut add =
+        |name: &Option<String>, ty: naga::Handle<naga::Type>, binding: &Option<naga::Binding>| {
+            if let Some(naga::Binding::Location { location, .. }) = binding {
+                let (scalar, components) = match module.types[ty].inner {
+                    naga::TypeInner::Scalar(scalar) => (scalar, 1),
+                    naga::TypeInner::Vector { size, scalar } => (scalar, size as u32),
+                    _ => return,
+                };
+                let scalar = match scalar.kind {
+                    naga::ScalarKind::Sint => UniformScalar::Sint,
+                    naga::ScalarKind::Uint => UniformScalar::Uint,
+                    _ => UniformScalar::Float,
+                };
+                inputs.push(VertexInput {
+                    name: name.clone().unwrap_or_default(),
+                    location: *location,
+                    scalar,
+                    components,
+                });
+            }
+        };
+    for argument in &entry.function.arguments {
+        match &module.types[argument.ty].inner {
+            naga::TypeInner::Struct { members, .. } if argument.binding.is_none() => {
+                for member in members {
+                    add(&member.name, member.ty, &member.binding);
+                }
+            }
+            _ => add(&argument.name, argument.ty, &argument.binding),
+        }
+    }
+    inputs.sort_by_key(|i| i.location);
+    Some(inputs)
+}
+
+/// Layout entries for every resource the module binds, by group. Each
+/// entry is visible to the stages whose entry points use it. Float
+/// textures are assumed to be filterable, and uniform buffers get a
+/// `min_binding_size` so wgpu checks the buffer is big enough.
+pub fn reflect_bind_groups(
+    module: &naga::Module,
+) -> anyhow::Result<BTreeMap<u32, Vec<wgpu::BindGroupLayoutEntry>>> {
+    let info = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(module)?;
+
+    let mut groups: BTreeMap<u32, Vec<wgpu::BindGroupLayoutEntry>> = BTreeMap::new();
+    for (handle, var) in module.global_variables.iter() {
+        let binding = match &var.binding {
+            Some(binding) => binding,
+            None => continue,
+        };
+        let mut visibility = wgpu::ShaderStages::NONE;
+        for (index, entry) in module.entry_points.iter().enumerate() {
+            if !info.get_entry_point(index)[handle].is_empty() {
+                visibility |= match entry.stage {
+                    naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                    naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                    naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                };
+            }
+        }
+        let name = var.name.as_deref().unwrap_or("?");
+        let (ty, count) = match module.types[var.ty].inner {
+            naga::TypeInner::BindingArray { base, size } => {
+                let count = match size {
+                    naga::ArraySize::Constant(count) => Some(count),
+                    naga::ArraySize::Dynamic => {
+                        anyhow::bail!("{} is a binding array without a size", name)
+                    }
+                };
+                (base, count)
+            }
+            _ => (var.ty, None),
+        };
+        let ty = binding_type(module, var.space, ty)
+            .ok_or_else(|| anyhow::anyhow!("Can't make a layout entry for {}", name))?;
+        groups
+            .entry(binding.group)
+            .or_default()
+            .push(wgpu::BindGroupLayoutEntry {
+                binding: binding.binding,
+                visibility,
+                ty,
+                count,
+            });
+    }
+    for entries in groups.values_mut() {
+        entries.sort_by_key(|e| e.binding);
+    }
+    Ok(groups)
+}
+
+fn binding_type(
+    module: &naga::Module,
+    space: naga::AddressSpace,
+    ty: naga::Handle<naga::Type>,
+) -> Option<wgpu::BindingType> {
+    let inner = &module.types[ty].inner;
+    match space {
+        naga::AddressSpace::Uniform => {
+            return Some(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: std::num::NonZeroU64::new(inner.size(module.to_ctx()) as u64),
+            })
+        }
+        naga::AddressSpace::Storage { access } => {
+            return Some(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage {
+                    read_only: !access.contains(naga::StorageAccess::STORE),
+                },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+        }
+        _ => {}
+    }
+    match *inner {
+        naga::TypeInner::Sampler { comparison } => {
+            Some(wgpu::BindingType::Sampler(if comparison {
+                wgpu::SamplerBindingType::Comparison
+            } else {
+                wgpu::SamplerBindingType::Filtering
+            }))
+        }
+        naga::TypeInner::Image {
+            dim,
+            arrayed,
+            class,
+        } => {
+            let view_dimension = match (dim, arrayed) {
+                (naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+                (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                (naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+                (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+            };
+            match class {
+                naga::ImageClass::Sampled { kind, multi } => Some(wgpu::BindingType::Texture {
+                    sample_type: match kind {
+                        naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                        naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                        _ => wgpu::TextureSampleType::Float { filterable: !multi },
+                    },
+                    view_dimension,
+                    multisampled: multi,
+                }),
+                naga::ImageClass::Depth { multi } => Some(wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension,
+                    multisampled: multi,
+                }),
+                naga::ImageClass::Storage { format, access } => {
+                    let read = access.contains(naga::StorageAccess::LOAD);
+                    let write = access.contains(naga::StorageAccess::STORE);
+                    Some(wgpu::BindingType::StorageTexture {
+                        access: match (read, write) {
+                            (true, true) => wgpu::StorageTextureAccess::ReadWrite,
+                            (true, false) => wgpu::StorageTextureAccess::ReadOnly,
+                            _ => wgpu::StorageTextureAccess::WriteOnly,
+                        },
+                        format: storage_format(format)?,
+                        view_dimension,
+                    })
+                }
+            }
+        }
+        _ => None,
+    }
+}
+
+fn storage_format(format: naga::StorageFormat) -> Option<wgpu::TextureFormat> {
+    use naga::StorageFormat as S;
+    use wgpu::TextureFormat as T;
+    Some(match format {
+        S::R32Uint => T::R32Uint,
+        S::R32Sint => T::R32Sint,
+        S::R32Float => T::R32Float,
+        S::Rg32Uint => T::Rg32Uint,
+        S::Rg32Sint => T::Rg32Sint,
+        S::Rg32Float => T::Rg32Float,
+        S::Rgba8Unorm => T::Rgba8Unorm,
+        S::Rgba8Snorm => T::Rgba8Snorm,
+        S::Rgba8Uint => T::Rgba8Uint,
+        S::Rgba8Sint => T::Rgba8Sint,
+        S::Bgra8Unorm => T::Bgra8Unorm,
+        S::Rgba16Uint => T::Rgba16Uint,
+        S::Rgba16Sint => T::Rgba16Sint,
+        S::Rgba16Float => T::Rgba16Float,
+        S::Rgba32Uint => T::Rgba32Uint,
+        S::Rgba32Sint => T::Rgba32Sint,
+        S::Rgba32Float => T::Rgba32Float,
+        _ => return None,
+    })
+}
+
+/// Bind group and pipeline layouts made from what a pipeline's shaders
+/// bind, so they can't drift out of sync with the WGSL. Pass it to
+/// [RenderPipelineBuilder::from_reflection](crate::RenderPipelineBuilder::from_reflection)
+/// and use [ReflectedLayout::bind_group_layout] to make the bind groups.
+///
+/// ```ignore
+/// let reflected = ReflectedLayout::new(&display.device, "Forward", &[SHADER])?;
+/// let pipeline = RenderPipelineBuilder::from_reflection(&reflected)
+///     .vertex_shader(wgpu::ShaderModuleDescriptor { label: Some("Forward"), source: wgpu::ShaderSource::Wgsl(SHADER.into()) })
+///     // ...
+///     .build(&display.device)?;
+/// let material_layout = reflected.bind_group_layout(0).unwrap();
+/// ```
+pub struct ReflectedLayout {
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    pipeline_layout: wgpu::PipelineLayout,
+    entries: BTreeMap<u32, Vec<wgpu::BindGroupLayoutEntry>>,
+    vertex_inputs: Vec<(String, Vec<VertexInput>)>,
+}
+
+impl ReflectedLayout {
+    /// Reflects every WGSL source, usually the vertex and fragment shader.
+    /// A binding used by more than one has to have the same type in each.
+    /// Unused groups below the highest one are left empty.
+    pub fn new(device: &wgpu::Device, label: &str, sources: &[&str]) -> anyhow::Result<Self> {
+        let mut entries: BTreeMap<u32, Vec<wgpu::BindGroupLayoutEntry>> = BTreeMap::new();
+        let mut vertex_inputs = Vec::new();
+        for source in sources {
+            let module = crate::shader::validate_wgsl(source)?;
+            for (group, reflected) in reflect_bind_groups(&module)? {
+                let merged = entries.entry(group).or_default();
+                for entry in reflected {
+                    match merged.iter_mut().find(|e| e.binding == entry.binding) {
+                        Some(existing)
+                            if existing.ty == entry.ty && existing.count == entry.count =>
+                        {
+                            existing.visibility |= entry.visibility;
+                        }
+                        Some(_) => anyhow::bail!(
+                            "@group({}) @binding({}) has different types in different shaders",
+                            group,
+                            entry.binding
+                        ),
+                        None => merged.push(entry),
+                    }
+                }
+                merged.sort_by_key(|e| e.binding);
+            }
+            for entry in &module.entry_points {
+                if entry.stage == naga::ShaderStage::Vertex {
+                    let inputs = reflect_vertex_inputs(&module, &entry.name).unwrap_or_default();
+                    vertex_inputs.push((entry.name.clone(), inputs));
+                }
+            }
+        }
+
+        let count = entries.keys().next_back().map_or(0, |g| g + 1);
+        let bind_group_layouts = (0..count)
+            .map(|group| {
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some(&format!("{} group {}", label, group)),
+                    entries: entries.get(&group).map_or(&[], |e| &e[..]),
+                })
+            })
+            .collect::<Vec<_>>();
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
+            push_constant_ranges: &[],
+        });
+        Ok(Self {
+            bind_group_layouts,
+            pipeline_layout,
+            entries,
+            vertex_inputs,
+        })
+    }
+
+    pub fn pipeline_layout(&self) -> &wgpu::PipelineLayout {
+        &self.pipeline_layout
+    }
+
+    /// `None` past the highest group the shaders use
+    pub fn bind_group_layout(&self, group: u32) -> Option<&wgpu::BindGroupLayout> {
+        self.bind_group_layouts.get(group as usize)
+    }
+
+    /// The reflected entries of `group`, empty if the shaders don't use it
+    pub fn entries(&self, group: u32) -> &[wgpu::BindGroupLayoutEntry] {
+        self.entries.get(&group).map_or(&[], |e| &e[..])
+    }
+
+    /// What the vertex entry point `entry_point` expects from vertex
+    /// buffers
+    pub fn vertex_inputs(&self, entry_point: &str) -> Option<&[VertexInput]> {
+        self.vertex_inputs
+            .iter()
+            .find(|(name, _)| name == entry_point)
+            .map(|(_, inputs)| &inputs[..])
+    }
+
+    /// Checks `buffers` supply every input of `entry_point` with a format
+    /// of the right type. wgpu would panic on the mismatch instead.
+    pub fn check_vertex_buffers(
+        &self,
+        entry_point: &str,
+        buffers: &[wgpu::VertexBufferLayout],
+    ) -> anyhow::Result<()> {
+        let inputs = self.vertex_inputs(entry_point).ok_or_else(|| {
+            anyhow::anyhow!("There's no vertex entry point called {}", entry_point)
+        })?;
+        for input in inputs {
+            let attribute = buffers
+                .iter()
+                .flat_map(|b| b.attributes)
+                .find(|a| a.shader_location == input.location);
+            match attribute {
+                None => anyhow::bail!(
+                    "{} needs {} at @location({}), but no vertex buffer has it",
+                    entry_point,
+                    input.name,
+                    input.location
+                ),
+                Some(a) if !input.accepts(a.format) => anyhow::bail!(
+                    "{} at @location({}) is {:?} in the vertex buffer, which doesn't fit {:?}",
+                    input.name,
+                    input.location,
+                    a.format,
+                    input.format()
+                ),
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reflects_struct_members() {
+        let module = naga::front::wgsl::parse_str(
+            "
+            struct Params {
+                speed: f32,
+                tint_color: vec3<f32>,
+                steps: i32,
+                transform: mat4x4<f32>,
+            }
+            @group(1) @binding(0) var<uniform> params: Params;
+            ",
+        )
+        .unwrap();
+
+        let layout = reflect_uniform(&module, 1, 0).unwrap();
+        assert_eq!(layout.name, "params");
+        assert_eq!(layout.size, 96);
+        let names = layout
+            .fields
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["speed", "tint_color", "steps"]);
+        assert_eq!(layout.fields[1].offset, 16);
+        assert_eq!(layout.fields[1].components, 3);
+        assert_eq!(layout.fields[2].scalar, UniformScalar::Sint);
+
+        assert!(reflect_uniform(&module, 0, 0).is_none());
+    }
+
+    #[test]
+    fn reflects_bindings_and_vertex_inputs() {
+        let module = crate::shader::validate_wgsl(
+            "
+            struct Camera { view_proj: mat4x4<f32> }
+            @group(1) @binding(0) var<uniform> camera: Camera;
+            @group(0) @binding(1) var diffuse_sampler: sampler;
+            @group(0) @binding(0) var diffuse: texture_2d<f32>;
+            @group(2) @binding(0) var<storage, read> unused: array<f32>;
+            struct VertexInput {
+                @location(0) position: vec3<f32>,
+                @location(2) ids: vec2<u32>,
+            }
+            struct VertexOutput {
+                @builtin(position) clip_position: vec4<f32>,
+                @location(0) uv: vec2<f32>,
+            }
+            @vertex
+            fn vs_main(vertex: VertexInput, @location(1) uv: vec2<f32>) -> VertexOutput {
+                var out: VertexOutput;
+                out.clip_position = camera.view_proj * vec4<f32>(vertex.position, 1.0);
+                out.uv = uv;
+                return out;
+            }
+            @fragment
+            fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+                return textureSample(diffuse, diffuse_sampler, in.uv);
+            }
+            ",
+        )
+        .unwrap();
+
+        let groups = reflect_bind_groups(&module).unwrap();
+        assert_eq!(groups.keys().copied().collect::<Vec<_>>(), [0, 1, 2]);
+        let material = &groups[&0];
+        assert_eq!(material[0].binding, 0);
+        assert_eq!(material[0].visibility, wgpu::ShaderStages::FRAGMENT);
+        assert!(matches!(material[0].ty, wgpu::BindingType::Texture { .. }));
+        assert!(matches!(
+            material[1].ty,
+            wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering)
+        ));
+        let camera = groups[&1][0];
+        assert_eq!(camera.visibility, wgpu::ShaderStages::VERTEX);
+        assert!(matches!(
+            camera.ty,
+            wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                min_binding_size: Some(size),
+                ..
+            } if size.get() == 64
+        ));
+        assert_eq!(groups[&2][0].visibility, wgpu::ShaderStages::NONE);
+
+        let inputs = reflect_vertex_inputs(&module, "vs_main").unwrap();
+        let locations = inputs.iter().map(|i| i.location).collect::<Vec<_>>();
+        assert_eq!(locations, [0, 1, 2]);
+        assert_eq!(inputs[0].format(), wgpu::VertexFormat::Float32x3);
+        assert_eq!(inputs[2].format(), wgpu::VertexFormat::Uint32x2);
+        assert!(inputs[1].accepts(wgpu::VertexFormat::Unorm16x2));
+        assert!(!inputs[2].accepts(wgpu::VertexFormat::Float32x2));
+        assert!(reflect_vertex_inputs(&module, "fs_main").is_none());
+    }
+}
@@ -0,0 +1,693 @@
+use std::collections::BTreeSet;
+
+use anyhow::*;
+
+/// A texture in a [RenderGraph], from [RenderGraph::import] or
+/// [RenderGraph::create]. Only means something in the graph it came from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct GraphTexture(usize);
+
+/// What a transient texture is made as. Transients with the same
+/// description that are never needed at the same time share a texture.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TransientDesc {
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub sample_count: u32,
+}
+
+impl TransientDesc {
+    pub fn new(format: wgpu::TextureFormat, width: u32, height: u32) -> Self {
+        Self {
+            format,
+            width: width.max(1),
+            height: height.max(1),
+            sample_count: 1,
+        }
+    }
+
+    /// The size of the surface
+    pub fn surface(format: wgpu::TextureFormat, config: &wgpu::SurfaceConfiguration) -> Self {
+        Self::new(format, config.width, config.height)
+    }
+
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+}
+
+struct ResourceDecl {
+    name: String,
+    /// None for imported textures
+    transient: Option<TransientDesc>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct DepthDecl {
+    texture: GraphTexture,
+    clear: Option<f32>,
+    read_only: bool,
+}
+
+#[derive(Default)]
+struct PassDecl {
+    name: String,
+    /// Color attachments in order, and what to clear them to
+    colors: Vec<(GraphTexture, Option<wgpu::Color>)>,
+    depth: Option<DepthDecl>,
+    /// Sampled or copied from
+    reads: Vec<GraphTexture>,
+    /// Written some other way than as an attachment, by encoder passes
+    writes: Vec<GraphTexture>,
+    /// Encoder passes can have effects the graph can't see, so they're
+    /// never culled
+    keep: bool,
+}
+
+impl PassDecl {
+    fn written(&self) -> impl Iterator<Item = GraphTexture> + '_ {
+        self.colors
+            .iter()
+            .map(|(t, _)| *t)
+            .chain(self.depth.filter(|d| !d.read_only).map(|d| d.texture))
+            .chain(self.writes.iter().copied())
+    }
+
+    fn read(&self) -> impl Iterator<Item = GraphTexture> + '_ {
+        self.reads
+            .iter()
+            .copied()
+            .chain(self.depth.filter(|d| d.read_only).map(|d| d.texture))
+    }
+}
+
+type RenderFn<'a> = Box<dyn FnOnce(&mut wgpu::RenderPass<'_>, &GraphResources<'_>) + 'a>;
+type EncodeFn<'a> = Box<dyn FnOnce(&mut wgpu::CommandEncoder, &GraphResources<'_>) + 'a>;
+
+enum PassRun<'a> {
+    Render(RenderFn<'a>),
+    Encode(EncodeFn<'a>),
+}
+
+/// The views a pass can read while it runs
+pub struct GraphResources<'r> {
+    views: Vec<Option<&'r wgpu::TextureView>>,
+}
+
+impl GraphResources<'_> {
+    /// Panics for textures the pass didn't declare
+    pub fn view(&self, texture: GraphTexture) -> &wgpu::TextureView {
+        self.views[texture.0].expect("Texture isn't used by this frame's graph")
+    }
+}
+
+/// A frame's passes, declared with the textures they read and write
+/// instead of in the order they run. [RenderGraph::execute] works out the
+/// order, drops passes nothing uses, makes the transient textures in
+/// between, sharing them where their uses don't overlap, and picks each
+/// attachment's load and store ops: cleared the first time it's written,
+/// loaded after that, and only stored if something later needs it.
+///
+/// A pass's reads see every write to that texture in the graph, whatever
+/// order they were added in. Several passes writing one texture run in
+/// the order they were added.
+///
+/// The graph is made again each frame. The [RenderGraphPool] it takes its
+/// textures from lives as long as the demo.
+///
+/// ```ignore
+/// let mut graph = RenderGraph::new();
+/// let frame = graph.import("frame", &frame_view);
+/// let depth = graph.create("depth", TransientDesc::surface(Texture::DEPTH_FORMAT, &display.config));
+/// let hdr = graph.create("hdr", TransientDesc::surface(HdrPipeline::FORMAT, &display.config));
+///
+/// // Added first, but runs second because it reads hdr
+/// graph.add_pass("tonemap").read(hdr).write(frame).render(|pass, res| {
+///     let bind_group = tonemap.bind_group(&display.device, res.view(hdr));
+///     pass.set_pipeline(&tonemap.pipeline);
+///     pass.set_bind_group(0, &bind_group, &[]);
+///     pass.draw(0..3, 0..1);
+/// });
+/// graph
+///     .add_pass("scene")
+///     .clear(hdr, wgpu::Color::BLACK)
+///     .clear_depth(depth, 1.0)
+///     .render(|pass, _| scene.draw(pass));
+///
+/// graph.execute(&display.device, &mut encoder, &mut self.graph_pool)?;
+/// ```
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    resources: Vec<ResourceDecl>,
+    imports: Vec<Option<&'a wgpu::TextureView>>,
+    passes: Vec<PassDecl>,
+    runs: Vec<PassRun<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A texture from outside the graph, like the surface or a history
+    /// buffer. What's in it is loaded rather than cleared, and whatever the
+    /// graph writes to it is stored.
+    pub fn import(&mut self, name: &str, view: &'a wgpu::TextureView) -> GraphTexture {
+        self.add_resource(name, None, Some(view))
+    }
+
+    /// A texture that only lasts the frame, made by the graph when a pass
+    /// first needs it
+    pub fn create(&mut self, name: &str, desc: TransientDesc) -> GraphTexture {
+        self.add_resource(name, Some(desc), None)
+    }
+
+    fn add_resource(
+        &mut self,
+        name: &str,
+        transient: Option<TransientDesc>,
+        view: Option<&'a wgpu::TextureView>,
+    ) -> GraphTexture {
+        self.resources.push(ResourceDecl {
+            name: name.to_string(),
+            transient,
+        });
+        self.imports.push(view);
+        GraphTexture(self.resources.len() - 1)
+    }
+
+    /// Starts declaring a pass. It's added when the builder's
+    /// [PassBuilder::render] or [PassBuilder::encode] is called.
+    pub fn add_pass<'g>(&'g mut self, name: &str) -> PassBuilder<'g, 'a> {
+        PassBuilder {
+            graph: self,
+            decl: PassDecl {
+                name: name.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Runs the passes that lead to an imported texture, in an order that
+    /// satisfies their reads. Fails without recording anything if a pass
+    /// reads a transient nothing writes, or the passes depend on each
+    /// other in a loop.
+    pub fn execute(
+        self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pool: &mut RenderGraphPool,
+    ) -> Result<()> {
+        crate::cpu_scope!("RenderGraph::execute");
+        let plan = plan(&self.resources, &self.passes)?;
+        let slots = pool.acquire(device, &plan.physical);
+        let pool: &RenderGraphPool = pool;
+        let resources = GraphResources {
+            views: self
+                .imports
+                .iter()
+                .zip(&plan.slots)
+                .map(|(import, slot)| match (import, slot) {
+                    (Some(view), _) => Some(*view),
+                    (None, Some(slot)) => Some(&pool.textures[slots[*slot]].view),
+                    (None, None) => None,
+                })
+                .collect(),
+        };
+
+        let mut runs: Vec<_> = self.runs.into_iter().map(Some).collect();
+        for (&index, ops) in plan.order.iter().zip(&plan.ops) {
+            let decl = &self.passes[index];
+            match runs[index].take().unwrap() {
+                PassRun::Encode(run) => run(encoder, &resources),
+                PassRun::Render(run) => {
+                    let color_attachments: Vec<_> = decl
+                        .colors
+                        .iter()
+                        .zip(&ops.colors)
+                        .map(|((texture, _), ops)| {
+                            Some(wgpu::RenderPassColorAttachment {
+                                view: resources.view(*texture),
+                                resolve_target: None,
+                                ops: *ops,
+                            })
+                        })
+                        .collect();
+                    let depth_stencil_attachment =
+                        decl.depth
+                            .map(|depth| wgpu::RenderPassDepthStencilAttachment {
+                                view: resources.view(depth.texture),
+                                depth_ops: ops.depth,
+                                stencil_ops: None,
+                            });
+                    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some(&decl.name),
+                        color_attachments: &color_attachments,
+                        depth_stencil_attachment,
+                        occlusion_query_set: None,
+                        timestamp_writes: None,
+                    });
+                    run(&mut pass, &resources);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Declares one pass of a [RenderGraph]
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    decl: PassDecl,
+}
+
+impl<'a> PassBuilder<'_, 'a> {
+    /// Sampled or copied from. Runs after every pass that writes it.
+    pub fn read(mut self, texture: GraphTexture) -> Self {
+        self.decl.reads.push(texture);
+        self
+    }
+
+    /// The next color attachment. Cleared to transparent if nothing has
+    /// written it yet this frame.
+    pub fn write(mut self, texture: GraphTexture) -> Self {
+        self.decl.colors.push((texture, None));
+        self
+    }
+
+    /// The next color attachment, cleared to `color` first
+    pub fn clear(mut self, texture: GraphTexture, color: wgpu::Color) -> Self {
+        self.decl.colors.push((texture, Some(color)));
+        self
+    }
+
+    /// The depth attachment, tested and written
+    pub fn depth(mut self, texture: GraphTexture) -> Self {
+        self.decl.depth = Some(DepthDecl {
+            texture,
+            clear: None,
+            read_only: false,
+        });
+        self
+    }
+
+    /// The depth attachment, cleared to `depth` first
+    pub fn clear_depth(mut self, texture: GraphTexture, depth: f32) -> Self {
+        self.decl.depth = Some(DepthDecl {
+            texture,
+            clear: Some(depth),
+            read_only: false,
+        });
+        self
+    }
+
+    /// The depth attachment, tested against but not written
+    pub fn read_depth(mut self, texture: GraphTexture) -> Self {
+        self.decl.depth = Some(DepthDecl {
+            texture,
+            clear: None,
+            read_only: true,
+        });
+        self
+    }
+
+    /// Adds the pass, which draws into a render pass the graph begins with
+    /// the declared attachments
+    pub fn render<F>(self, run: F)
+    where
+        F: FnOnce(&mut wgpu::RenderPass<'_>, &GraphResources<'_>) + 'a,
+    {
+        self.graph.passes.push(self.decl);
+        self.graph.runs.push(PassRun::Render(Box::new(run)));
+    }
+
+    /// Adds the pass, which records whatever it likes, like copies or
+    /// compute. Attachments declared on it are treated as written some
+    /// other way, and it's never culled.
+    pub fn encode<F>(mut self, run: F)
+    where
+        F: FnOnce(&mut wgpu::CommandEncoder, &GraphResources<'_>) + 'a,
+    {
+        let colors = std::mem::take(&mut self.decl.colors);
+        self.decl.writes.extend(colors.into_iter().map(|(t, _)| t));
+        self.decl.keep = true;
+        self.graph.passes.push(self.decl);
+        self.graph.runs.push(PassRun::Encode(Box::new(run)));
+    }
+}
+
+#[derive(Debug, Default)]
+struct PassOps {
+    colors: Vec<wgpu::Operations<wgpu::Color>>,
+    depth: Option<wgpu::Operations<f32>>,
+}
+
+/// How a graph runs, worked out without touching the GPU
+#[derive(Debug)]
+struct Plan {
+    /// Pass indices in the order they run
+    order: Vec<usize>,
+    /// For each pass in `order`
+    ops: Vec<PassOps>,
+    /// Which of `physical` each resource uses. None for imports and
+    /// transients no pass that runs uses.
+    slots: Vec<Option<usize>>,
+    /// The textures the transients need
+    physical: Vec<TransientDesc>,
+}
+
+fn plan(resources: &[ResourceDecl], passes: &[PassDecl]) -> Result<Plan> {
+    let imported = |t: GraphTexture| resources[t.0].transient.is_none();
+    let mut writers = vec![Vec::new(); resources.len()];
+    let mut readers = vec![Vec::new(); resources.len()];
+    for (p, pass) in passes.iter().enumerate() {
+        for t in pass.written() {
+            writers[t.0].push(p);
+        }
+    }
+    for (p, pass) in passes.iter().enumerate() {
+        for t in pass.read() {
+            let name = &resources[t.0].name;
+            if pass.written().any(|w| w == t) {
+                bail!("Pass {} reads and writes {}", pass.name, name);
+            }
+            if !imported(t) && writers[t.0].is_empty() {
+                bail!("Pass {} reads {}, which nothing writes", pass.name, name);
+            }
+            readers[t.0].push(p);
+        }
+    }
+
+    // Passes that lead to an import, or can't be seen through
+    let mut needed: Vec<bool> = passes
+        .iter()
+        .map(|p| p.keep || p.written().any(imported))
+        .collect();
+    let mut stack: Vec<usize> = (0..passes.len()).filter(|&p| needed[p]).collect();
+    while let Some(p) = stack.pop() {
+        for t in passes[p].read().chain(passes[p].written()) {
+            for &w in &writers[t.0] {
+                if !needed[w] {
+                    needed[w] = true;
+                    stack.push(w);
+                }
+            }
+        }
+    }
+
+    // Writers run in the order they were added, readers after the last
+    let mut after = vec![Vec::new(); passes.len()];
+    let mut waiting = vec![0; passes.len()];
+    let mut edge = |from: usize, to: usize| {
+        if needed[from] && needed[to] {
+            after[from].push(to);
+            waiting[to] += 1;
+        }
+    };
+    for (writers, readers) in writers.iter().zip(&readers) {
+        for pair in writers.windows(2) {
+            edge(pair[0], pair[1]);
+        }
+        if let Some(&last) = writers.last() {
+            for &r in readers {
+                edge(last, r);
+            }
+        }
+    }
+    let mut ready: BTreeSet<usize> = (0..passes.len())
+        .filter(|&p| needed[p] && waiting[p] == 0)
+        .collect();
+    let mut order = Vec::new();
+    while let Some(p) = ready.pop_first() {
+        order.push(p);
+        for &next in &after[p] {
+            waiting[next] -= 1;
+            if waiting[next] == 0 {
+                ready.insert(next);
+            }
+        }
+    }
+    let count = needed.iter().filter(|n| **n).count();
+    if order.len() != count {
+        let stuck: Vec<_> = (0..passes.len())
+            .filter(|&p| needed[p] && waiting[p] > 0)
+            .map(|p| passes[p].name.as_str())
+            .collect();
+        bail!(
+            "Passes depend on each other in a loop: {}",
+            stuck.join(", ")
+        );
+    }
+
+    // Where in the order each resource is first and last used
+    let mut first = vec![usize::MAX; resources.len()];
+    let mut last = vec![0; resources.len()];
+    for (i, &p) in order.iter().enumerate() {
+        for t in passes[p].read().chain(passes[p].written()) {
+            first[t.0] = first[t.0].min(i);
+            last[t.0] = last[t.0].max(i);
+        }
+    }
+
+    // Transients share a texture with one that's finished with by the time
+    // they're first used
+    let mut transients: Vec<usize> = (0..resources.len())
+        .filter(|&r| resources[r].transient.is_some() && first[r] != usize::MAX)
+        .collect();
+    transients.sort_by_key(|&r| first[r]);
+    let mut physical: Vec<TransientDesc> = Vec::new();
+    let mut free_after: Vec<usize> = Vec::new();
+    let mut slots = vec![None; resources.len()];
+    for r in transients {
+        let desc = resources[r].transient.unwrap();
+        let slot = (0..physical.len())
+            .find(|&s| physical[s] == desc && free_after[s] < first[r])
+            .unwrap_or_else(|| {
+                physical.push(desc);
+                free_after.push(0);
+                physical.len() - 1
+            });
+        free_after[slot] = last[r];
+        slots[r] = Some(slot);
+    }
+
+    // Cleared on the first write, stored if anything after wants it
+    let mut written = vec![false; resources.len()];
+    let mut ops = Vec::with_capacity(order.len());
+    for (i, &p) in order.iter().enumerate() {
+        let pass = &passes[p];
+        let store = |t: GraphTexture| {
+            if imported(t) || last[t.0] > i {
+                wgpu::StoreOp::Store
+            } else {
+                wgpu::StoreOp::Discard
+            }
+        };
+        let mut pass_ops = PassOps::default();
+        for &(t, clear) in &pass.colors {
+            let load = match clear {
+                Some(color) => wgpu::LoadOp::Clear(color),
+                None if written[t.0] || imported(t) => wgpu::LoadOp::Load,
+                None => wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+            };
+            pass_ops.colors.push(wgpu::Operations {
+                load,
+                store: store(t),
+            });
+            written[t.0] = true;
+        }
+        if let Some(depth) = pass.depth.filter(|d| !d.read_only) {
+            let t = depth.texture;
+            let load = match depth.clear {
+                Some(value) => wgpu::LoadOp::Clear(value),
+                None if written[t.0] || imported(t) => wgpu::LoadOp::Load,
+                None => wgpu::LoadOp::Clear(1.0),
+            };
+            pass_ops.depth = Some(wgpu::Operations {
+                load,
+                store: store(t),
+            });
+            written[t.0] = true;
+        }
+        for &t in &pass.writes {
+            written[t.0] = true;
+        }
+        ops.push(pass_ops);
+    }
+
+    Ok(Plan {
+        order,
+        ops,
+        slots,
+        physical,
+    })
+}
+
+struct PooledTexture {
+    desc: TransientDesc,
+    view: wgpu::TextureView,
+    last_used: u64,
+}
+
+/// The textures [RenderGraph]s make their transients from, kept between
+/// frames so they aren't made again every time. Ones that go a frame
+/// without being used, like after a resize, are dropped.
+#[derive(Default)]
+pub struct RenderGraphPool {
+    textures: Vec<PooledTexture>,
+    frame: u64,
+}
+
+impl RenderGraphPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Textures held right now
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+
+    /// Index into `textures` for each desc
+    fn acquire(&mut self, device: &wgpu::Device, descs: &[TransientDesc]) -> Vec<usize> {
+        self.frame += 1;
+        let frame = self.frame;
+        self.textures.retain(|t| t.last_used + 1 >= frame);
+        descs
+            .iter()
+            .map(|desc| {
+                if let Some(i) = self
+                    .textures
+                    .iter()
+                    .position(|t| t.desc == *desc && t.last_used != frame)
+                {
+                    self.textures[i].last_used = frame;
+                    return i;
+                }
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("RenderGraph::transient"),
+                    size: wgpu::Extent3d {
+                        width: desc.width,
+                        height: desc.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: desc.sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: desc.format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::COPY_SRC
+                        | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                });
+                self.textures.push(PooledTexture {
+                    desc: *desc,
+                    view: texture.create_view(&Default::default()),
+                    last_used: frame,
+                });
+                self.textures.len() - 1
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transient(name: &str) -> ResourceDecl {
+        ResourceDecl {
+            name: name.to_string(),
+            transient: Some(TransientDesc::new(wgpu::TextureFormat::Rgba16Float, 64, 64)),
+        }
+    }
+
+    fn pass(name: &str, reads: &[usize], writes: &[usize]) -> PassDecl {
+        PassDecl {
+            name: name.to_string(),
+            colors: writes.iter().map(|&t| (GraphTexture(t), None)).collect(),
+            reads: reads.iter().map(|&t| GraphTexture(t)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn passes_are_ordered_culled_and_aliased() {
+        let resources = vec![
+            ResourceDecl {
+                name: "frame".to_string(),
+                transient: None,
+            },
+            transient("scene"),
+            transient("bright"),
+            transient("blurred"),
+            transient("unused"),
+        ];
+        // Added out of order, with one pass nothing needs
+        let passes = vec![
+            pass("composite", &[1, 3], &[0]),
+            pass("debug", &[1], &[4]),
+            pass("blur", &[2], &[3]),
+            pass("scene", &[], &[1]),
+            pass("bright", &[1], &[2]),
+        ];
+        let plan = plan(&resources, &passes).unwrap();
+        assert_eq!(plan.order, [3, 4, 2, 0]);
+
+        // The blur reads bright while writing blurred, and scene lasts
+        // until composite, so nothing can share
+        assert_eq!(plan.slots[4], None);
+        assert_eq!(plan.physical.len(), 3);
+        assert_ne!(plan.slots[1], plan.slots[3]);
+
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT);
+        // scene is cleared and kept for composite
+        assert_eq!(plan.ops[0].colors[0].load, clear);
+        assert_eq!(plan.ops[0].colors[0].store, wgpu::StoreOp::Store);
+        // The frame is imported, so it's loaded and stored
+        assert_eq!(plan.ops[3].colors[0].load, wgpu::LoadOp::Load);
+        assert_eq!(plan.ops[3].colors[0].store, wgpu::StoreOp::Store);
+
+        // Reading something only a later transient pass writes is fine,
+        // reading what nothing writes isn't
+        let bad = vec![pass("composite", &[2], &[0])];
+        assert!(super::plan(&resources, &bad).is_err());
+        let looped = vec![pass("a", &[1], &[2]), pass("b", &[2], &[1, 0])];
+        assert!(super::plan(&resources, &looped).is_err());
+    }
+
+    #[test]
+    fn transients_share_once_finished() {
+        let resources = vec![
+            ResourceDecl {
+                name: "frame".to_string(),
+                transient: None,
+            },
+            transient("a"),
+            transient("b"),
+            transient("c"),
+        ];
+        // a -> b -> c -> frame, so c can reuse a's texture
+        let passes = vec![
+            pass("a", &[], &[1]),
+            pass("b", &[1], &[2]),
+            pass("c", &[2], &[3]),
+            pass("out", &[3], &[0]),
+        ];
+        let plan = plan(&resources, &passes).unwrap();
+        assert_eq!(plan.physical.len(), 2);
+        assert_eq!(plan.slots[1], plan.slots[3]);
+        // c's write clears whatever a left behind
+        assert_eq!(
+            plan.ops[2].colors[0].load,
+            wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT)
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use renderdoc::{RenderDoc, V141};
+use winit::keyboard::KeyCode;
+
+/// Triggers RenderDoc captures from inside the demo. RenderDoc's own
+/// capture key only works when it thinks our window has focus, which is
+/// unreliable, so this lets the framework ask for a capture directly.
+///
+/// This does nothing unless the demo was launched from RenderDoc.
+pub struct RenderDocCapture {
+    api: Option<RenderDoc<V141>>,
+    /// Pressing this captures the next frame. Defaults to F10.
+    pub hotkey: KeyCode,
+}
+
+impl RenderDocCapture {
+    /// Has to be called before the wgpu instance is created so RenderDoc
+    /// can hook the graphics API.
+    pub fn new() -> Self {
+        let api = match RenderDoc::new() {
+            Ok(api) => {
+                log::info!("RenderDoc attached, press F10 to capture a frame");
+                Some(api)
+            }
+            Err(e) => {
+                log::debug!("RenderDoc isn't available: {}", e);
+                None
+            }
+        };
+        Self {
+            api,
+            hotkey: KeyCode::F10,
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.api.is_some()
+    }
+
+    /// Captures the next presented frame. Returns false if RenderDoc isn't
+    /// attached.
+    pub fn trigger(&mut self) -> bool {
+        match &mut self.api {
+            Some(api) => {
+                api.trigger_capture();
+                log::info!("Capturing frame {}", api.get_num_captures() + 1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Called by the framework for every key event
+    pub fn process_key(&mut self, key: KeyCode, pressed: bool) {
+        if pressed && key == self.hotkey {
+            self.trigger();
+        }
+    }
+}
+
+impl Default for RenderDocCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
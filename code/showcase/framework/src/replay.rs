@@ -0,0 +1,357 @@
+//! Records input to a file and plays it back frame for frame, so bugs in
+//! interactive showcases can be reproduced exactly.
+//!
+//! Set `FRAMEWORK_RECORD_INPUT=input.json` to record a session (saved when
+//! the window closes) and `FRAMEWORK_REPLAY_INPUT=input.json` to play it
+//! back. While recording or playing, [Time] advances by a fixed delta
+//! instead of the wall clock, and demos should seed their random numbers
+//! from [InputReplay::seed], usually through [crate::Display::rng], so the
+//! run is identical every time. `FRAMEWORK_SEED=1234` fixes the seed
+//! without a recording, for captures that have to match between runs.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, MouseButton, MouseScrollDelta};
+use winit::keyboard::KeyCode;
+
+use crate::input::{GamepadEvent, Input, MidiEvent};
+use crate::time::Time;
+
+/// Everything [Input] can be told about, in a form that can be saved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent {
+    Key {
+        key: KeyCode,
+        pressed: bool,
+    },
+    MouseButton {
+        button: MouseButton,
+        pressed: bool,
+    },
+    Cursor(Option<(f64, f64)>),
+    MouseMotion(f64, f64),
+    /// In lines
+    Scroll(f32),
+    Midi(MidiEvent),
+    Gamepad(GamepadEvent),
+}
+
+impl InputEvent {
+    pub fn apply(&self, input: &mut Input) {
+        let state = |pressed| {
+            if pressed {
+                ElementState::Pressed
+            } else {
+                ElementState::Released
+            }
+        };
+        match *self {
+            InputEvent::Key { key, pressed } => input.process_key(key, state(pressed)),
+            InputEvent::MouseButton { button, pressed } => {
+                input.process_mouse_button(button, state(pressed))
+            }
+            InputEvent::Cursor(position) => input.process_cursor(position),
+            InputEvent::MouseMotion(dx, dy) => input.process_mouse_motion(dx, dy),
+            InputEvent::Scroll(lines) => {
+                input.process_scroll(MouseScrollDelta::LineDelta(0.0, lines))
+            }
+            InputEvent::Midi(event) => input.process_midi(event),
+            InputEvent::Gamepad(event) => input.process_gamepad(event),
+        }
+    }
+}
+
+/// A recorded session. Golden image tests can load one of these and feed
+/// each frame's events to an [Input] while advancing [Time] by `delta`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InputRecording {
+    pub seed: u64,
+    pub delta: Duration,
+    /// The events applied before each frame's update
+    pub frames: Vec<Vec<InputEvent>>,
+}
+
+impl InputRecording {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Unable to open {}", path.display()))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("{} isn't a valid input recording", path.display()))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Unable to create {}", path.display()))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+enum Mode {
+    Live,
+    Recording {
+        path: Option<PathBuf>,
+        recording: InputRecording,
+        pending: Vec<InputEvent>,
+    },
+    Playing {
+        recording: InputRecording,
+        frame: usize,
+    },
+}
+
+/// Sits between the window's events and [Input]. The framework owns one in
+/// [crate::Display] and routes every event through it.
+pub struct InputReplay {
+    mode: Mode,
+    seed: u64,
+    fixed_delta: Duration,
+}
+
+impl Default for InputReplay {
+    fn default() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self {
+            mode: Mode::Live,
+            seed,
+            fixed_delta: Duration::from_secs(1) / 60,
+        }
+    }
+}
+
+impl InputReplay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts recording or playing back depending on the
+    /// `FRAMEWORK_RECORD_INPUT` and `FRAMEWORK_REPLAY_INPUT` variables.
+    pub fn from_env() -> Result<Self> {
+        let mut replay = Self::new();
+        if let Some(seed) = std::env::var_os("FRAMEWORK_SEED") {
+            let seed = seed.to_string_lossy();
+            let seed = seed
+                .trim()
+                .parse()
+                .with_context(|| format!("FRAMEWORK_SEED isn't a number: {}", seed))?;
+            replay.set_seed(seed);
+        }
+        if let Some(path) = std::env::var_os("FRAMEWORK_REPLAY_INPUT") {
+            replay.play(path)?;
+        } else if let Some(path) = std::env::var_os("FRAMEWORK_RECORD_INPUT") {
+            replay.record(Some(PathBuf::from(path)));
+        }
+        Ok(replay)
+    }
+
+    /// The delta used while recording. Defaults to 60 fps.
+    pub fn set_fixed_delta(&mut self, delta: Duration) {
+        self.fixed_delta = delta;
+    }
+
+    /// Seed demos should use for anything random. It's saved with the
+    /// recording so playback makes the same choices.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Replaces the seed picked from the clock. Playing a recording
+    /// replaces it again with the recording's.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// Starts recording. If `path` is set the recording is saved there by
+    /// [InputReplay::finish].
+    pub fn record(&mut self, path: Option<PathBuf>) {
+        self.mode = Mode::Recording {
+            path,
+            recording: InputRecording {
+                seed: self.seed,
+                delta: self.fixed_delta,
+                frames: Vec::new(),
+            },
+            pending: Vec::new(),
+        };
+    }
+
+    pub fn play<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let recording = InputRecording::load(path.as_ref())?;
+        log::info!(
+            "Replaying {} frames from {}",
+            recording.frames.len(),
+            path.as_ref().display()
+        );
+        self.play_recording(recording);
+        Ok(())
+    }
+
+    pub fn play_recording(&mut self, recording: InputRecording) {
+        self.seed = recording.seed;
+        self.mode = Mode::Playing {
+            recording,
+            frame: 0,
+        };
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self.mode, Mode::Recording { .. })
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(self.mode, Mode::Playing { .. })
+    }
+
+    /// Called with every live event. Returns false if the event should be
+    /// ignored because a recording is playing.
+    pub fn capture(&mut self, event: &InputEvent) -> bool {
+        match &mut self.mode {
+            Mode::Live => true,
+            Mode::Recording { pending, .. } => {
+                pending.push(event.clone());
+                true
+            }
+            Mode::Playing { .. } => false,
+        }
+    }
+
+    /// Advances `time` and returns the recorded events for this frame,
+    /// which have already been applied to `input`. Live sessions tick
+    /// with the wall clock and return nothing.
+    pub fn begin_frame(
+        &mut self,
+        time: &mut Time,
+        input: &mut Input,
+    ) -> (Duration, Vec<InputEvent>) {
+        match &mut self.mode {
+            Mode::Live => {
+                input.begin_frame();
+                (time.tick(), Vec::new())
+            }
+            Mode::Recording {
+                recording, pending, ..
+            } => {
+                // MIDI and gamepads are polled rather than sent as window
+                // events
+                input.begin_frame();
+                pending.extend(input.midi_events().iter().copied().map(InputEvent::Midi));
+                pending.extend(
+                    input
+                        .gamepad_events()
+                        .iter()
+                        .copied()
+                        .map(InputEvent::Gamepad),
+                );
+                recording.frames.push(std::mem::take(pending));
+                (time.advance(recording.delta), Vec::new())
+            }
+            Mode::Playing { recording, frame } => {
+                let delta = recording.delta;
+                let events = recording.frames.get(*frame).cloned();
+                *frame += 1;
+                match events {
+                    Some(events) => {
+                        for event in &events {
+                            event.apply(input);
+                        }
+                        (time.advance(delta), events)
+                    }
+                    None => {
+                        log::info!("Replay finished, switching to live input");
+                        self.mode = Mode::Live;
+                        input.begin_frame();
+                        (time.tick(), Vec::new())
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stops recording and returns what was recorded
+    pub fn stop(&mut self) -> Option<InputRecording> {
+        match std::mem::replace(&mut self.mode, Mode::Live) {
+            Mode::Recording { recording, .. } => Some(recording),
+            _ => None,
+        }
+    }
+
+    /// Stops recording and saves it to the path given to
+    /// [InputReplay::record]. The framework calls this on exit.
+    pub fn finish(&mut self) -> Result<()> {
+        let path = match &self.mode {
+            Mode::Recording {
+                path: Some(path), ..
+            } => path.clone(),
+            _ => return Ok(()),
+        };
+        if let Some(recording) = self.stop() {
+            recording.save(&path)?;
+            log::info!(
+                "Saved {} frames of input to {}",
+                recording.frames.len(),
+                path.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::GamepadAxis;
+
+    #[test]
+    fn playback_matches_recording() {
+        let mut replay = InputReplay::new();
+        let mut time = Time::new();
+        let mut input = Input::new();
+        replay.record(None);
+
+        let press = InputEvent::Key {
+            key: KeyCode::Space,
+            pressed: true,
+        };
+        assert!(replay.capture(&press));
+        press.apply(&mut input);
+        // Gamepads are polled, so they're picked up in begin_frame
+        let stick = GamepadEvent::AxisChanged(GamepadAxis::LeftStickX, 0.75);
+        input.process_gamepad(stick);
+        replay.begin_frame(&mut time, &mut input);
+        input.end_frame();
+        replay.begin_frame(&mut time, &mut input);
+        let recording = replay.stop().unwrap();
+        assert_eq!(
+            recording.frames,
+            [vec![press.clone(), InputEvent::Gamepad(stick)], vec![]]
+        );
+
+        let json = serde_json::to_string(&recording).unwrap();
+        let recording: InputRecording = serde_json::from_str(&json).unwrap();
+        let seed = recording.seed;
+
+        let mut replay = InputReplay::new();
+        let mut time = Time::new();
+        let mut input = Input::new();
+        replay.play_recording(recording);
+        assert_eq!(replay.seed(), seed);
+        assert!(!replay.capture(&InputEvent::MouseMotion(1.0, 0.0)));
+
+        let (dt, events) = replay.begin_frame(&mut time, &mut input);
+        assert_eq!(dt, Duration::from_secs(1) / 60);
+        assert_eq!(events, [press, InputEvent::Gamepad(stick)]);
+        assert!(input.is_key_pressed(KeyCode::Space));
+        assert_eq!(input.gamepad_axis(GamepadAxis::LeftStickX), 0.75);
+        replay.begin_frame(&mut time, &mut input);
+        assert!(replay.is_playing());
+        replay.begin_frame(&mut time, &mut input);
+        assert!(!replay.is_playing());
+    }
+}
@@ -0,0 +1,441 @@
+use cgmath::*;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::jobs::JobPool;
+use crate::rng::Rng;
+use crate::scene::Transform;
+
+/// How many candidates [poisson_disk] tries around each point before
+/// giving up on it. 30 is what Bridson's paper uses.
+const POISSON_ATTEMPTS: u32 = 30;
+
+/// A place to put something, with the way the surface faces there
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ScatterPoint {
+    pub position: Point3<f32>,
+    pub normal: Vector3<f32>,
+}
+
+impl ScatterPoint {
+    /// A point on the ground, at height 0 facing up
+    pub fn on_ground(point: Point2<f32>) -> Self {
+        Self {
+            position: Point3::new(point.x, 0.0, point.y),
+            normal: Vector3::unit_y(),
+        }
+    }
+}
+
+/// Points in a rectangle that are all at least `radius` apart, packed
+/// about as tightly as that allows. Looks natural for trees and rocks,
+/// without the clumps and gaps of purely random points. The same `seed`
+/// gives the same points.
+pub fn poisson_disk(
+    min: Point2<f32>,
+    max: Point2<f32>,
+    radius: f32,
+    seed: u64,
+) -> Vec<Point2<f32>> {
+    let size = max - min;
+    if radius <= 0.0 || size.x <= 0.0 || size.y <= 0.0 {
+        return Vec::new();
+    }
+    let mut rng = Rng::new(seed);
+    // Cells are small enough to hold one point at most
+    let cell = radius / std::f32::consts::SQRT_2;
+    let columns = (size.x / cell).ceil() as usize;
+    let rows = (size.y / cell).ceil() as usize;
+    let mut grid = vec![usize::MAX; columns * rows];
+    let cell_of = |p: Point2<f32>| {
+        let x = (((p.x - min.x) / cell) as usize).min(columns - 1);
+        let y = (((p.y - min.y) / cell) as usize).min(rows - 1);
+        (x, y)
+    };
+
+    let mut points = Vec::new();
+    let mut active = Vec::new();
+    let first = min + Vector2::new(rng.next_f32() * size.x, rng.next_f32() * size.y);
+    let (x, y) = cell_of(first);
+    grid[y * columns + x] = 0;
+    points.push(first);
+    active.push(0);
+
+    while !active.is_empty() {
+        let slot = rng.below(active.len());
+        let center = points[active[slot]];
+        let mut found = false;
+        for _ in 0..POISSON_ATTEMPTS {
+            let angle = rng.next_f32() * std::f32::consts::TAU;
+            let distance = radius * (1.0 + rng.next_f32());
+            let candidate = center + Vector2::new(angle.cos(), angle.sin()) * distance;
+            if candidate.x < min.x
+                || candidate.y < min.y
+                || candidate.x >= max.x
+                || candidate.y >= max.y
+            {
+                continue;
+            }
+            let (x, y) = cell_of(candidate);
+            let too_close = (y.saturating_sub(2)..(y + 3).min(rows)).any(|ny| {
+                (x.saturating_sub(2)..(x + 3).min(columns)).any(|nx| {
+                    let other = grid[ny * columns + nx];
+                    other != usize::MAX && points[other].distance2(candidate) < radius * radius
+                })
+            });
+            if !too_close {
+                grid[y * columns + x] = points.len();
+                active.push(points.len());
+                points.push(candidate);
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            active.swap_remove(slot);
+        }
+    }
+    points
+}
+
+/// One point per `spacing` sized cell of a rectangle, moved up to
+/// `jitter` of a cell from its center. 0 gives a perfect grid, which with
+/// a little jitter suits city blocks and orchards.
+pub fn grid_jitter(
+    min: Point2<f32>,
+    max: Point2<f32>,
+    spacing: f32,
+    jitter: f32,
+    seed: u64,
+) -> Vec<Point2<f32>> {
+    let size = max - min;
+    if spacing <= 0.0 || size.x <= 0.0 || size.y <= 0.0 {
+        return Vec::new();
+    }
+    let mut rng = Rng::new(seed);
+    let columns = (size.x / spacing).floor() as u32;
+    let rows = (size.y / spacing).floor() as u32;
+    // Centers the grid when the size isn't a multiple of spacing
+    let start = min
+        + Vector2::new(
+            (size.x - columns as f32 * spacing) * 0.5,
+            (size.y - rows as f32 * spacing) * 0.5,
+        );
+    let mut points = Vec::with_capacity((columns * rows) as usize);
+    for row in 0..rows {
+        for column in 0..columns {
+            let offset = Vector2::new(rng.next_f32() - 0.5, rng.next_f32() - 0.5) * jitter;
+            let cell = Vector2::new(column as f32 + 0.5, row as f32 + 0.5) + offset;
+            points.push(start + cell * spacing);
+        }
+    }
+    points
+}
+
+/// Points spread over a triangle mesh that are all at least `radius`
+/// apart, with the normal of the triangle they landed on. Big triangles
+/// get more points than small ones, so the spacing follows the surface
+/// rather than the triangles.
+pub fn poisson_disk_on_surface(
+    positions: &[Point3<f32>],
+    indices: &[u32],
+    radius: f32,
+    seed: u64,
+) -> Vec<ScatterPoint> {
+    if radius <= 0.0 {
+        return Vec::new();
+    }
+    let triangles = indices
+        .chunks_exact(3)
+        .map(|t| {
+            let [a, b, c] = [0, 1, 2].map(|i| positions[t[i] as usize]);
+            let cross = (b - a).cross(c - a);
+            (a, b, c, cross)
+        })
+        .filter(|t| t.3.magnitude2() > 0.0)
+        .collect::<Vec<_>>();
+    let mut cumulative = Vec::with_capacity(triangles.len());
+    let mut area = 0.0;
+    for t in &triangles {
+        area += t.3.magnitude() * 0.5;
+        cumulative.push(area);
+    }
+    if area <= 0.0 {
+        return Vec::new();
+    }
+
+    // Throws a lot more darts than can fit and keeps the ones with room,
+    // which gets close to a proper Poisson disk without needing to walk
+    // across triangles
+    let mut rng = Rng::new(seed);
+    let candidates = ((area / (radius * radius)) * 8.0).ceil() as usize;
+    let cell = radius;
+    let cell_of = |p: Point3<f32>| (p / cell).map(|v| v.floor() as i32);
+    let mut grid: HashMap<Point3<i32>, Vec<usize>> = HashMap::new();
+    let mut points: Vec<ScatterPoint> = Vec::new();
+    for _ in 0..candidates {
+        let target = rng.next_f32() * area;
+        let index = cumulative
+            .partition_point(|&a| a < target)
+            .min(triangles.len() - 1);
+        let (a, b, c, cross) = triangles[index];
+        let (mut u, mut v) = (rng.next_f32(), rng.next_f32());
+        if u + v > 1.0 {
+            u = 1.0 - u;
+            v = 1.0 - v;
+        }
+        let position = a + (b - a) * u + (c - a) * v;
+        let home = cell_of(position);
+        let too_close = (-1..=1).any(|x| {
+            (-1..=1).any(|y| {
+                (-1..=1).any(|z| {
+                    grid.get(&(home + Vector3::new(x, y, z)))
+                        .is_some_and(|cell| {
+                            cell.iter()
+                                .any(|&i| points[i].position.distance2(position) < radius * radius)
+                        })
+                })
+            })
+        });
+        if !too_close {
+            grid.entry(home).or_default().push(points.len());
+            points.push(ScatterPoint {
+                position,
+                normal: cross.normalize(),
+            });
+        }
+    }
+    points
+}
+
+/// How much of something should be in each part of a rectangle on the
+/// ground, from 0 for none to 1 for everything. Usually painted as a
+/// grayscale image.
+#[derive(Debug, Clone)]
+pub struct DensityMask {
+    width: u32,
+    height: u32,
+    values: Vec<f32>,
+    min: Point2<f32>,
+    max: Point2<f32>,
+}
+
+impl DensityMask {
+    /// `values` go row by row, with the first row at `min.y`
+    pub fn new(
+        width: u32,
+        height: u32,
+        values: Vec<f32>,
+        min: Point2<f32>,
+        max: Point2<f32>,
+    ) -> Self {
+        assert_eq!(values.len(), (width * height) as usize);
+        assert!(width > 0 && height > 0);
+        Self {
+            width,
+            height,
+            values,
+            min,
+            max,
+        }
+    }
+
+    /// Uses the brightness of `img`, with its top row at `min.y`
+    pub fn from_image(img: &image::DynamicImage, min: Point2<f32>, max: Point2<f32>) -> Self {
+        let gray = img.to_luma32f();
+        Self::new(gray.width(), gray.height(), gray.into_raw(), min, max)
+    }
+
+    /// The density at `point`, blended between texels. Anywhere outside
+    /// the rectangle is 0.
+    pub fn sample(&self, point: Point2<f32>) -> f32 {
+        let size = self.max - self.min;
+        let uv = Vector2::new(
+            (point.x - self.min.x) / size.x,
+            (point.y - self.min.y) / size.y,
+        );
+        if !(0.0..=1.0).contains(&uv.x) || !(0.0..=1.0).contains(&uv.y) {
+            return 0.0;
+        }
+        let x = (uv.x * self.width as f32 - 0.5).max(0.0);
+        let y = (uv.y * self.height as f32 - 0.5).max(0.0);
+        let (x0, y0) = (x as u32, y as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let value = |x: u32, y: u32| self.values[(y * self.width + x) as usize];
+        let (fx, fy) = (x.fract(), y.fract());
+        let top = value(x0, y0) * (1.0 - fx) + value(x1, y0) * fx;
+        let bottom = value(x0, y1) * (1.0 - fx) + value(x1, y1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    /// Looks up a point in the world by its x and z
+    pub fn sample_ground(&self, position: Point3<f32>) -> f32 {
+        self.sample(Point2::new(position.x, position.z))
+    }
+}
+
+/// Randomly drops points, keeping each one with the chance `density`
+/// gives for it. Works with [DensityMask::sample_ground] or any function
+/// of position, like one that thins things out on steep slopes.
+pub fn thin_points(
+    points: &[ScatterPoint],
+    density: impl Fn(&ScatterPoint) -> f32,
+    seed: u64,
+) -> Vec<ScatterPoint> {
+    let mut rng = Rng::new(seed);
+    points
+        .iter()
+        .filter(|p| rng.next_f32() < density(p))
+        .copied()
+        .collect()
+}
+
+/// Turns [ScatterPoint]s into transforms for instancing, with a random
+/// spin and size so copies of the same model don't all look alike.
+///
+/// ```ignore
+/// let points = poisson_disk(Point2::new(-50.0, -50.0), Point2::new(50.0, 50.0), 2.0, 1)
+///     .into_iter()
+///     .map(ScatterPoint::on_ground)
+///     .collect::<Vec<_>>();
+/// let trees = thin_points(&points, |p| forest.sample_ground(p.position), 2);
+/// let instances = Scatter::default().instance_data(&trees);
+/// let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+///     label: Some("Trees"),
+///     contents: bytemuck::cast_slice(&instances),
+///     usage: wgpu::BufferUsages::VERTEX,
+/// });
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scatter {
+    /// Picks the scales and yaws, eg. from [crate::Rng::next_u64]
+    pub seed: u64,
+    /// Uniform scale, picked evenly from this range
+    pub scale: Range<f32>,
+    /// Spins each instance around its up axis
+    pub random_yaw: bool,
+    /// 0 keeps instances upright, like trees. 1 stands them along the
+    /// surface normal, like rocks or grass on a hillside.
+    pub align_to_normal: f32,
+    /// Moves instances along their up axis, to sink them into the ground
+    /// when negative
+    pub offset: f32,
+}
+
+impl Default for Scatter {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            scale: 0.8..1.2,
+            random_yaw: true,
+            align_to_normal: 0.0,
+            offset: 0.0,
+        }
+    }
+}
+
+impl Scatter {
+    pub fn transforms(&self, points: &[ScatterPoint]) -> Vec<Transform> {
+        let mut rng = Rng::new(self.seed);
+        points
+            .iter()
+            .map(|point| {
+                let up = Vector3::unit_y()
+                    .lerp(point.normal, self.align_to_normal.clamp(0.0, 1.0))
+                    .normalize();
+                let tilt = Quaternion::from_arc(Vector3::unit_y(), up, None);
+                let yaw = if self.random_yaw {
+                    Quaternion::from_angle_y(Rad(rng.next_f32() * std::f32::consts::TAU))
+                } else {
+                    Quaternion::one()
+                };
+                let scale = self.scale.start + (self.scale.end - self.scale.start) * rng.next_f32();
+                Transform::new(
+                    point.position.to_vec() + up * self.offset,
+                    tilt * yaw,
+                    Vector3::new(scale, scale, scale),
+                )
+            })
+            .collect()
+    }
+
+    /// Model matrices in the tutorials' InstanceRaw layout
+    pub fn instance_data(&self, points: &[ScatterPoint]) -> Vec<[[f32; 4]; 4]> {
+        JobPool::global().map(&self.transforms(points), 1024, |t| t.matrix().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closest_pair(points: &[Point3<f32>]) -> f32 {
+        let mut closest = f32::INFINITY;
+        for (i, a) in points.iter().enumerate() {
+            for b in &points[i + 1..] {
+                closest = closest.min(a.distance(*b));
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn poisson_points_keep_their_distance() {
+        let (min, max) = (Point2::new(-5.0, -5.0), Point2::new(5.0, 5.0));
+        let points = poisson_disk(min, max, 0.5, 7);
+        // A perfect hex packing fits about 460, random packing gets well
+        // over half of that
+        assert!(points.len() > 200, "only {} points", points.len());
+        assert!(points
+            .iter()
+            .all(|p| p.x >= min.x && p.y >= min.y && p.x < max.x && p.y < max.y));
+        let flat = points
+            .iter()
+            .map(|p| ScatterPoint::on_ground(*p).position)
+            .collect::<Vec<_>>();
+        assert!(closest_pair(&flat) >= 0.5);
+        assert_eq!(points, poisson_disk(min, max, 0.5, 7));
+    }
+
+    #[test]
+    fn surface_points_stay_on_the_surface() {
+        // A 4x4 quad standing up along x and y, facing +z
+        let positions = [
+            Point3::new(0.0, 0.0, 1.0),
+            Point3::new(4.0, 0.0, 1.0),
+            Point3::new(4.0, 4.0, 1.0),
+            Point3::new(0.0, 4.0, 1.0),
+        ];
+        let points = poisson_disk_on_surface(&positions, &[0, 1, 2, 0, 2, 3], 0.5, 3);
+        assert!(points.len() > 20, "only {} points", points.len());
+        for p in &points {
+            assert!((p.position.z - 1.0).abs() < 1e-5);
+            assert!(p.normal.z > 0.999);
+        }
+        let positions = points.iter().map(|p| p.position).collect::<Vec<_>>();
+        assert!(closest_pair(&positions) >= 0.5);
+    }
+
+    #[test]
+    fn density_masks_thin_points() {
+        let points = grid_jitter(Point2::new(0.0, 0.0), Point2::new(10.0, 10.0), 0.5, 0.5, 1)
+            .into_iter()
+            .map(ScatterPoint::on_ground)
+            .collect::<Vec<_>>();
+        assert_eq!(points.len(), 400);
+        // Empty on the left half, full on the right
+        let mask = DensityMask::new(
+            2,
+            1,
+            vec![0.0, 1.0],
+            Point2::new(0.0, 0.0),
+            Point2::new(10.0, 10.0),
+        );
+        assert_eq!(mask.sample(Point2::new(1.0, 5.0)), 0.0);
+        assert_eq!(mask.sample(Point2::new(9.0, 5.0)), 1.0);
+        assert_eq!(mask.sample(Point2::new(-1.0, 5.0)), 0.0);
+        let kept = thin_points(&points, |p| mask.sample_ground(p.position), 2);
+        assert!(kept.iter().all(|p| p.position.x > 2.5));
+        assert!(kept.iter().filter(|p| p.position.x > 7.5).count() == 100);
+    }
+}
@@ -0,0 +1,225 @@
+use anyhow::*;
+use cgmath::*;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Transform {
+    pub fn new(translation: Vector3<f32>, rotation: Quaternion<f32>, scale: Vector3<f32>) -> Self {
+        Self {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::zero(),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+/// Index of a [Node] in a [Scene]. Nodes are never removed, so these stay
+/// valid for the lifetime of the scene.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+pub struct Node {
+    pub name: String,
+    /// Relative to the parent node
+    pub transform: Transform,
+    /// Index into whatever list of models the demo keeps
+    pub model: Option<usize>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl Node {
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+/// A simple transform hierarchy.
+#[derive(Default)]
+pub struct Scene {
+    nodes: Vec<Node>,
+    roots: Vec<NodeId>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_node(&mut self, name: &str, transform: Transform, parent: Option<NodeId>) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            name: String::from(name),
+            transform,
+            model: None,
+            parent,
+            children: Vec::new(),
+        });
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = NodeId> {
+        (0..self.nodes.len()).map(NodeId)
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter().position(|n| n.name == name).map(NodeId)
+    }
+
+    /// Returns true if `ancestor` is `id` or one of its parents
+    pub fn is_ancestor(&self, ancestor: NodeId, id: NodeId) -> bool {
+        let mut current = Some(id);
+        while let Some(node) = current {
+            if node == ancestor {
+                return true;
+            }
+            current = self.nodes[node.0].parent;
+        }
+        false
+    }
+
+    /// Moves `id` under `parent`, or to the root if `parent` is `None`.
+    /// The local transform is left as is, so the node will move in world
+    /// space if the parent's transform is different.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> Result<()> {
+        if let Some(parent) = parent {
+            if self.is_ancestor(id, parent) {
+                bail!(
+                    "Can't parent {:?} to {:?} as it would create a cycle",
+                    self.nodes[id.0].name,
+                    self.nodes[parent.0].name
+                );
+            }
+        }
+
+        match self.nodes[id.0].parent {
+            Some(old) => self.nodes[old.0].children.retain(|c| *c != id),
+            None => self.roots.retain(|c| *c != id),
+        }
+        match parent {
+            Some(new) => self.nodes[new.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        self.nodes[id.0].parent = parent;
+
+        Ok(())
+    }
+
+    pub fn world_matrix(&self, id: NodeId) -> Matrix4<f32> {
+        let node = &self.nodes[id.0];
+        let local = node.transform.matrix();
+        match node.parent {
+            Some(parent) => self.world_matrix(parent) * local,
+            None => local,
+        }
+    }
+
+    /// World matrices for every node, indexed by [NodeId::index]
+    pub fn world_matrices(&self) -> Vec<Matrix4<f32>> {
+        let mut matrices = vec![Matrix4::identity(); self.nodes.len()];
+        let mut stack = self
+            .roots
+            .iter()
+            .map(|id| (*id, Matrix4::identity()))
+            .collect::<Vec<_>>();
+        while let Some((id, parent_matrix)) = stack.pop() {
+            let node = &self.nodes[id.0];
+            let matrix = parent_matrix * node.transform.matrix();
+            matrices[id.0] = matrix;
+            stack.extend(node.children.iter().map(|c| (*c, matrix)));
+        }
+        matrices
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reparent_rejects_cycles() {
+        let mut scene = Scene::new();
+        let a = scene.add_node("a", Transform::default(), None);
+        let b = scene.add_node("b", Transform::default(), Some(a));
+        let c = scene.add_node("c", Transform::default(), Some(b));
+
+        assert!(scene.set_parent(a, Some(c)).is_err());
+        assert!(scene.set_parent(a, Some(a)).is_err());
+
+        scene.set_parent(c, None).unwrap();
+        assert_eq!(scene.roots(), &[a, c]);
+        assert!(scene.node(b).children().is_empty());
+    }
+
+    #[test]
+    fn world_matrices_match_world_matrix() {
+        let mut scene = Scene::new();
+        let mut transform = Transform {
+            translation: Vector3::new(1.0, 2.0, 3.0),
+            ..Default::default()
+        };
+        let a = scene.add_node("a", transform, None);
+        transform.rotation = Quaternion::from_angle_y(Deg(90.0));
+        let b = scene.add_node("b", transform, Some(a));
+
+        let matrices = scene.world_matrices();
+        assert_eq!(matrices[b.index()], scene.world_matrix(b));
+        let origin = cgmath::Transform::transform_point(&matrices[b.index()], Point3::origin());
+        assert_eq!(origin, Point3::new(2.0, 4.0, 6.0));
+    }
+}
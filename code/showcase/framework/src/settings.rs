@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::bookmarks::CameraBookmark;
+
+/// User preferences that carry over between runs and demos. The framework
+/// loads them from [Settings::PATH] at startup and saves them on exit if
+/// they changed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Scales mouse look in every demo, see [crate::Input::mouse_delta]
+    pub mouse_sensitivity: f64,
+    /// Each demo's [crate::CameraBookmarks] by slot
+    pub camera_bookmarks: BTreeMap<String, BTreeMap<u8, CameraBookmark>>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mouse_sensitivity: 1.0,
+            camera_bookmarks: BTreeMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    pub const PATH: &'static str = "settings.json";
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Unable to open {}", path.display()))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("{} isn't a valid settings file", path.display()))
+    }
+
+    /// Falls back to the defaults if the file is missing or broken
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Self::default();
+        }
+        Self::load(path).unwrap_or_else(|e| {
+            log::warn!("Using default settings: {:?}", e);
+            Self::default()
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Unable to create {}", path.display()))?;
+        serde_json::to_writer_pretty(std::io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_use_defaults() {
+        let settings: Settings = serde_json::from_str("{}").unwrap();
+        assert_eq!(settings, Settings::default());
+        let settings: Settings = serde_json::from_str(r#"{"mouse_sensitivity":2.5}"#).unwrap();
+        assert_eq!(settings.mouse_sensitivity, 2.5);
+    }
+}
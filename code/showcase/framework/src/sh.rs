@@ -0,0 +1,456 @@
+use std::mem;
+
+use anyhow::*;
+use cgmath::*;
+
+use crate::texture::Cubemap;
+
+/// WGSL for reading an [ShBuffer] bound to group 2, see
+/// [ShBuffer::layout_entry]. Put it after [crate::PBR_WGSL] to get
+/// `sh_irradiance(n)` and `pbr_sh_ambient(surface, n)`.
+pub const SH_WGSL: &str = include_str!("sh.wgsl");
+
+/// Coefficients in a second order spherical harmonics probe
+pub const SH_COEFFICIENTS: usize = 9;
+
+/// How much each band is kept when convolving with a cosine lobe, divided
+/// by pi so a white sky gives an ambient color of 1
+const BAND_SCALES: [f32; 3] = [1.0, 2.0 / 3.0, 0.25];
+
+/// The light arriving at a point from every direction, squeezed into nine
+/// colors. It's a blurry but cheap stand-in for an
+/// [crate::EnvironmentMap]'s irradiance map: evaluating it is a handful of
+/// multiplies and it fits in a uniform buffer, so it works on WebGL2 and
+/// can be different for each object. Probes can be blended with
+/// [ShProbe::lerp] and added together.
+///
+/// ```ignore
+/// let sky = ProceduralSky::default();
+/// let probe = ShProbe::from_fn(32, |direction| sky.color(direction));
+/// let mut probes = ShBuffer::new(&display.device, 16);
+/// probes.update(&display.queue, &[probe]);
+///
+/// // Each object sets the light's bind group with its own probe
+/// pass.set_bind_group(2, &light_bind_group, &[probes.offset(0)]);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShProbe {
+    /// Linear RGB radiance in the real SH basis, ordered by band then by
+    /// m from -l to l
+    pub coefficients: [[f32; 3]; SH_COEFFICIENTS],
+}
+
+impl Default for ShProbe {
+    fn default() -> Self {
+        Self {
+            coefficients: [[0.0; 3]; SH_COEFFICIENTS],
+        }
+    }
+}
+
+impl ShProbe {
+    /// A probe with the same light from every direction
+    pub fn uniform(color: [f32; 3]) -> Self {
+        let mut probe = Self::default();
+        // Undo the basis function and the band scale so irradiance gives
+        // `color` back
+        probe.coefficients[0] = color.map(|c| c / 0.282095);
+        probe
+    }
+
+    /// Projects `radiance`, the light coming from each direction, by
+    /// sampling it at the texels of a cube map `size` texels a side
+    pub fn from_fn(size: u32, mut radiance: impl FnMut(Vector3<f32>) -> Vector3<f32>) -> Self {
+        let mut probe = Self::default();
+        let size = size.max(1);
+        for face in 0..6 {
+            for y in 0..size {
+                for x in 0..size {
+                    let (direction, weight) = cube_texel(face, x, y, size);
+                    probe.add_sample(direction, radiance(direction), weight);
+                }
+            }
+        }
+        probe
+    }
+
+    /// Reads `cubemap` back from the GPU and projects it. It waits for the
+    /// GPU, so it doesn't work on the web; project the source image with
+    /// [ShProbe::from_fn] there instead.
+    pub fn from_cubemap(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cubemap: &Cubemap,
+    ) -> Result<Self> {
+        let size = cubemap.size;
+        let texels = read_layers(device, queue, &cubemap.texture, size, 6)?;
+        let mut probe = Self::default();
+        for face in 0..6 {
+            for y in 0..size {
+                for x in 0..size {
+                    let color = texels[((face * size + y) * size + x) as usize];
+                    let (direction, weight) = cube_texel(face, x, y, size);
+                    probe.add_sample(direction, color, weight);
+                }
+            }
+        }
+        Ok(probe)
+    }
+
+    /// The light arriving at a surface facing `normal`, the same as
+    /// `sh_irradiance` in [SH_WGSL]. It's in the units of
+    /// [crate::DeferredLighting::ambient], ie. a white sky gives 1.
+    pub fn irradiance(&self, normal: Vector3<f32>) -> [f32; 3] {
+        let basis = basis(normal.normalize());
+        let mut result = [0.0; 3];
+        for (i, coefficient) in self.coefficients.iter().enumerate() {
+            let weight = basis[i] * BAND_SCALES[band(i)];
+            for c in 0..3 {
+                result[c] += coefficient[c] * weight;
+            }
+        }
+        result.map(|c| c.max(0.0))
+    }
+
+    /// Blends between two probes, eg. ones either side of an object
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let mut probe = *self;
+        for (a, b) in probe.coefficients.iter_mut().zip(other.coefficients.iter()) {
+            for c in 0..3 {
+                a[c] += (b[c] - a[c]) * t;
+            }
+        }
+        probe
+    }
+
+    /// Scales every coefficient, for fading a probe in or out
+    pub fn scale(&self, factor: f32) -> Self {
+        let mut probe = *self;
+        for coefficient in probe.coefficients.iter_mut() {
+            *coefficient = coefficient.map(|c| c * factor);
+        }
+        probe
+    }
+
+    pub(crate) fn add_sample(
+        &mut self,
+        direction: Vector3<f32>,
+        radiance: Vector3<f32>,
+        weight: f32,
+    ) {
+        let basis = basis(direction);
+        for (coefficient, b) in self.coefficients.iter_mut().zip(basis.iter()) {
+            coefficient[0] += radiance.x * b * weight;
+            coefficient[1] += radiance.y * b * weight;
+            coefficient[2] += radiance.z * b * weight;
+        }
+    }
+
+    /// The coefficients convolved for diffuse light, which is what the
+    /// WGSL evaluates
+    pub(crate) fn convolved(self) -> [[f32; 3]; SH_COEFFICIENTS] {
+        let mut coefficients = self.coefficients;
+        for (i, c) in coefficients.iter_mut().enumerate() {
+            *c = c.map(|c| c * BAND_SCALES[band(i)]);
+        }
+        coefficients
+    }
+
+    fn to_raw(self) -> ShProbeRaw {
+        ShProbeRaw {
+            coefficients: self.convolved().map(|[r, g, b]| [r, g, b, 0.0]),
+        }
+    }
+}
+
+impl std::ops::Add for ShProbe {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        for (a, b) in self.coefficients.iter_mut().zip(other.coefficients.iter()) {
+            for c in 0..3 {
+                a[c] += b[c];
+            }
+        }
+        self
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShProbeRaw {
+    coefficients: [[f32; 4]; SH_COEFFICIENTS],
+}
+
+/// [ShProbe]s on the GPU, one per object or per region. They share one
+/// uniform buffer and a draw picks its probe with [ShBuffer::offset] as
+/// the binding's dynamic offset.
+pub struct ShBuffer {
+    buffer: wgpu::Buffer,
+    stride: u32,
+    capacity: u32,
+}
+
+impl ShBuffer {
+    pub fn new(device: &wgpu::Device, capacity: u32) -> Self {
+        let capacity = capacity.max(1);
+        let alignment = device.limits().min_uniform_buffer_offset_alignment;
+        let stride = (mem::size_of::<ShProbeRaw>() as u32).div_ceil(alignment) * alignment;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ShBuffer"),
+            size: (stride * capacity) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            stride,
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Uploads `probes` from the start of the buffer. Any past
+    /// [ShBuffer::capacity] are left out.
+    pub fn update(&self, queue: &wgpu::Queue, probes: &[ShProbe]) {
+        self.update_range(queue, 0, probes);
+    }
+
+    /// Uploads `probes` starting at `first`, for changing a few objects'
+    /// probes as they move
+    pub fn update_range(&self, queue: &wgpu::Queue, first: u32, probes: &[ShProbe]) {
+        let count = probes
+            .len()
+            .min(self.capacity.saturating_sub(first) as usize);
+        if count == 0 {
+            return;
+        }
+        let mut data = vec![0u8; self.stride as usize * count];
+        for (i, probe) in probes[..count].iter().enumerate() {
+            let raw = probe.to_raw();
+            data[i * self.stride as usize..][..mem::size_of::<ShProbeRaw>()]
+                .copy_from_slice(bytemuck::bytes_of(&raw));
+        }
+        queue.write_buffer(
+            &self.buffer,
+            (first * self.stride) as wgpu::BufferAddress,
+            &data,
+        );
+    }
+
+    /// The dynamic offset that binds probe `index`
+    pub fn offset(&self, index: u32) -> u32 {
+        index.min(self.capacity - 1) * self.stride
+    }
+
+    /// One probe at `binding`, with a dynamic offset. [SH_WGSL] expects 6
+    /// in the light's bind group.
+    pub fn layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: wgpu::BufferSize::new(mem::size_of::<ShProbeRaw>() as _),
+            },
+            count: None,
+        }
+    }
+
+    /// The resource for [ShBuffer::layout_entry]
+    pub fn bind_group_entry(&self, binding: u32) -> wgpu::BindGroupEntry<'_> {
+        wgpu::BindGroupEntry {
+            binding,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer: &self.buffer,
+                offset: 0,
+                size: wgpu::BufferSize::new(mem::size_of::<ShProbeRaw>() as _),
+            }),
+        }
+    }
+}
+
+/// The nine real SH basis functions at unit `d`
+fn basis(d: Vector3<f32>) -> [f32; SH_COEFFICIENTS] {
+    let (x, y, z) = (d.x, d.y, d.z);
+    [
+        0.282095,
+        0.488603 * y,
+        0.488603 * z,
+        0.488603 * x,
+        1.092548 * x * y,
+        1.092548 * y * z,
+        0.315392 * (3.0 * z * z - 1.0),
+        1.092548 * x * z,
+        0.546274 * (x * x - y * y),
+    ]
+}
+
+fn band(index: usize) -> usize {
+    match index {
+        0 => 0,
+        1..=3 => 1,
+        _ => 2,
+    }
+}
+
+/// Where texel (`x`, `y`) of `face` looks, like `face_direction` in
+/// cube_face.wgsl, and the solid angle it covers
+fn cube_texel(face: u32, x: u32, y: u32, size: u32) -> (Vector3<f32>, f32) {
+    let texel = 2.0 / size as f32;
+    let s = (x as f32 + 0.5) * texel - 1.0;
+    let t = (y as f32 + 0.5) * texel - 1.0;
+    let direction = match face {
+        0 => Vector3::new(1.0, -t, -s),
+        1 => Vector3::new(-1.0, -t, s),
+        2 => Vector3::new(s, 1.0, t),
+        3 => Vector3::new(s, -1.0, -t),
+        4 => Vector3::new(s, -t, 1.0),
+        _ => Vector3::new(-s, -t, -1.0),
+    };
+    (direction.normalize(), texel_solid_angle(x, y, size))
+}
+
+/// How much of the sphere texel (`x`, `y`) of a 90 degree face `size`
+/// texels a side covers
+pub(crate) fn texel_solid_angle(x: u32, y: u32, size: u32) -> f32 {
+    let texel = 2.0 / size as f32;
+    let s = (x as f32 + 0.5) * texel - 1.0;
+    let t = (y as f32 + 0.5) * texel - 1.0;
+    let corner = |a: f32, b: f32| (a * b).atan2((a * a + b * b + 1.0).sqrt());
+    let (x0, y0) = (s - texel * 0.5, t - texel * 0.5);
+    let (x1, y1) = (s + texel * 0.5, t + texel * 0.5);
+    corner(x0, y0) - corner(x0, y1) - corner(x1, y0) + corner(x1, y1)
+}
+
+/// Reads `layers` square layers of an `Rgba16Float` texture with
+/// `COPY_SRC` back to the CPU, row by row. It waits for the GPU.
+pub(crate) fn read_layers(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    size: u32,
+    layers: u32,
+) -> Result<Vec<Vector3<f32>>> {
+    let texel_bytes = mem::size_of::<[u16; 4]>() as u32;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let bytes_per_row = (size * texel_bytes).div_ceil(align) * align;
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("ShProbe::readback"),
+        size: (bytes_per_row * size * layers) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("ShProbe::read_layers"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: Some(size),
+            },
+        },
+        wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: layers,
+        },
+    );
+    queue.submit([encoder.finish()]);
+
+    let slice = buffer.slice(..);
+    let (tx, rx) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = tx.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    rx.recv()??;
+
+    let data = slice.get_mapped_range();
+    let mut texels = Vec::with_capacity((size * size * layers) as usize);
+    for row in data.chunks(bytes_per_row as usize) {
+        for texel in row[..(size * texel_bytes) as usize].chunks_exact(texel_bytes as usize) {
+            let channel =
+                |i: usize| f16_to_f32(u16::from_ne_bytes([texel[i * 2], texel[i * 2 + 1]]));
+            texels.push(Vector3::new(channel(0), channel(1), channel(2)));
+        }
+    }
+    drop(data);
+    buffer.unmap();
+    Ok(texels)
+}
+
+/// Rounds towards zero, and flushes values too small for a normal half
+/// to 0
+pub(crate) fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    if value.is_nan() {
+        return 0x7e00;
+    }
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        return sign;
+    }
+    sign | ((exponent as u16) << 10) | ((bits >> 13) & 0x3ff) as u16
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sh_probes_match_the_sky() {
+        // A uniform white sky lights every surface the same
+        let white = ShProbe::from_fn(8, |_| Vector3::new(1.0, 1.0, 1.0));
+        for n in [
+            Vector3::unit_x(),
+            -Vector3::unit_y(),
+            Vector3::new(1.0, 1.0, 1.0),
+        ] {
+            for c in white.irradiance(n) {
+                assert!((c - 1.0).abs() < 1e-3, "{}", c);
+            }
+        }
+        assert!((ShProbe::uniform([0.5; 3]).irradiance(Vector3::unit_z())[1] - 0.5).abs() < 1e-5);
+
+        // A sky lit from above
+        let sky = ShProbe::from_fn(16, |d| Vector3::new(1.0, 1.0, 1.0) * d.y.max(0.0));
+        assert!(sky.irradiance(Vector3::unit_y())[0] > sky.irradiance(Vector3::unit_x())[0]);
+        assert!(sky.irradiance(-Vector3::unit_y())[0] < 0.1);
+
+        let gray = white.lerp(&ShProbe::default(), 0.5);
+        assert!((gray.irradiance(Vector3::unit_x())[2] - 0.5).abs() < 1e-3);
+
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        for value in [0.0, 1.0, -2.5, 0.125, 1000.0] {
+            assert_eq!(f16_to_f32(f32_to_f16(value)), value);
+        }
+        assert_eq!(mem::size_of::<ShProbeRaw>(), 144);
+        crate::shader::validate_wgsl(&format!("{}{}", crate::model::PBR_WGSL, SH_WGSL)).unwrap();
+    }
+}
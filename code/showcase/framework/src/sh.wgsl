@@ -0,0 +1,42 @@
+// Reads a probe out of an ShBuffer bound to group 2 at binding 6, after the
+// light, an EnvironmentMap and an Ssao. Each object picks its probe with
+// the binding's dynamic offset.
+
+struct ShProbe {
+    // Already convolved for diffuse light, so evaluating is a dot product
+    // per channel. w is unused.
+    coefficients: array<vec4<f32>, 9>,
+}
+
+@group(2) @binding(6)
+var<uniform> sh_probe: ShProbe;
+
+// Evaluates convolved coefficients, like an ShProbe's on the GPU, for a
+// surface facing `n`
+fn sh_evaluate(c: array<vec4<f32>, 9>, n: vec3<f32>) -> vec3<f32> {
+    let x = n.x;
+    let y = n.y;
+    let z = n.z;
+    var result = c[0].rgb * 0.282095;
+    result += c[1].rgb * (0.488603 * y);
+    result += c[2].rgb * (0.488603 * z);
+    result += c[3].rgb * (0.488603 * x);
+    result += c[4].rgb * (1.092548 * x * y);
+    result += c[5].rgb * (1.092548 * y * z);
+    result += c[6].rgb * (0.315392 * (3.0 * z * z - 1.0));
+    result += c[7].rgb * (1.092548 * x * z);
+    result += c[8].rgb * (0.546274 * (x * x - y * y));
+    return max(result, vec3<f32>(0.0));
+}
+
+// The ambient light arriving at a surface facing `n`, in the same units
+// as pbr_ambient's ambient color
+fn sh_irradiance(n: vec3<f32>) -> vec3<f32> {
+    return sh_evaluate(sh_probe.coefficients, n);
+}
+
+// pbr_ambient with the probe's light in place of a flat color. Needs the
+// Surface from PBR_WGSL or BRDF_WGSL.
+fn pbr_sh_ambient(surface: Surface, n: vec3<f32>) -> vec3<f32> {
+    return pbr_ambient(surface, sh_irradiance(n));
+}
@@ -0,0 +1,291 @@
+use thiserror::Error;
+
+/// How many lines either side of the error to keep in [ShaderError::snippet]
+const SNIPPET_CONTEXT: u32 = 2;
+
+/// A WGSL compile error with the position it was reported at.
+#[derive(Error, Debug, Clone)]
+#[error("{message}")]
+pub struct ShaderError {
+    /// Usually the shader module's label or file name
+    pub label: Option<String>,
+    /// The full error as formatted by naga, including the source snippet
+    pub message: String,
+    /// 1-based line the error points at, if naga reported one
+    pub line: Option<u32>,
+    /// 1-based column (in bytes) the error points at
+    pub column: Option<u32>,
+    /// The lines around the error along with their line numbers
+    pub snippet: Vec<(u32, String)>,
+}
+
+impl ShaderError {
+    pub(crate) fn new(
+        message: String,
+        source: Option<&str>,
+        location: Option<naga::SourceLocation>,
+    ) -> Self {
+        let line = location.map(|l| l.line_number);
+        let snippet = match (source, line) {
+            (Some(source), Some(line)) => {
+                let first = line.saturating_sub(SNIPPET_CONTEXT).max(1);
+                source
+                    .lines()
+                    .enumerate()
+                    .map(|(i, text)| (i as u32 + 1, text.to_string()))
+                    .skip(first as usize - 1)
+                    .take((line - first + SNIPPET_CONTEXT + 1) as usize)
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        Self {
+            label: None,
+            message,
+            line,
+            column: location.map(|l| l.line_position),
+            snippet,
+        }
+    }
+
+    pub fn with_label(mut self, label: Option<&str>) -> Self {
+        self.label = label.map(String::from);
+        self
+    }
+
+    /// Where the error happened as `label:line:column`
+    pub fn summary(&self) -> String {
+        let label = self.label.as_deref().unwrap_or("shader");
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => format!("{}:{}:{}", label, line, column),
+            _ => label.to_string(),
+        }
+    }
+
+    /// The offending lines with a caret under the error, eg.
+    ///
+    /// ```text
+    ///  11 | let x = foo;
+    ///     |         ^
+    /// ```
+    pub fn source_context(&self) -> String {
+        let mut out = String::new();
+        for (number, text) in &self.snippet {
+            out += &format!("{:>4} | {}\n", number, text);
+            if Some(*number) == self.line {
+                let column = self.column.unwrap_or(1).max(1) as usize;
+                out += &format!("     | {}^\n", " ".repeat(column - 1));
+            }
+        }
+        out
+    }
+}
+
+/// Parses and validates `source` with naga. wgpu treats an invalid shader
+/// as a fatal error, so code that compiles user supplied WGSL at runtime
+/// should check it with this first.
+pub fn validate_wgsl(source: &str) -> Result<naga::Module, ShaderError> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| {
+        ShaderError::new(e.emit_to_string(source), Some(source), e.location(source))
+    })?;
+    validate_module(&module, source)?;
+    Ok(module)
+}
+
+/// Parses and validates GLSL for one `stage`. Entry points are always
+/// called `"main"`, which is what [crate::RenderPipelineBuilder] defaults
+/// to.
+#[cfg(feature = "glsl")]
+pub fn validate_glsl(source: &str, stage: wgpu::ShaderStages) -> Result<naga::Module, ShaderError> {
+    let stage = match stage {
+        wgpu::ShaderStages::VERTEX => naga::ShaderStage::Vertex,
+        wgpu::ShaderStages::FRAGMENT => naga::ShaderStage::Fragment,
+        wgpu::ShaderStages::COMPUTE => naga::ShaderStage::Compute,
+        _ => {
+            let message = format!("GLSL is compiled one stage at a time, not {:?}", stage);
+            return Err(ShaderError::new(message, None, None));
+        }
+    };
+    let module = naga::front::glsl::Frontend::default()
+        .parse(&naga::front::glsl::Options::from(stage), source)
+        .map_err(|e| {
+            let location = e.errors.first().and_then(|e| e.location(source));
+            ShaderError::new(e.emit_to_string(source), Some(source), location)
+        })?;
+    validate_module(&module, source)?;
+    Ok(module)
+}
+
+/// Parses and validates a compiled SPIR-V binary, such as the output of
+/// glslc or glslangValidator
+#[cfg(feature = "spirv")]
+pub fn validate_spirv(data: &[u8]) -> Result<naga::Module, ShaderError> {
+    let module = naga::front::spv::parse_u8_slice(data, &Default::default())
+        .map_err(|e| ShaderError::new(format!("Invalid SPIR-V: {}", e), None, None))?;
+    // There's no source for naga to point into
+    validate_module(&module, "")?;
+    Ok(module)
+}
+
+fn validate_module(module: &naga::Module, source: &str) -> Result<(), ShaderError> {
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(module)
+    .map_err(|e| ShaderError::new(e.emit_to_string(source), Some(source), e.location(source)))?;
+    Ok(())
+}
+
+/// A shader module descriptor for GLSL, to go in
+/// [crate::RenderPipelineBuilder] next to WGSL ones. It's translated by
+/// naga up front, so errors come back here rather than from wgpu.
+///
+/// ```ignore
+/// let pipeline = RenderPipelineBuilder::new()
+///     .layout(&layout)
+///     .vertex_shader(glsl_module("shader.vert", include_str!("shader.vert"), wgpu::ShaderStages::VERTEX)?)
+///     .fragment_shader(glsl_module("shader.frag", include_str!("shader.frag"), wgpu::ShaderStages::FRAGMENT)?)
+///     // ...
+///     .build(&display.device)?;
+/// ```
+#[cfg(feature = "glsl")]
+pub fn glsl_module<'a>(
+    label: &'a str,
+    source: &str,
+    stage: wgpu::ShaderStages,
+) -> Result<wgpu::ShaderModuleDescriptor<'a>, ShaderError> {
+    let module = validate_glsl(source, stage).map_err(|e| e.with_label(Some(label)))?;
+    Ok(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Naga(std::borrow::Cow::Owned(module)),
+    })
+}
+
+/// Like [glsl_module], for SPIR-V that's already been compiled
+#[cfg(feature = "spirv")]
+pub fn spirv_module<'a>(
+    label: &'a str,
+    data: &[u8],
+) -> Result<wgpu::ShaderModuleDescriptor<'a>, ShaderError> {
+    let module = validate_spirv(data).map_err(|e| e.with_label(Some(label)))?;
+    Ok(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Naga(std::borrow::Cow::Owned(module)),
+    })
+}
+
+/// Validates a shader module descriptor if it contains WGSL or a naga
+/// module. Other kinds of shader source are passed through as is.
+pub fn validate_shader_module(desc: &wgpu::ShaderModuleDescriptor) -> Result<(), ShaderError> {
+    match &desc.source {
+        wgpu::ShaderSource::Wgsl(source) => {
+            validate_wgsl(source).map_err(|e| e.with_label(desc.label))?;
+        }
+        #[cfg(any(feature = "glsl", feature = "spirv"))]
+        wgpu::ShaderSource::Naga(module) => {
+            validate_module(module, "").map_err(|e| e.with_label(desc.label))?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Runs `f` inside a validation error scope, returning any error wgpu
+/// reported instead of letting the default handler panic. Browsers don't
+/// let the main thread wait for the scope, so on the web `f` runs without
+/// one and errors go to wgpu's uncaptured error handler instead.
+pub fn catch_validation_errors<T>(
+    device: &wgpu::Device,
+    f: impl FnOnce() -> T,
+) -> Result<T, ShaderError> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let result = f();
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(error) => Err(ShaderError::new(error.to_string(), None, None)),
+            None => Ok(result),
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = device;
+        Ok(f())
+    }
+}
+
+/// Holds the last pipeline that compiled successfully. Rebuilding with
+/// broken shader code stores the error instead of replacing the pipeline,
+/// so the demo keeps running while the error is shown to the user.
+pub struct FallbackPipeline {
+    pipeline: wgpu::RenderPipeline,
+    error: Option<ShaderError>,
+}
+
+impl FallbackPipeline {
+    pub fn new(pipeline: wgpu::RenderPipeline) -> Self {
+        Self {
+            pipeline,
+            error: None,
+        }
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    /// The error from the last rebuild, if it failed
+    pub fn error(&self) -> Option<&ShaderError> {
+        self.error.as_ref()
+    }
+
+    /// Swaps in the new pipeline if it built successfully. Returns true
+    /// if the pipeline was replaced.
+    pub fn update(&mut self, result: anyhow::Result<wgpu::RenderPipeline>) -> bool {
+        match result {
+            Ok(pipeline) => {
+                self.pipeline = pipeline;
+                self.error = None;
+                true
+            }
+            Err(e) => {
+                let error = match e.downcast::<ShaderError>() {
+                    Ok(e) => e,
+                    Err(e) => ShaderError::new(format!("{:#}", e), None, None),
+                };
+                log::error!("{}: {}", error.summary(), error.message);
+                self.error = Some(error);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_points_at_offending_line() {
+        let source = "fn a() {}\nfn b() {}\nfn c() -> f32 {\n    return foo;\n}\n";
+        let error = validate_wgsl(source).unwrap_err();
+        assert_eq!(error.line, Some(4));
+        assert_eq!(error.snippet.first().map(|s| s.0), Some(2));
+        assert_eq!(error.snippet.last().map(|s| s.0), Some(5));
+        assert!(error.source_context().contains("   4 |     return foo;"));
+    }
+
+    #[cfg(feature = "glsl")]
+    #[test]
+    fn glsl_compiles_to_naga() {
+        let source = "#version 450\nlayout(location=0) in vec3 a_position;\nvoid main() {\n    gl_Position = vec4(a_position, 1.0);\n}\n";
+        let module = validate_glsl(source, wgpu::ShaderStages::VERTEX).unwrap();
+        assert_eq!(module.entry_points[0].name, "main");
+
+        let broken = source.replace("a_position, 1.0", "b_position, 1.0");
+        let error = validate_glsl(&broken, wgpu::ShaderStages::VERTEX).unwrap_err();
+        assert_eq!(error.line, Some(4));
+        assert!(validate_glsl(source, wgpu::ShaderStages::VERTEX_FRAGMENT).is_err());
+    }
+}
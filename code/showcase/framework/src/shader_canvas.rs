@@ -5,18 +5,61 @@
 //! - [ ] Drawing to texture (maybe have the render pass decide this?)
 //! - [ ] Saving to file
 
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 use thiserror::Error;
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
+use crate::hot_reload::FileWatcher;
+use crate::reflection::{reflect_uniform, UniformField, UniformLayout, UniformScalar};
+use crate::shader::{catch_validation_errors, validate_wgsl, ShaderError};
+
+/// Fragment shaders can declare their own uniform struct at
+/// `@group(1) @binding(0)`. The canvas reflects its fields so they can
+/// be tweaked at runtime without any extra Rust code.
+pub const CANVAS_UNIFORM_GROUP: u32 = 1;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct SimulationData {
-    clear_color: [f32; 4],
-    canvas_size: [f32; 2],
-    mouse_pos: [f32; 2],
-    time: f32,
-    delta_time: f32,
+pub(crate) struct SimulationData {
+    pub(crate) clear_color: [f32; 4],
+    pub(crate) canvas_size: [f32; 2],
+    pub(crate) mouse_pos: [f32; 2],
+    pub(crate) time: f32,
+    pub(crate) delta_time: f32,
+    // WGSL rounds the struct up to a multiple of 16 bytes
+    _padding: [f32; 2],
+}
+
+impl SimulationData {
+    pub(crate) fn new(canvas_size: [f32; 2], clear_color: [f32; 4]) -> Self {
+        Self {
+            time: 0.0,
+            delta_time: 0.0,
+            mouse_pos: [0.0; 2],
+            canvas_size,
+            clear_color,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Tracks the time since the first frame for [SimulationData].
+#[derive(Debug, Default)]
+pub(crate) struct SimulationClock {
+    start_time: Option<Instant>,
+    last_time: Option<Instant>,
+}
+
+impl SimulationClock {
+    pub(crate) fn tick(&mut self, data: &mut SimulationData) {
+        let current_time = Instant::now();
+        let start_time = *self.start_time.get_or_insert(current_time);
+        let last_time = self.last_time.unwrap_or(current_time);
+        self.last_time = Some(current_time);
+        data.time = (current_time - start_time).as_secs_f32();
+        data.delta_time = (current_time - last_time).as_secs_f32();
+    }
 }
 
 #[derive(Error, Debug)]
@@ -27,18 +70,258 @@ pub enum ShaderBuildError {
     InvalidFragmentShader,
     #[error("Please supply a valid display format")]
     InvalidDisplayFormat,
+    #[error("Please supply a valid compute shader")]
+    InvalidComputeShader,
+    #[error(transparent)]
+    Compile(#[from] ShaderError),
+    #[error(transparent)]
+    Pipeline(#[from] anyhow::Error),
+}
+
+/// The values of the user's custom uniform struct, see [CANVAS_UNIFORM_GROUP].
+pub struct CanvasUniforms {
+    layout: UniformLayout,
+    data: Vec<u32>,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    dirty: bool,
+}
+
+impl CanvasUniforms {
+    /// Values of fields that exist in `previous` with the same type are
+    /// carried over so editing a shader doesn't reset everything.
+    fn new(
+        device: &wgpu::Device,
+        layout: UniformLayout,
+        previous: Option<&CanvasUniforms>,
+    ) -> Self {
+        // WebGL needs uniforms to be 16 byte aligned
+        let size = (layout.size as usize).max(16).div_ceil(16) * 16;
+        let mut data = vec![0u32; size / 4];
+        if let Some(previous) = previous {
+            for field in &layout.fields {
+                let old = previous.layout.fields.iter().find(|f| {
+                    f.name == field.name
+                        && f.scalar == field.scalar
+                        && f.components == field.components
+                });
+                if let Some(old) = old {
+                    data[words(field)].copy_from_slice(&previous.data[words(old)]);
+                }
+            }
+        }
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("CanvasUniforms::buffer"),
+            contents: bytemuck::cast_slice(&data),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("CanvasUniforms::bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("CanvasUniforms::bind_group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            layout,
+            data,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            dirty: false,
+        }
+    }
+
+    pub fn layout(&self) -> &UniformLayout {
+        &self.layout
+    }
+
+    pub fn fields(&self) -> &[UniformField] {
+        &self.layout.fields
+    }
+
+    /// The components of the field at `index` if it's a float or vector
+    /// of floats.
+    pub fn floats_mut(&mut self, index: usize) -> Option<&mut [f32]> {
+        self.field_mut(index, UniformScalar::Float)
+            .map(bytemuck::cast_slice_mut)
+    }
+
+    pub fn sints_mut(&mut self, index: usize) -> Option<&mut [i32]> {
+        self.field_mut(index, UniformScalar::Sint)
+            .map(bytemuck::cast_slice_mut)
+    }
+
+    pub fn uints_mut(&mut self, index: usize) -> Option<&mut [u32]> {
+        self.field_mut(index, UniformScalar::Uint)
+    }
+
+    /// Sets a float field by name. Returns false if there's no float field
+    /// with that name.
+    pub fn set_floats(&mut self, name: &str, values: &[f32]) -> bool {
+        let index = match self.layout.fields.iter().position(|f| f.name == name) {
+            Some(index) => index,
+            None => return false,
+        };
+        match self.floats_mut(index) {
+            Some(floats) => {
+                let n = floats.len().min(values.len());
+                floats[..n].copy_from_slice(&values[..n]);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn field_mut(&mut self, index: usize, scalar: UniformScalar) -> Option<&mut [u32]> {
+        let field = self.layout.fields.get(index)?;
+        if field.scalar != scalar {
+            return None;
+        }
+        // We don't know if the caller will change anything, so assume
+        // they will.
+        self.dirty = true;
+        Some(&mut self.data[words(field)])
+    }
+
+    fn upload(&mut self, queue: &wgpu::Queue) {
+        if self.dirty {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.data));
+            self.dirty = false;
+        }
+    }
+}
+
+fn words(field: &UniformField) -> std::ops::Range<usize> {
+    let start = field.offset as usize / 4;
+    start..start + field.components as usize
 }
 
 pub struct ShaderCanvas {
     pipeline: wgpu::RenderPipeline,
-    start_time: Option<Instant>,
-    last_time: Option<Instant>,
+    simulation_bind_group_layout: wgpu::BindGroupLayout,
+    uniforms: Option<CanvasUniforms>,
+    vert_module: wgpu::ShaderModule,
+    display_format: wgpu::TextureFormat,
+    fragment_source: Option<String>,
+    fragment_file: Option<(PathBuf, FileWatcher)>,
+    clock: SimulationClock,
     simulation_data: SimulationData,
     simulation_data_buffer: wgpu::Buffer,
     simulation_bind_group: wgpu::BindGroup,
 }
 
 impl ShaderCanvas {
+    /// The WGSL the canvas is currently using, if it was built from WGSL
+    pub fn fragment_source(&self) -> Option<&str> {
+        self.fragment_source.as_deref()
+    }
+
+    /// Recompiles the canvas with new fragment shader code. If the code
+    /// doesn't compile the canvas keeps using the previous shader.
+    pub fn set_fragment_source(
+        &mut self,
+        device: &wgpu::Device,
+        source: &str,
+    ) -> Result<(), ShaderError> {
+        let module = validate_wgsl(source)?;
+        let uniforms = reflect_uniform(&module, CANVAS_UNIFORM_GROUP, 0)
+            .map(|layout| CanvasUniforms::new(device, layout, self.uniforms.as_ref()));
+        let pipeline = catch_validation_errors(device, || {
+            let frag_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("ShaderCanvas::fragment_shader"),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            let pipeline_layout = create_pipeline_layout(
+                device,
+                None,
+                &self.simulation_bind_group_layout,
+                uniforms.as_ref(),
+            );
+            create_pipeline(
+                device,
+                None,
+                &pipeline_layout,
+                &self.vert_module,
+                &frag_module,
+                self.display_format,
+            )
+        })?;
+        self.pipeline = pipeline;
+        self.uniforms = uniforms;
+        self.fragment_source = Some(source.to_string());
+        Ok(())
+    }
+
+    /// Loads the fragment shader from a WGSL file, and reloads it from
+    /// [ShaderCanvas::maintain] whenever the file changes
+    pub fn watch_fragment_file<P: AsRef<Path>>(
+        &mut self,
+        device: &wgpu::Device,
+        path: P,
+    ) -> Result<(), ShaderError> {
+        let path = path.as_ref().to_path_buf();
+        let mut watcher = FileWatcher::new();
+        watcher.watch(&path);
+        self.fragment_file = Some((path, watcher));
+        self.reload_fragment_file(device)
+    }
+
+    fn reload_fragment_file(&mut self, device: &wgpu::Device) -> Result<(), ShaderError> {
+        let path = match &self.fragment_file {
+            Some((path, _)) => path.clone(),
+            None => return Ok(()),
+        };
+        let label = path.display().to_string();
+        let source = std::fs::read_to_string(&path).map_err(|e| {
+            ShaderError::new(format!("Unable to read {}: {}", label, e), None, None)
+        })?;
+        self.set_fragment_source(device, &source)
+            .map_err(|e| e.with_label(Some(&label)))
+    }
+
+    /// Reloads the file from [ShaderCanvas::watch_fragment_file] if it
+    /// changed. A file that doesn't compile leaves the last working shader
+    /// running, and the error is logged and returned.
+    pub fn maintain(&mut self, device: &wgpu::Device) -> Option<Result<(), ShaderError>> {
+        let (_, watcher) = self.fragment_file.as_mut()?;
+        if !watcher.poll() {
+            return None;
+        }
+        let result = self.reload_fragment_file(device);
+        match &result {
+            Ok(()) => log::info!("Reloaded canvas shader"),
+            Err(e) => log::error!("{}: {}", e.summary(), e.message),
+        }
+        Some(result)
+    }
+
+    /// The fragment shader's custom uniforms, if it declares any
+    pub fn uniforms(&self) -> Option<&CanvasUniforms> {
+        self.uniforms.as_ref()
+    }
+
+    pub fn uniforms_mut(&mut self) -> Option<&mut CanvasUniforms> {
+        self.uniforms.as_mut()
+    }
+
     pub fn input(&mut self, mouse_x: f32, mouse_y: f32) {
         self.simulation_data.mouse_pos[0] = mouse_x;
         self.simulation_data.mouse_pos[1] = mouse_y;
@@ -57,19 +340,8 @@ impl ShaderCanvas {
         width: f32,
         height: f32,
     ) {
-        let current_time = Instant::now();
-        let start_time = match self.start_time {
-            Some(t) => t,
-            None => {
-                let t = current_time;
-                self.start_time = Some(t);
-                t
-            }
-        };
-        let last_time = self.last_time.unwrap_or(current_time);
-        self.last_time = Some(current_time);
-        self.simulation_data.time = (current_time - start_time).as_secs_f32();
-        self.simulation_data.delta_time = (current_time - last_time).as_secs_f32();
+        crate::cpu_scope!("ShaderCanvas::render");
+        self.clock.tick(&mut self.simulation_data);
         self.simulation_data.canvas_size[0] = width;
         self.simulation_data.canvas_size[1] = height;
         queue.write_buffer(
@@ -77,6 +349,9 @@ impl ShaderCanvas {
             0,
             bytemuck::cast_slice(&[self.simulation_data]),
         );
+        if let Some(uniforms) = &mut self.uniforms {
+            uniforms.upload(queue);
+        }
 
         let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Shader Canvas Render Pass"),
@@ -93,6 +368,9 @@ impl ShaderCanvas {
             timestamp_writes: None,
         });
         pass.set_bind_group(0, &self.simulation_bind_group, &[]);
+        if let Some(uniforms) = &self.uniforms {
+            pass.set_bind_group(CANVAS_UNIFORM_GROUP, &uniforms.bind_group, &[]);
+        }
         pass.set_pipeline(&self.pipeline);
         pass.draw(0..6, 0..1);
     }
@@ -157,13 +435,7 @@ impl<'a> ShaderCanvasBuilder<'a> {
             .take()
             .ok_or(ShaderBuildError::InvalidVertexShader)?;
 
-        let simulation_data = SimulationData {
-            time: 0.0,
-            delta_time: 0.0,
-            mouse_pos: [0.0; 2],
-            canvas_size: self.canvas_size,
-            clear_color: self.clear_color,
-        };
+        let simulation_data = SimulationData::new(self.canvas_size, self.clear_color);
         let simulation_data_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: self.label,
             contents: bytemuck::cast_slice(&[simulation_data]),
@@ -196,69 +468,119 @@ impl<'a> ShaderCanvasBuilder<'a> {
             }],
         });
 
+        let fragment_source = match &frag_code.source {
+            wgpu::ShaderSource::Wgsl(source) => Some(source.to_string()),
+            _ => None,
+        };
+        let uniforms = fragment_source
+            .as_deref()
+            .and_then(|source| naga::front::wgsl::parse_str(source).ok())
+            .and_then(|module| reflect_uniform(&module, CANVAS_UNIFORM_GROUP, 0))
+            .map(|layout| CanvasUniforms::new(device, layout, None));
         let vert_module = device.create_shader_module(vert_code);
         let frag_module = device.create_shader_module(frag_code);
 
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: self.label,
-            bind_group_layouts: &[&simulation_bind_group_layout],
-            push_constant_ranges: &[],
-        });
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: self.label,
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                entry_point: "main",
-                module: &vert_module,
-                buffers: &[],
-                compilation_options: Default::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                entry_point: "main",
-                module: &frag_module,
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: display_format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: Default::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::POLYGON_MODE_LINE
-                // or Features::POLYGON_MODE_POINT
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            // If the pipeline will be used with a multiview render pass, this
-            // indicates how many array layers the attachments will have.
-            multiview: None,
-            // Useful for optimizing shader compilation on Android
-            cache: None,
-        });
+        let pipeline_layout = create_pipeline_layout(
+            device,
+            self.label,
+            &simulation_bind_group_layout,
+            uniforms.as_ref(),
+        );
+        let pipeline = create_pipeline(
+            device,
+            self.label,
+            &pipeline_layout,
+            &vert_module,
+            &frag_module,
+            display_format,
+        );
 
         Ok(ShaderCanvas {
             pipeline,
-            start_time: None,
-            last_time: None,
+            simulation_bind_group_layout,
+            uniforms,
+            vert_module,
+            display_format,
+            fragment_source,
+            fragment_file: None,
+            clock: SimulationClock::default(),
             simulation_data,
             simulation_data_buffer,
             simulation_bind_group,
         })
     }
 }
+
+fn create_pipeline_layout(
+    device: &wgpu::Device,
+    label: Option<&str>,
+    simulation_bind_group_layout: &wgpu::BindGroupLayout,
+    uniforms: Option<&CanvasUniforms>,
+) -> wgpu::PipelineLayout {
+    let mut bind_group_layouts = vec![simulation_bind_group_layout];
+    if let Some(uniforms) = uniforms {
+        bind_group_layouts.push(&uniforms.bind_group_layout);
+    }
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label,
+        bind_group_layouts: &bind_group_layouts,
+        push_constant_ranges: &[],
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    label: Option<&str>,
+    pipeline_layout: &wgpu::PipelineLayout,
+    vert_module: &wgpu::ShaderModule,
+    frag_module: &wgpu::ShaderModule,
+    display_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label,
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            entry_point: "main",
+            module: vert_module,
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            entry_point: "main",
+            module: frag_module,
+            targets: &[Some(wgpu::ColorTargetState {
+                format: display_format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent::REPLACE,
+                    alpha: wgpu::BlendComponent::REPLACE,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            // Setting this to anything other than Fill requires Features::POLYGON_MODE_LINE
+            // or Features::POLYGON_MODE_POINT
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        // If the pipeline will be used with a multiview render pass, this
+        // indicates how many array layers the attachments will have.
+        multiview: None,
+        // Useful for optimizing shader compilation on Android
+        cache: None,
+    })
+}
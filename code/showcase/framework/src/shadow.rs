@@ -0,0 +1,1259 @@
+use std::ops::Range;
+
+use anyhow::*;
+use cgmath::*;
+use wgpu::util::DeviceExt;
+
+use crate::camera::{Camera, Projection};
+use crate::model::{Model, ModelVertex, Vertex};
+use crate::pipeline::RenderPipelineBuilder;
+use crate::shader::catch_validation_errors;
+use crate::texture::Texture;
+
+/// WGSL for reading a [ShadowMap] bound to group 3. Put it in front of a
+/// shader to get `shadow(world_position)`.
+pub const SHADOW_WGSL: &str = concat!(
+    include_str!("shadow_sample.wgsl"),
+    include_str!("shadow_filter.wgsl")
+);
+
+/// WGSL for reading a [CascadedShadowMap] bound to group 3. It has the
+/// same `shadow(world_position)` as [SHADOW_WGSL].
+pub const SHADOW_CASCADES_WGSL: &str = concat!(
+    include_str!("shadow_cascades.wgsl"),
+    include_str!("shadow_filter.wgsl")
+);
+
+pub const MAX_CASCADES: usize = 4;
+
+/// WGSL for reading a [PointShadowMap] bound to group 3. It has the same
+/// `shadow(world_position)` as [SHADOW_WGSL].
+pub const SHADOW_POINT_WGSL: &str = concat!(
+    include_str!("shadow_point_sample.wgsl"),
+    include_str!("shadow_filter.wgsl")
+);
+
+/// How shadow edges are softened when they're sampled. Every shadow map
+/// has one in its `filter` field, which takes effect on its next
+/// `update`.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum ShadowFilter {
+    /// A single comparison, so edges follow the shadow map's texels
+    #[default]
+    Hard,
+    /// Averages a square of comparisons `kernel` texels across. Cost goes
+    /// up with the square of the kernel.
+    Pcf { kernel: u32 },
+    /// Percentage closer soft shadows. Edges get softer the further they
+    /// are from what casts them, like with a real light of `light_size`
+    /// texels across. Costs about as much as a kernel 8 [ShadowFilter::Pcf].
+    Pcss { light_size: f32 },
+}
+
+/// Keeps surfaces from shadowing themselves, which shows up as stripes or
+/// dots of shadow called acne. Too much makes shadows come loose from
+/// what casts them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShadowBias {
+    /// Added to depth in the shadow pass, in the depth format's smallest
+    /// steps
+    pub constant: i32,
+    /// Added to depth in the shadow pass, more the steeper a surface is to
+    /// the light
+    pub slope_scale: f32,
+    /// Taken off a receiver's depth, from 0 to 1, before it's compared
+    pub depth: f32,
+    /// How many texels `shadow_normal_offset` pushes a receiver along its
+    /// normal before looking it up. Works better than depth bias on
+    /// surfaces nearly edge on to the light.
+    pub normal_offset: f32,
+}
+
+impl Default for ShadowBias {
+    fn default() -> Self {
+        Self {
+            constant: 2,
+            slope_scale: 2.0,
+            depth: 0.0,
+            normal_offset: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowFilterUniform {
+    mode: u32,
+    kernel: u32,
+    light_size: f32,
+    texel_size: f32,
+    depth_bias: f32,
+    normal_offset: f32,
+}
+
+impl ShadowFilter {
+    fn uniform(self, size: u32, bias: ShadowBias) -> ShadowFilterUniform {
+        let (mode, kernel, light_size) = match self {
+            Self::Hard => (0, 1, 0.0),
+            Self::Pcf { kernel } => (1, kernel.max(1), 0.0),
+            Self::Pcss { light_size } => (2, 1, light_size.max(0.0)),
+        };
+        ShadowFilterUniform {
+            mode,
+            kernel,
+            light_size,
+            texel_size: 1.0 / size as f32,
+            depth_bias: bias.depth,
+            normal_offset: bias.normal_offset,
+        }
+    }
+}
+
+/// Remaps depth from -1 to 1 into 0 to 1
+#[rustfmt::skip]
+const DEPTH_TO_WGPU: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+// look_to_rh can't use an up vector parallel to the view
+fn light_up(direction: Vector3<f32>) -> Vector3<f32> {
+    if direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    }
+}
+
+/// Moves `center` sideways, as the light sees it, onto a whole number of
+/// texels of a `size` shadow map covering `radius`. Otherwise a moving
+/// camera moves the shadow map by fractions of a texel, which makes shadow
+/// edges shimmer.
+pub fn snap_to_texels(
+    direction: Vector3<f32>,
+    center: Point3<f32>,
+    radius: f32,
+    size: u32,
+) -> Point3<f32> {
+    let direction = direction.normalize();
+    // The same axes directional_light_matrix ends up with
+    let right = direction.cross(light_up(direction)).normalize();
+    let up = right.cross(direction);
+    let texel = radius * 2.0 / size.max(1) as f32;
+    let offset = center.to_vec();
+    let (x, y) = (offset.dot(right), offset.dot(up));
+    center + right * ((x / texel).round() * texel - x) + up * ((y / texel).round() * texel - y)
+}
+
+/// Rounds a fitted radius up a little. It only really changes with the
+/// projection, but float error would nudge it every frame and undo
+/// [snap_to_texels].
+fn stable_radius(radius: f32) -> f32 {
+    (radius * 16.0).ceil() / 16.0
+}
+
+/// [directional_light_matrix] with `center` snapped by [snap_to_texels]
+fn snapped_light_matrix(
+    direction: Vector3<f32>,
+    center: Point3<f32>,
+    radius: f32,
+    size: u32,
+) -> Matrix4<f32> {
+    let center = snap_to_texels(direction, center, radius, size);
+    directional_light_matrix(direction, center, radius)
+}
+
+/// An orthographic view along `direction` that covers a sphere of
+/// `radius` around `center`
+pub fn directional_light_matrix(
+    direction: Vector3<f32>,
+    center: Point3<f32>,
+    radius: f32,
+) -> Matrix4<f32> {
+    let direction = direction.normalize();
+    let up = light_up(direction);
+    let eye = center - direction * radius;
+    let view = Matrix4::look_to_rh(eye, direction, up);
+    let proj = ortho(-radius, radius, -radius, radius, 0.0, radius * 2.0);
+    DEPTH_TO_WGPU * proj * view
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    view_proj: [[f32; 4]; 4],
+}
+
+/// A depth map of the scene from a directional light. Draw into it with
+/// [ShadowMap::begin_pass] and [DrawShadow], then bind
+/// [ShadowMap::bind_group] to group 3 of a pipeline whose shader starts
+/// with [SHADOW_WGSL].
+///
+/// ```ignore
+/// let mut shadow = ShadowMap::new(&display.device, 2048, std::mem::size_of::<InstanceRaw>() as _)?;
+/// shadow.direction = (-1.0, -2.0, -0.5).into();
+///
+/// // In Demo::render
+/// shadow.fit(&self.camera, &self.projection);
+/// shadow.update(&display.queue);
+/// {
+///     let mut pass = shadow.begin_pass(&mut encoder);
+///     pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+///     pass.draw_shadow_model_instanced(&self.model, 0..self.instances.len() as u32);
+/// }
+/// // Then the main pass with shadow.bind_group at group 3
+/// ```
+pub struct ShadowMap {
+    /// The way the light is shining
+    pub direction: Vector3<f32>,
+    /// The middle of the area that gets shadows
+    pub center: Point3<f32>,
+    /// How far from `center` shadows reach. Smaller gives sharper shadows.
+    pub radius: f32,
+    /// How far from the camera [ShadowMap::fit] gives shadows. `None` uses
+    /// the projection's far plane.
+    pub max_distance: Option<f32>,
+    pub filter: ShadowFilter,
+    /// For sampling the shadow map in the main pass
+    pub layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    bias: ShadowBias,
+    instance_stride: wgpu::BufferAddress,
+    texture: Texture<'static>,
+    uniform: wgpu::Buffer,
+    filter_uniform: wgpu::Buffer,
+    light_layout: wgpu::BindGroupLayout,
+    light_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowMap {
+    /// `instance_stride` is the size of the instance data in vertex buffer
+    /// 1, which needs the model matrix at locations 5 to 8
+    pub fn new(
+        device: &wgpu::Device,
+        size: u32,
+        instance_stride: wgpu::BufferAddress,
+    ) -> Result<Self> {
+        let texture = depth_texture(device, "ShadowMap::texture", size, 1);
+        let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("ShadowMap::uniform"),
+            contents: bytemuck::cast_slice(&[ShadowUniform {
+                view_proj: Matrix4::identity().into(),
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let light_layout = light_layout(device);
+        let light_bind_group = light_bind_group(device, &light_layout, &uniform);
+        let filter_uniform = filter_uniform(device);
+        let layout = sampling_layout(device, "ShadowMap::layout", wgpu::TextureViewDimension::D2);
+        let bind_group = sampling_bind_group(device, &layout, &uniform, &filter_uniform, &texture);
+        let bias = ShadowBias::default();
+        let pipeline = depth_pipeline(device, &light_layout, instance_stride, bias)?;
+
+        Ok(Self {
+            direction: Vector3::new(-1.0, -1.0, -1.0),
+            center: Point3::origin(),
+            radius: 20.0,
+            max_distance: None,
+            filter: ShadowFilter::default(),
+            layout,
+            bind_group,
+            bias,
+            instance_stride,
+            texture,
+            uniform,
+            filter_uniform,
+            light_layout,
+            light_bind_group,
+            pipeline,
+        })
+    }
+
+    /// The light's matrix, with [ShadowMap::center] snapped to the shadow
+    /// map's texels so shadow edges hold still as it moves
+    pub fn view_proj(&self) -> Matrix4<f32> {
+        snapped_light_matrix(self.direction, self.center, self.radius, self.resolution())
+    }
+
+    pub fn bias(&self) -> ShadowBias {
+        self.bias
+    }
+
+    /// Rebuilds the depth pipeline, since its part of the bias is baked
+    /// into it. The rest takes effect on the next [ShadowMap::update].
+    pub fn set_bias(&mut self, device: &wgpu::Device, bias: ShadowBias) -> Result<()> {
+        if bias.constant != self.bias.constant || bias.slope_scale != self.bias.slope_scale {
+            self.pipeline = depth_pipeline(device, &self.light_layout, self.instance_stride, bias)?;
+        }
+        self.bias = bias;
+        Ok(())
+    }
+
+    /// The width and height of the shadow map in texels
+    pub fn resolution(&self) -> u32 {
+        self.texture.texture.width()
+    }
+
+    /// Replaces the depth texture and [ShadowMap::bind_group]. The new one
+    /// is empty until the next shadow pass.
+    pub fn set_resolution(&mut self, device: &wgpu::Device, size: u32) {
+        self.texture = depth_texture(device, "ShadowMap::texture", size, 1);
+        self.bind_group = sampling_bind_group(
+            device,
+            &self.layout,
+            &self.uniform,
+            &self.filter_uniform,
+            &self.texture,
+        );
+    }
+
+    /// Sets [ShadowMap::center] and [ShadowMap::radius] to cover what the
+    /// camera can see, up to [ShadowMap::max_distance], instead of tuning
+    /// them by hand. Call it before [ShadowMap::update] whenever the camera
+    /// or light moves.
+    pub fn fit(&mut self, camera: &Camera, projection: &Projection) {
+        let near = projection.znear();
+        let far = self
+            .max_distance
+            .map_or(projection.zfar(), |d| d.min(projection.zfar()));
+        let (center, radius) = frustum_slice_sphere(
+            camera.calc_matrix(),
+            projection.fovy(),
+            projection.aspect(),
+            near,
+            far,
+        );
+        self.center = center;
+        self.radius = stable_radius(radius);
+    }
+
+    /// The depth texture, for debug views
+    pub fn texture(&self) -> &Texture<'static> {
+        &self.texture
+    }
+
+    /// Uploads the light's matrix, the filter and the bias. Call it before
+    /// the shadow pass whenever any of them have changed.
+    pub fn update(&self, queue: &wgpu::Queue) {
+        let uniform = ShadowUniform {
+            view_proj: self.view_proj().into(),
+        };
+        queue.write_buffer(&self.uniform, 0, bytemuck::cast_slice(&[uniform]));
+        write_filter(
+            queue,
+            &self.filter_uniform,
+            self.filter,
+            self.bias,
+            &self.texture,
+        );
+    }
+
+    /// Clears the shadow map and starts a pass with the depth only
+    /// pipeline and the light bound. Instance data goes in vertex buffer 1.
+    pub fn begin_pass<'a>(&'a self, encoder: &'a mut wgpu::CommandEncoder) -> wgpu::RenderPass<'a> {
+        let mut pass = begin_depth_pass(encoder, "ShadowMap::begin_pass", &self.texture.view);
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.light_bind_group, &[]);
+        pass
+    }
+}
+
+/// Where the camera's view is split between cascades, as distances from
+/// the camera. `lambda` blends between even splits at 0 and logarithmic
+/// ones at 1, which give close cascades more detail.
+pub fn cascade_splits(near: f32, far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    (1..=count)
+        .map(|i| {
+            let t = i as f32 / count as f32;
+            let log = near * (far / near).powf(t);
+            let linear = near + (far - near) * t;
+            linear + (log - linear) * lambda
+        })
+        .collect()
+}
+
+/// A sphere around the part of a perspective camera's view between `near`
+/// and `far`. `view` is the camera's view matrix.
+pub fn frustum_slice_sphere(
+    view: Matrix4<f32>,
+    fovy: Rad<f32>,
+    aspect: f32,
+    near: f32,
+    far: f32,
+) -> (Point3<f32>, f32) {
+    let tan = (fovy / 2.0).tan();
+    let inverse = view.invert().unwrap_or_else(Matrix4::identity);
+    let corners = [near, far]
+        .iter()
+        .flat_map(|&d| {
+            let (h, w) = (d * tan, d * tan * aspect);
+            [
+                Point3::new(-w, -h, -d),
+                Point3::new(w, -h, -d),
+                Point3::new(-w, h, -d),
+                Point3::new(w, h, -d),
+            ]
+        })
+        .map(|p| Point3::from_homogeneous(inverse * p.to_homogeneous()))
+        .collect::<Vec<_>>();
+    let center = Point3::centroid(&corners);
+    let radius = corners
+        .iter()
+        .map(|p| p.distance(center))
+        .fold(0.0, f32::max);
+    (center, radius)
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CascadeUniform {
+    camera_view: [[f32; 4]; 4],
+    view_proj: [[[f32; 4]; 4]; MAX_CASCADES],
+    splits: [f32; MAX_CASCADES],
+    count: u32,
+    _padding: [u32; 3],
+}
+
+/// A directional light's shadows split into cascades along the camera's
+/// view, so close up shadows stay sharp without giving up on far away
+/// ones. Each cascade is a layer of the depth texture with its own pass.
+///
+/// ```ignore
+/// let mut shadow = CascadedShadowMap::new(&display.device, 2048, 4, std::mem::size_of::<InstanceRaw>() as _)?;
+///
+/// // In Demo::render
+/// shadow.update(&display.queue, &self.camera, &self.projection);
+/// for cascade in 0..shadow.cascades() {
+///     let mut pass = shadow.begin_pass(&mut encoder, cascade);
+///     pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+///     pass.draw_shadow_model_instanced(&self.model, 0..self.instances.len() as u32);
+/// }
+/// // Then the main pass with shadow.bind_group at group 3
+/// ```
+pub struct CascadedShadowMap {
+    pub direction: Vector3<f32>,
+    /// See [cascade_splits]
+    pub split_lambda: f32,
+    /// Nothing further from the camera than this gets shadows. `None` uses
+    /// the projection's far plane.
+    pub max_distance: Option<f32>,
+    pub filter: ShadowFilter,
+    pub layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    bias: ShadowBias,
+    instance_stride: wgpu::BufferAddress,
+    texture: Texture<'static>,
+    layer_views: Vec<wgpu::TextureView>,
+    uniform: wgpu::Buffer,
+    filter_uniform: wgpu::Buffer,
+    cascade_uniforms: Vec<wgpu::Buffer>,
+    light_layout: wgpu::BindGroupLayout,
+    cascade_bind_groups: Vec<wgpu::BindGroup>,
+    pipeline: wgpu::RenderPipeline,
+    splits: Vec<f32>,
+}
+
+impl CascadedShadowMap {
+    /// `cascades` is clamped to 1 to [MAX_CASCADES]. See [ShadowMap::new]
+    /// for `instance_stride`.
+    pub fn new(
+        device: &wgpu::Device,
+        size: u32,
+        cascades: usize,
+        instance_stride: wgpu::BufferAddress,
+    ) -> Result<Self> {
+        let cascades = cascades.clamp(1, MAX_CASCADES);
+        let texture = depth_texture(device, "CascadedShadowMap::texture", size, cascades as u32);
+        let layer_views = layer_views(&texture, "CascadedShadowMap::layer_view", cascades as u32);
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("CascadedShadowMap::uniform"),
+            size: std::mem::size_of::<CascadeUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let light_layout = light_layout(device);
+        let cascade_uniforms = (0..cascades)
+            .map(|_| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("CascadedShadowMap::cascade_uniform"),
+                    size: std::mem::size_of::<ShadowUniform>() as _,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect::<Vec<_>>();
+        let cascade_bind_groups = cascade_uniforms
+            .iter()
+            .map(|buffer| light_bind_group(device, &light_layout, buffer))
+            .collect();
+        let filter_uniform = filter_uniform(device);
+        let layout = sampling_layout(
+            device,
+            "CascadedShadowMap::layout",
+            wgpu::TextureViewDimension::D2Array,
+        );
+        let bind_group = sampling_bind_group(device, &layout, &uniform, &filter_uniform, &texture);
+        let bias = ShadowBias::default();
+        let pipeline = depth_pipeline(device, &light_layout, instance_stride, bias)?;
+
+        Ok(Self {
+            direction: Vector3::new(-1.0, -1.0, -1.0),
+            split_lambda: 0.75,
+            max_distance: None,
+            filter: ShadowFilter::default(),
+            layout,
+            bind_group,
+            bias,
+            instance_stride,
+            texture,
+            layer_views,
+            uniform,
+            filter_uniform,
+            cascade_uniforms,
+            light_layout,
+            cascade_bind_groups,
+            pipeline,
+            splits: Vec::new(),
+        })
+    }
+
+    pub fn cascades(&self) -> usize {
+        self.layer_views.len()
+    }
+
+    pub fn bias(&self) -> ShadowBias {
+        self.bias
+    }
+
+    /// Like [ShadowMap::set_bias]
+    pub fn set_bias(&mut self, device: &wgpu::Device, bias: ShadowBias) -> Result<()> {
+        if bias.constant != self.bias.constant || bias.slope_scale != self.bias.slope_scale {
+            self.pipeline = depth_pipeline(device, &self.light_layout, self.instance_stride, bias)?;
+        }
+        self.bias = bias;
+        Ok(())
+    }
+
+    /// The width and height of each cascade in texels
+    pub fn resolution(&self) -> u32 {
+        self.texture.texture.width()
+    }
+
+    /// Like [ShadowMap::set_resolution], for every cascade
+    pub fn set_resolution(&mut self, device: &wgpu::Device, size: u32) {
+        let layers = self.cascades() as u32;
+        self.texture = depth_texture(device, "CascadedShadowMap::texture", size, layers);
+        self.layer_views = layer_views(&self.texture, "CascadedShadowMap::layer_view", layers);
+        self.bind_group = sampling_bind_group(
+            device,
+            &self.layout,
+            &self.uniform,
+            &self.filter_uniform,
+            &self.texture,
+        );
+    }
+
+    /// The far distance of each cascade from the last [CascadedShadowMap::update]
+    pub fn splits(&self) -> &[f32] {
+        &self.splits
+    }
+
+    /// The depth texture array, for debug views
+    pub fn texture(&self) -> &Texture<'static> {
+        &self.texture
+    }
+
+    /// Fits the cascades to the camera and uploads their matrices, the
+    /// filter and the bias. Each cascade is snapped to its texels like
+    /// [ShadowMap::view_proj]. Call it before the shadow passes whenever
+    /// the camera or light moves.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera, projection: &Projection) {
+        let near = projection.znear();
+        let far = self
+            .max_distance
+            .map_or(projection.zfar(), |d| d.min(projection.zfar()));
+        let view = camera.calc_matrix();
+        self.splits = cascade_splits(near, far, self.cascades(), self.split_lambda);
+
+        let mut uniform = CascadeUniform {
+            camera_view: view.into(),
+            view_proj: [Matrix4::identity().into(); MAX_CASCADES],
+            splits: [0.0; MAX_CASCADES],
+            count: self.cascades() as u32,
+            _padding: [0; 3],
+        };
+        let mut start = near;
+        for (i, &end) in self.splits.iter().enumerate() {
+            let (center, radius) =
+                frustum_slice_sphere(view, projection.fovy(), projection.aspect(), start, end);
+            let view_proj = snapped_light_matrix(
+                self.direction,
+                center,
+                stable_radius(radius),
+                self.resolution(),
+            );
+            uniform.view_proj[i] = view_proj.into();
+            uniform.splits[i] = end;
+            queue.write_buffer(
+                &self.cascade_uniforms[i],
+                0,
+                bytemuck::cast_slice(&[ShadowUniform {
+                    view_proj: view_proj.into(),
+                }]),
+            );
+            start = end;
+        }
+        queue.write_buffer(&self.uniform, 0, bytemuck::cast_slice(&[uniform]));
+        write_filter(
+            queue,
+            &self.filter_uniform,
+            self.filter,
+            self.bias,
+            &self.texture,
+        );
+    }
+
+    /// Like [ShadowMap::begin_pass], for one cascade
+    pub fn begin_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        cascade: usize,
+    ) -> wgpu::RenderPass<'a> {
+        let mut pass = begin_depth_pass(
+            encoder,
+            "CascadedShadowMap::begin_pass",
+            &self.layer_views[cascade],
+        );
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.cascade_bind_groups[cascade], &[]);
+        pass
+    }
+}
+
+/// The view of each face of a point light's shadow map, in +X, -X, +Y,
+/// -Y, +Z, -Z order
+pub fn point_light_matrices(position: Point3<f32>, near: f32, far: f32) -> [Matrix4<f32>; 6] {
+    let proj = DEPTH_TO_WGPU * perspective(Deg(90.0), 1.0, near, far);
+    let faces = [
+        (Vector3::unit_x(), -Vector3::unit_y()),
+        (-Vector3::unit_x(), -Vector3::unit_y()),
+        (Vector3::unit_y(), Vector3::unit_z()),
+        (-Vector3::unit_y(), -Vector3::unit_z()),
+        (Vector3::unit_z(), -Vector3::unit_y()),
+        (-Vector3::unit_z(), -Vector3::unit_y()),
+    ];
+    faces.map(|(dir, up)| proj * Matrix4::look_to_rh(position, dir, up))
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PointFaceUniform {
+    view_proj: [[f32; 4]; 4],
+    light: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PointShadowUniform {
+    view_proj: [[[f32; 4]; 4]; 6],
+    light: [f32; 4],
+}
+
+/// Shadows for a point light like [LightUniform](crate::LightUniform),
+/// which shines every way at once. The six faces are layers of a depth
+/// texture array rather than a cube map, and store distance from the
+/// light. Drawing works like [CascadedShadowMap] with a pass per face.
+///
+/// ```ignore
+/// let mut shadow = PointShadowMap::new(&display.device, 1024, std::mem::size_of::<InstanceRaw>() as _)?;
+///
+/// // In Demo::render
+/// shadow.position = self.light_position;
+/// shadow.update(&display.queue);
+/// for face in 0..6 {
+///     let mut pass = shadow.begin_pass(&mut encoder, face);
+///     pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+///     pass.draw_shadow_model_instanced(&self.model, 0..self.instances.len() as u32);
+/// }
+/// // Then the main pass with shadow.bind_group at group 3
+/// ```
+pub struct PointShadowMap {
+    pub position: Point3<f32>,
+    /// How far from the light shadows reach
+    pub far: f32,
+    pub filter: ShadowFilter,
+    pub layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    texture: Texture<'static>,
+    face_views: Vec<wgpu::TextureView>,
+    uniform: wgpu::Buffer,
+    filter_uniform: wgpu::Buffer,
+    face_uniforms: Vec<wgpu::Buffer>,
+    face_bind_groups: Vec<wgpu::BindGroup>,
+    pipeline: wgpu::RenderPipeline,
+    bias: ShadowBias,
+}
+
+impl PointShadowMap {
+    /// See [ShadowMap::new] for `instance_stride`
+    pub fn new(
+        device: &wgpu::Device,
+        size: u32,
+        instance_stride: wgpu::BufferAddress,
+    ) -> Result<Self> {
+        // GL guesses a texture with exactly 6 layers is a cube map, so
+        // there's a seventh that's never used
+        let texture = depth_texture(device, "PointShadowMap::texture", size, 7);
+        let face_views = layer_views(&texture, "PointShadowMap::face_view", 6);
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("PointShadowMap::uniform"),
+            size: std::mem::size_of::<PointShadowUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // The fragment shader needs the light's position too
+        let face_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("PointShadowMap::face_layout"),
+            entries: &[uniform_entry(
+                wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            )],
+        });
+        let face_uniforms = (0..6)
+            .map(|_| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("PointShadowMap::face_uniform"),
+                    size: std::mem::size_of::<PointFaceUniform>() as _,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect::<Vec<_>>();
+        let face_bind_groups = face_uniforms
+            .iter()
+            .map(|buffer| light_bind_group(device, &face_layout, buffer))
+            .collect();
+        let filter_uniform = filter_uniform(device);
+        let layout = sampling_layout(
+            device,
+            "PointShadowMap::layout",
+            wgpu::TextureViewDimension::D2Array,
+        );
+        let bind_group = sampling_bind_group(device, &layout, &uniform, &filter_uniform, &texture);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PointShadowMap::pipeline_layout"),
+            bind_group_layouts: &[&face_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(wgpu::include_wgsl!("shadow_point.wgsl"))
+            .fragment_shader(wgpu::include_wgsl!("shadow_point.wgsl"))
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .cull_mode(Some(wgpu::Face::Back))
+            .depth_no_stencil(
+                Texture::DEPTH_FORMAT,
+                true,
+                wgpu::CompareFunction::LessEqual,
+            )
+            .vertex_buffer::<ModelVertex>()
+            .vertex_buffer_desc(instance_layout(instance_stride))
+            .build(device)?;
+
+        Ok(Self {
+            position: Point3::origin(),
+            far: 25.0,
+            filter: ShadowFilter::default(),
+            bias: ShadowBias {
+                depth: 0.005,
+                ..Default::default()
+            },
+            layout,
+            bind_group,
+            texture,
+            face_views,
+            uniform,
+            filter_uniform,
+            face_uniforms,
+            face_bind_groups,
+            pipeline,
+        })
+    }
+
+    /// The depth texture array, for debug views
+    pub fn texture(&self) -> &Texture<'static> {
+        &self.texture
+    }
+
+    pub fn bias(&self) -> ShadowBias {
+        self.bias
+    }
+
+    /// Like [ShadowMap::set_bias], but the faces store their own depth, so
+    /// only [ShadowBias::depth] and [ShadowBias::normal_offset] are used
+    /// and there's no pipeline to rebuild. Takes effect on the next
+    /// `update`.
+    pub fn set_bias(&mut self, bias: ShadowBias) {
+        self.bias = bias;
+    }
+
+    /// The width and height of each face in texels
+    pub fn resolution(&self) -> u32 {
+        self.texture.texture.width()
+    }
+
+    /// Like [ShadowMap::set_resolution], for every face
+    pub fn set_resolution(&mut self, device: &wgpu::Device, size: u32) {
+        self.texture = depth_texture(device, "PointShadowMap::texture", size, 7);
+        self.face_views = layer_views(&self.texture, "PointShadowMap::face_view", 6);
+        self.bind_group = sampling_bind_group(
+            device,
+            &self.layout,
+            &self.uniform,
+            &self.filter_uniform,
+            &self.texture,
+        );
+    }
+
+    /// Uploads the light's position, face matrices, filter and bias. Call
+    /// it before the shadow passes whenever the light has moved.
+    pub fn update(&self, queue: &wgpu::Queue) {
+        let matrices = point_light_matrices(self.position, self.far * 0.001, self.far);
+        let light = self
+            .position
+            .to_homogeneous()
+            .truncate()
+            .extend(self.far)
+            .into();
+        for (buffer, view_proj) in self.face_uniforms.iter().zip(&matrices) {
+            let face = PointFaceUniform {
+                view_proj: (*view_proj).into(),
+                light,
+            };
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[face]));
+        }
+        let uniform = PointShadowUniform {
+            view_proj: matrices.map(|m| m.into()),
+            light,
+        };
+        queue.write_buffer(&self.uniform, 0, bytemuck::cast_slice(&[uniform]));
+        write_filter(
+            queue,
+            &self.filter_uniform,
+            self.filter,
+            self.bias,
+            &self.texture,
+        );
+    }
+
+    /// Like [ShadowMap::begin_pass], for one face
+    pub fn begin_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        face: usize,
+    ) -> wgpu::RenderPass<'a> {
+        let mut pass = begin_depth_pass(
+            encoder,
+            "PointShadowMap::begin_pass",
+            &self.face_views[face],
+        );
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.face_bind_groups[face], &[]);
+        pass
+    }
+}
+
+fn depth_texture(
+    device: &wgpu::Device,
+    label: &'static str,
+    size: u32,
+    layers: u32,
+) -> Texture<'static> {
+    Texture::from_descriptor(
+        device,
+        wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+    )
+}
+
+/// A 2D view of each of the first `layers` layers, to draw into
+fn layer_views(texture: &Texture, label: &str, layers: u32) -> Vec<wgpu::TextureView> {
+    (0..layers)
+        .map(|layer| {
+            texture.texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some(label),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_array_layer: layer,
+                array_layer_count: Some(1),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+fn uniform_entry(visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    uniform_entry_at(0, visibility)
+}
+
+fn uniform_entry_at(binding: u32, visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// The light's matrix for the depth pass
+fn light_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("shadow::light_layout"),
+        entries: &[uniform_entry(wgpu::ShaderStages::VERTEX)],
+    })
+}
+
+fn light_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("shadow::light_bind_group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: uniform.as_entire_binding(),
+        }],
+    })
+}
+
+fn filter_uniform(device: &wgpu::Device) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("shadow::filter_uniform"),
+        size: std::mem::size_of::<ShadowFilterUniform>() as _,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn write_filter(
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    filter: ShadowFilter,
+    bias: ShadowBias,
+    texture: &Texture,
+) {
+    let uniform = filter.uniform(texture.texture.width(), bias);
+    queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[uniform]));
+}
+
+/// The uniform, depth texture and comparison sampler for the main pass,
+/// then the filter
+fn sampling_layout(
+    device: &wgpu::Device,
+    label: &str,
+    view_dimension: wgpu::TextureViewDimension,
+) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some(label),
+        entries: &[
+            uniform_entry(wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT),
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                count: None,
+            },
+            uniform_entry_at(3, wgpu::ShaderStages::FRAGMENT),
+        ],
+    })
+}
+
+fn sampling_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform: &wgpu::Buffer,
+    filter_uniform: &wgpu::Buffer,
+    texture: &Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("shadow::bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: filter_uniform.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+/// The model matrix from the tutorials' InstanceRaw
+const INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+    5 => Float32x4,
+    6 => Float32x4,
+    7 => Float32x4,
+    8 => Float32x4,
+];
+
+fn instance_layout(stride: wgpu::BufferAddress) -> wgpu::VertexBufferLayout<'static> {
+    wgpu::VertexBufferLayout {
+        array_stride: stride,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &INSTANCE_ATTRIBUTES,
+    }
+}
+
+fn depth_pipeline(
+    device: &wgpu::Device,
+    light_layout: &wgpu::BindGroupLayout,
+    instance_stride: wgpu::BufferAddress,
+    bias: ShadowBias,
+) -> Result<wgpu::RenderPipeline> {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("shadow::pipeline_layout"),
+        bind_group_layouts: &[light_layout],
+        push_constant_ranges: &[],
+    });
+    // RenderPipelineBuilder needs a fragment shader, and a depth only pass
+    // doesn't have one
+    let pipeline = catch_validation_errors(device, || {
+        let module = device.create_shader_module(wgpu::include_wgsl!("shadow.wgsl"));
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("shadow::pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc(), instance_layout(instance_stride)],
+                compilation_options: Default::default(),
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: Default::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: bias.constant,
+                    slope_scale: bias.slope_scale,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: Default::default(),
+            multiview: None,
+            cache: None,
+        })
+    })?;
+    Ok(pipeline)
+}
+
+fn begin_depth_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    label: &str,
+    view: &'a wgpu::TextureView,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[],
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+            view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: wgpu::StoreOp::Store,
+            }),
+            stencil_ops: None,
+        }),
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    })
+}
+
+/// Draws geometry into a [ShadowMap::begin_pass]. Materials don't matter
+/// to depth, so only the vertex and index buffers are set.
+pub trait DrawShadow<'a> {
+    fn draw_shadow_model(&mut self, model: &'a Model);
+    fn draw_shadow_model_instanced(&mut self, model: &'a Model, instances: Range<u32>);
+}
+
+impl<'a, 'b> DrawShadow<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_shadow_model(&mut self, model: &'b Model) {
+        self.draw_shadow_model_instanced(model, 0..1);
+    }
+
+    fn draw_shadow_model_instanced(&mut self, model: &'b Model, instances: Range<u32>) {
+        for mesh in &model.meshes {
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            self.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn light_matrix_covers_the_sphere() {
+        let center = Point3::new(1.0, 2.0, 3.0);
+        let m = directional_light_matrix(Vector3::new(0.0, -1.0, 0.0), center, 5.0);
+        let project = |p: Point3<f32>| Point3::from_homogeneous(m * p.to_homogeneous());
+
+        let middle = project(center);
+        assert!(middle.x.abs() < 1e-5 && middle.y.abs() < 1e-5);
+        assert!((middle.z - 0.5).abs() < 1e-5);
+        // Closest to the light is depth 0, furthest is 1
+        assert!(project(center + Vector3::new(0.0, 5.0, 0.0)).z.abs() < 1e-5);
+        assert!((project(center - Vector3::new(0.0, 5.0, 0.0)).z - 1.0).abs() < 1e-5);
+        assert!((project(center + Vector3::new(5.0, 0.0, 0.0)).x.abs() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn cascades_cover_the_view() {
+        let even = cascade_splits(1.0, 101.0, 4, 0.0);
+        assert_eq!(even, vec![26.0, 51.0, 76.0, 101.0]);
+        let log = cascade_splits(1.0, 100.0, 2, 1.0);
+        assert!((log[0] - 10.0).abs() < 1e-4 && (log[1] - 100.0).abs() < 1e-3);
+
+        let view = Matrix4::look_to_rh(
+            Point3::new(0.0, 0.0, 5.0),
+            -Vector3::unit_z(),
+            Vector3::unit_y(),
+        );
+        let (center, radius) = frustum_slice_sphere(view, Deg(90.0).into(), 1.0, 1.0, 3.0);
+        // Halfway along the slice, in front of the camera
+        assert!((center - Point3::new(0.0, 0.0, 3.0)).magnitude() < 1e-4);
+        // The far corners are 3 out on x and y and 1 back from the center
+        assert!((radius - 19f32.sqrt()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn snapped_centers_move_by_whole_texels() {
+        let direction = Vector3::new(-1.0, -2.0, -0.5);
+        let (radius, size) = (10.0, 512);
+        let texel_ndc = 2.0 / size as f32;
+        let a = snap_to_texels(direction, Point3::new(0.3, 1.7, -2.2), radius, size);
+        let b = snap_to_texels(direction, Point3::new(0.8, 1.6, -1.9), radius, size);
+        // A fixed point lands a whole number of texels apart in both maps
+        let p = Point3::new(4.0, 0.5, 3.0).to_homogeneous();
+        let pa = directional_light_matrix(direction, a, radius) * p;
+        let pb = directional_light_matrix(direction, b, radius) * p;
+        for shift in [pa.x - pb.x, pa.y - pb.y] {
+            let texels = shift / texel_ndc;
+            assert!((texels - texels.round()).abs() < 1e-2, "{}", texels);
+        }
+        // Snapping only moves sideways, by at most half a texel each way
+        let texel = radius * 2.0 / size as f32;
+        assert!((a - Point3::new(0.3, 1.7, -2.2)).magnitude() <= texel);
+    }
+
+    #[test]
+    fn snapped_matrices_ignore_sub_texel_moves() {
+        let direction = Vector3::new(-1.0, -2.0, -0.5);
+        let (radius, size) = (10.0, 512);
+        let right = direction.cross(light_up(direction)).normalize();
+        let nudge = right * (radius * 2.0 / size as f32) * 0.2;
+        let a = snapped_light_matrix(direction, Point3::origin(), radius, size);
+        let b = snapped_light_matrix(direction, Point3::origin() + nudge, radius, size);
+        let p = Point3::new(4.0, 0.5, 3.0).to_homogeneous();
+        assert!((a * p - b * p).magnitude() < 1e-5);
+        assert_eq!(stable_radius(10.01), stable_radius(10.02));
+    }
+
+    #[test]
+    fn filters_validate() {
+        let shader = r#"
+@fragment
+fn fs_main(
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+) -> @location(0) vec4<f32> {
+    return vec4<f32>(shadow(world_position), shadow_normal_offset(world_position, world_normal), 0.0, 1.0);
+}
+"#;
+        for snippet in [SHADOW_WGSL, SHADOW_CASCADES_WGSL, SHADOW_POINT_WGSL] {
+            let source = format!("{}{}", snippet, shader);
+            crate::shader::validate_wgsl(&source).unwrap();
+        }
+
+        let bias = ShadowBias {
+            normal_offset: 1.5,
+            ..Default::default()
+        };
+        let pcf = ShadowFilter::Pcf { kernel: 0 }.uniform(1024, bias);
+        assert_eq!((pcf.mode, pcf.kernel), (1, 1));
+        assert_eq!(pcf.texel_size, 1.0 / 1024.0);
+        assert_eq!(pcf.normal_offset, 1.5);
+    }
+
+    #[test]
+    fn point_light_faces_look_along_their_axis() {
+        let position = Point3::new(1.0, 2.0, 3.0);
+        let matrices = point_light_matrices(position, 0.1, 10.0);
+        let axes = [
+            Vector3::unit_x(),
+            -Vector3::unit_x(),
+            Vector3::unit_y(),
+            -Vector3::unit_y(),
+            Vector3::unit_z(),
+            -Vector3::unit_z(),
+        ];
+        for (m, axis) in matrices.iter().zip(&axes) {
+            let clip = m * (position + axis * 5.0).to_homogeneous();
+            assert!(clip.w > 0.0);
+            assert!(clip.x.abs() < 1e-4 && clip.y.abs() < 1e-4);
+            // The opposite direction is behind this face
+            assert!((m * (position - axis * 5.0).to_homogeneous()).w < 0.0);
+        }
+    }
+}
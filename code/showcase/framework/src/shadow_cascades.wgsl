@@ -0,0 +1,53 @@
+// Reads a CascadedShadowMap bound to group 3
+
+struct ShadowCascades {
+    camera_view: mat4x4<f32>,
+    view_proj: array<mat4x4<f32>, 4>,
+    // How far from the camera each cascade reaches
+    splits: vec4<f32>,
+    count: u32,
+}
+
+@group(3) @binding(0)
+var<uniform> shadow_cascades: ShadowCascades;
+@group(3) @binding(1)
+var shadow_texture: texture_depth_2d_array;
+@group(3) @binding(2)
+var shadow_sampler: sampler_comparison;
+
+// The closest cascade that reaches `world_position`, or the cascade count
+// if none do
+fn shadow_cascade(world_position: vec3<f32>) -> u32 {
+    let depth = -(shadow_cascades.camera_view * vec4<f32>(world_position, 1.0)).z;
+    for (var i = 0u; i < shadow_cascades.count; i += 1u) {
+        if (depth < shadow_cascades.splits[i]) {
+            return i;
+        }
+    }
+    return shadow_cascades.count;
+}
+
+// 1 when `world_position` is lit, 0 when it's in shadow. Anything past the
+// last cascade is lit.
+fn shadow(world_position: vec3<f32>) -> f32 {
+    let cascade = shadow_cascade(world_position);
+    if (cascade >= shadow_cascades.count) {
+        return 1.0;
+    }
+    let clip = shadow_cascades.view_proj[cascade] * vec4<f32>(world_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+    return shadow_filtered(uv, i32(cascade), ndc.z);
+}
+
+fn shadow_texel_world(world_position: vec3<f32>) -> f32 {
+    let cascade = min(shadow_cascade(world_position), shadow_cascades.count - 1u);
+    return shadow_ortho_texel(shadow_cascades.view_proj[cascade]);
+}
+
+fn shadow_compare(uv: vec2<f32>, layer: i32, depth: f32) -> f32 {
+    return textureSampleCompareLevel(shadow_texture, shadow_sampler, uv, layer, depth);
+}
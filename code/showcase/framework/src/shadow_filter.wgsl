@@ -0,0 +1,92 @@
+// Softens shadow edges as set by ShadowFilter and applies ShadowBias. The
+// shadow snippet this is added to provides shadow, shadow_compare and
+// shadow_texel_world for its texture.
+
+struct ShadowFilter {
+    // 0 is hard, 1 is PCF and 2 is PCSS
+    mode: u32,
+    // PCF's square is kernel texels across
+    kernel: u32,
+    // PCSS's light size in texels
+    light_size: f32,
+    texel_size: f32,
+    // Taken off the receiver's depth before comparing
+    depth_bias: f32,
+    // In texels
+    normal_offset: f32,
+}
+
+@group(3) @binding(3)
+var<uniform> shadow_filter: ShadowFilter;
+
+// Averages comparisons over a square `kernel` samples across, spaced
+// `spacing` texels apart
+fn shadow_pcf(uv: vec2<f32>, layer: i32, depth: f32, kernel: i32, spacing: f32) -> f32 {
+    let step = shadow_filter.texel_size * spacing;
+    let offset = f32(kernel - 1) * 0.5;
+    var lit = 0.0;
+    for (var y = 0; y < kernel; y += 1) {
+        for (var x = 0; x < kernel; x += 1) {
+            let texel = vec2<f32>(f32(x) - offset, f32(y) - offset);
+            lit += shadow_compare(uv + texel * step, layer, depth);
+        }
+    }
+    return lit / f32(kernel * kernel);
+}
+
+// Finds the average depth of whatever is between the light and `depth`,
+// then blurs more the further the receiver is from it. GL can only compare
+// against depth textures, so the average comes from how much is blocked at
+// a few depths between the light and the receiver.
+fn shadow_pcss(uv: vec2<f32>, layer: i32, depth: f32) -> f32 {
+    let search = shadow_filter.light_size * 0.5 * shadow_filter.texel_size;
+    let steps = 4;
+    var blocked = 0.0;
+    var blocked_below = 0.0;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let sample_uv = uv + vec2<f32>(f32(x), f32(y)) * search;
+            blocked += 1.0 - shadow_compare(sample_uv, layer, depth);
+            for (var i = 0; i < steps; i += 1) {
+                let t = depth * (f32(i) + 0.5) / f32(steps);
+                blocked_below += 1.0 - shadow_compare(sample_uv, layer, t);
+            }
+        }
+    }
+    if (blocked <= 0.0) {
+        return 1.0;
+    }
+    // The mean of depths under `depth` is `depth` less the area under
+    // their cumulative distribution
+    let blocker_depth = depth - blocked_below * depth / f32(steps) / blocked;
+    let penumbra = (depth - blocker_depth) / max(blocker_depth, 0.0001) * shadow_filter.light_size;
+    return shadow_pcf(uv, layer, depth, 5, max(penumbra * 0.25, 1.0));
+}
+
+fn shadow_filtered(uv: vec2<f32>, layer: i32, receiver_depth: f32) -> f32 {
+    let depth = receiver_depth - shadow_filter.depth_bias;
+    switch shadow_filter.mode {
+        case 1u: {
+            return shadow_pcf(uv, layer, depth, i32(shadow_filter.kernel), 1.0);
+        }
+        case 2u: {
+            return shadow_pcss(uv, layer, depth);
+        }
+        default: {
+            return shadow_compare(uv, layer, depth);
+        }
+    }
+}
+
+// How big a texel of an orthographic `view_proj` is in world space
+fn shadow_ortho_texel(view_proj: mat4x4<f32>) -> f32 {
+    let scale = length(vec3<f32>(view_proj[0].x, view_proj[1].x, view_proj[2].x));
+    return 2.0 * shadow_filter.texel_size / scale;
+}
+
+// Like shadow, but first pushes `world_position` out along `world_normal`
+// by ShadowBias::normal_offset texels
+fn shadow_normal_offset(world_position: vec3<f32>, world_normal: vec3<f32>) -> f32 {
+    let offset = shadow_filter.normal_offset * shadow_texel_world(world_position);
+    return shadow(world_position + normalize(world_normal) * offset);
+}
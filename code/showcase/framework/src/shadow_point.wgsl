@@ -0,0 +1,47 @@
+struct PointShadowFace {
+    view_proj: mat4x4<f32>,
+    // xyz is the light's position, w is how far shadows reach
+    light: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> face: PointShadowFace;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+// Same layout as the tutorials' InstanceRaw
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(vertex: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(vertex.position, 1.0);
+    var out: VertexOutput;
+    out.clip_position = face.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    return out;
+}
+
+// Stores distance from the light instead of projected depth, so every
+// face agrees on what a depth means
+@fragment
+fn fs_main(in: VertexOutput) -> @builtin(frag_depth) f32 {
+    return distance(in.world_position, face.light.xyz) / face.light.w;
+}
@@ -0,0 +1,51 @@
+// Reads a PointShadowMap bound to group 3
+
+struct PointShadow {
+    view_proj: array<mat4x4<f32>, 6>,
+    // xyz is the light's position, w is how far shadows reach
+    light: vec4<f32>,
+}
+
+@group(3) @binding(0)
+var<uniform> point_shadow: PointShadow;
+@group(3) @binding(1)
+var shadow_texture: texture_depth_2d_array;
+@group(3) @binding(2)
+var shadow_sampler: sampler_comparison;
+
+// Which face of the shadow map looks along `dir`, in +X, -X, +Y, -Y, +Z,
+// -Z order
+fn shadow_face(dir: vec3<f32>) -> u32 {
+    let a = abs(dir);
+    if (a.x >= a.y && a.x >= a.z) {
+        return select(1u, 0u, dir.x > 0.0);
+    }
+    if (a.y >= a.z) {
+        return select(3u, 2u, dir.y > 0.0);
+    }
+    return select(5u, 4u, dir.z > 0.0);
+}
+
+// 1 when `world_position` is lit, 0 when it's in shadow. Anything further
+// than the shadow's reach is lit.
+fn shadow(world_position: vec3<f32>) -> f32 {
+    let to_point = world_position - point_shadow.light.xyz;
+    let depth = length(to_point) / point_shadow.light.w;
+    if (depth > 1.0) {
+        return 1.0;
+    }
+    let face = shadow_face(to_point);
+    let clip = point_shadow.view_proj[face] * vec4<f32>(world_position, 1.0);
+    let ndc = clip.xy / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    return shadow_filtered(uv, i32(face), depth);
+}
+
+// Each face covers 90 degrees, so texels grow with distance
+fn shadow_texel_world(world_position: vec3<f32>) -> f32 {
+    return 2.0 * shadow_filter.texel_size * distance(world_position, point_shadow.light.xyz);
+}
+
+fn shadow_compare(uv: vec2<f32>, layer: i32, depth: f32) -> f32 {
+    return textureSampleCompareLevel(shadow_texture, shadow_sampler, uv, layer, depth);
+}
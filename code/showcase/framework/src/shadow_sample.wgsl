@@ -0,0 +1,32 @@
+// Reads a ShadowMap bound to group 3
+
+struct ShadowLight {
+    view_proj: mat4x4<f32>,
+}
+
+@group(3) @binding(0)
+var<uniform> shadow_light: ShadowLight;
+@group(3) @binding(1)
+var shadow_texture: texture_depth_2d;
+@group(3) @binding(2)
+var shadow_sampler: sampler_comparison;
+
+// 1 when `world_position` is lit, 0 when it's in shadow. Anything outside
+// of the shadow map is lit.
+fn shadow(world_position: vec3<f32>) -> f32 {
+    let clip = shadow_light.view_proj * vec4<f32>(world_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+    return shadow_filtered(uv, 0, ndc.z);
+}
+
+fn shadow_texel_world(world_position: vec3<f32>) -> f32 {
+    return shadow_ortho_texel(shadow_light.view_proj);
+}
+
+fn shadow_compare(uv: vec2<f32>, layer: i32, depth: f32) -> f32 {
+    return textureSampleCompareLevel(shadow_texture, shadow_sampler, uv, depth);
+}
@@ -0,0 +1,381 @@
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::animation::JointBuffer;
+use crate::capabilities::Capabilities;
+use crate::model::{Material, Mesh, ModelVertex, Vertex};
+use crate::shader::catch_validation_errors;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// WGSL for skinning in the vertex shader with a
+/// [JointTexture](crate::JointTexture) bound to group 3. Put it in front
+/// of a shader to get `skin_matrix(joints, weights)`.
+pub const SKINNING_TEXTURE_WGSL: &str = include_str!("skinning_texture.wgsl");
+
+/// How skinned meshes get posed on this device
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SkinningMode {
+    /// [Skinning] and [SkinnedMesh] with a [JointBuffer]
+    Compute,
+    /// [VertexSkinnedMesh] with a [JointTexture](crate::JointTexture), for
+    /// WebGL2
+    VertexTexture,
+}
+
+impl SkinningMode {
+    pub fn new(capabilities: &Capabilities) -> Self {
+        if capabilities.compute_shaders {
+            Self::Compute
+        } else {
+            Self::VertexTexture
+        }
+    }
+}
+
+/// Which joints move a vertex and by how much. Weights don't need to add
+/// up to 1, and a vertex with no weight isn't moved.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinVertex {
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+impl Vertex for SkinVertex {
+    /// Locations 12 and 13, after [ModelVertex] and the instance data the
+    /// demos use
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<SkinVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[u32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// The bind pose of a skinned [Mesh] from a file, kept on the CPU so a
+/// [SkinnedMesh] or [VertexSkinnedMesh] can be made from it. The mesh
+/// itself is in the bind pose too, without its node's transform, since
+/// the joints place it.
+///
+/// ```ignore
+/// let model = Model::load_gltf(&display.device, &display.queue, &mut assets, &layout, "character.glb")?;
+/// let animators = Animator::load_gltf("character.glb")?;
+/// let mesh = &model.meshes[0];
+/// let skin = mesh.skin.as_ref().context("Not skinned")?;
+/// let joints = JointBuffer::new(&display.device, animators[skin.skin].skeleton().len());
+/// let character = SkinnedMesh::new(&display.device, &skinning, &mesh.name, &skin.vertices, &skin.weights, &skin.indices, mesh.material, &joints)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct MeshSkin {
+    /// Which of the file's skins the joints index into, in the same order
+    /// as the animators from [Animator::load_gltf](crate::Animator)
+    pub skin: usize,
+    pub vertices: Vec<ModelVertex>,
+    /// One for each of `vertices`
+    pub weights: Vec<SkinVertex>,
+    pub indices: Vec<u32>,
+}
+
+/// The compute pipeline that poses [SkinnedMesh]es. One is enough for
+/// every skinned mesh in a demo. WebGL2 has no compute shaders, so check
+/// [SkinningMode::new] first.
+///
+/// ```ignore
+/// let skinning = Skinning::new(&display.device)?;
+/// let joints = JointBuffer::new(&display.device, animator.skeleton().len());
+/// let character = SkinnedMesh::new(&display.device, &skinning, "character", &vertices, &skin, &indices, 0, &joints);
+///
+/// // Each frame, before drawing
+/// joints.write(&display.queue, animator.joint_matrices());
+/// skinning.skin(&mut encoder, &[&character]);
+/// pass.draw_mesh(&character.mesh, &material, &camera_bind_group, &light_bind_group);
+/// ```
+pub struct Skinning {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl Skinning {
+    pub fn new(device: &wgpu::Device) -> Result<Self> {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Skinning::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, true),
+                storage(3, false),
+                storage(4, true),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skinning::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = catch_validation_errors(device, || {
+            let module = device.create_shader_module(wgpu::include_wgsl!("skinning.wgsl"));
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Skinning::pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        })?;
+        Ok(Self { layout, pipeline })
+    }
+
+    /// Poses `meshes` with the joint matrices currently in their
+    /// [JointBuffer]s
+    pub fn skin(&self, encoder: &mut wgpu::CommandEncoder, meshes: &[&SkinnedMesh]) {
+        crate::cpu_scope!("Skinning::skin");
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Skinning::skin"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        for mesh in meshes {
+            pass.set_bind_group(0, &mesh.bind_group, &[]);
+            pass.dispatch_workgroups(mesh.vertex_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+    }
+}
+
+/// A mesh whose vertices are posed on the GPU by [Skinning]. The bind pose
+/// stays untouched, and [SkinnedMesh::mesh] draws the posed copy like any
+/// other mesh.
+pub struct SkinnedMesh {
+    pub mesh: Mesh,
+    bind_group: wgpu::BindGroup,
+    vertex_count: u32,
+}
+
+impl SkinnedMesh {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        skinning: &Skinning,
+        name: &str,
+        vertices: &[ModelVertex],
+        skin: &[SkinVertex],
+        indices: &[u32],
+        material: usize,
+        joints: &JointBuffer,
+    ) -> Result<Self> {
+        ensure!(
+            vertices.len() == skin.len(),
+            "{} has {} vertices but {} skin weights",
+            name,
+            vertices.len(),
+            skin.len()
+        );
+        ensure!(!vertices.is_empty(), "{} has no vertices", name);
+
+        let bind_pose = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Bind Pose Buffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let skin_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Skin Buffer", name)),
+            contents: bytemuck::cast_slice(skin),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        // Starts out in the bind pose, so drawing before the first skin
+        // pass still shows something sensible
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", name)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let vertex_count = vertices.len() as u32;
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Skin Params", name)),
+            contents: bytemuck::cast_slice(&[vertex_count, 0, 0, 0]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(name),
+            layout: &skinning.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: bind_pose.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: skin_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: vertex_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: joints.buffer().as_entire_binding(),
+                },
+            ],
+        });
+
+        Ok(Self {
+            mesh: Mesh {
+                name: name.to_string(),
+                vertex_buffer,
+                index_buffer,
+                num_elements: indices.len() as u32,
+                material,
+                morph_targets: None,
+                skin: None,
+            },
+            bind_group,
+            vertex_count,
+        })
+    }
+}
+
+/// A mesh posed in the vertex shader instead of by [Skinning], for
+/// devices without compute shaders. The pipeline takes [ModelVertex] in
+/// slot 0 and [SkinVertex] in slot 1, and the vertex shader starts with
+/// [SKINNING_TEXTURE_WGSL].
+///
+/// ```ignore
+/// let joints = JointTexture::new(&display.device, animator.skeleton().len());
+/// let character = VertexSkinnedMesh::new(&display.device, "character", &vertices, &skin, &indices, 0)?;
+///
+/// // Each frame
+/// joints.write(&display.queue, animator.joint_matrices());
+/// character.draw(&mut pass, &material, &camera_bind_group, &light_bind_group, &joints);
+/// ```
+pub struct VertexSkinnedMesh {
+    pub mesh: Mesh,
+    pub skin_buffer: wgpu::Buffer,
+}
+
+impl VertexSkinnedMesh {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        vertices: &[ModelVertex],
+        skin: &[SkinVertex],
+        indices: &[u32],
+        material: usize,
+    ) -> Result<Self> {
+        ensure!(
+            vertices.len() == skin.len(),
+            "{} has {} vertices but {} skin weights",
+            name,
+            vertices.len(),
+            skin.len()
+        );
+        let skin_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Skin Buffer", name)),
+            contents: bytemuck::cast_slice(skin),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        Ok(Self {
+            mesh: Mesh::from_vertices(device, name, vertices, indices, material),
+            skin_buffer,
+        })
+    }
+
+    /// Binds the material, camera and lights to groups 0 to 2 like
+    /// [DrawModel](crate::DrawModel), and the joints to group 3
+    pub fn draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        material: &'a Material,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        joints: &'a crate::animation::JointTexture,
+    ) {
+        pass.set_vertex_buffer(0, self.mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, self.skin_buffer.slice(..));
+        pass.set_index_buffer(self.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        pass.set_bind_group(0, &material.bind_group, &[]);
+        pass.set_bind_group(1, camera_bind_group, &[]);
+        pass.set_bind_group(2, light_bind_group, &[]);
+        pass.set_bind_group(3, &joints.bind_group, &[]);
+        pass.draw_indexed(0..self.mesh.num_elements, 0, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Matrix4, SquareMatrix, Vector3};
+
+    #[test]
+    fn texture_skinning_wgsl_validates() {
+        let shader = r#"
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    @location(12) joints: vec4<u32>,
+    @location(13) weights: vec4<f32>,
+) -> @builtin(position) vec4<f32> {
+    return skin_matrix(joints, weights) * vec4<f32>(position, 1.0);
+}
+"#;
+        let source = format!("{}{}", SKINNING_TEXTURE_WGSL, shader);
+        crate::shader::validate_wgsl(&source).unwrap();
+    }
+
+    #[test]
+    fn joint_texels_hold_one_column_each() {
+        let matrices = [
+            Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0)),
+            Matrix4::from_scale(2.0),
+            Matrix4::identity(),
+        ];
+        let texels = crate::animation::joint_texels(&matrices, 2);
+
+        // joint_matrix() reads texel i of a row as column i
+        assert_eq!(texels.len(), 2);
+        assert_eq!(texels[0][0], [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(texels[0][3], [1.0, 2.0, 3.0, 1.0]);
+        assert_eq!(texels[1][1], [0.0, 2.0, 0.0, 0.0]);
+        assert_eq!(texels[1][3], [0.0, 0.0, 0.0, 1.0]);
+    }
+}
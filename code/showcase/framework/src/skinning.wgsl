@@ -0,0 +1,79 @@
+struct SkinParams {
+    vertex_count: u32,
+}
+
+struct SkinVertex {
+    joints: vec4<u32>,
+    weights: vec4<f32>,
+}
+
+// ModelVertex is 14 tightly packed floats, which a struct can't match
+const STRIDE: u32 = 14u;
+const POSITION: u32 = 0u;
+const TEX_COORDS: u32 = 3u;
+const NORMAL: u32 = 5u;
+const TANGENT: u32 = 8u;
+const BITANGENT: u32 = 11u;
+
+@group(0) @binding(0)
+var<uniform> params: SkinParams;
+@group(0) @binding(1)
+var<storage, read> bind_pose: array<f32>;
+@group(0) @binding(2)
+var<storage, read> skin: array<SkinVertex>;
+@group(0) @binding(3)
+var<storage, read_write> skinned: array<f32>;
+@group(0) @binding(4)
+var<storage, read> joints: array<mat4x4<f32>>;
+
+fn read3(offset: u32) -> vec3<f32> {
+    return vec3<f32>(bind_pose[offset], bind_pose[offset + 1u], bind_pose[offset + 2u]);
+}
+
+fn write3(offset: u32, value: vec3<f32>) {
+    skinned[offset] = value.x;
+    skinned[offset + 1u] = value.y;
+    skinned[offset + 2u] = value.z;
+}
+
+fn skin_direction(m: mat4x4<f32>, offset: u32) -> vec3<f32> {
+    let v = (m * vec4<f32>(read3(offset), 0.0)).xyz;
+    let len = length(v);
+    if (len > 0.0) {
+        return v / len;
+    }
+    return v;
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= params.vertex_count) {
+        return;
+    }
+
+    let s = skin[i];
+    let total = s.weights.x + s.weights.y + s.weights.z + s.weights.w;
+    var m = mat4x4<f32>(
+        vec4<f32>(1.0, 0.0, 0.0, 0.0),
+        vec4<f32>(0.0, 1.0, 0.0, 0.0),
+        vec4<f32>(0.0, 0.0, 1.0, 0.0),
+        vec4<f32>(0.0, 0.0, 0.0, 1.0),
+    );
+    // Vertices without weights stay where they are
+    if (total > 0.0) {
+        let w = s.weights / total;
+        m = joints[s.joints.x] * w.x
+            + joints[s.joints.y] * w.y
+            + joints[s.joints.z] * w.z
+            + joints[s.joints.w] * w.w;
+    }
+
+    let base = i * STRIDE;
+    write3(base + POSITION, (m * vec4<f32>(read3(base + POSITION), 1.0)).xyz);
+    skinned[base + TEX_COORDS] = bind_pose[base + TEX_COORDS];
+    skinned[base + TEX_COORDS + 1u] = bind_pose[base + TEX_COORDS + 1u];
+    write3(base + NORMAL, skin_direction(m, base + NORMAL));
+    write3(base + TANGENT, skin_direction(m, base + TANGENT));
+    write3(base + BITANGENT, skin_direction(m, base + BITANGENT));
+}
@@ -0,0 +1,35 @@
+// Joint matrices from a JointTexture, for skinning in the vertex shader
+// where storage buffers aren't available. Each row of the texture is one
+// joint, with a column of the matrix in each texel.
+
+@group(3) @binding(0)
+var joint_texture: texture_2d<f32>;
+
+fn joint_matrix(joint: u32) -> mat4x4<f32> {
+    let row = i32(joint);
+    return mat4x4<f32>(
+        textureLoad(joint_texture, vec2<i32>(0, row), 0),
+        textureLoad(joint_texture, vec2<i32>(1, row), 0),
+        textureLoad(joint_texture, vec2<i32>(2, row), 0),
+        textureLoad(joint_texture, vec2<i32>(3, row), 0),
+    );
+}
+
+// Blends the joint matrices for a vertex. Vertices without weights stay
+// where they are.
+fn skin_matrix(joints: vec4<u32>, weights: vec4<f32>) -> mat4x4<f32> {
+    let total = weights.x + weights.y + weights.z + weights.w;
+    if (total <= 0.0) {
+        return mat4x4<f32>(
+            vec4<f32>(1.0, 0.0, 0.0, 0.0),
+            vec4<f32>(0.0, 1.0, 0.0, 0.0),
+            vec4<f32>(0.0, 0.0, 1.0, 0.0),
+            vec4<f32>(0.0, 0.0, 0.0, 1.0),
+        );
+    }
+    let w = weights / total;
+    return joint_matrix(joints.x) * w.x
+        + joint_matrix(joints.y) * w.y
+        + joint_matrix(joints.z) * w.z
+        + joint_matrix(joints.w) * w.w;
+}
@@ -0,0 +1,235 @@
+use anyhow::*;
+use cgmath::*;
+use wgpu::util::DeviceExt;
+
+use crate::pipeline::RenderPipelineBuilder;
+use crate::texture::{self, Cubemap};
+
+const SKY_WGSL: &str = concat!(include_str!("cube_face.wgsl"), include_str!("sky.wgsl"));
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    zenith: [f32; 4],
+    horizon: [f32; 4],
+    ground: [f32; 4],
+    sun_direction: [f32; 4],
+    sun_color: [f32; 4],
+}
+
+/// A sky made from a gradient and a sun, for demos that don't load an HDR
+/// environment. [ProceduralSky::bake] paints it into a [Cubemap] for a
+/// [crate::Skybox] or an [crate::EnvironmentMap], and
+/// [ProceduralSky::ambient] gives a flat ambient color to go with it.
+/// Colors are linear and can go past 1.
+///
+/// ```ignore
+/// let mut sky = ProceduralSky::default();
+/// sky.set_sun_from_light(light_direction);
+/// let cubemap = sky.bake(&display.device, &display.queue, 256)?;
+/// let skybox = Skybox::new(&display.device, &cubemap, display.config.format, Some(Texture::DEPTH_FORMAT))?;
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ProceduralSky {
+    /// Straight up
+    pub zenith: [f32; 3],
+    pub horizon: [f32; 3],
+    /// Below the horizon
+    pub ground: [f32; 3],
+    /// How far up the sky the horizon color reaches. 1 fades evenly to
+    /// the zenith, lower keeps it to a band of haze.
+    pub haze: f32,
+    /// Towards the sun
+    pub sun_direction: Vector3<f32>,
+    pub sun_color: [f32; 3],
+    /// The sun disc's angular radius
+    pub sun_size: Rad<f32>,
+}
+
+impl Default for ProceduralSky {
+    fn default() -> Self {
+        Self {
+            zenith: [0.12, 0.3, 0.75],
+            horizon: [0.65, 0.75, 0.9],
+            ground: [0.2, 0.18, 0.16],
+            haze: 0.3,
+            sun_direction: Vector3::new(0.4, 0.6, 0.3).normalize(),
+            sun_color: [20.0, 18.0, 15.0],
+            sun_size: Deg(1.5).into(),
+        }
+    }
+}
+
+impl ProceduralSky {
+    /// Puts the sun where a directional light travelling along
+    /// `light_direction` comes from, like the ones [crate::ShadowMap] takes
+    pub fn set_sun_from_light(&mut self, light_direction: Vector3<f32>) {
+        self.sun_direction = -light_direction;
+    }
+
+    /// The sky looking along `direction`, the same as the baked cube map
+    pub fn color(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        let d = direction.normalize();
+        let zenith = Vector3::from(self.zenith);
+        let horizon = Vector3::from(self.horizon);
+        let mut color = if d.y >= 0.0 {
+            let t = (1.0 - d.y).powf(1.0 / self.haze.max(0.01));
+            zenith.lerp(horizon, t)
+        } else {
+            horizon.lerp(self.ground.into(), smoothstep(0.0, 0.1, -d.y))
+        };
+        let (outer, inner) = self.sun_edges();
+        let disc = smoothstep(outer, inner, d.dot(self.sun()));
+        color += Vector3::from(self.sun_color) * disc * smoothstep(-0.01, 0.0, d.y);
+        color
+    }
+
+    /// The light the sky, without the sun, casts on ground facing up. The
+    /// sun should light the scene as a directional light instead.
+    pub fn ambient(&self) -> [f32; 3] {
+        let sunless = Self {
+            sun_color: [0.0; 3],
+            ..*self
+        };
+        // Cosine weighted, so rings of equal height count by how much of
+        // the sky they cover as seen from the ground
+        const RINGS: u32 = 32;
+        let mut total = Vector3::zero();
+        let mut weights = 0.0;
+        for ring in 0..RINGS {
+            let up = (ring as f32 + 0.5) / RINGS as f32;
+            let side = (1.0 - up * up).sqrt();
+            let weight = up * side;
+            total += sunless.color(Vector3::new(side, up, 0.0)) * weight;
+            weights += weight;
+        }
+        (total / weights).into()
+    }
+
+    /// Paints the sky into a cube map `size` texels a side. Bake it again
+    /// after changing the sky, it isn't redrawn every frame.
+    pub fn bake(&self, device: &wgpu::Device, queue: &wgpu::Queue, size: u32) -> Result<Cubemap> {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ProceduralSky::layout"),
+            entries: &[
+                texture::cube_face_layout_entry(0),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ProceduralSky::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = || wgpu::ShaderModuleDescriptor {
+            label: Some("sky.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(SKY_WGSL.into()),
+        };
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(shader())
+            .fragment_shader(shader())
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .color_solid(Cubemap::FORMAT)
+            .build(device)?;
+
+        let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("ProceduralSky::uniform"),
+            contents: bytemuck::cast_slice(&[self.to_raw()]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let (faces, stride) = texture::cube_face_buffer(device);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ProceduralSky::bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: texture::cube_face_binding(&faces),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform.as_entire_binding(),
+                },
+            ],
+        });
+        let cubemap = Cubemap::empty(device, size.max(1));
+        texture::draw_cube_faces(
+            device,
+            queue,
+            "ProceduralSky::bake",
+            &cubemap.texture,
+            &pipeline,
+            &bind_group,
+            stride,
+        );
+        Ok(cubemap)
+    }
+
+    fn sun(&self) -> Vector3<f32> {
+        if self.sun_direction.magnitude2() > 0.0 {
+            self.sun_direction.normalize()
+        } else {
+            Vector3::unit_y()
+        }
+    }
+
+    /// The cosines of the disc's radius and of where its edge starts to
+    /// fade
+    fn sun_edges(&self) -> (f32, f32) {
+        (self.sun_size.cos(), (self.sun_size * 0.8).cos())
+    }
+
+    fn to_raw(self) -> SkyUniform {
+        let [zr, zg, zb] = self.zenith;
+        let [hr, hg, hb] = self.horizon;
+        let [gr, gg, gb] = self.ground;
+        let [sr, sg, sb] = self.sun_color;
+        let sun = self.sun();
+        let (outer, inner) = self.sun_edges();
+        SkyUniform {
+            zenith: [zr, zg, zb, self.haze],
+            horizon: [hr, hg, hb, 0.0],
+            ground: [gr, gg, gb, 0.0],
+            sun_direction: [sun.x, sun.y, sun.z, outer],
+            sun_color: [sr, sg, sb, inner],
+        }
+    }
+}
+
+/// WGSL's smoothstep
+fn smoothstep(low: f32, high: f32, x: f32) -> f32 {
+    let t = ((x - low) / (high - low)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sky_has_a_gradient_and_a_sun() {
+        let sky = ProceduralSky::default();
+        let zenith = sky.color(Vector3::unit_y());
+        assert!((zenith - Vector3::from(sky.zenith)).magnitude() < 1e-5);
+        let horizon = sky.color(Vector3::new(1.0, 0.0, 0.0));
+        assert!((horizon - Vector3::from(sky.horizon)).magnitude() < 1e-5);
+        assert!(sky.color(sky.sun_direction).x > 10.0);
+        // Ambient leaves the sun out, so it's between the sky's colors
+        let ambient = sky.ambient();
+        assert!(ambient[2] >= sky.zenith[2].min(sky.horizon[2]));
+        assert!(ambient[2] <= sky.zenith[2].max(sky.horizon[2]));
+
+        crate::shader::validate_wgsl(SKY_WGSL).unwrap();
+    }
+}
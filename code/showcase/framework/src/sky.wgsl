@@ -0,0 +1,37 @@
+// Paints a ProceduralSky onto a cube map. Needs cube_face.wgsl in front.
+// sky_color has a copy in sky.rs for the ambient color, so change both.
+
+struct Sky {
+    // w is how far up the sky the horizon color reaches
+    zenith: vec4<f32>,
+    horizon: vec4<f32>,
+    ground: vec4<f32>,
+    // Towards the sun. w is the cosine of the disc's angular radius.
+    sun_direction: vec4<f32>,
+    // w is the cosine of where the disc's edge starts to fade
+    sun_color: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> face: u32;
+@group(0) @binding(1)
+var<uniform> sky: Sky;
+
+fn sky_color(d: vec3<f32>) -> vec3<f32> {
+    var color: vec3<f32>;
+    if (d.y >= 0.0) {
+        let t = pow(1.0 - d.y, 1.0 / max(sky.zenith.w, 0.01));
+        color = mix(sky.zenith.rgb, sky.horizon.rgb, t);
+    } else {
+        color = mix(sky.horizon.rgb, sky.ground.rgb, smoothstep(0.0, 0.1, -d.y));
+    }
+    let cos_angle = dot(d, sky.sun_direction.xyz);
+    let disc = smoothstep(sky.sun_direction.w, sky.sun_color.w, cos_angle);
+    // The ground hides the sun as it sets
+    return color + sky.sun_color.rgb * disc * smoothstep(-0.01, 0.0, d.y);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(sky_color(face_direction(face, in.uv)), 1.0);
+}
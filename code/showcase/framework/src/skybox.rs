@@ -0,0 +1,216 @@
+use anyhow::*;
+use cgmath::*;
+
+use crate::camera::{Camera, Projection};
+use crate::pipeline::RenderPipelineBuilder;
+use crate::texture::Cubemap;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyboxUniform {
+    inv_view_proj: [[f32; 4]; 4],
+}
+
+/// Draws a [Cubemap] behind the scene instead of a flat clear color. It
+/// draws at the far plane with depth writes off, so it can go before or
+/// after the scene in the same render pass. Clear depth to 1.0 as usual.
+///
+/// ```ignore
+/// let sky = Cubemap::load_hdr(&display.device, &display.queue, "res/sky.hdr")?;
+/// let skybox = Skybox::new(&display.device, &sky, display.config.format, Some(Texture::DEPTH_FORMAT))?;
+///
+/// // In Demo::render
+/// skybox.update(&display.queue, &camera, &projection);
+/// model_pass.draw_model(...);
+/// skybox.draw(&mut model_pass);
+/// ```
+pub struct Skybox {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Skybox {
+    /// `depth_format` is the format of the pass's depth attachment, if it
+    /// has one
+    pub fn new(
+        device: &wgpu::Device,
+        cubemap: &Cubemap,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+    ) -> Result<Self> {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Skybox::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let mut builder = RenderPipelineBuilder::new();
+        builder
+            .layout(&pipeline_layout)
+            .vertex_shader(wgpu::include_wgsl!("skybox.wgsl"))
+            .fragment_shader(wgpu::include_wgsl!("skybox.wgsl"))
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .color_solid(color_format);
+        if let Some(format) = depth_format {
+            // LessEqual since the sky is exactly on the far plane
+            builder.depth_no_stencil(format, false, wgpu::CompareFunction::LessEqual);
+        }
+        let pipeline = builder.build(device)?;
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Skybox::uniform_buffer"),
+            size: std::mem::size_of::<SkyboxUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = create_bind_group(device, &layout, &uniform_buffer, cubemap);
+
+        Ok(Self {
+            pipeline,
+            layout,
+            uniform_buffer,
+            bind_group,
+        })
+    }
+
+    pub fn set_cubemap(&mut self, device: &wgpu::Device, cubemap: &Cubemap) {
+        self.bind_group = create_bind_group(device, &self.layout, &self.uniform_buffer, cubemap);
+    }
+
+    /// Call this when the camera moves. Only its rotation matters, as the
+    /// sky is infinitely far away.
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera, projection: &Projection) {
+        let uniform = SkyboxUniform {
+            inv_view_proj: inv_view_proj(camera, projection).into(),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Draws into a pass that's already been started. It changes the
+    /// pass's bind group 0.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+/// Takes a point on the screen back to a direction from the camera
+fn inv_view_proj(camera: &Camera, projection: &Projection) -> Matrix4<f32> {
+    let view = Matrix4::look_to_rh(Point3::origin(), camera.forward(), camera.up());
+    (projection.calc_matrix() * view)
+        .invert()
+        .unwrap_or_else(Matrix4::identity)
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    cubemap: &Cubemap,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Skybox::bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&cubemap.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(&cubemap.sampler),
+            },
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What the shader does for a pixel at `ndc`
+    fn sky_direction(inv_view_proj: Matrix4<f32>, ndc: Vector2<f32>) -> Vector3<f32> {
+        let unproject = |depth| {
+            let point = inv_view_proj * Vector4::new(ndc.x, ndc.y, depth, 1.0);
+            point.truncate() / point.w
+        };
+        let near = unproject(0.0);
+        let direction = (unproject(0.5) - near).normalize();
+        if direction.dot(near) < 0.0 {
+            -direction
+        } else {
+            direction
+        }
+    }
+
+    #[test]
+    fn sky_lines_up_with_distant_points() {
+        let projection = Projection::new(1920, 1080, Deg(60.0), 0.1, 100.0);
+        let mut camera = Camera::new((0.0, 0.0, 0.0), Deg(30.0), Deg(-20.0));
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
+        let matrix = inv_view_proj(&camera, &projection);
+
+        let directions = [
+            camera.forward(),
+            (camera.forward() + camera.up() * 0.3).normalize(),
+            (camera.forward() - camera.right() * 0.5).normalize(),
+        ];
+        for direction in directions {
+            // Just inside the far plane
+            let far = camera.position + direction * 99.0;
+            let clip = view_proj * far.to_homogeneous();
+            let ndc = clip.truncate().truncate() / clip.w;
+            let sky = sky_direction(matrix, ndc);
+            assert!(sky.dot(direction) > 0.999, "{:?} {:?}", sky, direction);
+        }
+
+        // Only the camera's rotation matters
+        camera.position = Point3::new(50.0, -10.0, 3.0);
+        let moved = inv_view_proj(&camera, &projection);
+        let center = Vector2::new(0.0, 0.0);
+        assert!(sky_direction(moved, center).dot(camera.forward()) > 0.9999);
+    }
+
+    #[test]
+    fn skybox_wgsl_validates() {
+        crate::shader::validate_wgsl(include_str!("skybox.wgsl")).unwrap();
+    }
+}
@@ -0,0 +1,45 @@
+// Draws a cube map behind everything else. The triangle sits on the far
+// plane, so anything drawn with depth testing covers it.
+
+struct SkyboxUniform {
+    // The inverse of the projection times the view's rotation
+    inv_view_proj: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> skybox: SkyboxUniform;
+@group(0) @binding(1)
+var sky_texture: texture_cube<f32>;
+@group(0) @binding(2)
+var sky_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+    var out: VertexOutput;
+    out.ndc = uv * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(out.ndc, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Far away things drawn here are along the line through every point
+    // drawn here. It misses the camera away from the middle of the screen,
+    // see world_position_at in deferred.wgsl, and the far plane's depth
+    // of 1 is where OPENGL_TO_WGPU_MATRIX puts the eye, so two nearer
+    // depths give the line.
+    let a = skybox.inv_view_proj * vec4<f32>(in.ndc, 0.0, 1.0);
+    let b = skybox.inv_view_proj * vec4<f32>(in.ndc, 0.5, 1.0);
+    let near = a.xyz / a.w;
+    var direction = normalize(b.xyz / b.w - near);
+    if (dot(direction, near) < 0.0) {
+        direction = -direction;
+    }
+    return textureSample(sky_texture, sky_sampler, direction);
+}
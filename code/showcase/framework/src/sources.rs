@@ -0,0 +1,287 @@
+use anyhow::*;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use crate::assets::Assets;
+use crate::model::{LoadOptions, Model};
+use crate::pack::{load_obj_with, AssetPack};
+use crate::texture::Texture;
+
+/// Somewhere [AssetSources] can read files from. Paths are relative to
+/// the source's root, with `/` between folders.
+pub trait AssetSource {
+    /// `Ok(None)` when the source doesn't have the file, so the next one
+    /// gets a look
+    fn read(&self, path: &str) -> Result<Option<Cow<'_, [u8]>>>;
+}
+
+/// Files in a folder on disk
+pub struct DirSource {
+    pub root: PathBuf,
+}
+
+impl DirSource {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl AssetSource for DirSource {
+    fn read(&self, path: &str) -> Result<Option<Cow<'_, [u8]>>> {
+        let path = self.root.join(path);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let data =
+            std::fs::read(&path).with_context(|| format!("Unable to read {}", path.display()))?;
+        Ok(Some(Cow::Owned(data)))
+    }
+}
+
+impl AssetSource for AssetPack {
+    fn read(&self, path: &str) -> Result<Option<Cow<'_, [u8]>>> {
+        Ok(self.get(path).map(Cow::Borrowed))
+    }
+}
+
+/// A zip file, read into memory when it's opened
+#[cfg(feature = "archives")]
+pub struct ZipSource {
+    archive: std::sync::Mutex<zip::ZipArchive<std::io::Cursor<Vec<u8>>>>,
+}
+
+#[cfg(feature = "archives")]
+impl ZipSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data =
+            std::fs::read(path).with_context(|| format!("Unable to read {}", path.display()))?;
+        Self::from_bytes(data).with_context(|| format!("Unable to open {}", path.display()))
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        let archive = zip::ZipArchive::new(std::io::Cursor::new(data))?;
+        Ok(Self {
+            archive: std::sync::Mutex::new(archive),
+        })
+    }
+}
+
+#[cfg(feature = "archives")]
+impl AssetSource for ZipSource {
+    fn read(&self, path: &str) -> Result<Option<Cow<'_, [u8]>>> {
+        use std::io::Read;
+        let mut archive = self.archive.lock().unwrap();
+        let mut file = match archive.by_name(path) {
+            Result::Ok(file) => file,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Unable to read {}", path)),
+        };
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)
+            .with_context(|| format!("Unable to read {}", path))?;
+        Ok(Some(Cow::Owned(data)))
+    }
+}
+
+/// An uncompressed tar file, unpacked into memory when it's opened
+#[cfg(feature = "archives")]
+pub struct TarSource {
+    files: std::collections::HashMap<String, Vec<u8>>,
+}
+
+#[cfg(feature = "archives")]
+impl TarSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data =
+            std::fs::read(path).with_context(|| format!("Unable to read {}", path.display()))?;
+        Self::from_bytes(&data).with_context(|| format!("Unable to open {}", path.display()))
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        use std::io::Read;
+        let mut files = std::collections::HashMap::new();
+        let mut archive = tar::Archive::new(data);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = entry.path()?;
+            // Tools like to put "./" in front of everything
+            let path = path.strip_prefix(".").unwrap_or(&path);
+            let key = path
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            files.insert(key, data);
+        }
+        Ok(Self { files })
+    }
+}
+
+#[cfg(feature = "archives")]
+impl AssetSource for TarSource {
+    fn read(&self, path: &str) -> Result<Option<Cow<'_, [u8]>>> {
+        Ok(self.files.get(path).map(|data| Cow::Borrowed(&data[..])))
+    }
+}
+
+struct Mount {
+    priority: i32,
+    source: Box<dyn AssetSource>,
+}
+
+/// Folders, packs and archives layered on top of each other, so a sample
+/// scene can be dropped in as a single file and override the files it
+/// shares with the built in ones. Reads go to the source with the highest
+/// priority that has the file, and the last one mounted wins a tie.
+///
+/// ```ignore
+/// let mut sources = AssetSources::new();
+/// sources.mount_path("res", 0)?;
+/// // Needs the "archives" feature
+/// sources.mount_path("downloads/city.zip", 10)?;
+/// let model = sources.load_obj(&display.device, &display.queue, &mut assets, &layout, "city.obj")?;
+/// ```
+#[derive(Default)]
+pub struct AssetSources {
+    mounts: Vec<Mount>,
+}
+
+impl AssetSources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mount<S: AssetSource + 'static>(&mut self, source: S, priority: i32) -> &mut Self {
+        // Stable, so mounts with the same priority keep the newest first
+        let at = self.mounts.partition_point(|m| m.priority > priority);
+        self.mounts.insert(
+            at,
+            Mount {
+                priority,
+                source: Box::new(source),
+            },
+        );
+        self
+    }
+
+    /// Mounts a folder, an [AssetPack] or, with the "archives" feature, a
+    /// .zip or .tar file, going by the path
+    pub fn mount_path<P: AsRef<Path>>(&mut self, path: P, priority: i32) -> Result<&mut Self> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            return Ok(self.mount(DirSource::new(path), priority));
+        }
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("pack") => Ok(self.mount(AssetPack::open(path)?, priority)),
+            #[cfg(feature = "archives")]
+            Some("zip") => Ok(self.mount(ZipSource::open(path)?, priority)),
+            #[cfg(feature = "archives")]
+            Some("tar") => Ok(self.mount(TarSource::open(path)?, priority)),
+            _ => bail!("Don't know how to mount {}", path.display()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.mounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mounts.is_empty()
+    }
+
+    /// The file from the highest priority source that has it
+    pub fn read(&self, path: &str) -> Result<Cow<'_, [u8]>> {
+        for mount in &self.mounts {
+            if let Some(data) = mount.source.read(path)? {
+                return Ok(data);
+            }
+        }
+        bail!("{} isn't in any mounted source", path)
+    }
+
+    pub fn load_texture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &str,
+        is_normal_map: bool,
+    ) -> Result<Texture<'static>> {
+        Texture::from_bytes(device, queue, Some(path), is_normal_map, &self.read(path)?)
+            .with_context(|| format!("Unable to load {}", path))
+    }
+
+    /// Reads a WGSL file. Unlike [AssetPack::load_shader] this has to
+    /// copy it, so it's a `String` to go in a shader module descriptor.
+    pub fn load_shader(&self, path: &str) -> Result<String> {
+        String::from_utf8(self.read(path)?.into_owned())
+            .with_context(|| format!("{} isn't UTF-8", path))
+    }
+
+    /// Like [AssetPack::load_obj_with_options]. The .mtl and textures can
+    /// come from different sources than the .obj.
+    pub fn load_obj_with_options(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+        path: &str,
+        options: &LoadOptions,
+    ) -> Result<Model> {
+        load_obj_with(device, queue, assets, layout, path, options, |path| {
+            self.read(path)
+        })
+    }
+
+    pub fn load_obj(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+        path: &str,
+    ) -> Result<Model> {
+        self.load_obj_with_options(device, queue, assets, layout, path, &LoadOptions::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::PackBuilder;
+
+    fn pack(files: &[(&str, &str)]) -> AssetPack {
+        let mut builder = PackBuilder::new();
+        for (path, data) in files {
+            builder.add(path, data.as_bytes().to_vec());
+        }
+        AssetPack::from_bytes(builder.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn higher_priority_sources_win() {
+        let mut sources = AssetSources::new();
+        sources
+            .mount(pack(&[("a", "base"), ("b", "base")]), 0)
+            .mount(pack(&[("a", "scene")]), 10)
+            .mount(pack(&[("b", "newer")]), 0);
+        assert_eq!(&sources.read("a").unwrap()[..], b"scene");
+        // Same priority, so the later mount wins
+        assert_eq!(&sources.read("b").unwrap()[..], b"newer");
+        assert!(sources.read("c").is_err());
+        assert_eq!(sources.len(), 3);
+    }
+}
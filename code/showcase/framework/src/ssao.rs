@@ -0,0 +1,457 @@
+use anyhow::*;
+use cgmath::*;
+
+use crate::camera::{Camera, Projection};
+use crate::pipeline::{framework_shader, RenderPipelineBuilder};
+use crate::texture::Texture;
+
+/// WGSL for reading an [Ssao] bound to group 2, see
+/// [Ssao::layout_entry]. It has `ambient_occlusion(frag_coord)`.
+pub const SSAO_WGSL: &str = include_str!("ssao_sample.wgsl");
+
+/// The most kernel samples [SsaoSettings::samples] can ask for
+pub const MAX_SSAO_SAMPLES: u32 = 64;
+
+/// Quality and look of an [Ssao], changed with [Ssao::set_settings]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SsaoSettings {
+    /// How far around each point is searched for occluders, in world units
+    pub radius: f32,
+    /// Depth an occluder has to be in front by, to stop flat surfaces
+    /// shading themselves
+    pub bias: f32,
+    /// Darkens the result, 1 leaves it as is
+    pub intensity: f32,
+    /// Points tested per pixel, up to [MAX_SSAO_SAMPLES]. More cost more
+    /// and band less.
+    pub samples: u32,
+    /// Pixels the blur reaches each way. 2 hides the 4x4 noise.
+    pub blur_radius: u32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.0,
+            samples: 16,
+            blur_radius: 2,
+        }
+    }
+}
+
+/// What [Ssao] reads. Both have to be the size passed to [Ssao::new].
+pub struct SsaoInputs<'a> {
+    /// A depth texture with `TEXTURE_BINDING`, like
+    /// [crate::Display::create_depth_texture] or [crate::GBuffer::depth]
+    pub depth: &'a wgpu::TextureView,
+    /// World space normals in rgb, like [crate::GBuffer::normal]. Forward
+    /// renderers can leave it out to have normals rebuilt from depth.
+    pub normal: Option<&'a wgpu::TextureView>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniform {
+    projection: [[f32; 4]; 4],
+    inv_projection: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    samples: u32,
+    blur_radius: u32,
+    has_normals: u32,
+    _padding: [u32; 2],
+}
+
+/// Screen space ambient occlusion: darkens creases and corners that
+/// ambient light wouldn't reach. The result is 1 where nothing blocks
+/// ambient light and goes down to 0, for multiplying into ambient
+/// lighting. [crate::DeferredLighting::set_occlusion] takes it directly,
+/// and forward shaders can read it with [SSAO_WGSL].
+///
+/// ```ignore
+/// let mut ssao = Ssao::new(&display.device, width, height, SsaoInputs { depth: &depth.view, normal: None }, SsaoSettings::default())?;
+///
+/// // In Demo::render, after the depth buffer is drawn
+/// ssao.update(&display.queue, &camera, &projection);
+/// ssao.render(&mut encoder);
+/// ```
+pub struct Ssao {
+    settings: SsaoSettings,
+    uniform: SsaoUniform,
+    uniform_buffer: wgpu::Buffer,
+    input_layout: wgpu::BindGroupLayout,
+    occlusion_layout: wgpu::BindGroupLayout,
+    ssao_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    /// Bound when there's no normal texture, and never read
+    no_normals: wgpu::TextureView,
+    targets: SsaoTargets,
+}
+
+struct SsaoTargets {
+    noisy: Texture<'static>,
+    blurred: Texture<'static>,
+    input_bind_group: wgpu::BindGroup,
+    noisy_bind_group: wgpu::BindGroup,
+}
+
+impl Ssao {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        inputs: SsaoInputs,
+        settings: SsaoSettings,
+    ) -> Result<Self> {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Ssao::uniform_buffer"),
+            size: std::mem::size_of::<SsaoUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let input_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Ssao::input_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let occlusion_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Ssao::occlusion_layout"),
+            entries: &[Self::layout_entry(0)],
+        });
+
+        let ssao_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ssao::ssao_pipeline_layout"),
+            bind_group_layouts: &[&input_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = framework_shader("ssao.wgsl", include_str!("ssao.wgsl"));
+        let ssao_pipeline = RenderPipelineBuilder::new()
+            .layout(&ssao_pipeline_layout)
+            .vertex_shader(shader.module())
+            .fragment_shader(shader.module())
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_ssao")
+            .color_solid(Self::FORMAT)
+            .build(device)?;
+        let blur_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ssao::blur_pipeline_layout"),
+            bind_group_layouts: &[&input_layout, &occlusion_layout],
+            push_constant_ranges: &[],
+        });
+        let blur_pipeline = RenderPipelineBuilder::new()
+            .layout(&blur_pipeline_layout)
+            .vertex_shader(shader.module())
+            .fragment_shader(shader.module())
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_blur")
+            .color_solid(Self::FORMAT)
+            .build(device)?;
+
+        let no_normals = device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Ssao::no_normals"),
+                size: wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba16Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&Default::default());
+
+        let mut uniform = SsaoUniform {
+            projection: Matrix4::identity().into(),
+            inv_projection: Matrix4::identity().into(),
+            view: Matrix4::identity().into(),
+            radius: 0.0,
+            bias: 0.0,
+            intensity: 0.0,
+            samples: 0,
+            blur_radius: 0,
+            has_normals: inputs.normal.is_some() as u32,
+            _padding: [0; 2],
+        };
+        apply_settings(&mut uniform, settings);
+        let targets = SsaoTargets::new(
+            device,
+            &input_layout,
+            &occlusion_layout,
+            &uniform_buffer,
+            &no_normals,
+            width,
+            height,
+            &inputs,
+        );
+
+        Ok(Self {
+            settings,
+            uniform,
+            uniform_buffer,
+            input_layout,
+            occlusion_layout,
+            ssao_pipeline,
+            blur_pipeline,
+            no_normals,
+            targets,
+        })
+    }
+
+    pub fn settings(&self) -> SsaoSettings {
+        self.settings
+    }
+
+    /// Takes effect on the next [Ssao::update]
+    pub fn set_settings(&mut self, settings: SsaoSettings) {
+        self.settings = settings;
+        apply_settings(&mut self.uniform, settings);
+    }
+
+    /// Uploads the camera and settings. Call it each frame the camera
+    /// moves, before [Ssao::render].
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera, projection: &Projection) {
+        let proj = projection.calc_matrix();
+        self.uniform.projection = proj.into();
+        self.uniform.inv_projection = proj.invert().unwrap_or_else(Matrix4::identity).into();
+        self.uniform.view = camera.calc_matrix().into();
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&self.uniform));
+    }
+
+    /// Remakes the output at the inputs' new size
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32, inputs: SsaoInputs) {
+        self.uniform.has_normals = inputs.normal.is_some() as u32;
+        self.targets = SsaoTargets::new(
+            device,
+            &self.input_layout,
+            &self.occlusion_layout,
+            &self.uniform_buffer,
+            &self.no_normals,
+            width,
+            height,
+            &inputs,
+        );
+    }
+
+    /// Draws the occlusion and blurs it into [Ssao::view]
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder) {
+        crate::cpu_scope!("Ssao::render");
+        let targets = &self.targets;
+        {
+            let mut pass = begin_pass(encoder, "Ssao::ssao", &targets.noisy.view);
+            pass.set_pipeline(&self.ssao_pipeline);
+            pass.set_bind_group(0, &targets.input_bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        let mut pass = begin_pass(encoder, "Ssao::blur", &targets.blurred.view);
+        pass.set_pipeline(&self.blur_pipeline);
+        pass.set_bind_group(0, &targets.input_bind_group, &[]);
+        pass.set_bind_group(1, &targets.noisy_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    /// The blurred occlusion in r
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.targets.blurred.view
+    }
+
+    /// The occlusion texture at `binding`. [SSAO_WGSL] expects 5 in the
+    /// light's bind group.
+    pub fn layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        }
+    }
+
+    /// The resource for [Ssao::layout_entry]. It changes on
+    /// [Ssao::resize], so bind groups with it need remaking then.
+    pub fn bind_group_entry(&self, binding: u32) -> wgpu::BindGroupEntry<'_> {
+        wgpu::BindGroupEntry {
+            binding,
+            resource: wgpu::BindingResource::TextureView(self.view()),
+        }
+    }
+}
+
+impl SsaoTargets {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        device: &wgpu::Device,
+        input_layout: &wgpu::BindGroupLayout,
+        occlusion_layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        no_normals: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+        inputs: &SsaoInputs,
+    ) -> Self {
+        let target = |label| {
+            Texture::from_descriptor(
+                device,
+                wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: width.max(1),
+                        height: height.max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: Ssao::FORMAT,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                },
+            )
+        };
+        let noisy = target("Ssao::noisy");
+        let blurred = target("Ssao::blurred");
+        let input_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Ssao::input_bind_group"),
+            layout: input_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(inputs.depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        inputs.normal.unwrap_or(no_normals),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let noisy_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Ssao::noisy_bind_group"),
+            layout: occlusion_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&noisy.view),
+            }],
+        });
+        Self {
+            noisy,
+            blurred,
+            input_bind_group,
+            noisy_bind_group,
+        }
+    }
+}
+
+fn apply_settings(uniform: &mut SsaoUniform, settings: SsaoSettings) {
+    uniform.radius = settings.radius;
+    uniform.bias = settings.bias;
+    uniform.intensity = settings.intensity;
+    uniform.samples = settings.samples.clamp(1, MAX_SSAO_SAMPLES);
+    uniform.blur_radius = settings.blur_radius;
+}
+
+fn begin_pass<'e>(
+    encoder: &'e mut wgpu::CommandEncoder,
+    label: &str,
+    view: &wgpu::TextureView,
+) -> wgpu::RenderPass<'e> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssao_wgsl_validates() {
+        crate::shader::validate_wgsl(
+            &framework_shader("ssao.wgsl", include_str!("ssao.wgsl")).source,
+        )
+        .unwrap();
+        crate::shader::validate_wgsl(SSAO_WGSL).unwrap();
+        // Has to match the WGSL struct's layout
+        assert_eq!(std::mem::size_of::<SsaoUniform>(), 224);
+    }
+
+    #[test]
+    fn settings_clamp_sample_count() {
+        let mut uniform: SsaoUniform = bytemuck::Zeroable::zeroed();
+        let settings = SsaoSettings {
+            samples: 0,
+            ..Default::default()
+        };
+        apply_settings(&mut uniform, settings);
+        assert_eq!(uniform.samples, 1);
+        apply_settings(
+            &mut uniform,
+            SsaoSettings {
+                samples: MAX_SSAO_SAMPLES + 1,
+                ..settings
+            },
+        );
+        assert_eq!(uniform.samples, MAX_SSAO_SAMPLES);
+        assert_eq!(uniform.radius, settings.radius);
+        assert_eq!(uniform.blur_radius, settings.blur_radius);
+    }
+}
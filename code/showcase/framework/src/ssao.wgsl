@@ -0,0 +1,150 @@
+// Screen space ambient occlusion. fs_ssao counts how much of a hemisphere
+// of points around each pixel is behind the depth buffer, and fs_blur
+// smooths out the noise that comes from turning the hemisphere each pixel.
+
+struct SsaoUniform {
+    projection: mat4x4<f32>,
+    inv_projection: mat4x4<f32>,
+    view: mat4x4<f32>,
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    samples: u32,
+    blur_radius: u32,
+    // 0 when normals are rebuilt from depth
+    has_normals: u32,
+    _padding: vec2<u32>,
+}
+
+@group(0) @binding(0)
+var depth_texture: texture_depth_2d;
+@group(0) @binding(1)
+var normal_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> ssao: SsaoUniform;
+
+@group(1) @binding(0)
+var occlusion_texture: texture_2d<f32>;
+
+const SSAO_TAU: f32 = 6.2831853;
+
+#include "framework/fullscreen.wgsl"
+
+fn clamp_coord(coord: vec2<i32>) -> vec2<i32> {
+    return clamp(coord, vec2<i32>(0), vec2<i32>(textureDimensions(depth_texture)) - 1);
+}
+
+// The view space position of what was drawn at `coord`
+fn view_position(coord: vec2<i32>) -> vec3<f32> {
+    let c = clamp_coord(coord);
+    let size = vec2<f32>(textureDimensions(depth_texture));
+    let depth = textureLoad(depth_texture, c, 0);
+    let uv = (vec2<f32>(c) + 0.5) / size;
+    let p = ssao.inv_projection * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    return p.xyz / p.w;
+}
+
+// The view space normal at `coord`, from the normal texture or from the
+// neighbours on whichever side is closer in depth, so edges stay sharp
+fn view_normal(coord: vec2<i32>, p: vec3<f32>) -> vec3<f32> {
+    if (ssao.has_normals == 1u) {
+        let world = textureLoad(normal_texture, coord, 0).xyz;
+        return normalize((ssao.view * vec4<f32>(world, 0.0)).xyz);
+    }
+    let right = view_position(coord + vec2<i32>(1, 0)) - p;
+    let left = p - view_position(coord - vec2<i32>(1, 0));
+    let down = view_position(coord + vec2<i32>(0, 1)) - p;
+    let up = p - view_position(coord - vec2<i32>(0, 1));
+    let dx = select(left, right, abs(right.z) < abs(left.z));
+    let dy = select(up, down, abs(down.z) < abs(up.z));
+    var n = normalize(cross(dy, dx));
+    // Face the camera, which looks down -z
+    if (dot(n, p) > 0.0) {
+        n = -n;
+    }
+    return n;
+}
+
+// Van der Corput, for spreading kernel samples evenly
+fn radical_inverse(index: u32) -> f32 {
+    var bits = index;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064e-10;
+}
+
+// Kernel sample `i` of `count` in a hemisphere around +z, turned by
+// `angle`. More of them land close to the middle, where occlusion
+// matters most.
+fn kernel_sample(i: u32, count: u32, angle: f32) -> vec3<f32> {
+    let u = (f32(i) + 0.5) / f32(count);
+    let phi = radical_inverse(i) * SSAO_TAU + angle;
+    let sin_theta = sqrt(u);
+    let direction = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, sqrt(1.0 - u));
+    let t = f32(i + 1u) / f32(count);
+    return direction * mix(0.1, 1.0, t * t);
+}
+
+@fragment
+fn fs_ssao(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(in.clip_position.xy);
+    // Nothing was drawn here
+    if (textureLoad(depth_texture, coord, 0) >= 1.0) {
+        return vec4<f32>(1.0);
+    }
+    let p = view_position(coord);
+    let n = view_normal(coord, p);
+    var helper = vec3<f32>(1.0, 0.0, 0.0);
+    if (abs(n.x) > 0.9) {
+        helper = vec3<f32>(0.0, 1.0, 0.0);
+    }
+    let t = normalize(cross(helper, n));
+    let b = cross(n, t);
+
+    // Turns repeat every 4x4 pixels, which the blur then averages away
+    let cell = (u32(coord.x) & 3u) + (u32(coord.y) & 3u) * 4u;
+    let angle = f32((cell * 7u) % 16u) / 16.0 * SSAO_TAU;
+
+    let size = vec2<f32>(textureDimensions(depth_texture));
+    let count = max(ssao.samples, 1u);
+    var occlusion = 0.0;
+    for (var i = 0u; i < count; i++) {
+        let k = kernel_sample(i, count, angle);
+        let s = p + (t * k.x + b * k.y + n * k.z) * ssao.radius;
+        let clip = ssao.projection * vec4<f32>(s, 1.0);
+        let ndc = clip.xy / clip.w;
+        let sample_coord = vec2<i32>(vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5) * size);
+        let scene_z = view_position(sample_coord).z;
+        // Things far in front of the point don't shade it
+        let in_range = smoothstep(0.0, 1.0, ssao.radius / max(abs(p.z - scene_z), 0.0001));
+        if (scene_z >= s.z + ssao.bias) {
+            occlusion += in_range;
+        }
+    }
+    let ao = pow(clamp(1.0 - occlusion / f32(count), 0.0, 1.0), ssao.intensity);
+    return vec4<f32>(ao, ao, ao, 1.0);
+}
+
+// A box blur that skips neighbours at a different depth
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(in.clip_position.xy);
+    let z = view_position(coord).z;
+    let r = i32(ssao.blur_radius);
+    let max_coord = vec2<i32>(textureDimensions(occlusion_texture)) - 1;
+    var total = 0.0;
+    var weights = 0.0;
+    for (var y = -r; y <= r; y++) {
+        for (var x = -r; x <= r; x++) {
+            let c = clamp(coord + vec2<i32>(x, y), vec2<i32>(0), max_coord);
+            let weight = select(0.0, 1.0, abs(view_position(c).z - z) < ssao.radius);
+            total += textureLoad(occlusion_texture, c, 0).r * weight;
+            weights += weight;
+        }
+    }
+    let ao = total / max(weights, 1.0);
+    return vec4<f32>(ao, ao, ao, 1.0);
+}
@@ -0,0 +1,11 @@
+// Reads an Ssao bound to group 2 at binding 5, after the light and an
+// EnvironmentMap. It has to be the size of the target being drawn.
+
+@group(2) @binding(5)
+var ssao_texture: texture_2d<f32>;
+
+// How much ambient light reaches the fragment at `frag_coord`, its
+// @builtin(position). Multiply it into the surface's occlusion.
+fn ambient_occlusion(frag_coord: vec4<f32>) -> f32 {
+    return textureLoad(ssao_texture, vec2<i32>(frag_coord.xy), 0).r;
+}
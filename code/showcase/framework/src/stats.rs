@@ -0,0 +1,362 @@
+use std::ops::{AddAssign, Range};
+
+use crate::model::{DrawModel, Material, Mesh, Model, DRAW_INDEXED_INDIRECT_SIZE};
+
+const MAX_BIND_GROUPS: usize = 8;
+
+/// What a frame asked the GPU to do. Draws and state changes made through
+/// a [CountingPass] are tallied automatically; culling happens outside of
+/// render passes, so culling code adds to `culled_cpu` and `culled_gpu`
+/// itself.
+///
+/// The framework keeps the current frame's stats in
+/// [crate::Display::frame_stats], and the previous frame's in
+/// [crate::Display::last_frame_stats].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub draw_calls: u32,
+    pub instances: u32,
+    /// Assumes triangle lists
+    pub triangles: u64,
+    /// Instances skipped by culling on the CPU
+    pub culled_cpu: u32,
+    /// Instances skipped by culling on the GPU, once read back
+    pub culled_gpu: u32,
+    /// Pipeline switches, not counting setting the one that's already set
+    pub pipeline_changes: u32,
+    /// Like `pipeline_changes`, but for bind groups
+    pub bind_group_changes: u32,
+    pub buffer_changes: u32,
+}
+
+impl FrameStats {
+    fn add_draw(&mut self, vertices: Range<u32>, instances: Range<u32>) {
+        let instances = instances.end.saturating_sub(instances.start);
+        self.draw_calls += 1;
+        self.instances += instances;
+        self.triangles +=
+            (vertices.end.saturating_sub(vertices.start) / 3) as u64 * instances as u64;
+    }
+}
+
+impl AddAssign for FrameStats {
+    fn add_assign(&mut self, other: Self) {
+        self.draw_calls += other.draw_calls;
+        self.instances += other.instances;
+        self.triangles += other.triangles;
+        self.culled_cpu += other.culled_cpu;
+        self.culled_gpu += other.culled_gpu;
+        self.pipeline_changes += other.pipeline_changes;
+        self.bind_group_changes += other.bind_group_changes;
+        self.buffer_changes += other.buffer_changes;
+    }
+}
+
+/// How many of each kind of GPU resource are alive, from
+/// [crate::Display::resource_counts]. A count that keeps climbing from
+/// frame to frame is a leak.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ResourceCounts {
+    pub buffers: usize,
+    pub textures: usize,
+    pub texture_views: usize,
+    pub samplers: usize,
+    pub bind_groups: usize,
+    pub render_pipelines: usize,
+    pub compute_pipelines: usize,
+    pub shader_modules: usize,
+    pub query_sets: usize,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<&wgpu::core::hub::HubReport> for ResourceCounts {
+    fn from(report: &wgpu::core::hub::HubReport) -> Self {
+        Self {
+            buffers: report.buffers.num_allocated,
+            textures: report.textures.num_allocated,
+            texture_views: report.texture_views.num_allocated,
+            samplers: report.samplers.num_allocated,
+            bind_groups: report.bind_groups.num_allocated,
+            render_pipelines: report.render_pipelines.num_allocated,
+            compute_pipelines: report.compute_pipelines.num_allocated,
+            shader_modules: report.shader_modules.num_allocated,
+            query_sets: report.query_sets.num_allocated,
+        }
+    }
+}
+
+/// Wraps a render pass and tallies what goes through it into
+/// [FrameStats]. It has the same draw and state methods as
+/// [wgpu::RenderPass], and [DrawModel] works on it too.
+///
+/// ```ignore
+/// let mut pass = CountingPass::new(&mut render_pass, &mut display.frame_stats);
+/// pass.set_pipeline(&self.pipeline);
+/// pass.draw_model(&self.model, &self.camera_bind_group, &self.light_bind_group);
+/// ```
+pub struct CountingPass<'p, 'a> {
+    pass: &'p mut wgpu::RenderPass<'a>,
+    stats: &'p mut FrameStats,
+    pipeline: Option<wgpu::Id<wgpu::RenderPipeline>>,
+    bind_groups: [Option<wgpu::Id<wgpu::BindGroup>>; MAX_BIND_GROUPS],
+}
+
+impl<'p, 'a> CountingPass<'p, 'a> {
+    pub fn new(pass: &'p mut wgpu::RenderPass<'a>, stats: &'p mut FrameStats) -> Self {
+        Self {
+            pass,
+            stats,
+            pipeline: None,
+            bind_groups: [None; MAX_BIND_GROUPS],
+        }
+    }
+
+    /// The wrapped pass, for anything that shouldn't be counted
+    pub fn pass(&mut self) -> &mut wgpu::RenderPass<'a> {
+        self.pass
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: &'a wgpu::RenderPipeline) {
+        if self.pipeline != Some(pipeline.global_id()) {
+            self.pipeline = Some(pipeline.global_id());
+            self.stats.pipeline_changes += 1;
+        }
+        self.pass.set_pipeline(pipeline);
+    }
+
+    pub fn set_bind_group(
+        &mut self,
+        index: u32,
+        bind_group: &'a wgpu::BindGroup,
+        offsets: &[wgpu::DynamicOffset],
+    ) {
+        // Dynamic offsets change what's bound even if the group doesn't
+        match self.bind_groups.get_mut(index as usize) {
+            Some(current) if offsets.is_empty() && *current == Some(bind_group.global_id()) => {}
+            Some(current) => {
+                *current = Some(bind_group.global_id());
+                self.stats.bind_group_changes += 1;
+            }
+            None => self.stats.bind_group_changes += 1,
+        }
+        self.pass.set_bind_group(index, bind_group, offsets);
+    }
+
+    pub fn set_vertex_buffer(&mut self, slot: u32, buffer_slice: wgpu::BufferSlice<'a>) {
+        self.stats.buffer_changes += 1;
+        self.pass.set_vertex_buffer(slot, buffer_slice);
+    }
+
+    pub fn set_index_buffer(
+        &mut self,
+        buffer_slice: wgpu::BufferSlice<'a>,
+        index_format: wgpu::IndexFormat,
+    ) {
+        self.stats.buffer_changes += 1;
+        self.pass.set_index_buffer(buffer_slice, index_format);
+    }
+
+    pub fn draw(&mut self, vertices: Range<u32>, instances: Range<u32>) {
+        self.stats.add_draw(vertices.clone(), instances.clone());
+        self.pass.draw(vertices, instances);
+    }
+
+    pub fn draw_indexed(&mut self, indices: Range<u32>, base_vertex: i32, instances: Range<u32>) {
+        self.stats.add_draw(indices.clone(), instances.clone());
+        self.pass.draw_indexed(indices, base_vertex, instances);
+    }
+
+    /// Counts as a draw call, but what's drawn is up to the GPU so
+    /// instances and triangles aren't counted
+    pub fn draw_indexed_indirect(
+        &mut self,
+        indirect_buffer: &'a wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+    ) {
+        self.stats.draw_calls += 1;
+        self.pass
+            .draw_indexed_indirect(indirect_buffer, indirect_offset);
+    }
+
+    /// Counts as `count` draw calls, like [CountingPass::draw_indexed_indirect]
+    pub fn multi_draw_indexed_indirect(
+        &mut self,
+        indirect_buffer: &'a wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        count: u32,
+    ) {
+        self.stats.draw_calls += count;
+        self.pass
+            .multi_draw_indexed_indirect(indirect_buffer, indirect_offset, count);
+    }
+}
+
+impl<'p, 'a, 'b> DrawModel<'b> for CountingPass<'p, 'a>
+where
+    'b: 'a,
+{
+    fn draw_mesh(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group, light_bind_group);
+    }
+
+    fn draw_mesh_instanced(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+
+    fn draw_model(
+        &mut self,
+        model: &'b Model,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.draw_model_instanced(model, 0..1, camera_bind_group, light_bind_group);
+    }
+
+    fn draw_model_instanced(
+        &mut self,
+        model: &'b Model,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for mesh in &model.meshes {
+            let material = &model.materials[mesh.material];
+            self.draw_mesh_instanced(
+                mesh,
+                material,
+                instances.clone(),
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
+
+    fn draw_model_instanced_with_material(
+        &mut self,
+        model: &'b Model,
+        material: &'b Material,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for mesh in &model.meshes {
+            self.draw_mesh_instanced(
+                mesh,
+                material,
+                instances.clone(),
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
+
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        indirect: &'b wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed_indirect(indirect, offset);
+    }
+
+    fn draw_mesh_multi_indirect(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        indirect: &'b wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        count: u32,
+        capabilities: &crate::Capabilities,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        if !capabilities.multi_draw_indirect {
+            for i in 0..count as wgpu::BufferAddress {
+                let offset = offset + i * DRAW_INDEXED_INDIRECT_SIZE;
+                self.draw_mesh_indirect(
+                    mesh,
+                    material,
+                    indirect,
+                    offset,
+                    camera_bind_group,
+                    light_bind_group,
+                );
+            }
+            return;
+        }
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.multi_draw_indexed_indirect(indirect, offset, count);
+    }
+
+    fn draw_model_indirect(
+        &mut self,
+        model: &'b Model,
+        indirect: &'b wgpu::Buffer,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for (i, mesh) in model.meshes.iter().enumerate() {
+            self.draw_mesh_indirect(
+                mesh,
+                &model.materials[mesh.material],
+                indirect,
+                i as wgpu::BufferAddress * DRAW_INDEXED_INDIRECT_SIZE,
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_add_up() {
+        let mut stats = FrameStats::default();
+        stats.add_draw(0..36, 0..10);
+        stats.add_draw(6..12, 2..3);
+        assert_eq!(stats.draw_calls, 2);
+        assert_eq!(stats.instances, 11);
+        assert_eq!(stats.triangles, 12 * 10 + 2);
+
+        let mut total = FrameStats {
+            culled_cpu: 4,
+            ..Default::default()
+        };
+        total += stats;
+        assert_eq!(total.culled_cpu, 4);
+        assert_eq!(total.triangles, 122);
+    }
+}
@@ -0,0 +1,558 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use anyhow::*;
+use winit::keyboard::KeyCode;
+
+use crate::color::Color;
+use crate::cpu_profiler::{cpu_frame_timings, CpuScopeTiming};
+use crate::gpu_profiler::GpuScopeTiming;
+use crate::input::Input;
+use crate::pipeline::RenderPipelineBuilder;
+use crate::stats::{FrameStats, ResourceCounts};
+
+/// Frames kept for the graph and the averages, about 2 seconds at 60 FPS
+const HISTORY: usize = 120;
+/// Screen pixels for each pixel of the font
+const SCALE: f32 = 2.0;
+const MARGIN: f32 = 8.0;
+const PADDING: f32 = 6.0;
+const BAR_WIDTH: f32 = 2.0;
+const GRAPH_HEIGHT: f32 = 40.0;
+/// The frame time at the top of the graph, in seconds. Slower frames are
+/// cut off.
+const GRAPH_MAX: f32 = 1.0 / 20.0;
+const TARGET: f32 = 1.0 / 60.0;
+/// Scopes listed under CPU and under GPU. The inspector lists them all.
+const MAX_SCOPES: usize = 8;
+
+const INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+    wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OverlayUniform {
+    screen_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct RectInstance {
+    rect: [f32; 4],
+    color: [f32; 4],
+}
+
+/// FPS, a graph of recent frame times, what the frame drew and how many
+/// GPU resources are alive, in the top left corner. [crate::run] times
+/// every frame and [crate::Display::present] draws it, so demos that
+/// present through the display get it without doing anything.
+///
+/// Draw counts come from [crate::Display::frame_stats], so they only
+/// cover passes wrapped in a [crate::CountingPass]. Under them are the
+/// last frame's [crate::cpu_scope!] times, and the GPU times given to
+/// [StatsOverlay::set_gpu_timings]. The text is a built in 3 by 5 pixel
+/// font, so no font file is needed.
+pub struct StatsOverlay {
+    /// Shows and hides the overlay. Defaults to F3.
+    pub key: KeyCode,
+    visible: bool,
+    /// In seconds, oldest first
+    frame_times: VecDeque<f32>,
+    last_frame: Option<Instant>,
+    gpu_timings: Vec<GpuScopeTiming>,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    instance_buffer: wgpu::Buffer,
+}
+
+impl StatsOverlay {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat) -> Result<Self> {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("StatsOverlay::layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("StatsOverlay::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(wgpu::include_wgsl!("stats_overlay.wgsl"))
+            .fragment_shader(wgpu::include_wgsl!("stats_overlay.wgsl"))
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .primitive_topology(wgpu::PrimitiveTopology::TriangleStrip)
+            .vertex_buffer_desc(wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<RectInstance>() as _,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &INSTANCE_ATTRIBUTES,
+            })
+            .color_state(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+            .build(device)?;
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("StatsOverlay::uniform_buffer"),
+            size: std::mem::size_of::<OverlayUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("StatsOverlay::bind_group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        Ok(Self {
+            key: KeyCode::F3,
+            visible: false,
+            frame_times: VecDeque::with_capacity(HISTORY),
+            last_frame: None,
+            gpu_timings: Vec::new(),
+            pipeline,
+            uniform_buffer,
+            bind_group,
+            instance_buffer: create_instance_buffer(device, 1024),
+        })
+    }
+
+    /// Handles the key binding
+    pub fn update(&mut self, input: &Input) {
+        if input.is_key_pressed(self.key) {
+            self.visible = !self.visible;
+            log::info!(
+                "Stats overlay {}",
+                if self.visible { "shown" } else { "hidden" }
+            );
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    /// Marks the start of a frame, timing the one before it by the wall
+    /// clock. Paused or scaled [crate::Time] doesn't change it.
+    pub fn begin_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last_frame {
+            self.push_frame_time(now - last);
+        }
+        self.last_frame = Some(now);
+    }
+
+    /// Adds a frame to the history, for frames timed some other way
+    pub fn push_frame_time(&mut self, frame_time: Duration) {
+        if self.frame_times.len() == HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time.as_secs_f32());
+    }
+
+    /// Shows a [crate::GpuProfiler]'s pass times next to the CPU ones.
+    /// Demos call this with [crate::GpuProfiler::last_frame] after
+    /// ending the profiler's frame.
+    pub fn set_gpu_timings(&mut self, timings: &[GpuScopeTiming]) {
+        self.gpu_timings.clear();
+        self.gpu_timings.extend_from_slice(timings);
+    }
+
+    /// Averaged over the frames in the graph
+    pub fn fps(&self) -> f32 {
+        let total: f32 = self.frame_times.iter().sum();
+        if total > 0.0 {
+            self.frame_times.len() as f32 / total
+        } else {
+            0.0
+        }
+    }
+
+    /// The slowest frame in the graph
+    pub fn worst_frame_time(&self) -> Duration {
+        let worst = self.frame_times.iter().copied().fold(0.0, f32::max);
+        Duration::from_secs_f32(worst)
+    }
+
+    /// Draws over `view`, which should be `width` by `height` and in the
+    /// format the overlay was created with. Does nothing while hidden.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+        stats: &FrameStats,
+        resources: Option<&ResourceCounts>,
+    ) {
+        if !self.visible {
+            return;
+        }
+        crate::cpu_scope!("StatsOverlay::render");
+        let rects = self.layout(stats, resources, &cpu_frame_timings());
+        let uniform = OverlayUniform {
+            screen_size: [width as f32, height as f32],
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        let needed = (rects.len() * std::mem::size_of::<RectInstance>()) as u64;
+        if needed > self.instance_buffer.size() {
+            self.instance_buffer = create_instance_buffer(device, rects.len().next_power_of_two());
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&rects));
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("StatsOverlay"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        pass.draw(0..4, 0..rects.len() as u32);
+    }
+
+    /// The panel, text and graph as rectangles, back to front
+    fn layout(
+        &self,
+        stats: &FrameStats,
+        resources: Option<&ResourceCounts>,
+        cpu_timings: &[CpuScopeTiming],
+    ) -> Vec<RectInstance> {
+        let mut lines = stat_lines(self.fps(), self.worst_frame_time(), stats, resources);
+        lines.extend(timing_lines(cpu_timings, &self.gpu_timings));
+        let longest = lines.iter().map(|l| l.len()).max().unwrap_or(0) as f32;
+        let text_width = longest * GLYPH_ADVANCE * SCALE;
+        let text_height = lines.len() as f32 * LINE_HEIGHT * SCALE;
+        let panel_width = text_width.max(HISTORY as f32 * BAR_WIDTH) + PADDING * 2.0;
+        let panel_height = text_height + GRAPH_HEIGHT + PADDING * 3.0;
+
+        let mut rects = Vec::new();
+        push_rect(
+            &mut rects,
+            [MARGIN, MARGIN, panel_width, panel_height],
+            Color::BLACK.with_alpha(0.6),
+        );
+        let (left, top) = (MARGIN + PADDING, MARGIN + PADDING);
+        for (i, line) in lines.iter().enumerate() {
+            let y = top + i as f32 * LINE_HEIGHT * SCALE;
+            push_text(&mut rects, line, left, y, Color::WHITE);
+        }
+
+        let bottom = top + text_height + PADDING + GRAPH_HEIGHT;
+        for (i, &frame_time) in self.frame_times.iter().enumerate() {
+            let height = (frame_time / GRAPH_MAX).min(1.0) * GRAPH_HEIGHT;
+            let x = left + (HISTORY - self.frame_times.len() + i) as f32 * BAR_WIDTH;
+            push_rect(
+                &mut rects,
+                [x, bottom - height, BAR_WIDTH, height],
+                frame_time_color(frame_time),
+            );
+        }
+        let target = bottom - TARGET / GRAPH_MAX * GRAPH_HEIGHT;
+        push_rect(
+            &mut rects,
+            [left, target, HISTORY as f32 * BAR_WIDTH, 1.0],
+            Color::WHITE.with_alpha(0.5),
+        );
+        rects
+    }
+}
+
+/// Green up to 60 FPS, yellow to 30 and red below that
+fn frame_time_color(frame_time: f32) -> Color {
+    if frame_time <= TARGET * 1.05 {
+        Color::GREEN
+    } else if frame_time <= TARGET * 2.1 {
+        Color::YELLOW
+    } else {
+        Color::RED
+    }
+}
+
+/// The overlay's text, one line each
+fn stat_lines(
+    fps: f32,
+    worst: Duration,
+    stats: &FrameStats,
+    resources: Option<&ResourceCounts>,
+) -> Vec<String> {
+    let frame_ms = if fps > 0.0 { 1000.0 / fps } else { 0.0 };
+    let mut lines = vec![
+        format!("FPS {:.1}", fps),
+        format!("FRAME {:.2} MS", frame_ms),
+        format!("WORST {:.2} MS", worst.as_secs_f32() * 1000.0),
+        format!("DRAWS {}", short_count(stats.draw_calls as u64)),
+        format!("TRIANGLES {}", short_count(stats.triangles)),
+        format!("INSTANCES {}", short_count(stats.instances as u64)),
+        format!(
+            "CULLED {}",
+            short_count((stats.culled_cpu + stats.culled_gpu) as u64)
+        ),
+        format!(
+            "SWITCHES {} / {}",
+            stats.pipeline_changes, stats.bind_group_changes
+        ),
+    ];
+    if let Some(r) = resources {
+        lines.extend([
+            format!("BUFFERS {}", r.buffers),
+            format!("TEXTURES {} / {}", r.textures, r.texture_views),
+            format!("BIND GROUPS {}", r.bind_groups),
+            format!("PIPELINES {} / {}", r.render_pipelines, r.compute_pipelines),
+        ]);
+    }
+    lines
+}
+
+/// A CPU and a GPU section, each listing scopes indented by how deeply
+/// they're nested. Empty sections are left out.
+fn timing_lines(cpu: &[CpuScopeTiming], gpu: &[GpuScopeTiming]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut section = |title: &str, scopes: Vec<(usize, &str, Duration)>| {
+        if scopes.is_empty() {
+            return;
+        }
+        lines.push(title.to_string());
+        for &(depth, name, duration) in scopes.iter().take(MAX_SCOPES) {
+            lines.push(format!(
+                "{}{} {:.2} MS",
+                " ".repeat(depth + 1),
+                name,
+                duration.as_secs_f32() * 1000.0
+            ));
+        }
+        if scopes.len() > MAX_SCOPES {
+            lines.push(format!(" {} MORE", scopes.len() - MAX_SCOPES));
+        }
+    };
+    section(
+        "CPU",
+        cpu.iter().map(|t| (t.depth, t.name, t.duration)).collect(),
+    );
+    section(
+        "GPU",
+        gpu.iter().map(|t| (t.depth, t.name, t.duration)).collect(),
+    );
+    lines
+}
+
+/// Big counts to 3 significant figures with a K or M on the end
+fn short_count(n: u64) -> String {
+    match n {
+        0..=9_999 => n.to_string(),
+        10_000..=999_999 => format!("{:.1}K", n as f32 / 1e3),
+        _ => format!("{:.2}M", n as f32 / 1e6),
+    }
+}
+
+/// Font pixels from one glyph's left to the next's
+const GLYPH_ADVANCE: f32 = 4.0;
+/// Font pixels from one line's top to the next's
+const LINE_HEIGHT: f32 = 7.0;
+
+fn push_rect(rects: &mut Vec<RectInstance>, rect: [f32; 4], color: Color) {
+    rects.push(RectInstance {
+        rect,
+        color: color.into(),
+    });
+}
+
+/// One rectangle per lit pixel of each glyph. Lower case is drawn as
+/// upper case, and anything the font lacks as a space.
+fn push_text(rects: &mut Vec<RectInstance>, text: &str, x: f32, y: f32, color: Color) {
+    for (i, c) in text.chars().enumerate() {
+        let left = x + i as f32 * GLYPH_ADVANCE * SCALE;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 {
+                    let px = [
+                        left + col as f32 * SCALE,
+                        y + row as f32 * SCALE,
+                        SCALE,
+                        SCALE,
+                    ];
+                    push_rect(rects, px, color);
+                }
+            }
+        }
+    }
+}
+
+/// Rows of a 3 by 5 pixel glyph, top first, with the left pixel in the
+/// highest bit
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        _ => [0; 5],
+    }
+}
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("StatsOverlay::instance_buffer"),
+        size: (capacity.max(1) * std::mem::size_of::<RectInstance>()) as u64,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_overlay_wgsl_validates() {
+        crate::shader::validate_wgsl(include_str!("stats_overlay.wgsl")).unwrap();
+    }
+
+    #[test]
+    fn text_and_counts_lay_out() {
+        assert_eq!(short_count(9_999), "9999");
+        assert_eq!(short_count(12_345), "12.3K");
+        assert_eq!(short_count(2_500_000), "2.50M");
+
+        let mut rects = Vec::new();
+        push_text(&mut rects, "1 x", 10.0, 20.0, Color::WHITE);
+        // 8 pixels in the 1, none in the space and 9 in the x
+        assert_eq!(rects.len(), 17);
+        assert_eq!(rects[0].rect, [10.0 + SCALE, 20.0, SCALE, SCALE]);
+        let x_left = 10.0 + 2.0 * GLYPH_ADVANCE * SCALE;
+        assert!(rects[8..].iter().all(|r| r.rect[0] >= x_left));
+
+        let lines = stat_lines(
+            60.0,
+            Duration::from_millis(20),
+            &FrameStats::default(),
+            None,
+        );
+        assert_eq!(lines[0], "FPS 60.0");
+        assert_eq!(lines[1], "FRAME 16.67 MS");
+        assert_eq!(lines[2], "WORST 20.00 MS");
+        let with_resources = stat_lines(
+            60.0,
+            Duration::ZERO,
+            &FrameStats::default(),
+            Some(&ResourceCounts::default()),
+        );
+        assert_eq!(with_resources.len(), lines.len() + 4);
+        // Everything the overlay says can be drawn
+        for line in with_resources {
+            assert!(
+                line.chars().all(|c| c == ' ' || glyph(c) != [0; 5]),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn scope_times_are_listed_by_section() {
+        assert!(timing_lines(&[], &[]).is_empty());
+
+        let cpu = [
+            CpuScopeTiming {
+                name: "render",
+                depth: 0,
+                duration: Duration::from_micros(2500),
+                calls: 1,
+            },
+            CpuScopeTiming {
+                name: "shadows",
+                depth: 1,
+                duration: Duration::from_micros(500),
+                calls: 3,
+            },
+        ];
+        let gpu = vec![
+            GpuScopeTiming {
+                name: "pass",
+                depth: 0,
+                duration: Duration::from_millis(1),
+            };
+            MAX_SCOPES + 2
+        ];
+        let lines = timing_lines(&cpu, &gpu);
+        assert_eq!(lines[..3], ["CPU", " render 2.50 MS", "  shadows 0.50 MS"]);
+        assert_eq!(lines[3], "GPU");
+        assert_eq!(lines[4], " pass 1.00 MS");
+        assert_eq!(lines.len(), 4 + MAX_SCOPES + 1);
+        assert_eq!(lines.last().unwrap(), " 2 MORE");
+
+        let only_gpu = timing_lines(&[], &gpu[..1]);
+        assert_eq!(only_gpu, ["GPU", " pass 1.00 MS"]);
+    }
+}
@@ -0,0 +1,41 @@
+// Solid screen space rectangles for StatsOverlay. Positions are in
+// pixels from the top left.
+
+struct OverlayUniform {
+    screen_size: vec2<f32>,
+    _padding: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> overlay: OverlayUniform;
+
+struct RectInput {
+    // x, y, width, height in pixels
+    @location(0) rect: vec4<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+// Drawn as a 4 vertex triangle strip per rectangle
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32, rect: RectInput) -> VertexOutput {
+    let corner = vec2<f32>(f32(i & 1u), f32(i >> 1u));
+    let pixel = rect.rect.xy + corner * rect.rect.zw;
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(
+        pixel / overlay.screen_size * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0),
+        0.0,
+        1.0,
+    );
+    out.color = rect.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,165 @@
+use crate::texture;
+
+/// WGSL with `stylized_light` and `stylized_ambient`, which shade a
+/// [crate::Material] by its [Shading], and the `vs_outline` and
+/// `fs_outline` entry points, see [crate::RenderPipelineBuilder::outline].
+/// Put [crate::PBR_WGSL] and [crate::MODEL_VERTEX_WGSL] in front.
+pub const STYLIZED_WGSL: &str = include_str!("stylized.wgsl");
+
+/// How a [crate::Material] turns light into color
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ShadingModel {
+    /// Metallic-roughness, the same as `pbr_light`
+    #[default]
+    Pbr,
+    /// Looks up [crate::MaterialTextures::matcap] by the normal as the
+    /// camera sees it, ignoring lights. Cheap and good for sculpts.
+    Matcap,
+    /// Cel shading, looking up [crate::MaterialTextures::ramp] by how much
+    /// each light faces the surface
+    Toon,
+}
+
+impl ShadingModel {
+    /// Matches the constants in stylized.wgsl
+    fn id(self) -> u32 {
+        match self {
+            ShadingModel::Pbr => 0,
+            ShadingModel::Matcap => 1,
+            ShadingModel::Toon => 2,
+        }
+    }
+}
+
+/// A Fresnel glow around the silhouette, added on top of any
+/// [ShadingModel]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RimLight {
+    /// Linear
+    pub color: [f32; 3],
+    /// Higher keeps the rim closer to the edge
+    pub power: f32,
+    /// 0 turns the rim off
+    pub strength: f32,
+}
+
+/// What `fs_outline` draws, `width` out from the surface
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Outline {
+    pub color: [f32; 4],
+    /// In world units
+    pub width: f32,
+}
+
+/// A [crate::Material]'s shading. Set it and call
+/// [crate::Material::update_shading].
+///
+/// ```ignore
+/// material.shading.model = ShadingModel::Toon;
+/// material.shading.rim.strength = 0.5;
+/// material.update_shading(&display.queue);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Shading {
+    pub model: ShadingModel,
+    pub rim: RimLight,
+    pub outline: Outline,
+}
+
+impl Default for Shading {
+    fn default() -> Self {
+        Self {
+            model: ShadingModel::Pbr,
+            rim: RimLight {
+                color: [1.0; 3],
+                power: 3.0,
+                strength: 0.0,
+            },
+            outline: Outline {
+                color: [0.0, 0.0, 0.0, 1.0],
+                width: 0.02,
+            },
+        }
+    }
+}
+
+impl Shading {
+    pub(crate) fn to_raw(self) -> ShadingRaw {
+        let [r, g, b] = self.rim.color;
+        ShadingRaw {
+            model: self.model.id(),
+            rim_power: self.rim.power,
+            rim_strength: self.rim.strength,
+            outline_width: self.outline.width,
+            rim_color: [r, g, b, 1.0],
+            outline_color: self.outline.color,
+        }
+    }
+}
+
+/// Mirrors `Shading` in stylized.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct ShadingRaw {
+    model: u32,
+    rim_power: f32,
+    rim_strength: f32,
+    outline_width: f32,
+    rim_color: [f32; 4],
+    outline_color: [f32; 4],
+}
+
+/// A ramp of `bands` flat steps from dark to white, for
+/// [ShadingModel::Toon]. Ramps can be any width, and colored too, but
+/// need a sampler that clamps.
+pub fn toon_ramp(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    bands: u32,
+) -> texture::Texture<'static> {
+    let levels = ramp_levels(bands);
+    let img = image::RgbaImage::from_fn(levels.len() as u32, 1, |x, _| {
+        let level = levels[x as usize];
+        image::Rgba([level, level, level, 255])
+    });
+    let mut texture = texture::Texture::from_image(
+        device,
+        queue,
+        &image::DynamicImage::ImageRgba8(img),
+        Some("toon_ramp"),
+        true,
+    )
+    .unwrap();
+    // Blending between texels would blur the bands together
+    texture.sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("toon_ramp"),
+        ..Default::default()
+    });
+    texture
+}
+
+/// The brightness of each band, going up evenly to white
+fn ramp_levels(bands: u32) -> Vec<u8> {
+    let bands = bands.max(1);
+    (1..=bands).map(|band| (255 * band / bands) as u8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shading_matches_wgsl() {
+        assert_eq!(ramp_levels(3), vec![85, 170, 255]);
+        assert_eq!(ramp_levels(0), vec![255]);
+        // Has to match the WGSL struct's layout
+        assert_eq!(std::mem::size_of::<ShadingRaw>(), 48);
+        crate::shader::validate_wgsl(&format!(
+            "{}{}{}",
+            crate::PBR_WGSL,
+            crate::MODEL_VERTEX_WGSL,
+            STYLIZED_WGSL
+        ))
+        .unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+// Non-photoreal shading for a Material: matcaps, toon ramps and Fresnel
+// rims, picked per material by its Shading. Needs PBR_WGSL and
+// MODEL_VERTEX_WGSL in front. stylized_light and stylized_ambient stand
+// in for pbr_light and pbr_ambient, and fall back to them for
+// ShadingModel::Pbr.
+
+const SHADING_PBR: u32 = 0u;
+const SHADING_MATCAP: u32 = 1u;
+const SHADING_TOON: u32 = 2u;
+
+struct Shading {
+    model: u32,
+    rim_power: f32,
+    // 0 turns the rim off
+    rim_strength: f32,
+    // In world units, pushed out along the normal
+    outline_width: f32,
+    rim_color: vec4<f32>,
+    outline_color: vec4<f32>,
+}
+
+@group(0) @binding(14)
+var matcap_texture: texture_2d<f32>;
+@group(0) @binding(15)
+var matcap_sampler: sampler;
+@group(0) @binding(16)
+var ramp_texture: texture_2d<f32>;
+@group(0) @binding(17)
+var ramp_sampler: sampler;
+@group(0) @binding(18)
+var<uniform> shading: Shading;
+
+// Where the world space normal `n` lands on a matcap, looking along `v`
+// from the camera. The camera's up is taken to be world up, which holds
+// unless it looks straight up or down.
+fn matcap_uv(n: vec3<f32>, v: vec3<f32>) -> vec2<f32> {
+    var right = cross(vec3<f32>(0.0, 1.0, 0.0), v);
+    if (dot(right, right) < 0.0001) {
+        right = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    right = normalize(right);
+    let up = cross(v, right);
+    return vec2<f32>(dot(n, right), -dot(n, up)) * 0.5 + 0.5;
+}
+
+// Fresnel rim, brightest where the surface turns away from the camera
+fn rim_light(n: vec3<f32>, v: vec3<f32>) -> vec3<f32> {
+    let facing = clamp(dot(n, v), 0.0, 1.0);
+    return shading.rim_color.rgb * pow(1.0 - facing, max(shading.rim_power, 0.0001)) * shading.rim_strength;
+}
+
+// pbr_light for the material's shading model. Matcaps have their lighting
+// painted in, so lights don't add anything to them.
+fn stylized_light(surface: Surface, n: vec3<f32>, v: vec3<f32>, l: vec3<f32>, radiance: vec3<f32>) -> vec3<f32> {
+    switch shading.model {
+        case SHADING_MATCAP: {
+            return vec3<f32>(0.0);
+        }
+        case SHADING_TOON: {
+            // Half Lambert, so the back of the ramp shades the dark side
+            let x = clamp(dot(n, l) * 0.5 + 0.5, 0.0, 1.0);
+            let ramp = textureSampleLevel(ramp_texture, ramp_sampler, vec2<f32>(x, 0.5), 0.0).rgb;
+            return surface.albedo.rgb * ramp * radiance;
+        }
+        default: {
+            return pbr_light(surface, n, v, l, radiance);
+        }
+    }
+}
+
+// pbr_ambient for the material's shading model, plus the rim
+fn stylized_ambient(surface: Surface, n: vec3<f32>, v: vec3<f32>, ambient: vec3<f32>) -> vec3<f32> {
+    var color: vec3<f32>;
+    if (shading.model == SHADING_MATCAP) {
+        let matcap = textureSampleLevel(matcap_texture, matcap_sampler, matcap_uv(n, v), 0.0).rgb;
+        color = matcap * surface.albedo.rgb * surface.occlusion + surface.emissive;
+    } else {
+        color = pbr_ambient(surface, ambient);
+    }
+    return color + rim_light(n, v);
+}
+
+// An inverted hull outline: the model pushed out along its normals. Draw
+// it with front faces culled, so only the shell behind the model shows.
+@vertex
+fn vs_outline(model: ModelVertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let linear = mat3x3<f32>(
+        instance.model_matrix_0.xyz,
+        instance.model_matrix_1.xyz,
+        instance.model_matrix_2.xyz,
+    );
+    let position = (model_matrix * vec4<f32>(model.position, 1.0)).xyz;
+    let normal = normalize(linear * model.normal);
+    return camera.view_proj * vec4<f32>(position + normal * shading.outline_width, 1.0);
+}
+
+@fragment
+fn fs_outline() -> @location(0) vec4<f32> {
+    return shading.outline_color;
+}
@@ -0,0 +1,549 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use ab_glyph::{Font as _, FontArc, GlyphId, ScaleFont};
+use anyhow::*;
+
+use crate::pipeline::RenderPipelineBuilder;
+
+const ATLAS_SIZE: u32 = 1024;
+/// Empty texels around each glyph, so filtering doesn't pick up the
+/// neighbours
+const ATLAS_PADDING: u32 = 1;
+
+/// A TrueType or OpenType font for [TextRenderer]
+#[derive(Clone)]
+pub struct Font {
+    font: FontArc,
+}
+
+impl Font {
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        let font = FontArc::try_from_vec(data).map_err(|e| anyhow!("Invalid font: {}", e))?;
+        Ok(Self { font })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data =
+            std::fs::read(path).with_context(|| format!("Unable to read {}", path.display()))?;
+        Self::from_bytes(data).with_context(|| format!("Unable to load {}", path.display()))
+    }
+}
+
+/// A block of text for [TextRenderer::queue]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextSection<'a> {
+    pub text: &'a str,
+    /// The top left corner in pixels from the top left of the screen
+    pub position: [f32; 2],
+    /// The height of a line in pixels
+    pub size: f32,
+    /// Linear, with alpha
+    pub color: [f32; 4],
+    /// Wraps between words to stay inside this many pixels
+    pub max_width: Option<f32>,
+}
+
+impl Default for TextSection<'_> {
+    fn default() -> Self {
+        Self {
+            text: "",
+            position: [0.0, 0.0],
+            size: 16.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+            max_width: None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GlyphInstance {
+    rect: [f32; 4],
+    uv_rect: [f32; 4],
+    color: [f32; 4],
+}
+
+const INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+    wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TextUniform {
+    screen_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+/// Where a glyph at one size is in the atlas. `offset` is from the pen
+/// position on the baseline to the bitmap's top left.
+#[derive(Debug, Copy, Clone)]
+struct AtlasGlyph {
+    offset: [f32; 2],
+    origin: [u32; 2],
+    size: [u32; 2],
+}
+
+/// Screen space text, for labels, frame rates and control hints without
+/// a whole gui. Glyphs are rasterized into an atlas the first time
+/// they're used at a size and drawn as quads after that.
+///
+/// ```ignore
+/// let font = Font::load("res/fonts/Inter.ttf")?;
+/// let mut text = TextRenderer::new(&display.device, font, display.config.format, display.sample_count())?;
+///
+/// // In Demo::render
+/// text.queue(&TextSection {
+///     text: &format!("{:.0} fps", 1.0 / display.time.delta_secs()),
+///     position: [8.0, 8.0],
+///     ..Default::default()
+/// });
+/// text.update(&display.device, &display.queue, display.config.width, display.config.height);
+/// text.draw(&mut pass);
+/// ```
+pub struct TextRenderer {
+    font: Font,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    atlas: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    packer: ShelfPacker,
+    glyphs: HashMap<(GlyphId, u32), Option<AtlasGlyph>>,
+    /// Glyphs queued this frame, waiting for [TextRenderer::update]
+    queued: Vec<(GlyphId, u32, [f32; 2], [f32; 4])>,
+    instance_buffer: wgpu::Buffer,
+    num_instances: u32,
+}
+
+impl TextRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        font: Font,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Result<Self> {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TextRenderer::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("TextRenderer::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(wgpu::include_wgsl!("text.wgsl"))
+            .fragment_shader(wgpu::include_wgsl!("text.wgsl"))
+            .vertex_entry_point("vs_main")
+            .fragment_entry_point("fs_main")
+            .primitive_topology(wgpu::PrimitiveTopology::TriangleStrip)
+            .vertex_buffer_desc(wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<GlyphInstance>() as _,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &INSTANCE_ATTRIBUTES,
+            })
+            .color_state(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+            .sample_count(sample_count)
+            .build(device)?;
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("TextRenderer::uniform_buffer"),
+            size: std::mem::size_of::<TextUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let atlas = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("TextRenderer::atlas"),
+            size: wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = atlas.create_view(&Default::default());
+        // Quads are snapped to whole pixels, so there's nothing to filter
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("TextRenderer::sampler"),
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("TextRenderer::bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        Ok(Self {
+            font,
+            pipeline,
+            uniform_buffer,
+            atlas,
+            bind_group,
+            packer: ShelfPacker::new(ATLAS_SIZE, ATLAS_SIZE),
+            glyphs: HashMap::new(),
+            queued: Vec::new(),
+            instance_buffer: create_instance_buffer(device, 256),
+            num_instances: 0,
+        })
+    }
+
+    /// How wide and tall `section` would be, in pixels
+    pub fn measure(&self, section: &TextSection) -> [f32; 2] {
+        let scaled = self.font.font.as_scaled(section.size);
+        let line_height = scaled.height() + scaled.line_gap();
+        let placed = self.layout(section);
+        let width = placed
+            .iter()
+            .map(|p| p.x + scaled.h_advance(scaled.glyph_id(p.c)))
+            .fold(0.0, f32::max);
+        let lines = placed.last().map_or(0, |p| p.line + 1).max(1);
+        [width, lines as f32 * line_height]
+    }
+
+    /// Adds `section` to the next [TextRenderer::update]
+    pub fn queue(&mut self, section: &TextSection) {
+        let scaled = self.font.font.as_scaled(section.size);
+        let line_height = scaled.height() + scaled.line_gap();
+        let [x, y] = section.position;
+        let baseline = y + scaled.ascent();
+        let size = section.size.round() as u32;
+        for p in self.layout(section) {
+            let pen = [
+                (x + p.x).round(),
+                (baseline + p.line as f32 * line_height).round(),
+            ];
+            let id = scaled.glyph_id(p.c);
+            self.queued.push((id, size, pen, section.color));
+        }
+    }
+
+    fn layout(&self, section: &TextSection) -> Vec<PlacedChar> {
+        let scaled = self.font.font.as_scaled(section.size);
+        layout_text(
+            section.text,
+            section.max_width,
+            |c| scaled.h_advance(scaled.glyph_id(c)),
+            |a, b| scaled.kern(scaled.glyph_id(a), scaled.glyph_id(b)),
+        )
+    }
+
+    /// Rasterizes any new glyphs and uploads everything queued since the
+    /// last update, then starts collecting the next frame's text.
+    /// `width` and `height` are the size of the target.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
+        crate::cpu_scope!("TextRenderer::update");
+        let uniform = TextUniform {
+            screen_size: [width as f32, height as f32],
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let queued = std::mem::take(&mut self.queued);
+        let mut instances = Vec::with_capacity(queued.len());
+        for (id, size, pen, color) in queued {
+            let glyph = match self.glyph(queue, id, size) {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+            let atlas = ATLAS_SIZE as f32;
+            instances.push(GlyphInstance {
+                rect: [
+                    pen[0] + glyph.offset[0],
+                    pen[1] + glyph.offset[1],
+                    glyph.size[0] as f32,
+                    glyph.size[1] as f32,
+                ],
+                uv_rect: [
+                    glyph.origin[0] as f32 / atlas,
+                    glyph.origin[1] as f32 / atlas,
+                    glyph.size[0] as f32 / atlas,
+                    glyph.size[1] as f32 / atlas,
+                ],
+                color,
+            });
+        }
+
+        let needed = (instances.len() * std::mem::size_of::<GlyphInstance>()) as u64;
+        if needed > self.instance_buffer.size() {
+            self.instance_buffer =
+                create_instance_buffer(device, instances.len().next_power_of_two());
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+        self.num_instances = instances.len() as u32;
+    }
+
+    /// The glyph in the atlas, rasterizing it if this is the first time.
+    /// None for glyphs with nothing to draw, like spaces.
+    fn glyph(&mut self, queue: &wgpu::Queue, id: GlyphId, size: u32) -> Option<AtlasGlyph> {
+        if let Some(glyph) = self.glyphs.get(&(id, size)) {
+            return *glyph;
+        }
+        let glyph = self.rasterize(queue, id, size);
+        self.glyphs.insert((id, size), glyph);
+        glyph
+    }
+
+    fn rasterize(&mut self, queue: &wgpu::Queue, id: GlyphId, size: u32) -> Option<AtlasGlyph> {
+        let outlined = self.font.font.outline_glyph(id.with_scale(size as f32))?;
+        let bounds = outlined.px_bounds();
+        let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let origin = match self.packer.allocate(width, height) {
+            Some(origin) => origin,
+            None => {
+                // Start over rather than grow, text rarely needs more
+                // than one atlas worth at a time. Glyphs already placed
+                // this frame may be wrong until the next one.
+                log::warn!("TextRenderer atlas is full, clearing it");
+                self.packer = ShelfPacker::new(ATLAS_SIZE, ATLAS_SIZE);
+                self.glyphs.clear();
+                self.packer.allocate(width, height)?
+            }
+        };
+        let mut coverage = vec![0u8; (width * height) as usize];
+        outlined.draw(|x, y, c| {
+            if x < width && y < height {
+                coverage[(y * width + x) as usize] = (c.clamp(0.0, 1.0) * 255.0) as u8;
+            }
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.atlas,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: origin[0],
+                    y: origin[1],
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &coverage,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width),
+                rows_per_image: Some(height),
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        Some(AtlasGlyph {
+            offset: [bounds.min.x, bounds.min.y],
+            origin,
+            size: [width, height],
+        })
+    }
+
+    /// Draws the text from the last update into a pass that's already
+    /// been started, on top of what's there. It changes the pass's bind
+    /// group 0.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        if self.num_instances == 0 {
+            return;
+        }
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        pass.draw(0..4, 0..self.num_instances);
+    }
+}
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("TextRenderer::instance_buffer"),
+        size: (capacity.max(1) * std::mem::size_of::<GlyphInstance>()) as u64,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Fills an atlas in rows, each as tall as the tallest thing in it
+#[derive(Debug)]
+struct ShelfPacker {
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+    row_height: u32,
+}
+
+impl ShelfPacker {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            x: ATLAS_PADDING,
+            y: ATLAS_PADDING,
+            row_height: 0,
+        }
+    }
+
+    /// The top left of a free `width` by `height` space, or None when
+    /// the atlas is full
+    fn allocate(&mut self, width: u32, height: u32) -> Option<[u32; 2]> {
+        let (mut x, mut y, mut row_height) = (self.x, self.y, self.row_height);
+        if x + width + ATLAS_PADDING > self.width {
+            x = ATLAS_PADDING;
+            y += row_height + ATLAS_PADDING;
+            row_height = 0;
+        }
+        if x + width + ATLAS_PADDING > self.width || y + height + ATLAS_PADDING > self.height {
+            return None;
+        }
+        self.x = x + width + ATLAS_PADDING;
+        self.y = y;
+        self.row_height = row_height.max(height);
+        Some([x, y])
+    }
+}
+
+/// A character's pen position along its line
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct PlacedChar {
+    c: char,
+    x: f32,
+    line: usize,
+}
+
+/// Places each character of `text` on a line, breaking at newlines and,
+/// with `max_width`, between words that would go past it. Spaces aren't
+/// placed, as there's nothing to draw.
+fn layout_text(
+    text: &str,
+    max_width: Option<f32>,
+    advance: impl Fn(char) -> f32,
+    kern: impl Fn(char, char) -> f32,
+) -> Vec<PlacedChar> {
+    let word_width = |word: &str| {
+        let mut prev = None;
+        word.chars()
+            .map(|c| {
+                let k = prev.map_or(0.0, |p| kern(p, c));
+                prev = Some(c);
+                k + advance(c)
+            })
+            .sum::<f32>()
+    };
+    let mut placed = Vec::new();
+    let mut line = 0;
+    for (i, paragraph) in text.split('\n').enumerate() {
+        if i > 0 {
+            line += 1;
+        }
+        let mut x = 0.0;
+        let mut prev = None;
+        for (j, word) in paragraph.split(' ').enumerate() {
+            if j > 0 {
+                let space = advance(' ');
+                let wraps = max_width.is_some_and(|max| x + space + word_width(word) > max);
+                if wraps && x > 0.0 {
+                    line += 1;
+                    x = 0.0;
+                    prev = None;
+                } else {
+                    x += space;
+                    prev = Some(' ');
+                }
+            }
+            for c in word.chars() {
+                x += prev.map_or(0.0, |p| kern(p, c));
+                placed.push(PlacedChar { c, x, line });
+                x += advance(c);
+                prev = Some(c);
+            }
+        }
+    }
+    placed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_wraps_between_words_and_packs() {
+        let placed = layout_text("ab cd\nef", Some(5.0), |_| 1.0, |_, _| 0.0);
+        let lines: Vec<_> = placed.iter().map(|p| (p.c, p.x, p.line)).collect();
+        assert_eq!(
+            lines,
+            [
+                ('a', 0.0, 0),
+                ('b', 1.0, 0),
+                ('c', 3.0, 0),
+                ('d', 4.0, 0),
+                ('e', 0.0, 1),
+                ('f', 1.0, 1),
+            ]
+        );
+        // Too narrow for both words, and kerning pulls b towards a
+        let placed = layout_text("ab cd", Some(4.0), |_| 1.0, |_, _| -0.25);
+        assert_eq!(placed[1].x, 0.75);
+        assert_eq!((placed[2].x, placed[2].line), (0.0, 1));
+
+        let mut packer = ShelfPacker::new(16, 16);
+        assert_eq!(packer.allocate(6, 4), Some([1, 1]));
+        assert_eq!(packer.allocate(6, 2), Some([8, 1]));
+        // Starts a new row below the tallest so far
+        assert_eq!(packer.allocate(6, 6), Some([1, 6]));
+        assert_eq!(packer.allocate(20, 1), None);
+        assert_eq!(packer.allocate(6, 6), Some([8, 6]));
+        assert_eq!(packer.allocate(6, 6), None);
+        assert_eq!(packer.allocate(1, 1), Some([1, 13]));
+
+        crate::shader::validate_wgsl(include_str!("text.wgsl")).unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+// Screen space glyph quads for TextRenderer. Positions are in pixels
+// from the top left, and the atlas holds each glyph's coverage.
+
+struct TextUniform {
+    screen_size: vec2<f32>,
+    _padding: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> text: TextUniform;
+@group(0) @binding(1)
+var atlas: texture_2d<f32>;
+@group(0) @binding(2)
+var atlas_sampler: sampler;
+
+struct GlyphInput {
+    // x, y, width, height in pixels
+    @location(0) rect: vec4<f32>,
+    // The same in atlas uvs
+    @location(1) uv_rect: vec4<f32>,
+    @location(2) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+// Drawn as a 4 vertex triangle strip per glyph
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32, glyph: GlyphInput) -> VertexOutput {
+    let corner = vec2<f32>(f32(i & 1u), f32(i >> 1u));
+    let pixel = glyph.rect.xy + corner * glyph.rect.zw;
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(
+        pixel / text.screen_size * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0),
+        0.0,
+        1.0,
+    );
+    out.uv = glyph.uv_rect.xy + corner * glyph.uv_rect.zw;
+    out.color = glyph.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSample(atlas, atlas_sampler, in.uv).r;
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
@@ -3,7 +3,10 @@ use image::GenericImageView;
 use std::mem;
 use std::path::Path;
 
+use wgpu::util::DeviceExt;
+
 use crate::buffer;
+use crate::pipeline::RenderPipelineBuilder;
 
 pub struct Texture<'a> {
     pub texture: wgpu::Texture,
@@ -27,6 +30,43 @@ impl<'a> Texture<'a> {
         Self::from_image(device, queue, &img, Some(label), is_normal_map)
     }
 
+    /// Loads several textures, decoding the files in parallel on
+    /// [crate::JobPool::global] before uploading them here
+    pub fn load_all<P: AsRef<Path> + Sync>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        files: &[(P, bool)],
+    ) -> Vec<Result<Self>> {
+        let images = crate::jobs::JobPool::global().map(files, 1, |(path, _)| {
+            let path = path.as_ref();
+            image::open(path).with_context(|| format!("Unable to load {}", path.display()))
+        });
+        images
+            .into_iter()
+            .zip(files)
+            .map(|(img, (path, is_normal_map))| {
+                let label = path.as_ref().to_string_lossy();
+                Self::from_image(device, queue, &img?, Some(&label), *is_normal_map)
+            })
+            .collect()
+    }
+
+    /// Starts decoding `path` on [crate::JobPool::global] and returns
+    /// straight away. Check on it with [crate::Job::try_wait] and upload
+    /// the image with [Texture::from_image] once it's done.
+    pub fn decode<P: AsRef<Path>>(path: P) -> crate::jobs::Job<Result<image::DynamicImage>> {
+        let path = path.as_ref().to_path_buf();
+        crate::jobs::JobPool::global().spawn(move || {
+            image::open(&path).with_context(|| format!("Unable to load {}", path.display()))
+        })
+    }
+
+    /// [Texture::decode] for a file that's already been read or fetched
+    pub fn decode_bytes(bytes: Vec<u8>) -> crate::jobs::Job<Result<image::DynamicImage>> {
+        crate::jobs::JobPool::global()
+            .spawn(move || image::load_from_memory(&bytes).context("Unable to decode image"))
+    }
+
     pub fn from_descriptor(device: &wgpu::Device, desc: wgpu::TextureDescriptor<'a>) -> Self {
         let texture = device.create_texture(&desc);
 
@@ -121,7 +161,6 @@ impl<'a> Texture<'a> {
             mipmap_filter: wgpu::FilterMode::Nearest,
             lod_min_clamp: 0.0,
             lod_max_clamp: 100.0,
-            compare: Some(wgpu::CompareFunction::Always),
             ..Default::default()
         });
 
@@ -133,9 +172,84 @@ impl<'a> Texture<'a> {
         })
     }
 
+    /// A 1x1 texture of one color, for material slots that don't have a
+    /// texture of their own
+    pub fn solid(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        is_normal_map: bool,
+    ) -> Self {
+        let img =
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        Self::from_image(device, queue, &img, None, is_normal_map).unwrap()
+    }
+
+    /// A magenta and black checkerboard that's hard to miss. Normal maps
+    /// get a flat normal instead so lighting still looks right.
+    pub fn placeholder(device: &wgpu::Device, queue: &wgpu::Queue, is_normal_map: bool) -> Self {
+        const SIZE: u32 = 8;
+        let img = image::RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+            if is_normal_map {
+                image::Rgba([128, 128, 255, 255])
+            } else if (x + y) % 2 == 0 {
+                image::Rgba([255, 0, 255, 255])
+            } else {
+                image::Rgba([0, 0, 0, 255])
+            }
+        });
+        let mut texture = Self::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(img),
+            Some("placeholder"),
+            is_normal_map,
+        )
+        .unwrap();
+        // Keep the checks crisp when they're stretched over a surface
+        texture.sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Texture::placeholder"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            ..Default::default()
+        });
+        texture
+    }
+
+    /// Like [Texture::load], but logs a warning and returns
+    /// [Texture::placeholder] if the file can't be loaded.
+    pub fn load_or_placeholder<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: P,
+        is_normal_map: bool,
+    ) -> Self {
+        match Self::load(device, queue, path.as_ref(), is_normal_map) {
+            Result::Ok(texture) => texture,
+            Err(e) => {
+                log::warn!(
+                    "Unable to load {}, using a placeholder: {:#}",
+                    path.as_ref().display(),
+                    e
+                );
+                Self::placeholder(device, queue, is_normal_map)
+            }
+        }
+    }
+
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        Self::create_depth_texture_multisampled(device, config, 1)
+    }
+
+    /// For passes drawing with MSAA, which need depth with the same
+    /// sample count as color
+    pub fn create_depth_texture_multisampled(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
     ) -> Self {
         let desc = wgpu::TextureDescriptor {
             label: None,
@@ -145,10 +259,11 @@ impl<'a> Texture<'a> {
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            // TEXTURE_BINDING lets screen space effects read the depth buffer
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[Self::DEPTH_FORMAT],
         };
         Self::from_descriptor(device, desc)
@@ -175,3 +290,521 @@ impl<'a> Texture<'a> {
         raw_buffer
     }
 }
+
+const EQUIRECTANGULAR_WGSL: &str = concat!(
+    include_str!("cube_face.wgsl"),
+    include_str!("equirectangular.wgsl")
+);
+
+/// A cube texture for skies and [crate::EnvironmentMap]s. `view` is a
+/// cube view.
+pub struct Cubemap {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    pub size: u32,
+}
+
+impl Cubemap {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// Loads an equirectangular panorama, like the .hdr files on Poly
+    /// Haven, with [Cubemap::from_equirectangular]
+    pub fn load_hdr<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: P,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let img = image::open(path)
+            .with_context(|| format!("Unable to load {}", path.display()))?
+            .into_rgb32f();
+        Self::from_equirectangular(device, queue, &img)
+    }
+
+    /// Projects a panorama that's twice as wide as it is tall onto the
+    /// faces of a cube map on the GPU. Each face is a quarter of the
+    /// panorama's width, which keeps about the same detail.
+    pub fn from_equirectangular(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        hdr_image: &image::Rgb32FImage,
+    ) -> Result<Self> {
+        let (width, height) = hdr_image.dimensions();
+        let max_size = device.limits().max_texture_dimension_2d;
+        if width > max_size || height > max_size {
+            bail!(
+                "A {}x{} panorama is larger than the {} pixels this device supports",
+                width,
+                height,
+                max_size
+            );
+        }
+        let size = (width / 4).max(1);
+
+        // Rgba32Float since wgpu has no 3 channel formats
+        let rgba = hdr_image
+            .pixels()
+            .flat_map(|p| [p[0], p[1], p[2], 1.0])
+            .collect::<Vec<f32>>();
+        let panorama_size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let panorama = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Cubemap::panorama"),
+            size: panorama_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &panorama,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(&rgba),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(mem::size_of::<[f32; 4]>() as u32 * width),
+                rows_per_image: Some(height),
+            },
+            panorama_size,
+        );
+
+        let cubemap = Self::empty(device, size);
+        project_equirectangular(device, queue, &panorama, &cubemap.texture)?;
+        Ok(cubemap)
+    }
+
+    /// A cube map of `size` texels a side for [draw_cube_faces] to fill
+    pub(crate) fn empty(device: &wgpu::Device, size: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Cubemap"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Cubemap::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            size,
+        }
+    }
+}
+
+/// The `face` uniform cube_face.wgsl shaders read, bound with a dynamic
+/// offset so one bind group serves every face
+pub(crate) fn cube_face_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: true,
+            min_binding_size: wgpu::BufferSize::new(mem::size_of::<u32>() as _),
+        },
+        count: None,
+    }
+}
+
+/// The faces' indices for [cube_face_layout_entry], each at an offset the
+/// device allows binding at. Returns the buffer and the offset between
+/// faces.
+pub(crate) fn cube_face_buffer(device: &wgpu::Device) -> (wgpu::Buffer, u32) {
+    let stride = device.limits().min_uniform_buffer_offset_alignment as usize;
+    let mut faces = vec![0u8; stride * 6];
+    for face in 0..6u32 {
+        faces[face as usize * stride..][..4].copy_from_slice(&face.to_ne_bytes());
+    }
+    let faces = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Cubemap::faces"),
+        contents: &faces,
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    (faces, stride as u32)
+}
+
+/// Binds one face's index out of a [cube_face_buffer]
+pub(crate) fn cube_face_binding(faces: &wgpu::Buffer) -> wgpu::BindingResource<'_> {
+    wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+        buffer: faces,
+        offset: 0,
+        size: wgpu::BufferSize::new(mem::size_of::<u32>() as _),
+    })
+}
+
+/// Draws each face of `cubemap` with `pipeline`, one render pass per
+/// face. `bind_group` has a face from [cube_face_buffer] as its only
+/// dynamic offset.
+pub(crate) fn draw_cube_faces(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+    cubemap: &wgpu::Texture,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+    stride: u32,
+) {
+    let mut encoder =
+        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some(label) });
+    for face in 0..6u32 {
+        let view = cubemap.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: face,
+            array_layer_count: Some(1),
+            ..Default::default()
+        });
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[face * stride]);
+        pass.draw(0..3, 0..1);
+    }
+    queue.submit([encoder.finish()]);
+}
+
+/// Draws each face of `cubemap` from `panorama`, one render pass per face
+fn project_equirectangular(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    panorama: &wgpu::Texture,
+    cubemap: &wgpu::Texture,
+) -> Result<()> {
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Cubemap::equirectangular_layout"),
+        entries: &[
+            cube_face_layout_entry(0),
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ],
+    });
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Cubemap::equirectangular_pipeline_layout"),
+        bind_group_layouts: &[&layout],
+        push_constant_ranges: &[],
+    });
+    let shader = || wgpu::ShaderModuleDescriptor {
+        label: Some("equirectangular.wgsl"),
+        source: wgpu::ShaderSource::Wgsl(EQUIRECTANGULAR_WGSL.into()),
+    };
+    let pipeline = RenderPipelineBuilder::new()
+        .layout(&pipeline_layout)
+        .vertex_shader(shader())
+        .fragment_shader(shader())
+        .vertex_entry_point("vs_main")
+        .fragment_entry_point("fs_main")
+        .color_solid(Cubemap::FORMAT)
+        .build(device)?;
+
+    let (faces, stride) = cube_face_buffer(device);
+    let panorama_view = panorama.create_view(&Default::default());
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Cubemap::equirectangular_bind_group"),
+        layout: &layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: cube_face_binding(&faces),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&panorama_view),
+            },
+        ],
+    });
+    draw_cube_faces(
+        device,
+        queue,
+        "Cubemap::from_equirectangular",
+        cubemap,
+        &pipeline,
+        &bind_group,
+        stride,
+    );
+    Ok(())
+}
+
+/// A 3D color lookup table for [crate::ColorGrading]. Each color's red,
+/// green and blue pick a texel, whose color replaces it. Both are sRGB
+/// encoded, like the LUTs image editors export.
+pub struct ColorLut {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    /// Texels along each side
+    pub size: u32,
+}
+
+impl ColorLut {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// Loads a .cube file, or any other image as a strip, see
+    /// [ColorLut::from_strip]
+    pub fn load<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: P,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let is_cube = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("cube"));
+        if is_cube {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Unable to open {}", path.display()))?;
+            Self::from_cube(device, queue, &text)
+                .with_context(|| format!("{} isn't a valid .cube file", path.display()))
+        } else {
+            let img =
+                image::open(path).with_context(|| format!("Unable to load {}", path.display()))?;
+            Self::from_strip(device, queue, &img)
+                .with_context(|| format!("{} isn't a valid LUT strip", path.display()))
+        }
+    }
+
+    /// Parses an Adobe .cube file's 3D table. Its domain has to be the
+    /// default 0 to 1.
+    pub fn from_cube(device: &wgpu::Device, queue: &wgpu::Queue, text: &str) -> Result<Self> {
+        let (size, colors) = parse_cube(text)?;
+        Ok(Self::from_colors(device, queue, size, &colors))
+    }
+
+    /// A horizontal strip of square slices, one for each step of blue,
+    /// with red across each slice and green down it. A 16 texel LUT is a
+    /// 256x16 image.
+    pub fn from_strip(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+    ) -> Result<Self> {
+        let (size, colors) = strip_colors(&img.to_rgba8())?;
+        Ok(Self::from_colors(device, queue, size, &colors))
+    }
+
+    /// Leaves every color as it is
+    pub fn identity(device: &wgpu::Device, queue: &wgpu::Queue, size: u32) -> Self {
+        let size = size.max(2);
+        let step = 1.0 / (size - 1) as f32;
+        let colors: Vec<_> = (0..size * size * size)
+            .map(|i| {
+                let (r, g, b) = (i % size, i / size % size, i / (size * size));
+                [r as f32 * step, g as f32 * step, b as f32 * step]
+            })
+            .collect();
+        Self::from_colors(device, queue, size, &colors)
+    }
+
+    /// `colors` has red changing fastest, then green, then blue
+    fn from_colors(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: u32,
+        colors: &[[f32; 3]],
+    ) -> Self {
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("ColorLut"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let texels: Vec<u16> = colors
+            .iter()
+            .flat_map(|&[r, g, b]| [r, g, b, 1.0])
+            .map(crate::sh::f32_to_f16)
+            .collect();
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(&texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(size * 8),
+                rows_per_image: Some(size),
+            },
+            extent,
+        );
+        let view = texture.create_view(&Default::default());
+        Self {
+            texture,
+            view,
+            size,
+        }
+    }
+}
+
+/// A .cube file's size and colors, red changing fastest
+pub(crate) fn parse_cube(text: &str) -> Result<(u32, Vec<[f32; 3]>)> {
+    let mut size = None;
+    let mut colors = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let mut words = line.split_whitespace();
+        let first = match words.next() {
+            Some(first) if !first.starts_with('#') => first,
+            _ => continue,
+        };
+        let values = || -> Result<Vec<f32>> {
+            line.split_whitespace()
+                .skip(1)
+                .map(|v| v.parse::<f32>().map_err(Error::from))
+                .collect()
+        };
+        match first {
+            "TITLE" => {}
+            "LUT_1D_SIZE" => bail!("Only 3D LUTs are supported"),
+            "LUT_3D_SIZE" => {
+                let n: u32 = words.next().context("LUT_3D_SIZE needs a size")?.parse()?;
+                if !(2..=256).contains(&n) {
+                    bail!("A LUT_3D_SIZE of {} isn't from 2 to 256", n);
+                }
+                size = Some(n);
+            }
+            "DOMAIN_MIN" if values()? != [0.0; 3] => bail!("Only a DOMAIN_MIN of 0 is supported"),
+            "DOMAIN_MAX" if values()? != [1.0; 3] => bail!("Only a DOMAIN_MAX of 1 is supported"),
+            "DOMAIN_MIN" | "DOMAIN_MAX" => {}
+            _ => {
+                let rgb: Vec<f32> = line
+                    .split_whitespace()
+                    .map(|v| v.parse::<f32>())
+                    .collect::<Result<_, _>>()
+                    .with_context(|| format!("Line {} isn't a color", number + 1))?;
+                if rgb.len() != 3 {
+                    bail!("Line {} isn't a color", number + 1);
+                }
+                colors.push([rgb[0], rgb[1], rgb[2]]);
+            }
+        }
+    }
+    let size = size.context("The file has no LUT_3D_SIZE")?;
+    if colors.len() != (size * size * size) as usize {
+        bail!(
+            "A size {} LUT needs {} colors, not {}",
+            size,
+            size * size * size,
+            colors.len()
+        );
+    }
+    Ok((size, colors))
+}
+
+/// A LUT strip's size and colors, red changing fastest
+pub(crate) fn strip_colors(img: &image::RgbaImage) -> Result<(u32, Vec<[f32; 3]>)> {
+    let (width, size) = img.dimensions();
+    if size < 2 || width != size * size {
+        bail!("A {}x{} image isn't a strip of square slices", width, size);
+    }
+    let colors = (0..size * size * size)
+        .map(|i| {
+            let (r, g, b) = (i % size, i / size % size, i / (size * size));
+            let pixel = img.get_pixel(b * size + r, g);
+            [0, 1, 2].map(|c| pixel[c] as f32 / 255.0)
+        })
+        .collect();
+    Ok((size, colors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equirectangular_wgsl_validates() {
+        crate::shader::validate_wgsl(EQUIRECTANGULAR_WGSL).unwrap();
+    }
+
+    #[test]
+    fn cube_files_and_strips_are_parsed() {
+        let cube = "# Swaps red and blue
+TITLE \"swap\"
+LUT_3D_SIZE 2
+DOMAIN_MIN 0 0 0
+DOMAIN_MAX 1 1 1
+
+0 0 0
+0 0 1
+0 1 0
+0 1 1
+1 0 0
+1 0 1
+1 1 0
+1 1 1
+";
+        let (size, colors) = parse_cube(cube).unwrap();
+        assert_eq!(size, 2);
+        // Red is 1 and the rest 0
+        assert_eq!(colors[1], [0.0, 0.0, 1.0]);
+        assert!(parse_cube("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+        assert!(parse_cube("LUT_3D_SIZE 2\nDOMAIN_MAX 2 2 2\n").is_err());
+
+        let mut strip = image::RgbaImage::new(4, 2);
+        // Green 1 and blue 1 is the second slice's bottom row
+        strip.put_pixel(2, 1, image::Rgba([255, 128, 0, 255]));
+        let (size, colors) = strip_colors(&strip).unwrap();
+        assert_eq!(size, 2);
+        let color = colors[2 + 4];
+        assert_eq!(color[0], 1.0);
+        assert_eq!(color[1], 128.0 / 255.0);
+        assert!(strip_colors(&image::RgbaImage::new(4, 4)).is_err());
+    }
+}
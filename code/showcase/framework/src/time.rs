@@ -0,0 +1,130 @@
+use std::time::{Duration, Instant};
+
+/// Frame timing for demos. The framework ticks this once per frame before
+/// calling [crate::Demo::update], so everything that reads it during a
+/// frame sees the same values.
+pub struct Time {
+    last_tick: Option<Instant>,
+    elapsed: Duration,
+    delta: Duration,
+    frame: u64,
+    paused: bool,
+    scale: f32,
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Self {
+            last_tick: None,
+            elapsed: Duration::ZERO,
+            delta: Duration::ZERO,
+            frame: 0,
+            paused: false,
+            scale: 1.0,
+        }
+    }
+}
+
+impl Time {
+    /// Caps the delta so a long stall (dragging the window, hitting a
+    /// breakpoint) doesn't make simulations explode
+    const MAX_DELTA: Duration = Duration::from_millis(250);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advances to the next frame and returns the new delta
+    pub fn tick(&mut self) -> Duration {
+        let now = Instant::now();
+        let real_delta = match self.last_tick {
+            Some(last) => now - last,
+            None => Duration::ZERO,
+        };
+        self.last_tick = Some(now);
+        self.advance(real_delta)
+    }
+
+    /// Advances by a fixed amount instead of the wall clock. Useful for
+    /// rendering videos or tests. Like [Time::tick], deltas are capped at
+    /// [Time::MAX_DELTA] before scaling.
+    pub fn advance(&mut self, real_delta: Duration) -> Duration {
+        self.delta = if self.paused {
+            Duration::ZERO
+        } else {
+            real_delta.min(Self::MAX_DELTA).mul_f32(self.scale)
+        };
+        self.elapsed += self.delta;
+        self.frame += 1;
+        self.delta
+    }
+
+    /// Scaled time since the demo started, not counting pauses
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn elapsed_secs(&self) -> f32 {
+        self.elapsed.as_secs_f32()
+    }
+
+    /// Scaled time since the last frame. Zero while paused.
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    pub fn delta_secs(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    /// How many frames have been ticked, including paused ones
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Slow motion or fast forward. Negative values are clamped to 0.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.max(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance_clamps_pauses_and_scales() {
+        let mut time = Time::new();
+        let delta = time.advance(Duration::from_secs(5));
+        assert_eq!(delta, Time::MAX_DELTA);
+        assert_eq!(time.elapsed(), Time::MAX_DELTA);
+
+        time.set_paused(true);
+        assert_eq!(time.advance(Duration::from_millis(100)), Duration::ZERO);
+        assert_eq!(time.delta(), Duration::ZERO);
+        assert_eq!(time.elapsed(), Time::MAX_DELTA);
+        assert_eq!(time.frame(), 2);
+
+        time.set_paused(false);
+        time.set_scale(-2.0);
+        assert_eq!(time.scale(), 0.0);
+        assert_eq!(time.advance(Duration::from_millis(100)), Duration::ZERO);
+
+        time.set_scale(2.0);
+        let delta = time.advance(Duration::from_millis(100));
+        assert!((delta.as_secs_f32() - 0.2).abs() < 1e-6);
+        assert_eq!(time.frame(), 4);
+    }
+}
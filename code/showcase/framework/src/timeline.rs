@@ -0,0 +1,345 @@
+//! Scripting for demoscene style showcases. A [Timeline] owns the
+//! playhead and fires events, and [Track]s hold keyframed values that are
+//! sampled at the playhead each frame.
+//!
+//! ```ignore
+//! enum Cue { CutTo(usize), Shader(&'static str) }
+//!
+//! let mut timeline = Timeline::new(30.0);
+//! timeline.event(0.0, Cue::CutTo(0)).event(12.5, Cue::Shader("tunnel"));
+//! let mut fov = Track::new();
+//! fov.key(0.0, 45.0, Easing::EaseInOut).key(10.0, 90.0, Easing::Linear);
+//!
+//! // Each frame
+//! for cue in timeline.update(&display.time) { ... }
+//! projection.fovy = Deg(fov.sample(timeline.time()).unwrap());
+//! ```
+
+use cgmath::*;
+
+use crate::time::Time;
+
+/// Values that can be blended between keyframes.
+pub trait Interpolate: Clone {
+    fn interpolate(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Vector2<f32> {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self.lerp(*other, t)
+    }
+}
+
+impl Interpolate for Vector3<f32> {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self.lerp(*other, t)
+    }
+}
+
+impl Interpolate for Vector4<f32> {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self.lerp(*other, t)
+    }
+}
+
+impl Interpolate for Point3<f32> {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Quaternion<f32> {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self.slerp(*other, t)
+    }
+}
+
+impl<const N: usize> Interpolate for [f32; N] {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        let mut out = *self;
+        for (o, b) in out.iter_mut().zip(other) {
+            *o += (b - *o) * t;
+        }
+        out
+    }
+}
+
+/// How a keyframe blends into the next one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Easing {
+    /// Hold the value until the next keyframe
+    Step,
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Step => 0.0,
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Keyframe<T> {
+    time: f32,
+    value: T,
+    easing: Easing,
+}
+
+/// A keyframed value. Before the first key it holds the first value, and
+/// after the last key it holds the last one.
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    keys: Vec<Keyframe<T>>,
+}
+
+impl<T> Default for Track<T> {
+    fn default() -> Self {
+        Self { keys: Vec::new() }
+    }
+}
+
+impl<T: Interpolate> Track<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a keyframe. `easing` controls the blend towards the next key.
+    /// Adding a key at the same time as an existing one replaces it.
+    pub fn key(&mut self, time: f32, value: T, easing: Easing) -> &mut Self {
+        let key = Keyframe {
+            time,
+            value,
+            easing,
+        };
+        match self.keys.binary_search_by(|k| k.time.total_cmp(&time)) {
+            Ok(i) => self.keys[i] = key,
+            Err(i) => self.keys.insert(i, key),
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The time of the last keyframe
+    pub fn end(&self) -> f32 {
+        self.keys.last().map_or(0.0, |k| k.time)
+    }
+
+    /// `None` if the track has no keys
+    pub fn sample(&self, time: f32) -> Option<T> {
+        let next = self.keys.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return self.keys.first().map(|k| k.value.clone());
+        }
+        let current = &self.keys[next - 1];
+        let next = match self.keys.get(next) {
+            Some(next) => next,
+            None => return Some(current.value.clone()),
+        };
+        let t = (time - current.time) / (next.time - current.time);
+        Some(
+            current
+                .value
+                .interpolate(&next.value, current.easing.apply(t)),
+        )
+    }
+}
+
+/// A playhead with events scheduled along it. Events fire once when the
+/// playhead passes them while playing. Seeking doesn't fire anything, so
+/// scrubbing around in an editor won't replay every cut on the way.
+pub struct Timeline<E> {
+    time: f32,
+    duration: f32,
+    playing: bool,
+    looping: bool,
+    speed: f32,
+    events: Vec<(f32, E)>,
+}
+
+impl<E: Clone> Timeline<E> {
+    /// Creates a paused timeline with the playhead at 0
+    pub fn new(duration: f32) -> Self {
+        Self {
+            time: 0.0,
+            duration: duration.max(0.0),
+            playing: false,
+            looping: false,
+            speed: 1.0,
+            events: Vec::new(),
+        }
+    }
+
+    /// Schedules `event` to fire at `time` seconds
+    pub fn event(&mut self, time: f32, event: E) -> &mut Self {
+        let i = self.events.partition_point(|(t, _)| *t <= time);
+        self.events.insert(i, (time, event));
+        self
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = (f32, &E)> {
+        self.events.iter().map(|(t, e)| (*t, e))
+    }
+
+    pub fn looping(&mut self, looping: bool) -> &mut Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Playback rate, 1 is real time
+    pub fn speed(&mut self, speed: f32) -> &mut Self {
+        self.speed = speed.max(0.0);
+        self
+    }
+
+    pub fn play(&mut self) {
+        if !self.looping && self.time >= self.duration {
+            self.time = 0.0;
+        }
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn toggle(&mut self) {
+        if self.playing {
+            self.pause();
+        } else {
+            self.play();
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Moves the playhead without firing events
+    pub fn seek(&mut self, time: f32) {
+        self.time = time.clamp(0.0, self.duration);
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    /// How far through the timeline the playhead is, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        if self.duration > 0.0 {
+            self.time / self.duration
+        } else {
+            0.0
+        }
+    }
+
+    /// Advances by the frame's delta time, returning the events that were
+    /// passed in the order they were scheduled.
+    pub fn update(&mut self, time: &Time) -> Vec<E> {
+        self.advance(time.delta_secs())
+    }
+
+    /// Like [Timeline::update], but with an explicit delta in seconds
+    pub fn advance(&mut self, dt: f32) -> Vec<E> {
+        let mut fired = Vec::new();
+        if !self.playing {
+            return fired;
+        }
+
+        // Events fire when the playhead goes from at or before them to
+        // after them, so a paused frame never fires anything twice
+        let mut from = self.time;
+        let mut to = self.time + dt * self.speed;
+        while to >= self.duration {
+            if !self.looping || self.duration <= 0.0 {
+                // Nothing comes after the end, so include events on it
+                self.fire(from, self.duration, true, &mut fired);
+                self.time = self.duration;
+                self.playing = false;
+                return fired;
+            }
+            self.fire(from, self.duration, false, &mut fired);
+            from = 0.0;
+            to -= self.duration;
+        }
+        self.fire(from, to, false, &mut fired);
+        self.time = to;
+        fired
+    }
+
+    fn fire(&self, from: f32, to: f32, inclusive: bool, fired: &mut Vec<E>) {
+        fired.extend(
+            self.events
+                .iter()
+                .filter(|(t, _)| *t >= from && (*t < to || (inclusive && *t <= to)))
+                .map(|(_, e)| e.clone()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_eases_between_keys() {
+        let mut track = Track::new();
+        track
+            .key(1.0, 0.0f32, Easing::Linear)
+            .key(3.0, 10.0, Easing::Step)
+            .key(2.0, 4.0, Easing::EaseIn);
+
+        assert_eq!(track.sample(0.0), Some(0.0));
+        assert_eq!(track.sample(1.5), Some(2.0));
+        assert_eq!(track.sample(2.5), Some(4.0 + 6.0 * 0.25));
+        assert_eq!(track.sample(5.0), Some(10.0));
+        assert_eq!(Track::<f32>::new().sample(0.0), None);
+    }
+
+    #[test]
+    fn events_fire_once_and_loop() {
+        let mut timeline = Timeline::new(4.0);
+        timeline
+            .event(0.0, "start")
+            .event(1.0, "a")
+            .event(3.0, "b")
+            .looping(true);
+
+        assert!(timeline.advance(1.0).is_empty());
+        timeline.play();
+        assert_eq!(timeline.advance(0.5), ["start"]);
+        assert!(timeline.advance(0.0).is_empty());
+        assert_eq!(timeline.advance(0.6), ["a"]);
+        assert!(timeline.advance(1.0).is_empty());
+        assert_eq!(timeline.advance(2.4), ["b", "start"]);
+        assert!((timeline.time() - 0.5).abs() < 1e-5);
+
+        timeline.seek(3.5);
+        timeline.looping(false).event(4.0, "end");
+        assert_eq!(timeline.advance(1.0), ["end"]);
+        assert!(!timeline.is_playing());
+        assert_eq!(timeline.time(), 4.0);
+    }
+}
@@ -0,0 +1,122 @@
+//! Physical light units, so a lamp set up in one scene is as bright in
+//! another and the tonemapper sees the same range of values everywhere.
+//! Distances are in meters.
+//!
+//! Lights are described the way they're sold and measured: lumens for
+//! the light a bulb gives off in total, candela for how bright it is in
+//! one direction, and lux for how much falls on a surface, like sunlight
+//! on the ground. A camera's [Exposure] turns them into the values shaders
+//! work with. They're pre-exposed, scaled down by the exposure before
+//! they reach the GPU, since daylight in lux would overflow the
+//! `Rgba16Float` HDR target. Leave [crate::HdrPipeline]'s own exposure at
+//! 1 when using these.
+//!
+//! ```ignore
+//! // An overcast day outside, a 60 W equivalent bulb inside
+//! let exposure = Exposure::from_ev100(12.0);
+//! let bulb = PointLight::new((0.0, 2.5, 0.0), [1.0, 0.9, 0.8], 10.0)
+//!     .with_lumens(800.0, &exposure);
+//! let sun = exposure.expose(10_000.0);
+//! ```
+
+use std::f32::consts::PI;
+
+/// How bright a light that gives off `lumens` evenly in every direction
+/// is in each one, in candela
+pub fn lumens_to_candela(lumens: f32) -> f32 {
+    lumens / (4.0 * PI)
+}
+
+pub fn candela_to_lumens(candela: f32) -> f32 {
+    candela * 4.0 * PI
+}
+
+/// Like [lumens_to_candela] for a light that only shines into a cone,
+/// where `outer_cos` is the cosine of the angle from its middle to its
+/// edge. Narrowing the cone concentrates the same light, so it gets
+/// brighter.
+pub fn spot_lumens_to_candela(lumens: f32, outer_cos: f32) -> f32 {
+    // The solid angle of the cone
+    let steradians = 2.0 * PI * (1.0 - outer_cos.clamp(-1.0, 1.0));
+    lumens / steradians.max(1e-6)
+}
+
+/// The lux falling `distance` meters away from a light of `candela`
+/// facing it
+pub fn illuminance(candela: f32, distance: f32) -> f32 {
+    candela / (distance * distance).max(1e-6)
+}
+
+/// A camera's exposure as an EV at ISO 100. Each step up halves how much
+/// light gets in, for brighter scenes: about 15 for a sunny day, 12 for
+/// overcast, 8 for a lit room and 3 for a street at night.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Exposure {
+    ev100: f32,
+}
+
+impl Exposure {
+    pub fn from_ev100(ev100: f32) -> Self {
+        Self { ev100 }
+    }
+
+    /// From a physical camera's settings: `aperture` as an f-number like
+    /// 16 for f/16, `shutter_speed` in seconds and `iso`
+    pub fn from_camera(aperture: f32, shutter_speed: f32, iso: f32) -> Self {
+        Self::from_ev100((aperture * aperture / shutter_speed * 100.0 / iso).log2())
+    }
+
+    /// What a light meter picks for a scene whose average luminance is
+    /// `luminance` candela per square meter, like auto exposure
+    pub fn from_luminance(luminance: f32) -> Self {
+        // 12.5 is the usual reflected light meter calibration
+        Self::from_ev100((luminance.max(1e-6) * 100.0 / 12.5).log2())
+    }
+
+    pub fn ev100(&self) -> f32 {
+        self.ev100
+    }
+
+    /// Brighter by `stops`, or darker for negative ones
+    pub fn compensate(self, stops: f32) -> Self {
+        Self::from_ev100(self.ev100 - stops)
+    }
+
+    /// What photometric values are multiplied by on the way into shaders.
+    /// The brightest luminance that doesn't clip comes out at 1.
+    pub fn multiplier(&self) -> f32 {
+        1.0 / (1.2 * 2f32.powf(self.ev100))
+    }
+
+    /// Candela, lux or candela per square meter as a shader value. Lux
+    /// goes straight in as a directional light's color scale, and
+    /// luminance as an emissive or sky color scale.
+    pub fn expose(&self, value: f32) -> f32 {
+        value * self.multiplier()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions_agree() {
+        assert!((candela_to_lumens(lumens_to_candela(800.0)) - 800.0).abs() < 1e-3);
+        // A cone opened all the way round is a point light
+        assert!((spot_lumens_to_candela(800.0, -1.0) - lumens_to_candela(800.0)).abs() < 1e-4);
+        assert!(spot_lumens_to_candela(800.0, 0.9) > lumens_to_candela(800.0));
+        assert_eq!(illuminance(100.0, 2.0), 25.0);
+
+        // Sunny 16: f/16 at 1/125 s and ISO 100 is about EV 15
+        let sunny = Exposure::from_camera(16.0, 1.0 / 125.0, 100.0);
+        assert!((sunny.ev100() - 15.0).abs() < 0.05);
+        assert_eq!(sunny.compensate(1.0).ev100(), sunny.ev100() - 1.0);
+        // The brightest unclipped luminance comes out at 1
+        let exposure = Exposure::from_ev100(10.0);
+        assert!((exposure.expose(1.2 * 1024.0) - 1.0).abs() < 1e-5);
+        // Metering a scene puts its average at 18% grey, near 0.1
+        let metered = Exposure::from_luminance(500.0).expose(500.0);
+        assert!((metered - 0.104).abs() < 0.01, "{}", metered);
+    }
+}
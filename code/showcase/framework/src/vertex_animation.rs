@@ -0,0 +1,153 @@
+use cgmath::*;
+
+/// WGSL with `vs_model_animated`, which draws a [crate::Material]'s
+/// [VertexAnimation] with the same pipeline setup as `vs_model`, and the
+/// functions it's made from for custom vertex shaders. Put
+/// [crate::MODEL_VERTEX_WGSL] in front.
+pub const VERTEX_ANIMATION_WGSL: &str = include_str!("vertex_animation.wgsl");
+
+/// Pushes the tops of meshes around, for grass and trees. Sway grows with
+/// the square of the height above the instance's origin.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WindSway {
+    /// Which way the wind blows, on the ground
+    pub direction: Vector2<f32>,
+    /// How far points at `height` are pushed
+    pub strength: f32,
+    /// Sways a second
+    pub frequency: f32,
+    /// Height above the origin that sways the full strength
+    pub height: f32,
+}
+
+/// Waves along the texture's u, which doesn't move at u = 0, displacing
+/// along the normal
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FlagWave {
+    pub amplitude: f32,
+    /// In texture coordinates
+    pub wavelength: f32,
+    /// Waves a second
+    pub speed: f32,
+}
+
+/// Floats up and down and spins about the instance's origin, for pickups
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bobbing {
+    pub amplitude: f32,
+    /// Bobs a second
+    pub frequency: f32,
+    /// Turns a second
+    pub spin: f32,
+}
+
+/// How a [crate::Material] moves its vertices, read by
+/// `vs_model_animated` in [VERTEX_ANIMATION_WGSL]. The default doesn't
+/// move anything.
+///
+/// ```ignore
+/// material.animation.wind = WindSway {
+///     direction: Vector2::new(1.0, 0.0),
+///     strength: 0.2,
+///     frequency: 0.5,
+///     height: 1.5,
+/// };
+/// // Every frame
+/// material.update_animation(&display.queue, display.time.elapsed_secs());
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VertexAnimation {
+    pub wind: WindSway,
+    pub flag: FlagWave,
+    pub bob: Bobbing,
+    /// Texture coordinates a second
+    pub uv_scroll: Vector2<f32>,
+}
+
+impl Default for VertexAnimation {
+    fn default() -> Self {
+        Self {
+            wind: WindSway {
+                direction: Vector2::new(1.0, 0.0),
+                strength: 0.0,
+                frequency: 0.5,
+                height: 1.0,
+            },
+            flag: FlagWave {
+                amplitude: 0.0,
+                wavelength: 0.5,
+                speed: 1.0,
+            },
+            bob: Bobbing {
+                amplitude: 0.0,
+                frequency: 0.5,
+                spin: 0.0,
+            },
+            uv_scroll: Vector2::zero(),
+        }
+    }
+}
+
+impl VertexAnimation {
+    pub(crate) fn to_raw(self, time: f32) -> VertexAnimationRaw {
+        let direction = if self.wind.direction.magnitude2() > 0.0 {
+            self.wind.direction.normalize()
+        } else {
+            Vector2::zero()
+        };
+        VertexAnimationRaw {
+            wind: [
+                direction.x,
+                direction.y,
+                self.wind.strength,
+                self.wind.frequency,
+            ],
+            wind_height: [self.wind.height, 0.0, 0.0, 0.0],
+            flag: [
+                self.flag.amplitude,
+                self.flag.wavelength,
+                self.flag.speed,
+                0.0,
+            ],
+            bob: [self.bob.amplitude, self.bob.frequency, self.bob.spin, 0.0],
+            uv_scroll: [self.uv_scroll.x, self.uv_scroll.y, 0.0, 0.0],
+            time: [time, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+/// Mirrors `VertexAnimation` in vertex_animation.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct VertexAnimationRaw {
+    wind: [f32; 4],
+    wind_height: [f32; 4],
+    flag: [f32; 4],
+    bob: [f32; 4],
+    uv_scroll: [f32; 4],
+    time: [f32; 4],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wind_direction_is_normalized() {
+        let mut animation = VertexAnimation::default();
+        animation.wind.direction = Vector2::new(3.0, 4.0);
+        let raw = animation.to_raw(2.0);
+        assert_eq!(&raw.wind[..2], &[0.6, 0.8]);
+        assert_eq!(raw.time[0], 2.0);
+        animation.wind.direction = Vector2::zero();
+        assert_eq!(&animation.to_raw(0.0).wind[..2], &[0.0, 0.0]);
+
+        crate::shader::validate_wgsl(&format!(
+            "{}{}{}",
+            crate::PBR_WGSL,
+            crate::MODEL_VERTEX_WGSL,
+            VERTEX_ANIMATION_WGSL
+        ))
+        .unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+// Vertex animation for ModelVertex meshes: wind sway, flag waving,
+// bobbing and scrolling texture coordinates. Needs MODEL_VERTEX_WGSL in
+// front, and reads a Material's VertexAnimation from group 0. Every
+// effect is off at zero, so vs_model_animated can stand in for vs_model.
+
+struct VertexAnimation {
+    // Direction on the ground in xy, then strength and sways a second
+    wind: vec4<f32>,
+    // x is the height above the origin where sway reaches full strength
+    wind_height: vec4<f32>,
+    // Amplitude, wavelength in texture coordinates, and waves a second
+    flag: vec4<f32>,
+    // Amplitude, bobs a second, and turns a second about y
+    bob: vec4<f32>,
+    // Texture coordinates a second in xy
+    uv_scroll: vec4<f32>,
+    // x is the time in seconds
+    time: vec4<f32>,
+}
+
+@group(0) @binding(13)
+var<uniform> vertex_animation: VertexAnimation;
+
+const TAU: f32 = 6.2831853;
+
+// How far wind pushes a point, more the higher it is above `origin`. The
+// phase comes from the origin so neighbouring plants don't move in step.
+fn wind_sway(position: vec3<f32>, origin: vec3<f32>, wind: vec4<f32>, height: f32, time: f32) -> vec3<f32> {
+    let up = max(position.y - origin.y, 0.0) / max(height, 0.0001);
+    let bend = up * up;
+    let phase = dot(origin.xz, vec2<f32>(0.37, 0.21));
+    let t = time * wind.w * TAU + phase;
+    let sway = 0.7 * sin(t) + 0.3 * sin(t * 2.3 + phase);
+    let push = wind.z * bend * (0.6 + 0.4 * sway);
+    return vec3<f32>(wind.x, 0.0, wind.y) * push;
+}
+
+// Waves travelling along u, pinned at u = 0 like a flag on its pole
+fn flag_wave(normal: vec3<f32>, uv: vec2<f32>, flag: vec4<f32>, time: f32) -> vec3<f32> {
+    let wavelength = max(flag.y, 0.0001);
+    let wave = sin((uv.x / wavelength - time * flag.z) * TAU);
+    return normal * wave * flag.x * uv.x;
+}
+
+// Turns a direction about y by `angle`
+fn turn_y(v: vec3<f32>, angle: f32) -> vec3<f32> {
+    let c = cos(angle);
+    let s = sin(angle);
+    return vec3<f32>(c * v.x + s * v.z, v.y, -s * v.x + c * v.z);
+}
+
+// Bobs a point up and down and spins it about `origin`
+fn bob(position: vec3<f32>, origin: vec3<f32>, params: vec4<f32>, time: f32) -> vec3<f32> {
+    let turned = origin + turn_y(position - origin, params.z * time * TAU);
+    return turned + vec3<f32>(0.0, sin(time * params.y * TAU) * params.x, 0.0);
+}
+
+fn scroll_uv(uv: vec2<f32>, velocity: vec2<f32>, time: f32) -> vec2<f32> {
+    return uv + velocity * time;
+}
+
+// vs_model with the material's VertexAnimation applied in world space
+@vertex
+fn vs_model_animated(model: ModelVertexInput, instance: InstanceInput) -> ModelVertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let linear = mat3x3<f32>(
+        instance.model_matrix_0.xyz,
+        instance.model_matrix_1.xyz,
+        instance.model_matrix_2.xyz,
+    );
+    let anim = vertex_animation;
+    let time = anim.time.x;
+    let origin = instance.model_matrix_3.xyz;
+    let spin = anim.bob.z * time * TAU;
+
+    var position = (model_matrix * vec4<f32>(model.position, 1.0)).xyz;
+    let normal = turn_y(linear * model.normal, spin);
+    position = bob(position, origin, anim.bob, time);
+    position = position + wind_sway(position, origin, anim.wind, anim.wind_height.x, time);
+    position = position + flag_wave(normalize(normal), model.tex_coords, anim.flag, time);
+
+    var out: ModelVertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.world_position = position;
+    out.tex_coords = scroll_uv(model.tex_coords, anim.uv_scroll.xy, time);
+    // Sway and waves bend the surface without turning the normals, which
+    // is hard to spot at the strengths they're meant for
+    out.normal = normal;
+    out.tangent = turn_y(linear * model.tangent, spin);
+    out.bitangent = turn_y(linear * model.bitangent, spin);
+    return out;
+}
@@ -0,0 +1,137 @@
+/// A rectangle of the surface in physical pixels, with the origin in the
+/// top left like wgpu's viewports.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Which corner [ViewportRect::inset] goes in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl ViewportRect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The whole of a `width` by `height` surface
+    pub fn full(width: u32, height: u32) -> Self {
+        Self::new(0.0, 0.0, width as f32, height as f32)
+    }
+
+    /// Width over height, for building projections
+    pub fn aspect(&self) -> f32 {
+        if self.height > 0.0 {
+            self.width / self.height
+        } else {
+            1.0
+        }
+    }
+
+    /// The largest rect with the given aspect ratio that fits inside this
+    /// one, centered. The rest is left as bars on two sides.
+    pub fn letterbox(&self, aspect: f32) -> Self {
+        if self.aspect() > aspect {
+            // Too wide, bars on the left and right
+            let width = self.height * aspect;
+            Self::new(
+                self.x + (self.width - width) * 0.5,
+                self.y,
+                width,
+                self.height,
+            )
+        } else {
+            let height = self.width / aspect;
+            Self::new(
+                self.x,
+                self.y + (self.height - height) * 0.5,
+                self.width,
+                height,
+            )
+        }
+    }
+
+    /// A picture-in-picture rect in one corner. `size` is the fraction of
+    /// this rect's height to use, and the inset keeps `aspect`. `margin` is
+    /// in pixels. The inset is shrunk if needed to stay inside this rect.
+    pub fn inset(&self, corner: Corner, size: f32, aspect: f32, margin: f32) -> Self {
+        let height = (self.height * size)
+            .min(self.height - margin * 2.0)
+            .min((self.width - margin * 2.0) / aspect)
+            .max(0.0);
+        let width = height * aspect;
+        let x = match corner {
+            Corner::TopLeft | Corner::BottomLeft => self.x + margin,
+            Corner::TopRight | Corner::BottomRight => self.x + self.width - width - margin,
+        };
+        let y = match corner {
+            Corner::TopLeft | Corner::TopRight => self.y + margin,
+            Corner::BottomLeft | Corner::BottomRight => self.y + self.height - height - margin,
+        };
+        Self::new(x, y, width, height)
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// Converts a position on the surface, like
+    /// [crate::Input::cursor_position], to 0-1 within this rect. `None` if
+    /// it's outside.
+    pub fn to_local(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        if !self.contains(x, y) {
+            return None;
+        }
+        Some(((x - self.x) / self.width, (y - self.y) / self.height))
+    }
+}
+
+pub trait RenderPassExt {
+    /// Draws into `rect` only. Clip space maps onto the rect, so anything
+    /// outside of it is clipped like it would be at the edge of the screen.
+    fn set_viewport_rect(&mut self, rect: ViewportRect);
+}
+
+impl RenderPassExt for wgpu::RenderPass<'_> {
+    fn set_viewport_rect(&mut self, rect: ViewportRect) {
+        self.set_viewport(rect.x, rect.y, rect.width, rect.height, 0.0, 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn letterbox_and_inset() {
+        let surface = ViewportRect::full(1920, 1080);
+        assert_eq!(
+            surface.letterbox(4.0 / 3.0),
+            ViewportRect::new(240.0, 0.0, 1440.0, 1080.0)
+        );
+        assert_eq!(
+            ViewportRect::full(1000, 1000).letterbox(2.0),
+            ViewportRect::new(0.0, 250.0, 1000.0, 500.0)
+        );
+
+        let inset = surface.inset(Corner::BottomRight, 0.25, 1.0, 10.0);
+        assert_eq!(inset, ViewportRect::new(1640.0, 800.0, 270.0, 270.0));
+        assert_eq!(inset.to_local(1640.0 + 135.0, 800.0), Some((0.5, 0.0)));
+        assert_eq!(inset.to_local(0.0, 0.0), None);
+        let wide = surface.inset(Corner::TopLeft, 1.0, 4.0, 10.0);
+        assert_eq!(wide, ViewportRect::new(10.0, 10.0, 1900.0, 475.0));
+    }
+}
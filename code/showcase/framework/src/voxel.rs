@@ -0,0 +1,589 @@
+use cgmath::*;
+use std::collections::{HashMap, HashSet};
+use wgpu::util::DeviceExt;
+
+use crate::model::Vertex;
+
+/// Blocks along each side of a [Chunk]
+pub const CHUNK_SIZE: i32 = 32;
+const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+/// WGSL with `vs_voxel`, a vertex shader for [VoxelVertex] with the camera
+/// at group 1, and `sample_atlas` for fragment shaders to read a face's
+/// tile from a [BlockAtlas] texture. Nearest filtering keeps tiles from
+/// bleeding into each other.
+pub const VOXEL_WGSL: &str = include_str!("voxel.wgsl");
+
+/// What's in a cell of a [Chunk]. Anything but [Block::AIR] is solid, and
+/// the number picks its textures in the [BlockAtlas].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Block(pub u16);
+
+impl Block {
+    pub const AIR: Self = Self(0);
+
+    pub fn is_air(self) -> bool {
+        self == Self::AIR
+    }
+}
+
+/// A [CHUNK_SIZE] cube of blocks
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    blocks: Vec<Block>,
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self {
+            blocks: vec![Block::AIR; CHUNK_VOLUME],
+        }
+    }
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `f` with the position of every block in the chunk
+    pub fn from_fn(mut f: impl FnMut([i32; 3]) -> Block) -> Self {
+        let mut chunk = Self::new();
+        for z in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    chunk.set([x, y, z], f([x, y, z]));
+                }
+            }
+        }
+        chunk
+    }
+
+    fn index([x, y, z]: [i32; 3]) -> usize {
+        debug_assert!([x, y, z].iter().all(|c| (0..CHUNK_SIZE).contains(c)));
+        ((z * CHUNK_SIZE + y) * CHUNK_SIZE + x) as usize
+    }
+
+    pub fn get(&self, local: [i32; 3]) -> Block {
+        self.blocks[Self::index(local)]
+    }
+
+    pub fn set(&mut self, local: [i32; 3], block: Block) {
+        self.blocks[Self::index(local)] = block;
+    }
+
+    /// Whether it's all air
+    pub fn is_empty(&self) -> bool {
+        self.blocks.iter().all(|b| b.is_air())
+    }
+}
+
+/// The atlas tiles a block's faces use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockFaces {
+    pub top: u32,
+    pub side: u32,
+    pub bottom: u32,
+}
+
+impl BlockFaces {
+    /// The same tile on every face
+    pub fn all(tile: u32) -> Self {
+        Self {
+            top: tile,
+            side: tile,
+            bottom: tile,
+        }
+    }
+
+    fn tile(&self, normal: [i32; 3]) -> u32 {
+        match normal[1] {
+            1 => self.top,
+            -1 => self.bottom,
+            _ => self.side,
+        }
+    }
+}
+
+/// A texture split into a grid of square tiles, numbered along each row
+/// from the top left, and which tiles each block uses. Blocks that
+/// haven't been given faces use tile 0.
+///
+/// ```ignore
+/// let mut atlas = BlockAtlas::new(16, 16);
+/// atlas
+///     .set(GRASS, BlockFaces { top: 0, side: 3, bottom: 2 })
+///     .set(DIRT, BlockFaces::all(2))
+///     .set(STONE, BlockFaces::all(1));
+/// ```
+#[derive(Debug, Clone)]
+pub struct BlockAtlas {
+    pub columns: u32,
+    pub rows: u32,
+    faces: HashMap<Block, BlockFaces>,
+}
+
+impl BlockAtlas {
+    pub fn new(columns: u32, rows: u32) -> Self {
+        Self {
+            columns,
+            rows,
+            faces: HashMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, block: Block, faces: BlockFaces) -> &mut Self {
+        self.faces.insert(block, faces);
+        self
+    }
+
+    pub fn faces(&self, block: Block) -> BlockFaces {
+        self.faces
+            .get(&block)
+            .copied()
+            .unwrap_or(BlockFaces::all(0))
+    }
+
+    /// The grid size to pass to `sample_atlas` in [VOXEL_WGSL]
+    pub fn grid(&self) -> [u32; 2] {
+        [self.columns, self.rows]
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct VoxelVertex {
+    pub position: [f32; 3],
+    /// In blocks rather than 0 to 1, so a tile repeats across merged faces
+    pub uv: [f32; 2],
+    pub normal: [f32; 3],
+    /// Which [BlockAtlas] tile the face uses
+    pub tile: u32,
+}
+
+impl Vertex for VoxelVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<VoxelVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+            ],
+        }
+    }
+}
+
+/// Builds the visible faces of `chunk`, merging neighbouring faces of the
+/// same block into larger quads. `outside` is asked about blocks just past
+/// the edges of the chunk, in the chunk's own coordinates, so faces
+/// against a neighbouring chunk can be skipped. `origin` is where the
+/// chunk's corner is in the world.
+pub fn greedy_mesh(
+    chunk: &Chunk,
+    origin: Point3<f32>,
+    atlas: &BlockAtlas,
+    outside: impl Fn([i32; 3]) -> Block,
+) -> (Vec<VoxelVertex>, Vec<u32>) {
+    let block = |p: [i32; 3]| {
+        if p.iter().all(|c| (0..CHUNK_SIZE).contains(c)) {
+            chunk.get(p)
+        } else {
+            outside(p)
+        }
+    };
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let n = CHUNK_SIZE as usize;
+    // Which block's face is in each cell of a slice, and whether it faces
+    // along the axis or against it
+    let mut mask: Vec<Option<(Block, bool)>> = vec![None; n * n];
+    for d in 0..3 {
+        let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+        // The faces between slice s and s + 1
+        for s in -1..CHUNK_SIZE {
+            for j in 0..CHUNK_SIZE {
+                for i in 0..CHUNK_SIZE {
+                    let mut a = [0; 3];
+                    a[d] = s;
+                    a[u] = i;
+                    a[v] = j;
+                    let mut b = a;
+                    b[d] += 1;
+                    let (block_a, block_b) = (block(a), block(b));
+                    // Each chunk only makes the faces of its own blocks
+                    mask[(j * CHUNK_SIZE + i) as usize] =
+                        if s >= 0 && !block_a.is_air() && block_b.is_air() {
+                            Some((block_a, true))
+                        } else if s + 1 < CHUNK_SIZE && block_a.is_air() && !block_b.is_air() {
+                            Some((block_b, false))
+                        } else {
+                            None
+                        };
+                }
+            }
+            for j in 0..n {
+                let mut i = 0;
+                while i < n {
+                    let Some(face) = mask[j * n + i] else {
+                        i += 1;
+                        continue;
+                    };
+                    let mut width = 1;
+                    while i + width < n && mask[j * n + i + width] == Some(face) {
+                        width += 1;
+                    }
+                    let mut height = 1;
+                    while j + height < n
+                        && (0..width).all(|k| mask[(j + height) * n + i + k] == Some(face))
+                    {
+                        height += 1;
+                    }
+                    for row in j..j + height {
+                        mask[row * n + i..row * n + i + width].fill(None);
+                    }
+                    let (block, forward) = face;
+                    let mut normal = [0; 3];
+                    normal[d] = if forward { 1 } else { -1 };
+                    let corners = [(0, 0), (width, 0), (width, height), (0, height)];
+                    let base = vertices.len() as u32;
+                    for (du, dv) in corners {
+                        let mut p = [0.0; 3];
+                        p[d] = (s + 1) as f32;
+                        p[u] = (i + du) as f32;
+                        p[v] = (j + dv) as f32;
+                        let position = origin + Vector3::from(p);
+                        vertices.push(VoxelVertex {
+                            position: position.into(),
+                            uv: face_uv(position, normal),
+                            normal: normal.map(|c| c as f32),
+                            tile: atlas.faces(block).tile(normal),
+                        });
+                    }
+                    // The corners go anticlockwise seen from the axis,
+                    // which is the front for faces that point along it
+                    if forward {
+                        indices.extend_from_slice(&[
+                            base,
+                            base + 1,
+                            base + 2,
+                            base,
+                            base + 2,
+                            base + 3,
+                        ]);
+                    } else {
+                        indices.extend_from_slice(&[
+                            base,
+                            base + 2,
+                            base + 1,
+                            base,
+                            base + 3,
+                            base + 2,
+                        ]);
+                    }
+                    i += width;
+                }
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+/// Texture coordinates from the position, so they line up across merged
+/// faces. Sides have v running down and u to the right as seen from
+/// outside.
+fn face_uv(p: Point3<f32>, normal: [i32; 3]) -> [f32; 2] {
+    match normal {
+        [1, 0, 0] => [-p.z, -p.y],
+        [-1, 0, 0] => [p.z, -p.y],
+        [0, 0, 1] => [p.x, -p.y],
+        [0, 0, -1] => [-p.x, -p.y],
+        _ => [p.x, p.z],
+    }
+}
+
+/// The GPU buffers for one chunk
+pub struct ChunkMesh {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub num_indices: u32,
+}
+
+/// Chunks of blocks on an endless grid, with a mesh for each that's
+/// rebuilt when its blocks change. Rebuilds wait for
+/// [VoxelWorld::rebuild], which does the ones nearest the camera first and
+/// stops after a budget, so editing or loading lots of chunks spreads the
+/// work over several frames.
+///
+/// ```ignore
+/// let mut world = VoxelWorld::new(atlas);
+/// world.insert_chunk([0, 0, 0], Chunk::from_fn(|[_, y, _]| if y < 8 { STONE } else { Block::AIR }));
+/// world.set_block([3, 8, 3], GRASS);
+/// // Every frame
+/// world.rebuild(&display.device, camera.position, 4);
+/// pass.set_pipeline(&voxel_pipeline);
+/// pass.set_bind_group(0, &atlas_bind_group, &[]);
+/// pass.set_bind_group(1, &camera_bind_group, &[]);
+/// world.draw(&mut pass);
+/// ```
+pub struct VoxelWorld {
+    pub atlas: BlockAtlas,
+    chunks: HashMap<[i32; 3], Chunk>,
+    meshes: HashMap<[i32; 3], ChunkMesh>,
+    dirty: HashSet<[i32; 3]>,
+}
+
+impl VoxelWorld {
+    pub fn new(atlas: BlockAtlas) -> Self {
+        Self {
+            atlas,
+            chunks: HashMap::new(),
+            meshes: HashMap::new(),
+            dirty: HashSet::new(),
+        }
+    }
+
+    /// The chunk a block is in, and where it is in that chunk
+    pub fn chunk_coords(block: [i32; 3]) -> ([i32; 3], [i32; 3]) {
+        (
+            block.map(|c| c.div_euclid(CHUNK_SIZE)),
+            block.map(|c| c.rem_euclid(CHUNK_SIZE)),
+        )
+    }
+
+    pub fn chunk(&self, coords: [i32; 3]) -> Option<&Chunk> {
+        self.chunks.get(&coords)
+    }
+
+    /// Replaces a chunk. Its neighbours are rebuilt too, as faces against
+    /// it may have appeared or gone.
+    pub fn insert_chunk(&mut self, coords: [i32; 3], chunk: Chunk) {
+        self.chunks.insert(coords, chunk);
+        self.mark_with_neighbours(coords);
+    }
+
+    pub fn remove_chunk(&mut self, coords: [i32; 3]) -> Option<Chunk> {
+        let chunk = self.chunks.remove(&coords)?;
+        self.meshes.remove(&coords);
+        self.dirty.remove(&coords);
+        for neighbour in neighbours(coords) {
+            if self.chunks.contains_key(&neighbour) {
+                self.dirty.insert(neighbour);
+            }
+        }
+        Some(chunk)
+    }
+
+    fn mark_with_neighbours(&mut self, coords: [i32; 3]) {
+        self.dirty.insert(coords);
+        for neighbour in neighbours(coords) {
+            if self.chunks.contains_key(&neighbour) {
+                self.dirty.insert(neighbour);
+            }
+        }
+    }
+
+    /// Air where there's no chunk
+    pub fn block(&self, position: [i32; 3]) -> Block {
+        let (chunk, local) = Self::chunk_coords(position);
+        self.chunks
+            .get(&chunk)
+            .map_or(Block::AIR, |chunk| chunk.get(local))
+    }
+
+    /// Adds a chunk if there isn't one there yet
+    pub fn set_block(&mut self, position: [i32; 3], block: Block) {
+        let (coords, local) = Self::chunk_coords(position);
+        if !self.chunks.contains_key(&coords) && block.is_air() {
+            return;
+        }
+        self.chunks.entry(coords).or_default().set(local, block);
+        self.dirty.insert(coords);
+        // Blocks on the edge can hide or show faces next door
+        for axis in 0..3 {
+            let step = match local[axis] {
+                0 => -1,
+                l if l == CHUNK_SIZE - 1 => 1,
+                _ => continue,
+            };
+            let mut neighbour = coords;
+            neighbour[axis] += step;
+            if self.chunks.contains_key(&neighbour) {
+                self.dirty.insert(neighbour);
+            }
+        }
+    }
+
+    /// How many chunks are waiting to be rebuilt
+    pub fn dirty_count(&self) -> usize {
+        self.dirty.len()
+    }
+
+    /// Meshes a chunk on the CPU, with its neighbours hiding the faces
+    /// between them
+    pub fn mesh_chunk(&self, coords: [i32; 3]) -> (Vec<VoxelVertex>, Vec<u32>) {
+        let chunk = match self.chunks.get(&coords) {
+            Some(chunk) => chunk,
+            None => return (Vec::new(), Vec::new()),
+        };
+        let corner = coords.map(|c| c * CHUNK_SIZE);
+        greedy_mesh(
+            chunk,
+            Point3::from(corner.map(|c| c as f32)),
+            &self.atlas,
+            |[x, y, z]| self.block([corner[0] + x, corner[1] + y, corner[2] + z]),
+        )
+    }
+
+    /// Rebuilds up to `budget` of the changed chunks, nearest to `focus`
+    /// first. Returns how many are still waiting.
+    pub fn rebuild(&mut self, device: &wgpu::Device, focus: Point3<f32>, budget: usize) -> usize {
+        crate::cpu_scope!("VoxelWorld::rebuild");
+        let mut dirty = self.dirty.iter().copied().collect::<Vec<_>>();
+        let distance = |coords: &[i32; 3]| {
+            let center = coords.map(|c| (c as f32 + 0.5) * CHUNK_SIZE as f32);
+            Point3::from(center).distance2(focus)
+        };
+        dirty.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+        for coords in dirty.into_iter().take(budget) {
+            self.dirty.remove(&coords);
+            let (vertices, indices) = self.mesh_chunk(coords);
+            if indices.is_empty() {
+                self.meshes.remove(&coords);
+                continue;
+            }
+            let label = format!("Chunk {:?}", coords);
+            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Vertex Buffer", label)),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Index Buffer", label)),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+            self.meshes.insert(
+                coords,
+                ChunkMesh {
+                    vertex_buffer,
+                    index_buffer,
+                    num_indices: indices.len() as u32,
+                },
+            );
+        }
+        self.dirty.len()
+    }
+
+    pub fn meshes(&self) -> impl Iterator<Item = ([i32; 3], &ChunkMesh)> {
+        self.meshes.iter().map(|(coords, mesh)| (*coords, mesh))
+    }
+
+    /// Draws every chunk that has a mesh. The pipeline and bind groups
+    /// need to be set already.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        for mesh in self.meshes.values() {
+            pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
+        }
+    }
+}
+
+fn neighbours([x, y, z]: [i32; 3]) -> [[i32; 3]; 6] {
+    [
+        [x - 1, y, z],
+        [x + 1, y, z],
+        [x, y - 1, z],
+        [x, y + 1, z],
+        [x, y, z - 1],
+        [x, y, z + 1],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn greedy_mesh_merges_faces() {
+        let stone = Block(1);
+        // A 4 x 2 x 3 box makes one quad per side
+        let chunk = Chunk::from_fn(|[x, y, z]| {
+            if x < 4 && y < 2 && z < 3 {
+                stone
+            } else {
+                Block::AIR
+            }
+        });
+        let mut atlas = BlockAtlas::new(4, 4);
+        atlas.set(
+            stone,
+            BlockFaces {
+                top: 1,
+                side: 2,
+                bottom: 3,
+            },
+        );
+        let (vertices, indices) = greedy_mesh(&chunk, Point3::origin(), &atlas, |_| Block::AIR);
+        assert_eq!(vertices.len(), 6 * 4);
+        assert_eq!(indices.len(), 6 * 6);
+        for quad in indices.chunks(6) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(vertices[quad[i] as usize].position));
+            let normal = Vector3::from(vertices[quad[0] as usize].normal);
+            assert!((b - a).cross(c - a).dot(normal) > 0.0, "quad faces in");
+        }
+        let top = vertices
+            .iter()
+            .find(|v| v.normal == [0.0, 1.0, 0.0])
+            .unwrap();
+        assert_eq!(top.tile, 1);
+
+        // The sides against the chunk's edges are hidden by solid blocks
+        // past them
+        let (_, indices) = greedy_mesh(&chunk, Point3::origin(), &atlas, |_| stone);
+        assert_eq!(indices.len(), 3 * 6);
+    }
+
+    #[test]
+    fn world_hides_faces_between_chunks() {
+        let mut world = VoxelWorld::new(BlockAtlas::new(1, 1));
+        let full = Chunk::from_fn(|_| Block(1));
+        world.insert_chunk([0, 0, 0], full.clone());
+        world.insert_chunk([1, 0, 0], full);
+        let (_, indices) = world.mesh_chunk([0, 0, 0]);
+        // Five sides, the sixth is against the other chunk
+        assert_eq!(indices.len(), 5 * 6);
+        assert_eq!(world.dirty_count(), 2);
+
+        assert_eq!(
+            VoxelWorld::chunk_coords([-1, 32, 5]),
+            ([-1, 1, 0], [31, 0, 5])
+        );
+        world.set_block([-1, 0, 0], Block(2));
+        assert_eq!(world.block([-1, 0, 0]), Block(2));
+        assert!(world.chunk([-1, 0, 0]).is_some());
+        world.set_block([5, -40, 0], Block::AIR);
+        assert!(world.chunk([0, -2, 0]).is_none());
+        crate::shader::validate_wgsl(VOXEL_WGSL).unwrap();
+    }
+}
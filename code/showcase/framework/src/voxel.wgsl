@@ -0,0 +1,52 @@
+// A vertex shader for VoxelVertex, with the camera at group 1. Chunk
+// meshes are already in world space so there's no instance matrix.
+
+struct Camera {
+    view_position: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+struct VoxelVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tile: u32,
+}
+
+struct VoxelVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    // In blocks, so it runs past 1 across merged faces
+    @location(1) uv: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) @interpolate(flat) tile: u32,
+}
+
+@vertex
+fn vs_voxel(in: VoxelVertexInput) -> VoxelVertexOutput {
+    var out: VoxelVertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(in.position, 1.0);
+    out.world_position = in.position;
+    out.uv = in.uv;
+    out.normal = in.normal;
+    out.tile = in.tile;
+    return out;
+}
+
+// Samples the face's tile from an atlas `grid` tiles across and down,
+// repeating it once per block. The gradients come from the unwrapped uv
+// so mip selection doesn't jump where the tile repeats.
+fn sample_atlas(
+    atlas: texture_2d<f32>,
+    atlas_sampler: sampler,
+    in: VoxelVertexOutput,
+    grid: vec2<u32>,
+) -> vec4<f32> {
+    let size = 1.0 / vec2<f32>(grid);
+    let tile = vec2<f32>(vec2<u32>(in.tile % grid.x, in.tile / grid.x));
+    let uv = (tile + fract(in.uv)) * size;
+    return textureSampleGrad(atlas, atlas_sampler, uv, dpdx(in.uv) * size, dpdy(in.uv) * size);
+}
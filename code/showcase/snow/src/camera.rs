@@ -1,4 +1,7 @@
-use std::{f32::consts::FRAC_PI_2, time::Duration};
+use std::{
+    f32::consts::{FRAC_PI_2, PI},
+    time::Duration,
+};
 
 use winit::{dpi::PhysicalPosition, event::MouseScrollDelta, keyboard::KeyCode};
 
@@ -13,22 +16,39 @@ pub struct Camera {
 
 impl Camera {
     pub fn new<V: Into<glam::Vec3>>(position: V, yaw: f32, pitch: f32) -> Self {
-        Self {
+        let mut camera = Self {
             position: position.into(),
-            yaw,
-            pitch,
+            yaw: 0.0,
+            pitch: 0.0,
+        };
+        camera.rotate(yaw, pitch);
+        camera
+    }
+
+    /// Turns the camera, keeping it from looking straight up or down where
+    /// the view matrix breaks. Yaw is wrapped to stay between -π and π so
+    /// it doesn't lose precision after spinning for a long time.
+    pub fn rotate(&mut self, yaw: f32, pitch: f32) {
+        let yaw = self.yaw + yaw;
+        let pitch = self.pitch + pitch;
+        // Bad mouse input shouldn't leave the camera stuck on NaN
+        if yaw.is_finite() {
+            self.yaw = (yaw + PI).rem_euclid(2.0 * PI) - PI;
+        }
+        if pitch.is_finite() {
+            self.pitch = pitch.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2);
         }
     }
 
-    pub fn calc_matrix(&self) -> glam::Mat4 {
+    /// The direction the camera is looking
+    pub fn forward(&self) -> glam::Vec3 {
         let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        glam::Vec3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
+    }
 
-        glam::Mat4::look_to_rh(
-            self.position,
-            glam::Vec3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
-            glam::Vec3::Y,
-        )
+    pub fn calc_matrix(&self) -> glam::Mat4 {
+        glam::Mat4::look_to_rh(self.position, self.forward(), glam::Vec3::Y)
     }
 }
 
@@ -74,6 +94,13 @@ pub struct CameraController {
 }
 
 impl CameraController {
+    /// Radians turned per pixel of mouse motion, and distance moved per
+    /// pixel of scrolling, before `sensitivity` is applied
+    const LOOK_SCALE: f32 = 1.0 / 60.0;
+    const MAX_STEP: f32 = 1.0 / 60.0;
+    /// Keeps a huge dt from taking forever
+    const MAX_STEPS: f32 = 64.0;
+
     pub fn new(speed: f32, sensitivity: f32) -> Self {
         Self {
             amount_left: 0.0,
@@ -121,9 +148,11 @@ impl CameraController {
         }
     }
 
+    /// Accumulates motion, which [update_camera](Self::update_camera)
+    /// applies and then clears
     pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        self.rotate_horizontal = mouse_dx as f32;
-        self.rotate_vertical = mouse_dy as f32;
+        self.rotate_horizontal += mouse_dx as f32;
+        self.rotate_vertical += mouse_dy as f32;
     }
 
     #[allow(unused)]
@@ -138,6 +167,37 @@ impl CameraController {
     pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
         let dt = dt.as_secs_f32();
 
+        // Mouse motion and scrolling are distances rather than speeds, so
+        // they aren't scaled by dt
+        let yaw = self.rotate_horizontal * self.sensitivity * Self::LOOK_SCALE;
+        let pitch = -self.rotate_vertical * self.sensitivity * Self::LOOK_SCALE;
+        let scroll = self.scroll * self.speed * self.sensitivity * Self::LOOK_SCALE;
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+        self.scroll = 0.0;
+
+        // Turning and moving are interleaved in steps of at most MAX_STEP,
+        // so the path curves the same way at any frame rate
+        let steps = (dt / Self::MAX_STEP).ceil().clamp(1.0, Self::MAX_STEPS);
+        let step_dt = dt / steps;
+        let half_yaw = yaw / steps * 0.5;
+        let half_pitch = pitch / steps * 0.5;
+        for _ in 0..steps as u32 {
+            // Moving in the middle of each step's turn is more accurate
+            // than before or after it
+            camera.rotate(half_yaw, half_pitch);
+            self.move_camera(camera, step_dt);
+            camera.rotate(half_yaw, half_pitch);
+        }
+
+        // Move in/out (aka. "zoom")
+        // Note: this isn't an actual zoom. The camera's position
+        // changes when zooming. I've added this to make it easier
+        // to get closer to an object you want to focus on.
+        camera.position += camera.forward() * scroll;
+    }
+
+    fn move_camera(&self, camera: &mut Camera, dt: f32) {
         // Move forward/backward and left/right
         let (yaw_sin, yaw_cos) = camera.yaw.sin_cos();
         let forward = glam::Vec3::new(yaw_cos, 0.0, yaw_sin).normalize();
@@ -145,35 +205,8 @@ impl CameraController {
         camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
         camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
 
-        // Move in/out (aka. "zoom")
-        // Note: this isn't an actual zoom. The camera's position
-        // changes when zooming. I've added this to make it easier
-        // to get closer to an object you want to focus on.
-        let (pitch_sin, pitch_cos) = camera.pitch.sin_cos();
-        let scrollward =
-            glam::Vec3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
-        camera.position += scrollward * self.scroll * self.speed * self.sensitivity * dt;
-        self.scroll = 0.0;
-
         // Move up/down. Since we don't use roll, we can just
         // modify the y coordinate directly.
         camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
-
-        // Rotate
-        camera.yaw += self.rotate_horizontal * self.sensitivity * dt;
-        camera.pitch += -self.rotate_vertical * self.sensitivity * dt;
-
-        // If process_mouse isn't called every frame, these values
-        // will not get set to zero, and the camera will rotate
-        // when moving in a non cardinal direction.
-        self.rotate_horizontal = 0.0;
-        self.rotate_vertical = 0.0;
-
-        // Keep the camera's angle from going too high/low.
-        if camera.pitch < -SAFE_FRAC_PI_2 {
-            camera.pitch = -SAFE_FRAC_PI_2;
-        } else if camera.pitch > SAFE_FRAC_PI_2 {
-            camera.pitch = SAFE_FRAC_PI_2;
-        }
     }
 }
@@ -362,7 +362,7 @@ impl framework::Demo for Snow {
         drop(draw_pass);
 
         display.queue.submit([encoder.finish()]);
-        frame.present();
+        display.present(frame);
     }
 }
 
@@ -11,6 +11,11 @@ use wasm_bindgen_cli_support::Bindgen;
 struct WasmTarget {
     package: String,
     out: String,
+    /// Builds with atomics and framework/wasm-threads so the package's
+    /// JobPool gets web workers. Needs a nightly toolchain with rust-src,
+    /// and the page has to be served with COOP/COEP headers.
+    #[serde(default)]
+    threads: bool,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -28,23 +33,60 @@ fn main() -> anyhow::Result<()> {
 
     let targets: Vec<WasmTarget> = serde_json::from_str(&json)?;
 
-    // Tell cargo to build all targets as wasm32-unknown-unknown
-    let mut command = Command::new("cargo");
-
     let start_time = std::time::Instant::now();
 
-    command
-        .arg("build")
-        .arg("--release")
-        .arg("--target")
-        .arg("wasm32-unknown-unknown");
-    for target in &targets {
-        command.arg("-p").arg(&target.package);
+    // Tell cargo to build all targets as wasm32-unknown-unknown
+    let (threaded, single): (Vec<_>, Vec<_>) = targets.iter().partition(|t| t.threads);
+    if !single.is_empty() {
+        let mut command = Command::new("cargo");
+        command
+            .arg("build")
+            .arg("--release")
+            .arg("--target")
+            .arg("wasm32-unknown-unknown");
+        for target in &single {
+            command.arg("-p").arg(&target.package);
+        }
+        let status = command.spawn()?.wait()?;
+
+        if !status.success() {
+            bail!("Failed to compile WASM with code ({status})");
+        }
     }
-    let status = command.spawn()?.wait()?;
 
-    if !status.success() {
-        bail!("Failed to compile WASM with code ({status})");
+    // Shared memory needs std rebuilt with atomics, which only nightly can
+    // do. These go in their own target dir so the flags don't make cargo
+    // rebuild everything else.
+    if !threaded.is_empty() {
+        let mut command = Command::new("cargo");
+        command
+            .arg("+nightly")
+            .arg("build")
+            .arg("--release")
+            .arg("--target")
+            .arg("wasm32-unknown-unknown")
+            .arg("--target-dir")
+            .arg("target/wasm-threads")
+            .arg("-Z")
+            .arg("build-std=panic_abort,std")
+            .arg("--features")
+            .arg("framework/wasm-threads")
+            .env(
+                "RUSTFLAGS",
+                "-C target-feature=+atomics,+bulk-memory,+mutable-globals",
+            );
+        for target in &threaded {
+            command.arg("-p").arg(&target.package);
+        }
+        let status = command.spawn()?.wait()?;
+
+        if !status.success() {
+            bail!("Failed to compile threaded WASM with code ({status})");
+        }
+        log::info!(
+            "Threaded targets need Cross-Origin-Opener-Policy: same-origin and \
+            Cross-Origin-Embedder-Policy: require-corp headers to run"
+        );
     }
 
     let errors = targets
@@ -59,7 +101,12 @@ fn main() -> anyhow::Result<()> {
             Bindgen::new()
                 .bundler(true)?
                 .input_path(format!(
-                    "target/wasm32-unknown-unknown/release/{}.wasm",
+                    "{}/wasm32-unknown-unknown/release/{}.wasm",
+                    if target.threads {
+                        "target/wasm-threads"
+                    } else {
+                        "target"
+                    },
                     target.package
                 ))
                 .out_name("demo")
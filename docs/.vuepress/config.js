@@ -9,6 +9,16 @@ module.exports = {
             url: (_, $site, path) => ($site.themeConfig.domain || '') + path,
         },
     },
+    // Demos built with threads need SharedArrayBuffer, which browsers only
+    // allow on cross-origin isolated pages
+    beforeDevServer(app) {
+        if (!process.env.WASM_THREADS) return;
+        app.use((req, res, next) => {
+            res.setHeader('Cross-Origin-Opener-Policy', 'same-origin');
+            res.setHeader('Cross-Origin-Embedder-Policy', 'require-corp');
+            next();
+        });
+    },
     themeConfig: {
         domain: '/learn-wgpu',
         author: {